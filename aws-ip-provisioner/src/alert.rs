@@ -0,0 +1,240 @@
+use std::io::{self, Error, ErrorKind};
+
+use hyper::{Body, Method, Request};
+use serde_json::{json, Value};
+use tokio::time::{timeout, Duration};
+
+use crate::{sdk, secret, tls, transfer};
+
+/// PagerDuty Events API v2.
+/// ref. <https://developer.pagerduty.com/docs/events-api-v2/trigger-events/>
+pub const PAGERDUTY_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+/// Opsgenie Alert API (e.g., "https://api.eu.opsgenie.com/v2/alerts" for the EU instance).
+/// ref. <https://docs.opsgenie.com/docs/alert-api>
+pub const OPSGENIE_URL: &str = "https://api.opsgenie.com/v2/alerts";
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to page on persistent reconcile failures in "daemon" mode.
+#[derive(Debug, Clone)]
+pub struct Alert {
+    /// "none", "pagerduty", or "opsgenie".
+    pub provider: String,
+    /// Where to read the PagerDuty routing key or the Opsgenie API key
+    /// (see "secret::Source::parse"), "env" for the provider's default variable.
+    pub key_source: String,
+    /// API URL, empty for the provider's default.
+    pub url: String,
+    /// Consecutive failures that trigger the alert.
+    pub failure_threshold: u32,
+}
+
+impl Alert {
+    pub fn is_enabled(&self) -> bool {
+        !self.provider.is_empty() && self.provider != "none"
+    }
+
+    /// Returns the environment variable of the key for the "env" source.
+    pub fn default_key_env(&self) -> &'static str {
+        if self.provider == "opsgenie" {
+            "OPSGENIE_API_KEY"
+        } else {
+            "PAGERDUTY_ROUTING_KEY"
+        }
+    }
+
+    pub fn api_url(&self) -> &str {
+        if !self.url.is_empty() {
+            &self.url
+        } else if self.provider == "opsgenie" {
+            OPSGENIE_URL
+        } else {
+            PAGERDUTY_URL
+        }
+    }
+}
+
+/// Action of the alert request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Trigger,
+    Resolve,
+}
+
+/// Returns the URL, the authorization header (if any), and the JSON body of
+/// the alert request, deduplicated by "dedup_key" (PagerDuty "dedup_key",
+/// Opsgenie "alias"), so that the repeated failures of the same instance
+/// and "Id" update one incident rather than paging again.
+pub fn request(
+    alert: &Alert,
+    key: &str,
+    action: Action,
+    dedup_key: &str,
+    summary: &str,
+    details: &Value,
+) -> (String, Option<String>, Value) {
+    let url = alert.api_url().trim_end_matches('/');
+    if alert.provider == "opsgenie" {
+        let authorization = Some(format!("GenieKey {key}"));
+        return match action {
+            Action::Trigger => (
+                url.to_string(),
+                authorization,
+                json!({
+                    "message": summary,
+                    "alias": dedup_key,
+                    "description": details.to_string(),
+                    "details": details,
+                    "priority": "P1",
+                    "source": crate::APP_NAME,
+                }),
+            ),
+            Action::Resolve => (
+                // the alias has slashes (e.g., "aws-ip-provisioner/i-.../node-1")
+                format!(
+                    "{url}/{}/close?identifierType=alias",
+                    transfer::encode(dedup_key)
+                ),
+                authorization,
+                json!({ "source": crate::APP_NAME, "note": summary }),
+            ),
+        };
+    }
+    let body = match action {
+        Action::Trigger => json!({
+            "routing_key": key,
+            "event_action": "trigger",
+            "dedup_key": dedup_key,
+            "payload": {
+                "summary": summary,
+                "source": details["instance_id"],
+                "severity": "critical",
+                "component": crate::APP_NAME,
+                "custom_details": details,
+            },
+        }),
+        Action::Resolve => json!({
+            "routing_key": key,
+            "event_action": "resolve",
+            "dedup_key": dedup_key,
+        }),
+    };
+    (url.to_string(), None, body)
+}
+
+/// Counts the consecutive reconcile failures, triggering the alert at the
+/// threshold (once) and resolving it on the next success. A failed alert
+/// request is logged and retried on the next failure (or success),
+/// never failing the daemon.
+pub struct Alerter {
+    alert: Alert,
+    sdk_opts: sdk::Options,
+    dedup_key: String,
+    details: Value,
+    consecutive_failures: u32,
+    triggered: bool,
+}
+
+impl Alerter {
+    pub fn new(alert: Alert, sdk_opts: sdk::Options, instance_id: &str, id: &str) -> Self {
+        Self {
+            alert,
+            sdk_opts,
+            dedup_key: format!("{}/{instance_id}/{id}", crate::APP_NAME),
+            details: json!({ "instance_id": instance_id, "id": id }),
+            consecutive_failures: 0,
+            triggered: false,
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.triggered
+    }
+
+    pub async fn record_failure(&mut self, reason: &str) {
+        self.consecutive_failures += 1;
+        if !self.alert.is_enabled()
+            || self.triggered
+            || self.consecutive_failures < self.alert.failure_threshold.max(1)
+        {
+            return;
+        }
+        let summary = format!(
+            "{} failed to reconcile EIP of \"{}\" on {} {} consecutive times: {reason}",
+            crate::APP_NAME,
+            self.details["id"].as_str().unwrap_or_default(),
+            self.details["instance_id"].as_str().unwrap_or_default(),
+            self.consecutive_failures
+        );
+        let mut details = self.details.clone();
+        details["consecutive_failures"] = self.consecutive_failures.into();
+        details["last_error"] = reason.into();
+        match self.send(Action::Trigger, &summary, &details).await {
+            Ok(_) => {
+                log::warn!("triggered {} alert {}", self.alert.provider, self.dedup_key);
+                self.triggered = true;
+            }
+            Err(e) => log::warn!("failed to trigger {} alert '{}'", self.alert.provider, e),
+        }
+    }
+
+    pub async fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        if !self.triggered {
+            return;
+        }
+        let summary = format!("{} reconciled the EIP again", crate::APP_NAME);
+        match self
+            .send(Action::Resolve, &summary, &self.details.clone())
+            .await
+        {
+            Ok(_) => {
+                log::info!("resolved {} alert {}", self.alert.provider, self.dedup_key);
+                self.triggered = false;
+            }
+            Err(e) => log::warn!("failed to resolve {} alert '{}'", self.alert.provider, e),
+        }
+    }
+
+    async fn send(&self, action: Action, summary: &str, details: &Value) -> io::Result<()> {
+        let key = secret::read(
+            &self.alert.key_source,
+            self.alert.default_key_env(),
+            &self.sdk_opts,
+        )
+        .await?;
+        let (url, authorization, body) =
+            request(&self.alert, &key, action, &self.dedup_key, summary, details);
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(&url)
+            .header("content-type", "application/json");
+        if let Some(v) = authorization {
+            req = req.header("authorization", v);
+        }
+        let req = req
+            .body(Body::from(serde_json::to_vec(&body)?))
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid alert URL {}", e)))?;
+        let client = hyper::Client::builder().build::<_, Body>(tls::https_connector());
+        let resp = timeout(SEND_TIMEOUT, client.request(req))
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, format!("POST {url} timed out")))?
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed POST {url} {}", e)))?;
+        if !resp.status().is_success() {
+            if matches!(resp.status().as_u16(), 401 | 403) {
+                // re-reads the key on the next send (e.g., rotated since cached)
+                secret::invalidate(
+                    &self.alert.key_source,
+                    self.alert.default_key_env(),
+                    &self.sdk_opts,
+                );
+            }
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("alert POST {url} returned {}", resp.status()),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Error, ErrorKind, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_manager::sts;
+use aws_types::SdkConfig;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{ec2, eip};
+
+/// Actions of the host history ("ip-manager history"), out of the audited calls.
+pub const HISTORY_ACTIONS: [&str; 5] = [
+    "allocate",
+    "associate",
+    "disassociate",
+    // recorded by the pool release of the earlier versions, now "disassociate"
+    "release",
+    "release_address",
+];
+
+/// Audit log of the mutating AWS API calls of the run, disabled by default.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    /// "None" means disabled.
+    log: Mutex<Option<Writer>>,
+}
+
+#[derive(Debug)]
+struct Writer {
+    file_path: String,
+    /// ARN of the caller identity, the same for every call of the process.
+    caller: String,
+    /// "Id" tag value of the host, empty if none (e.g., "ip-manager transfer").
+    id: String,
+}
+
+impl AuditLog {
+    /// Enables the audit log to the file (appended as JSON lines),
+    /// resolving the caller identity with STS, and records the "Id" tag value
+    /// of the host with every call. Empty file path disables the audit log.
+    pub async fn init(&self, file_path: &str, id: &str, sts_config: &SdkConfig) -> io::Result<()> {
+        if file_path.is_empty() {
+            *self.log.lock().unwrap() = None;
+            return Ok(());
+        }
+
+        let identity = sts::Manager::new(sts_config)
+            .get_identity()
+            .await
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "failed to resolve the caller identity for the audit log {} (retryable {})",
+                        e.message(),
+                        e.is_retryable()
+                    ),
+                )
+            })?;
+        self.enable(file_path, &identity.role_arn, id)
+    }
+
+    /// Enables the audit log to the file as the caller (see "init"), creating
+    /// its directory (e.g., "/var/lib/ip-manager").
+    pub fn enable(&self, file_path: &str, caller: &str, id: &str) -> io::Result<()> {
+        if let Some(parent_dir) = Path::new(file_path).parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+        // fail before any mutation if the file is not writable
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)?;
+        log::info!("writing audit log to {file_path} as {caller}");
+
+        *self.log.lock().unwrap() = Some(Writer {
+            file_path: file_path.to_string(),
+            caller: caller.to_string(),
+            id: id.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Disables the audit log.
+    pub fn disable(&self) {
+        *self.log.lock().unwrap() = None;
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.log.lock().unwrap().is_some()
+    }
+
+    /// Returns the current association of the EIP as the "before" state
+    /// (instance ID, ENI ID, or empty if not associated).
+    /// Only describes the EIP if the audit log is enabled.
+    pub async fn association(&self, ec2_manager: &ec2::Manager, allocation_id: &str) -> String {
+        if !self.enabled() {
+            return String::new();
+        }
+        match eip::describe_by_allocation_id(ec2_manager, allocation_id).await {
+            Ok(Some(addr)) => addr
+                .instance_id()
+                .or_else(|| addr.network_interface_id())
+                .unwrap_or_default()
+                .to_string(),
+            Ok(None) => String::new(),
+            Err(e) => {
+                log::warn!(
+                    "failed to describe {allocation_id} for the audit log '{}'",
+                    e
+                );
+                String::from("unknown")
+            }
+        }
+    }
+
+    /// Appends the record of the mutating call with its outcome, e.g.,
+    /// {"ts":1673000000,"caller":"arn:aws:sts::...","action":"associate","allocation_id":"eipalloc-...",
+    /// "before":"","after":"i-...","result":"success"}.
+    /// Returns an error if the record cannot be written, so that no change goes untracked.
+    /// The records of "HISTORY_ACTIONS" are the host history (see "query").
+    pub fn record<T>(
+        &self,
+        action: &str,
+        fields: &[(&str, &str)],
+        ret: &io::Result<T>,
+    ) -> io::Result<()> {
+        let log = self.log.lock().unwrap();
+        let log = match log.as_ref() {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        let mut m = Map::new();
+        m.insert(
+            String::from("ts"),
+            Value::from(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default(),
+            ),
+        );
+        m.insert(String::from("caller"), Value::from(log.caller.as_str()));
+        if !log.id.is_empty() {
+            m.insert(String::from("id"), Value::from(log.id.as_str()));
+        }
+        m.insert(String::from("action"), Value::from(action));
+        for (k, v) in fields {
+            m.insert(k.to_string(), Value::from(*v));
+        }
+        match ret {
+            Ok(_) => {
+                m.insert(String::from("result"), Value::from("success"));
+            }
+            Err(e) => {
+                m.insert(String::from("result"), Value::from("failure"));
+                m.insert(String::from("error"), Value::from(e.to_string()));
+            }
+        }
+
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log.file_path)?;
+        // single write per record, so concurrent writers do not interleave
+        f.write_all(format!("{}\n", Value::Object(m)).as_bytes())
+            .and_then(|_| f.sync_data())
+            .map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("failed to write audit log {} '{}'", log.file_path, e),
+                )
+            })
+    }
+}
+
+/// Record of the audit log, as read back for the host history.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Entry {
+    /// Seconds since the epoch.
+    pub ts: u64,
+    pub action: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub allocation_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub public_ip: String,
+    /// Association before the call (e.g., instance ID, ENI ID), empty if none.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub before: String,
+    /// Association after the call, empty if none.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub after: String,
+    /// "success" or "failure".
+    pub result: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub error: String,
+    /// ARN of the caller identity.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub caller: String,
+    /// "Id" tag value of the recording host.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+}
+
+/// Selects the history entries, matching all the non-empty fields.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub allocation_id: String,
+    pub public_ip: String,
+    pub action: String,
+    /// Seconds since the epoch, 0 for all.
+    pub since: u64,
+}
+
+impl Filter {
+    pub fn matches(&self, e: &Entry) -> bool {
+        (self.allocation_id.is_empty() || e.allocation_id == self.allocation_id)
+            && (self.public_ip.is_empty() || e.public_ip == self.public_ip)
+            && (self.action.is_empty() || e.action == self.action)
+            && e.ts >= self.since
+    }
+}
+
+/// Returns the entries of the audit log file matching the filter, oldest first,
+/// out of the records of "HISTORY_ACTIONS". The public IP of the later calls of an allocation is filled in from its
+/// allocation, so that the history of an address can be queried by its IP.
+/// A torn last line (e.g., the host crashed mid-write) is skipped.
+pub fn query(file_path: &str, filter: &Filter) -> io::Result<Vec<Entry>> {
+    let f = File::open(file_path).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("failed to open audit log {file_path} '{}'", e),
+        )
+    })?;
+    let mut ips = HashMap::new();
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(f).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut entry: Entry = match serde_json::from_str(&line) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("skipping malformed line {} of {file_path} '{}'", i + 1, e);
+                continue;
+            }
+        };
+        if !HISTORY_ACTIONS.contains(&entry.action.as_str()) {
+            continue;
+        }
+        if !entry.public_ip.is_empty() {
+            ips.insert(entry.allocation_id.clone(), entry.public_ip.clone());
+        } else if let Some(ip) = ips.get(&entry.allocation_id) {
+            entry.public_ip = ip.clone();
+        }
+        if filter.matches(&entry) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Span of time an association held the address, as recorded in the audit logs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Holding {
+    pub public_ip: String,
+    pub allocation_id: String,
+    /// Instance ID or ENI ID the address was associated with.
+    pub holder: String,
+    /// "Id" tag value of the host that associated the address.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    /// Audit log file that recorded the association.
+    pub audit_log_file: String,
+    /// Seconds since the epoch.
+    pub from: u64,
+    /// Seconds since the epoch, "None" if no audit log recorded the end yet.
+    pub until: Option<u64>,
+}
+
+impl Holding {
+    /// Returns true if the address was held at the time (seconds since the epoch).
+    pub fn held_at(&self, ts: u64) -> bool {
+        self.from <= ts && self.until.map(|until| ts < until).unwrap_or(true)
+    }
+}
+
+/// Returns the timeline of who held the address, oldest first, assembled from
+/// the entries of one or more audit logs (e.g., collected from every host of the
+/// fleet), each paired with its audit log file path. An association ends with the
+/// disassociation or release of the allocation, or with its next association,
+/// which may be recorded by another host (e.g., "--no-steal=false").
+/// The address is matched through the allocations recorded in any of the
+/// audit logs, so an EIP allocated outside the audit logs is only found by its
+/// allocation ID (see "query").
+pub fn timeline(public_ip: &str, audit_logs: &[(String, Vec<Entry>)]) -> Vec<Holding> {
+    let mut ips = HashMap::new();
+    for (_, entries) in audit_logs.iter() {
+        for e in entries.iter().filter(|e| !e.public_ip.is_empty()) {
+            ips.insert(e.allocation_id.clone(), e.public_ip.clone());
+        }
+    }
+    let mut entries: Vec<(&str, &Entry)> = audit_logs
+        .iter()
+        .flat_map(|(path, entries)| entries.iter().map(move |e| (path.as_str(), e)))
+        .filter(|(_, e)| e.result == "success")
+        .filter(|(_, e)| ips.get(&e.allocation_id).map(|ip| ip.as_str()) == Some(public_ip))
+        .collect();
+    // stable, so the calls of the same second keep their order in each audit log
+    entries.sort_by_key(|(_, e)| e.ts);
+
+    let mut holdings: Vec<Holding> = Vec::new();
+    let mut open: HashMap<String, usize> = HashMap::new();
+    for (path, e) in entries {
+        if e.action == "allocate" {
+            continue;
+        }
+        // e.g., the same host re-associating on every boot
+        if let Some(&i) = open.get(&e.allocation_id) {
+            if e.action == "associate" && holdings[i].holder == e.after {
+                continue;
+            }
+        }
+        if let Some(i) = open.remove(&e.allocation_id) {
+            holdings[i].until = Some(e.ts);
+        }
+        if e.action == "associate" && !e.after.is_empty() {
+            open.insert(e.allocation_id.clone(), holdings.len());
+            holdings.push(Holding {
+                public_ip: public_ip.to_string(),
+                allocation_id: e.allocation_id.clone(),
+                holder: e.after.clone(),
+                id: e.id.clone(),
+                audit_log_file: path.to_string(),
+                from: e.ts,
+                until: None,
+            });
+        }
+    }
+    holdings
+}
+
+/// Parses "--since" as the age (e.g., "90s", "30m", "24h", "7d") or the
+/// seconds since the epoch, returning the seconds since the epoch.
+pub fn parse_since(s: &str, now: u64) -> io::Result<u64> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Ok(0);
+    }
+    let invalid = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid '{s}' (expected e.g., '24h', '7d', or the seconds since the epoch)"),
+        )
+    };
+    let (n, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c),
+        _ => return s.parse::<u64>().map_err(|_| invalid()),
+    };
+    let n = n.parse::<u64>().map_err(|_| invalid())?;
+    let seconds = match unit {
+        's' => n,
+        'm' => n * 60,
+        'h' => n * 3600,
+        'd' => n * 86400,
+        _ => return Err(invalid()),
+    };
+    Ok(now.saturating_sub(seconds))
+}
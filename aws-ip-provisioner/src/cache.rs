@@ -0,0 +1,70 @@
+use std::{collections::HashMap, io};
+
+use aws_sdk_ec2::model::Address;
+use tokio::time::{Duration, Instant};
+
+use crate::provisioner::Ec2;
+
+/// Caches the DescribeAddresses and DescribeInstances results for a short TTL,
+/// so the reconcile loop does not call EC2 APIs on every tick.
+/// Must be invalidated after any mutation (associate, disassociate, tag).
+pub struct DescribeCache {
+    ttl: Duration,
+    addresses: HashMap<String, (Instant, Option<Address>)>,
+    instances: HashMap<String, (Instant, bool)>,
+}
+
+impl DescribeCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            addresses: HashMap::new(),
+            instances: HashMap::new(),
+        }
+    }
+
+    /// Describes the EIP by its allocation ID, served from cache if fresh.
+    pub async fn address(
+        &mut self,
+        ec2: &dyn Ec2,
+        allocation_id: &str,
+    ) -> io::Result<Option<Address>> {
+        if let Some((at, addr)) = self.addresses.get(allocation_id) {
+            if at.elapsed() < self.ttl {
+                log::debug!("describe cache hit for {allocation_id}");
+                return Ok(addr.clone());
+            }
+        }
+
+        let addr = ec2.describe_by_allocation_id(allocation_id).await?;
+        self.addresses
+            .insert(allocation_id.to_string(), (Instant::now(), addr.clone()));
+        Ok(addr)
+    }
+
+    /// Returns true if the instance is running, served from cache if fresh.
+    pub async fn is_instance_running(
+        &mut self,
+        ec2: &dyn Ec2,
+        instance_id: &str,
+    ) -> io::Result<bool> {
+        if let Some((at, running)) = self.instances.get(instance_id) {
+            if at.elapsed() < self.ttl {
+                log::debug!("describe cache hit for {instance_id}");
+                return Ok(*running);
+            }
+        }
+
+        let running = ec2.is_instance_running(instance_id).await?;
+        self.instances
+            .insert(instance_id.to_string(), (Instant::now(), running));
+        Ok(running)
+    }
+
+    /// Drops all cached results.
+    pub fn invalidate(&mut self) {
+        log::debug!("invalidating describe cache");
+        self.addresses.clear();
+        self.instances.clear();
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    time::Duration,
+};
+
+use aws_sdk_ec2::model::{Address, NetworkInterface};
+use tokio::time::sleep;
+
+use crate::{
+    ec2, eip,
+    provisioner::{BoxFuture, Ec2},
+};
+
+/// Delay of the "timeout" fault, long enough to trip the usual client timeouts.
+const TIMEOUT_DELAY: Duration = Duration::from_secs(30);
+
+/// Operations of the "Ec2" wrapper that faults can target ("*" for all).
+const OPERATIONS: &[&str] = &[
+    "describe",
+    "is_instance_running",
+    "is_ready_to_associate",
+    "allocate",
+    "create_tags",
+    "delete_tags",
+    "associate",
+    "assign_ipv6_address",
+    "release_address",
+    "disassociate",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Fault {
+    /// Fails with the probability.
+    Fail(f64),
+    /// Hangs for "TIMEOUT_DELAY", and then fails as timed out.
+    Timeout,
+    /// Delays the call, and then proceeds.
+    Delay(Duration),
+}
+
+/// Wraps the EC2 API calls to fail or delay as configured, to validate
+/// the retry config and the restart policies (e.g., systemd "Restart=on-failure").
+pub struct Chaos<'a> {
+    inner: &'a dyn Ec2,
+    faults: Vec<(String, Fault)>,
+}
+
+impl<'a> Chaos<'a> {
+    /// Parses the comma-separated faults "<operation>:<fault>", where the fault is
+    /// the failure probability (e.g., "allocate:0.3"), "timeout", or "delay=<seconds>".
+    pub fn new(inner: &'a dyn Ec2, spec: &str) -> io::Result<Self> {
+        let mut faults = Vec::new();
+        for s in spec.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+            let (op, fault) = s.split_once(':').ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid fault '{s}' (expected \"<operation>:<fault>\")"),
+                )
+            })?;
+            if op != "*" && !OPERATIONS.contains(&op) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown operation '{op}' (expected one of {OPERATIONS:?} or \"*\")"),
+                ));
+            }
+            let fault = if fault == "timeout" {
+                Fault::Timeout
+            } else if let Some(secs) = fault.strip_prefix("delay=") {
+                Fault::Delay(Duration::from_secs(secs.parse().map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid delay in '{s}' ({})", e),
+                    )
+                })?))
+            } else {
+                let p: f64 = fault.parse().map_err(|e| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid probability in '{s}' ({})", e),
+                    )
+                })?;
+                if !(0.0..=1.0).contains(&p) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("probability in '{s}' must be between 0 and 1"),
+                    ));
+                }
+                Fault::Fail(p)
+            };
+            faults.push((op.to_string(), fault));
+        }
+        if !faults.is_empty() {
+            log::warn!("injecting faults into EC2 API calls {:?}", faults);
+        }
+        Ok(Self { inner, faults })
+    }
+
+    /// Applies the faults of the operation, in order.
+    async fn inject(&self, op: &str) -> io::Result<()> {
+        for (_, fault) in self.faults.iter().filter(|(o, _)| o == op || o == "*") {
+            match fault {
+                Fault::Fail(p) => {
+                    if (random_manager::u32() as f64) < p * (u32::MAX as f64) {
+                        log::warn!("injecting failure into {op}");
+                        return Err(Error::new(
+                            ErrorKind::Other,
+                            format!("injected failure in {op}"),
+                        ));
+                    }
+                }
+                Fault::Timeout => {
+                    log::warn!("injecting timeout into {op}");
+                    sleep(TIMEOUT_DELAY).await;
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        format!("injected timeout in {op}"),
+                    ));
+                }
+                Fault::Delay(d) => {
+                    log::warn!("injecting delay {d:?} into {op}");
+                    sleep(*d).await;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Ec2 for Chaos<'_> {
+    fn describe_by_tags<'a>(
+        &'a self,
+        tags: &'a [(&'a str, &'a str)],
+    ) -> BoxFuture<'a, Vec<Address>> {
+        Box::pin(async move {
+            self.inject("describe").await?;
+            self.inner.describe_by_tags(tags).await
+        })
+    }
+
+    fn describe_by_allocation_id<'a>(
+        &'a self,
+        allocation_id: &'a str,
+    ) -> BoxFuture<'a, Option<Address>> {
+        Box::pin(async move {
+            self.inject("describe").await?;
+            self.inner.describe_by_allocation_id(allocation_id).await
+        })
+    }
+
+    fn is_instance_running<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            self.inject("is_instance_running").await?;
+            self.inner.is_instance_running(instance_id).await
+        })
+    }
+
+    fn is_ready_to_associate<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(async move {
+            self.inject("is_ready_to_associate").await?;
+            self.inner.is_ready_to_associate(instance_id).await
+        })
+    }
+
+    fn describe_network_interfaces<'a>(
+        &'a self,
+        instance_id: &'a str,
+    ) -> BoxFuture<'a, Vec<NetworkInterface>> {
+        Box::pin(async move {
+            self.inject("describe").await?;
+            self.inner.describe_network_interfaces(instance_id).await
+        })
+    }
+
+    fn assign_ipv6_address<'a>(&'a self, network_interface_id: &'a str) -> BoxFuture<'a, String> {
+        Box::pin(async move {
+            self.inject("assign_ipv6_address").await?;
+            self.inner.assign_ipv6_address(network_interface_id).await
+        })
+    }
+
+    fn allocate<'a>(
+        &'a self,
+        id_tag: (&'a str, &'a str),
+        kind_tag: (&'a str, &'a str),
+        client_token: &'a str,
+        public_ipv4_pool: &'a str,
+    ) -> BoxFuture<'a, ec2::Eip> {
+        Box::pin(async move {
+            self.inject("allocate").await?;
+            self.inner
+                .allocate(id_tag, kind_tag, client_token, public_ipv4_pool)
+                .await
+        })
+    }
+
+    fn create_tags<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        tags: Vec<(String, String)>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            self.inject("create_tags").await?;
+            self.inner.create_tags(allocation_id, tags).await
+        })
+    }
+
+    fn delete_tags<'a>(&'a self, allocation_id: &'a str, keys: Vec<String>) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            self.inject("delete_tags").await?;
+            self.inner.delete_tags(allocation_id, keys).await
+        })
+    }
+
+    fn release_address<'a>(&'a self, allocation_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            self.inject("release_address").await?;
+            self.inner.release_address(allocation_id).await
+        })
+    }
+
+    fn disassociate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        association_id: &'a str,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            self.inject("disassociate").await?;
+            self.inner.disassociate(allocation_id, association_id).await
+        })
+    }
+
+    fn associate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        instance_id: &'a str,
+        target: &'a eip::Target,
+        allow_reassociation: bool,
+    ) -> BoxFuture<'a, String> {
+        Box::pin(async move {
+            self.inject("associate").await?;
+            self.inner
+                .associate(allocation_id, instance_id, target, allow_reassociation)
+                .await
+        })
+    }
+}
@@ -0,0 +1,128 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Error, ErrorKind},
+    path::Path,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+const VERSION: u32 = 1;
+
+/// Progress of a fleet command (e.g., "inventory --org"), saved after every
+/// unit of work (e.g., an account or a region) so that an interrupted run
+/// resumes where it left off, rather than starting over on a large estate.
+/// Only the succeeded units are recorded, so that the resumed run retries
+/// the failed ones.
+pub struct Checkpoint {
+    /// Empty to keep the progress in memory only.
+    path: String,
+    file: File,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct File {
+    version: u32,
+    /// Command and the parameters that shape its results (e.g., the tag filter),
+    /// so that a checkpoint of another run is never resumed.
+    command: String,
+    /// Results of the done units, keyed by the unit.
+    done: BTreeMap<String, Value>,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint file, resuming it if it was written by the same
+    /// command, or starting over otherwise.
+    pub fn open(path: &str, command: &str) -> io::Result<Self> {
+        let fresh = File {
+            version: VERSION,
+            command: command.to_string(),
+            done: BTreeMap::new(),
+        };
+        if path.is_empty() || !Path::new(path).exists() {
+            return Ok(Self {
+                path: path.to_string(),
+                file: fresh,
+            });
+        }
+
+        let file: File = serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid checkpoint file {path} '{}'", e),
+            )
+        })?;
+        if file.version != VERSION || file.command != command {
+            log::warn!(
+                "checkpoint file {path} is of another run ('{}') -- starting over",
+                file.command
+            );
+            return Ok(Self {
+                path: path.to_string(),
+                file: fresh,
+            });
+        }
+        log::info!(
+            "resuming from checkpoint file {path} ({} done)",
+            file.done.len()
+        );
+        Ok(Self {
+            path: path.to_string(),
+            file,
+        })
+    }
+
+    /// Returns the number of the done units.
+    pub fn done(&self) -> usize {
+        self.file.done.len()
+    }
+
+    /// Returns the recorded result of the unit, "None" if not done yet.
+    pub fn get<T: DeserializeOwned>(&self, unit: &str) -> Option<T> {
+        let v = self.file.done.get(unit)?;
+        match serde_json::from_value(v.clone()) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                log::warn!("ignoring checkpoint of {unit} '{}'", e);
+                None
+            }
+        }
+    }
+
+    /// Records the result of the done unit, and saves the checkpoint file.
+    pub fn record<T: Serialize>(&mut self, unit: &str, result: &T) -> io::Result<()> {
+        let v = serde_json::to_value(result).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize checkpoint {}", e),
+            )
+        })?;
+        self.file.done.insert(unit.to_string(), v);
+        self.save()
+    }
+
+    /// Removes the checkpoint file once all the units are done.
+    pub fn finish(&self) -> io::Result<()> {
+        if !self.path.is_empty() && Path::new(&self.path).exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if self.path.is_empty() {
+            return Ok(());
+        }
+        let d = serde_json::to_string_pretty(&self.file).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize checkpoint {}", e),
+            )
+        })?;
+        // renamed, so that an interruption mid-write never loses the progress
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, d)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
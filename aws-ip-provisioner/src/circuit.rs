@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use tokio::time::{Duration, Instant};
+
+use crate::metrics;
+
+/// Gauge that is 1 while the circuit is open, 0 otherwise.
+pub const OPEN_GAUGE: &str = "circuit_breaker_open";
+
+/// Stops calling AWS/IMDS after the consecutive failures reach the threshold,
+/// and resumes after the cool-down period (half-open: one trial call).
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cool_down: Duration,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    /// True from opening until the next successful call, including the
+    /// half-open trial.
+    opened: bool,
+    metrics: Arc<metrics::Registry>,
+}
+
+impl CircuitBreaker {
+    pub fn new(
+        failure_threshold: u32,
+        cool_down: Duration,
+        metrics: Arc<metrics::Registry>,
+    ) -> Self {
+        metrics.set_gauge(OPEN_GAUGE, 0.0);
+        Self {
+            failure_threshold,
+            cool_down,
+            consecutive_failures: 0,
+            open_until: None,
+            opened: false,
+            metrics,
+        }
+    }
+
+    /// Returns true if the call is allowed.
+    pub fn allow(&mut self) -> bool {
+        match self.open_until {
+            Some(until) if Instant::now() < until => false,
+            Some(_) => {
+                log::info!("circuit cool-down elapsed -- allowing a trial call");
+                self.open_until = None;
+                true
+            }
+            None => true,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        if self.opened {
+            log::info!("circuit closed after a successful call");
+            self.opened = false;
+        }
+        self.consecutive_failures = 0;
+        self.metrics.set_gauge(OPEN_GAUGE, 0.0);
+    }
+
+    pub fn record_failure(&mut self, reason: &str) {
+        self.consecutive_failures += 1;
+        if self.failure_threshold == 0 || self.consecutive_failures < self.failure_threshold {
+            return;
+        }
+        log::warn!(
+            "opening circuit for {:?} after {} consecutive failures (last error '{reason}')",
+            self.cool_down,
+            self.consecutive_failures
+        );
+        self.open_until = Some(Instant::now() + self.cool_down);
+        self.opened = true;
+        self.metrics.set_gauge(OPEN_GAUGE, 1.0);
+    }
+}
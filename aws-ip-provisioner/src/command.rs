@@ -2,16 +2,34 @@ use std::{
     env,
     io::{self, Error, ErrorKind},
     path::Path,
+    sync::Arc,
 };
 
-use aws_manager::{self, ec2};
-use clap::{crate_version, value_parser, Arg, Command};
-use tokio::time::{sleep, Duration};
+#[cfg(feature = "chaos")]
+use crate::chaos;
+#[cfg(feature = "consul")]
+use crate::consul;
+use crate::{
+    alert, config,
+    context::Context,
+    daemon, dns, drain, ec2, firewall, hook, hostname, identity,
+    imds::{self, Imds},
+    lifecycle, logging, notify, pipeline, platform, progress,
+    provisioner::{AwsEc2, BoxFuture, Ec2, ImdsMetadata, Provisioner, SystemClock, SystemRng},
+    reachability, route53, sdk, security_group,
+    state::{self, State},
+    stun, summary, textfile,
+    transfer::Transfer,
+    wireguard,
+};
+use aws_manager::autoscaling;
+use clap::{crate_version, value_parser, Arg, ArgMatches, Command};
+use tokio::time::{Duration, Instant};
 
 pub const NAME: &str = "aws-ip-provisioner";
 
 pub fn new() -> Command {
-    Command::new(NAME)
+    let cmd = Command::new(NAME)
         .version(crate_version!())
         .about("Provisions the Elastic IP to the local EC2 instance")
         .long_about(
@@ -22,7 +40,164 @@ The EC2 instance is automatically fetched.
 
 Commands may run multiple times with idempotency.
 
-Requires IAM instance role of: ec2:AllocateAddress, ec2:AssociateAddress, and ec2:DescribeAddresses.
+Requires IAM instance role of: ec2:AllocateAddress, ec2:AssociateAddress, ec2:DescribeAddresses, and ec2:DescribeInstances.
+
+The \"daemon\" mode provisions the EIP and keeps running to reconcile the association,
+and to watch for spot interruption notices and rebalance recommendations, and then releases the EIP back to the pool,
+or swaps it to a standby instance (same \"Kind\" tag, and \"Standby=true\" tag).
+
+The \"watch\" mode only watches the EIP already provisioned in the mounted EIP file,
+and re-associates it if silently detached (e.g., instance stop/start), logging a warning event
+and incrementing the \"eip_reassociated_total\" counter.
+
+With \"--config-file\", the \"daemon\" and \"watch\" modes re-read the file on SIGHUP
+and apply the changed settings (e.g., intervals, hook commands, kind tags) without restarting.
+The file is a JSON object keyed by the long flag names (e.g., {\"reconcile-interval-seconds\": 60}),
+and is also applied at start, overriding the flags.
+
+\"--alert-provider\" pages after \"--alert-failure-threshold\" consecutive reconcile failures
+in the \"daemon\" and \"watch\" modes (PagerDuty Events API v2, or Opsgenie), deduplicated
+on the instance ID and the \"Id\" tag value, and resolves the alert on the next successful
+reconcile. The routing key (or API key) is read from \"--alert-key-source\" (e.g.,
+\"ssm:/ip-manager/pagerduty-routing-key\"), by default PAGERDUTY_ROUTING_KEY or OPSGENIE_API_KEY.
+
+\"--slack-webhook-source\" and \"--discord-webhook-source\" post the progress events (e.g.,
+\"allocated\", \"associated\", \"released\", and \"failed\", chosen per channel with \"--slack-events\"
+and \"--discord-events\") to the chat webhook, in the background so that a slow webhook never
+delays the provisioning, regardless of \"--progress\".
+\"--on-phase-cmd\" runs the local command at every progress event instead, with the event name
+(\"{phase}\") and the event as JSON (\"{payload}\", as \"--progress=ndjson\" prints it), for the
+hosts without outbound metrics to report to the local supervisor or telemetry agent
+(e.g., --on-phase-cmd='/usr/local/bin/report-phase {phase} {payload}').
+
+On Windows, the mounted EIP file defaults to \"C:\\ProgramData\\ip-manager\\eip.yaml\",
+the config file is reloaded on Ctrl+Break instead of SIGHUP, the hostname is set with
+\"Rename-Computer\" (effective after the restart), and the firewall backends and the
+\"syslog\" and \"journald\" log targets are not available (\"ip-manager generate-windows-task\"
+renders the scheduled task to run it at startup).
+
+The \"terminate-hook\" mode waits for the auto scaling termination lifecycle state,
+and returns the EIP to the pool (disassociate and re-tag) rather than releasing it,
+so that the replacement instance can claim the same address.
+It additionally requires ec2:DisassociateAddress, ec2:CreateTags, ec2:DescribeTags,
+and autoscaling:CompleteLifecycleAction.
+
+\"--post-associate-cmd\" and \"--post-release-cmd\" run after the respective phases
+(e.g., firewall updates, service restarts), with the EIP in the templated arguments
+and the \"IP_MANAGER_PUBLIC_IP\", \"IP_MANAGER_ALLOCATION_ID\", \"IP_MANAGER_INSTANCE_ID\",
+and \"IP_MANAGER_PHASE\" env vars.
+
+\"--firewall-backend\" installs the rules templated from \"--firewall-rules-file\"
+(e.g., DNAT to \"{private_ip}\" for NAT instances) after association, and removes them on release.
+
+\"--set-hostname-from-dns\" and \"--update-etc-hosts\" point the hostname and the hosts file
+to the DNS name of the EIP (PTR record, or the public DNS name), for software that advertises
+its own address. It additionally requires ec2:DescribeAddressesAttribute.
+
+\"--summary-path\" writes the outcome (\"succeeded\", \"skipped\", or \"failed\"), the resource IDs,
+the per-phase durations, the retries, and the warnings of the run as JSON at exit, for the bake
+pipelines and the fleet orchestrators. See \"ip-manager generate-schema summary\" for the schema.
+When the retries of an operation (e.g., IMDS) are exhausted, the summary and the final error
+include every attempt (timestamp, error kind, backoff), and the exit status is 75 (EX_TEMPFAIL)
+rather than 1, to retry the run later rather than treat it as a misconfiguration.
+\"--metrics-textfile-dir\" writes the same outcome, durations, and retries, with the gauges and
+counters of the run, as \"aws_ip_provisioner.prom\" for the node_exporter textfile collector, so
+that the fleets already scraping node_exporter get the provisioning metrics of the one-shot runs.
+
+\"--pool-lease-seconds\" tags the EIP claimed from the pool with \"ClaimedBy\" (the instance ID)
+and \"ClaimExpiresAt\", renewed in \"daemon\" mode. A claim whose lease expired while the EIP is
+not associated (e.g., the instance crashed before associating) is reclaimed by the next
+claimant, while the associated claims are never taken over.
+
+\"--pool-min-free\" and \"--pool-max-free\" autoscale the pool of the \"Kind\" in \"daemon\" mode,
+on every reconcile: pre-allocating the unassociated pool-available EIPs up to the minimum
+(until the EIP quota), and releasing the newest down to the maximum, with the \"pool_scaled\"
+progress event. Enable it on one daemon per \"Kind\" (e.g., a dedicated instance), as the
+daemons do not coordinate. It additionally requires ec2:ReleaseAddress.
+
+\"--sync-security-group-id\" keeps the ingress rules of the security group in lockstep with the
+associated EIPs of the \"Kind\": one rule per \"--port-ranges\" entry (e.g., \"tcp:30303,udp:30303\")
+from each \"<public-ip>/32\", described as \"ip-manager:<Id>\", after association and on every
+reconcile in \"daemon\" mode. The rules of the released EIPs are revoked, and the rules added by
+hand are never touched. It additionally requires ec2:DescribeSecurityGroupRules,
+ec2:AuthorizeSecurityGroupIngress, and ec2:RevokeSecurityGroupIngress.
+
+\"--drain-cmd\" and/or \"--drain-url\" signal the application before the EIP moves away on
+interruption (\"--on-interruption=swap\" to the standby, or \"release\"), and \"--drain-port\" waits
+for its established connections to close, up to \"--drain-timeout-seconds\", so that the move
+is coordinated with the workload rather than cutting the connections mid-traffic. Keep the
+deadline well within the two-minute spot interruption notice.
+
+\"--state-backend=consul\" mirrors the mounted EIP file in Consul KV (\"<prefix>/state/<Id>\"),
+restoring it when the file is missing (e.g., the replacement instance with a fresh volume), and
+claims the \"Id\" with a Consul session lock (\"<prefix>/claims/<Id>\") while provisioning, so that
+two instances of the same \"Id\" never provision at once. \"--consul-service-name\" registers the
+EIP as the service of the local Consul agent after association (with the TCP health check of
+\"--consul-service-port\" on the private IP), and deregisters it on release.
+
+\"--namespace\" runs multiple instances on one host (e.g., one for the public EIP, one for
+an internal VIP) without clobbering each other: it prefixes the \"Id\" and \"Kind\" tag values
+and the mounted EIP file name with \"<namespace>-\", the metric names with \"<namespace>_\",
+and owns its own hosts file block and iptables rule comment. The nftables tables are named
+in \"--firewall-rules-file\", so keep them distinct per namespace.
+
+\"--desired-tags\" converges the tags of the EIP on every reconcile in \"daemon\" and \"watch\" mode,
+adding the missing and correcting the changed ones, so that a tag policy change rolls out by
+updating the flag (or the config file) rather than retagging the addresses by hand.
+\"--remove-unknown-tags\" also deletes the other tags, but the \"Id\" and \"Kind\" tags, the tool's
+own tags (e.g., \"ClientToken\", \"PoolStatus\"), and the reserved \"aws:\" tags. It additionally
+requires ec2:DeleteTags.
+
+A throttled AWS API call (e.g., \"RequestLimitExceeded\") is logged with \"throttled=<code>\" and
+counted in \"aws_api_throttled_total\", and halves the client-side rate (\"aws_api_adaptive_rps\"),
+which then recovers additively with every successful call up to \"--max-api-rps\", so that
+the fleet members back off together rather than escalating the throttling with retries.
+
+\"--route53-zone-id\" upserts the A record \"--route53-record-name\" of the EIP in the hosted zone, and
+\"--route53-private-zone-id\" the A record \"--route53-private-record-name\" of the private IP in the
+private hosted zone (e.g., an internal name of the same node), each with its own TTL, before
+\"--post-associate-cmd\". Requires \"route53:ChangeResourceRecordSets\" on the zones.
+For the DNS-level failover of the multi-node deployments, \"--route53-health-check\" (e.g., \"tcp:9651\")
+creates a Route53 health check against the EIP (\"route53:CreateHealthCheck\", idempotent per EIP)
+for the record, and \"--route53-routing\" upserts it as the failover primary or secondary, weighted,
+or latency record of \"--route53-set-identifier\". The health check of the previous EIP is not deleted.
+
+\"--verify-dns-name\" waits after \"--post-associate-cmd\" (e.g., a DNS record upsert) until the name
+resolves to the EIP on \"--verify-dns-resolvers\", so that the later boot steps do not race the update.
+
+\"--verify-reachability\" (e.g., \"tcp://:9651\") then asks the reflector of \"--reachability-service-url\"
+to connect to each port of the EIP from outside the VPC, until \"--verify-reachability-timeout-seconds\",
+failing the run if the EIP attached but the security groups or the network ACLs still block the traffic.
+The reflector answers \"POST <url>?protocol=tcp&host=<ip>&port=<port>\", with the signed instance identity
+document as the body, with {\"reachable\": bool, \"error\": ...}; \"ip-manager serve --reachability-reflector\"
+serves it, admitting the instance as for \"/v1/eip\" and probing only the instance's own public IP.
+
+\"--stun-server\" discovers the public IP that the traffic of the instance actually leaves with
+(STUN Binding, RFC 5389), and cross-checks it with the EIP after association and on every
+reconcile, catching what IMDS does not reveal (e.g., a NAT gateway in the route of the subnet,
+or the EIP associated elsewhere): the discrepancy is reported as a summary warning (with the
+\"effective_public_ip\" resource) and the \"eip_public_ip_mismatch\" gauge, never failing the run.
+
+\"--wireguard-peers\" then points the WireGuard peers of the mesh to the EIP (\"<eip>:<listen port>\")
+after every association (e.g., the rotation or the failover in \"daemon\" mode), with \"wg set\" over
+SSH (\"ssh://root@10.0.1.5\"), by rewriting the \"Endpoint\" of the peer in the config file
+(\"file:/etc/wireguard/peers/wg0.conf\"), or by POSTing {\"interface\", \"public_key\", \"endpoint\"}
+to the mesh controller (\"https://...\"). The peers that fail are reported as summary warnings
+(and \"wireguard_peer_update_failures_total\"), never failing the run.
+
+An IPv6-only instance has no IPv4 to map an EIP to, so it fails with the guidance by default.
+With \"--ipv6-only=assign-ipv6\", it skips the EIP, ensures a global IPv6 address on the primary
+network interface, and runs \"--post-associate-cmd\" with \"{ipv6}\" (e.g., to update the DNS
+AAAA record). It additionally requires ec2:DescribeNetworkInterfaces and ec2:AssignIpv6Addresses.
+
+The mounted EIP file records the account and the region of the EIP. If the volume is re-attached
+to an instance of another account or region, where the EIP cannot be used, it fails by default
+rather than calling EC2 with the stale allocation ID. \"--on-foreign-state=reallocate\" replaces
+the EIP (recovered by the tags, claimed from the pool, or allocated), and \"ignore\" uses it anyway.
+
+\"--dual-stack\" ensures a global IPv6 address on the network interface of the EIP in the same run,
+records it next to the EIP in the mounted EIP file, and passes both to the hook (\"{public_ip}\"
+and \"{ipv6}\", for the DNS A and AAAA records) and the hosts file. It requires the same actions.
 
 e.g.,
 
@@ -33,28 +208,885 @@ $ aws-ip-provisioner \
 --id-tag-value=TEST-ID \
 --kind-tag-key=Kind \
 --kind-tag-value=aws-ip-provisioner \
+--mounted-eip-file-path=/data/eip.yaml \
+--post-associate-cmd=\"/usr/local/bin/update-dns.sh {public_ip}\"
+
+$ aws-ip-provisioner \
+--log-level=info \
+--mode=terminate-hook \
+--lifecycle-hook-name=TEST-TERMINATE-HOOK \
+--id-tag-key=Id \
+--id-tag-value=TEST-ID \
+--kind-tag-key=Kind \
+--kind-tag-value=aws-ip-provisioner \
 --mounted-eip-file-path=/data/eip.yaml
 
 ",
         )
+        .args(global_args());
+    with_args(cmd)
+}
+
+/// Returns the flags shared by all providers
+/// (set "global" in the "ip-manager" subcommand tree).
+pub fn global_args() -> Vec<Arg> {
+    vec![
+        Arg::new("LOG_LEVEL")
+            .long("log-level")
+            .short('l')
+            .help("Sets the log level")
+            .required(false)
+            .num_args(1)
+            .value_parser(["trace", "debug", "info", "warn", "error"])
+            .default_value("info"),
+        Arg::new("LOG_FILTER")
+            .long("log-filter")
+            .help("Sets the comma-separated per-module log levels on top of \"--log-level\" (e.g., \"aws_smithy_http=trace,aws_ip_provisioner::sdk=debug\" to trace the AWS HTTP layer and log the request ID of every call)")
+            .required(false)
+            .num_args(1),
+        Arg::new("LOG_TARGET")
+            .long("log-target")
+            .help("Sets where to write the logs (\"syslog\" for /dev/log, \"journald\" for the journal with the structured fields)")
+            .required(false)
+            .num_args(1)
+            .value_parser(["stderr", "syslog", "journald"])
+            .default_value("stderr"),
+        Arg::new("OUTPUT")
+            .long("output")
+            .short('o')
+            .help("Sets the output format of the provisioned address (\"text\" for logs only, \"json\" to print to stdout)")
+            .required(false)
+            .num_args(1)
+            .value_parser(["text", "json"])
+            .default_value("text"),
+        Arg::new("STATE_BACKEND")
+            .long("state-backend")
+            .help("Sets where to persist the provisioned address (\"file\" for the mounted file path, \"consul\" to also mirror it in Consul KV and claim the Id with a Consul session)")
+            .required(false)
+            .num_args(1)
+            .value_parser(["file", "consul"])
+            .default_value("file"),
+    ]
+}
+
+/// Adds the EIP provisioner flags to the command, except the global flags.
+pub fn with_args(cmd: Command) -> Command {
+    let cmd = cmd.arg(
+            Arg::new("MODE")
+                .long("mode")
+                .help("Sets the run mode (\"provision\" to allocate and associate, \"daemon\" to keep watching interruptions after provision, \"watch\" to only re-associate the provisioned EIP if detached, \"terminate-hook\" to return the EIP to the pool on scale-in)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["provision", "daemon", "watch", "terminate-hook"])
+                .default_value("provision"),
+        )
+        .arg(
+            Arg::new("PROGRESS")
+                .long("progress")
+                .help("Sets the progress event format (\"ndjson\" to print one JSON event per phase transition to stdout)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["none", "ndjson"])
+                .default_value("none"),
+        )
+        .arg(
+            Arg::new("SLACK_WEBHOOK_SOURCE")
+                .long("slack-webhook-source")
+                .help("Sets where to read the Slack incoming webhook URL to notify the events to (\"env\" for SLACK_WEBHOOK_URL, \"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\", empty to disable)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("SLACK_EVENTS")
+                .long("slack-events")
+                .help("Sets the comma-separated events to notify to Slack (e.g., \"associated,failed\")")
+                .required(false)
+                .num_args(1)
+                .default_value(notify::DEFAULT_EVENTS),
+        )
+        .arg(
+            Arg::new("DISCORD_WEBHOOK_SOURCE")
+                .long("discord-webhook-source")
+                .help("Sets where to read the Discord webhook URL to notify the events to (\"env\" for DISCORD_WEBHOOK_URL, \"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\", empty to disable)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("DISCORD_EVENTS")
+                .long("discord-events")
+                .help("Sets the comma-separated events to notify to Discord (e.g., \"associated,failed\")")
+                .required(false)
+                .num_args(1)
+                .default_value(notify::DEFAULT_EVENTS),
+        )
+        .arg(
+            Arg::new("LIFECYCLE_HOOK_NAME")
+                .long("lifecycle-hook-name")
+                .help("Sets the auto scaling termination lifecycle hook name to complete (only used for \"terminate-hook\" mode)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("ON_INTERRUPTION")
+                .long("on-interruption")
+                .help("Sets the action on spot interruption or rebalance recommendation (only used for \"daemon\" mode)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["release", "swap", "noop"])
+                .default_value("release"),
+        )
+        .arg(
+            Arg::new("WATCH_INTERVAL_SECONDS")
+                .long("watch-interval-seconds")
+                .help("Sets the interval in seconds to poll instance metadata (only used for \"daemon\" mode)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("RECONCILE_INTERVAL_SECONDS")
+                .long("reconcile-interval-seconds")
+                .help("Sets the interval in seconds to reconcile the EIP association (only used for \"daemon\" and \"watch\" modes)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("CONFIG_FILE")
+                .long("config-file")
+                .help("Sets the JSON file of the settings to apply at start and to reload on SIGHUP (only used for \"daemon\" and \"watch\" modes, empty to disable)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("TIMING_REPORT_PATH")
+                .long("timing-report-path")
+                .help("Sets the file to write the per-phase durations (random wait, IMDS, allocate, describe, associate, DNS) of the provisioning to as JSON (empty to only log the summary)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("SUMMARY_PATH")
+                .long("summary-path")
+                .help("Sets the file to write the summary of the run (outcome, resource IDs, durations, retries, warnings) to as JSON at exit, in the schema of \"ip-manager generate-schema summary\" (empty to disable)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("METRICS_TEXTFILE_DIR")
+                .long("metrics-textfile-dir")
+                .help("Sets the node_exporter textfile collector directory to write the metrics of the run (outcome, durations, retries, and the gauges and counters) to at exit as \"aws_ip_provisioner.prom\" (empty to disable)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("AUDIT_LOG_FILE")
+                .long("audit-log-file")
+                .help("Sets the file to append the audit log of every allocate, associate, release, and retag call to, as JSON lines with the caller identity and the \"Id\" tag value, queried with \"ip-manager history\" (e.g., \"/var/lib/ip-manager/audit.jsonl\", empty to disable)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("WATCH_SOURCE")
+                .long("watch-source")
+                .help("Sets how to check the EIP association (\"describe\" for DescribeAddresses, \"imds\" for the instance metadata public-ipv4, only used for \"watch\" mode)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["describe", "imds"])
+                .default_value("describe"),
+        )
+        .arg(
+            Arg::new("DESCRIBE_CACHE_TTL_SECONDS")
+                .long("describe-cache-ttl-seconds")
+                .help("Sets the TTL in seconds to cache describe API results (only used for \"daemon\" and \"watch\" modes)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("10"),
+        )
+        .arg(
+            Arg::new("CIRCUIT_FAILURE_THRESHOLD")
+                .long("circuit-failure-threshold")
+                .help("Sets the number of consecutive reconcile failures to open the circuit (only used for \"daemon\" and \"watch\" modes, 0 to disable)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("CIRCUIT_COOL_DOWN_SECONDS")
+                .long("circuit-cool-down-seconds")
+                .help("Sets the seconds to back off reconcile once the circuit opens (only used for \"daemon\" and \"watch\" modes)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("300"),
+        )
+        .arg(
+            Arg::new("ALERT_PROVIDER")
+                .long("alert-provider")
+                .help("Sets where to page on persistent reconcile failures (only used for \"daemon\" and \"watch\" modes)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["none", "pagerduty", "opsgenie"])
+                .default_value("none"),
+        )
+        .arg(
+            Arg::new("ALERT_KEY_SOURCE")
+                .long("alert-key-source")
+                .help("Sets where to read the PagerDuty routing key or the Opsgenie API key (\"env\" for PAGERDUTY_ROUTING_KEY or OPSGENIE_API_KEY, \"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\")")
+                .required(false)
+                .num_args(1)
+                .default_value("env"),
+        )
+        .arg(
+            Arg::new("ALERT_URL")
+                .long("alert-url")
+                .help("Sets the alert API URL (empty for the provider's, e.g., \"https://api.eu.opsgenie.com/v2/alerts\" for the Opsgenie EU instance)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ALERT_FAILURE_THRESHOLD")
+                .long("alert-failure-threshold")
+                .help("Sets the number of consecutive reconcile failures to trigger the alert")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("3"),
+        )
+        .arg(
+            Arg::new("SECRET_CACHE_TTL_SECONDS")
+                .long("secret-cache-ttl-seconds")
+                .help("Sets the TTL in seconds to cache the credentials read from SSM or Secrets Manager (e.g., the alert key), dropped early if the provider rejects them")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("300"),
+        )
+        .arg(
+            Arg::new("NO_STEAL")
+                .long("no-steal")
+                .help("Aborts rather than re-associating the EIP that is attached to another running instance or ENI")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("true"),
+        )
+        .arg(
+            Arg::new("DESIRED_TAGS")
+                .long("desired-tags")
+                .help("Sets the comma-separated tags (\"key=value\") to converge the EIP to on every reconcile (e.g., \"Team=infra,CostCenter=1234\")")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("REMOVE_UNKNOWN_TAGS")
+                .long("remove-unknown-tags")
+                .help("Removes the EIP tags other than \"--desired-tags\" and the tool's own tags on every reconcile")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("false"),
+        )
+        .arg(
+            Arg::new("SKIP_IF_PUBLIC_IP")
+                .long("skip-if-public-ip")
+                .help("Exits successfully without provisioning if the instance already has a public IPv4 other than the EIP in the state file (e.g., auto-assigned public IP)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("false"),
+        )
+        .arg(
+            Arg::new("DUAL_STACK")
+                .long("dual-stack")
+                .help("Manages an IPv6 address on the network interface of the EIP as well, recording both in the mounted EIP file, and mapping both in the hook (\"{ipv6}\") and the hosts file")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("false"),
+        )
+        .arg(
+            Arg::new("MAX_PARALLEL_ADDRESS_OPS")
+                .long("max-parallel-address-ops")
+                .help("Sets the maximum number of the independent address operations in flight at once (e.g., the EIP association and the \"--dual-stack\" IPv6 assignment), 1 to run them one by one")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32).range(1..))
+                .default_value("4"),
+        )
+        .arg(
+            Arg::new("IPV6_ONLY")
+                .long("ipv6-only")
+                .help("Sets what to do on an IPv6-only instance that has no IPv4 to map an EIP to (\"fail\" with the guidance, or \"assign-ipv6\" to skip the EIP and ensure an IPv6 address on the primary network interface)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["fail", "assign-ipv6"])
+                .default_value("fail"),
+        )
+        .arg(
+            Arg::new("ON_FOREIGN_STATE")
+                .long("on-foreign-state")
+                .help("Sets what to do when the mounted EIP file is of another account or region (\"fail\", \"ignore\" to use the EIP anyway, or \"reallocate\" to replace it)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["fail", "ignore", "reallocate"])
+                .default_value("fail"),
+        )
+        .arg(
+            Arg::new("CONFLICT_POLICY")
+                .long("conflict-policy")
+                .help("Sets how to pick one when multiple tool-tagged EIPs match (\"oldest\", \"newest\", \"fail\", or \"interactive\")")
+                .required(false)
+                .num_args(1)
+                .value_parser(["oldest", "newest", "fail", "interactive"])
+                .default_value("oldest"),
+        )
+        .arg(
+            Arg::new("POOL_LEASE_SECONDS")
+                .long("pool-lease-seconds")
+                .help("Sets the lease in seconds of the EIP claimed from the pool, renewed in \"daemon\" mode, after which an unassociated claim is reclaimed by the others (0 for claims that never expire)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u64))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("POOL_MIN_FREE")
+                .long("pool-min-free")
+                .help("Sets the number of unassociated pool-available EIPs to pre-allocate up to, in \"daemon\" mode (0 to disable)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("POOL_MAX_FREE")
+                .long("pool-max-free")
+                .help("Sets the number of unassociated pool-available EIPs to release down to, in \"daemon\" mode (0 for no upper bound)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("SYNC_SECURITY_GROUP_ID")
+                .long("sync-security-group-id")
+                .help("Sets the security group whose ingress rules to sync with the fleet's EIPs (empty to disable)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("PORT_RANGES")
+                .long("port-ranges")
+                .help("Sets the comma-separated ports to allow from the fleet's EIPs in \"--sync-security-group-id\" (e.g., tcp:30303,udp:30303,tcp:26656-26657)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("CONSUL_ADDRESS")
+                .long("consul-address")
+                .help("Sets the HTTP API address of the Consul agent for \"--state-backend=consul\" and \"--consul-service-name\" (the ACL token from CONSUL_HTTP_TOKEN)")
+                .required(false)
+                .num_args(1)
+                .default_value("http://127.0.0.1:8500"),
+        )
+        .arg(
+            Arg::new("CONSUL_KV_PREFIX")
+                .long("consul-kv-prefix")
+                .help("Sets the Consul KV prefix of the states (\"<prefix>/state/<Id>\") and the claims (\"<prefix>/claims/<Id>\")")
+                .required(false)
+                .num_args(1)
+                .default_value("ip-manager"),
+        )
+        .arg(
+            Arg::new("CONSUL_SERVICE_NAME")
+                .long("consul-service-name")
+                .help("Sets the Consul service to register the EIP as, after association (empty to disable)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("CONSUL_SERVICE_PORT")
+                .long("consul-service-port")
+                .help("Sets the port of the Consul service, health-checked over TCP on the private IP (0 for no port and no check)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u16))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("MAX_API_RPS")
+                .long("max-api-rps")
+                .help("Sets the maximum number of AWS API requests per second shared across all operations (0 to disable)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("AWS_PROFILE")
+                .long("aws-profile")
+                .help("Sets the named profile for AWS credentials and region (defaults to the instance role)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("ROLE_ARN")
+                .long("role-arn")
+                .help("Sets the IAM role ARN to assume with the web identity token (e.g., IAM roles for service accounts)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("WEB_IDENTITY_TOKEN_FILE")
+                .long("web-identity-token-file")
+                .help("Sets the web identity token file for AssumeRoleWithWebIdentity (e.g., /var/run/secrets/eks.amazonaws.com/serviceaccount/token)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("ALLOCATION_ROLE_ARN")
+                .long("allocation-role-arn")
+                .help("Sets the IAM role ARN in the account that owns the EIPs (e.g., central networking account), to allocate there and transfer to the local account (empty to allocate locally)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("PUBLIC_IPV4_POOL")
+                .long("public-ipv4-pool")
+                .help("Sets the public IPv4 pool to allocate from (e.g., BYOIP pool of the allocation account, or RAM-shared with the local account)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("HTTPS_PROXY")
+                .long("https-proxy")
+                .help("Sets the proxy URL to route AWS API calls via HTTP CONNECT (e.g., http://proxy.internal:3128)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("CA_BUNDLE")
+                .long("ca-bundle")
+                .help("Sets the PEM file of additional CA certificates to trust for AWS API calls (e.g., TLS interception proxy)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("USE_FIPS")
+                .long("use-fips")
+                .help("Uses FIPS endpoints for AWS API calls (e.g., ec2-fips.us-gov-west-1.amazonaws.com)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("false"),
+        )
+        .arg(
+            Arg::new("USE_DUAL_STACK")
+                .long("use-dual-stack")
+                .help("Uses dual-stack (IPv6) endpoints for AWS API calls (e.g., ec2.us-west-2.api.aws)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("false"),
+        )
+        .arg(
+            Arg::new("ENDPOINT_URL")
+                .long("endpoint-url")
+                .help("Sets the endpoint URL for all AWS API calls (e.g., http://localhost:4566 for LocalStack, empty for the regional endpoints)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("IMDS_ENDPOINT")
+                .long("imds-endpoint")
+                .help("Sets the instance metadata service URL (e.g., a local mock for tests)")
+                .required(false)
+                .num_args(1)
+                .default_value(imds::DEFAULT_ENDPOINT),
+        )
+        .arg(
+            Arg::new("IMDS_REQUIRE_V2")
+                .long("imds-require-v2")
+                .help("Requires IMDSv2 session tokens (set false to fall back to IMDSv1)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("true"),
+        )
+        .arg(
+            Arg::new("IMDS_RETRIES")
+                .long("imds-retries")
+                .help("Sets the number of retries with exponential backoff for instance metadata requests")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("FIX_IMDS_HOP_LIMIT")
+                .long("fix-imds-hop-limit")
+                .help("Raises the IMDSv2 PUT response hop limit to 2 if the token request times out (e.g., in containers)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("false"),
+        )
+        .arg(
+            Arg::new("INSTANCE_ID_FALLBACK")
+                .long("instance-id-fallback")
+                .help("Sets how to find the local instance via DescribeInstances when IMDS is unreachable (\"none\", \"mac\", or \"tag:<key>=<value>\")")
+                .required(false)
+                .num_args(1)
+                .default_value("none"),
+        )
+        .arg(
+            Arg::new("ASSOCIATE_READY_TIMEOUT_SECONDS")
+                .long("associate-ready-timeout-seconds")
+                .help("Sets the maximum seconds to wait for the instance to be running and its network interface attached before associating (0 to not wait)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("120"),
+        )
+        .arg(
+            Arg::new("TARGET_ENI_TAG")
+                .long("target-eni-tag")
+                .help("Sets the tag of the local instance's network interface to associate with (e.g., \"Name=public\", empty for the primary network interface)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("PRIVATE_IP_ADDRESS")
+                .long("private-ip-address")
+                .help("Sets the private IP of the target network interface to associate with (e.g., a secondary private IP per service, empty for the primary private IP), recorded in the mounted EIP file")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("TARGET_DEVICE_INDEX")
+                .long("target-device-index")
+                .help("Sets the device index of the local instance's network interface to associate with (0 for the primary network interface)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("INITIAL_WAIT_RANDOM_SECONDS")
+                .long("initial-wait-random-seconds")
+                .help("Sets the maximum number of seconds to wait (value chosen at random with the range)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("WAIT_STRATEGY")
+                .long("wait-strategy")
+                .help("Sets how to pick the initial wait seconds (\"random\", \"hash-instance-id\", or \"ordinal-from-asg\" to spread the instances evenly)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["random", "hash-instance-id", "ordinal-from-asg"])
+                .default_value("random"),
+        )
+        .arg(
+            Arg::new("POST_ASSOCIATE_CMD")
+                .long("post-associate-cmd")
+                .help("Sets the command to run after the EIP is associated (arguments may use \"{public_ip}\", \"{private_ip}\", \"{allocation_id}\", \"{instance_id}\", also exposed as \"IP_MANAGER_*\" env vars)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("POST_RELEASE_CMD")
+                .long("post-release-cmd")
+                .help("Sets the command to run after the EIP is released or swapped (same variables as \"--post-associate-cmd\")")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("ON_PHASE_CMD")
+                .long("on-phase-cmd")
+                .help("Sets the command to run in the background at every progress event (arguments may use \"{phase}\" and \"{payload}\" for the event as JSON, also exposed as \"IP_MANAGER_*\" env vars), never failing the run")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("HOOK_TIMEOUT_SECONDS")
+                .long("hook-timeout-seconds")
+                .help("Sets the timeout in seconds for the post-associate, post-release, and on-phase commands")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("60"),
+        )
+        .arg(
+            Arg::new("HOOK_FAILURE_POLICY")
+                .long("hook-failure-policy")
+                .help("Sets whether a failed or timed-out hook command fails the run (\"fail\") or is logged (\"warn\")")
+                .required(false)
+                .num_args(1)
+                .value_parser(["warn", "fail"])
+                .default_value("warn"),
+        )
+        .arg(
+            Arg::new("DRAIN_CMD")
+                .long("drain-cmd")
+                .help("Sets the command to signal the application to drain before the EIP moves away on interruption (same variables as \"--post-associate-cmd\", plus \"{target_instance_id}\" on swap)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("DRAIN_URL")
+                .long("drain-url")
+                .help("Sets the URL to POST the drain signal to (JSON of the same variables) before the EIP moves away on interruption")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("DRAIN_PORT")
+                .long("drain-port")
+                .help("Sets the local TCP port whose established connections to wait for before the EIP moves away (0 to only signal)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u16))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("DRAIN_TIMEOUT_SECONDS")
+                .long("drain-timeout-seconds")
+                .help("Sets the deadline in seconds of the drain phase, after which the EIP moves anyway")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u64))
+                .default_value("30"),
+        )
+        .arg(
+            Arg::new("FIREWALL_BACKEND")
+                .long("firewall-backend")
+                .help("Sets the firewall to install the templated rules to after association, removed on release (\"none\" to disable)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["none", "nftables", "iptables"])
+                .default_value("none"),
+        )
         .arg(
-            Arg::new("LOG_LEVEL")
-                .long("log-level")
-                .short('l')
-                .help("Sets the log level")
+            Arg::new("FIREWALL_RULES_FILE")
+                .long("firewall-rules-file")
+                .help("Sets the rules template file (nft ruleset of the tool-owned tables, or \"<table> <chain> <rule spec>\" lines for iptables), with \"{public_ip}\", \"{private_ip}\", \"{allocation_id}\", and \"{instance_id}\"")
                 .required(false)
                 .num_args(1)
-                .value_parser(["debug", "info"])
-                .default_value("info"),
+                .default_value("/etc/ip-manager/firewall.rules"),
         )
         .arg(
-            Arg::new("INITIAL_WAIT_RANDOM_SECONDS")
-                .long("initial-wait-random-seconds")
-                .help("Sets the maximum number of seconds to wait (value chosen at random with the range)")
+            Arg::new("SET_HOSTNAME_FROM_DNS")
+                .long("set-hostname-from-dns")
+                .help("Sets the system hostname to the DNS name of the EIP after association")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("false"),
+        )
+        .arg(
+            Arg::new("UPDATE_ETC_HOSTS")
+                .long("update-etc-hosts")
+                .help("Maps the DNS name of the EIP in /etc/hosts (the hosts file under \"System32\\drivers\\etc\" on Windows) after association")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("false"),
+        )
+        .arg(
+            Arg::new("ETC_HOSTS_ADDRESS")
+                .long("etc-hosts-address")
+                .help("Sets the address to map the DNS name to in /etc/hosts (\"private\" as the VPC DNS resolves it in the VPC, or \"public\")")
+                .required(false)
+                .num_args(1)
+                .value_parser(["private", "public"])
+                .default_value("private"),
+        )
+        .arg(
+            Arg::new("ROUTE53_ZONE_ID")
+                .long("route53-zone-id")
+                .help("Sets the Route53 hosted zone ID to upsert the A record of the EIP in (empty to not update Route53)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ROUTE53_RECORD_NAME")
+                .long("route53-record-name")
+                .help("Sets the name of the A record of the EIP, templated with the post-associate variables (e.g., \"{instance_id}.example.com\")")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ROUTE53_TTL")
+                .long("route53-ttl")
+                .help("Sets the TTL of the A record of the EIP in seconds")
                 .required(false)
                 .num_args(1)
                 .value_parser(value_parser!(u32))
-                .default_value("5"),
+                .default_value("60"),
+        )
+        .arg(
+            Arg::new("ROUTE53_HEALTH_CHECK")
+                .long("route53-health-check")
+                .help("Sets the Route53 health check to create against the EIP for its record, as \"tcp:<port>\", \"http:<port>[/path]\", or \"https:<port>[/path]\" (e.g., \"tcp:9651\", empty to not check)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ROUTE53_ROUTING")
+                .long("route53-routing")
+                .help("Sets the routing policy of the record of the EIP (\"simple\", \"failover-primary\", \"failover-secondary\", \"weighted:<0-255>\", or \"latency\")")
+                .required(false)
+                .num_args(1)
+                .default_value("simple"),
+        )
+        .arg(
+            Arg::new("ROUTE53_SET_IDENTIFIER")
+                .long("route53-set-identifier")
+                .help("Sets the set identifier of the record of the EIP for the non-simple routing, templated with the post-associate variables")
+                .required(false)
+                .num_args(1)
+                .default_value("{instance_id}"),
+        )
+        .arg(
+            Arg::new("ROUTE53_PRIVATE_ZONE_ID")
+                .long("route53-private-zone-id")
+                .help("Sets the Route53 private hosted zone ID to upsert the A record of the private IP in (empty to not update)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ROUTE53_PRIVATE_RECORD_NAME")
+                .long("route53-private-record-name")
+                .help("Sets the name of the A record of the private IP, templated with the post-associate variables (e.g., \"{instance_id}.internal.example.com\")")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ROUTE53_PRIVATE_TTL")
+                .long("route53-private-ttl")
+                .help("Sets the TTL of the A record of the private IP in seconds")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("60"),
+        )
+        .arg(
+            Arg::new("VERIFY_DNS_NAME")
+                .long("verify-dns-name")
+                .help("Sets the DNS name to wait for until it resolves to the EIP (and the IPv6 of \"--dual-stack\") after the post-associate command, templated with its variables (e.g., \"{instance_id}.example.com\", empty to not wait)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("VERIFY_DNS_RESOLVERS")
+                .long("verify-dns-resolvers")
+                .help("Sets the comma-separated resolvers to verify the DNS name against, as IP or IP:port (e.g., the authoritative name servers, empty for the system resolver)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("VERIFY_DNS_TIMEOUT_SECONDS")
+                .long("verify-dns-timeout-seconds")
+                .help("Sets the maximum seconds to wait for the DNS name to resolve to the new addresses")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u64))
+                .default_value("300"),
+        )
+        .arg(
+            Arg::new("VERIFY_REACHABILITY")
+                .long("verify-reachability")
+                .help("Sets the comma-separated ports to verify from outside after association, as \"tcp://[host]:port\" with the EIP for the empty host (e.g., \"tcp://:9651\", empty to not verify)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("REACHABILITY_SERVICE_URL")
+                .long("reachability-service-url")
+                .help("Sets the URL of the reflector that probes the ports from outside (e.g., \"http://10.0.0.10:9540/reachability\" of \"ip-manager serve --reachability-reflector\")")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("VERIFY_REACHABILITY_TIMEOUT_SECONDS")
+                .long("verify-reachability-timeout-seconds")
+                .help("Sets the maximum seconds to wait for the ports to be reachable from outside")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u64))
+                .default_value("120"),
+        )
+        .arg(
+            Arg::new("STUN_SERVER")
+                .long("stun-server")
+                .help("Sets the STUN server (host:port) to discover the public IP the traffic leaves with, cross-checked with the EIP after association and on every reconcile (e.g., \"stun.l.google.com:19302\", empty to not check)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("WIREGUARD_PEERS")
+                .long("wireguard-peers")
+                .help("Sets the comma-separated WireGuard peers to point to the EIP after association (\"ssh://[user@]host[:port]\", \"file:<path>\", or the mesh controller URL, empty to not update)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("WIREGUARD_INTERFACE")
+                .long("wireguard-interface")
+                .help("Sets the WireGuard interface of this node and of the peers")
+                .required(false)
+                .num_args(1)
+                .default_value("wg0"),
+        )
+        .arg(
+            Arg::new("WIREGUARD_PUBLIC_KEY")
+                .long("wireguard-public-key")
+                .help("Sets the WireGuard public key of this node in the peers' config (empty to read with \"wg show\")")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("WIREGUARD_LISTEN_PORT")
+                .long("wireguard-listen-port")
+                .help("Sets the WireGuard listen port of the endpoint (0 to read with \"wg show\")")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u16))
+                .default_value("0"),
         )
         .arg(
             Arg::new("ID_TAG_KEY")
@@ -92,14 +1124,137 @@ $ aws-ip-provisioner \
                 .help("Sets the file path to store Elastic IP information mapped to this volume path")
                 .required(true)
                 .num_args(1)
-                .default_value("/data/eip.yaml"),
+                .default_value(platform::DEFAULT_MOUNTED_EIP_FILE_PATH),
         )
+        .arg(
+            Arg::new("NAMESPACE")
+                .long("namespace")
+                .help("Sets the namespace that prefixes the tag values, the mounted EIP file name, the metrics, the hosts file block, and the iptables rule comment, to run multiple instances on one host (empty for none)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        );
+
+    // hidden, for resilience testing only (e.g., "allocate:0.3,associate:timeout")
+    #[cfg(feature = "chaos")]
+    let cmd = cmd.arg(
+        Arg::new("INJECT_FAILURE")
+            .long("inject-failure")
+            .help("Sets the comma-separated faults to inject into the EC2 API calls (\"<operation>:<probability>\", \"<operation>:timeout\", or \"<operation>:delay=<seconds>\")")
+            .hide(true)
+            .required(false)
+            .num_args(1),
+    );
+
+    cmd
 }
 
 /// Defines flag options.
+#[derive(Clone)]
 pub struct Flags {
     pub log_level: String,
+    pub log_filter: String,
+    pub log_target: String,
+    pub output: String,
+    pub state_backend: String,
+    pub mode: String,
+    pub progress: String,
+    pub slack_webhook_source: String,
+    pub slack_events: String,
+    pub discord_webhook_source: String,
+    pub discord_events: String,
+    pub lifecycle_hook_name: String,
+    pub on_interruption: String,
+    pub watch_interval_seconds: u32,
+    pub reconcile_interval_seconds: u32,
+    pub watch_source: String,
+    pub config_file: String,
+    pub audit_log_file: String,
+    pub timing_report_path: String,
+    pub summary_path: String,
+    pub metrics_textfile_dir: String,
+    pub describe_cache_ttl_seconds: u32,
+    pub circuit_failure_threshold: u32,
+    pub circuit_cool_down_seconds: u32,
+    pub alert_provider: String,
+    pub alert_key_source: String,
+    pub alert_url: String,
+    pub alert_failure_threshold: u32,
+    pub secret_cache_ttl_seconds: u32,
+    pub no_steal: bool,
+    pub desired_tags: String,
+    pub remove_unknown_tags: bool,
+    pub skip_if_public_ip: bool,
+    pub dual_stack: bool,
+    pub max_parallel_address_ops: u32,
+    pub ipv6_only: String,
+    pub on_foreign_state: String,
+    pub conflict_policy: String,
+    pub pool_lease_seconds: u64,
+    pub pool_min_free: u32,
+    pub pool_max_free: u32,
+    pub sync_security_group_id: String,
+    pub port_ranges: String,
+    pub consul_address: String,
+    pub consul_kv_prefix: String,
+    pub consul_service_name: String,
+    pub consul_service_port: u16,
+    pub max_api_rps: u32,
+    pub aws_profile: String,
+    pub role_arn: String,
+    pub web_identity_token_file: String,
+    pub allocation_role_arn: String,
+    pub public_ipv4_pool: String,
+    pub https_proxy: String,
+    pub ca_bundle: String,
+    pub use_fips: bool,
+    pub use_dual_stack: bool,
+    pub endpoint_url: String,
+    pub imds_endpoint: String,
+    pub imds_require_v2: bool,
+    pub imds_retries: u32,
+    pub fix_imds_hop_limit: bool,
+    pub instance_id_fallback: String,
+    pub associate_ready_timeout_seconds: u32,
+    pub target_eni_tag: String,
+    pub target_device_index: u32,
+    pub private_ip_address: String,
     pub initial_wait_random_seconds: u32,
+    pub wait_strategy: String,
+    pub post_associate_cmd: String,
+    pub post_release_cmd: String,
+    pub on_phase_cmd: String,
+    pub hook_timeout_seconds: u32,
+    pub hook_failure_policy: String,
+    pub drain_cmd: String,
+    pub drain_url: String,
+    pub drain_port: u16,
+    pub drain_timeout_seconds: u64,
+    pub firewall_backend: String,
+    pub firewall_rules_file: String,
+    pub set_hostname_from_dns: bool,
+    pub update_etc_hosts: bool,
+    pub etc_hosts_address: String,
+    pub route53_zone_id: String,
+    pub route53_record_name: String,
+    pub route53_ttl: u32,
+    pub route53_health_check: String,
+    pub route53_routing: String,
+    pub route53_set_identifier: String,
+    pub route53_private_zone_id: String,
+    pub route53_private_record_name: String,
+    pub route53_private_ttl: u32,
+    pub verify_dns_name: String,
+    pub verify_dns_resolvers: String,
+    pub verify_dns_timeout_seconds: u64,
+    pub verify_reachability: String,
+    pub reachability_service_url: String,
+    pub verify_reachability_timeout_seconds: u64,
+    pub stun_server: String,
+    pub wireguard_peers: String,
+    pub wireguard_interface: String,
+    pub wireguard_public_key: String,
+    pub wireguard_listen_port: u16,
 
     pub id_tag_key: String,
     pub id_tag_value: String,
@@ -107,127 +1262,1186 @@ pub struct Flags {
     pub kind_tag_value: String,
 
     pub mounted_eip_file_path: String,
+    pub namespace: String,
+
+    #[cfg(feature = "chaos")]
+    pub inject_failure: String,
 }
 
-pub async fn execute(opts: Flags) -> io::Result<()> {
-    println!("{} version: {}", NAME, crate_version!());
+/// Parses the flag options from the matches of "new" or "with_args".
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    let log_level = matches
+        .get_one::<String>("LOG_LEVEL")
+        .unwrap_or(&String::from("info"))
+        .clone();
+    let log_filter = matches
+        .get_one::<String>("LOG_FILTER")
+        .unwrap_or(&String::new())
+        .clone();
+    let log_target = matches
+        .get_one::<String>("LOG_TARGET")
+        .unwrap_or(&String::from("stderr"))
+        .clone();
+    let output = matches
+        .get_one::<String>("OUTPUT")
+        .unwrap_or(&String::from("text"))
+        .clone();
+    let state_backend = matches
+        .get_one::<String>("STATE_BACKEND")
+        .unwrap_or(&String::from("file"))
+        .clone();
 
-    // ref. <https://github.com/env-logger-rs/env_logger/issues/47>
-    env_logger::init_from_env(
-        env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, opts.log_level),
+    let mode = matches
+        .get_one::<String>("MODE")
+        .unwrap_or(&String::from("provision"))
+        .clone();
+    let progress = matches
+        .get_one::<String>("PROGRESS")
+        .unwrap_or(&String::from("none"))
+        .clone();
+    let slack_webhook_source = matches
+        .get_one::<String>("SLACK_WEBHOOK_SOURCE")
+        .unwrap_or(&String::new())
+        .clone();
+    let slack_events = matches
+        .get_one::<String>("SLACK_EVENTS")
+        .cloned()
+        .unwrap_or_else(|| notify::DEFAULT_EVENTS.to_string());
+    let discord_webhook_source = matches
+        .get_one::<String>("DISCORD_WEBHOOK_SOURCE")
+        .unwrap_or(&String::new())
+        .clone();
+    let discord_events = matches
+        .get_one::<String>("DISCORD_EVENTS")
+        .cloned()
+        .unwrap_or_else(|| notify::DEFAULT_EVENTS.to_string());
+    let lifecycle_hook_name = matches
+        .get_one::<String>("LIFECYCLE_HOOK_NAME")
+        .unwrap_or(&String::new())
+        .clone();
+    let on_interruption = matches
+        .get_one::<String>("ON_INTERRUPTION")
+        .unwrap_or(&String::from("release"))
+        .clone();
+    let watch_interval_seconds = *matches
+        .get_one::<u32>("WATCH_INTERVAL_SECONDS")
+        .unwrap_or(&5);
+    let reconcile_interval_seconds = *matches
+        .get_one::<u32>("RECONCILE_INTERVAL_SECONDS")
+        .unwrap_or(&30);
+    let watch_source = matches
+        .get_one::<String>("WATCH_SOURCE")
+        .unwrap_or(&String::from("describe"))
+        .clone();
+    let config_file = matches
+        .get_one::<String>("CONFIG_FILE")
+        .unwrap_or(&String::new())
+        .clone();
+    let audit_log_file = matches
+        .get_one::<String>("AUDIT_LOG_FILE")
+        .unwrap_or(&String::new())
+        .clone();
+    let timing_report_path = matches
+        .get_one::<String>("TIMING_REPORT_PATH")
+        .unwrap_or(&String::new())
+        .clone();
+    let summary_path = matches
+        .get_one::<String>("SUMMARY_PATH")
+        .unwrap_or(&String::new())
+        .clone();
+    let metrics_textfile_dir = matches
+        .get_one::<String>("METRICS_TEXTFILE_DIR")
+        .unwrap_or(&String::new())
+        .clone();
+    let describe_cache_ttl_seconds = *matches
+        .get_one::<u32>("DESCRIBE_CACHE_TTL_SECONDS")
+        .unwrap_or(&10);
+    let circuit_failure_threshold = *matches
+        .get_one::<u32>("CIRCUIT_FAILURE_THRESHOLD")
+        .unwrap_or(&5);
+    let circuit_cool_down_seconds = *matches
+        .get_one::<u32>("CIRCUIT_COOL_DOWN_SECONDS")
+        .unwrap_or(&300);
+    let alert_provider = matches
+        .get_one::<String>("ALERT_PROVIDER")
+        .unwrap_or(&String::from("none"))
+        .clone();
+    let alert_key_source = matches
+        .get_one::<String>("ALERT_KEY_SOURCE")
+        .unwrap_or(&String::from("env"))
+        .clone();
+    let alert_url = matches
+        .get_one::<String>("ALERT_URL")
+        .unwrap_or(&String::new())
+        .clone();
+    let alert_failure_threshold = *matches
+        .get_one::<u32>("ALERT_FAILURE_THRESHOLD")
+        .unwrap_or(&3);
+    let secret_cache_ttl_seconds = *matches
+        .get_one::<u32>("SECRET_CACHE_TTL_SECONDS")
+        .unwrap_or(&300);
+    let no_steal = *matches.get_one::<bool>("NO_STEAL").unwrap_or(&true);
+    let desired_tags = matches
+        .get_one::<String>("DESIRED_TAGS")
+        .unwrap_or(&String::new())
+        .clone();
+    let remove_unknown_tags = *matches
+        .get_one::<bool>("REMOVE_UNKNOWN_TAGS")
+        .unwrap_or(&false);
+    let skip_if_public_ip = *matches
+        .get_one::<bool>("SKIP_IF_PUBLIC_IP")
+        .unwrap_or(&false);
+    let dual_stack = *matches.get_one::<bool>("DUAL_STACK").unwrap_or(&false);
+    let max_parallel_address_ops = *matches
+        .get_one::<u32>("MAX_PARALLEL_ADDRESS_OPS")
+        .unwrap_or(&4);
+    let ipv6_only = matches
+        .get_one::<String>("IPV6_ONLY")
+        .unwrap_or(&String::from("fail"))
+        .clone();
+    let on_foreign_state = matches
+        .get_one::<String>("ON_FOREIGN_STATE")
+        .unwrap_or(&String::from("fail"))
+        .clone();
+    let conflict_policy = matches
+        .get_one::<String>("CONFLICT_POLICY")
+        .unwrap_or(&String::from("oldest"))
+        .clone();
+    let pool_lease_seconds = *matches.get_one::<u64>("POOL_LEASE_SECONDS").unwrap_or(&0);
+    let pool_min_free = *matches.get_one::<u32>("POOL_MIN_FREE").unwrap_or(&0);
+    let pool_max_free = *matches.get_one::<u32>("POOL_MAX_FREE").unwrap_or(&0);
+    let sync_security_group_id = matches
+        .get_one::<String>("SYNC_SECURITY_GROUP_ID")
+        .unwrap_or(&String::new())
+        .clone();
+    let port_ranges = matches
+        .get_one::<String>("PORT_RANGES")
+        .unwrap_or(&String::new())
+        .clone();
+    let consul_address = matches
+        .get_one::<String>("CONSUL_ADDRESS")
+        .unwrap_or(&String::from("http://127.0.0.1:8500"))
+        .clone();
+    let consul_kv_prefix = matches
+        .get_one::<String>("CONSUL_KV_PREFIX")
+        .unwrap_or(&String::from("ip-manager"))
+        .clone();
+    let consul_service_name = matches
+        .get_one::<String>("CONSUL_SERVICE_NAME")
+        .unwrap_or(&String::new())
+        .clone();
+    let consul_service_port = *matches.get_one::<u16>("CONSUL_SERVICE_PORT").unwrap_or(&0);
+    let max_api_rps = *matches.get_one::<u32>("MAX_API_RPS").unwrap_or(&0);
+    let aws_profile = matches
+        .get_one::<String>("AWS_PROFILE")
+        .unwrap_or(&String::new())
+        .clone();
+    let role_arn = matches
+        .get_one::<String>("ROLE_ARN")
+        .unwrap_or(&String::new())
+        .clone();
+    let web_identity_token_file = matches
+        .get_one::<String>("WEB_IDENTITY_TOKEN_FILE")
+        .unwrap_or(&String::new())
+        .clone();
+    let allocation_role_arn = matches
+        .get_one::<String>("ALLOCATION_ROLE_ARN")
+        .unwrap_or(&String::new())
+        .clone();
+    let public_ipv4_pool = matches
+        .get_one::<String>("PUBLIC_IPV4_POOL")
+        .unwrap_or(&String::new())
+        .clone();
+    let https_proxy = matches
+        .get_one::<String>("HTTPS_PROXY")
+        .unwrap_or(&String::new())
+        .clone();
+    let ca_bundle = matches
+        .get_one::<String>("CA_BUNDLE")
+        .unwrap_or(&String::new())
+        .clone();
+    let use_fips = *matches.get_one::<bool>("USE_FIPS").unwrap_or(&false);
+    let use_dual_stack = *matches.get_one::<bool>("USE_DUAL_STACK").unwrap_or(&false);
+    let endpoint_url = matches
+        .get_one::<String>("ENDPOINT_URL")
+        .unwrap_or(&String::new())
+        .clone();
+    let imds_endpoint = matches
+        .get_one::<String>("IMDS_ENDPOINT")
+        .unwrap_or(&String::from(imds::DEFAULT_ENDPOINT))
+        .clone();
+    let imds_require_v2 = *matches.get_one::<bool>("IMDS_REQUIRE_V2").unwrap_or(&true);
+    let imds_retries = *matches.get_one::<u32>("IMDS_RETRIES").unwrap_or(&5);
+    let fix_imds_hop_limit = *matches
+        .get_one::<bool>("FIX_IMDS_HOP_LIMIT")
+        .unwrap_or(&false);
+    let instance_id_fallback = matches
+        .get_one::<String>("INSTANCE_ID_FALLBACK")
+        .unwrap_or(&String::from("none"))
+        .clone();
+
+    let associate_ready_timeout_seconds = *matches
+        .get_one::<u32>("ASSOCIATE_READY_TIMEOUT_SECONDS")
+        .unwrap_or(&120);
+    let target_eni_tag = matches
+        .get_one::<String>("TARGET_ENI_TAG")
+        .unwrap_or(&String::new())
+        .clone();
+    let target_device_index = *matches.get_one::<u32>("TARGET_DEVICE_INDEX").unwrap_or(&0);
+    let private_ip_address = matches
+        .get_one::<String>("PRIVATE_IP_ADDRESS")
+        .unwrap_or(&String::new())
+        .clone();
+    let initial_wait_random_seconds = *matches
+        .get_one::<u32>("INITIAL_WAIT_RANDOM_SECONDS")
+        .unwrap_or(&5);
+    let wait_strategy = matches
+        .get_one::<String>("WAIT_STRATEGY")
+        .unwrap_or(&String::from("random"))
+        .clone();
+    let post_associate_cmd = matches
+        .get_one::<String>("POST_ASSOCIATE_CMD")
+        .unwrap_or(&String::new())
+        .clone();
+    let post_release_cmd = matches
+        .get_one::<String>("POST_RELEASE_CMD")
+        .unwrap_or(&String::new())
+        .clone();
+    let on_phase_cmd = matches
+        .get_one::<String>("ON_PHASE_CMD")
+        .unwrap_or(&String::new())
+        .clone();
+    let hook_timeout_seconds = *matches
+        .get_one::<u32>("HOOK_TIMEOUT_SECONDS")
+        .unwrap_or(&60);
+    let hook_failure_policy = matches
+        .get_one::<String>("HOOK_FAILURE_POLICY")
+        .unwrap_or(&String::from("warn"))
+        .clone();
+    let drain_cmd = matches
+        .get_one::<String>("DRAIN_CMD")
+        .unwrap_or(&String::new())
+        .clone();
+    let drain_url = matches
+        .get_one::<String>("DRAIN_URL")
+        .unwrap_or(&String::new())
+        .clone();
+    let drain_port = *matches.get_one::<u16>("DRAIN_PORT").unwrap_or(&0);
+    let drain_timeout_seconds = *matches
+        .get_one::<u64>("DRAIN_TIMEOUT_SECONDS")
+        .unwrap_or(&30);
+    let firewall_backend = matches
+        .get_one::<String>("FIREWALL_BACKEND")
+        .unwrap_or(&String::from("none"))
+        .clone();
+    let firewall_rules_file = matches
+        .get_one::<String>("FIREWALL_RULES_FILE")
+        .unwrap_or(&String::from("/etc/ip-manager/firewall.rules"))
+        .clone();
+    let set_hostname_from_dns = *matches
+        .get_one::<bool>("SET_HOSTNAME_FROM_DNS")
+        .unwrap_or(&false);
+    let update_etc_hosts = *matches
+        .get_one::<bool>("UPDATE_ETC_HOSTS")
+        .unwrap_or(&false);
+    let etc_hosts_address = matches
+        .get_one::<String>("ETC_HOSTS_ADDRESS")
+        .unwrap_or(&String::from("private"))
+        .clone();
+    let route53_zone_id = matches
+        .get_one::<String>("ROUTE53_ZONE_ID")
+        .unwrap_or(&String::new())
+        .clone();
+    let route53_record_name = matches
+        .get_one::<String>("ROUTE53_RECORD_NAME")
+        .unwrap_or(&String::new())
+        .clone();
+    let route53_ttl = *matches.get_one::<u32>("ROUTE53_TTL").unwrap_or(&60);
+    let route53_health_check = matches
+        .get_one::<String>("ROUTE53_HEALTH_CHECK")
+        .unwrap_or(&String::new())
+        .clone();
+    let route53_routing = matches
+        .get_one::<String>("ROUTE53_ROUTING")
+        .unwrap_or(&String::from("simple"))
+        .clone();
+    let route53_set_identifier = matches
+        .get_one::<String>("ROUTE53_SET_IDENTIFIER")
+        .unwrap_or(&String::from("{instance_id}"))
+        .clone();
+    let route53_private_zone_id = matches
+        .get_one::<String>("ROUTE53_PRIVATE_ZONE_ID")
+        .unwrap_or(&String::new())
+        .clone();
+    let route53_private_record_name = matches
+        .get_one::<String>("ROUTE53_PRIVATE_RECORD_NAME")
+        .unwrap_or(&String::new())
+        .clone();
+    let route53_private_ttl = *matches.get_one::<u32>("ROUTE53_PRIVATE_TTL").unwrap_or(&60);
+    let verify_dns_name = matches
+        .get_one::<String>("VERIFY_DNS_NAME")
+        .unwrap_or(&String::new())
+        .clone();
+    let verify_dns_resolvers = matches
+        .get_one::<String>("VERIFY_DNS_RESOLVERS")
+        .unwrap_or(&String::new())
+        .clone();
+    let verify_reachability = matches
+        .get_one::<String>("VERIFY_REACHABILITY")
+        .unwrap_or(&String::new())
+        .clone();
+    let reachability_service_url = matches
+        .get_one::<String>("REACHABILITY_SERVICE_URL")
+        .unwrap_or(&String::new())
+        .clone();
+    let verify_reachability_timeout_seconds = *matches
+        .get_one::<u64>("VERIFY_REACHABILITY_TIMEOUT_SECONDS")
+        .unwrap_or(&120);
+    let stun_server = matches
+        .get_one::<String>("STUN_SERVER")
+        .unwrap_or(&String::new())
+        .clone();
+    let wireguard_peers = matches
+        .get_one::<String>("WIREGUARD_PEERS")
+        .unwrap_or(&String::new())
+        .clone();
+    let wireguard_interface = matches
+        .get_one::<String>("WIREGUARD_INTERFACE")
+        .unwrap_or(&String::from("wg0"))
+        .clone();
+    let wireguard_public_key = matches
+        .get_one::<String>("WIREGUARD_PUBLIC_KEY")
+        .unwrap_or(&String::new())
+        .clone();
+    let wireguard_listen_port = *matches
+        .get_one::<u16>("WIREGUARD_LISTEN_PORT")
+        .unwrap_or(&0);
+    let verify_dns_timeout_seconds = *matches
+        .get_one::<u64>("VERIFY_DNS_TIMEOUT_SECONDS")
+        .unwrap_or(&300);
+
+    let namespace = matches
+        .get_one::<String>("NAMESPACE")
+        .unwrap_or(&String::new())
+        .clone();
+
+    let id_tag_key = matches.get_one::<String>("ID_TAG_KEY").unwrap().clone();
+    let id_tag_value = namespaced(
+        &namespace,
+        matches.get_one::<String>("ID_TAG_VALUE").unwrap(),
+    );
+    let kind_tag_key = matches.get_one::<String>("KIND_TAG_KEY").unwrap().clone();
+    let kind_tag_value = namespaced(
+        &namespace,
+        matches.get_one::<String>("KIND_TAG_VALUE").unwrap(),
+    );
+
+    let mounted_eip_file_path = namespaced_file_path(
+        &namespace,
+        matches
+            .get_one::<String>("MOUNTED_EIP_FILE_PATH")
+            .unwrap_or(&String::from(platform::DEFAULT_MOUNTED_EIP_FILE_PATH)),
     );
-    log::info!("starting 'aws-ip-provisioner'");
 
-    let shared_config = aws_manager::load_config(None).await?;
-    let ec2_manager = ec2::Manager::new(&shared_config);
+    Flags {
+        log_level,
+        log_filter,
+        log_target,
+        output,
+        state_backend,
+        mode,
+        progress,
+        slack_webhook_source,
+        slack_events,
+        discord_webhook_source,
+        discord_events,
+        lifecycle_hook_name,
+        on_interruption,
+        watch_interval_seconds,
+        reconcile_interval_seconds,
+        watch_source,
+        config_file,
+        audit_log_file,
+        timing_report_path,
+        summary_path,
+        metrics_textfile_dir,
+        describe_cache_ttl_seconds,
+        circuit_failure_threshold,
+        circuit_cool_down_seconds,
+        alert_provider,
+        alert_key_source,
+        alert_url,
+        alert_failure_threshold,
+        secret_cache_ttl_seconds,
+        no_steal,
+        desired_tags,
+        remove_unknown_tags,
+        skip_if_public_ip,
+        dual_stack,
+        max_parallel_address_ops,
+        ipv6_only,
+        on_foreign_state,
+        conflict_policy,
+        pool_lease_seconds,
+        pool_min_free,
+        pool_max_free,
+        sync_security_group_id,
+        port_ranges,
+        consul_address,
+        consul_kv_prefix,
+        consul_service_name,
+        consul_service_port,
+        max_api_rps,
+        aws_profile,
+        role_arn,
+        web_identity_token_file,
+        allocation_role_arn,
+        public_ipv4_pool,
+        https_proxy,
+        ca_bundle,
+        use_fips,
+        use_dual_stack,
+        endpoint_url,
+        imds_endpoint,
+        imds_require_v2,
+        imds_retries,
+        fix_imds_hop_limit,
+        instance_id_fallback,
+        associate_ready_timeout_seconds,
+        target_eni_tag,
+        target_device_index,
+        private_ip_address,
+        initial_wait_random_seconds,
+        wait_strategy,
+        post_associate_cmd,
+        post_release_cmd,
+        on_phase_cmd,
+        hook_timeout_seconds,
+        hook_failure_policy,
+        drain_cmd,
+        drain_url,
+        drain_port,
+        drain_timeout_seconds,
+        firewall_backend,
+        firewall_rules_file,
+        set_hostname_from_dns,
+        update_etc_hosts,
+        etc_hosts_address,
+        route53_zone_id,
+        route53_record_name,
+        route53_ttl,
+        route53_health_check,
+        route53_routing,
+        route53_set_identifier,
+        route53_private_zone_id,
+        route53_private_record_name,
+        route53_private_ttl,
+        verify_dns_name,
+        verify_dns_resolvers,
+        verify_dns_timeout_seconds,
+        verify_reachability,
+        reachability_service_url,
+        verify_reachability_timeout_seconds,
+        stun_server,
+        wireguard_peers,
+        wireguard_interface,
+        wireguard_public_key,
+        wireguard_listen_port,
+        id_tag_key,
+        id_tag_value,
+        kind_tag_key,
+        kind_tag_value,
+        mounted_eip_file_path,
+        namespace,
 
-    let ec2_instance_id = ec2::metadata::fetch_instance_id().await.map_err(|e| {
-        Error::new(
-            ErrorKind::Other,
-            format!("failed fetch_instance_id '{}'", e),
-        )
-    })?;
+        #[cfg(feature = "chaos")]
+        inject_failure: matches
+            .get_one::<String>("INJECT_FAILURE")
+            .unwrap_or(&String::new())
+            .clone(),
+    }
+}
 
-    let sleep_sec = if opts.initial_wait_random_seconds > 0 {
-        random_manager::u32() % opts.initial_wait_random_seconds
+/// Returns the value prefixed with the namespace (e.g., "internal-vip-node-1"),
+/// as is if the namespace is empty.
+pub fn namespaced(namespace: &str, v: &str) -> String {
+    if namespace.is_empty() {
+        v.to_string()
     } else {
-        0
+        format!("{namespace}-{v}")
+    }
+}
+
+/// Returns the file path with the namespace prefixing the file name
+/// (e.g., "/data/internal-vip-eip.yaml"), so that the instances may share the volume.
+pub fn namespaced_file_path(namespace: &str, file_path: &str) -> String {
+    if namespace.is_empty() {
+        return file_path.to_string();
+    }
+    let path = Path::new(file_path);
+    let file_name = path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(namespaced(namespace, &file_name))
+        .to_string_lossy()
+        .to_string()
+}
+
+impl Flags {
+    /// Returns the AWS SDK options (proxy, endpoints, and credentials) of the flags,
+    /// with the rate limit of the run.
+    pub fn sdk_options(&self, ctx: &Context) -> sdk::Options {
+        sdk::Options {
+            https_proxy: self.https_proxy.clone(),
+            ca_bundle: self.ca_bundle.clone(),
+            use_fips: self.use_fips,
+            use_dual_stack: self.use_dual_stack,
+            endpoint_url: self.endpoint_url.clone(),
+            profile: self.aws_profile.clone(),
+            role_arn: self.role_arn.clone(),
+            web_identity_token_file: self.web_identity_token_file.clone(),
+            role_session_name: NAME.to_string(),
+            ratelimit: ctx.ratelimit.clone(),
+            secrets: ctx.secrets.clone(),
+        }
+    }
+
+    #[cfg(feature = "consul")]
+    pub fn consul(&self) -> consul::Client {
+        consul::Client::new(&self.consul_address)
+    }
+
+    /// Returns the IMDS client, warning its retries in the summary of the run.
+    pub fn imds(&self, ctx: &Context) -> Imds {
+        let mut imds = Imds::new(self.imds_require_v2, self.imds_retries);
+        imds.endpoint = self.imds_endpoint.clone();
+        imds.summary = ctx.summary.clone();
+        imds
+    }
+
+    pub fn post_associate_hook(&self) -> hook::Hook {
+        hook::Hook::new(
+            &self.post_associate_cmd,
+            self.hook_timeout_seconds,
+            &self.hook_failure_policy,
+        )
+    }
+
+    pub fn post_release_hook(&self) -> hook::Hook {
+        hook::Hook::new(
+            &self.post_release_cmd,
+            self.hook_timeout_seconds,
+            &self.hook_failure_policy,
+        )
+    }
+
+    /// Returns the Route53 records of the EIP and the private IP to upsert,
+    /// with the names templated with the post-associate variables.
+    /// Only the record of the EIP takes the routing policy and the health check.
+    pub fn route53_records(
+        &self,
+        public_ip: &str,
+        private_ip: &str,
+        vars: &[(&str, String)],
+    ) -> io::Result<Vec<route53::Record>> {
+        let mut records = Vec::new();
+        if !self.route53_zone_id.is_empty() {
+            records.push(route53::Record {
+                zone_id: self.route53_zone_id.clone(),
+                name: hook::render(&self.route53_record_name, vars),
+                ttl: self.route53_ttl,
+                value: public_ip.to_string(),
+                routing: route53::Routing::parse(&self.route53_routing)?,
+                set_identifier: hook::render(&self.route53_set_identifier, vars),
+                health_check_id: String::new(),
+            });
+        }
+        if !self.route53_private_zone_id.is_empty() && !private_ip.is_empty() {
+            records.push(route53::Record {
+                zone_id: self.route53_private_zone_id.clone(),
+                name: hook::render(&self.route53_private_record_name, vars),
+                ttl: self.route53_private_ttl,
+                value: private_ip.to_string(),
+                routing: route53::Routing::Simple,
+                set_identifier: String::new(),
+                health_check_id: String::new(),
+            });
+        }
+        Ok(records)
+    }
+
+    /// Returns true if the flags need the "consul" feature.
+    pub fn uses_consul(&self) -> bool {
+        self.state_backend == "consul" || !self.consul_service_name.is_empty()
+    }
+
+    /// Returns the notification sinks with their events.
+    pub fn notify_subscriptions(&self, ctx: &Context) -> Vec<notify::Subscription> {
+        let mut subscriptions = Vec::new();
+        if !self.slack_webhook_source.is_empty() {
+            subscriptions.push(notify::Subscription::new(
+                &self.slack_events,
+                Arc::new(notify::SlackWebhook {
+                    url_source: self.slack_webhook_source.clone(),
+                    sdk_opts: self.sdk_options(ctx),
+                }),
+            ));
+        }
+        if !self.discord_webhook_source.is_empty() {
+            subscriptions.push(notify::Subscription::new(
+                &self.discord_events,
+                Arc::new(notify::DiscordWebhook {
+                    url_source: self.discord_webhook_source.clone(),
+                    sdk_opts: self.sdk_options(ctx),
+                }),
+            ));
+        }
+        if !self.on_phase_cmd.is_empty() {
+            subscriptions.push(notify::Subscription::new(
+                &progress::EVENTS.join(","),
+                Arc::new(notify::PhaseCommand::new(
+                    &self.on_phase_cmd,
+                    self.hook_timeout_seconds,
+                )),
+            ));
+        }
+        subscriptions
+    }
+
+    pub fn alert(&self) -> alert::Alert {
+        alert::Alert {
+            provider: self.alert_provider.clone(),
+            key_source: self.alert_key_source.clone(),
+            url: self.alert_url.clone(),
+            failure_threshold: self.alert_failure_threshold,
+        }
+    }
+
+    pub fn drain(&self) -> drain::Drain {
+        drain::Drain {
+            cmd: self.drain_cmd.clone(),
+            url: self.drain_url.clone(),
+            port: self.drain_port,
+            timeout: Duration::from_secs(self.drain_timeout_seconds),
+        }
+    }
+}
+
+/// Updates the hostname and the hosts file, installs the firewall rules,
+/// syncs the security group, registers the Consul service, runs the
+/// post-associate hook, and waits for the DNS name to resolve to the EIP.
+pub async fn post_associate(
+    ctx: &Context,
+    imds: &Imds,
+    ec2_manager: &ec2::Manager,
+    opts: &Flags,
+    eip: &ec2::Eip,
+    ec2_instance_id: &str,
+) -> io::Result<()> {
+    let mut vars = hook::eip_vars(eip, ec2_instance_id);
+    // EC2 maps the EIP to the primary private IP, which the guest OS sees
+    let private_ip = match imds.fetch("local-ipv4").await {
+        Ok(v) => v.trim().to_string(),
+        Err(e) => {
+            ctx.summary
+                .warn(&format!("failed to fetch local-ipv4 '{}'", e));
+            String::new()
+        }
     };
-    if sleep_sec > 0 {
-        log::info!("waiting for random seconds {}", sleep_sec);
-        sleep(Duration::from_secs(sleep_sec as u64)).await;
-    } else {
-        log::info!("skipping random sleep...");
+    vars.push(("private_ip", private_ip.clone()));
+    // recorded by the provisioning run, for the DNS AAAA record next to the A record
+    let mut ipv6 = String::new();
+    if opts.dual_stack {
+        ipv6 = State::load(&opts.mounted_eip_file_path)?.ipv6_address;
+        vars.push(("ipv6", ipv6.clone()));
     }
 
-    log::info!(
-        "checking if the local instance {} has an already created elastic Ip (for reuse) via {}",
-        ec2_instance_id,
-        opts.mounted_eip_file_path
-    );
-    let eip = if Path::new(&opts.mounted_eip_file_path).exists() {
+    if opts.set_hostname_from_dns || opts.update_etc_hosts {
+        let name = ctx
+            .timing
+            .measure("dns", hostname::dns_name(ec2_manager, eip, ec2_instance_id))
+            .await?;
+        if opts.set_hostname_from_dns {
+            hostname::set_hostname(&name)?;
+        }
+        if opts.update_etc_hosts {
+            let ip = if opts.etc_hosts_address == "public" || private_ip.is_empty() {
+                &eip.public_ip
+            } else {
+                &private_ip
+            };
+            let mut ips = vec![ip.as_str()];
+            if !ipv6.is_empty() {
+                ips.push(&ipv6);
+            }
+            hostname::update_etc_hosts(platform::HOSTS_FILE, &ips, &name, &opts.namespace)?;
+        }
+        progress::emit(ctx, progress::DNS_UPDATED, &[("dns_name", &name)]);
+        vars.push(("dns_name", name));
+    }
+    firewall::install(
+        &opts.firewall_backend,
+        &opts.firewall_rules_file,
+        &vars,
+        &opts.namespace,
+    )?;
+    if !opts.sync_security_group_id.is_empty() {
+        let plan = security_group::sync_fleet(ec2_manager, opts).await?;
         log::info!(
-            "mounted EIP file path exists -- loading existing {}",
-            opts.mounted_eip_file_path
+            "synced security group {} (authorized {}, revoked {})",
+            opts.sync_security_group_id,
+            plan.authorize.len(),
+            plan.revoke.len()
         );
-        ec2::Eip::load(&opts.mounted_eip_file_path)
-            .map_err(|e| Error::new(ErrorKind::Other, format!("failed ec2::Eip::load '{}'", e)))?
-    } else {
-        log::info!("mounted EIP file does not exist in the mounted volume path -- creating one!");
-        ec2_manager
-            .allocate_eip(
-                &opts.id_tag_key,
-                &opts.id_tag_value,
-                &opts.kind_tag_key,
-                &opts.kind_tag_value,
-            )
-            .await
-            .map_err(|e| {
-                Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "failed ec2_manager.allocate_eip {} (retryable {})",
-                        e.message(),
-                        e.is_retryable()
+    }
+    #[cfg(feature = "consul")]
+    if !opts.consul_service_name.is_empty() {
+        consul::register_service(&opts.consul(), opts, eip, ec2_instance_id, &private_ip).await?;
+    }
+    let mut records = opts.route53_records(&eip.public_ip, &private_ip, &vars)?;
+    if !records.is_empty() {
+        let sdk_opts = opts.sdk_options(ctx);
+        let shared_config = sdk::load_config(None, &sdk_opts).await?;
+        if let Some(check) = route53::HealthCheck::parse(&opts.route53_health_check)? {
+            let id = ctx
+                .timing
+                .measure(
+                    "route53_health_check",
+                    route53::create_health_check(
+                        &shared_config,
+                        &sdk_opts,
+                        &check,
+                        &eip.public_ip,
+                        ec2_instance_id,
                     ),
                 )
-            })?
-    };
-    eip.sync(&opts.mounted_eip_file_path)?;
+                .await?;
+            for r in records.iter_mut().filter(|r| r.value == eip.public_ip) {
+                r.health_check_id = id.clone();
+            }
+        }
+        let comment = format!("{} {}", crate::APP_NAME, ec2_instance_id);
+        ctx.timing
+            .measure(
+                "route53",
+                route53::upsert(&shared_config, &sdk_opts, &records, &comment),
+            )
+            .await?;
+        let names: Vec<&str> = records.iter().map(|r| r.name.as_str()).collect();
+        progress::emit(
+            ctx,
+            progress::DNS_UPDATED,
+            &[("dns_name", &names.join(","))],
+        );
+    }
+    opts.post_associate_hook().run("associate", &vars).await?;
 
-    log::info!(
-        "checking the instance has already been associated with elastic IP {:?}",
-        eip
-    );
-    let eips = ec2_manager
-        .describe_eips_by_instance_id(&ec2_instance_id)
-        .await
-        .map_err(|e| {
-            Error::new(
-                ErrorKind::Other,
-                format!(
-                    "failed ec2_manager.describe_eips_by_instance_id {} (retryable {})",
-                    e.message(),
-                    e.is_retryable()
+    if !opts.verify_dns_name.is_empty() {
+        // the hook (e.g., "update-dns.sh {public_ip}") upserts the records
+        let name = hook::render(&opts.verify_dns_name, &vars);
+        let mut expected = vec![eip.public_ip.clone()];
+        if !ipv6.is_empty() {
+            expected.push(ipv6);
+        }
+        ctx.timing
+            .measure(
+                "dns_verify",
+                dns::verify(
+                    ctx,
+                    &name,
+                    &expected,
+                    &opts.verify_dns_resolvers,
+                    opts.verify_dns_timeout_seconds,
                 ),
             )
-        })?;
-    let need_associate_eip = if eips.is_empty() {
-        log::info!(
-            "no existing EIP found, now associating {:?} to {ec2_instance_id}",
-            eip
+            .await?;
+        progress::emit(ctx, progress::DNS_VERIFIED, &[("dns_name", &name)]);
+    }
+
+    if !opts.verify_reachability.is_empty() {
+        let targets = reachability::parse(&opts.verify_reachability)?;
+        let signed = identity::fetch(imds).await?;
+        ctx.timing
+            .measure(
+                "reachability_verify",
+                reachability::verify(
+                    ctx,
+                    &opts.reachability_service_url,
+                    &signed,
+                    &eip.public_ip,
+                    &targets,
+                    opts.verify_reachability_timeout_seconds,
+                ),
+            )
+            .await?;
+        progress::emit(
+            ctx,
+            progress::REACHABILITY_VERIFIED,
+            &[("targets", &opts.verify_reachability)],
         );
-        true
-    } else {
-        log::info!("existing EIPs found {:?}", eips);
-        let mut found = false;
-        for ev in eips.iter() {
-            log::info!("address {:?}", ev);
-            let allocation_id = ev.allocation_id.to_owned().unwrap();
-            if allocation_id == eip.allocation_id {
-                log::info!("{ec2_instance_id} already has EIP allocation ID {allocation_id} -- no need to associate once more");
-                found = true;
-                break;
+    }
+    if !opts.stun_server.is_empty() {
+        ctx.timing
+            .measure(
+                "stun",
+                stun::cross_check(ctx, &opts.stun_server, &eip.public_ip),
+            )
+            .await;
+    }
+    if !opts.wireguard_peers.is_empty() {
+        ctx.timing
+            .measure(
+                "wireguard",
+                update_wireguard_peers(ctx, opts, &eip.public_ip),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Points the WireGuard peers to the EIP, with the public key and the listen port
+/// of the local interface unless set.
+async fn update_wireguard_peers(ctx: &Context, opts: &Flags, public_ip: &str) -> io::Result<()> {
+    let targets = wireguard::parse(&opts.wireguard_peers)?;
+    let (mut public_key, mut port) = (
+        opts.wireguard_public_key.clone(),
+        opts.wireguard_listen_port,
+    );
+    if public_key.is_empty() || port == 0 {
+        match wireguard::local_peer(&opts.wireguard_interface).await {
+            Ok((k, p)) => {
+                if public_key.is_empty() {
+                    public_key = k;
+                }
+                if port == 0 {
+                    port = p;
+                }
+            }
+            Err(e) => {
+                ctx.summary.warn(&format!(
+                    "failed to read WireGuard interface {} '{}' -- not updating the peers",
+                    opts.wireguard_interface, e
+                ));
+                return Ok(());
             }
         }
-        !found // if already associated EIP not found, need associate existing one
+    }
+    let endpoint = format!("{public_ip}:{port}");
+    wireguard::update_peers(
+        ctx,
+        &targets,
+        &opts.wireguard_interface,
+        &public_key,
+        &endpoint,
+    )
+    .await;
+    progress::emit(ctx, progress::WIREGUARD_UPDATED, &[("endpoint", &endpoint)]);
+    Ok(())
+}
+
+/// Removes the firewall rules, deregisters the Consul service, and runs the post-release hook.
+pub async fn post_release(
+    ctx: &Context,
+    opts: &Flags,
+    eip: &ec2::Eip,
+    ec2_instance_id: &str,
+) -> io::Result<()> {
+    progress::emit(
+        ctx,
+        progress::RELEASED,
+        &[
+            ("allocation_id", &eip.allocation_id),
+            ("public_ip", &eip.public_ip),
+            ("instance_id", ec2_instance_id),
+        ],
+    );
+    firewall::remove(
+        &opts.firewall_backend,
+        &opts.firewall_rules_file,
+        &opts.namespace,
+    )?;
+    #[cfg(feature = "consul")]
+    if !opts.consul_service_name.is_empty() {
+        consul::deregister_service(&opts.consul(), opts).await?;
+    }
+    opts.post_release_hook()
+        .run("release", &hook::eip_vars(eip, ec2_instance_id))
+        .await
+}
+
+pub async fn execute(opts: Flags) -> io::Result<()> {
+    let ctx = Context::default();
+    ctx.progress.init(&opts.progress);
+    ctx.notify.init(opts.notify_subscriptions(&ctx));
+    progress::emit(&ctx, progress::STARTED, &[("mode", &opts.mode)]);
+    let started = Instant::now();
+    let (summary_path, mode) = (opts.summary_path.clone(), opts.mode.clone());
+    let (metrics_textfile_dir, namespace) =
+        (opts.metrics_textfile_dir.clone(), opts.namespace.clone());
+    let res = run(&ctx, opts).await;
+    if let Err(e) = &res {
+        progress::emit(&ctx, progress::FAILED, &[("error", &e.to_string())]);
+    }
+    ctx.notify.flush(Duration::from_secs(10)).await;
+    if !summary_path.is_empty() {
+        // the run result takes precedence over the summary write failure
+        if let Err(e) = summary::write(&ctx, &summary_path, &mode, &res, started.elapsed()) {
+            log::warn!("failed to write summary {summary_path} '{}'", e);
+        }
+    }
+    if !metrics_textfile_dir.is_empty() {
+        let ret = textfile::write(
+            &ctx,
+            &metrics_textfile_dir,
+            &namespace,
+            &mode,
+            &res,
+            started.elapsed(),
+        );
+        if let Err(e) = ret {
+            log::warn!("failed to write metrics to {metrics_textfile_dir} '{}'", e);
+        }
+    }
+    res
+}
+
+async fn run(ctx: &Context, mut opts: Flags) -> io::Result<()> {
+    let started = Instant::now();
+    // keep stdout parseable for the JSON output and the progress events
+    if opts.output == "text" && !ctx.progress.enabled() {
+        println!("{} version: {}", NAME, crate_version!());
+    }
+
+    // may be already initialized by "ip-manager" (e.g., provider detection)
+    logging::init(&opts.log_level, &opts.log_filter, &opts.log_target)?;
+    log::info!("starting 'aws-ip-provisioner'");
+
+    if !opts.config_file.is_empty() {
+        let file_path = opts.config_file.clone();
+        let changed = config::apply(&mut opts, &file_path)?;
+        log::info!("applied config {:?} from {file_path}", changed);
+    }
+    if !cfg!(feature = "consul") && opts.uses_consul() {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "Consul flags set but built without the \"consul\" feature",
+        ));
+    }
+
+    ctx.ratelimit.init(opts.max_api_rps);
+    ctx.secrets
+        .init(Duration::from_secs(opts.secret_cache_ttl_seconds as u64));
+    ctx.metrics.set_namespace(&opts.namespace);
+    let sdk_opts = opts.sdk_options(ctx);
+    let shared_config = sdk::load_config(None, &sdk_opts).await?;
+    ctx.audit
+        .init(
+            &opts.audit_log_file,
+            &opts.id_tag_value,
+            &sdk::for_service(&shared_config, "sts", &sdk_opts)?,
+        )
+        .await?;
+    let ec2_manager = ec2::Manager::new(&sdk::for_service(&shared_config, "ec2", &sdk_opts)?);
+    let asg_manager =
+        autoscaling::Manager::new(&sdk::for_service(&shared_config, "autoscaling", &sdk_opts)?);
+
+    let imds = opts.imds(ctx);
+    let hop_limited = ctx.timing.measure("imds", imds.is_hop_limited()).await;
+    if hop_limited {
+        ctx.summary.warn(&imds::hop_limit_diagnostic());
+    }
+    let metadata = ImdsMetadata {
+        imds: &imds,
+        ec2_manager: &ec2_manager,
+        fallback: opts.instance_id_fallback.clone(),
+        hop_limited,
     };
-    if need_associate_eip {
-        let _association_id = ec2_manager
-            .associate_eip(&eip.allocation_id, &ec2_instance_id)
-            .await
-            .map_err(|e| {
-                Error::new(
-                    ErrorKind::Other,
-                    format!(
-                        "failed ec2_manager.associate_eip {} (retryable {})",
-                        e.message(),
-                        e.is_retryable()
-                    ),
-                )
-            })?;
+    let aws_ec2 = AwsEc2 {
+        ec2_manager: &ec2_manager,
+        ctx,
+    };
+    #[cfg(feature = "chaos")]
+    let chaos = chaos::Chaos::new(&aws_ec2, &opts.inject_failure)?;
+    #[cfg(feature = "chaos")]
+    let ec2_api: &dyn Ec2 = &chaos;
+    #[cfg(not(feature = "chaos"))]
+    let ec2_api: &dyn Ec2 = &aws_ec2;
+    let transfer = if opts.allocation_role_arn.is_empty() {
+        None
+    } else {
+        Some(
+            Transfer::new(
+                ec2_api,
+                ctx,
+                &shared_config,
+                &sdk_opts,
+                &opts.allocation_role_arn,
+            )
+            .await?,
+        )
+    };
+    let ec2_api: &dyn Ec2 = match &transfer {
+        Some(v) => v,
+        None => ec2_api,
+    };
+    let provisioner = Provisioner::new(ctx, &opts, ec2_api, &metadata, &SystemClock, &SystemRng);
+    let ec2_instance_id = provisioner.instance_id().await?;
+    progress::emit(ctx, progress::IMDS_OK, &[("instance_id", &ec2_instance_id)]);
+    ctx.summary.resource("instance_id", &ec2_instance_id);
+    if hop_limited && opts.fix_imds_hop_limit {
+        imds::fix_hop_limit(&ec2_manager, &ec2_instance_id, 2).await?;
+    }
+    #[cfg(feature = "consul")]
+    if opts.state_backend == "consul" {
+        consul::restore_state(&opts.consul(), &opts).await?;
+    }
+
+    if opts.mode == "terminate-hook" {
+        return lifecycle::handle_terminate(
+            ctx,
+            &imds,
+            &ec2_manager,
+            &asg_manager,
+            &ec2_instance_id,
+            &opts,
+            Duration::from_secs(5),
+        )
+        .await
+        .map(|_| progress::emit(ctx, progress::DONE, &[]));
+    }
+
+    if opts.mode == "watch" {
+        if !Path::new(&opts.mounted_eip_file_path).exists() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "mounted EIP file path {} does not exist -- provision first",
+                    opts.mounted_eip_file_path
+                ),
+            ));
+        }
+        let eip = state::load_eip(&opts.mounted_eip_file_path)?;
+        return daemon::watch(ctx, &imds, &ec2_manager, &opts, &ec2_instance_id, &eip).await;
+    }
+
+    if opts.skip_if_public_ip {
+        if let Some(ip) = provisioner.unmanaged_public_ip().await? {
+            log::info!(
+                "instance already has public IPv4 {ip} not managed by this tool -- skipping"
+            );
+            ctx.summary
+                .skip("instance already has a public IPv4 not managed by this tool");
+            progress::emit(
+                ctx,
+                progress::DONE,
+                &[("skipped", "true"), ("public_ip", &ip)],
+            );
+            return Ok(());
+        }
+    }
+
+    if let Some(ipv6) = provisioner.ipv6_only(&ec2_instance_id).await? {
+        // nothing to keep associated, so no daemon either
+        let vars = hook::ipv6_vars(&ipv6, &ec2_instance_id);
+        opts.post_associate_hook().run("associate", &vars).await?;
+        ctx.summary.resource("ipv6", &ipv6);
+        ctx.summary.skip("IPv6-only instance");
+        progress::emit(ctx, progress::DONE, &[("skipped", "true"), ("ipv6", &ipv6)]);
+        if opts.output == "json" {
+            println!("{}", serde_json::json!({ "ipv6": ipv6 }));
+        } else {
+            log::info!("IPv6-only instance {ec2_instance_id} uses {ipv6} instead of an EIP");
+        }
+        return Ok(());
     }
 
+    ctx.timing
+        .measure("random_wait", provisioner.initial_wait(&ec2_instance_id))
+        .await?;
+    #[cfg(feature = "consul")]
+    let claim = if opts.state_backend == "consul" {
+        let key = consul::claim_key(&opts.consul_kv_prefix, &opts.id_tag_value);
+        Some(
+            ctx.timing
+                .measure(
+                    "consul_claim",
+                    consul::Claim::acquire(&opts.consul(), &key, &ec2_instance_id),
+                )
+                .await?,
+        )
+    } else {
+        None
+    };
+    // the IPv6 address does not depend on the EIP, so assigned meanwhile
+    let (mut provisioned, mut ipv6) = (None, String::new());
+    let mut tasks: Vec<BoxFuture<'_, ()>> = vec![Box::pin(async {
+        provisioned = Some(provisioner.provision(&ec2_instance_id).await?);
+        Ok::<_, io::Error>(())
+    })];
+    if opts.dual_stack {
+        tasks.push(Box::pin(async {
+            ipv6 = ctx
+                .timing
+                .measure("ipv6", provisioner.ensure_ipv6(&ec2_instance_id))
+                .await?;
+            Ok::<_, io::Error>(())
+        }));
+    }
+    let res: io::Result<()> = pipeline::run_bounded(opts.max_parallel_address_ops as usize, tasks)
+        .await
+        .into_iter()
+        .collect();
+    #[cfg(feature = "consul")]
+    if let Some(claim) = claim {
+        // the EIP tags and the association record the claim from here on
+        claim.release().await;
+    }
+    res?;
+    let eip = provisioned.ok_or_else(|| Error::new(ErrorKind::Other, "no EIP provisioned"))?;
     log::info!("successfully provisioned and associated EIP!");
+    ctx.summary.resource("allocation_id", &eip.allocation_id);
+    ctx.summary.resource("public_ip", &eip.public_ip);
+    if opts.dual_stack {
+        state::record_ipv6_address(&opts.mounted_eip_file_path, &ipv6)?;
+        ctx.summary.resource("ipv6", &ipv6);
+        log::info!("dual-stack with EIP {} and IPv6 {ipv6}", eip.public_ip);
+    }
+    // the EIP is already associated, so the richer state is best-effort
+    if let Err(e) = provisioner.record_state(&eip).await {
+        ctx.summary
+            .warn(&format!("failed to record the EIP state '{}'", e));
+    }
+    #[cfg(feature = "consul")]
+    if opts.state_backend == "consul" {
+        consul::save_state(&opts.consul(), &opts).await?;
+    }
+    post_associate(ctx, &imds, &ec2_manager, &opts, &eip, &ec2_instance_id).await?;
+    log::info!("timing {}", ctx.timing.summary(started.elapsed()));
+    if !opts.timing_report_path.is_empty() {
+        ctx.timing
+            .write_report(&opts.timing_report_path, started.elapsed())?;
+    }
+    progress::emit(
+        ctx,
+        progress::DONE,
+        &[
+            ("allocation_id", &eip.allocation_id),
+            ("public_ip", &eip.public_ip),
+            ("ipv6", &ipv6),
+        ],
+    );
+    if opts.output == "json" {
+        let mut v = serde_json::to_value(&eip)
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to serialize EIP {}", e)))?;
+        if !ipv6.is_empty() {
+            v["ipv6_address"] = serde_json::Value::from(ipv6);
+        }
+        println!("{v}");
+    }
+
+    if opts.mode == "daemon" {
+        daemon::run(ctx, &imds, &ec2_manager, &opts, &ec2_instance_id, &eip).await?;
+    }
     Ok(())
 }
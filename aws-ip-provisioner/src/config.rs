@@ -0,0 +1,210 @@
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    command::{self, Flags},
+    tags,
+};
+
+/// Applies the config file to the flags, returning the changed keys.
+///
+/// The file is a JSON object keyed by the long flag names
+/// (e.g., {"reconcile-interval-seconds": 60, "post-associate-cmd": "..."}),
+/// limited to the settings that can change while the daemon is running.
+/// Validates all values before changing any, so that a bad file is ignored as a whole.
+pub fn apply(opts: &mut Flags, file_path: &str) -> io::Result<Vec<String>> {
+    let d = fs::read_to_string(file_path).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("failed to read config file {file_path} '{}'", e),
+        )
+    })?;
+    let parsed: Value = serde_json::from_str(&d).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("failed to parse config file {file_path} '{}'", e),
+        )
+    })?;
+    let entries = parsed.as_object().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("config file {file_path} is not a JSON object"),
+        )
+    })?;
+
+    let mut updated = opts.clone();
+    for (key, v) in entries.iter() {
+        let s = match v {
+            Value::String(s) => s.clone(),
+            Value::Number(_) | Value::Bool(_) => v.to_string(),
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("config '{key}' must be a string, number, or boolean"),
+                ))
+            }
+        };
+        match key.as_str() {
+            "on-interruption" => {
+                updated.on_interruption = one_of(key, &s, &["release", "swap", "noop"])?
+            }
+            "watch-interval-seconds" => updated.watch_interval_seconds = seconds(key, &s)?,
+            "reconcile-interval-seconds" => updated.reconcile_interval_seconds = seconds(key, &s)?,
+            "watch-source" => updated.watch_source = one_of(key, &s, &["describe", "imds"])?,
+            "describe-cache-ttl-seconds" => updated.describe_cache_ttl_seconds = number(key, &s)?,
+            "circuit-failure-threshold" => updated.circuit_failure_threshold = number(key, &s)?,
+            "circuit-cool-down-seconds" => updated.circuit_cool_down_seconds = number(key, &s)?,
+            "no-steal" => updated.no_steal = boolean(key, &s)?,
+            "desired-tags" => {
+                tags::parse(&s)?;
+                updated.desired_tags = s
+            }
+            "remove-unknown-tags" => updated.remove_unknown_tags = boolean(key, &s)?,
+            "post-associate-cmd" => updated.post_associate_cmd = s,
+            "post-release-cmd" => updated.post_release_cmd = s,
+            "hook-timeout-seconds" => updated.hook_timeout_seconds = number(key, &s)?,
+            "hook-failure-policy" => {
+                updated.hook_failure_policy = one_of(key, &s, &["warn", "fail"])?
+            }
+            "kind-tag-key" => updated.kind_tag_key = s,
+            // as the flag is (see "command::parse_flags")
+            "kind-tag-value" => {
+                updated.kind_tag_value = command::namespaced(&updated.namespace, &s)
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("config '{key}' is unknown or cannot be reloaded"),
+                ))
+            }
+        }
+    }
+
+    let mut changed = Vec::new();
+    for key in entries.keys() {
+        if describe(opts, key) != describe(&updated, key) {
+            changed.push(key.clone());
+        }
+    }
+    *opts = updated;
+    Ok(changed)
+}
+
+/// Returns the JSON Schema of the config file, with the same keys and values as "apply".
+pub fn schema() -> Value {
+    let seconds =
+        |description: &str| json!({"description": description, "type": "integer", "minimum": 1});
+    let number =
+        |description: &str| json!({"description": description, "type": "integer", "minimum": 0});
+    let string = |description: &str| json!({"description": description, "type": "string"});
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "aws-ip-provisioner config file",
+        "description": format!("Settings reloaded while the daemon is running (aws-ip-provisioner {})", env!("CARGO_PKG_VERSION")),
+        "type": "object",
+        "properties": {
+            "on-interruption": {
+                "description": "Action on the spot interruption notice",
+                "enum": ["release", "swap", "noop"],
+            },
+            "watch-interval-seconds": seconds("Interval to poll the spot interruption notice"),
+            "reconcile-interval-seconds": seconds("Interval to reconcile the EIP association"),
+            "watch-source": {
+                "description": "Source of the association state to reconcile",
+                "enum": ["describe", "imds"],
+            },
+            "describe-cache-ttl-seconds": number("TTL in seconds to cache describe API results"),
+            "circuit-failure-threshold": number("Consecutive reconcile failures to open the circuit (0 to disable)"),
+            "circuit-cool-down-seconds": number("Seconds to keep the circuit open before retrying"),
+            "no-steal": {
+                "description": "Never disassociate the EIP from another live instance",
+                "type": "boolean",
+            },
+            "desired-tags": {
+                "description": "Comma-separated tags (key=value) to converge the EIP to",
+                "type": "string",
+                "examples": ["Team=infra,CostCenter=1234"],
+            },
+            "remove-unknown-tags": {
+                "description": "Remove the EIP tags other than the desired and the tool's own",
+                "type": "boolean",
+            },
+            "post-associate-cmd": string("Command to run after association"),
+            "post-release-cmd": string("Command to run after release"),
+            "hook-timeout-seconds": number("Timeout of the hook commands"),
+            "hook-failure-policy": {
+                "description": "Whether a failed hook fails the run",
+                "enum": ["warn", "fail"],
+            },
+            "kind-tag-key": string("Key of the EIP tag that groups the tool-managed EIPs"),
+            "kind-tag-value": string("Value of the EIP tag that groups the tool-managed EIPs"),
+        },
+        "additionalProperties": false,
+    })
+}
+
+/// Returns the current value of the reloadable setting, for the change log.
+fn describe(opts: &Flags, key: &str) -> String {
+    match key {
+        "on-interruption" => opts.on_interruption.clone(),
+        "watch-interval-seconds" => opts.watch_interval_seconds.to_string(),
+        "reconcile-interval-seconds" => opts.reconcile_interval_seconds.to_string(),
+        "watch-source" => opts.watch_source.clone(),
+        "describe-cache-ttl-seconds" => opts.describe_cache_ttl_seconds.to_string(),
+        "circuit-failure-threshold" => opts.circuit_failure_threshold.to_string(),
+        "circuit-cool-down-seconds" => opts.circuit_cool_down_seconds.to_string(),
+        "no-steal" => opts.no_steal.to_string(),
+        "desired-tags" => opts.desired_tags.clone(),
+        "remove-unknown-tags" => opts.remove_unknown_tags.to_string(),
+        "post-associate-cmd" => opts.post_associate_cmd.clone(),
+        "post-release-cmd" => opts.post_release_cmd.clone(),
+        "hook-timeout-seconds" => opts.hook_timeout_seconds.to_string(),
+        "hook-failure-policy" => opts.hook_failure_policy.clone(),
+        "kind-tag-key" => opts.kind_tag_key.clone(),
+        "kind-tag-value" => opts.kind_tag_value.clone(),
+        _ => String::new(),
+    }
+}
+
+fn number(key: &str, s: &str) -> io::Result<u32> {
+    s.parse::<u32>().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid config '{key}' value '{s}' ({})", e),
+        )
+    })
+}
+
+/// Rejects zero, which would turn the daemon loop into a busy loop.
+fn seconds(key: &str, s: &str) -> io::Result<u32> {
+    match number(key, s)? {
+        0 => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("config '{key}' must be positive"),
+        )),
+        v => Ok(v),
+    }
+}
+
+fn boolean(key: &str, s: &str) -> io::Result<bool> {
+    s.parse::<bool>().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid config '{key}' value '{s}' ({})", e),
+        )
+    })
+}
+
+fn one_of(key: &str, s: &str, values: &[&str]) -> io::Result<String> {
+    if !values.contains(&s) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid config '{key}' value '{s}' (expected one of {values:?})"),
+        ));
+    }
+    Ok(s.to_string())
+}
@@ -0,0 +1,80 @@
+use std::io::{self, BufRead, Error, ErrorKind, Write};
+
+use aws_sdk_ec2::model::Address;
+
+/// Tag key that records the allocation time of the EIP in unix seconds.
+/// EC2 does not expose the allocation time, so the tool tags it on allocation.
+pub const ALLOCATED_AT_TAG_KEY: &str = "AllocatedAt";
+
+/// Picks one address out of the candidates with the conflict policy
+/// ("oldest", "newest", "fail", or "interactive").
+/// Addresses without the "AllocatedAt" tag are treated as the oldest,
+/// and ties are broken by the allocation ID so the pick is deterministic.
+pub fn select(mut addrs: Vec<Address>, policy: &str) -> io::Result<Option<Address>> {
+    if addrs.len() <= 1 {
+        return Ok(addrs.pop());
+    }
+    log::warn!(
+        "found {} matching EIPs -- resolving with conflict policy '{policy}'",
+        addrs.len()
+    );
+    addrs.sort_by_key(|addr| (allocated_at(addr), addr.allocation_id.clone()));
+
+    match policy {
+        "oldest" => Ok(Some(addrs.remove(0))),
+        "newest" => Ok(addrs.pop()),
+        "interactive" => prompt(addrs),
+        _ => Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "found {} matching EIPs {:?} (conflict policy '{policy}')",
+                addrs.len(),
+                addrs
+                    .iter()
+                    .map(|addr| addr.allocation_id().unwrap_or_default())
+                    .collect::<Vec<_>>()
+            ),
+        )),
+    }
+}
+
+/// Returns the "AllocatedAt" tag, zero if not tagged.
+pub fn allocated_at(addr: &Address) -> u64 {
+    for tag in addr.tags().unwrap_or_default() {
+        if tag.key() == Some(ALLOCATED_AT_TAG_KEY) {
+            return tag.value().unwrap_or_default().parse().unwrap_or(0);
+        }
+    }
+    0
+}
+
+fn prompt(mut addrs: Vec<Address>) -> io::Result<Option<Address>> {
+    let mut stdout = io::stdout();
+    for (i, addr) in addrs.iter().enumerate() {
+        writeln!(
+            stdout,
+            "[{i}] {} (allocation ID {}, allocated at {})",
+            addr.public_ip().unwrap_or_default(),
+            addr.allocation_id().unwrap_or_default(),
+            allocated_at(addr)
+        )?;
+    }
+    write!(stdout, "select the EIP to use [0-{}]: ", addrs.len() - 1)?;
+    stdout.flush()?;
+
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    let idx: usize = line.trim().parse().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid selection '{}' ({})", line.trim(), e),
+        )
+    })?;
+    if idx >= addrs.len() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("selection {idx} out of range"),
+        ));
+    }
+    Ok(Some(addrs.remove(idx)))
+}
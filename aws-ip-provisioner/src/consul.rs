@@ -0,0 +1,367 @@
+use std::{
+    env, fs,
+    io::{self, Error, ErrorKind},
+    path::Path,
+};
+
+use hyper::{body, client::HttpConnector, Body, Method, Request, StatusCode};
+use hyper_rustls::HttpsConnector;
+use serde_json::{json, Value};
+use tokio::{
+    task::JoinHandle,
+    time::{sleep, timeout, Duration, Instant},
+};
+
+use crate::{command::Flags, ec2, state::State, tls};
+
+/// Environment variable of the ACL token, same as the Consul CLI,
+/// rather than a flag visible in the process list.
+pub const TOKEN_ENV: &str = "CONSUL_HTTP_TOKEN";
+
+/// TTL of the claim session, renewed at half of it while claiming.
+/// ref. <https://developer.hashicorp.com/consul/api-docs/session#ttl>
+const SESSION_TTL: Duration = Duration::from_secs(30);
+
+/// How long to wait for the other node to release the claim of the same "Id".
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Consul HTTP API client of the local agent (or the servers).
+#[derive(Clone)]
+pub struct Client {
+    inner: hyper::Client<HttpsConnector<HttpConnector>>,
+    address: String,
+    token: String,
+}
+
+impl Client {
+    /// Creates the client of the address (e.g., "http://127.0.0.1:8500"),
+    /// with the ACL token of "CONSUL_HTTP_TOKEN" if set.
+    pub fn new(address: &str) -> Self {
+        let https = tls::https_connector();
+        Self {
+            inner: hyper::Client::builder().build(https),
+            address: address.trim_end_matches('/').to_string(),
+            token: env::var(TOKEN_ENV).unwrap_or_default(),
+        }
+    }
+
+    /// Sends the request to the path (e.g., "/v1/kv/foo?raw"), and returns
+    /// the status and the body, for the callers to handle 404.
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Vec<u8>,
+    ) -> io::Result<(StatusCode, Vec<u8>)> {
+        let uri = format!("{}{path}", self.address);
+        let mut req = Request::builder().method(method.clone()).uri(&uri);
+        if !self.token.is_empty() {
+            req = req.header("X-Consul-Token", &self.token);
+        }
+        let req = req.body(Body::from(body)).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to build {method} {uri} {}", e),
+            )
+        })?;
+        let resp = timeout(Duration::from_secs(10), self.inner.request(req))
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, format!("{method} {uri} timed out")))?
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed {method} {uri} {}", e)))?;
+        let status = resp.status();
+        let bytes = body::to_bytes(resp.into_body()).await.map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to read {method} {uri} response {}", e),
+            )
+        })?;
+        if status != StatusCode::OK && status != StatusCode::NOT_FOUND {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "failed {method} {uri} {status} '{}'",
+                    String::from_utf8_lossy(&bytes)
+                ),
+            ));
+        }
+        Ok((status, bytes.to_vec()))
+    }
+
+    /// Same as "request", but fails on 404 and returns the JSON response.
+    async fn request_json(&self, method: Method, path: &str, body: Vec<u8>) -> io::Result<Value> {
+        let (status, bytes) = self.request(method.clone(), path, body).await?;
+        if status == StatusCode::NOT_FOUND {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{method} {path} not found"),
+            ));
+        }
+        serde_json::from_slice(&bytes).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse {method} {path} response {}", e),
+            )
+        })
+    }
+
+    /// Returns the raw value of the key, or "None" if it does not exist.
+    /// ref. <https://developer.hashicorp.com/consul/api-docs/kv#read-key>
+    pub async fn kv_get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let (status, bytes) = self
+            .request(Method::GET, &format!("/v1/kv/{key}?raw"), Vec::new())
+            .await?;
+        Ok(if status == StatusCode::NOT_FOUND {
+            None
+        } else {
+            Some(bytes)
+        })
+    }
+
+    /// ref. <https://developer.hashicorp.com/consul/api-docs/kv#create-update-key>
+    pub async fn kv_put(&self, key: &str, value: Vec<u8>) -> io::Result<()> {
+        let resp = self
+            .request_json(Method::PUT, &format!("/v1/kv/{key}"), value)
+            .await?;
+        if resp != Value::Bool(true) {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("failed to write Consul key {key} ({resp})"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the Consul key of the state of the "Id" tag value.
+pub fn state_key(prefix: &str, id_tag_value: &str) -> String {
+    format!("{}/state/{id_tag_value}", prefix.trim_matches('/'))
+}
+
+/// Returns the Consul key of the claim of the "Id" tag value.
+pub fn claim_key(prefix: &str, id_tag_value: &str) -> String {
+    format!("{}/claims/{id_tag_value}", prefix.trim_matches('/'))
+}
+
+/// Writes the mounted EIP file from Consul KV if the file does not exist
+/// (e.g., the replacement instance with a fresh volume), so that the
+/// run picks up the EIP of the "Id" as with the file backend.
+/// Returns true if restored.
+pub async fn restore_state(client: &Client, opts: &Flags) -> io::Result<bool> {
+    if Path::new(&opts.mounted_eip_file_path).exists() {
+        return Ok(false);
+    }
+    let key = state_key(&opts.consul_kv_prefix, &opts.id_tag_value);
+    let d = match client.kv_get(&key).await? {
+        Some(d) if !d.is_empty() => d,
+        _ => {
+            log::info!("no state in Consul key {key}");
+            return Ok(false);
+        }
+    };
+    // saved as any other state write (renamed into place), never a partial file
+    let mut restored: State = serde_yaml::from_slice(&d).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid state in Consul key {key} '{}'", e),
+        )
+    })?;
+    restored.save(&opts.mounted_eip_file_path)?;
+    log::info!(
+        "restored {} from Consul key {key}",
+        opts.mounted_eip_file_path
+    );
+    Ok(true)
+}
+
+/// Writes the mounted EIP file to Consul KV.
+pub async fn save_state(client: &Client, opts: &Flags) -> io::Result<()> {
+    let key = state_key(&opts.consul_kv_prefix, &opts.id_tag_value);
+    let d = fs::read(&opts.mounted_eip_file_path)?;
+    client.kv_put(&key, d).await?;
+    log::info!("saved {} to Consul key {key}", opts.mounted_eip_file_path);
+    Ok(())
+}
+
+/// Claim of the "Id" held with a Consul session, so that two instances of the
+/// same "Id" (e.g., the replacement booting before the old one terminates) do
+/// not provision at the same time. The session is deleted with its lock if the
+/// holder dies without releasing it (after "SESSION_TTL").
+/// ref. <https://developer.hashicorp.com/consul/docs/dynamic-app-config/sessions>
+pub struct Claim {
+    client: Client,
+    key: String,
+    session_id: String,
+    renew: JoinHandle<()>,
+}
+
+impl Claim {
+    /// Creates the session and acquires the key with it, waiting up to
+    /// "CLAIM_TIMEOUT" for the other holder to release it.
+    pub async fn acquire(client: &Client, key: &str, ec2_instance_id: &str) -> io::Result<Self> {
+        let resp = client
+            .request_json(
+                Method::PUT,
+                "/v1/session/create",
+                serde_json::to_vec(&json!({
+                    "Name": format!("ip-manager-{ec2_instance_id}"),
+                    "TTL": format!("{}s", SESSION_TTL.as_secs()),
+                    "Behavior": "delete",
+                    "LockDelay": "0s",
+                }))?,
+            )
+            .await?;
+        let session_id = resp["ID"]
+            .as_str()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no session ID from Consul"))?
+            .to_string();
+
+        let renew = {
+            let client = client.clone();
+            let path = format!("/v1/session/renew/{session_id}");
+            tokio::spawn(async move {
+                loop {
+                    sleep(SESSION_TTL / 2).await;
+                    if let Err(e) = client.request(Method::PUT, &path, Vec::new()).await {
+                        log::warn!("failed to renew Consul session '{}'", e);
+                    }
+                }
+            })
+        };
+        let claim = Self {
+            client: client.clone(),
+            key: key.to_string(),
+            session_id,
+            renew,
+        };
+
+        let value = serde_json::to_vec(&json!({ "instance_id": ec2_instance_id }))?;
+        let started = Instant::now();
+        loop {
+            let acquired = claim
+                .client
+                .request_json(
+                    Method::PUT,
+                    &format!("/v1/kv/{key}?acquire={}", claim.session_id),
+                    value.clone(),
+                )
+                .await;
+            match acquired {
+                Ok(Value::Bool(true)) => {
+                    log::info!("claimed Consul key {key}");
+                    return Ok(claim);
+                }
+                Ok(_) if started.elapsed() < CLAIM_TIMEOUT => {
+                    log::info!("Consul key {key} is claimed by another node -- waiting");
+                    sleep(Duration::from_secs(5)).await;
+                }
+                Ok(_) => {
+                    claim.release().await;
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        format!("Consul key {key} still claimed by another node after {CLAIM_TIMEOUT:?}"),
+                    ));
+                }
+                Err(e) => {
+                    claim.release().await;
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Releases the key and destroys the session, logging the failure
+    /// since the session expires anyway.
+    pub async fn release(self) {
+        self.renew.abort();
+        let path = format!("/v1/kv/{}?release={}", self.key, self.session_id);
+        if let Err(e) = self.client.request(Method::PUT, &path, Vec::new()).await {
+            log::warn!("failed to release Consul key {} '{}'", self.key, e);
+        }
+        let path = format!("/v1/session/destroy/{}", self.session_id);
+        if let Err(e) = self.client.request(Method::PUT, &path, Vec::new()).await {
+            log::warn!("failed to destroy Consul session '{}'", e);
+        }
+    }
+}
+
+/// Returns the service ID of the "Id" tag value (unique per node).
+pub fn service_id(service_name: &str, id_tag_value: &str) -> String {
+    format!("{service_name}-{id_tag_value}")
+}
+
+/// Returns the service definition of the EIP, with the TCP health check
+/// of "--consul-service-port" on the private IP if set (the instance cannot
+/// reach its own EIP from inside the VPC on every network path).
+pub fn service_definition(
+    opts: &Flags,
+    eip: &ec2::Eip,
+    ec2_instance_id: &str,
+    private_ip: &str,
+) -> Value {
+    let mut v = json!({
+        "ID": service_id(&opts.consul_service_name, &opts.id_tag_value),
+        "Name": opts.consul_service_name,
+        "Address": eip.public_ip,
+        "Meta": {
+            "id": opts.id_tag_value,
+            "kind": opts.kind_tag_value,
+            "allocation_id": eip.allocation_id,
+            "instance_id": ec2_instance_id,
+        },
+    });
+    if !opts.kind_tag_value.is_empty() {
+        v["Tags"] = json!([opts.kind_tag_value]);
+    }
+    if opts.consul_service_port > 0 {
+        v["Port"] = json!(opts.consul_service_port);
+        if !private_ip.is_empty() {
+            v["Check"] = json!({
+                "Name": format!("{} TCP", opts.consul_service_name),
+                "TCP": format!("{private_ip}:{}", opts.consul_service_port),
+                "Interval": "10s",
+                "Timeout": "2s",
+                "DeregisterCriticalServiceAfter": "30m",
+            });
+        }
+    }
+    v
+}
+
+/// Registers (or updates) the EIP as the service of the local agent.
+/// ref. <https://developer.hashicorp.com/consul/api-docs/agent/service#register-service>
+pub async fn register_service(
+    client: &Client,
+    opts: &Flags,
+    eip: &ec2::Eip,
+    ec2_instance_id: &str,
+    private_ip: &str,
+) -> io::Result<()> {
+    let def = service_definition(opts, eip, ec2_instance_id, private_ip);
+    client
+        .request(
+            Method::PUT,
+            "/v1/agent/service/register?replace-existing-checks=true",
+            serde_json::to_vec(&def)?,
+        )
+        .await?;
+    log::info!(
+        "registered Consul service {} at {}",
+        def["ID"],
+        eip.public_ip
+    );
+    Ok(())
+}
+
+/// Deregisters the service, which is not an error if already deregistered.
+pub async fn deregister_service(client: &Client, opts: &Flags) -> io::Result<()> {
+    let id = service_id(&opts.consul_service_name, &opts.id_tag_value);
+    client
+        .request(
+            Method::PUT,
+            &format!("/v1/agent/service/deregister/{id}"),
+            Vec::new(),
+        )
+        .await?;
+    log::info!("deregistered Consul service {id}");
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+
+use crate::{audit, metrics, notify, progress, ratelimit, secret, summary, timing};
+
+/// State of one run shared by its calls: the audit log, the summary, the metrics,
+/// the phase durations, the AWS API rate limit, and the progress and notification sinks.
+/// Passed down the calls rather than kept in process globals, so that the runs
+/// in the same process (e.g., the tests in parallel) never see each other's state.
+pub struct Context {
+    pub audit: audit::AuditLog,
+    /// Shared with the IMDS client, which warns on its retries (see "Flags::imds").
+    pub summary: Arc<summary::Summary>,
+    /// Shared with the rate limit, which counts the throttled calls.
+    pub metrics: Arc<metrics::Registry>,
+    pub timing: timing::Timings,
+    /// Shared with the HTTP clients of the AWS SDK (see "sdk::Options").
+    pub ratelimit: Arc<ratelimit::Limiter>,
+    /// Shared with the alert and the notification sinks (see "sdk::Options"),
+    /// so that their credentials are not re-fetched on every send.
+    pub secrets: Arc<secret::SecretCache>,
+    pub progress: progress::Progress,
+    pub notify: notify::Notifier,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        let metrics = Arc::new(metrics::Registry::default());
+        let ratelimit = Arc::new(ratelimit::Limiter::new(metrics.clone()));
+        Self::shared(metrics, ratelimit)
+    }
+}
+
+impl Context {
+    /// Returns the fresh state of one run that shares only the metrics and
+    /// the AWS API rate limit with the other runs (e.g., the requests of "ip-manager serve").
+    pub fn shared(metrics: Arc<metrics::Registry>, ratelimit: Arc<ratelimit::Limiter>) -> Self {
+        Self {
+            audit: audit::AuditLog::default(),
+            summary: Arc::new(summary::Summary::default()),
+            metrics,
+            timing: timing::Timings::default(),
+            ratelimit,
+            secrets: Arc::new(secret::SecretCache::default()),
+            progress: progress::Progress::default(),
+            notify: notify::Notifier::default(),
+        }
+    }
+}
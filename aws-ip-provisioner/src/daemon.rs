@@ -0,0 +1,453 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_sdk_ec2::model::Address;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::{
+    alert::Alerter,
+    cache::DescribeCache,
+    circuit::CircuitBreaker,
+    command::{self, Flags},
+    config,
+    context::Context,
+    ec2,
+    imds::Imds,
+    interruption, platform, pool, progress,
+    provisioner::{self, AwsEc2, Clock, Ec2},
+    security_group, state, stun, tags,
+};
+
+/// Counter of the repairs of the EIP association (e.g., silently detached on instance stop/start).
+pub const REASSOCIATED_COUNTER: &str = "eip_reassociated_total";
+
+/// Counter of the tag set repairs ("--desired-tags").
+pub const RETAGGED_COUNTER: &str = "eip_retagged_total";
+
+/// Keeps running after the provision, to watch for spot interruption notices
+/// (every "watch_interval_seconds") and to reconcile the EIP association
+/// (every "reconcile_interval_seconds"), until the instance gets interrupted.
+/// Renews the lease of the pool claim ("pool_lease_seconds").
+/// Reloads the config file on SIGHUP (Ctrl+Break on Windows).
+pub async fn run(
+    ctx: &Context,
+    imds: &Imds,
+    ec2_manager: &ec2::Manager,
+    opts: &Flags,
+    ec2_instance_id: &str,
+    eip: &ec2::Eip,
+) -> io::Result<()> {
+    let mut opts = opts.clone();
+    let mut watch_interval = Duration::from_secs(opts.watch_interval_seconds as u64);
+    let mut reconcile_interval = Duration::from_secs(opts.reconcile_interval_seconds as u64);
+    let mut cache = DescribeCache::new(Duration::from_secs(opts.describe_cache_ttl_seconds as u64));
+    let mut circuit = CircuitBreaker::new(
+        opts.circuit_failure_threshold,
+        Duration::from_secs(opts.circuit_cool_down_seconds as u64),
+        ctx.metrics.clone(),
+    );
+    let mut alerter = Alerter::new(
+        opts.alert(),
+        opts.sdk_options(ctx),
+        ec2_instance_id,
+        &opts.id_tag_value,
+    );
+    let ec2 = AwsEc2 { ec2_manager, ctx };
+    let mut hangup = platform::Reload::new()?;
+    log::info!(
+        "running daemon (watch interval {watch_interval:?}, reconcile interval {reconcile_interval:?})"
+    );
+
+    let mut last_reconcile = Instant::now();
+    let mut last_renew = Instant::now();
+    loop {
+        tokio::select! {
+            _ = sleep(watch_interval) => {}
+            _ = hangup.recv() => {
+                if reload(&mut opts) {
+                    watch_interval = Duration::from_secs(opts.watch_interval_seconds as u64);
+                    reconcile_interval = Duration::from_secs(opts.reconcile_interval_seconds as u64);
+                    cache = DescribeCache::new(Duration::from_secs(opts.describe_cache_ttl_seconds as u64));
+                    circuit = CircuitBreaker::new(
+                        opts.circuit_failure_threshold,
+                        Duration::from_secs(opts.circuit_cool_down_seconds as u64),
+                        ctx.metrics.clone(),
+                    );
+                }
+                continue;
+            }
+        }
+
+        if interruption::check(imds).await {
+            return interruption::handle(ctx, ec2_manager, ec2_instance_id, eip, &opts).await;
+        }
+
+        // renew at a third of the lease, to survive two failed renewals
+        if opts.pool_lease_seconds > 0
+            && last_renew.elapsed() >= Duration::from_secs(opts.pool_lease_seconds / 3)
+        {
+            last_renew = Instant::now();
+            let lease = pool::Lease {
+                instance_id: ec2_instance_id,
+                seconds: opts.pool_lease_seconds,
+                now: provisioner::SystemClock.now_unix_seconds(),
+            };
+            if let Err(e) = pool::renew(&ec2, &eip.allocation_id, &lease).await {
+                log::warn!("failed to renew the lease of {} '{}'", eip.allocation_id, e);
+            }
+        }
+
+        if last_reconcile.elapsed() < reconcile_interval {
+            continue;
+        }
+        if !circuit.allow() {
+            log::debug!("circuit is open -- skipping reconcile");
+            continue;
+        }
+        last_reconcile = Instant::now();
+        match reconcile(ctx, &ec2, &mut cache, &opts, ec2_instance_id, eip).await {
+            Ok(reassociated) => {
+                circuit.record_success();
+                alerter.record_success().await;
+                cross_check_public_ip(ctx, &opts, eip).await;
+                if reassociated {
+                    if let Err(e) =
+                        command::post_associate(ctx, imds, ec2_manager, &opts, eip, ec2_instance_id)
+                            .await
+                    {
+                        log::warn!("failed post-associate steps '{}'", e);
+                    }
+                }
+            }
+            Err(e) => {
+                log::warn!("failed to reconcile EIP association '{}'", e);
+                circuit.record_failure(&e.to_string());
+                alerter.record_failure(&e.to_string()).await;
+            }
+        }
+
+        if opts.pool_min_free > 0 || opts.pool_max_free > 0 {
+            autoscale_pool(ctx, &ec2, &opts).await;
+        }
+        if !opts.sync_security_group_id.is_empty() {
+            // the other nodes' EIPs change without this node re-associating
+            if let Err(e) = security_group::sync_fleet(ec2_manager, &opts).await {
+                log::warn!(
+                    "failed to sync security group {} '{}'",
+                    opts.sync_security_group_id,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Autoscales the pool, logging the failure to retry on the next reconcile.
+async fn autoscale_pool(ctx: &Context, ec2: &dyn Ec2, opts: &Flags) {
+    let now = provisioner::SystemClock.now_unix_seconds();
+    match pool::autoscale(ec2, opts, now).await {
+        Ok(scaled) => {
+            let free = scaled.free + scaled.allocated - scaled.released;
+            ctx.metrics.set_gauge(pool::FREE_GAUGE, free as f64);
+            if scaled.allocated > 0 || scaled.released > 0 {
+                progress::emit(
+                    ctx,
+                    progress::POOL_SCALED,
+                    &[
+                        ("free", &free.to_string()),
+                        ("allocated", &scaled.allocated.to_string()),
+                        ("released", &scaled.released.to_string()),
+                    ],
+                );
+            }
+        }
+        Err(e) => log::warn!("failed to autoscale the pool '{}'", e),
+    }
+}
+
+/// Only watches the association of the already provisioned EIP
+/// (every "reconcile_interval_seconds"), re-associating it if detached.
+/// With "imds" watch source, checks the public IPv4 of the instance metadata
+/// and only calls the EC2 API when it does not match.
+/// Reloads the config file on SIGHUP.
+pub async fn watch(
+    ctx: &Context,
+    imds: &Imds,
+    ec2_manager: &ec2::Manager,
+    opts: &Flags,
+    ec2_instance_id: &str,
+    eip: &ec2::Eip,
+) -> io::Result<()> {
+    let mut opts = opts.clone();
+    let mut reconcile_interval = Duration::from_secs(opts.reconcile_interval_seconds as u64);
+    let mut cache = DescribeCache::new(Duration::from_secs(opts.describe_cache_ttl_seconds as u64));
+    let mut circuit = CircuitBreaker::new(
+        opts.circuit_failure_threshold,
+        Duration::from_secs(opts.circuit_cool_down_seconds as u64),
+        ctx.metrics.clone(),
+    );
+    let mut alerter = Alerter::new(
+        opts.alert(),
+        opts.sdk_options(ctx),
+        ec2_instance_id,
+        &opts.id_tag_value,
+    );
+    let ec2 = AwsEc2 { ec2_manager, ctx };
+    let mut hangup = platform::Reload::new()?;
+    log::info!(
+        "watching EIP {} association (source {}, interval {reconcile_interval:?})",
+        eip.public_ip,
+        opts.watch_source
+    );
+
+    loop {
+        tokio::select! {
+            _ = sleep(reconcile_interval) => {}
+            _ = hangup.recv() => {
+                if reload(&mut opts) {
+                    reconcile_interval = Duration::from_secs(opts.reconcile_interval_seconds as u64);
+                    cache = DescribeCache::new(Duration::from_secs(opts.describe_cache_ttl_seconds as u64));
+                    circuit = CircuitBreaker::new(
+                        opts.circuit_failure_threshold,
+                        Duration::from_secs(opts.circuit_cool_down_seconds as u64),
+                        ctx.metrics.clone(),
+                    );
+                }
+                continue;
+            }
+        }
+
+        if opts.watch_source == "imds" {
+            match imds.fetch("public-ipv4").await {
+                Ok(ip) if ip.trim() == eip.public_ip => {
+                    log::debug!("instance metadata public-ipv4 matches {}", eip.public_ip);
+                    continue;
+                }
+                Ok(ip) => log::info!(
+                    "instance metadata public-ipv4 '{}' does not match {}",
+                    ip.trim(),
+                    eip.public_ip
+                ),
+                // no public IPv4 at all (404) once the EIP is detached
+                Err(e) => log::info!("failed to fetch public-ipv4 '{}'", e),
+            }
+        }
+
+        if !circuit.allow() {
+            log::debug!("circuit is open -- skipping reconcile");
+            continue;
+        }
+        match reconcile(ctx, &ec2, &mut cache, &opts, ec2_instance_id, eip).await {
+            Ok(reassociated) => {
+                circuit.record_success();
+                alerter.record_success().await;
+                cross_check_public_ip(ctx, &opts, eip).await;
+                if reassociated {
+                    if let Err(e) =
+                        command::post_associate(ctx, imds, ec2_manager, &opts, eip, ec2_instance_id)
+                            .await
+                    {
+                        log::warn!("failed post-associate steps '{}'", e);
+                    }
+                }
+            }
+            Err(e) => {
+                log::warn!("failed to reconcile EIP association '{}'", e);
+                circuit.record_failure(&e.to_string());
+                alerter.record_failure(&e.to_string()).await;
+            }
+        }
+    }
+}
+
+/// Compares the EIP with the public IP seen by "--stun-server" (see "stun::check"),
+/// e.g., a NAT gateway added to the route of the subnet after the provisioning.
+async fn cross_check_public_ip(ctx: &Context, opts: &Flags, eip: &ec2::Eip) {
+    if opts.stun_server.is_empty() {
+        return;
+    }
+    match stun::check(ctx, &opts.stun_server, &eip.public_ip).await {
+        Ok((_, Some(mismatch))) => log::warn!("{mismatch}"),
+        Ok(_) => {}
+        Err(e) => log::warn!(
+            "failed to discover the public IP with STUN server {} '{}'",
+            opts.stun_server,
+            e
+        ),
+    }
+}
+
+/// Reloads the config file into the flags, keeping the current flags if it fails,
+/// so that a bad rollout does not stop the daemon. Returns true if any setting changed.
+fn reload(opts: &mut Flags) -> bool {
+    if opts.config_file.is_empty() {
+        log::info!("received SIGHUP without --config-file -- ignoring");
+        return false;
+    }
+    log::info!("received SIGHUP -- reloading {}", opts.config_file);
+    let file_path = opts.config_file.clone();
+    match config::apply(opts, &file_path) {
+        Ok(changed) if changed.is_empty() => {
+            log::info!("no config change in {file_path}");
+            false
+        }
+        Ok(changed) => {
+            log::info!("reloaded config {:?} from {file_path}", changed);
+            true
+        }
+        Err(e) => {
+            log::warn!(
+                "failed to reload config '{}' -- keeping the current config",
+                e
+            );
+            false
+        }
+    }
+}
+
+/// Re-associates the EIP if it is no longer associated with the local instance,
+/// or with the target network interface ("--target-eni-tag", "--target-device-index").
+/// Converges the EIP tags to "--desired-tags" along the way.
+/// Returns true if re-associated.
+async fn reconcile(
+    ctx: &Context,
+    ec2: &dyn Ec2,
+    cache: &mut DescribeCache,
+    opts: &Flags,
+    ec2_instance_id: &str,
+    eip: &ec2::Eip,
+) -> io::Result<bool> {
+    let addr = match cache.address(ec2, &eip.allocation_id).await? {
+        Some(v) => v,
+        None => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("EIP {} not found", eip.allocation_id),
+            ));
+        }
+    };
+    if !opts.desired_tags.is_empty() || opts.remove_unknown_tags {
+        // tag drift is repaired best-effort, never failing the association repair
+        match converge_tags(ctx, ec2, opts, &addr).await {
+            Ok(true) => cache.invalidate(),
+            Ok(false) => {}
+            Err(e) => log::warn!("failed to converge EIP {} tags '{}'", eip.public_ip, e),
+        }
+    }
+
+    // the association that this instance made, verified rather than the allocation ID only
+    let recorded = state::State::load(&opts.mounted_eip_file_path)?;
+    if recorded.instance_id == ec2_instance_id
+        && recorded.is_associated()
+        && addr.association_id() != Some(recorded.association_id.as_str())
+    {
+        log::warn!(
+            "event: EIP {} was re-associated since association {} (now {:?} with {:?})",
+            eip.public_ip,
+            recorded.association_id,
+            addr.association_id(),
+            addr.instance_id().or_else(|| addr.network_interface_id())
+        );
+        if addr.instance_id() == Some(ec2_instance_id) {
+            state::record_association(
+                &opts.mounted_eip_file_path,
+                addr.association_id().unwrap_or_default(),
+                ec2_instance_id,
+                provisioner::SystemClock.now_unix_seconds(),
+            )?;
+        }
+    }
+
+    // re-resolved every time, as the target ENI may have been replaced
+    let target = provisioner::resolve_target(ec2, opts, ec2_instance_id).await?;
+    if addr.instance_id() == Some(ec2_instance_id) {
+        if target.matches(&addr) {
+            log::debug!("EIP {} is associated with {ec2_instance_id}", eip.public_ip);
+            return Ok(false);
+        }
+        log::warn!(
+            "EIP {} is associated with {ec2_instance_id} but on network interface {:?} (private IP {:?})",
+            eip.public_ip,
+            addr.network_interface_id(),
+            addr.private_ip_address()
+        );
+    } else if addr.association_id().is_some() {
+        let live = match addr.instance_id() {
+            Some(other) => cache.is_instance_running(ec2, other).await?,
+            None => true,
+        };
+        if live && opts.no_steal {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "EIP {} is associated with another live resource -- skipping (no-steal)",
+                    eip.public_ip
+                ),
+            ));
+        }
+    }
+
+    log::warn!(
+        "EIP {} is not associated with {} -- re-associating",
+        eip.public_ip,
+        target.describe(ec2_instance_id)
+    );
+    let ret = ec2
+        .associate(&eip.allocation_id, ec2_instance_id, &target, true)
+        .await;
+    cache.invalidate();
+    let association_id = ret?;
+    log::warn!(
+        "event: repaired EIP {} association with {} (association ID {association_id})",
+        eip.public_ip,
+        target.describe(ec2_instance_id)
+    );
+    state::record_association(
+        &opts.mounted_eip_file_path,
+        &association_id,
+        ec2_instance_id,
+        provisioner::SystemClock.now_unix_seconds(),
+    )?;
+    provisioner::verify_association(
+        ec2,
+        &provisioner::SystemClock,
+        &eip.allocation_id,
+        &association_id,
+    )
+    .await?;
+    ctx.metrics.inc_counter(REASSOCIATED_COUNTER);
+    Ok(true)
+}
+
+/// Converges the EIP tags to "--desired-tags" (see "tags::diff"),
+/// returning whether any tag changed.
+async fn converge_tags(
+    ctx: &Context,
+    ec2: &dyn Ec2,
+    opts: &Flags,
+    addr: &Address,
+) -> io::Result<bool> {
+    let desired = tags::parse(&opts.desired_tags)?;
+    let diff = tags::diff(
+        &tags::of(addr),
+        &desired,
+        opts.remove_unknown_tags,
+        &[&opts.id_tag_key, &opts.kind_tag_key],
+    );
+    if diff.is_empty() {
+        return Ok(false);
+    }
+    let allocation_id = addr.allocation_id().unwrap_or_default();
+    log::warn!(
+        "event: converging EIP {allocation_id} tags (upsert {:?}, remove {:?})",
+        diff.upsert,
+        diff.remove
+    );
+    if !diff.upsert.is_empty() {
+        ec2.create_tags(allocation_id, diff.upsert).await?;
+    }
+    if !diff.remove.is_empty() {
+        ec2.delete_tags(allocation_id, diff.remove).await?;
+    }
+    ctx.metrics.inc_counter(RETAGGED_COUNTER);
+    Ok(true)
+}
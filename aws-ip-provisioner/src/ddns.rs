@@ -0,0 +1,357 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hyper::{Body, Method, Request};
+use ring::hmac;
+use tokio::{
+    net::UdpSocket,
+    time::{timeout, Duration},
+};
+
+use crate::{dns, tls};
+
+/// Update URLs of the dynamic DNS services.
+/// ref. <https://www.duckdns.org/spec.jsp>
+pub const DUCKDNS_URL: &str = "https://www.duckdns.org/update";
+/// ref. <https://help.dyn.com/remote-access-api/perform-update/>
+pub const DYN_URL: &str = "https://members.dyndns.org/v3/update";
+/// ref. <https://www.noip.com/integrate/request>
+pub const NOIP_URL: &str = "https://dynupdate.no-ip.com/nic/update";
+
+/// Timeout of each update request.
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(15);
+
+const TYPE_A: u16 = 1;
+const TYPE_SOA: u16 = 6;
+const TYPE_AAAA: u16 = 28;
+const TYPE_TSIG: u16 = 250;
+const CLASS_IN: u16 = 1;
+const CLASS_ANY: u16 = 255;
+
+/// DNS UPDATE opcode in the header flags.
+/// ref. <https://www.rfc-editor.org/rfc/rfc2136#section-2.2>
+const OPCODE_UPDATE: u16 = 5 << 11;
+
+/// Allowed clock skew of the TSIG signature.
+const TSIG_FUDGE: u16 = 300;
+
+/// Updates the DuckDNS domain (e.g., "mynode" of "mynode.duckdns.org") with the token.
+pub async fn duckdns(url: &str, domain: &str, token: &str, ip: &str) -> io::Result<()> {
+    let domain = domain.trim_end_matches(".duckdns.org");
+    let param = if ip.contains(':') { "ipv6" } else { "ip" };
+    let body = get(
+        &format!("{url}?domains={domain}&token={token}&{param}={ip}"),
+        &[],
+    )
+    .await?;
+    // "KO" for the wrong token or domain, with no detail
+    if body.trim() != "OK" {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "DuckDNS rejected the update of {domain} '{}' -- check the token and the domain",
+                body.trim()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Updates the hostname with the dyndns2 protocol of Dyn and No-IP,
+/// with the username and the password (or the updater client key).
+pub async fn dyndns2(
+    url: &str,
+    hostname: &str,
+    username: &str,
+    password: &str,
+    ip: &str,
+) -> io::Result<()> {
+    let auth = STANDARD.encode(format!("{username}:{password}"));
+    let body = get(
+        &format!("{url}?hostname={hostname}&myip={ip}"),
+        &[("Authorization", format!("Basic {auth}"))],
+    )
+    .await?;
+    parse_dyndns2_response(&body)
+}
+
+/// Parses the dyndns2 return code (e.g., "good 203.0.113.7", "nochg 203.0.113.7").
+/// ref. <https://help.dyn.com/remote-access-api/return-codes/>
+pub fn parse_dyndns2_response(body: &str) -> io::Result<()> {
+    let code = body.split_whitespace().next().unwrap_or_default();
+    let (kind, reason) = match code {
+        "good" | "nochg" => return Ok(()),
+        "badauth" => (ErrorKind::PermissionDenied, "invalid username or password"),
+        "!donator" => (
+            ErrorKind::PermissionDenied,
+            "feature not available for the account",
+        ),
+        "notfqdn" | "nohost" => (
+            ErrorKind::NotFound,
+            "hostname does not exist in the account",
+        ),
+        "numhost" => (ErrorKind::InvalidInput, "too many hosts in the update"),
+        "abuse" => (
+            ErrorKind::PermissionDenied,
+            "hostname blocked for update abuse",
+        ),
+        "badagent" => (ErrorKind::InvalidInput, "user agent blocked"),
+        "dnserr" | "911" => (ErrorKind::Other, "server error -- retry later"),
+        _ => (ErrorKind::InvalidData, "unknown return code"),
+    };
+    Err(Error::new(
+        kind,
+        format!(
+            "dynamic DNS update failed with '{}' ({reason})",
+            body.trim()
+        ),
+    ))
+}
+
+async fn get(url: &str, headers: &[(&str, String)]) -> io::Result<String> {
+    let mut req = Request::builder()
+        .method(Method::GET)
+        .uri(url)
+        // No-IP blocks the clients without the user agent
+        .header(
+            "User-Agent",
+            format!("ip-manager/{}", env!("CARGO_PKG_VERSION")),
+        );
+    for (k, v) in headers {
+        req = req.header(*k, v);
+    }
+    let req = req.body(Body::empty()).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid dynamic DNS URL {}", e),
+        )
+    })?;
+    let client = hyper::Client::builder().build::<_, Body>(tls::https_connector());
+    let resp = timeout(UPDATE_TIMEOUT, client.request(req))
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, "dynamic DNS update timed out"))?
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed dynamic DNS update {}", e)))?;
+    let status = resp.status();
+    let b = hyper::body::to_bytes(resp.into_body()).await.map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to read dynamic DNS response {}", e),
+        )
+    })?;
+    let body = String::from_utf8_lossy(&b).to_string();
+    if status.as_u16() == 401 {
+        return Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "dynamic DNS update unauthorized -- check the credentials",
+        ));
+    }
+    if !status.is_success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("dynamic DNS update returned {status} '{}'", body.trim()),
+        ));
+    }
+    Ok(body)
+}
+
+/// Transaction signature key of RFC 2136 updates (e.g., "ddns-key." of BIND "tsig-keygen").
+/// ref. <https://www.rfc-editor.org/rfc/rfc8945>
+#[derive(Clone)]
+pub struct Tsig {
+    pub name: String,
+    /// "hmac-sha256" or "hmac-sha512".
+    pub algorithm: String,
+    pub secret: Vec<u8>,
+}
+
+impl Tsig {
+    /// Creates the key with the base64 secret (e.g., "secret" of the BIND key file).
+    pub fn new(name: &str, algorithm: &str, secret: &str) -> io::Result<Self> {
+        if !matches!(algorithm, "hmac-sha256" | "hmac-sha512") {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unsupported TSIG algorithm '{algorithm}' (expected hmac-sha256 or hmac-sha512)"),
+            ));
+        }
+        let secret = STANDARD.decode(secret.trim()).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid base64 TSIG secret {}", e),
+            )
+        })?;
+        Ok(Self {
+            name: name.to_lowercase(),
+            algorithm: algorithm.to_string(),
+            secret,
+        })
+    }
+
+    fn mac(&self, data: &[u8]) -> Vec<u8> {
+        let algorithm = if self.algorithm == "hmac-sha512" {
+            hmac::HMAC_SHA512
+        } else {
+            hmac::HMAC_SHA256
+        };
+        hmac::sign(&hmac::Key::new(algorithm, &self.secret), data)
+            .as_ref()
+            .to_vec()
+    }
+}
+
+/// Returns the update message that replaces the A (or AAAA) records of the name
+/// in the zone with the address.
+pub fn update_message(
+    id: u16,
+    zone: &str,
+    name: &str,
+    ip: IpAddr,
+    ttl: u32,
+) -> io::Result<Vec<u8>> {
+    let (rtype, rdata) = match ip {
+        IpAddr::V4(v) => (TYPE_A, v.octets().to_vec()),
+        IpAddr::V6(v) => (TYPE_AAAA, v.octets().to_vec()),
+    };
+    let mut msg = Vec::with_capacity(512);
+    msg.extend(id.to_be_bytes());
+    msg.extend(OPCODE_UPDATE.to_be_bytes());
+    // one zone, no prerequisites, two updates, no additional records
+    msg.extend([0, 1, 0, 0, 0, 2, 0, 0]);
+
+    dns::encode_name(&mut msg, zone)?;
+    msg.extend(TYPE_SOA.to_be_bytes());
+    msg.extend(CLASS_IN.to_be_bytes());
+
+    // deletes the RRset (class ANY, no TTL, no data)
+    dns::encode_name(&mut msg, name)?;
+    msg.extend(rtype.to_be_bytes());
+    msg.extend(CLASS_ANY.to_be_bytes());
+    msg.extend([0, 0, 0, 0, 0, 0]);
+
+    // then adds the record
+    dns::encode_name(&mut msg, name)?;
+    msg.extend(rtype.to_be_bytes());
+    msg.extend(CLASS_IN.to_be_bytes());
+    msg.extend(ttl.to_be_bytes());
+    msg.extend((rdata.len() as u16).to_be_bytes());
+    msg.extend(rdata);
+    Ok(msg)
+}
+
+/// Appends the TSIG record signed at "time_signed" (seconds since the epoch).
+/// ref. <https://www.rfc-editor.org/rfc/rfc8945#section-4.3.3>
+pub fn sign(msg: &[u8], key: &Tsig, time_signed: u64) -> io::Result<Vec<u8>> {
+    let mut key_name = Vec::new();
+    dns::encode_name(&mut key_name, &key.name)?;
+    let mut algorithm = Vec::new();
+    dns::encode_name(&mut algorithm, &key.algorithm)?;
+    let time = &time_signed.to_be_bytes()[2..];
+
+    let mut digest = msg.to_vec();
+    digest.extend(&key_name);
+    digest.extend(CLASS_ANY.to_be_bytes());
+    digest.extend([0, 0, 0, 0]);
+    digest.extend(&algorithm);
+    digest.extend(time);
+    digest.extend(TSIG_FUDGE.to_be_bytes());
+    // no error, no other data
+    digest.extend([0, 0, 0, 0]);
+    let mac = key.mac(&digest);
+
+    let mut rdata = algorithm;
+    rdata.extend(time);
+    rdata.extend(TSIG_FUDGE.to_be_bytes());
+    rdata.extend((mac.len() as u16).to_be_bytes());
+    rdata.extend(&mac);
+    // original ID, no error, no other data
+    rdata.extend(&msg[..2]);
+    rdata.extend([0, 0, 0, 0]);
+
+    let mut signed = msg.to_vec();
+    let arcount = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+    signed[10..12].copy_from_slice(&arcount.to_be_bytes());
+    signed.extend(key_name);
+    signed.extend(TYPE_TSIG.to_be_bytes());
+    signed.extend(CLASS_ANY.to_be_bytes());
+    signed.extend([0, 0, 0, 0]);
+    signed.extend((rdata.len() as u16).to_be_bytes());
+    signed.extend(rdata);
+    Ok(signed)
+}
+
+/// Replaces the address records of the name on the primary server of the zone
+/// with the DNS UPDATE of RFC 2136 (e.g., "nsupdate" to BIND, Knot, or PowerDNS),
+/// signed with the TSIG key if any.
+pub async fn rfc2136(
+    server: SocketAddr,
+    zone: &str,
+    name: &str,
+    ip: IpAddr,
+    ttl: u32,
+    key: Option<&Tsig>,
+) -> io::Result<()> {
+    let id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u16)
+        .unwrap_or_default();
+    let mut msg = update_message(id, zone, name, ip, ttl)?;
+    if let Some(key) = key {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        msg = sign(&msg, key, now)?;
+    }
+
+    let bind: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(&msg).await?;
+    let mut buf = [0u8; 1500];
+    let n = timeout(UPDATE_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::TimedOut,
+                format!("DNS update of {name} to {server} timed out"),
+            )
+        })??;
+    let resp = &buf[..n];
+    if n < 12 || resp[..2] != id.to_be_bytes() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "malformed DNS update response",
+        ));
+    }
+    let (kind, reason) = match resp[3] & 0x0f {
+        0 => return Ok(()),
+        2 => (ErrorKind::Other, "SERVFAIL"),
+        5 => (
+            ErrorKind::PermissionDenied,
+            "REFUSED -- check the update policy of the zone",
+        ),
+        9 => (
+            ErrorKind::PermissionDenied,
+            "NOTAUTH -- check the TSIG key and the clock",
+        ),
+        10 => (
+            ErrorKind::InvalidInput,
+            "NOTZONE -- the name is not in the zone",
+        ),
+        _ => (ErrorKind::Other, "unexpected response code"),
+    };
+    Err(Error::new(
+        kind,
+        format!(
+            "DNS update of {name} in {zone} to {server} failed with {} ({reason})",
+            resp[3] & 0x0f
+        ),
+    ))
+}
@@ -0,0 +1,267 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    time::{sleep, timeout, Duration, Instant},
+};
+
+use crate::{context::Context, summary};
+
+/// Interval between the resolution attempts.
+const VERIFY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Timeout of each query to a resolver.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+/// Waits until the name resolves to all the expected addresses on every resolver
+/// (e.g., after "--post-associate-cmd" upserted the records),
+/// so that the later boot steps that depend on the name do not race the update.
+/// The resolvers are comma-separated IPs or "IP:port" (port 53 by default),
+/// empty for the system resolver.
+pub async fn verify(
+    ctx: &Context,
+    name: &str,
+    expected: &[String],
+    resolvers: &str,
+    timeout_seconds: u64,
+) -> io::Result<()> {
+    let expected = expected
+        .iter()
+        .map(|v| {
+            v.parse::<IpAddr>().map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid address '{v}' ({})", e),
+                )
+            })
+        })
+        .collect::<io::Result<BTreeSet<IpAddr>>>()?;
+    let mut pending: Vec<String> = resolvers
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect();
+    if pending.is_empty() {
+        pending.push(String::new());
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(timeout_seconds);
+    let mut history = summary::History::new(&ctx.summary, "dns_verify");
+    loop {
+        let mut still_pending = Vec::new();
+        // of this round, for the attempt history
+        let (mut kind, mut failures) = (ErrorKind::Other, Vec::new());
+        for resolver in pending.iter() {
+            match resolve(name, resolver, &expected).await {
+                Ok(got) if expected.is_subset(&got) => {
+                    log::info!("{name} resolves to {expected:?} on {}", display(resolver));
+                }
+                Ok(got) => {
+                    log::info!(
+                        "{name} resolves to {got:?} on {}, waiting for {expected:?}",
+                        display(resolver)
+                    );
+                    failures.push(format!("{got:?} on {}", display(resolver)));
+                    still_pending.push(resolver.clone());
+                }
+                Err(e) => {
+                    log::warn!("failed to resolve {name} on {} '{}'", display(resolver), e);
+                    kind = e.kind();
+                    failures.push(format!("{} on {}", e, display(resolver)));
+                    still_pending.push(resolver.clone());
+                }
+            }
+        }
+        if still_pending.is_empty() {
+            return Ok(());
+        }
+        if Instant::now() + VERIFY_INTERVAL > deadline {
+            return Err(history.exhausted(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "{name} does not resolve to {expected:?} on {:?} after {timeout_seconds} seconds",
+                    still_pending.iter().map(|r| display(r)).collect::<Vec<_>>()
+                ),
+            )));
+        }
+        pending = still_pending;
+        history.retry(
+            &Error::new(kind, format!("{name} resolves to {}", failures.join(", "))),
+            VERIFY_INTERVAL,
+        );
+        sleep(VERIFY_INTERVAL).await;
+    }
+}
+
+/// Resolves the name on the resolver, querying the record types of the expected addresses.
+pub async fn resolve(
+    name: &str,
+    resolver: &str,
+    expected: &BTreeSet<IpAddr>,
+) -> io::Result<BTreeSet<IpAddr>> {
+    if resolver.is_empty() {
+        let addrs = timeout(QUERY_TIMEOUT, lookup_host((name, 0)))
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, format!("lookup {name} timed out")))??;
+        return Ok(addrs.map(|a| a.ip()).collect());
+    }
+
+    let server = parse_resolver(resolver)?;
+    let mut got = BTreeSet::new();
+    if expected.iter().any(|ip| ip.is_ipv4()) {
+        got.extend(query(name, server, TYPE_A).await?);
+    }
+    if expected.iter().any(|ip| ip.is_ipv6()) {
+        got.extend(query(name, server, TYPE_AAAA).await?);
+    }
+    Ok(got)
+}
+
+/// Parses "IP" or "IP:port" (e.g., "10.0.0.2", "[fd00:ec2::253]:53").
+pub fn parse_resolver(resolver: &str) -> io::Result<SocketAddr> {
+    if let Ok(addr) = resolver.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    resolver
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 53))
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid resolver '{resolver}' (expected IP or IP:port)"),
+            )
+        })
+}
+
+fn display(resolver: &str) -> &str {
+    if resolver.is_empty() {
+        "the system resolver"
+    } else {
+        resolver
+    }
+}
+
+/// Sends the recursive query over UDP, returning the addresses in the answer section.
+async fn query(name: &str, server: SocketAddr, qtype: u16) -> io::Result<Vec<IpAddr>> {
+    let bind: SocketAddr = if server.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+
+    let id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u16)
+        .unwrap_or_default()
+        ^ qtype;
+    socket.send(&encode_query(id, name, qtype)?).await?;
+    let mut buf = [0u8; 1500];
+    let n = timeout(QUERY_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::TimedOut,
+                format!("query {name} to {server} timed out"),
+            )
+        })??;
+    decode_answers(id, &buf[..n])
+}
+
+fn encode_query(id: u16, name: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let mut msg = Vec::with_capacity(512);
+    msg.extend(id.to_be_bytes());
+    // recursion desired
+    msg.extend(0x0100u16.to_be_bytes());
+    // one question, no records
+    msg.extend([0, 1, 0, 0, 0, 0, 0, 0]);
+    encode_name(&mut msg, name)?;
+    msg.extend(qtype.to_be_bytes());
+    // class IN
+    msg.extend(1u16.to_be_bytes());
+    Ok(msg)
+}
+
+/// Appends the name in the uncompressed wire format (e.g., "3www7example3com0").
+pub(crate) fn encode_name(msg: &mut Vec<u8>, name: &str) -> io::Result<()> {
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid DNS name '{name}'"),
+            ));
+        }
+        msg.push(label.len() as u8);
+        msg.extend(label.as_bytes());
+    }
+    msg.push(0);
+    Ok(())
+}
+
+fn decode_answers(id: u16, msg: &[u8]) -> io::Result<Vec<IpAddr>> {
+    let malformed = || Error::new(ErrorKind::InvalidData, "malformed DNS response");
+    let u16_at = |i: usize| -> io::Result<u16> {
+        msg.get(i..i + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(malformed)
+    };
+    if msg.len() < 12 || u16_at(0)? != id {
+        return Err(malformed());
+    }
+    match u16_at(2)? & 0x000f {
+        0 => {}
+        // NXDOMAIN, e.g., before the record is created
+        3 => return Ok(Vec::new()),
+        rcode => {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("DNS response code {rcode}"),
+            ))
+        }
+    }
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+
+    let mut i = 12;
+    for _ in 0..questions {
+        i = skip_name(msg, i).ok_or_else(malformed)? + 4;
+    }
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        i = skip_name(msg, i).ok_or_else(malformed)?;
+        let rtype = u16_at(i)?;
+        let rdlength = u16_at(i + 8)? as usize;
+        let rdata = msg.get(i + 10..i + 10 + rdlength).ok_or_else(malformed)?;
+        match (rtype, rdata.len()) {
+            (TYPE_A, 4) => addrs.push(IpAddr::from(<[u8; 4]>::try_from(rdata).unwrap())),
+            (TYPE_AAAA, 16) => addrs.push(IpAddr::from(<[u8; 16]>::try_from(rdata).unwrap())),
+            // e.g., CNAME in the chain
+            _ => {}
+        }
+        i += 10 + rdlength;
+    }
+    Ok(addrs)
+}
+
+/// Returns the offset after the (possibly compressed) name.
+fn skip_name(msg: &[u8], mut i: usize) -> Option<usize> {
+    loop {
+        let len = *msg.get(i)?;
+        if len == 0 {
+            return Some(i + 1);
+        }
+        if len & 0xc0 == 0xc0 {
+            return Some(i + 2);
+        }
+        i += 1 + len as usize;
+    }
+}
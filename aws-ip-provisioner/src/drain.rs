@@ -0,0 +1,173 @@
+use std::io::{self, Error, ErrorKind};
+
+use hyper::{Body, Method, Request};
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+use crate::{hook, tls};
+
+/// How often to count the connections while draining.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Drain phase before the EIP moves away from the instance (e.g., swap to
+/// the standby on interruption): signals the application to stop accepting
+/// and to close its connections, and waits for the connections to drain
+/// or the deadline, whichever comes first.
+#[derive(Debug, Clone)]
+pub struct Drain {
+    /// Command to signal the application (same variables as the hooks).
+    pub cmd: String,
+    /// URL to POST the drain signal to (e.g., "http://127.0.0.1:8080/drain").
+    pub url: String,
+    /// Local port of the connections to wait for (0 to only signal).
+    pub port: u16,
+    /// Deadline of the whole phase, after which the EIP moves anyway.
+    pub timeout: Duration,
+}
+
+impl Drain {
+    pub fn is_enabled(&self) -> bool {
+        !self.cmd.trim().is_empty() || !self.url.is_empty() || self.port > 0
+    }
+
+    /// Runs the drain phase, no-op if not enabled. A failed signal is logged
+    /// rather than returned, since the EIP must move before the instance is
+    /// gone (e.g., two minutes after the spot interruption notice).
+    pub async fn run(&self, vars: &[(&str, String)]) {
+        if !self.is_enabled() {
+            return;
+        }
+        let deadline = Instant::now() + self.timeout;
+        log::info!("draining for up to {:?}", self.timeout);
+
+        if !self.cmd.trim().is_empty() {
+            let hook = hook::Hook::new(&self.cmd, self.timeout.as_secs() as u32, "warn");
+            // "warn" policy, never an error
+            let _ = hook.run("drain", vars).await;
+        }
+        if !self.url.is_empty() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if let Err(e) = signal_url(&self.url, vars, left).await {
+                log::warn!("failed to signal drain to {} '{}'", self.url, e);
+            }
+        }
+        if self.port == 0 {
+            return;
+        }
+
+        loop {
+            let n = match established_connections(self.port) {
+                Ok(n) => n,
+                Err(e) => {
+                    log::warn!("failed to count connections '{}' -- not waiting", e);
+                    return;
+                }
+            };
+            if n == 0 {
+                log::info!("drained all connections on port {}", self.port);
+                return;
+            }
+            if Instant::now() >= deadline {
+                log::warn!(
+                    "{n} connections still open on port {} after {:?} -- moving the EIP anyway",
+                    self.port,
+                    self.timeout
+                );
+                return;
+            }
+            log::info!("waiting for {n} connections on port {} to drain", self.port);
+            sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// POSTs the variables as a JSON object (with "phase": "drain") to the URL,
+/// expecting a 2xx status.
+async fn signal_url(url: &str, vars: &[(&str, String)], left: Duration) -> io::Result<()> {
+    let mut body = serde_json::Map::new();
+    body.insert("phase".to_string(), "drain".into());
+    for (k, v) in vars.iter() {
+        body.insert(k.to_string(), v.clone().into());
+    }
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(&body)?))
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid drain URL {}", e)))?;
+    let https = tls::https_connector();
+    let resp = timeout(left, hyper::Client::builder().build(https).request(req))
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, "drain URL timed out"))?
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed POST {}", e)))?;
+    if !resp.status().is_success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("drain URL returned {}", resp.status()),
+        ));
+    }
+    log::info!("signaled drain to {url}");
+    Ok(())
+}
+
+/// Counts the established TCP connections (IPv4 and IPv6) on the local port.
+#[cfg(not(windows))]
+pub fn established_connections(port: u16) -> io::Result<usize> {
+    let mut n = count_established(&std::fs::read_to_string("/proc/net/tcp")?, port);
+    // absent if IPv6 is disabled
+    if let Ok(d) = std::fs::read_to_string("/proc/net/tcp6") {
+        n += count_established(&d, port);
+    }
+    Ok(n)
+}
+
+/// Counts the established TCP connections (IPv4 and IPv6) on the local port.
+#[cfg(windows)]
+pub fn established_connections(port: u16) -> io::Result<usize> {
+    let out = std::process::Command::new("netstat")
+        .args(["-an", "-p", "TCP"])
+        .output()?;
+    let mut n = count_netstat_established(&String::from_utf8_lossy(&out.stdout), port);
+    let out = std::process::Command::new("netstat")
+        .args(["-an", "-p", "TCPv6"])
+        .output()?;
+    n += count_netstat_established(&String::from_utf8_lossy(&out.stdout), port);
+    Ok(n)
+}
+
+/// Counts the "ESTABLISHED" rows of "netstat -an" on Windows whose local port
+/// is the port (e.g., "  TCP    10.0.0.5:443    203.0.113.7:50123    ESTABLISHED").
+pub fn count_netstat_established(netstat: &str, port: u16) -> usize {
+    netstat
+        .lines()
+        .filter(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 || fields[0] != "TCP" || fields[3] != "ESTABLISHED" {
+                return false;
+            }
+            fields[1]
+                .rsplit_once(':')
+                .and_then(|(_, p)| p.parse::<u16>().ok())
+                == Some(port)
+        })
+        .count()
+}
+
+/// Counts the ESTABLISHED ("01") rows of "/proc/net/tcp" (or "tcp6")
+/// whose local port is the port.
+/// ref. <https://www.kernel.org/doc/Documentation/networking/proc_net_tcp.txt>
+pub fn count_established(proc_net_tcp: &str, port: u16) -> usize {
+    proc_net_tcp
+        .lines()
+        .skip(1)
+        .filter(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 || fields[3] != "01" {
+                return false;
+            }
+            fields[1]
+                .rsplit_once(':')
+                .and_then(|(_, p)| u16::from_str_radix(p, 16).ok())
+                == Some(port)
+        })
+        .count()
+}
@@ -0,0 +1,31 @@
+use aws_sdk_ec2::Client;
+use aws_types::SdkConfig;
+use serde::{Deserialize, Serialize};
+
+/// EC2 client of the run. In place of "aws_manager::ec2", whose IMDS helpers
+/// pull in OpenSSL (through "http-manager"), so that all TLS is rustls
+/// (e.g., the static musl binary).
+#[derive(Debug, Clone)]
+pub struct Manager {
+    cli: Client,
+}
+
+impl Manager {
+    pub fn new(shared_config: &SdkConfig) -> Self {
+        Self {
+            cli: Client::new(shared_config),
+        }
+    }
+
+    pub fn client(&self) -> Client {
+        self.cli.clone()
+    }
+}
+
+/// Elastic IP of the instance, as recorded in the mounted EIP file.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct Eip {
+    pub allocation_id: String,
+    pub public_ip: String,
+}
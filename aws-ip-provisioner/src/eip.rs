@@ -0,0 +1,730 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    net::Ipv6Addr,
+};
+
+use aws_sdk_ec2::model::{
+    Address, AttachmentStatus, Filter, Instance, InstanceStateName, NetworkInterface,
+    NetworkInterfaceStatus, ResourceType, Tag, TagSpecification,
+};
+use serde_json::{json, Value};
+
+use crate::{context::Context, ec2, pool};
+
+/// Describes the EIPs with the server-side filters.
+/// DescribeAddresses has no pagination (no "NextToken"), and returns
+/// all matching addresses in one response, so callers must narrow down
+/// the results with filters rather than filtering on the client side.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeAddresses.html>
+pub async fn describe(
+    ec2_manager: &ec2::Manager,
+    filters: Vec<Filter>,
+) -> io::Result<Vec<Address>> {
+    let resp = ec2_manager
+        .client()
+        .describe_addresses()
+        .set_filters(Some(filters))
+        .send()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed describe_addresses {:?}", e),
+            )
+        })?;
+    Ok(resp.addresses().unwrap_or_default().to_vec())
+}
+
+/// Describes the EIPs with all the tags matching.
+pub async fn describe_by_tags(
+    ec2_manager: &ec2::Manager,
+    tags: &[(&str, &str)],
+) -> io::Result<Vec<Address>> {
+    let filters = tags
+        .iter()
+        .map(|(k, v)| {
+            Filter::builder()
+                .name(format!("tag:{k}"))
+                .values(*v)
+                .build()
+        })
+        .collect();
+    describe(ec2_manager, filters).await
+}
+
+/// Describes the EIP by its allocation ID.
+/// Returns "None" if the address does not exist.
+pub async fn describe_by_allocation_id(
+    ec2_manager: &ec2::Manager,
+    allocation_id: &str,
+) -> io::Result<Option<Address>> {
+    let ret = ec2_manager
+        .client()
+        .describe_addresses()
+        .allocation_ids(allocation_id)
+        .send()
+        .await;
+    match ret {
+        Ok(resp) => Ok(resp.addresses().unwrap_or_default().first().cloned()),
+        Err(e) => {
+            let msg = format!("{:?}", e);
+            if msg.contains("InvalidAllocationID.NotFound") {
+                return Ok(None);
+            }
+            Err(Error::new(
+                ErrorKind::Other,
+                format!("failed describe_addresses {msg}"),
+            ))
+        }
+    }
+}
+
+/// Returns the ("public IP", "Id" tag value) of the associated EIPs with the
+/// "Kind" tag (any value if empty), excluding the EIPs parked in the pool.
+pub async fn describe_fleet(
+    ec2_manager: &ec2::Manager,
+    kind_tag_key: &str,
+    kind_tag_value: &str,
+    id_tag_key: &str,
+) -> io::Result<Vec<(String, String)>> {
+    let filter = if kind_tag_value.is_empty() {
+        Filter::builder()
+            .name("tag-key")
+            .values(kind_tag_key)
+            .build()
+    } else {
+        Filter::builder()
+            .name(format!("tag:{kind_tag_key}"))
+            .values(kind_tag_value)
+            .build()
+    };
+    let addrs = describe(ec2_manager, vec![filter]).await?;
+    let tag = |addr: &Address, key: &str| {
+        addr.tags()
+            .unwrap_or_default()
+            .iter()
+            .find(|t| t.key() == Some(key))
+            .and_then(|t| t.value())
+            .unwrap_or_default()
+            .to_string()
+    };
+    Ok(addrs
+        .iter()
+        .filter(|a| a.association_id().is_some())
+        .filter(|a| tag(a, pool::STATUS_TAG_KEY) != pool::STATUS_AVAILABLE)
+        .filter_map(|a| a.public_ip().map(|ip| (ip.to_string(), tag(a, id_tag_key))))
+        .collect())
+}
+
+/// Returns the names of the regions enabled in the account (e.g., not the opt-in
+/// regions left disabled), sorted.
+pub async fn describe_regions(ec2_manager: &ec2::Manager) -> io::Result<Vec<String>> {
+    let resp = ec2_manager
+        .client()
+        .describe_regions()
+        .send()
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed describe_regions {:?}", e)))?;
+    let mut regions: Vec<String> = resp
+        .regions()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|r| r.region_name().map(|n| n.to_string()))
+        .collect();
+    regions.sort();
+    Ok(regions)
+}
+
+/// Describes all the instances with the filters, following "NextToken"
+/// so large accounts do not get truncated results.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeInstances.html>
+pub async fn describe_instances(
+    ec2_manager: &ec2::Manager,
+    filters: Vec<Filter>,
+) -> io::Result<Vec<Instance>> {
+    let mut instances = Vec::new();
+    let mut next_token: Option<String> = None;
+    loop {
+        let resp = ec2_manager
+            .client()
+            .describe_instances()
+            .set_filters(Some(filters.clone()))
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed describe_instances {:?}", e),
+                )
+            })?;
+        for reservation in resp.reservations().unwrap_or_default() {
+            instances.extend(reservation.instances().unwrap_or_default().to_vec());
+        }
+
+        next_token = resp.next_token().map(|v| v.to_string());
+        if next_token.is_none() {
+            break;
+        }
+        log::debug!(
+            "describe_instances has more pages ({} so far)",
+            instances.len()
+        );
+    }
+    Ok(instances)
+}
+
+/// Returns true if the instance is in "running" state.
+pub async fn is_instance_running(
+    ec2_manager: &ec2::Manager,
+    instance_id: &str,
+) -> io::Result<bool> {
+    let instances = describe_instances(
+        ec2_manager,
+        vec![Filter::builder()
+            .name("instance-id")
+            .values(instance_id)
+            .build()],
+    )
+    .await?;
+    for instance in instances.iter() {
+        if let Some(state) = instance.state() {
+            return Ok(state.name() == Some(&InstanceStateName::Running));
+        }
+    }
+    Ok(false)
+}
+
+/// Returns true if the instance is "running" and its primary network interface
+/// is "attached" and "in-use", so that the association does not fail with
+/// the transient errors (e.g., "InvalidInstanceID" right after the launch).
+/// Returns false if the instance is not visible yet (eventual consistency).
+pub async fn is_ready_to_associate(
+    ec2_manager: &ec2::Manager,
+    instance_id: &str,
+) -> io::Result<bool> {
+    let instances = describe_instances(
+        ec2_manager,
+        vec![Filter::builder()
+            .name("instance-id")
+            .values(instance_id)
+            .build()],
+    )
+    .await?;
+    let instance = match instances.first() {
+        Some(v) => v,
+        None => {
+            log::info!("{instance_id} not found yet");
+            return Ok(false);
+        }
+    };
+    let state = instance.state().and_then(|s| s.name());
+    if state != Some(&InstanceStateName::Running) {
+        log::info!("{instance_id} is {:?}, not running yet", state);
+        return Ok(false);
+    }
+    let eni = instance
+        .network_interfaces()
+        .unwrap_or_default()
+        .iter()
+        .find(|ni| ni.attachment().and_then(|a| a.device_index()) == Some(0));
+    let eni = match eni {
+        Some(v) => v,
+        None => {
+            log::info!("{instance_id} has no primary network interface yet");
+            return Ok(false);
+        }
+    };
+    let attachment = eni.attachment().and_then(|a| a.status());
+    if eni.status() != Some(&NetworkInterfaceStatus::InUse)
+        || attachment != Some(&AttachmentStatus::Attached)
+    {
+        log::info!(
+            "network interface {:?} of {instance_id} is {:?} (attachment {:?}), not ready yet",
+            eni.network_interface_id(),
+            eni.status(),
+            attachment
+        );
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Network interface and private IP of the instance to associate the EIP with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Target {
+    /// Empty for the primary network interface of the instance.
+    pub network_interface_id: String,
+    /// Empty for the primary private IP of the network interface.
+    pub private_ip_address: String,
+}
+
+impl Target {
+    /// Returns the target as logged and audited (e.g., "i-1/eni-2/10.0.0.12").
+    pub fn describe(&self, instance_id: &str) -> String {
+        let mut s = instance_id.to_string();
+        for v in [&self.network_interface_id, &self.private_ip_address] {
+            if !v.is_empty() {
+                s.push('/');
+                s.push_str(v);
+            }
+        }
+        s
+    }
+
+    /// Returns true if the EIP is associated with the target
+    /// (the caller checks the instance).
+    pub fn matches(&self, addr: &Address) -> bool {
+        (self.network_interface_id.is_empty()
+            || addr.network_interface_id() == Some(self.network_interface_id.as_str()))
+            && (self.private_ip_address.is_empty()
+                || addr.private_ip_address() == Some(self.private_ip_address.as_str()))
+    }
+}
+
+/// Describes the network interfaces attached to the instance.
+pub async fn describe_network_interfaces(
+    ec2_manager: &ec2::Manager,
+    instance_id: &str,
+) -> io::Result<Vec<NetworkInterface>> {
+    let resp = ec2_manager
+        .client()
+        .describe_network_interfaces()
+        .filters(
+            Filter::builder()
+                .name("attachment.instance-id")
+                .values(instance_id)
+                .build(),
+        )
+        .send()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed describe_network_interfaces {:?}", e),
+            )
+        })?;
+    Ok(resp.network_interfaces().unwrap_or_default().to_vec())
+}
+
+/// Associates the EIP with the target network interface (e.g., a secondary ENI of the instance),
+/// and its private IP if not empty.
+/// With "allow_reassociation", takes it over from another resource.
+pub async fn associate_network_interface(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    allocation_id: &str,
+    target: &Target,
+    allow_reassociation: bool,
+) -> io::Result<String> {
+    let network_interface_id = target.network_interface_id.as_str();
+    log::info!(
+        "associating elastic IP {allocation_id} with network interface {network_interface_id} (private IP {:?})",
+        target.private_ip_address
+    );
+    let before = ctx.audit.association(ec2_manager, allocation_id).await;
+    let mut req = ec2_manager
+        .client()
+        .associate_address()
+        .allocation_id(allocation_id)
+        .network_interface_id(network_interface_id)
+        .allow_reassociation(allow_reassociation);
+    if !target.private_ip_address.is_empty() {
+        req = req.private_ip_address(&target.private_ip_address);
+    }
+    let ret = req.send().await.map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed associate_address {:?}", e),
+        )
+    });
+    ctx.audit.record(
+        "associate",
+        &[
+            ("allocation_id", allocation_id),
+            ("before", &before),
+            ("after", network_interface_id),
+        ],
+        &ret,
+    )?;
+    Ok(ret?.association_id().unwrap_or_default().to_string())
+}
+
+/// Returns the global unicast IPv6 address of the network interface, if any.
+pub fn global_ipv6_address(ni: &NetworkInterface) -> Option<String> {
+    ni.ipv6_address()
+        .into_iter()
+        .chain(
+            ni.ipv6_addresses()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|a| a.ipv6_address()),
+        )
+        .filter_map(|v| v.parse::<Ipv6Addr>().ok())
+        // 2000::/3
+        .find(|ip| ip.segments()[0] & 0xe000 == 0x2000)
+        .map(|ip| ip.to_string())
+}
+
+/// Assigns an IPv6 address from the subnet's range to the network interface,
+/// returning the assigned address.
+pub async fn assign_ipv6_address(
+    ec2_manager: &ec2::Manager,
+    network_interface_id: &str,
+) -> io::Result<String> {
+    log::info!("assigning an IPv6 address to network interface {network_interface_id}");
+    let resp = ec2_manager
+        .client()
+        .assign_ipv6_addresses()
+        .network_interface_id(network_interface_id)
+        .ipv6_address_count(1)
+        .send()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed assign_ipv6_addresses {:?}", e),
+            )
+        })?;
+    resp.assigned_ipv6_addresses()
+        .unwrap_or_default()
+        .first()
+        .cloned()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::Other,
+                format!("no IPv6 address assigned to {network_interface_id}"),
+            )
+        })
+}
+
+/// Associates the EIP with the instance, allowing the re-association
+/// of an address that is already associated with another resource.
+pub async fn reassociate(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    allocation_id: &str,
+    instance_id: &str,
+) -> io::Result<String> {
+    log::info!("re-associating elastic IP {allocation_id} with EC2 instance {instance_id}");
+    let before = ctx.audit.association(ec2_manager, allocation_id).await;
+    let ret = ec2_manager
+        .client()
+        .associate_address()
+        .allocation_id(allocation_id)
+        .instance_id(instance_id)
+        .allow_reassociation(true)
+        .send()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed associate_address {:?}", e),
+            )
+        });
+    ctx.audit.record(
+        "associate",
+        &[
+            ("allocation_id", allocation_id),
+            ("before", &before),
+            ("after", instance_id),
+        ],
+        &ret,
+    )?;
+    Ok(ret?.association_id().unwrap_or_default().to_string())
+}
+
+/// Tag key of the idempotency token that the EIP was allocated with.
+/// AllocateAddress takes no "ClientToken", so the token is tagged atomically
+/// at allocation instead, and looked up before allocating again.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_AllocateAddress.html>
+pub const CLIENT_TOKEN_TAG_KEY: &str = "ClientToken";
+
+/// Returns the deterministic idempotency token of the instance and the "Id" tag value
+/// (FNV-1a of both, in hex), the same across the retries of the same allocation.
+pub fn client_token(instance_id: &str, id_tag_value: &str) -> String {
+    format!(
+        "{:016x}",
+        fnv1a(format!("{instance_id}/{id_tag_value}").as_bytes())
+    )
+}
+
+/// Returns the 64-bit FNV-1a hash, stable across the builds and the platforms
+/// (unlike "DefaultHasher").
+/// ref. <http://www.isthe.com/chongo/tech/comp/fnv/>
+pub fn fnv1a(d: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in d {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+/// Allocates a new EIP with the tags (e.g., "Name", "Id", "Kind", "ClientToken"),
+/// tagged in the same call so that no address exists without them.
+/// Allocates from the public IPv4 pool if not empty (e.g., BYOIP pool).
+pub async fn allocate(
+    ec2_manager: &ec2::Manager,
+    tags: &[(&str, &str)],
+    public_ipv4_pool: &str,
+) -> io::Result<ec2::Eip> {
+    log::info!("allocating elastic IP with tags {:?}", tags);
+    let mut spec = TagSpecification::builder().resource_type(ResourceType::ElasticIp);
+    for (k, v) in tags {
+        spec = spec.tags(Tag::builder().key(*k).value(*v).build());
+    }
+    let resp = ec2_manager
+        .client()
+        .allocate_address()
+        .tag_specifications(spec.build())
+        .set_public_ipv4_pool(if public_ipv4_pool.is_empty() {
+            None
+        } else {
+            Some(public_ipv4_pool.to_string())
+        })
+        .send()
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed allocate_address {:?}", e)))?;
+    Ok(ec2::Eip {
+        allocation_id: resp.allocation_id().unwrap_or_default().to_string(),
+        public_ip: resp.public_ip().unwrap_or_default().to_string(),
+    })
+}
+
+/// Checks that the EIP can be associated with the instance without associating it
+/// ("DryRun"), e.g., not to replace the current public IP of the instance.
+pub async fn associate_dry_run(
+    ec2_manager: &ec2::Manager,
+    allocation_id: &str,
+    instance_id: &str,
+) -> io::Result<()> {
+    let ret = ec2_manager
+        .client()
+        .associate_address()
+        .allocation_id(allocation_id)
+        .instance_id(instance_id)
+        .dry_run(true)
+        .send()
+        .await;
+    match ret {
+        // never expected, since dry runs fail either way
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let e = format!("{:?}", e);
+            if e.contains("DryRunOperation") {
+                Ok(())
+            } else {
+                Err(Error::new(
+                    ErrorKind::Other,
+                    format!("failed associate_address (dry run) {e}"),
+                ))
+            }
+        }
+    }
+}
+
+/// Disassociates the EIP by the association ID.
+pub async fn disassociate(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    allocation_id: &str,
+    association_id: &str,
+) -> io::Result<()> {
+    log::info!("disassociating elastic IP {allocation_id} (association ID {association_id})");
+    let before = ctx.audit.association(ec2_manager, allocation_id).await;
+    let ret = ec2_manager
+        .client()
+        .disassociate_address()
+        .association_id(association_id)
+        .send()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed disassociate_address {:?}", e),
+            )
+        });
+    ctx.audit.record(
+        "disassociate",
+        &[
+            ("allocation_id", allocation_id),
+            ("before", &before),
+            ("after", ""),
+        ],
+        &ret,
+    )?;
+    ret?;
+    Ok(())
+}
+
+/// Releases the EIP back to AWS, which cannot be undone.
+/// Only for the addresses allocated for the tool itself (e.g., "self-test")
+/// and the excess of the pool, as the managed ones are returned to the pool instead.
+pub async fn release_address(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    allocation_id: &str,
+) -> io::Result<()> {
+    log::info!("releasing elastic IP {allocation_id}");
+    let ret = ec2_manager
+        .client()
+        .release_address()
+        .allocation_id(allocation_id)
+        .send()
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed release_address {:?}", e)));
+    ctx.audit.record(
+        "release_address",
+        &[("allocation_id", allocation_id), ("after", "")],
+        &ret,
+    )?;
+    ret?;
+    Ok(())
+}
+
+/// Creates (or overwrites) the tags on the EIP.
+pub async fn create_tags(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    allocation_id: &str,
+    tags: Vec<(String, String)>,
+) -> io::Result<()> {
+    let after = tags
+        .iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut req = ec2_manager.client().create_tags().resources(allocation_id);
+    for (k, v) in tags {
+        req = req.tags(Tag::builder().key(k).value(v).build());
+    }
+    let ret = req.send().await.map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed create_tags for {allocation_id} {:?}", e),
+        )
+    });
+    ctx.audit.record(
+        "retag",
+        &[("allocation_id", allocation_id), ("after", &after)],
+        &ret,
+    )?;
+    ret?;
+    Ok(())
+}
+
+/// Deletes the tags of the keys from the EIP, whatever their values.
+pub async fn delete_tags(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    allocation_id: &str,
+    keys: Vec<String>,
+) -> io::Result<()> {
+    let before = keys.join(",");
+    let mut req = ec2_manager.client().delete_tags().resources(allocation_id);
+    for k in keys {
+        req = req.tags(Tag::builder().key(k).build());
+    }
+    let ret = req.send().await.map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed delete_tags for {allocation_id} {:?}", e),
+        )
+    });
+    ctx.audit.record(
+        "untag",
+        &[("allocation_id", allocation_id), ("before", &before)],
+        &ret,
+    )?;
+    ret?;
+    Ok(())
+}
+
+/// Returns the JSON Schema of the mounted EIP file (e.g., "/data/eip.yaml"),
+/// as written by "state::State::save".
+pub fn state_schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "aws-ip-provisioner state file",
+        "description": format!("Elastic IP mapped to the volume (aws-ip-provisioner {})", env!("CARGO_PKG_VERSION")),
+        "type": "object",
+        "properties": {
+            "allocation_id": {
+                "description": "Allocation ID of the EIP",
+                "type": "string",
+                "pattern": "^eipalloc-[0-9a-f]+$",
+            },
+            "public_ip": {
+                "description": "Public IPv4 address of the EIP",
+                "type": "string",
+                "format": "ipv4",
+            },
+            "private_ip_address": {
+                "description": "Private IPv4 address of the network interface that the EIP maps to (\"--private-ip-address\")",
+                "type": "string",
+                "format": "ipv4",
+            },
+            "private_ip_instance_id": {
+                "description": "Instance that the private IP was recorded on, ignored on the others",
+                "type": "string",
+            },
+            "ipv6_address": {
+                "description": "IPv6 address on the network interface of the EIP (\"--dual-stack\")",
+                "type": "string",
+                "format": "ipv6",
+            },
+            "account_id": {
+                "description": "Account of the EIP (\"--on-foreign-state\")",
+                "type": "string",
+            },
+            "region": {
+                "description": "Region of the EIP (\"--on-foreign-state\")",
+                "type": "string",
+            },
+            "association_id": {
+                "description": "Association ID of the EIP, as described after the association",
+                "type": "string",
+            },
+            "instance_id": {
+                "description": "Instance that the EIP is associated with",
+                "type": "string",
+            },
+            "network_interface_id": {
+                "description": "Network interface that the EIP is associated with",
+                "type": "string",
+            },
+            "association_private_ip_address": {
+                "description": "Private IPv4 address that the EIP is associated with",
+                "type": "string",
+                "format": "ipv4",
+            },
+            "network_border_group": {
+                "description": "Network border group of the EIP (e.g., \"us-west-2\")",
+                "type": "string",
+            },
+            "tags": {
+                "description": "Tags of the EIP",
+                "type": "object",
+                "additionalProperties": { "type": "string" },
+            },
+            "allocated_at": {
+                "description": "Unix timestamp in seconds when the EIP was allocated",
+                "type": "integer",
+            },
+            "associated_at": {
+                "description": "Unix timestamp in seconds when the provisioner first saw the association",
+                "type": "integer",
+            },
+            "updated_at": {
+                "description": "Unix timestamp in seconds when the provisioner last described the EIP",
+                "type": "integer",
+            },
+            "tool_version": {
+                "description": "Version of aws-ip-provisioner that last wrote the file",
+                "type": "string",
+            },
+        },
+        "required": ["allocation_id", "public_ip"],
+    })
+}
@@ -0,0 +1,211 @@
+use std::{
+    fs,
+    io::{self, Error, ErrorKind, Write},
+    process::{Command, Stdio},
+};
+
+use crate::hook;
+
+/// Comment that tags the iptables rules installed by this tool,
+/// so that they can be removed without re-rendering the templates.
+pub const IPTABLES_COMMENT: &str = "ip-manager";
+
+/// Returns the comment of the rules of the namespace ("--namespace"),
+/// so that one instance does not remove the rules of another.
+pub fn iptables_comment(namespace: &str) -> String {
+    if namespace.is_empty() {
+        IPTABLES_COMMENT.to_string()
+    } else {
+        format!("{IPTABLES_COMMENT}-{namespace}")
+    }
+}
+
+/// Installs the rules templated from the file with the variables (e.g., "{public_ip}").
+/// Idempotent, so it may run on every association.
+///
+/// With "nftables", the file is an nft ruleset whose tables are owned by this tool
+/// and atomically replaced (e.g., "table ip ip_manager { ... }").
+/// With "iptables", each line is "<table> <chain> <rule spec>"
+/// (e.g., "nat PREROUTING -d {private_ip} -p tcp --dport 443 -j DNAT --to-destination 10.0.1.5").
+/// With "iptables", the rules are tagged with the comment of the namespace.
+pub fn install(
+    backend: &str,
+    rules_file: &str,
+    vars: &[(&str, String)],
+    namespace: &str,
+) -> io::Result<()> {
+    if backend == "none" {
+        return Ok(());
+    }
+    let comment = iptables_comment(namespace);
+    let rendered = hook::render(&read_rules(rules_file)?, vars);
+    match backend {
+        "nftables" => {
+            // declaring and deleting first replaces the tables in one transaction
+            let mut d = String::new();
+            for table in nft_tables(&rendered)? {
+                d.push_str(&format!("table {table}\ndelete table {table}\n"));
+            }
+            d.push_str(&rendered);
+            log::info!("installing nftables rules from {rules_file}");
+            run("nft", &["-f", "-"], Some(&d))?;
+        }
+        "iptables" => {
+            for line in rule_lines(&rendered) {
+                let (table, chain, spec) = iptables_rule(line)?;
+                let mut args = vec![
+                    "-t",
+                    table,
+                    "-C",
+                    chain,
+                    "-m",
+                    "comment",
+                    "--comment",
+                    &comment,
+                ];
+                args.extend(spec.iter());
+                if run("iptables", &args, None).is_ok() {
+                    log::info!("iptables rule '{line}' already exists");
+                    continue;
+                }
+                args[2] = "-A";
+                log::info!("appending iptables rule '{line}'");
+                run("iptables", &args, None)?;
+            }
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown firewall backend '{backend}'"),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Removes the rules installed by "install", on release.
+pub fn remove(backend: &str, rules_file: &str, namespace: &str) -> io::Result<()> {
+    if backend == "none" {
+        return Ok(());
+    }
+    let comment = iptables_comment(namespace);
+    // variables only appear in the rules, not in the table names
+    let rules = read_rules(rules_file)?;
+    match backend {
+        "nftables" => {
+            for table in nft_tables(&rules)? {
+                log::info!("deleting nftables table {table}");
+                if let Err(e) = run("nft", &["delete", "table", &table], None) {
+                    log::warn!("failed to delete nftables table {table} '{}'", e);
+                }
+            }
+        }
+        "iptables" => {
+            let mut tables: Vec<&str> = Vec::new();
+            for line in rule_lines(&rules) {
+                let (table, _, _) = iptables_rule(line)?;
+                if !tables.contains(&table) {
+                    tables.push(table);
+                }
+            }
+            for table in tables {
+                let listed = run("iptables", &["-t", table, "-S"], None)?;
+                // exact match, not to remove the rules of the other namespaces
+                for rule in listed.lines().filter(|l| {
+                    let fields: Vec<&str> = l.split_whitespace().collect();
+                    l.starts_with("-A ")
+                        && fields
+                            .windows(2)
+                            .any(|w| w[0] == "--comment" && w[1] == comment)
+                }) {
+                    let mut args = vec!["-t", table, "-D"];
+                    args.extend(rule.split_whitespace().skip(1));
+                    log::info!("deleting iptables rule '{rule}' in {table}");
+                    run("iptables", &args, None)?;
+                }
+            }
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown firewall backend '{backend}'"),
+            ))
+        }
+    }
+    Ok(())
+}
+
+fn read_rules(rules_file: &str) -> io::Result<String> {
+    fs::read_to_string(rules_file).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("failed to read firewall rules file {rules_file} '{}'", e),
+        )
+    })
+}
+
+/// Returns the non-empty, non-comment lines.
+fn rule_lines(rules: &str) -> impl Iterator<Item = &str> {
+    rules
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+}
+
+/// Returns the "<family> <name>" of the tables declared in the nft ruleset.
+fn nft_tables(rules: &str) -> io::Result<Vec<String>> {
+    let mut tables = Vec::new();
+    for line in rule_lines(rules) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() >= 3 && fields[0] == "table" {
+            tables.push(format!("{} {}", fields[1], fields[2].trim_end_matches('{')));
+        }
+    }
+    if tables.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "nftables rules file declares no table (e.g., \"table ip ip_manager { ... }\")",
+        ));
+    }
+    Ok(tables)
+}
+
+fn iptables_rule(line: &str) -> io::Result<(&str, &str, Vec<&str>)> {
+    let mut fields = line.split_whitespace();
+    match (fields.next(), fields.next()) {
+        (Some(table), Some(chain)) => Ok((table, chain, fields.collect())),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid iptables rule '{line}' (expected \"<table> <chain> <rule spec>\")"),
+        )),
+    }
+}
+
+/// Runs the command with the optional stdin, returning its stdout.
+fn run(program: &str, args: &[&str], stdin: Option<&str>) -> io::Result<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let (Some(d), Some(mut w)) = (stdin, child.stdin.take()) {
+        w.write_all(d.as_bytes())?;
+    }
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed '{program} {}' {}",
+                args.join(" "),
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
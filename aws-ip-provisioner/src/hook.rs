@@ -0,0 +1,170 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    process::Stdio,
+};
+
+use tokio::{
+    process::Command,
+    time::{timeout, Duration},
+};
+
+use crate::{ec2, progress};
+
+/// Prefix of the environment variables exposed to the hook commands
+/// (e.g., "IP_MANAGER_PUBLIC_IP").
+pub const ENV_PREFIX: &str = "IP_MANAGER_";
+
+/// User command to run after a phase (e.g., "associate", "release").
+/// Each argument may reference the variables as "{name}" (e.g., "{public_ip}"),
+/// which are also exposed as "IP_MANAGER_<NAME>" environment variables.
+#[derive(Debug, Clone)]
+pub struct Hook {
+    pub cmd: String,
+    pub timeout: Duration,
+    /// Returns the error if the command fails, rather than logging it.
+    pub fail_on_error: bool,
+}
+
+impl Hook {
+    /// Creates a hook with the failure policy ("warn" or "fail").
+    pub fn new(cmd: &str, timeout_seconds: u32, failure_policy: &str) -> Self {
+        Self {
+            cmd: cmd.to_string(),
+            timeout: Duration::from_secs(timeout_seconds as u64),
+            fail_on_error: failure_policy == "fail",
+        }
+    }
+
+    /// Runs the command with the variables, no-op if the command is empty.
+    pub async fn run(&self, phase: &str, vars: &[(&str, String)]) -> io::Result<()> {
+        if self.cmd.trim().is_empty() {
+            return Ok(());
+        }
+        match self.exec(phase, vars).await {
+            Ok(_) => Ok(()),
+            Err(e) if self.fail_on_error => Err(e),
+            Err(e) => {
+                log::warn!("{} command failed '{}' -- ignoring", label(phase), e);
+                Ok(())
+            }
+        }
+    }
+
+    async fn exec(&self, phase: &str, vars: &[(&str, String)]) -> io::Result<()> {
+        let label = label(phase);
+        let mut all = vec![("phase", phase.to_string())];
+        all.extend(vars.iter().map(|(k, v)| (*k, v.clone())));
+
+        let argv: Vec<String> = split(&self.cmd)?
+            .iter()
+            .map(|arg| render(arg, &all))
+            .collect();
+        log::info!("running {label} command {:?}", argv);
+
+        let mut cmd = Command::new(&argv[0]);
+        cmd.args(&argv[1..]).stdin(Stdio::null()).kill_on_drop(true);
+        for (k, v) in all.iter() {
+            cmd.env(format!("{ENV_PREFIX}{}", k.to_uppercase()), v);
+        }
+        let out = timeout(self.timeout, cmd.output()).await.map_err(|_| {
+            Error::new(
+                ErrorKind::TimedOut,
+                format!("{label} command timed out after {:?}", self.timeout),
+            )
+        })??;
+        if !out.status.success() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "{label} command exited with {} {}",
+                    out.status,
+                    String::from_utf8_lossy(&out.stderr).trim()
+                ),
+            ));
+        }
+        log::info!(
+            "{label} command succeeded {}",
+            String::from_utf8_lossy(&out.stdout).trim()
+        );
+        Ok(())
+    }
+}
+
+/// Returns the name of the hook of the phase in the logs (e.g., "post-associate").
+fn label(phase: &str) -> String {
+    match phase {
+        // runs before the EIP moves, not after
+        "drain" => phase.to_string(),
+        _ if progress::EVENTS.contains(&phase) => format!("on-phase {phase}"),
+        _ => format!("post-{phase}"),
+    }
+}
+
+/// Returns the hook variables of the EIP and the instance.
+pub fn eip_vars(eip: &ec2::Eip, instance_id: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("public_ip", eip.public_ip.clone()),
+        ("allocation_id", eip.allocation_id.clone()),
+        ("instance_id", instance_id.to_string()),
+    ]
+}
+
+/// Returns the variables of the hook on an IPv6-only instance
+/// (e.g., "{ipv6}" for the DNS AAAA record), without the EIP ones.
+pub fn ipv6_vars(ipv6: &str, instance_id: &str) -> Vec<(&'static str, String)> {
+    vec![
+        ("ipv6", ipv6.to_string()),
+        ("instance_id", instance_id.to_string()),
+    ]
+}
+
+/// Replaces "{name}" in the argument with the variable values.
+pub fn render(arg: &str, vars: &[(&str, String)]) -> String {
+    let mut s = arg.to_string();
+    for (k, v) in vars.iter() {
+        s = s.replace(&format!("{{{k}}}"), v);
+    }
+    s
+}
+
+/// Splits the command into arguments by whitespace,
+/// keeping single- or double-quoted strings as one argument (no shell expansion).
+fn split(cmd: &str) -> io::Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut cur = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    for c in cmd.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => cur.push(c),
+            None if c == '\'' || c == '"' => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            None if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut cur));
+                    in_arg = false;
+                }
+            }
+            None => {
+                cur.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unterminated quote in command '{cmd}'"),
+        ));
+    }
+    if in_arg {
+        args.push(cur);
+    }
+    if args.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "empty command"));
+    }
+    Ok(args)
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Error, ErrorKind},
+};
+
+use aws_sdk_ec2::model::{AddressAttributeName, Filter};
+
+use crate::{ec2, eip};
+
+/// Markers of the block in the hosts file managed by this tool.
+const BEGIN_MARKER: &str = "# BEGIN ip-manager";
+const END_MARKER: &str = "# END ip-manager";
+
+/// Returns the DNS name bound to the EIP: the reverse DNS (PTR) record if set,
+/// otherwise the public DNS name of the instance (e.g., "ec2-203-0-113-1.compute-1.amazonaws.com").
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/Using_Elastic_Addressing_Reverse_DNS.html>
+pub async fn dns_name(
+    ec2_manager: &ec2::Manager,
+    eip: &ec2::Eip,
+    ec2_instance_id: &str,
+) -> io::Result<String> {
+    if let Some(ptr) = ptr_records(ec2_manager, std::slice::from_ref(&eip.allocation_id))
+        .await?
+        .remove(&eip.allocation_id)
+    {
+        return Ok(ptr);
+    }
+
+    let instances = eip::describe_instances(
+        ec2_manager,
+        vec![Filter::builder()
+            .name("instance-id")
+            .values(ec2_instance_id)
+            .build()],
+    )
+    .await?;
+    instances
+        .iter()
+        .filter_map(|i| i.public_dns_name())
+        .find(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "EIP {} has no PTR record, and {ec2_instance_id} has no public DNS name (VPC DNS hostnames disabled?)",
+                    eip.public_ip
+                ),
+            )
+        })
+}
+
+/// Returns the reverse DNS (PTR) records of the EIPs that have one, keyed by the allocation ID.
+pub async fn ptr_records(
+    ec2_manager: &ec2::Manager,
+    allocation_ids: &[String],
+) -> io::Result<HashMap<String, String>> {
+    if allocation_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let resp = ec2_manager
+        .client()
+        .describe_addresses_attribute()
+        .set_allocation_ids(Some(allocation_ids.to_vec()))
+        .attribute(AddressAttributeName::DomainName)
+        .send()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed describe_addresses_attribute {:?}", e),
+            )
+        })?;
+    Ok(resp
+        .addresses()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|a| match (a.allocation_id(), a.ptr_record()) {
+            (Some(id), Some(ptr)) if !ptr.is_empty() => {
+                Some((id.to_string(), ptr.trim_end_matches('.').to_string()))
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+/// Sets the system hostname, both for the running kernel and across reboots.
+#[cfg(not(windows))]
+pub fn set_hostname(name: &str) -> io::Result<()> {
+    let current = fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+    if current.trim() == name {
+        log::info!("hostname is already {name}");
+        return Ok(());
+    }
+    log::info!("setting hostname to {name} (was {})", current.trim());
+    fs::write("/proc/sys/kernel/hostname", name)?;
+    fs::write("/etc/hostname", format!("{name}\n"))
+}
+
+/// Sets the computer name, effective after the next restart
+/// (e.g., before the EC2Launch "reboot" task of the first boot).
+#[cfg(windows)]
+pub fn set_hostname(name: &str) -> io::Result<()> {
+    // NetBIOS names are at most 15 characters, so only the first label
+    let short = name.split('.').next().unwrap_or(name);
+    let current = std::env::var("COMPUTERNAME").unwrap_or_default();
+    if current.eq_ignore_ascii_case(short) {
+        log::info!("computer name is already {short}");
+        return Ok(());
+    }
+    log::info!("renaming computer to {short} (was {current}), effective after restart");
+    crate::platform::powershell(&format!(
+        "Rename-Computer -NewName {} -Force",
+        crate::platform::powershell_quote(short)
+    ))
+    .map(|_| ())
+}
+
+/// Maps the DNS name (and its first label) to the addresses (e.g., IPv4 and IPv6
+/// of "--dual-stack") in the hosts file, replacing the block written by the previous run.
+/// Each namespace ("--namespace") owns its own block.
+pub fn update_etc_hosts(
+    file_path: &str,
+    ips: &[&str],
+    name: &str,
+    namespace: &str,
+) -> io::Result<()> {
+    let (begin, end) = if namespace.is_empty() {
+        (BEGIN_MARKER.to_string(), END_MARKER.to_string())
+    } else {
+        (
+            format!("{BEGIN_MARKER} {namespace}"),
+            format!("{END_MARKER} {namespace}"),
+        )
+    };
+    let existing = fs::read_to_string(file_path)?;
+    let mut lines = Vec::new();
+    let mut in_block = false;
+    for line in existing.lines() {
+        let line_trimmed = line.trim();
+        if line_trimmed == begin {
+            in_block = true;
+        } else if line_trimmed == end {
+            in_block = false;
+        } else if !in_block {
+            lines.push(line.to_string());
+        }
+    }
+
+    let short = name.split('.').next().unwrap_or(name);
+    lines.push(begin);
+    for ip in ips.iter() {
+        if short != name {
+            lines.push(format!("{ip} {name} {short}"));
+        } else {
+            lines.push(format!("{ip} {name}"));
+        }
+    }
+    lines.push(end);
+    let d = lines.join("\n") + "\n";
+    if d == existing {
+        log::info!("{file_path} already maps {name} to {ips:?}");
+        return Ok(());
+    }
+
+    log::info!("mapping {name} to {ips:?} in {file_path}");
+    // write in place rather than renaming, since the file may be bind-mounted (e.g., containers)
+    fs::write(file_path, d)
+}
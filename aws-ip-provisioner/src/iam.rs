@@ -0,0 +1,82 @@
+use crate::command::Flags;
+
+/// Returns the IAM actions that the flags require of the local instance role, sorted.
+/// Keep in sync with the API calls of each feature.
+/// "sts:AssumeRole" of "--allocation-role-arn" is not included,
+/// as it should be scoped to the role rather than "*".
+pub fn required_actions(opts: &Flags) -> Vec<&'static str> {
+    let mut actions = vec![
+        "ec2:AllocateAddress",
+        "ec2:AssociateAddress",
+        "ec2:DescribeAddresses",
+        "ec2:DescribeInstances",
+        // tags on allocation, pool claims, and "AllocatedAt"
+        "ec2:CreateTags",
+    ];
+    if opts.mode != "provision" {
+        // returns the EIP to the pool on interruption or scale-in
+        actions.push("ec2:DisassociateAddress");
+    }
+    if opts.pool_min_free > 0 || opts.pool_max_free > 0 {
+        // releases the excess of the pool
+        actions.push("ec2:ReleaseAddress");
+    }
+    if opts.remove_unknown_tags {
+        actions.push("ec2:DeleteTags");
+    }
+    if !opts.sync_security_group_id.is_empty() {
+        actions.extend([
+            "ec2:DescribeSecurityGroupRules",
+            "ec2:AuthorizeSecurityGroupIngress",
+            "ec2:RevokeSecurityGroupIngress",
+        ]);
+    }
+    if opts.mode == "terminate-hook" {
+        actions.extend(["ec2:DescribeTags", "autoscaling:CompleteLifecycleAction"]);
+    }
+    if opts.wait_strategy == "ordinal-from-asg" {
+        actions.push("ec2:DescribeTags");
+    }
+    if !opts.target_eni_tag.is_empty()
+        || opts.target_device_index != 0
+        || !opts.private_ip_address.is_empty()
+    {
+        actions.push("ec2:DescribeNetworkInterfaces");
+    }
+    if opts.ipv6_only == "assign-ipv6" || opts.dual_stack {
+        actions.extend(["ec2:DescribeNetworkInterfaces", "ec2:AssignIpv6Addresses"]);
+    }
+    if opts.set_hostname_from_dns || opts.update_etc_hosts {
+        actions.push("ec2:DescribeAddressesAttribute");
+    }
+    if opts.fix_imds_hop_limit {
+        actions.push("ec2:ModifyInstanceMetadataOptions");
+    }
+    if !opts.route53_zone_id.is_empty() || !opts.route53_private_zone_id.is_empty() {
+        actions.push("route53:ChangeResourceRecordSets");
+    }
+    if !opts.route53_health_check.is_empty() {
+        actions.push("route53:CreateHealthCheck");
+    }
+    if !opts.audit_log_file.is_empty() {
+        actions.push("sts:GetCallerIdentity");
+    }
+    if !opts.allocation_role_arn.is_empty() {
+        actions.extend(["sts:GetCallerIdentity", "ec2:AcceptAddressTransfer"]);
+    }
+    actions.sort();
+    actions.dedup();
+    actions
+}
+
+/// Returns the IAM actions that "--allocation-role-arn" requires
+/// of the role in the account that owns the EIPs, sorted.
+pub fn allocation_role_actions() -> Vec<&'static str> {
+    vec![
+        "ec2:AllocateAddress",
+        "ec2:CreateTags",
+        "ec2:DescribeAddresses",
+        "ec2:EnableAddressTransfer",
+        "sts:GetCallerIdentity",
+    ]
+}
@@ -0,0 +1,409 @@
+use std::io::{self, Error, ErrorKind};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{digest, signature};
+use serde::{Deserialize, Serialize};
+
+use crate::imds::Imds;
+
+/// OID 1.2.840.113549.1.7.2
+const OID_SIGNED_DATA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02];
+/// OID 1.2.840.113549.1.9.4
+const OID_MESSAGE_DIGEST: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x04];
+/// OID 2.16.840.1.101.3.4.2.1
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+/// OID 2.16.840.1.101.3.4.2.2
+const OID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+/// OID 2.16.840.1.101.3.4.2.3
+const OID_SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+
+/// Maximum nesting of the indefinite-length TLVs, far deeper than PKCS7 needs,
+/// so that the crafted input cannot overflow the stack.
+const MAX_BER_DEPTH: usize = 32;
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_BIT_STRING: u8 = 0x03;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_CONTEXT_0: u8 = 0xa0;
+
+/// Fields of the instance identity document.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/instance-identity-documents.html>
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Document {
+    pub account_id: String,
+    pub instance_id: String,
+    pub region: String,
+    #[serde(default)]
+    pub availability_zone: String,
+    #[serde(default)]
+    pub private_ip: String,
+    #[serde(default)]
+    pub image_id: String,
+    #[serde(default)]
+    pub instance_type: String,
+    #[serde(default)]
+    pub pending_time: String,
+}
+
+/// Instance identity document as served by IMDS, with its PKCS7 signature.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Signed {
+    /// Raw document, exactly as signed (re-serializing breaks the signature).
+    pub document: String,
+    /// Base64 PKCS7 "SignedData" of the document ("rsa2048").
+    pub pkcs7: String,
+}
+
+/// Fetches the document and its RSA-2048 PKCS7 signature of the local instance.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/verify-rsa2048.html>
+pub async fn fetch(imds: &Imds) -> io::Result<Signed> {
+    Ok(Signed {
+        document: imds.fetch_dynamic("instance-identity/document").await?,
+        pkcs7: imds.fetch_dynamic("instance-identity/rsa2048").await?,
+    })
+}
+
+/// Parses the document without verifying it (e.g., the local IMDS is trusted).
+pub fn parse(document: &str) -> io::Result<Document> {
+    serde_json::from_str(document).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid instance identity document {}", e),
+        )
+    })
+}
+
+/// Verifies that the PKCS7 signature embeds the document and is signed by the
+/// certificate (the AWS RSA-2048 public certificate of the region, PEM),
+/// and returns the parsed document.
+/// Returns "ErrorKind::PermissionDenied" if the signature does not verify.
+pub fn verify(signed: &Signed, certificate_pem: &str) -> io::Result<Document> {
+    let der = decode_pkcs7(&signed.pkcs7)?;
+    let (content, signer) = parse_signed_data(&der)?;
+    if content != signed.document.as_bytes() {
+        return Err(denied("PKCS7 content is not the document"));
+    }
+
+    let public_key = rsa_public_key(&certificate_der(certificate_pem)?)?;
+    // SHA-256 or stronger only, never downgraded to SHA-1
+    let (digest_alg, verify_alg): (&digest::Algorithm, &dyn signature::VerificationAlgorithm) =
+        if signer.digest_oid == OID_SHA256 {
+            (&digest::SHA256, &signature::RSA_PKCS1_2048_8192_SHA256)
+        } else if signer.digest_oid == OID_SHA384 {
+            (&digest::SHA384, &signature::RSA_PKCS1_2048_8192_SHA384)
+        } else if signer.digest_oid == OID_SHA512 {
+            (&digest::SHA512, &signature::RSA_PKCS1_2048_8192_SHA512)
+        } else {
+            return Err(invalid(
+                "unsupported PKCS7 digest algorithm (SHA-256 or stronger required)",
+            ));
+        };
+
+    // with the signed attributes, the signature covers the attributes,
+    // which carry the digest of the content
+    let signed_bytes = match &signer.signed_attrs {
+        Some(attrs) => {
+            let expected = message_digest(attrs)?;
+            if digest::digest(digest_alg, &content).as_ref() != expected.as_slice() {
+                return Err(denied("PKCS7 message digest does not match the document"));
+            }
+            // signed as the explicit "SET OF", not the implicit "[0]"
+            let mut v = attrs.clone();
+            v[0] = TAG_SET;
+            v
+        }
+        None => content.clone(),
+    };
+    signature::UnparsedPublicKey::new(verify_alg, &public_key)
+        .verify(&signed_bytes, &signer.signature)
+        .map_err(|_| denied("PKCS7 signature does not verify with the certificate"))?;
+
+    parse(&signed.document)
+}
+
+fn denied(msg: &str) -> Error {
+    Error::new(ErrorKind::PermissionDenied, msg)
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Decodes the base64 PKCS7, with or without the PEM armor.
+fn decode_pkcs7(pkcs7: &str) -> io::Result<Vec<u8>> {
+    let b64: String = pkcs7
+        .lines()
+        .filter(|l| !l.starts_with("-----"))
+        .flat_map(|l| l.chars().filter(|c| !c.is_whitespace()))
+        .collect();
+    STANDARD
+        .decode(b64)
+        .map_err(|e| invalid(&format!("invalid base64 PKCS7 {}", e)))
+}
+
+fn certificate_der(certificate_pem: &str) -> io::Result<Vec<u8>> {
+    let mut rd = certificate_pem.as_bytes();
+    rustls_pemfile::certs(&mut rd)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid("no certificate in the PEM"))
+}
+
+/// Returns the "RSAPublicKey" of the X.509 certificate.
+fn rsa_public_key(cert: &[u8]) -> io::Result<Vec<u8>> {
+    let (cert, _) = Tlv::read(cert)?;
+    let tbs = cert.expect(TAG_SEQUENCE)?.children()?;
+    let tbs = tbs
+        .first()
+        .ok_or_else(|| invalid("empty certificate"))?
+        .expect(TAG_SEQUENCE)?
+        .children()?;
+    // [0] version, serial, signature, issuer, validity, subject, subjectPublicKeyInfo
+    let skip = if tbs.first().map(|t| t.tag) == Some(TAG_CONTEXT_0) {
+        6
+    } else {
+        5
+    };
+    let spki = tbs
+        .get(skip)
+        .ok_or_else(|| invalid("certificate without the public key"))?
+        .expect(TAG_SEQUENCE)?
+        .children()?;
+    let key = spki
+        .get(1)
+        .ok_or_else(|| invalid("certificate without the public key"))?
+        .expect(TAG_BIT_STRING)?;
+    // the first byte is the number of unused bits
+    match key.content.split_first() {
+        Some((0, v)) => Ok(v.to_vec()),
+        _ => Err(invalid("invalid certificate public key")),
+    }
+}
+
+struct SignerInfo {
+    digest_oid: Vec<u8>,
+    /// DER of the "[0] IMPLICIT" signed attributes, if any.
+    signed_attrs: Option<Vec<u8>>,
+    signature: Vec<u8>,
+}
+
+/// Returns the embedded content and the first signer of the PKCS7 "SignedData".
+/// ref. <https://www.rfc-editor.org/rfc/rfc2315#section-9.1>
+fn parse_signed_data(der: &[u8]) -> io::Result<(Vec<u8>, SignerInfo)> {
+    let (content_info, _) = Tlv::read(der)?;
+    let content_info = content_info.expect(TAG_SEQUENCE)?.children()?;
+    match content_info.first() {
+        Some(t) if t.tag == TAG_OID && t.content == OID_SIGNED_DATA => {}
+        _ => return Err(invalid("PKCS7 is not SignedData")),
+    }
+    let explicit = content_info
+        .get(1)
+        .ok_or_else(|| invalid("PKCS7 without SignedData"))?
+        .expect(TAG_CONTEXT_0)?
+        .children()?;
+    let signed_data = explicit
+        .first()
+        .ok_or_else(|| invalid("PKCS7 without SignedData"))?
+        .expect(TAG_SEQUENCE)?
+        .children()?;
+    // version, digestAlgorithms, contentInfo, [0] certificates, [1] crls, signerInfos
+    if signed_data.len() < 4 {
+        return Err(invalid("truncated PKCS7 SignedData"));
+    }
+    signed_data[0].expect(TAG_INTEGER)?;
+    let inner = signed_data[2].expect(TAG_SEQUENCE)?.children()?;
+    let content = match inner.get(1) {
+        Some(t) => match t.expect(TAG_CONTEXT_0)?.children()?.first() {
+            Some(v) => v.octets()?,
+            None => return Err(invalid("PKCS7 without content")),
+        },
+        None => return Err(invalid("PKCS7 without content (detached)")),
+    };
+    let signer_infos = signed_data.last().unwrap().expect(TAG_SET)?.children()?;
+    let signer = signer_infos
+        .first()
+        .ok_or_else(|| invalid("PKCS7 without signer"))?
+        .expect(TAG_SEQUENCE)?
+        .children()?;
+
+    // version, issuerAndSerialNumber, digestAlgorithm, [0] authenticatedAttributes,
+    // digestEncryptionAlgorithm, encryptedDigest
+    let digest_alg = signer
+        .get(2)
+        .ok_or_else(|| invalid("truncated PKCS7 signer"))?
+        .expect(TAG_SEQUENCE)?
+        .children()?;
+    let digest_oid = digest_alg
+        .first()
+        .ok_or_else(|| invalid("PKCS7 signer without digest algorithm"))?
+        .expect(TAG_OID)?
+        .content
+        .to_vec();
+    let (signed_attrs, rest) = match signer.get(3) {
+        Some(t) if t.tag == TAG_CONTEXT_0 => {
+            if t.indefinite {
+                return Err(invalid("PKCS7 signed attributes are not DER"));
+            }
+            (Some(t.raw.to_vec()), &signer[4..])
+        }
+        _ => (None, &signer[3.min(signer.len())..]),
+    };
+    let signature = rest
+        .get(1)
+        .ok_or_else(|| invalid("PKCS7 signer without signature"))?
+        .octets()?;
+    Ok((
+        content,
+        SignerInfo {
+            digest_oid,
+            signed_attrs,
+            signature,
+        },
+    ))
+}
+
+/// Returns the "messageDigest" of the signed attributes.
+fn message_digest(attrs: &[u8]) -> io::Result<Vec<u8>> {
+    let (attrs, _) = Tlv::read(attrs)?;
+    for attr in attrs.children()? {
+        let attr = attr.expect(TAG_SEQUENCE)?.children()?;
+        match (attr.first(), attr.get(1)) {
+            (Some(oid), Some(values))
+                if oid.tag == TAG_OID && oid.content == OID_MESSAGE_DIGEST =>
+            {
+                if let Some(v) = values.expect(TAG_SET)?.children()?.first() {
+                    return v.octets();
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(invalid("PKCS7 signed attributes without message digest"))
+}
+
+/// BER TLV, with the indefinite lengths that PKCS7 streaming encoders emit
+/// (e.g., "openssl smime -stream").
+struct Tlv<'a> {
+    tag: u8,
+    /// Whole encoding, header included.
+    raw: &'a [u8],
+    /// Contents, without the end-of-contents octets if indefinite.
+    content: &'a [u8],
+    indefinite: bool,
+    /// Nesting depth from the outermost TLV, limited to "MAX_BER_DEPTH"
+    /// across "children" (and "octets") as well.
+    depth: usize,
+}
+
+impl<'a> Tlv<'a> {
+    /// Reads one TLV, returning the rest of the input.
+    fn read(input: &'a [u8]) -> io::Result<(Self, &'a [u8])> {
+        Self::read_nested(input, 0)
+    }
+
+    fn read_nested(input: &'a [u8], depth: usize) -> io::Result<(Self, &'a [u8])> {
+        if depth > MAX_BER_DEPTH {
+            return Err(invalid("BER nested too deeply"));
+        }
+        let truncated = || invalid("truncated BER");
+        let (&tag, rest) = input.split_first().ok_or_else(truncated)?;
+        if tag & 0x1f == 0x1f {
+            return Err(invalid("unsupported BER high tag number"));
+        }
+        let (&first, mut rest) = rest.split_first().ok_or_else(truncated)?;
+        if first == 0x80 {
+            if tag & 0x20 == 0 {
+                return Err(invalid("indefinite length of primitive BER"));
+            }
+            let header = input.len() - rest.len();
+            let mut len = 0;
+            loop {
+                if rest.starts_with(&[0, 0]) {
+                    let end = header + len;
+                    return Ok((
+                        Self {
+                            tag,
+                            raw: &input[..end + 2],
+                            content: &input[header..end],
+                            indefinite: true,
+                            depth,
+                        },
+                        &input[end + 2..],
+                    ));
+                }
+                let (child, next) = Tlv::read_nested(rest, depth + 1)?;
+                len += child.raw.len();
+                rest = next;
+            }
+        }
+
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            let n = (first & 0x7f) as usize;
+            if n > 4 || rest.len() < n {
+                return Err(invalid("invalid BER length"));
+            }
+            let len = rest[..n].iter().fold(0, |acc, b| (acc << 8) | *b as usize);
+            rest = &rest[n..];
+            len
+        };
+        let header = input.len() - rest.len();
+        if rest.len() < len {
+            return Err(truncated());
+        }
+        Ok((
+            Self {
+                tag,
+                raw: &input[..header + len],
+                content: &rest[..len],
+                indefinite: false,
+                depth,
+            },
+            &rest[len..],
+        ))
+    }
+
+    fn children(&self) -> io::Result<Vec<Tlv<'a>>> {
+        let mut children = Vec::new();
+        let mut rest = self.content;
+        while !rest.is_empty() {
+            let (child, next) = Tlv::read_nested(rest, self.depth + 1)?;
+            children.push(child);
+            rest = next;
+        }
+        Ok(children)
+    }
+
+    fn expect(&self, tag: u8) -> io::Result<&Self> {
+        if self.tag != tag {
+            return Err(invalid(&format!(
+                "unexpected BER tag 0x{:02x} (expected 0x{tag:02x})",
+                self.tag
+            )));
+        }
+        Ok(self)
+    }
+
+    /// Returns the octets of the OCTET STRING, joining the constructed segments.
+    fn octets(&self) -> io::Result<Vec<u8>> {
+        match self.tag {
+            TAG_OCTET_STRING => Ok(self.content.to_vec()),
+            t if t == TAG_OCTET_STRING | 0x20 => {
+                let mut v = Vec::new();
+                for child in self.children()? {
+                    v.extend(child.octets()?);
+                }
+                Ok(v)
+            }
+            _ => Err(invalid(&format!(
+                "unexpected BER tag 0x{:02x} (expected OCTET STRING)",
+                self.tag
+            ))),
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    fmt, fs,
+    io::{self, Error, ErrorKind},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use aws_sdk_ec2::model::{Filter, InstanceStateName};
+use hyper::{body, Body, Client, Method, Request, StatusCode};
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+use crate::{ec2, eip, summary};
+
+pub const DEFAULT_ENDPOINT: &str = "http://169.254.169.254";
+
+/// TTL of the IMDSv2 session token (the maximum).
+const TOKEN_TTL: Duration = Duration::from_secs(21600);
+
+/// Margin before the token expiry to request the next one, so that
+/// a token never expires in flight.
+const TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// IMDSv2 session token, reused until it expires.
+#[derive(Clone)]
+struct Token {
+    value: String,
+    expires_at: Instant,
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Instance metadata service client with retries.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/configuring-instance-metadata-service.html>
+#[derive(Debug, Clone)]
+pub struct Imds {
+    /// Base URL of the metadata service (e.g., a local mock for tests).
+    pub endpoint: String,
+    /// Set true to fail rather than falling back to IMDSv1 (no session token).
+    pub require_v2: bool,
+    /// Number of retries after the first failed attempt.
+    pub retries: u32,
+    /// Timeout for each HTTP request.
+    pub timeout: Duration,
+    /// Session token shared by the clones, so that the fetches skip the PUT.
+    token: Arc<Mutex<Option<Token>>>,
+    /// Summary of the run, where the retries and the fallbacks are warned
+    /// (see "context::Context").
+    pub summary: Arc<summary::Summary>,
+}
+
+impl Imds {
+    pub fn new(require_v2: bool, retries: u32) -> Self {
+        Self {
+            endpoint: DEFAULT_ENDPOINT.to_string(),
+            require_v2,
+            retries,
+            timeout: Duration::from_secs(2),
+            token: Arc::new(Mutex::new(None)),
+            summary: Arc::new(summary::Summary::default()),
+        }
+    }
+
+    /// Fetches "meta-data/{path}", retrying with exponential backoff.
+    /// Returns "ErrorKind::NotFound" without retries if the path does not exist
+    /// (e.g., no spot interruption notice).
+    pub async fn fetch(&self, path: &str) -> io::Result<String> {
+        self.fetch_with_retries(&format!("meta-data/{path}")).await
+    }
+
+    /// Fetches "dynamic/{path}" (e.g., "instance-identity/document"), the same as "fetch".
+    pub async fn fetch_dynamic(&self, path: &str) -> io::Result<String> {
+        self.fetch_with_retries(&format!("dynamic/{path}")).await
+    }
+
+    async fn fetch_with_retries(&self, path: &str) -> io::Result<String> {
+        let mut backoff = Duration::from_millis(200);
+        let mut attempt = 0;
+        let mut history = summary::History::new(&self.summary, "imds");
+        loop {
+            match self.fetch_once(path).await {
+                Ok(v) => return Ok(v),
+                Err(e) if e.kind() == ErrorKind::NotFound => return Err(e),
+                Err(e) => {
+                    if attempt >= self.retries {
+                        return Err(history.exhausted(e));
+                    }
+                    attempt += 1;
+                    history.retry(&e, backoff);
+                    self.summary.warn(&format!(
+                        "failed to fetch {path} '{}' -- retrying in {backoff:?} ({attempt}/{})",
+                        e, self.retries
+                    ));
+                    sleep(backoff).await;
+                    backoff *= 2;
+                }
+            }
+        }
+    }
+
+    async fn fetch_once(&self, path: &str) -> io::Result<String> {
+        let token = self.session_token(false).await?;
+        match self.get(path, token.as_deref()).await {
+            // revoked before its expiry (e.g., the metadata service restarted)
+            Err(e) if token.is_some() && e.kind() == ErrorKind::PermissionDenied => {
+                log::info!("IMDSv2 token rejected -- fetching a new one");
+                let token = self.session_token(true).await?;
+                self.get(path, token.as_deref()).await
+            }
+            ret => ret,
+        }
+    }
+
+    /// Returns the cached session token, fetching one if none, expired, or
+    /// "refresh". Returns "None" to fall back to IMDSv1 unless "require_v2".
+    async fn session_token(&self, refresh: bool) -> io::Result<Option<String>> {
+        if !refresh {
+            let cached = self.token.lock().unwrap().clone();
+            if let Some(token) = cached.filter(|t| Instant::now() < t.expires_at) {
+                return Ok(Some(token.value));
+            }
+        }
+        match self.fetch_token().await {
+            Ok(value) => {
+                *self.token.lock().unwrap() = Some(Token {
+                    value: value.clone(),
+                    expires_at: Instant::now() + TOKEN_TTL - TOKEN_REFRESH_MARGIN,
+                });
+                Ok(Some(value))
+            }
+            Err(e) => {
+                if self.require_v2 {
+                    return Err(e);
+                }
+                self.summary.warn(&format!(
+                    "failed to fetch IMDSv2 token '{}' -- falling back to IMDSv1",
+                    e
+                ));
+                Ok(None)
+            }
+        }
+    }
+
+    async fn get(&self, path: &str, token: Option<&str>) -> io::Result<String> {
+        let mut req = Request::builder()
+            .method(Method::GET)
+            .uri(format!("{}/latest/{path}", self.endpoint));
+        if let Some(token) = token {
+            req = req.header("X-aws-ec2-metadata-token", token);
+        }
+        let req = req.body(Body::empty()).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to build GET {path} {}", e),
+            )
+        })?;
+        self.send(req).await
+    }
+
+    /// Returns true if the IMDSv2 token request times out, which is the symptom of
+    /// the PUT response hop limit (default 1) dropping responses to containers.
+    /// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/instancedata-data-retrieval.html#imds-considerations>
+    pub async fn is_hop_limited(&self) -> bool {
+        match self.fetch_token().await {
+            Err(e) => e.kind() == ErrorKind::TimedOut,
+            Ok(_) => false,
+        }
+    }
+
+    async fn fetch_token(&self) -> io::Result<String> {
+        let req = Request::builder()
+            .method(Method::PUT)
+            .uri(format!("{}/latest/api/token", self.endpoint))
+            .header(
+                "X-aws-ec2-metadata-token-ttl-seconds",
+                TOKEN_TTL.as_secs().to_string(),
+            )
+            .body(Body::empty())
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to build PUT api/token {}", e),
+                )
+            })?;
+        self.send(req).await
+    }
+
+    async fn send(&self, req: Request<Body>) -> io::Result<String> {
+        let uri = req.uri().to_string();
+        let resp = timeout(self.timeout, Client::new().request(req))
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, format!("{uri} timed out")))?
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed {uri} {}", e)))?;
+
+        let status = resp.status();
+        let bytes = body::to_bytes(resp.into_body())
+            .await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to read {uri} {}", e)))?;
+        let text = String::from_utf8(bytes.to_vec()).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{uri} returned non-UTF8 bytes {}", e),
+            )
+        })?;
+        match status {
+            StatusCode::OK => Ok(text),
+            StatusCode::NOT_FOUND => {
+                Err(Error::new(ErrorKind::NotFound, format!("{uri} not found")))
+            }
+            StatusCode::UNAUTHORIZED => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{uri} returned {status} (invalid or expired token)"),
+            )),
+            _ => Err(Error::new(
+                ErrorKind::Other,
+                format!("{uri} returned {status} '{text}'"),
+            )),
+        }
+    }
+}
+
+/// Returns the actionable diagnostic for the IMDSv2 hop limit issue.
+pub fn hop_limit_diagnostic() -> String {
+    let location = if in_container() {
+        "from inside a container"
+    } else {
+        "from this host"
+    };
+    format!(
+        "IMDSv2 token request timed out {location} -- the instance metadata PUT response hop limit is likely 1; \
+raise it with 'aws ec2 modify-instance-metadata-options --instance-id <INSTANCE_ID> --http-put-response-hop-limit 2 --http-endpoint enabled', \
+or pass '--fix-imds-hop-limit=true' (requires ec2:ModifyInstanceMetadataOptions and '--instance-id-fallback' or '--imds-require-v2=false' to find the instance)"
+    )
+}
+
+/// Raises the IMDSv2 PUT response hop limit so that containers can reach IMDS.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_ModifyInstanceMetadataOptions.html>
+pub async fn fix_hop_limit(
+    ec2_manager: &ec2::Manager,
+    instance_id: &str,
+    hop_limit: i32,
+) -> io::Result<()> {
+    log::info!("setting IMDS PUT response hop limit to {hop_limit} for {instance_id}");
+    ec2_manager
+        .client()
+        .modify_instance_metadata_options()
+        .instance_id(instance_id)
+        .http_put_response_hop_limit(hop_limit)
+        .send()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed modify_instance_metadata_options {:?}", e),
+            )
+        })?;
+    Ok(())
+}
+
+/// Returns true if the process runs inside a container.
+fn in_container() -> bool {
+    if Path::new("/.dockerenv").exists() || Path::new("/run/.containerenv").exists() {
+        return true;
+    }
+    match fs::read_to_string("/proc/1/cgroup") {
+        Ok(cgroup) => ["docker", "kubepods", "containerd", "libpod"]
+            .iter()
+            .any(|v| cgroup.contains(v)),
+        Err(_) => false,
+    }
+}
+
+/// Fetches the local instance ID from IMDS, and if IMDS is unreachable,
+/// falls back to DescribeInstances with the "fallback" selector:
+/// "none", "mac" (local network interface MAC addresses), or "tag:<key>=<value>".
+pub async fn fetch_instance_id(
+    imds: &Imds,
+    ec2_manager: &ec2::Manager,
+    fallback: &str,
+) -> io::Result<String> {
+    let err = match imds.fetch("instance-id").await {
+        Ok(v) => return Ok(v),
+        Err(e) => e,
+    };
+    if fallback == "none" {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("failed to fetch instance ID from IMDS '{}'", err),
+        ));
+    }
+    imds.summary.warn(&format!(
+        "failed to fetch instance ID from IMDS '{}' -- falling back to DescribeInstances by {fallback}",
+        err
+    ));
+
+    let mut filters = vec![Filter::builder()
+        .name("instance-state-name")
+        .set_values(Some(vec![
+            InstanceStateName::Pending.as_str().to_string(),
+            InstanceStateName::Running.as_str().to_string(),
+        ]))
+        .build()];
+    if fallback == "mac" {
+        filters.push(
+            Filter::builder()
+                .name("network-interface.mac-address")
+                .set_values(Some(local_mac_addresses()?))
+                .build(),
+        );
+    } else if let Some(kv) = fallback.strip_prefix("tag:") {
+        let (k, v) = kv.split_once('=').ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid tag fallback '{fallback}' (expected tag:<key>=<value>)"),
+            )
+        })?;
+        filters.push(Filter::builder().name(format!("tag:{k}")).values(v).build());
+    } else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown instance ID fallback '{fallback}'"),
+        ));
+    }
+
+    let instances = eip::describe_instances(ec2_manager, filters).await?;
+    match instances.as_slice() {
+        [instance] => Ok(instance.instance_id().unwrap_or_default().to_string()),
+        _ => Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "expected exactly one instance by {fallback}, found {}",
+                instances.len()
+            ),
+        )),
+    }
+}
+
+/// Reads the MAC addresses of the local network interfaces (except loopback).
+#[cfg(not(windows))]
+fn local_mac_addresses() -> io::Result<Vec<String>> {
+    let mut macs = Vec::new();
+    for entry in fs::read_dir("/sys/class/net")? {
+        let entry = entry?;
+        if entry.file_name() == "lo" {
+            continue;
+        }
+        let mac = fs::read_to_string(entry.path().join("address"))?;
+        let mac = mac.trim();
+        if !mac.is_empty() && mac != "00:00:00:00:00:00" {
+            macs.push(mac.to_string());
+        }
+    }
+    if macs.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "no local network interface MAC address found",
+        ));
+    }
+    Ok(macs)
+}
+
+/// Reads the MAC addresses of the local network adapters with "getmac"
+/// (e.g., "\"0A-1B-2C-3D-4E-5F\",\"\\Device\\Tcpip_{...}\""), as "0a:1b:2c:3d:4e:5f".
+#[cfg(windows)]
+fn local_mac_addresses() -> io::Result<Vec<String>> {
+    let out = std::process::Command::new("getmac")
+        .args(["/fo", "csv", "/nh"])
+        .output()?;
+    let macs: Vec<String> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter_map(|line| line.split(',').next())
+        .map(|v| v.trim().trim_matches('"').replace('-', ":").to_lowercase())
+        .filter(|mac| mac.len() == 17)
+        .collect();
+    if macs.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "no local network interface MAC address found",
+        ));
+    }
+    Ok(macs)
+}
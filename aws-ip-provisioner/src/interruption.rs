@@ -0,0 +1,115 @@
+use std::io;
+
+use aws_sdk_ec2::model::Filter;
+
+use crate::{
+    command::{self, Flags},
+    context::Context,
+    ec2, eip, hook,
+    imds::Imds,
+    pool,
+    provisioner::AwsEc2,
+};
+
+/// Tag key that marks an instance as a standby target for the EIP swap.
+pub const STANDBY_TAG_KEY: &str = "Standby";
+
+/// Checks the instance metadata for the spot interruption notice and
+/// the rebalance recommendation, and returns true if either shows up.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/spot-instance-termination-notices.html>
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/rebalance-recommendations.html>
+pub async fn check(imds: &Imds) -> bool {
+    // IMDS returns 404 when there is no notice, which is surfaced as an error
+    if let Ok(action) = imds.fetch("spot/instance-action").await {
+        log::warn!("received spot interruption notice {action}");
+        return true;
+    }
+    if let Ok(notice) = imds.fetch("events/recommendations/rebalance").await {
+        log::warn!("received rebalance recommendation {notice}");
+        return true;
+    }
+    false
+}
+
+/// Runs the "--on-interruption" action ("release", "swap", or "noop") after
+/// the drain phase, and then the post-release steps unless "noop".
+pub async fn handle(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    ec2_instance_id: &str,
+    eip: &ec2::Eip,
+    opts: &Flags,
+) -> io::Result<()> {
+    match opts.on_interruption.as_str() {
+        "release" => {
+            opts.drain()
+                .run(&hook::eip_vars(eip, ec2_instance_id))
+                .await;
+            pool::release(&AwsEc2 { ec2_manager, ctx }, eip).await?
+        }
+        "swap" => swap(ctx, ec2_manager, ec2_instance_id, eip, opts).await?,
+        _ => {
+            log::info!(
+                "on-interruption is {} -- keeping the EIP as is",
+                opts.on_interruption
+            );
+            return Ok(());
+        }
+    }
+    command::post_release(ctx, opts, eip, ec2_instance_id).await
+}
+
+/// Re-associates the EIP with a running standby instance of the same "Kind",
+/// once the connections are drained.
+async fn swap(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    ec2_instance_id: &str,
+    eip: &ec2::Eip,
+    opts: &Flags,
+) -> io::Result<()> {
+    let instances = eip::describe_instances(
+        ec2_manager,
+        vec![
+            Filter::builder()
+                .name(format!("tag:{}", opts.kind_tag_key))
+                .values(&opts.kind_tag_value)
+                .build(),
+            Filter::builder()
+                .name(format!("tag:{STANDBY_TAG_KEY}"))
+                .values("true")
+                .build(),
+            Filter::builder()
+                .name("instance-state-name")
+                .values("running")
+                .build(),
+        ],
+    )
+    .await?;
+    let standby_instance_id = instances
+        .iter()
+        .filter_map(|instance| instance.instance_id())
+        .find(|id| *id != ec2_instance_id)
+        .map(|id| id.to_string());
+    let standby_instance_id = match standby_instance_id {
+        Some(v) => v,
+        None => {
+            log::warn!("no standby instance found -- returning EIP to the pool instead");
+            opts.drain()
+                .run(&hook::eip_vars(eip, ec2_instance_id))
+                .await;
+            return pool::release(&AwsEc2 { ec2_manager, ctx }, eip).await;
+        }
+    };
+
+    let mut vars = hook::eip_vars(eip, ec2_instance_id);
+    vars.push(("target_instance_id", standby_instance_id.clone()));
+    opts.drain().run(&vars).await;
+
+    log::info!(
+        "swapping EIP {} from {ec2_instance_id} to standby {standby_instance_id}",
+        eip.public_ip
+    );
+    eip::reassociate(ctx, ec2_manager, &eip.allocation_id, &standby_instance_id).await?;
+    Ok(())
+}
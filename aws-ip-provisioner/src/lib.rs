@@ -0,0 +1,55 @@
+pub mod alert;
+pub mod audit;
+pub mod cache;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod checkpoint;
+pub mod circuit;
+pub mod command;
+pub mod config;
+pub mod conflict;
+#[cfg(feature = "consul")]
+pub mod consul;
+pub mod context;
+pub mod daemon;
+pub mod ddns;
+pub mod dns;
+pub mod drain;
+pub mod ec2;
+pub mod eip;
+pub mod firewall;
+pub mod hook;
+pub mod hostname;
+pub mod iam;
+pub mod identity;
+pub mod imds;
+pub mod interruption;
+pub mod lifecycle;
+pub mod logging;
+pub mod metrics;
+pub mod notify;
+pub mod peers;
+pub mod pipeline;
+pub mod platform;
+pub mod pool;
+pub mod prefix_list;
+pub mod progress;
+pub mod provisioner;
+pub mod ratelimit;
+pub mod reachability;
+pub mod route53;
+pub mod sdk;
+pub mod secret;
+pub mod security_group;
+pub mod state;
+pub mod stun;
+pub mod summary;
+pub mod tags;
+pub mod textfile;
+pub mod timing;
+pub mod tls;
+pub mod transfer;
+pub mod validate;
+pub mod wireguard;
+
+pub const APP_NAME: &str = "aws-ip-provisioner";
@@ -0,0 +1,156 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    path::Path,
+};
+
+use aws_manager::autoscaling;
+use aws_sdk_ec2::model::Filter;
+use tokio::time::{sleep, Duration};
+
+use crate::{
+    command::{self, Flags},
+    context::Context,
+    ec2, eip,
+    imds::Imds,
+    pool,
+    provisioner::AwsEc2,
+    state,
+};
+
+/// Target lifecycle state reported by IMDS once a scale-in began.
+/// ref. <https://docs.aws.amazon.com/autoscaling/ec2/userguide/retrieving-target-lifecycle-state-through-imds.html>
+const TARGET_LIFECYCLE_STATE_TERMINATED: &str = "Terminated";
+
+/// Instance tag key that auto scaling sets with the group name.
+const ASG_NAME_TAG_KEY: &str = "aws:autoscaling:groupName";
+
+/// Waits until the local instance is being terminated by its auto scaling group,
+/// and then returns the EIP in the mounted file path back to the pool.
+/// If the lifecycle hook name is non-empty, completes the lifecycle action
+/// so the termination proceeds without waiting for the hook timeout.
+/// Removes the firewall rules and runs the post-release hook once the EIP is back in the pool.
+pub async fn handle_terminate(
+    ctx: &Context,
+    imds: &Imds,
+    ec2_manager: &ec2::Manager,
+    asg_manager: &autoscaling::Manager,
+    ec2_instance_id: &str,
+    opts: &Flags,
+    poll_interval: Duration,
+) -> io::Result<()> {
+    log::info!("waiting for the instance {ec2_instance_id} to enter terminating lifecycle state");
+    loop {
+        match imds.fetch("autoscaling/target-lifecycle-state").await {
+            Ok(state) => {
+                if state.trim() == TARGET_LIFECYCLE_STATE_TERMINATED {
+                    log::info!("target lifecycle state is {state} -- releasing EIP to the pool");
+                    break;
+                }
+                log::debug!("target lifecycle state is {state}");
+            }
+            Err(e) => log::warn!("failed to fetch target lifecycle state '{}'", e),
+        }
+        sleep(poll_interval).await;
+    }
+
+    let mounted_eip_file_path = &opts.mounted_eip_file_path;
+    if Path::new(mounted_eip_file_path).exists() {
+        let eip = state::load_eip(mounted_eip_file_path)?;
+        pool::release(&AwsEc2 { ec2_manager, ctx }, &eip).await?;
+        command::post_release(ctx, opts, &eip, ec2_instance_id).await?;
+    } else {
+        log::warn!("mounted EIP file {mounted_eip_file_path} does not exist -- nothing to release");
+    }
+
+    let lifecycle_hook_name = &opts.lifecycle_hook_name;
+    if lifecycle_hook_name.is_empty() {
+        log::info!("empty lifecycle hook name -- skipping complete_lifecycle_action");
+        return Ok(());
+    }
+
+    let asg_name = asg_name(ec2_manager, ec2_instance_id).await?;
+    if asg_name.is_empty() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("instance {ec2_instance_id} has no '{ASG_NAME_TAG_KEY}' tag"),
+        ));
+    }
+
+    log::info!("completing lifecycle action {lifecycle_hook_name} for {asg_name}");
+    asg_manager
+        .client()
+        .complete_lifecycle_action()
+        .auto_scaling_group_name(asg_name)
+        .lifecycle_hook_name(lifecycle_hook_name)
+        .instance_id(ec2_instance_id)
+        .lifecycle_action_result("CONTINUE")
+        .send()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed complete_lifecycle_action {:?}", e),
+            )
+        })?;
+
+    log::info!("successfully returned EIP to the pool and completed the lifecycle action");
+    Ok(())
+}
+
+/// Returns the name of the auto scaling group of the instance, empty if none.
+pub async fn asg_name(ec2_manager: &ec2::Manager, ec2_instance_id: &str) -> io::Result<String> {
+    let resp = ec2_manager
+        .client()
+        .describe_tags()
+        .filters(
+            Filter::builder()
+                .name("resource-id")
+                .values(ec2_instance_id)
+                .build(),
+        )
+        .send()
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed describe_tags {:?}", e)))?;
+    Ok(resp
+        .tags()
+        .unwrap_or_default()
+        .iter()
+        .find(|tag| tag.key() == Some(ASG_NAME_TAG_KEY))
+        .and_then(|tag| tag.value())
+        .unwrap_or_default()
+        .to_string())
+}
+
+/// Returns the sorted IDs of the pending and running instances in the
+/// auto scaling group of the instance, empty if not in a group.
+pub async fn asg_members(
+    ec2_manager: &ec2::Manager,
+    ec2_instance_id: &str,
+) -> io::Result<Vec<String>> {
+    let asg_name = asg_name(ec2_manager, ec2_instance_id).await?;
+    if asg_name.is_empty() {
+        return Ok(Vec::new());
+    }
+    let instances = eip::describe_instances(
+        ec2_manager,
+        vec![
+            Filter::builder()
+                .name(format!("tag:{ASG_NAME_TAG_KEY}"))
+                .values(&asg_name)
+                .build(),
+            Filter::builder()
+                .name("instance-state-name")
+                .values("pending")
+                .values("running")
+                .build(),
+        ],
+    )
+    .await?;
+    let mut ids: Vec<String> = instances
+        .iter()
+        .filter_map(|i| i.instance_id().map(|v| v.to_string()))
+        .collect();
+    ids.sort();
+    log::info!("found {} instances in {asg_name}", ids.len());
+    Ok(ids)
+}
@@ -0,0 +1,180 @@
+use std::io::{self, Error};
+#[cfg(unix)]
+use std::{env, os::unix::net::UnixDatagram, process};
+
+#[cfg(unix)]
+use env_logger::filter::{Builder, Filter};
+#[cfg(unix)]
+use log::{Level, Log, Metadata, Record};
+
+/// Socket of the local syslog daemon (e.g., rsyslog, or journald's syslog compatibility).
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// Socket of the journald native protocol.
+/// ref. <https://systemd.io/JOURNAL_NATIVE_PROTOCOL/>
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Syslog facility "daemon".
+#[cfg(unix)]
+const FACILITY_DAEMON: u8 = 3;
+
+/// Returns the logger filter of the level with the per-module levels
+/// (e.g., "info,aws_smithy_http=trace"), the latter taking precedence.
+pub fn log_filter(log_level: &str, log_filter: &str) -> String {
+    if log_filter.is_empty() {
+        return log_level.to_string();
+    }
+    format!("{log_level},{log_filter}")
+}
+
+/// Initializes the logger to write to the target ("stderr", "syslog", or "journald"),
+/// with "RUST_LOG" overriding the level and the per-module filters.
+/// No-op if already initialized.
+pub fn init(log_level: &str, filters: &str, log_target: &str) -> io::Result<()> {
+    let filters = log_filter(log_level, filters);
+    let (path, target) = match log_target {
+        "syslog" => (SYSLOG_SOCKET, Target::Syslog),
+        "journald" => (JOURNALD_SOCKET, Target::Journald),
+        _ => {
+            // ref. <https://github.com/env-logger-rs/env_logger/issues/47>
+            let _ = env_logger::try_init_from_env(
+                env_logger::Env::default().filter_or(env_logger::DEFAULT_FILTER_ENV, filters),
+            );
+            return Ok(());
+        }
+    };
+
+    init_socket(path, target, log_target, &filters)
+}
+
+/// Initializes the logger of the local syslog or journald socket.
+#[cfg(unix)]
+fn init_socket(path: &str, target: Target, log_target: &str, filters: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("failed to connect to {log_target} socket {path} '{}'", e),
+        )
+    })?;
+    let filter = Builder::new()
+        .parse(&env::var(env_logger::DEFAULT_FILTER_ENV).unwrap_or_else(|_| filters.to_string()))
+        .build();
+    let max_level = filter.filter();
+    let identifier = env::current_exe()
+        .ok()
+        .and_then(|p| p.file_name().map(|v| v.to_string_lossy().to_string()))
+        .unwrap_or_else(|| String::from(crate::APP_NAME));
+    if log::set_boxed_logger(Box::new(SocketLogger {
+        filter,
+        socket,
+        target,
+        identifier,
+    }))
+    .is_ok()
+    {
+        log::set_max_level(max_level);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn init_socket(path: &str, _: Target, log_target: &str, _: &str) -> io::Result<()> {
+    Err(Error::new(
+        io::ErrorKind::Unsupported,
+        format!("log target {log_target} ({path}) is only available on Unix"),
+    ))
+}
+
+enum Target {
+    Syslog,
+    Journald,
+}
+
+/// Writes each record as one datagram to the local syslog or journald socket.
+#[cfg(unix)]
+struct SocketLogger {
+    filter: Filter,
+    socket: UnixDatagram,
+    target: Target,
+    identifier: String,
+}
+
+#[cfg(unix)]
+impl Log for SocketLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let d = match self.target {
+            Target::Syslog => syslog_message(&self.identifier, record),
+            Target::Journald => journald_message(&self.identifier, record),
+        };
+        // nowhere to report the failure, since this is the logger
+        let _ = self.socket.send(&d);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Returns the syslog severity of the level.
+/// ref. <https://www.rfc-editor.org/rfc/rfc5424#section-6.2.1>
+#[cfg(unix)]
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Formats the record as the local syslog message (e.g., "<30>aws-ip-provisioner[123]: ...").
+/// The daemon adds the timestamp and the hostname.
+#[cfg(unix)]
+fn syslog_message(identifier: &str, record: &Record) -> Vec<u8> {
+    format!(
+        "<{}>{identifier}[{}]: {}: {}",
+        FACILITY_DAEMON * 8 + severity(record.level()),
+        process::id(),
+        record.target(),
+        record.args()
+    )
+    .into_bytes()
+}
+
+/// Formats the record as the journald native message, with the structured fields
+/// to filter on (e.g., "journalctl SYSLOG_IDENTIFIER=aws-ip-provisioner PRIORITY=4").
+#[cfg(unix)]
+fn journald_message(identifier: &str, record: &Record) -> Vec<u8> {
+    let mut d = Vec::new();
+    append_field(&mut d, "MESSAGE", &record.args().to_string());
+    append_field(&mut d, "PRIORITY", &severity(record.level()).to_string());
+    append_field(&mut d, "SYSLOG_IDENTIFIER", identifier);
+    append_field(&mut d, "SYSLOG_FACILITY", &FACILITY_DAEMON.to_string());
+    append_field(&mut d, "TARGET", record.target());
+    if let Some(file) = record.file() {
+        append_field(&mut d, "CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        append_field(&mut d, "CODE_LINE", &line.to_string());
+    }
+    d
+}
+
+/// Appends "KEY=value\n", or the length-prefixed value if multi-line.
+#[cfg(unix)]
+fn append_field(d: &mut Vec<u8>, key: &str, v: &str) {
+    d.extend_from_slice(key.as_bytes());
+    if v.contains('\n') {
+        d.push(b'\n');
+        d.extend_from_slice(&(v.len() as u64).to_le_bytes());
+    } else {
+        d.push(b'=');
+    }
+    d.extend_from_slice(v.as_bytes());
+    d.push(b'\n');
+}
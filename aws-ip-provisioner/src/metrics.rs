@@ -0,0 +1,54 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Metrics registry of the run, keyed by the metric name.
+#[derive(Debug, Default)]
+pub struct Registry {
+    gauges: Mutex<BTreeMap<String, f64>>,
+    counters: Mutex<BTreeMap<String, u64>>,
+    /// Prefix of the metric names ("--namespace"), empty for none.
+    namespace: Mutex<String>,
+}
+
+impl Registry {
+    /// Sets the namespace that prefixes the metric names set afterwards
+    /// (e.g., "internal_vip_eip_reassociated_total"), with "-" replaced by "_".
+    pub fn set_namespace(&self, namespace: &str) {
+        *self.namespace.lock().unwrap() = namespace.replace('-', "_");
+    }
+
+    /// Returns the metric name prefixed with the namespace, as is if none.
+    pub fn namespaced(&self, name: &str) -> String {
+        let namespace = self.namespace.lock().unwrap();
+        if namespace.is_empty() {
+            name.to_string()
+        } else {
+            format!("{namespace}_{name}")
+        }
+    }
+
+    /// Sets the gauge value.
+    pub fn set_gauge(&self, name: &str, v: f64) {
+        let name = self.namespaced(name);
+        log::debug!("gauge {name}={v}");
+        self.gauges.lock().unwrap().insert(name, v);
+    }
+
+    /// Increments the counter by one.
+    pub fn inc_counter(&self, name: &str) {
+        let name = self.namespaced(name);
+        let mut counters = self.counters.lock().unwrap();
+        let cnt = counters.entry(name.clone()).or_insert(0);
+        *cnt += 1;
+        log::debug!("counter {name}={cnt}");
+    }
+
+    /// Returns all the gauges.
+    pub fn gauges(&self) -> BTreeMap<String, f64> {
+        self.gauges.lock().unwrap().clone()
+    }
+
+    /// Returns all the counters.
+    pub fn counters(&self) -> BTreeMap<String, u64> {
+        self.counters.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hyper::{Body, Method, Request};
+use serde_json::{json, Map, Value};
+use tokio::{
+    task::JoinHandle,
+    time::{timeout, Duration, Instant},
+};
+
+use crate::{hook, provisioner::BoxFuture, sdk, secret, tls};
+
+/// Events notified by default.
+pub const DEFAULT_EVENTS: &str = "allocated,associated,released,failed";
+
+/// Discord rejects the messages longer than 2,000 characters.
+const DISCORD_MAX_CONTENT: usize = 2000;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Progress event to notify (see "progress::emit").
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub name: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl Event {
+    pub fn new(name: &str, fields: &[(&str, &str)]) -> Self {
+        Self {
+            name: name.to_string(),
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    /// Returns the one-line summary (e.g.,
+    /// "aws-ip-provisioner associated: allocation_id=eipalloc-1, instance_id=i-1").
+    pub fn text(&self) -> String {
+        let mut s = format!("{} {}", crate::APP_NAME, self.name);
+        if !self.fields.is_empty() {
+            let fields: Vec<String> = self
+                .fields
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect();
+            s.push_str(": ");
+            s.push_str(&fields.join(", "));
+        }
+        s
+    }
+
+    /// Returns the event as the "--progress=ndjson" line (e.g.,
+    /// {"event":"allocated","ts":1673000000,"allocation_id":"eipalloc-..."}),
+    /// stamped with the current time.
+    pub fn payload(&self) -> Value {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let mut m = Map::new();
+        m.insert(String::from("event"), Value::from(self.name.as_str()));
+        m.insert(String::from("ts"), Value::from(ts));
+        for (k, v) in self.fields.iter() {
+            m.insert(k.clone(), Value::from(v.as_str()));
+        }
+        Value::Object(m)
+    }
+}
+
+/// Destination of the event notifications (e.g., a chat webhook).
+pub trait Sink: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, ()>;
+}
+
+/// Sink with the events it receives.
+#[derive(Clone)]
+pub struct Subscription {
+    pub events: Vec<String>,
+    pub sink: Arc<dyn Sink>,
+}
+
+impl Subscription {
+    /// Subscribes the sink to the comma-separated events.
+    pub fn new(events: &str, sink: Arc<dyn Sink>) -> Self {
+        Self {
+            events: events
+                .split(',')
+                .map(|e| e.trim().to_string())
+                .filter(|e| !e.is_empty())
+                .collect(),
+            sink,
+        }
+    }
+
+    pub fn wants(&self, event: &str) -> bool {
+        self.events.iter().any(|e| e == event)
+    }
+}
+
+/// Slack incoming webhook.
+/// ref. <https://api.slack.com/messaging/webhooks>
+pub struct SlackWebhook {
+    /// Where to read the webhook URL (see "secret::Source::parse").
+    pub url_source: String,
+    pub sdk_opts: sdk::Options,
+}
+
+impl Sink for SlackWebhook {
+    fn name(&self) -> &'static str {
+        "slack"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            post_webhook(
+                &self.url_source,
+                "SLACK_WEBHOOK_URL",
+                &self.sdk_opts,
+                &json!({ "text": event.text() }),
+            )
+            .await
+        })
+    }
+}
+
+/// Discord webhook.
+/// ref. <https://discord.com/developers/docs/resources/webhook#execute-webhook>
+pub struct DiscordWebhook {
+    /// Where to read the webhook URL (see "secret::Source::parse").
+    pub url_source: String,
+    pub sdk_opts: sdk::Options,
+}
+
+impl Sink for DiscordWebhook {
+    fn name(&self) -> &'static str {
+        "discord"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let content: String = event.text().chars().take(DISCORD_MAX_CONTENT).collect();
+            post_webhook(
+                &self.url_source,
+                "DISCORD_WEBHOOK_URL",
+                &self.sdk_opts,
+                &json!({ "content": content }),
+            )
+            .await
+        })
+    }
+}
+
+/// Local command run with the phase name ("{phase}") and the event as JSON
+/// ("{payload}"), also exposed as "IP_MANAGER_PHASE" and "IP_MANAGER_PAYLOAD",
+/// for the supervisors and telemetry agents of the hosts without outbound metrics.
+/// One command runs at a time, but the events may arrive out of order,
+/// so the consumers order them by "ts".
+pub struct PhaseCommand {
+    hook: hook::Hook,
+    running: tokio::sync::Mutex<()>,
+}
+
+impl PhaseCommand {
+    pub fn new(cmd: &str, timeout_seconds: u32) -> Self {
+        Self {
+            // the failure is logged by "dispatch", never failing the run
+            hook: hook::Hook::new(cmd, timeout_seconds, "fail"),
+            running: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+impl Sink for PhaseCommand {
+    fn name(&self) -> &'static str {
+        "on-phase-cmd"
+    }
+
+    fn send<'a>(&'a self, event: &'a Event) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let _running = self.running.lock().await;
+            self.hook
+                .run(&event.name, &[("payload", event.payload().to_string())])
+                .await
+        })
+    }
+}
+
+/// Notification sinks of the run, set once at start (see "init").
+#[derive(Default)]
+pub struct Notifier {
+    subscriptions: Mutex<Vec<Subscription>>,
+    /// Sends in flight, awaited by "flush" before the process exits.
+    pending: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Notifier {
+    /// Sets the sinks.
+    pub fn init(&self, subscriptions: Vec<Subscription>) {
+        *self.subscriptions.lock().unwrap() = subscriptions;
+    }
+
+    /// Sends the event to the subscribed sinks in the background, so that
+    /// a slow or failing webhook never delays the provisioning. A failed send
+    /// is logged, not retried.
+    pub fn dispatch(&self, event: &str, fields: &[(&str, &str)]) {
+        let subscriptions: Vec<Subscription> = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|s| s.wants(event))
+            .cloned()
+            .collect();
+        if subscriptions.is_empty() {
+            return;
+        }
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(h) => h,
+            Err(_) => {
+                log::warn!("no async runtime -- not notifying {event}");
+                return;
+            }
+        };
+        let event = Event::new(event, fields);
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|h| !h.is_finished());
+        for s in subscriptions {
+            let event = event.clone();
+            pending.push(runtime.spawn(async move {
+                if let Err(e) = s.sink.send(&event).await {
+                    log::warn!(
+                        "failed to notify {} of {} '{}'",
+                        s.sink.name(),
+                        event.name,
+                        e
+                    );
+                }
+            }));
+        }
+    }
+
+    /// Waits for the sends in flight, up to the deadline
+    /// (e.g., the "failed" notification right before the process exits).
+    pub async fn flush(&self, deadline: Duration) {
+        let pending: Vec<JoinHandle<()>> = self.pending.lock().unwrap().drain(..).collect();
+        let until = Instant::now() + deadline;
+        for h in pending {
+            let left = until.saturating_duration_since(Instant::now());
+            if timeout(left, h).await.is_err() {
+                log::warn!("notifications did not complete within {deadline:?}");
+                return;
+            }
+        }
+    }
+}
+
+/// POSTs the JSON body, expecting a 2xx status.
+/// POSTs to the webhook URL read from the source, dropping the cached URL
+/// if the webhook rejects it (e.g., revoked, and rotated since cached).
+async fn post_webhook(
+    url_source: &str,
+    default_env: &str,
+    sdk_opts: &sdk::Options,
+    body: &Value,
+) -> io::Result<()> {
+    let url = secret::read(url_source, default_env, sdk_opts).await?;
+    let ret = post_json(&url, body).await;
+    if matches!(&ret, Err(e) if e.kind() == ErrorKind::PermissionDenied) {
+        secret::invalidate(url_source, default_env, sdk_opts);
+    }
+    ret
+}
+
+async fn post_json(url: &str, body: &Value) -> io::Result<()> {
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(body)?))
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid webhook URL {}", e),
+            )
+        })?;
+    let client = hyper::Client::builder().build::<_, Body>(tls::https_connector());
+    let resp = timeout(SEND_TIMEOUT, client.request(req))
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, "webhook timed out"))?
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed webhook POST {}", e)))?;
+    if !resp.status().is_success() {
+        // Slack and Discord answer the revoked webhooks with 401, 403, or 404
+        let kind = match resp.status().as_u16() {
+            401 | 403 | 404 => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Other,
+        };
+        return Err(Error::new(
+            kind,
+            format!("webhook returned {}", resp.status()),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_sigv4::http_request::{
+    PayloadChecksumKind, PercentEncodingMode, SigningSettings, UriPathNormalizationMode,
+};
+use aws_types::SdkConfig;
+use hyper::{http, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::time::{sleep, Duration};
+
+use crate::{hook, sdk};
+
+/// Most attempts to publish when the other nodes keep updating the object.
+pub const MAX_PUBLISH_ATTEMPTS: u32 = 10;
+
+/// Peer exchange file, shared by the nodes in one S3 object.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Document {
+    #[serde(default)]
+    pub peers: Vec<Peer>,
+}
+
+/// Public IP of one node, identified by its "Id" within the "Kind".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Peer {
+    pub id: String,
+    #[serde(default)]
+    pub kind: String,
+    pub public_ip: String,
+    /// Unix seconds of the last change of the public IP.
+    #[serde(default)]
+    pub updated_at: u64,
+}
+
+impl Document {
+    /// Adds or replaces the peer of the same "Id" and "Kind", sorted.
+    /// Returns false if the peer is already published with the same public IP,
+    /// so that the unchanged nodes do not rewrite the object.
+    pub fn upsert(&mut self, peer: Peer) -> bool {
+        match self
+            .peers
+            .iter_mut()
+            .find(|p| p.id == peer.id && p.kind == peer.kind)
+        {
+            Some(p) if p.public_ip == peer.public_ip => return false,
+            Some(p) => *p = peer,
+            None => self.peers.push(peer),
+        }
+        self.peers
+            .sort_by(|a, b| (&a.kind, &a.id).cmp(&(&b.kind, &b.id)));
+        true
+    }
+
+    /// Renders the template (e.g., "{public_ip}:30303") of each peer of the "Kind"
+    /// (all kinds if empty), one per line, excluding the "Id" (e.g., the local node).
+    pub fn render(&self, kind: &str, exclude_id: &str, template: &str) -> String {
+        let mut s = String::new();
+        for p in self.peers.iter() {
+            if (!kind.is_empty() && p.kind != kind)
+                || (!exclude_id.is_empty() && p.id == exclude_id)
+            {
+                continue;
+            }
+            let vars = [
+                ("id", p.id.clone()),
+                ("kind", p.kind.clone()),
+                ("public_ip", p.public_ip.clone()),
+            ];
+            s.push_str(&hook::render(template, &vars));
+            s.push('\n');
+        }
+        s
+    }
+}
+
+/// S3 object of the peer exchange file.
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub region: String,
+    pub bucket: String,
+    pub key: String,
+}
+
+impl Object {
+    /// Returns the path-style URI, which also works with the bucket names
+    /// with dots and with the S3-compatible endpoints of "--endpoint-url".
+    /// ref. <https://docs.aws.amazon.com/AmazonS3/latest/userguide/VirtualHosting.html#path-style-access>
+    pub fn uri(&self, opts: &sdk::Options) -> String {
+        let endpoint = if !opts.endpoint_url.is_empty() {
+            opts.endpoint_url.trim_end_matches('/').to_string()
+        } else {
+            let suffix = if self.region.starts_with("cn-") {
+                "amazonaws.com.cn"
+            } else {
+                "amazonaws.com"
+            };
+            format!(
+                "https://s3{}{}.{}.{suffix}",
+                if opts.use_fips { "-fips" } else { "" },
+                if opts.use_dual_stack {
+                    ".dualstack"
+                } else {
+                    ""
+                },
+                self.region
+            )
+        };
+        format!("{endpoint}/{}/{}", self.bucket, encode_key(&self.key))
+    }
+}
+
+/// Percent-encodes the object key except the unreserved characters and "/".
+pub fn encode_key(key: &str) -> String {
+    let mut s = String::new();
+    for b in key.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                s.push(b as char)
+            }
+            _ => s.push_str(&format!("%{b:02X}")),
+        }
+    }
+    s
+}
+
+fn s3_settings() -> SigningSettings {
+    let mut settings = SigningSettings::default();
+    settings.percent_encoding_mode = PercentEncodingMode::Single;
+    settings.payload_checksum_kind = PayloadChecksumKind::XAmzSha256;
+    settings.uri_path_normalization_mode = UriPathNormalizationMode::Disabled;
+    settings
+}
+
+/// Returns the document and its ETag, or "None" if the object does not exist yet.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/API/API_GetObject.html>
+pub async fn get(
+    shared_config: &SdkConfig,
+    opts: &sdk::Options,
+    object: &Object,
+) -> io::Result<Option<(Document, String)>> {
+    let uri = object.uri(opts);
+    let request = http::Request::builder()
+        .method(Method::GET)
+        .uri(&uri)
+        .body(Vec::new())
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to build GET {uri} {}", e)))?;
+    let (status, headers, body) =
+        sdk::send_signed(shared_config, &object.region, "s3", request, s3_settings()).await?;
+    if status == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if status != StatusCode::OK {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed GET {uri} {status} '{}'",
+                String::from_utf8_lossy(&body)
+            ),
+        ));
+    }
+    let etag = headers
+        .get("etag")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let doc = serde_json::from_slice(&body).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid peers file {uri} ({})", e),
+        )
+    })?;
+    Ok(Some((doc, etag)))
+}
+
+/// Writes the document only if the object is still at the ETag (or still does
+/// not exist if "None"), and returns false if another node wrote it in the meantime.
+/// ref. <https://docs.aws.amazon.com/AmazonS3/latest/userguide/conditional-writes.html>
+pub async fn put(
+    shared_config: &SdkConfig,
+    opts: &sdk::Options,
+    object: &Object,
+    doc: &Document,
+    etag: Option<&str>,
+) -> io::Result<bool> {
+    let uri = object.uri(opts);
+    let body = serde_json::to_vec_pretty(doc)?;
+    let builder = http::Request::builder()
+        .method(Method::PUT)
+        .uri(&uri)
+        .header("content-type", "application/json");
+    let builder = match etag {
+        Some(etag) => builder.header("if-match", etag),
+        None => builder.header("if-none-match", "*"),
+    };
+    let request = builder
+        .body(body)
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to build PUT {uri} {}", e)))?;
+    let (status, _, body) =
+        sdk::send_signed(shared_config, &object.region, "s3", request, s3_settings()).await?;
+    match status {
+        StatusCode::OK => Ok(true),
+        // 409 if another conditional write is in flight
+        StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT => Ok(false),
+        _ => Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed PUT {uri} {status} '{}'",
+                String::from_utf8_lossy(&body)
+            ),
+        )),
+    }
+}
+
+/// Publishes the peer with optimistic concurrency: reads the object, adds or
+/// replaces the peer, and writes it back conditionally, retrying from the read
+/// when another node wrote it in the meantime. Returns false if already published.
+pub async fn publish(
+    shared_config: &SdkConfig,
+    opts: &sdk::Options,
+    object: &Object,
+    peer: &Peer,
+) -> io::Result<bool> {
+    for attempt in 1..=MAX_PUBLISH_ATTEMPTS {
+        let (mut doc, etag) = match get(shared_config, opts, object).await? {
+            Some((doc, etag)) => (doc, Some(etag)),
+            None => (Document::default(), None),
+        };
+        if !doc.upsert(peer.clone()) {
+            log::info!("peer {} already published as {}", peer.id, peer.public_ip);
+            return Ok(false);
+        }
+        if put(shared_config, opts, object, &doc, etag.as_deref()).await? {
+            log::info!("published peer {} as {}", peer.id, peer.public_ip);
+            return Ok(true);
+        }
+        // jittered, so that the nodes booting together do not keep colliding
+        let backoff =
+            Duration::from_millis(100 * attempt as u64 + random_manager::u32() as u64 % 500);
+        log::warn!(
+            "peers file changed concurrently (attempt {attempt}/{MAX_PUBLISH_ATTEMPTS}), retrying in {backoff:?}"
+        );
+        sleep(backoff).await;
+    }
+    Err(Error::new(
+        ErrorKind::TimedOut,
+        format!(
+            "failed to publish peer {} after {MAX_PUBLISH_ATTEMPTS} concurrent updates",
+            peer.id
+        ),
+    ))
+}
@@ -0,0 +1,44 @@
+use std::{
+    future::{self, Future},
+    io,
+    pin::Pin,
+    task::Poll,
+};
+
+/// Runs the independent address operations (e.g., the EIP association and
+/// the IPv6 assignment of the instance) concurrently, with at most
+/// "max_parallel" in flight, so that the API calls of one operation do not
+/// wait for the others. Polled on the calling task rather than spawned, so
+/// that the operations may borrow (e.g., the provisioner).
+/// Returns the results in the order of the tasks, failing none for another's error.
+pub async fn run_bounded<T, F>(max_parallel: usize, tasks: Vec<F>) -> Vec<io::Result<T>>
+where
+    F: Future<Output = io::Result<T>>,
+{
+    let mut results: Vec<Option<io::Result<T>>> = tasks.iter().map(|_| None).collect();
+    let mut pending = tasks.into_iter().enumerate();
+    let mut in_flight: Vec<(usize, Pin<Box<F>>)> = Vec::new();
+    loop {
+        while in_flight.len() < max_parallel.max(1) {
+            match pending.next() {
+                Some((i, task)) => in_flight.push((i, Box::pin(task))),
+                None => break,
+            }
+        }
+        if in_flight.is_empty() {
+            break;
+        }
+        let (j, ret) = future::poll_fn(|cx| {
+            for (j, (_, task)) in in_flight.iter_mut().enumerate() {
+                if let Poll::Ready(ret) = task.as_mut().poll(cx) {
+                    return Poll::Ready((j, ret));
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+        let (i, _) = in_flight.swap_remove(j);
+        results[i] = Some(ret);
+    }
+    results.into_iter().flatten().collect()
+}
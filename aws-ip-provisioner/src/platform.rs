@@ -0,0 +1,63 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    process::Command,
+};
+
+/// Default "--mounted-eip-file-path".
+#[cfg(not(windows))]
+pub const DEFAULT_MOUNTED_EIP_FILE_PATH: &str = "/data/eip.yaml";
+#[cfg(windows)]
+pub const DEFAULT_MOUNTED_EIP_FILE_PATH: &str = r"C:\ProgramData\ip-manager\eip.yaml";
+
+/// Hosts file of "--update-etc-hosts".
+#[cfg(not(windows))]
+pub const HOSTS_FILE: &str = "/etc/hosts";
+#[cfg(windows)]
+pub const HOSTS_FILE: &str = r"C:\Windows\System32\drivers\etc\hosts";
+
+/// Signal to reload the config file in "daemon" and "watch" modes:
+/// SIGHUP, or Ctrl+Break on Windows, which has no SIGHUP
+/// (e.g., "windows-kill -SIGBREAK <pid>").
+pub struct Reload {
+    #[cfg(unix)]
+    inner: tokio::signal::unix::Signal,
+    #[cfg(windows)]
+    inner: tokio::signal::windows::CtrlBreak,
+}
+
+impl Reload {
+    pub fn new() -> io::Result<Self> {
+        #[cfg(unix)]
+        let inner = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+        #[cfg(windows)]
+        let inner = tokio::signal::windows::ctrl_break()?;
+        Ok(Self { inner })
+    }
+
+    /// Waits for the next signal.
+    pub async fn recv(&mut self) -> Option<()> {
+        self.inner.recv().await
+    }
+}
+
+/// Runs the PowerShell script non-interactively, returning its stdout.
+pub fn powershell(script: &str) -> io::Result<String> {
+    let out = Command::new("powershell.exe")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .output()?;
+    if !out.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed PowerShell '{script}' {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+/// Quotes the value as a PowerShell single-quoted string (no expansion).
+pub fn powershell_quote(v: &str) -> String {
+    format!("'{}'", v.replace('\'', "''"))
+}
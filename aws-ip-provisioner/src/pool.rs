@@ -0,0 +1,329 @@
+use std::io;
+
+use aws_sdk_ec2::model::Address;
+
+use tokio::time::Duration;
+
+use crate::{
+    command::Flags,
+    conflict, ec2, eip,
+    provisioner::{Clock, Ec2},
+};
+
+/// Tag key that marks whether a tool-managed EIP is free for reuse.
+pub const STATUS_TAG_KEY: &str = "PoolStatus";
+/// Tag value for an EIP that has been handed back to the pool.
+pub const STATUS_AVAILABLE: &str = "available";
+/// Tag value for an EIP that is claimed by a live instance.
+pub const STATUS_CLAIMED: &str = "claimed";
+
+/// Tag key of the instance that holds the lease of the claimed EIP.
+pub const CLAIMED_BY_TAG_KEY: &str = "ClaimedBy";
+/// Tag key of the unix timestamp in seconds when the lease of the claimed EIP
+/// expires unless renewed. Empty if the claim never expires.
+pub const CLAIM_EXPIRES_AT_TAG_KEY: &str = "ClaimExpiresAt";
+
+/// Wait between tagging the claimed EIP and reading the tags back, so that the
+/// tags of a concurrent claimant (written last, and so the winner) show up.
+pub const CLAIM_SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+/// Gauge of the unassociated pool-available EIPs, as of the last autoscaling.
+pub const FREE_GAUGE: &str = "pool_free_addresses";
+
+/// Lease of the pool claim ("--pool-lease-seconds").
+pub struct Lease<'a> {
+    pub instance_id: &'a str,
+    /// Zero for the claims that never expire.
+    pub seconds: u64,
+    /// Current unix timestamp in seconds.
+    pub now: u64,
+}
+
+impl Lease<'_> {
+    /// Returns the lease tags to set on claim or renewal.
+    fn tags(&self) -> Vec<(String, String)> {
+        if self.seconds == 0 {
+            return Vec::new();
+        }
+        vec![
+            (CLAIMED_BY_TAG_KEY.to_string(), self.instance_id.to_string()),
+            (
+                CLAIM_EXPIRES_AT_TAG_KEY.to_string(),
+                (self.now + self.seconds).to_string(),
+            ),
+        ]
+    }
+}
+
+/// Returns true if the lease of the claimed EIP expired, so that the claimant
+/// is gone (e.g., crashed before associating). Never for the claims without
+/// the expiry (e.g., before the lease was enabled).
+pub fn is_claim_expired(addr: &Address, now: u64) -> bool {
+    addr.tags()
+        .unwrap_or_default()
+        .iter()
+        .find(|t| t.key() == Some(CLAIM_EXPIRES_AT_TAG_KEY))
+        .and_then(|t| t.value())
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|expires_at| expires_at <= now)
+        .unwrap_or(false)
+}
+
+/// Finds an unassociated pool-available EIP with the matching "Kind" tag,
+/// and claims it by re-tagging with the new "Id" tag value and the lease.
+/// The unassociated claimed EIPs whose lease expired are reclaimed as available,
+/// while the live claimants keep renewing theirs (or hold the association).
+/// If multiple addresses are available, the conflict policy picks one.
+/// The tags have no compare-and-swap, so the claim is read back after
+/// "CLAIM_SETTLE_DELAY": if another instance tagged the EIP last, it is
+/// left to that instance and the next available one is claimed instead.
+/// Returns "None" if the pool has no available address (left).
+pub async fn claim(
+    ec2: &dyn Ec2,
+    clock: &dyn Clock,
+    opts: &Flags,
+    lease: &Lease<'_>,
+) -> io::Result<Option<ec2::Eip>> {
+    let (kind_tag_key, kind_tag_value) = (opts.kind_tag_key.as_str(), opts.kind_tag_value.as_str());
+    // both filtered by the tags on the server side, and described concurrently
+    let available_tags = [
+        (kind_tag_key, kind_tag_value),
+        (STATUS_TAG_KEY, STATUS_AVAILABLE),
+    ];
+    let claimed_tags = [
+        (kind_tag_key, kind_tag_value),
+        (STATUS_TAG_KEY, STATUS_CLAIMED),
+    ];
+    let (mut addrs, claimed) = tokio::try_join!(
+        ec2.describe_by_tags(&available_tags),
+        ec2.describe_by_tags(&claimed_tags)
+    )?;
+    for addr in claimed {
+        if addr.association_id.is_none() && is_claim_expired(&addr, lease.now) {
+            log::info!("reclaiming pool EIP {:?} -- claim expired", addr.public_ip);
+            addrs.push(addr);
+        }
+    }
+    let mut addrs: Vec<_> = addrs
+        .into_iter()
+        .filter(|addr| {
+            if addr.association_id.is_some() {
+                log::info!(
+                    "skipping pool EIP {:?} -- already associated",
+                    addr.public_ip
+                );
+                return false;
+            }
+            true
+        })
+        .collect();
+
+    while let Some(addr) = conflict::select(addrs.clone(), &opts.conflict_policy)? {
+        let allocation_id = addr.allocation_id.to_owned().unwrap_or_default();
+        let public_ip = addr.public_ip.to_owned().unwrap_or_default();
+        log::info!("claiming pool EIP {public_ip} (allocation ID {allocation_id})");
+
+        let mut tags = vec![
+            (String::from("Name"), opts.id_tag_value.clone()),
+            (opts.id_tag_key.clone(), opts.id_tag_value.clone()),
+            (STATUS_TAG_KEY.to_string(), STATUS_CLAIMED.to_string()),
+        ];
+        if lease.seconds > 0 {
+            tags.extend(lease.tags());
+        } else {
+            tags.push((
+                CLAIMED_BY_TAG_KEY.to_string(),
+                lease.instance_id.to_string(),
+            ));
+            if is_claim_expired(&addr, lease.now) {
+                // reclaimed without the lease, so that it never expires again
+                tags.push((CLAIM_EXPIRES_AT_TAG_KEY.to_string(), String::new()));
+            }
+        }
+        ec2.create_tags(&allocation_id, tags).await?;
+
+        clock.sleep(CLAIM_SETTLE_DELAY).await?;
+        let claimed_by = ec2
+            .describe_by_allocation_id(&allocation_id)
+            .await?
+            .and_then(|addr| {
+                addr.tags()
+                    .unwrap_or_default()
+                    .iter()
+                    .find(|t| t.key() == Some(CLAIMED_BY_TAG_KEY))
+                    .and_then(|t| t.value())
+                    .map(|v| v.to_string())
+            })
+            .unwrap_or_default();
+        if claimed_by == lease.instance_id {
+            return Ok(Some(ec2::Eip {
+                allocation_id,
+                public_ip,
+            }));
+        }
+        log::warn!(
+            "pool EIP {public_ip} was claimed by '{claimed_by}' at the same time -- trying the next one"
+        );
+        addrs.retain(|a| a.allocation_id != addr.allocation_id);
+    }
+
+    log::info!("no available EIP found in the pool for {kind_tag_key}:{kind_tag_value}");
+    Ok(None)
+}
+
+/// Extends the lease of the EIP claimed by the instance (e.g., in daemon mode),
+/// so that it is not reclaimed. Returns false without renewing if the EIP is
+/// not a pool claim of the instance (e.g., allocated, or reclaimed by another
+/// after the lease expired).
+pub async fn renew(ec2: &dyn Ec2, allocation_id: &str, lease: &Lease<'_>) -> io::Result<bool> {
+    if lease.seconds == 0 {
+        return Ok(false);
+    }
+    let addr = match ec2.describe_by_allocation_id(allocation_id).await? {
+        Some(addr) => addr,
+        None => return Ok(false),
+    };
+    let tag = |key: &str| -> &str {
+        addr.tags()
+            .unwrap_or_default()
+            .iter()
+            .find(|t| t.key() == Some(key))
+            .and_then(|t| t.value())
+            .unwrap_or_default()
+    };
+    if tag(STATUS_TAG_KEY) != STATUS_CLAIMED {
+        return Ok(false);
+    }
+    if tag(CLAIMED_BY_TAG_KEY) != lease.instance_id {
+        log::warn!(
+            "pool EIP {allocation_id} is claimed by '{}', not {} -- not renewing",
+            tag(CLAIMED_BY_TAG_KEY),
+            lease.instance_id
+        );
+        return Ok(false);
+    }
+    ec2.create_tags(allocation_id, lease.tags()).await?;
+    log::info!(
+        "renewed the lease of pool EIP {allocation_id} for {} seconds",
+        lease.seconds
+    );
+    Ok(true)
+}
+
+/// Result of "autoscale".
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Scaled {
+    /// Free addresses before the scaling.
+    pub free: usize,
+    pub allocated: usize,
+    pub released: usize,
+}
+
+/// Keeps the unassociated pool-available EIPs of the "Kind" between
+/// "--pool-min-free" and "--pool-max-free" (zero for no upper bound),
+/// so that the scale-out claims from the pool rather than waiting on AllocateAddress.
+/// Releases the newest of the excess, keeping the long-lived addresses
+/// (e.g., allowlisted by the peers). Stops allocating at the EIP quota.
+pub async fn autoscale(ec2: &dyn Ec2, opts: &Flags, now: u64) -> io::Result<Scaled> {
+    let mut free: Vec<Address> = ec2
+        .describe_by_tags(&[
+            (&opts.kind_tag_key, &opts.kind_tag_value),
+            (STATUS_TAG_KEY, STATUS_AVAILABLE),
+        ])
+        .await?
+        .into_iter()
+        .filter(|addr| addr.association_id.is_none())
+        .collect();
+    let mut scaled = Scaled {
+        free: free.len(),
+        ..Default::default()
+    };
+    let (min_free, max_free) = (opts.pool_min_free as usize, opts.pool_max_free as usize);
+
+    for i in free.len()..min_free {
+        // unique per scaling, as the pre-allocated ones have no "Id" yet
+        let token = eip::client_token(&opts.kind_tag_value, &format!("pool/{now}/{i}"));
+        let eip = match ec2
+            .allocate(
+                (&opts.id_tag_key, ""),
+                (&opts.kind_tag_key, &opts.kind_tag_value),
+                &token,
+                &opts.public_ipv4_pool,
+            )
+            .await
+        {
+            Ok(eip) => eip,
+            Err(e) if e.to_string().contains("AddressLimitExceeded") => {
+                log::warn!(
+                    "EIP quota reached with {} free in the pool (min {min_free}) '{}'",
+                    free.len() + scaled.allocated,
+                    e
+                );
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        ec2.create_tags(
+            &eip.allocation_id,
+            vec![
+                (STATUS_TAG_KEY.to_string(), STATUS_AVAILABLE.to_string()),
+                (conflict::ALLOCATED_AT_TAG_KEY.to_string(), now.to_string()),
+            ],
+        )
+        .await?;
+        log::info!("pre-allocated EIP {} to the pool", eip.public_ip);
+        scaled.allocated += 1;
+    }
+
+    if max_free > 0 && free.len() > max_free {
+        free.sort_by_key(|addr| (conflict::allocated_at(addr), addr.allocation_id.clone()));
+        for addr in free.iter().rev().take(free.len() - max_free) {
+            let allocation_id = addr.allocation_id().unwrap_or_default();
+            // re-described, as a scale-out may have claimed it since the listing
+            match ec2.describe_by_allocation_id(allocation_id).await? {
+                Some(current)
+                    if current.association_id().is_none()
+                        && current.tags().unwrap_or_default().iter().any(|t| {
+                            t.key() == Some(STATUS_TAG_KEY) && t.value() == Some(STATUS_AVAILABLE)
+                        }) => {}
+                _ => {
+                    log::info!("not releasing EIP {allocation_id}, no longer free in the pool");
+                    continue;
+                }
+            }
+            ec2.release_address(allocation_id).await?;
+            log::info!(
+                "released EIP {} in excess of the pool (max {max_free})",
+                addr.public_ip().unwrap_or_default()
+            );
+            scaled.released += 1;
+        }
+    }
+    Ok(scaled)
+}
+
+/// Disassociates the EIP from the instance (if associated) and tags it as
+/// pool-available, without releasing the address.
+pub async fn release(ec2: &dyn Ec2, eip: &ec2::Eip) -> io::Result<()> {
+    if let Some(addr) = ec2.describe_by_allocation_id(&eip.allocation_id).await? {
+        if let Some(association_id) = addr.association_id() {
+            log::info!(
+                "disassociating EIP {} (association ID {association_id})",
+                eip.public_ip
+            );
+            ec2.disassociate(&eip.allocation_id, association_id).await?;
+        }
+    }
+
+    ec2.create_tags(
+        &eip.allocation_id,
+        vec![
+            (STATUS_TAG_KEY.to_string(), STATUS_AVAILABLE.to_string()),
+            (CLAIMED_BY_TAG_KEY.to_string(), String::new()),
+            (CLAIM_EXPIRES_AT_TAG_KEY.to_string(), String::new()),
+        ],
+    )
+    .await?;
+    log::info!("returned EIP {} to the pool", eip.public_ip);
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Error, ErrorKind},
+};
+
+use aws_sdk_ec2::model::{AddPrefixListEntry, PrefixListState, RemovePrefixListEntry};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::ec2;
+
+/// Prefix of the descriptions of the entries that this tool manages,
+/// so that the entries added by hand are never removed.
+pub const DESCRIPTION_PREFIX: &str = "ip-manager:";
+
+/// Most entries to add (and to remove) in one ModifyManagedPrefixList.
+pub const MAX_ENTRIES_PER_MODIFY: usize = 100;
+
+/// How long to wait for the previous modification to complete.
+const MODIFY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Entry of the managed prefix list.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Entry {
+    /// e.g., "203.0.113.10/32"
+    pub cidr: String,
+    pub description: String,
+}
+
+impl Entry {
+    /// Returns the entry of the EIP, described by its "Id" tag value.
+    pub fn for_eip(public_ip: &str, id: &str) -> Self {
+        Self {
+            cidr: format!("{public_ip}/32"),
+            description: format!("{DESCRIPTION_PREFIX}{id}"),
+        }
+    }
+
+    pub fn is_managed(&self) -> bool {
+        self.description.starts_with(DESCRIPTION_PREFIX)
+    }
+}
+
+/// Changes to make the prefix list match the EIPs.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub add: Vec<Entry>,
+    /// CIDRs of the managed entries to remove.
+    pub remove: Vec<String>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.remove.is_empty()
+    }
+}
+
+/// Returns the entries to add (desired, not in the list) and the managed
+/// entries to remove (not desired). The CIDRs already in the list with
+/// another description are left alone, since the list rejects duplicates.
+pub fn plan(current: &[Entry], desired: &[Entry]) -> Plan {
+    let existing: BTreeSet<&str> = current.iter().map(|e| e.cidr.as_str()).collect();
+    let wanted: BTreeSet<&str> = desired.iter().map(|e| e.cidr.as_str()).collect();
+
+    let mut add: Vec<Entry> = desired
+        .iter()
+        .filter(|e| !existing.contains(e.cidr.as_str()))
+        .cloned()
+        .collect();
+    add.sort();
+    add.dedup_by(|a, b| a.cidr == b.cidr);
+    let mut remove: Vec<String> = current
+        .iter()
+        .filter(|e| e.is_managed() && !wanted.contains(e.cidr.as_str()))
+        .map(|e| e.cidr.clone())
+        .collect();
+    remove.sort();
+    Plan { add, remove }
+}
+
+/// Version, capacity, and state of the managed prefix list.
+#[derive(Debug)]
+pub struct PrefixList {
+    pub version: i64,
+    pub max_entries: i32,
+    pub state: Option<PrefixListState>,
+}
+
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeManagedPrefixLists.html>
+pub async fn describe(ec2_manager: &ec2::Manager, prefix_list_id: &str) -> io::Result<PrefixList> {
+    let resp = ec2_manager
+        .client()
+        .describe_managed_prefix_lists()
+        .prefix_list_ids(prefix_list_id)
+        .send()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed describe_managed_prefix_lists {:?}", e),
+            )
+        })?;
+    let pl = resp
+        .prefix_lists()
+        .unwrap_or_default()
+        .first()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("prefix list {prefix_list_id} not found"),
+            )
+        })?;
+    if pl.address_family() != Some("IPv4") {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "prefix list {prefix_list_id} is {:?}, not IPv4",
+                pl.address_family()
+            ),
+        ));
+    }
+    Ok(PrefixList {
+        version: pl.version().unwrap_or_default(),
+        max_entries: pl.max_entries().unwrap_or_default(),
+        state: pl.state().cloned(),
+    })
+}
+
+/// Returns all the entries, following "NextToken".
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_GetManagedPrefixListEntries.html>
+pub async fn entries(ec2_manager: &ec2::Manager, prefix_list_id: &str) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut next_token: Option<String> = None;
+    loop {
+        let resp = ec2_manager
+            .client()
+            .get_managed_prefix_list_entries()
+            .prefix_list_id(prefix_list_id)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed get_managed_prefix_list_entries {:?}", e),
+                )
+            })?;
+        for e in resp.entries().unwrap_or_default() {
+            entries.push(Entry {
+                cidr: e.cidr().unwrap_or_default().to_string(),
+                description: e.description().unwrap_or_default().to_string(),
+            });
+        }
+        next_token = resp.next_token().map(|v| v.to_string());
+        if next_token.is_none() {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+/// Makes the managed entries of the prefix list match the desired entries,
+/// in batches of "MAX_ENTRIES_PER_MODIFY", and returns what changed.
+/// Fails before any change if the list cannot hold the entries.
+pub async fn sync(
+    ec2_manager: &ec2::Manager,
+    prefix_list_id: &str,
+    desired: &[Entry],
+) -> io::Result<Plan> {
+    let current = entries(ec2_manager, prefix_list_id).await?;
+    let plan = plan(&current, desired);
+    if plan.is_empty() {
+        log::info!("prefix list {prefix_list_id} is up to date");
+        return Ok(plan);
+    }
+
+    let pl = wait_for_complete(ec2_manager, prefix_list_id).await?;
+    let after = current.len() + plan.add.len() - plan.remove.len();
+    if after > pl.max_entries.max(0) as usize {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "prefix list {prefix_list_id} holds at most {} entries, but needs {after} (resize it with 'aws ec2 modify-managed-prefix-list --max-entries')",
+                pl.max_entries
+            ),
+        ));
+    }
+
+    let (mut add, mut remove) = (plan.add.as_slice(), plan.remove.as_slice());
+    let mut version = pl.version;
+    while !add.is_empty() || !remove.is_empty() {
+        let (add_batch, add_rest) = add.split_at(add.len().min(MAX_ENTRIES_PER_MODIFY));
+        let (remove_batch, remove_rest) = remove.split_at(remove.len().min(MAX_ENTRIES_PER_MODIFY));
+        log::info!(
+            "modifying prefix list {prefix_list_id} version {version} (adding {}, removing {})",
+            add_batch.len(),
+            remove_batch.len()
+        );
+        ec2_manager
+            .client()
+            .modify_managed_prefix_list()
+            .prefix_list_id(prefix_list_id)
+            .current_version(version)
+            .set_add_entries(if add_batch.is_empty() {
+                None
+            } else {
+                Some(
+                    add_batch
+                        .iter()
+                        .map(|e| {
+                            AddPrefixListEntry::builder()
+                                .cidr(&e.cidr)
+                                .description(&e.description)
+                                .build()
+                        })
+                        .collect(),
+                )
+            })
+            .set_remove_entries(if remove_batch.is_empty() {
+                None
+            } else {
+                Some(
+                    remove_batch
+                        .iter()
+                        .map(|cidr| RemovePrefixListEntry::builder().cidr(cidr).build())
+                        .collect(),
+                )
+            })
+            .send()
+            .await
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed modify_managed_prefix_list {:?}", e),
+                )
+            })?;
+        (add, remove) = (add_rest, remove_rest);
+        if !add.is_empty() || !remove.is_empty() {
+            version = wait_for_complete(ec2_manager, prefix_list_id)
+                .await?
+                .version;
+        }
+    }
+    Ok(plan)
+}
+
+/// Waits until no modification is in progress, since the list rejects
+/// the concurrent modifications.
+async fn wait_for_complete(
+    ec2_manager: &ec2::Manager,
+    prefix_list_id: &str,
+) -> io::Result<PrefixList> {
+    let started = Instant::now();
+    loop {
+        let pl = describe(ec2_manager, prefix_list_id).await?;
+        match &pl.state {
+            Some(PrefixListState::CreateComplete)
+            | Some(PrefixListState::ModifyComplete)
+            | Some(PrefixListState::RestoreComplete) => return Ok(pl),
+            Some(PrefixListState::CreateInProgress)
+            | Some(PrefixListState::ModifyInProgress)
+            | Some(PrefixListState::RestoreInProgress) => {}
+            state => {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("prefix list {prefix_list_id} is in state {:?}", state),
+                ))
+            }
+        }
+        if started.elapsed() > MODIFY_TIMEOUT {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "prefix list {prefix_list_id} still being modified after {MODIFY_TIMEOUT:?}"
+                ),
+            ));
+        }
+        sleep(Duration::from_secs(2)).await;
+    }
+}
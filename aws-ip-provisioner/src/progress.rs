@@ -0,0 +1,74 @@
+use std::{
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use crate::{context::Context, notify};
+
+pub const STARTED: &str = "started";
+pub const IMDS_OK: &str = "imds_ok";
+pub const ALLOCATED: &str = "allocated";
+pub const ASSOCIATED: &str = "associated";
+pub const RELEASED: &str = "released";
+pub const DNS_UPDATED: &str = "dns_updated";
+pub const DNS_VERIFIED: &str = "dns_verified";
+pub const REACHABILITY_VERIFIED: &str = "reachability_verified";
+pub const WIREGUARD_UPDATED: &str = "wireguard_updated";
+pub const POOL_SCALED: &str = "pool_scaled";
+pub const DONE: &str = "done";
+pub const FAILED: &str = "failed";
+
+/// All events, e.g., to validate the notification subscriptions.
+pub const EVENTS: &[&str] = &[
+    STARTED,
+    IMDS_OK,
+    ALLOCATED,
+    ASSOCIATED,
+    RELEASED,
+    DNS_UPDATED,
+    DNS_VERIFIED,
+    REACHABILITY_VERIFIED,
+    WIREGUARD_UPDATED,
+    POOL_SCALED,
+    DONE,
+    FAILED,
+];
+
+/// Progress format of the run ("none" to disable, "ndjson" for one JSON event per line).
+#[derive(Debug, Default)]
+pub struct Progress {
+    format: Mutex<String>,
+}
+
+impl Progress {
+    /// Sets the progress format.
+    pub fn init(&self, format: &str) {
+        *self.format.lock().unwrap() = format.to_string();
+    }
+
+    /// Returns true if the progress events are written to stdout,
+    /// in which case nothing else should be.
+    pub fn enabled(&self) -> bool {
+        *self.format.lock().unwrap() == "ndjson"
+    }
+}
+
+/// Writes the phase transition event with the fields to stdout
+/// (e.g., {"event":"allocated","ts":1673000000,"allocation_id":"eipalloc-..."}),
+/// and to the subscribed notification sinks regardless of the format.
+pub fn emit(ctx: &Context, event: &str, fields: &[(&str, &str)]) {
+    ctx.notify.dispatch(event, fields);
+    if !ctx.progress.enabled() {
+        return;
+    }
+
+    // single write per line, so concurrent events are not interleaved
+    let line = format!("{}\n", notify::Event::new(event, fields).payload());
+    let mut stdout = io::stdout().lock();
+    if let Err(e) = stdout
+        .write_all(line.as_bytes())
+        .and_then(|_| stdout.flush())
+    {
+        log::warn!("failed to write progress event {event} '{}'", e);
+    }
+}
@@ -0,0 +1,1232 @@
+use std::{
+    future::Future,
+    io::{self, Error, ErrorKind},
+    path::Path,
+    pin::Pin,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_sdk_ec2::model::{Address, NetworkInterface};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::{
+    command::Flags, conflict, context::Context, ec2, eip, identity, imds, imds::Imds, lifecycle,
+    pool, progress, state,
+};
+
+/// Interval to check if the instance is ready to associate.
+const READY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// First wait to verify the association, doubled on every attempt.
+const VERIFY_POLL_INITIAL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Describes to verify the association before giving up
+/// (waiting up to 7.75 seconds in total).
+const VERIFY_POLL_ATTEMPTS: u32 = 6;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// EC2 API calls that the provisioner makes, so that the decisions
+/// can be tested without AWS.
+pub trait Ec2: Send + Sync {
+    /// Describes the EIPs with all the tags matching.
+    fn describe_by_tags<'a>(
+        &'a self,
+        tags: &'a [(&'a str, &'a str)],
+    ) -> BoxFuture<'a, Vec<Address>>;
+
+    /// Describes the EIP by its allocation ID, "None" if it does not exist.
+    fn describe_by_allocation_id<'a>(
+        &'a self,
+        allocation_id: &'a str,
+    ) -> BoxFuture<'a, Option<Address>>;
+
+    /// Returns true if the instance is in "running" state.
+    fn is_instance_running<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, bool>;
+
+    /// Returns true if the instance is running and its network interface is attached.
+    fn is_ready_to_associate<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, bool>;
+
+    /// Describes the network interfaces attached to the instance.
+    fn describe_network_interfaces<'a>(
+        &'a self,
+        instance_id: &'a str,
+    ) -> BoxFuture<'a, Vec<NetworkInterface>>;
+
+    /// Assigns an IPv6 address to the network interface, returning the address.
+    fn assign_ipv6_address<'a>(&'a self, network_interface_id: &'a str) -> BoxFuture<'a, String>;
+
+    /// Allocates a new EIP with the "Id", "Kind", and "ClientToken" tags,
+    /// from the public IPv4 pool if not empty.
+    fn allocate<'a>(
+        &'a self,
+        id_tag: (&'a str, &'a str),
+        kind_tag: (&'a str, &'a str),
+        client_token: &'a str,
+        public_ipv4_pool: &'a str,
+    ) -> BoxFuture<'a, ec2::Eip>;
+
+    /// Creates (or overwrites) the tags on the EIP.
+    fn create_tags<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        tags: Vec<(String, String)>,
+    ) -> BoxFuture<'a, ()>;
+
+    /// Deletes the tags of the keys from the EIP, whatever their values.
+    fn delete_tags<'a>(&'a self, allocation_id: &'a str, keys: Vec<String>) -> BoxFuture<'a, ()>;
+
+    /// Releases the EIP back to AWS (e.g., the excess of the pool).
+    fn release_address<'a>(&'a self, allocation_id: &'a str) -> BoxFuture<'a, ()>;
+
+    /// Disassociates the EIP by the association ID.
+    fn disassociate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        association_id: &'a str,
+    ) -> BoxFuture<'a, ()>;
+
+    /// Associates the EIP with the target network interface of the instance.
+    /// With "allow_reassociation", takes it over from another resource.
+    /// Returns the association ID.
+    fn associate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        instance_id: &'a str,
+        target: &'a eip::Target,
+        allow_reassociation: bool,
+    ) -> BoxFuture<'a, String>;
+}
+
+/// Instance metadata that the provisioner reads.
+pub trait Metadata: Send + Sync {
+    /// Returns the ID of the local instance.
+    fn instance_id(&self) -> BoxFuture<'_, String>;
+
+    /// Returns the public IPv4 of the local instance, or "None" if it has none.
+    fn public_ipv4(&self) -> BoxFuture<'_, Option<String>>;
+
+    /// Returns the primary private IPv4 of the local instance,
+    /// or "None" if it has none (e.g., in an IPv6-only subnet).
+    fn local_ipv4(&self) -> BoxFuture<'_, Option<String>>;
+
+    /// Returns the sorted IDs of the live instances in the auto scaling group
+    /// of the instance, empty if not in a group.
+    fn asg_members<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, Vec<String>>;
+
+    /// Returns the account ID and the region of the instance.
+    fn account_and_region(&self) -> BoxFuture<'_, (String, String)>;
+}
+
+/// Source of the current time, and of the waits.
+pub trait Clock: Send + Sync {
+    /// Returns the current unix timestamp in seconds.
+    fn now_unix_seconds(&self) -> u64;
+
+    fn sleep(&self, d: Duration) -> BoxFuture<'_, ()>;
+}
+
+/// Source of the random numbers (e.g., the initial wait).
+pub trait Rng: Send + Sync {
+    fn u32(&self) -> u32;
+}
+
+/// EC2 API calls with the AWS SDK, recording the mutating calls
+/// in the audit log of the run.
+pub struct AwsEc2<'a> {
+    pub ec2_manager: &'a ec2::Manager,
+    pub ctx: &'a Context,
+}
+
+impl Ec2 for AwsEc2<'_> {
+    fn describe_by_tags<'a>(
+        &'a self,
+        tags: &'a [(&'a str, &'a str)],
+    ) -> BoxFuture<'a, Vec<Address>> {
+        Box::pin(eip::describe_by_tags(self.ec2_manager, tags))
+    }
+
+    fn describe_by_allocation_id<'a>(
+        &'a self,
+        allocation_id: &'a str,
+    ) -> BoxFuture<'a, Option<Address>> {
+        Box::pin(eip::describe_by_allocation_id(
+            self.ec2_manager,
+            allocation_id,
+        ))
+    }
+
+    fn is_instance_running<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(eip::is_instance_running(self.ec2_manager, instance_id))
+    }
+
+    fn is_ready_to_associate<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, bool> {
+        Box::pin(eip::is_ready_to_associate(self.ec2_manager, instance_id))
+    }
+
+    fn describe_network_interfaces<'a>(
+        &'a self,
+        instance_id: &'a str,
+    ) -> BoxFuture<'a, Vec<NetworkInterface>> {
+        Box::pin(eip::describe_network_interfaces(
+            self.ec2_manager,
+            instance_id,
+        ))
+    }
+
+    fn assign_ipv6_address<'a>(&'a self, network_interface_id: &'a str) -> BoxFuture<'a, String> {
+        Box::pin(eip::assign_ipv6_address(
+            self.ec2_manager,
+            network_interface_id,
+        ))
+    }
+
+    fn allocate<'a>(
+        &'a self,
+        id_tag: (&'a str, &'a str),
+        kind_tag: (&'a str, &'a str),
+        client_token: &'a str,
+        public_ipv4_pool: &'a str,
+    ) -> BoxFuture<'a, ec2::Eip> {
+        Box::pin(async move {
+            let ret = eip::allocate(
+                self.ec2_manager,
+                &[
+                    ("Name", id_tag.1),
+                    id_tag,
+                    kind_tag,
+                    (eip::CLIENT_TOKEN_TAG_KEY, client_token),
+                ],
+                public_ipv4_pool,
+            )
+            .await;
+            let (allocation_id, public_ip) = match &ret {
+                Ok(eip) => (eip.allocation_id.as_str(), eip.public_ip.as_str()),
+                Err(_) => ("", ""),
+            };
+            self.ctx.audit.record(
+                "allocate",
+                &[
+                    ("allocation_id", allocation_id),
+                    ("public_ip", public_ip),
+                    ("before", ""),
+                    ("after", ""),
+                ],
+                &ret,
+            )?;
+            ret
+        })
+    }
+
+    fn create_tags<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        tags: Vec<(String, String)>,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(eip::create_tags(
+            self.ctx,
+            self.ec2_manager,
+            allocation_id,
+            tags,
+        ))
+    }
+
+    fn delete_tags<'a>(&'a self, allocation_id: &'a str, keys: Vec<String>) -> BoxFuture<'a, ()> {
+        Box::pin(eip::delete_tags(
+            self.ctx,
+            self.ec2_manager,
+            allocation_id,
+            keys,
+        ))
+    }
+
+    fn release_address<'a>(&'a self, allocation_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(eip::release_address(
+            self.ctx,
+            self.ec2_manager,
+            allocation_id,
+        ))
+    }
+
+    fn disassociate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        association_id: &'a str,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(eip::disassociate(
+            self.ctx,
+            self.ec2_manager,
+            allocation_id,
+            association_id,
+        ))
+    }
+
+    fn associate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        instance_id: &'a str,
+        target: &'a eip::Target,
+        allow_reassociation: bool,
+    ) -> BoxFuture<'a, String> {
+        Box::pin(async move {
+            if !target.network_interface_id.is_empty() {
+                return eip::associate_network_interface(
+                    self.ctx,
+                    self.ec2_manager,
+                    allocation_id,
+                    target,
+                    allow_reassociation,
+                )
+                .await;
+            }
+            if allow_reassociation {
+                return eip::reassociate(self.ctx, self.ec2_manager, allocation_id, instance_id)
+                    .await;
+            }
+            let before = self
+                .ctx
+                .audit
+                .association(self.ec2_manager, allocation_id)
+                .await;
+            let ret = self
+                .ec2_manager
+                .client()
+                .associate_address()
+                .allocation_id(allocation_id)
+                .instance_id(instance_id)
+                .send()
+                .await
+                .map_err(|e| {
+                    Error::new(
+                        ErrorKind::Other,
+                        format!("failed associate_address {:?}", e),
+                    )
+                });
+            self.ctx.audit.record(
+                "associate",
+                &[
+                    ("allocation_id", allocation_id),
+                    ("before", &before),
+                    ("after", instance_id),
+                ],
+                &ret,
+            )?;
+            Ok(ret?.association_id().unwrap_or_default().to_string())
+        })
+    }
+}
+
+/// Reads the instance ID from IMDS, falling back to DescribeInstances
+/// (see "imds::fetch_instance_id").
+pub struct ImdsMetadata<'a> {
+    pub imds: &'a Imds,
+    pub ec2_manager: &'a ec2::Manager,
+    pub fallback: String,
+    /// Adds the hop limit diagnostic to the error, if IMDS looks hop-limited.
+    pub hop_limited: bool,
+}
+
+impl Metadata for ImdsMetadata<'_> {
+    fn instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move {
+            match imds::fetch_instance_id(self.imds, self.ec2_manager, &self.fallback).await {
+                Ok(v) => Ok(v),
+                Err(e) if self.hop_limited => Err(Error::new(
+                    e.kind(),
+                    format!("{} ({})", e, imds::hop_limit_diagnostic()),
+                )),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn public_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async move {
+            match self.imds.fetch("public-ipv4").await {
+                Ok(v) => Ok(Some(v.trim().to_string())),
+                // no public IPv4 assigned
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn local_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async move {
+            match self.imds.fetch("local-ipv4").await {
+                Ok(v) if !v.trim().is_empty() => Ok(Some(v.trim().to_string())),
+                // IPv6-only
+                Ok(_) => Ok(None),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn asg_members<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, Vec<String>> {
+        Box::pin(lifecycle::asg_members(self.ec2_manager, instance_id))
+    }
+
+    fn account_and_region(&self) -> BoxFuture<'_, (String, String)> {
+        Box::pin(async move {
+            let doc = self
+                .imds
+                .fetch_dynamic("instance-identity/document")
+                .await?;
+            let doc = identity::parse(&doc)?;
+            Ok((doc.account_id, doc.region))
+        })
+    }
+}
+
+/// Wall clock, with the tokio timer.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_unix_seconds(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    }
+
+    fn sleep(&self, d: Duration) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            sleep(d).await;
+            Ok(())
+        })
+    }
+}
+
+/// Random numbers from "random-manager".
+pub struct SystemRng;
+
+impl Rng for SystemRng {
+    fn u32(&self) -> u32 {
+        random_manager::u32()
+    }
+}
+
+/// Provisions the EIP to the local instance: loads (or claims from the pool,
+/// or allocates) the EIP and associates it, with all the side effects
+/// (EC2, IMDS, time, randomness) behind the traits.
+/// The state file is the only direct I/O.
+pub struct Provisioner<'a> {
+    ctx: &'a Context,
+    opts: &'a Flags,
+    ec2: &'a dyn Ec2,
+    metadata: &'a dyn Metadata,
+    clock: &'a dyn Clock,
+    rng: &'a dyn Rng,
+}
+
+impl<'a> Provisioner<'a> {
+    pub fn new(
+        ctx: &'a Context,
+        opts: &'a Flags,
+        ec2: &'a dyn Ec2,
+        metadata: &'a dyn Metadata,
+        clock: &'a dyn Clock,
+        rng: &'a dyn Rng,
+    ) -> Self {
+        Self {
+            ctx,
+            opts,
+            ec2,
+            metadata,
+            clock,
+            rng,
+        }
+    }
+
+    /// Returns the ID of the local instance.
+    pub async fn instance_id(&self) -> io::Result<String> {
+        self.ctx
+            .timing
+            .measure("imds", self.metadata.instance_id())
+            .await
+    }
+
+    /// Returns the public IPv4 of the instance if it is not the EIP in the state file
+    /// (e.g., auto-assigned, or an EIP associated out of band), in which case
+    /// the instance needs no managed EIP.
+    pub async fn unmanaged_public_ip(&self) -> io::Result<Option<String>> {
+        let ip = match self.metadata.public_ipv4().await? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        if state::State::load(&self.opts.mounted_eip_file_path)?.public_ip == ip {
+            return Ok(None);
+        }
+        Ok(Some(ip))
+    }
+
+    /// Returns the IPv6 address to use instead of an EIP if the instance is IPv6-only
+    /// (no IPv4 to map an EIP to), "None" if the instance has an IPv4.
+    /// With "--ipv6-only=fail", fails with the guidance instead.
+    /// With "--ipv6-only=assign-ipv6", assigns an IPv6 address to the primary
+    /// network interface if it has no global one yet.
+    pub async fn ipv6_only(&self, ec2_instance_id: &str) -> io::Result<Option<String>> {
+        if self.metadata.local_ipv4().await?.is_some() {
+            return Ok(None);
+        }
+        if self.opts.ipv6_only != "assign-ipv6" {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "instance {ec2_instance_id} has no IPv4 (IPv6-only subnet?), and an EIP can only be associated with an IPv4 -- launch it in a dual-stack subnet, or set --ipv6-only=assign-ipv6 to skip the EIP and use the IPv6 address"
+                ),
+            ));
+        }
+
+        let ipv6 = self.ensure_ipv6(ec2_instance_id).await?;
+        log::info!(
+            "skipping the EIP for IPv6-only instance {ec2_instance_id} -- outbound IPv4 goes through NAT64/DNS64 if the subnet has them, and an egress-only internet gateway allows no inbound IPv6"
+        );
+        Ok(Some(ipv6))
+    }
+
+    /// Returns the global IPv6 address of the network interface that the EIP maps to
+    /// (the target network interface, or the primary one), assigning one if it has none.
+    pub async fn ensure_ipv6(&self, ec2_instance_id: &str) -> io::Result<String> {
+        let target = resolve_target(self.ec2, self.opts, ec2_instance_id).await?;
+        let enis = self
+            .ec2
+            .describe_network_interfaces(ec2_instance_id)
+            .await?;
+        let ni = enis
+            .iter()
+            .find(|ni| {
+                let attached = ni.attachment().and_then(|a| {
+                    if a.instance_id() == Some(ec2_instance_id) {
+                        a.device_index()
+                    } else {
+                        None
+                    }
+                });
+                if target.network_interface_id.is_empty() {
+                    attached == Some(0)
+                } else {
+                    attached.is_some()
+                        && ni.network_interface_id() == Some(target.network_interface_id.as_str())
+                }
+            })
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "no network interface {:?} is attached to {ec2_instance_id}",
+                        target.describe(ec2_instance_id)
+                    ),
+                )
+            })?;
+        let network_interface_id = ni.network_interface_id().unwrap_or_default();
+        if ni.deny_all_igw_traffic() == Some(true) {
+            log::warn!(
+                "network interface {network_interface_id} drops the inbound traffic from the internet gateway -- its IPv6 address is only reachable from the VPC, peered VPCs, and on-premises networks"
+            );
+        }
+        match eip::global_ipv6_address(ni) {
+            Some(v) => {
+                log::info!("network interface {network_interface_id} already has IPv6 {v}");
+                Ok(v)
+            }
+            None => self.ec2.assign_ipv6_address(network_interface_id).await,
+        }
+    }
+
+    /// Records the association, tags, and timestamps of the provisioned EIP
+    /// in the mounted EIP file (see "state::State"), as described after the association.
+    pub async fn record_state(&self, eip: &ec2::Eip) -> io::Result<state::State> {
+        let addr = self
+            .ctx
+            .timing
+            .measure(
+                "describe",
+                self.ec2.describe_by_allocation_id(&eip.allocation_id),
+            )
+            .await?
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("EIP {} not found", eip.allocation_id),
+                )
+            })?;
+        state::record(
+            &self.opts.mounted_eip_file_path,
+            &addr,
+            self.clock.now_unix_seconds(),
+        )
+    }
+
+    /// Sleeps for seconds up to "initial_wait_random_seconds",
+    /// so that instances launched together do not race for the same pool address.
+    /// The wait strategy picks the seconds:
+    /// - "random": at random (default)
+    /// - "hash-instance-id": by the hash of the instance ID, the same across restarts
+    /// - "ordinal-from-asg": by the position of the instance in its auto scaling group,
+    ///   evenly spread over the window (random if not in a group)
+    ///
+    /// Returns the seconds waited.
+    pub async fn initial_wait(&self, ec2_instance_id: &str) -> io::Result<u32> {
+        let window = self.opts.initial_wait_random_seconds;
+        let sleep_sec = if window == 0 {
+            0
+        } else {
+            match self.opts.wait_strategy.as_str() {
+                "hash-instance-id" => {
+                    (eip::fnv1a(ec2_instance_id.as_bytes()) % window as u64) as u32
+                }
+                "ordinal-from-asg" => {
+                    let members = self.metadata.asg_members(ec2_instance_id).await?;
+                    match members.iter().position(|id| id == ec2_instance_id) {
+                        Some(i) => (i as u64 * window as u64 / members.len() as u64) as u32,
+                        None => {
+                            log::warn!(
+                                "{ec2_instance_id} is not in an auto scaling group -- waiting at random"
+                            );
+                            self.rng.u32() % window
+                        }
+                    }
+                }
+                _ => self.rng.u32() % window,
+            }
+        };
+        if sleep_sec > 0 {
+            log::info!(
+                "waiting for {sleep_sec} seconds (strategy {})",
+                self.opts.wait_strategy
+            );
+            self.clock
+                .sleep(Duration::from_secs(sleep_sec as u64))
+                .await?;
+        } else {
+            log::info!("skipping random sleep...");
+        }
+        Ok(sleep_sec)
+    }
+
+    /// Finds the EIP already tagged with this "Id" and "Kind" when the state file
+    /// is missing (e.g., the volume was replaced, or a previous attempt crashed
+    /// before syncing the state file), so that the address is reused rather than leaked.
+    /// Skips the pool-available addresses (claimed via the pool instead),
+    /// and the ones associated with another running instance.
+    /// Prefers the one allocated with this instance's client token, then
+    /// resolves the rest with the conflict policy.
+    pub async fn recover_by_tags(&self, ec2_instance_id: &str) -> io::Result<Option<ec2::Eip>> {
+        let opts = self.opts;
+        let addrs = self
+            .ec2
+            .describe_by_tags(&[
+                (&opts.id_tag_key, &opts.id_tag_value),
+                (&opts.kind_tag_key, &opts.kind_tag_value),
+            ])
+            .await?;
+
+        let mut candidates = Vec::new();
+        for addr in addrs {
+            if has_tag(&addr, pool::STATUS_TAG_KEY, pool::STATUS_AVAILABLE) {
+                continue;
+            }
+            let other = addr.instance_id().filter(|other| *other != ec2_instance_id);
+            if let Some(other) = other {
+                if self.ec2.is_instance_running(other).await? {
+                    log::warn!(
+                        "skipping EIP {:?} with the same tags -- associated with running instance {other}",
+                        addr.public_ip()
+                    );
+                    continue;
+                }
+            } else if addr.instance_id().is_none() && addr.network_interface_id().is_some() {
+                log::warn!(
+                    "skipping EIP {:?} with the same tags -- associated with {:?}",
+                    addr.public_ip(),
+                    addr.network_interface_id()
+                );
+                continue;
+            }
+            candidates.push(addr);
+        }
+
+        let client_token = eip::client_token(ec2_instance_id, &opts.id_tag_value);
+        let addr = match candidates
+            .iter()
+            .position(|addr| has_tag(addr, eip::CLIENT_TOKEN_TAG_KEY, &client_token))
+        {
+            Some(i) => Some(candidates.swap_remove(i)),
+            None => conflict::select(candidates, &opts.conflict_policy)?,
+        };
+        let addr = match addr {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let eip = ec2::Eip {
+            allocation_id: addr.allocation_id().unwrap_or_default().to_string(),
+            public_ip: addr.public_ip().unwrap_or_default().to_string(),
+        };
+        log::info!(
+            "recovered EIP {} (allocation ID {}) by its {}:{} tags",
+            eip.public_ip,
+            eip.allocation_id,
+            opts.id_tag_key,
+            opts.id_tag_value
+        );
+        if !addr
+            .tags()
+            .unwrap_or_default()
+            .iter()
+            .any(|t| t.key() == Some(conflict::ALLOCATED_AT_TAG_KEY))
+        {
+            self.tag_allocated_at(&eip.allocation_id).await?;
+        }
+        Ok(Some(eip))
+    }
+
+    /// Waits until the instance is running and its network interface is attached,
+    /// up to "associate_ready_timeout_seconds" (zero to skip the wait).
+    async fn wait_ready(&self, ec2_instance_id: &str) -> io::Result<()> {
+        let timeout = self.opts.associate_ready_timeout_seconds as u64;
+        if timeout == 0 {
+            return Ok(());
+        }
+        let mut waited = 0;
+        loop {
+            if self.ec2.is_ready_to_associate(ec2_instance_id).await? {
+                return Ok(());
+            }
+            if waited >= timeout {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("{ec2_instance_id} not ready to associate after {timeout} seconds"),
+                ));
+            }
+            log::info!(
+                "waiting for {ec2_instance_id} to be ready to associate ({waited}s/{timeout}s)"
+            );
+            self.clock.sleep(READY_POLL_INTERVAL).await?;
+            waited += READY_POLL_INTERVAL.as_secs();
+        }
+    }
+
+    /// Returns the network interface to associate with (see "resolve_target"),
+    /// recording the target private IP of the instance in the mounted EIP file
+    /// (or clearing the one of another instance).
+    pub async fn target(&self, ec2_instance_id: &str) -> io::Result<eip::Target> {
+        let target = resolve_target(self.ec2, self.opts, ec2_instance_id).await?;
+        let file_path = &self.opts.mounted_eip_file_path;
+        let recorded = state::State::load(file_path)?;
+        if recorded.private_ip_address != target.private_ip_address
+            || (!target.private_ip_address.is_empty()
+                && recorded.private_ip_instance_id != ec2_instance_id)
+        {
+            state::record_private_ip_address(
+                file_path,
+                &target.private_ip_address,
+                ec2_instance_id,
+            )?;
+        }
+        Ok(target)
+    }
+
+    /// Tags the EIP with the current time, for the age-based conflict policies.
+    async fn tag_allocated_at(&self, allocation_id: &str) -> io::Result<()> {
+        self.ec2
+            .create_tags(
+                allocation_id,
+                vec![(
+                    conflict::ALLOCATED_AT_TAG_KEY.to_string(),
+                    self.clock.now_unix_seconds().to_string(),
+                )],
+            )
+            .await
+    }
+
+    /// Returns the EIP of the mounted EIP file, "None" if the file does not exist.
+    /// If the file is of another account or region (e.g., the volume re-attached
+    /// to an instance elsewhere), where the EIP cannot be used, fails by default,
+    /// uses it anyway with "--on-foreign-state=ignore", or returns "None" to
+    /// allocate another with "--on-foreign-state=reallocate".
+    fn mounted_eip(&self, account_id: &str, region: &str) -> io::Result<Option<ec2::Eip>> {
+        let file_path = &self.opts.mounted_eip_file_path;
+        if !Path::new(file_path).exists() {
+            return Ok(None);
+        }
+        log::info!("mounted EIP file path exists -- loading existing {file_path}");
+        let recorded = state::State::load(file_path)?;
+        let eip = state::load_eip(file_path)?;
+        if !recorded.is_foreign(account_id, region) {
+            return Ok(Some(eip));
+        }
+        let msg = format!(
+            "mounted EIP file {file_path} holds EIP {} of account {:?} in {:?}, but the instance is of account {account_id:?} in {region:?}",
+            eip.public_ip, recorded.account_id, recorded.region
+        );
+        match self.opts.on_foreign_state.as_str() {
+            "ignore" => {
+                self.ctx.summary.warn(&format!("{msg} -- using it anyway (--on-foreign-state=ignore)"));
+                Ok(Some(eip))
+            }
+            "reallocate" => {
+                self.ctx.summary.warn(&format!(
+                    "{msg} -- replacing it (--on-foreign-state=reallocate)"
+                ));
+                Ok(None)
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{msg} -- an EIP cannot be used in another account or region (set --on-foreign-state=reallocate to replace it)"
+                ),
+            )),
+        }
+    }
+
+    /// Loads (or claims, or allocates) the EIP and associates it with the instance.
+    pub async fn provision(&self, ec2_instance_id: &str) -> io::Result<ec2::Eip> {
+        let opts = self.opts;
+        log::info!(
+            "checking if the local instance {} has an already created elastic Ip (for reuse) via {}",
+            ec2_instance_id,
+            opts.mounted_eip_file_path
+        );
+        let started = Instant::now();
+        // empty (not checked) if the instance identity document is not available
+        let (account_id, region) = match self
+            .ctx
+            .timing
+            .measure("imds", self.metadata.account_and_region())
+            .await
+        {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!(
+                    "failed to fetch the account and region of {ec2_instance_id} '{}'",
+                    e
+                );
+                (String::new(), String::new())
+            }
+        };
+        let eip = if let Some(eip) = self.mounted_eip(&account_id, &region)? {
+            allocated(self.ctx, &eip, "file");
+            eip
+        } else if let Some(eip) = self.recover_by_tags(ec2_instance_id).await? {
+            allocated(self.ctx, &eip, "tags");
+            eip
+        } else if let Some(eip) = pool::claim(
+            self.ec2,
+            self.clock,
+            opts,
+            &pool::Lease {
+                instance_id: ec2_instance_id,
+                seconds: opts.pool_lease_seconds,
+                now: self.clock.now_unix_seconds(),
+            },
+        )
+        .await?
+        {
+            log::info!("claimed EIP {} from the pool", eip.public_ip);
+            allocated(self.ctx, &eip, "pool");
+            eip
+        } else {
+            log::info!(
+                "mounted EIP file does not exist in the mounted volume path -- creating one!"
+            );
+            let eip = self
+                .ec2
+                .allocate(
+                    (&opts.id_tag_key, &opts.id_tag_value),
+                    (&opts.kind_tag_key, &opts.kind_tag_value),
+                    &eip::client_token(ec2_instance_id, &opts.id_tag_value),
+                    &opts.public_ipv4_pool,
+                )
+                .await?;
+            self.tag_allocated_at(&eip.allocation_id).await?;
+            allocated(self.ctx, &eip, "new");
+            eip
+        };
+        state::record_eip(&opts.mounted_eip_file_path, &eip)?;
+        state::record_location(&opts.mounted_eip_file_path, &account_id, &region)?;
+        self.ctx.timing.add("allocate", started.elapsed());
+        // the association that the last run on this instance made, if any,
+        // to tell an association lost since then from one never made
+        let recorded = state::State::load(&opts.mounted_eip_file_path)?;
+        let recorded_association_id = if recorded.instance_id == ec2_instance_id {
+            recorded.association_id
+        } else {
+            String::new()
+        };
+
+        log::info!(
+            "checking the instance has already been associated with elastic IP {:?}",
+            eip
+        );
+        // filtered by the allocation ID on the server side, rather than
+        // describing every address of the instance
+        let addr = self
+            .ctx
+            .timing
+            .measure(
+                "describe",
+                self.ec2.describe_by_allocation_id(&eip.allocation_id),
+            )
+            .await?;
+        if let Some(addr) = addr
+            .as_ref()
+            .filter(|addr| addr.instance_id() == Some(ec2_instance_id))
+        {
+            let target = self
+                .ctx
+                .timing
+                .measure("describe", self.target(ec2_instance_id))
+                .await?;
+            if target.matches(addr) {
+                let association_id = addr.association_id().unwrap_or_default();
+                if association_id != recorded_association_id {
+                    // e.g., re-associated by an operator, or the first run with this version
+                    log::info!(
+                        "EIP {} is associated with {ec2_instance_id} by association {association_id} (recorded {:?})",
+                        eip.public_ip,
+                        recorded_association_id
+                    );
+                    state::record_association(
+                        &opts.mounted_eip_file_path,
+                        association_id,
+                        ec2_instance_id,
+                        self.clock.now_unix_seconds(),
+                    )?;
+                }
+                log::info!(
+                    "{ec2_instance_id} already has EIP allocation ID {} -- no need to associate once more",
+                    eip.allocation_id
+                );
+                associated(self.ctx, &eip, ec2_instance_id, false);
+                return Ok(eip);
+            }
+            // e.g., the target ENI was replaced, and the EIP is left on another ENI
+            log::warn!(
+                "EIP {} is associated with {ec2_instance_id} but on network interface {:?} (private IP {:?}) -- re-associating to {}",
+                eip.public_ip,
+                addr.network_interface_id(),
+                addr.private_ip_address(),
+                target.describe(ec2_instance_id)
+            );
+            self.associate(&eip, ec2_instance_id, &target, true).await?;
+            return Ok(eip);
+        }
+        log::info!(
+            "{ec2_instance_id} does not have EIP {}, now associating {:?}",
+            eip.allocation_id,
+            eip
+        );
+        self.ctx
+            .timing
+            .measure("ready", self.wait_ready(ec2_instance_id))
+            .await?;
+        // described again, since the association may have changed while waiting
+        let (target, addr) = self
+            .ctx
+            .timing
+            .measure("describe", async {
+                tokio::try_join!(
+                    self.target(ec2_instance_id),
+                    self.ec2.describe_by_allocation_id(&eip.allocation_id)
+                )
+            })
+            .await?;
+
+        if let Some(addr) = addr {
+            if !recorded_association_id.is_empty()
+                && addr.association_id() != Some(recorded_association_id.as_str())
+            {
+                // the association of the last run is gone, not just never made
+                self.ctx.summary.warn(&format!(
+                    "EIP {} lost association {recorded_association_id} with {ec2_instance_id} since the last run (now associated with {:?})",
+                    eip.public_ip,
+                    addr.instance_id().or_else(|| addr.network_interface_id())
+                ));
+            }
+            if addr.association_id().is_some() {
+                // associated with another resource, since the local instance has no such EIP
+                let live = match addr.instance_id() {
+                    Some(other) => {
+                        self.ctx
+                            .timing
+                            .measure("describe", self.ec2.is_instance_running(other))
+                            .await?
+                    }
+                    None => true,
+                };
+                let other = addr
+                    .instance_id()
+                    .or_else(|| addr.network_interface_id())
+                    .unwrap_or_default();
+                if live && opts.no_steal {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        format!(
+                            "EIP {} is associated with another live resource {other} -- aborting (no-steal)",
+                            eip.public_ip
+                        ),
+                    ));
+                }
+                log::warn!(
+                    "EIP {} is associated with {other} (live {live}) -- re-associating to {}",
+                    eip.public_ip,
+                    target.describe(ec2_instance_id)
+                );
+                self.associate(&eip, ec2_instance_id, &target, true).await?;
+                return Ok(eip);
+            }
+        }
+
+        self.associate(&eip, ec2_instance_id, &target, false)
+            .await?;
+        Ok(eip)
+    }
+
+    /// Associates the EIP, and records the association ID in the mounted EIP file
+    /// to verify on the next runs.
+    async fn associate(
+        &self,
+        eip: &ec2::Eip,
+        ec2_instance_id: &str,
+        target: &eip::Target,
+        allow_reassociation: bool,
+    ) -> io::Result<()> {
+        let association_id = self
+            .ctx
+            .timing
+            .measure(
+                "associate",
+                self.ec2.associate(
+                    &eip.allocation_id,
+                    ec2_instance_id,
+                    target,
+                    allow_reassociation,
+                ),
+            )
+            .await?;
+        log::info!(
+            "associated EIP {} with {} (association ID {association_id})",
+            eip.public_ip,
+            target.describe(ec2_instance_id)
+        );
+        state::record_association(
+            &self.opts.mounted_eip_file_path,
+            &association_id,
+            ec2_instance_id,
+            self.clock.now_unix_seconds(),
+        )?;
+        self.ctx
+            .timing
+            .measure(
+                "verify",
+                verify_association(self.ec2, self.clock, &eip.allocation_id, &association_id),
+            )
+            .await?;
+        associated(self.ctx, eip, ec2_instance_id, allow_reassociation);
+        Ok(())
+    }
+}
+
+/// Polls the EIP with exponential backoff until the describe call reflects the
+/// association, since AssociateAddress may return before DescribeAddresses
+/// catches up. Otherwise, the next run (or the DNS update) would see the EIP
+/// still unassociated, and associate it once more.
+/// Fails if it is not reflected after "VERIFY_POLL_ATTEMPTS" (e.g., another
+/// association already replaced it).
+pub async fn verify_association(
+    ec2: &dyn Ec2,
+    clock: &dyn Clock,
+    allocation_id: &str,
+    association_id: &str,
+) -> io::Result<()> {
+    let mut interval = VERIFY_POLL_INITIAL_INTERVAL;
+    let mut seen = None;
+    for attempt in 1..=VERIFY_POLL_ATTEMPTS {
+        let addr = ec2.describe_by_allocation_id(allocation_id).await?;
+        seen = addr
+            .as_ref()
+            .and_then(|a| a.association_id())
+            .map(|v| v.to_string());
+        // the previous association, not yet replaced in the describe call,
+        // looks the same as another one made after ours, so keeps polling
+        if seen.as_deref() == Some(association_id) {
+            log::info!("verified EIP {allocation_id} association {association_id}");
+            return Ok(());
+        }
+        if attempt == VERIFY_POLL_ATTEMPTS {
+            break;
+        }
+        log::info!(
+            "EIP {allocation_id} association {association_id} not reflected yet (seen {seen:?}) -- retrying in {interval:?} ({attempt}/{VERIFY_POLL_ATTEMPTS})"
+        );
+        clock.sleep(interval).await?;
+        interval *= 2;
+    }
+    Err(Error::new(
+        ErrorKind::TimedOut,
+        format!(
+            "EIP {allocation_id} association {association_id} not reflected after {VERIFY_POLL_ATTEMPTS} describes (seen {seen:?})"
+        ),
+    ))
+}
+
+/// Returns the network interface to associate with, by "--target-eni-tag"
+/// or "--target-device-index", failing if it is not attached to the instance.
+/// With "--private-ip-address" (or the one recorded in the mounted EIP file
+/// on this instance, ignored on another),
+/// also targets the private IP, failing if the network interface does not have it,
+/// and defaults to the network interface that has it.
+/// Returns the primary network interface (empty target) without describing, by default.
+pub async fn resolve_target(
+    ec2: &dyn Ec2,
+    opts: &Flags,
+    ec2_instance_id: &str,
+) -> io::Result<eip::Target> {
+    let private_ip_address = if opts.private_ip_address.is_empty() {
+        let recorded = state::State::load(&opts.mounted_eip_file_path)?;
+        let ip = recorded.private_ip_address_of(ec2_instance_id);
+        if ip.is_empty() && !recorded.private_ip_address.is_empty() {
+            log::info!(
+                "ignoring private IP {} recorded on another instance -- targeting the default network interface",
+                recorded.private_ip_address
+            );
+        }
+        ip.to_string()
+    } else {
+        opts.private_ip_address.clone()
+    };
+    if opts.target_eni_tag.is_empty()
+        && opts.target_device_index == 0
+        && private_ip_address.is_empty()
+    {
+        return Ok(eip::Target::default());
+    }
+    if !opts.target_eni_tag.is_empty() && opts.target_device_index != 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--target-eni-tag and --target-device-index cannot be used together",
+        ));
+    }
+    let tag = if opts.target_eni_tag.is_empty() {
+        None
+    } else {
+        Some(opts.target_eni_tag.split_once('=').ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "--target-eni-tag '{}' must be <key>=<value>",
+                    opts.target_eni_tag
+                ),
+            )
+        })?)
+    };
+    let has_private_ip = |ni: &NetworkInterface| {
+        ni.private_ip_addresses()
+            .unwrap_or_default()
+            .iter()
+            .any(|p| p.private_ip_address() == Some(private_ip_address.as_str()))
+    };
+
+    let enis = ec2.describe_network_interfaces(ec2_instance_id).await?;
+    let found: Vec<&NetworkInterface> = enis
+        .iter()
+        // in case the filter is ignored, never associate with another instance's ENI
+        .filter(|ni| ni.attachment().and_then(|a| a.instance_id()) == Some(ec2_instance_id))
+        .filter(|ni| match tag {
+            Some((k, v)) => ni
+                .tag_set()
+                .unwrap_or_default()
+                .iter()
+                .any(|t| t.key() == Some(k) && t.value() == Some(v)),
+            None if opts.target_device_index == 0 && !private_ip_address.is_empty() => {
+                has_private_ip(ni)
+            }
+            None => {
+                ni.attachment().and_then(|a| a.device_index())
+                    == Some(opts.target_device_index as i32)
+            }
+        })
+        .collect();
+    let wanted = match tag {
+        Some(_) => format!("tag {}", opts.target_eni_tag),
+        None if opts.target_device_index == 0 => format!("private IP {private_ip_address}"),
+        None => format!("device index {}", opts.target_device_index),
+    };
+    let ni = match found.as_slice() {
+        [ni] => ni,
+        [] => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "no network interface with {wanted} is attached to {ec2_instance_id} (attached {:?})",
+                    enis.iter()
+                        .map(|ni| ni.network_interface_id().unwrap_or_default())
+                        .collect::<Vec<_>>()
+                ),
+            ))
+        }
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} network interfaces with {wanted} are attached to {ec2_instance_id}",
+                    found.len()
+                ),
+            ))
+        }
+    };
+    let network_interface_id = ni.network_interface_id().unwrap_or_default();
+    if !private_ip_address.is_empty() && !has_private_ip(ni) {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "private IP {private_ip_address} is not assigned to network interface {network_interface_id} ({wanted})"
+            ),
+        ));
+    }
+    log::info!(
+        "targeting network interface {network_interface_id} ({wanted}, private IP {:?})",
+        private_ip_address
+    );
+    Ok(eip::Target {
+        network_interface_id: network_interface_id.to_string(),
+        private_ip_address,
+    })
+}
+
+/// Emits the "allocated" progress event, with where the EIP came from
+/// ("file" for the mounted file, "tags" for the one recovered by its tags,
+/// "pool", or "new").
+fn allocated(ctx: &Context, eip: &ec2::Eip, source: &str) {
+    progress::emit(
+        ctx,
+        progress::ALLOCATED,
+        &[
+            ("allocation_id", &eip.allocation_id),
+            ("public_ip", &eip.public_ip),
+            ("source", source),
+        ],
+    );
+}
+
+fn associated(ctx: &Context, eip: &ec2::Eip, ec2_instance_id: &str, reassociated: bool) {
+    progress::emit(
+        ctx,
+        progress::ASSOCIATED,
+        &[
+            ("allocation_id", &eip.allocation_id),
+            ("instance_id", ec2_instance_id),
+            ("reassociated", if reassociated { "true" } else { "false" }),
+        ],
+    );
+}
+
+fn has_tag(addr: &Address, key: &str, value: &str) -> bool {
+    addr.tags()
+        .unwrap_or_default()
+        .iter()
+        .any(|t| t.key() == Some(key) && t.value() == Some(value))
+}
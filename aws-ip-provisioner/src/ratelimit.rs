@@ -0,0 +1,237 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::metrics;
+
+/// Counter of the throttled AWS API calls (e.g., "RequestLimitExceeded").
+pub const THROTTLED_COUNTER: &str = "aws_api_throttled_total";
+
+/// Gauge of the adaptive requests per second while throttled (0 if not throttled).
+pub const ADAPTIVE_RPS_GAUGE: &str = "aws_api_adaptive_rps";
+
+/// Error codes of the throttled calls across the query (EC2, STS) and
+/// JSON (e.g., Organizations) protocols, and S3.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/throttling.html>
+pub const THROTTLING_CODES: [&str; 6] = [
+    "RequestLimitExceeded",
+    "ThrottlingException",
+    "Throttling",
+    "TooManyRequestsException",
+    "RequestThrottled",
+    "SlowDown",
+];
+
+/// Requests per second to back off from on the first throttling
+/// without "--max-api-rps", halved right away.
+const UNLIMITED_RPS: f64 = 20.0;
+
+/// Floor of the adaptive rate, so that the calls never stall.
+const MIN_ADAPTIVE_RPS: f64 = 0.5;
+
+/// Requests per second regained per successful call while throttled.
+const ADDITIVE_INCREASE: f64 = 0.1;
+
+tokio::task_local! {
+    /// Scope of the API calls of the current task (see "scoped").
+    static SCOPE: String;
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rps: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rps: f64) -> Self {
+        Self {
+            rps,
+            tokens: rps,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Consumes a token, returning how long to wait for it.
+    fn reserve(&mut self) -> Duration {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * self.rps;
+        self.tokens = (self.tokens + refill).min(self.rps);
+        self.last_refill = now;
+
+        // reserve the token even if not yet available, so concurrent callers queue up
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / self.rps)
+    }
+}
+
+/// Rate limit shared by all AWS API calls of the run
+/// (see "sdk::Options"), unlimited by default.
+#[derive(Debug)]
+pub struct Limiter {
+    /// Token bucket of all the calls. "None" means unlimited.
+    limiter: Mutex<Option<TokenBucket>>,
+    /// Token buckets per scope (e.g., "<account>/<region>" of a fleet command),
+    /// with the requests per second of each. "None" means unlimited.
+    scoped: Mutex<Option<(f64, HashMap<String, TokenBucket>)>>,
+    /// Adaptive token bucket while throttled (AIMD: halved on every throttled
+    /// call, increased additively on every successful one), dropped once back
+    /// at the configured rate. "None" means not throttled.
+    adaptive: Mutex<Option<TokenBucket>>,
+    /// Where the throttled calls are counted.
+    metrics: Arc<metrics::Registry>,
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new(Arc::new(metrics::Registry::default()))
+    }
+}
+
+impl Limiter {
+    pub fn new(metrics: Arc<metrics::Registry>) -> Self {
+        Self {
+            limiter: Mutex::new(None),
+            scoped: Mutex::new(None),
+            adaptive: Mutex::new(None),
+            metrics,
+        }
+    }
+
+    /// Sets the maximum number of AWS API requests per second,
+    /// with the burst of the same size. Zero disables the rate limit.
+    pub fn init(&self, max_api_rps: u32) {
+        let mut limiter = self.limiter.lock().unwrap();
+        if max_api_rps == 0 {
+            *limiter = None;
+            return;
+        }
+        log::info!("limiting AWS API calls to {max_api_rps} requests per second");
+        *limiter = Some(TokenBucket::new(max_api_rps as f64));
+    }
+
+    /// Sets the maximum number of AWS API requests per second of each scope
+    /// (see "scoped"), on top of the overall limit. Zero disables it.
+    pub fn init_scoped(&self, max_api_rps: u32) {
+        let mut scoped = self.scoped.lock().unwrap();
+        if max_api_rps == 0 {
+            *scoped = None;
+            return;
+        }
+        log::info!("limiting AWS API calls to {max_api_rps} requests per second per scope");
+        *scoped = Some((max_api_rps as f64, HashMap::new()));
+    }
+
+    /// Waits until a token is available, and consumes it.
+    /// Called by the HTTP client before every AWS API call (see "sdk::Options").
+    pub async fn acquire(&self) {
+        let mut wait = match self.limiter.lock().unwrap().as_mut() {
+            Some(bucket) => bucket.reserve(),
+            None => Duration::ZERO,
+        };
+        if let Some(bucket) = self.adaptive.lock().unwrap().as_mut() {
+            wait = wait.max(bucket.reserve());
+        }
+        if let Ok(scope) = SCOPE.try_with(|s| s.clone()) {
+            if let Some((rps, buckets)) = self.scoped.lock().unwrap().as_mut() {
+                let bucket = buckets
+                    .entry(scope)
+                    .or_insert_with(|| TokenBucket::new(*rps));
+                wait = wait.max(bucket.reserve());
+            }
+        }
+        if wait.is_zero() {
+            return;
+        }
+        log::debug!("rate limited -- waiting {wait:?} before the AWS API call");
+        sleep(wait).await;
+    }
+
+    /// Halves the adaptive rate on the throttled call (multiplicative decrease),
+    /// so that the fleet members back off rather than retrying into a throttling storm.
+    pub fn on_throttled(&self, code: &str) {
+        self.metrics.inc_counter(THROTTLED_COUNTER);
+        let ceiling = self.configured_rps();
+        let mut adaptive = self.adaptive.lock().unwrap();
+        let bucket = adaptive.get_or_insert_with(|| TokenBucket::new(ceiling));
+        bucket.rps = (bucket.rps / 2.0).max(MIN_ADAPTIVE_RPS);
+        bucket.tokens = bucket.tokens.min(bucket.rps);
+        log::warn!(
+            "throttled by {code} -- backing off AWS API calls to {:.1} requests per second",
+            bucket.rps
+        );
+        self.metrics.set_gauge(ADAPTIVE_RPS_GAUGE, bucket.rps);
+    }
+
+    /// Increases the adaptive rate on the successful call (additive increase),
+    /// until back at the configured rate.
+    pub fn on_success(&self) {
+        let mut adaptive = self.adaptive.lock().unwrap();
+        let bucket = match adaptive.as_mut() {
+            Some(v) => v,
+            None => return,
+        };
+        bucket.rps += ADDITIVE_INCREASE;
+        let ceiling = self.configured_rps();
+        if bucket.rps >= ceiling {
+            log::info!(
+                "no longer throttled -- AWS API calls back at {ceiling:.1} requests per second"
+            );
+            *adaptive = None;
+            self.metrics.set_gauge(ADAPTIVE_RPS_GAUGE, 0.0);
+            return;
+        }
+        self.metrics.set_gauge(ADAPTIVE_RPS_GAUGE, bucket.rps);
+    }
+
+    /// Returns the adaptive requests per second, "None" if not throttled.
+    pub fn adaptive_rps(&self) -> Option<f64> {
+        self.adaptive.lock().unwrap().as_ref().map(|b| b.rps)
+    }
+
+    fn configured_rps(&self) -> f64 {
+        self.limiter
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|b| b.rps)
+            .unwrap_or(UNLIMITED_RPS)
+    }
+}
+
+/// Runs the future with its AWS API calls limited as the scope
+/// (e.g., "123456789012/us-west-2"), so that one account or region
+/// of a fleet command is never throttled by the calls to the others.
+pub async fn scoped<F: Future>(scope: String, f: F) -> F::Output {
+    SCOPE.scope(scope, f).await
+}
+
+/// Returns the throttling error code of the AWS API response, if throttled
+/// (e.g., "<Code>RequestLimitExceeded</Code>" of EC2 with 503).
+pub fn throttling_code(status: u16, body: &[u8]) -> Option<&'static str> {
+    if status < 400 {
+        return None;
+    }
+    let body = String::from_utf8_lossy(body);
+    // e.g., "<Code>Throttling</Code>" (query), "__type": "...#ThrottlingException" (JSON)
+    THROTTLING_CODES
+        .into_iter()
+        .find(|c| {
+            body.contains(&format!("<Code>{c}</Code>"))
+                || body.contains(&format!("\"{c}\""))
+                || body.contains(&format!("#{c}\""))
+        })
+        .or(if status == 429 {
+            Some("TooManyRequests")
+        } else {
+            None
+        })
+}
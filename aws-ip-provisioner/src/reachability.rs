@@ -0,0 +1,255 @@
+use std::{
+    fmt,
+    io::{self, Error, ErrorKind},
+    net::IpAddr,
+};
+
+use hyper::{Body, Method, Request};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::TcpStream,
+    time::{sleep, timeout, Duration, Instant},
+};
+
+use crate::{context::Context, identity, summary, tls};
+
+/// Interval between the verification rounds, as the security group
+/// and the network ACL changes take a few seconds to propagate.
+const VERIFY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Timeout of each request to the reflector, including its own probe.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Timeout of the TCP connect of the reflector's probe.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Port to verify from outside the VPC (e.g., "tcp://:9651").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// Empty for the EIP.
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "tcp://[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "tcp://{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// Answer of the reflector (e.g., "ip-manager serve --reachability-reflector")
+/// to "POST <url>?protocol=tcp&host=<ip>&port=<port>" with the signed
+/// instance identity document as the body.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Verdict {
+    pub reachable: bool,
+    /// Why not reachable (e.g., "connection timed out"), empty if reachable.
+    #[serde(default)]
+    pub error: String,
+}
+
+/// Parses the comma-separated targets of "--verify-reachability"
+/// (e.g., "tcp://:9651,tcp://:443"), where the empty host is the EIP.
+/// Only TCP can be verified, as an unanswered UDP probe is not a proof of a block.
+pub fn parse(s: &str) -> io::Result<Vec<Target>> {
+    let mut targets = Vec::new();
+    for v in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let rest = v.strip_prefix("tcp://").ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("'{v}' is not in the form 'tcp://[host]:port'"),
+            )
+        })?;
+        let (host, port) = rest
+            .rsplit_once(':')
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("'{v}' has no port")))?;
+        let port = match port.parse::<u16>() {
+            Ok(p) if p > 0 => p,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("'{v}' has an invalid port '{port}'"),
+                ))
+            }
+        };
+        targets.push(Target {
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+        });
+    }
+    Ok(targets)
+}
+
+/// Waits until every target is reachable from the reflector, so that the EIP
+/// that attached but is still blocked (e.g., by the security group or the
+/// network ACL) fails the run rather than silently dropping the traffic.
+/// The reflector admits the instance by its signed identity document.
+pub async fn verify(
+    ctx: &Context,
+    service_url: &str,
+    signed: &identity::Signed,
+    public_ip: &str,
+    targets: &[Target],
+    timeout_seconds: u64,
+) -> io::Result<()> {
+    let mut pending: Vec<Target> = targets
+        .iter()
+        .map(|t| Target {
+            host: if t.host.is_empty() {
+                public_ip.to_string()
+            } else {
+                t.host.clone()
+            },
+            port: t.port,
+        })
+        .collect();
+
+    let deadline = Instant::now() + Duration::from_secs(timeout_seconds);
+    let mut history = summary::History::new(&ctx.summary, "reachability_verify");
+    loop {
+        let mut still_pending = Vec::new();
+        let mut failures = Vec::new();
+        for target in pending.iter() {
+            match check(service_url, signed, target).await {
+                Ok(v) if v.reachable => log::info!("{target} is reachable from {service_url}"),
+                Ok(v) => {
+                    log::info!("{target} is not reachable from {service_url} ({})", v.error);
+                    failures.push(format!("{target} ({})", v.error));
+                    still_pending.push(target.clone());
+                }
+                Err(e) => {
+                    log::warn!("failed to check {target} with {service_url} '{}'", e);
+                    failures.push(format!("{target} (reflector: {})", e));
+                    still_pending.push(target.clone());
+                }
+            }
+        }
+        if still_pending.is_empty() {
+            return Ok(());
+        }
+        if Instant::now() + VERIFY_INTERVAL > deadline {
+            return Err(history.exhausted(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "{} not reachable from outside after {timeout_seconds} seconds -- check the security groups and the network ACLs of the instance",
+                    failures.join(", ")
+                ),
+            )));
+        }
+        pending = still_pending;
+        history.retry(
+            &Error::new(
+                ErrorKind::Other,
+                format!("not reachable {}", failures.join(", ")),
+            ),
+            VERIFY_INTERVAL,
+        );
+        sleep(VERIFY_INTERVAL).await;
+    }
+}
+
+/// Asks the reflector whether the target is reachable.
+pub async fn check(
+    service_url: &str,
+    signed: &identity::Signed,
+    target: &Target,
+) -> io::Result<Verdict> {
+    let sep = if service_url.contains('?') { '&' } else { '?' };
+    let uri = format!(
+        "{service_url}{sep}protocol=tcp&host={}&port={}",
+        target.host, target.port
+    );
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(&uri)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(signed)?))
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid reachability service URL {}", e),
+            )
+        })?;
+    let client = hyper::Client::builder().build::<_, Body>(tls::https_connector());
+    let resp = timeout(CHECK_TIMEOUT, client.request(req))
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, "reflector timed out"))?
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed reflector GET {}", e)))?;
+    let status = resp.status();
+    let b = hyper::body::to_bytes(resp.into_body())
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to read reflector {}", e)))?;
+    if !status.is_success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "reflector returned {status} '{}'",
+                String::from_utf8_lossy(&b).trim()
+            ),
+        ));
+    }
+    serde_json::from_slice(&b).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid reflector response {}", e),
+        )
+    })
+}
+
+/// Returns true if the address is routable on the internet, so that the
+/// reflector never probes its own networks (e.g., the VPC, the loopback,
+/// or the instance metadata at "169.254.169.254" and "fd00:ec2::254").
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v) => {
+            let [a, b, ..] = v.octets();
+            !(v.is_private()
+                || v.is_loopback()
+                || v.is_link_local()
+                || v.is_unspecified()
+                || v.is_broadcast()
+                || v.is_multicast()
+                // "this network", shared address space (carrier-grade NAT), reserved
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64)
+                || a >= 240)
+        }
+        IpAddr::V6(v) => {
+            if let Some(v4) = v.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let first = v.segments()[0];
+            !(v.is_loopback()
+                || v.is_unspecified()
+                || v.is_multicast()
+                // unique local (e.g., "fd00:ec2::254"), link-local
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Connects to the target as the reflector, from its own network.
+pub async fn probe(host: &str, port: u16) -> Verdict {
+    match timeout(PROBE_TIMEOUT, TcpStream::connect((host, port))).await {
+        Ok(Ok(_)) => Verdict {
+            reachable: true,
+            error: String::new(),
+        },
+        Ok(Err(e)) => Verdict {
+            reachable: false,
+            error: e.to_string(),
+        },
+        Err(_) => Verdict {
+            reachable: false,
+            error: format!("connection timed out after {PROBE_TIMEOUT:?}"),
+        },
+    }
+}
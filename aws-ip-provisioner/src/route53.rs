@@ -0,0 +1,360 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_sigv4::http_request::SigningSettings;
+use aws_types::SdkConfig;
+use hyper::{http, Method, StatusCode};
+use ring::digest;
+
+use crate::sdk;
+
+/// ref. <https://docs.aws.amazon.com/Route53/latest/APIReference/Welcome.html>
+const API_VERSION: &str = "2013-04-01";
+const XMLNS: &str = "https://route53.amazonaws.com/doc/2013-04-01/";
+
+/// Record of the EIP or the private IP to upsert in a hosted zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Hosted zone ID (e.g., "Z0123456789ABCDEFGHIJ"), public or private.
+    pub zone_id: String,
+    pub name: String,
+    pub ttl: u32,
+    pub value: String,
+    pub routing: Routing,
+    /// Distinguishes the records of the same name and type of the other nodes
+    /// (e.g., the instance ID), empty for "Routing::Simple".
+    pub set_identifier: String,
+    /// Health check of the record, empty for none.
+    pub health_check_id: String,
+}
+
+/// Routing policy of the record, so that the records of the nodes sharing
+/// the name fail over (or share the traffic) at the DNS level.
+/// ref. <https://docs.aws.amazon.com/Route53/latest/DeveloperGuide/routing-policy.html>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Routing {
+    Simple,
+    /// "PRIMARY" or "SECONDARY".
+    Failover(&'static str),
+    /// Relative weight (0 to 255).
+    Weighted(u8),
+    /// In the region of the instance.
+    Latency,
+}
+
+impl Routing {
+    /// Parses "simple", "failover-primary", "failover-secondary",
+    /// "weighted:<0-255>", or "latency".
+    pub fn parse(s: &str) -> io::Result<Self> {
+        match s {
+            "" | "simple" => Ok(Self::Simple),
+            "failover-primary" => Ok(Self::Failover("PRIMARY")),
+            "failover-secondary" => Ok(Self::Failover("SECONDARY")),
+            "latency" => Ok(Self::Latency),
+            _ => match s.strip_prefix("weighted:").map(|w| w.parse::<u8>()) {
+                Some(Ok(w)) => Ok(Self::Weighted(w)),
+                _ => Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid Route53 routing '{s}' (simple, failover-primary, failover-secondary, weighted:<0-255>, or latency)"),
+                )),
+            },
+        }
+    }
+}
+
+/// Health check of the EIP that Route53 runs from its checkers on the internet.
+/// ref. <https://docs.aws.amazon.com/Route53/latest/APIReference/API_HealthCheckConfig.html>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// "TCP", "HTTP", or "HTTPS".
+    pub protocol: String,
+    pub port: u16,
+    /// Path of the HTTP(S) request, empty for TCP.
+    pub path: String,
+}
+
+impl HealthCheck {
+    /// Parses "tcp:<port>", "http:<port>[/path]", or "https:<port>[/path]",
+    /// or returns "None" for the empty string.
+    pub fn parse(s: &str) -> io::Result<Option<Self>> {
+        if s.is_empty() {
+            return Ok(None);
+        }
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "invalid Route53 health check '{s}' (e.g., \"tcp:9651\" or \"http:80/health\")"
+                ),
+            )
+        };
+        let (protocol, rest) = s.split_once(':').ok_or_else(invalid)?;
+        let (port, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let port = match port.parse::<u16>() {
+            Ok(p) if p > 0 => p,
+            _ => return Err(invalid()),
+        };
+        match protocol {
+            "tcp" if path.is_empty() => Ok(Some(Self {
+                protocol: String::from("TCP"),
+                port,
+                path: String::new(),
+            })),
+            "http" | "https" => Ok(Some(Self {
+                protocol: protocol.to_uppercase(),
+                port,
+                path: if path.is_empty() { "/" } else { path }.to_string(),
+            })),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Returns the idempotency token of the health check of the IP, so that
+    /// the reruns on the same EIP get the existing health check back.
+    pub fn caller_reference(&self, ip: &str, instance_id: &str) -> String {
+        let d = digest::digest(
+            &digest::SHA256,
+            format!(
+                "{instance_id} {ip} {} {} {}",
+                self.protocol, self.port, self.path
+            )
+            .as_bytes(),
+        );
+        let hex: String = d.as_ref()[..16]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        format!("ip-manager-{hex}")
+    }
+
+    /// Returns the "CreateHealthCheck" request against the IP.
+    /// ref. <https://docs.aws.amazon.com/Route53/latest/APIReference/API_CreateHealthCheck.html>
+    pub fn create_request(&self, ip: &str, caller_reference: &str) -> String {
+        let path = if self.path.is_empty() {
+            String::new()
+        } else {
+            format!("<ResourcePath>{}</ResourcePath>", escape(&self.path))
+        };
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<CreateHealthCheckRequest xmlns=\"{XMLNS}\"><CallerReference>{}</CallerReference><HealthCheckConfig><IPAddress>{}</IPAddress><Port>{}</Port><Type>{}</Type>{path}<RequestInterval>30</RequestInterval><FailureThreshold>3</FailureThreshold></HealthCheckConfig></CreateHealthCheckRequest>",
+            escape(caller_reference),
+            escape(ip),
+            self.port,
+            self.protocol,
+        )
+    }
+}
+
+impl Record {
+    /// Returns "AAAA" for the IPv6 address, "A" otherwise.
+    pub fn record_type(&self) -> &'static str {
+        if self.value.contains(':') {
+            "AAAA"
+        } else {
+            "A"
+        }
+    }
+}
+
+/// Returns the endpoint of the global Route53 API (or "--endpoint-url"),
+/// and the region to sign the requests in.
+/// ref. <https://docs.aws.amazon.com/general/latest/gr/r53.html>
+pub fn endpoint(region: &str, opts: &sdk::Options) -> (String, &'static str) {
+    let china = region.starts_with("cn-");
+    let signing_region = if china { "cn-northwest-1" } else { "us-east-1" };
+    if !opts.endpoint_url.is_empty() {
+        return (
+            opts.endpoint_url.trim_end_matches('/').to_string(),
+            signing_region,
+        );
+    }
+    let uri = match (china, opts.use_fips) {
+        (true, _) => "https://route53.amazonaws.com.cn",
+        (false, true) => "https://route53-fips.amazonaws.com",
+        (false, false) => "https://route53.amazonaws.com",
+    };
+    (uri.to_string(), signing_region)
+}
+
+/// Returns the "ChangeResourceRecordSets" request that upserts the records of one zone,
+/// with the region of the latency records.
+/// ref. <https://docs.aws.amazon.com/Route53/latest/APIReference/API_ChangeResourceRecordSets.html>
+pub fn change_batch(records: &[Record], region: &str, comment: &str) -> String {
+    let mut changes = String::new();
+    for r in records {
+        // in the order of the "ResourceRecordSet" schema
+        let routing = match &r.routing {
+            Routing::Simple => String::new(),
+            Routing::Failover(v) => format!("<Failover>{v}</Failover>"),
+            Routing::Weighted(w) => format!("<Weight>{w}</Weight>"),
+            Routing::Latency => format!("<Region>{}</Region>", escape(region)),
+        };
+        let set_identifier = if r.routing == Routing::Simple {
+            String::new()
+        } else {
+            format!(
+                "<SetIdentifier>{}</SetIdentifier>",
+                escape(&r.set_identifier)
+            )
+        };
+        let health_check = if r.health_check_id.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "<HealthCheckId>{}</HealthCheckId>",
+                escape(&r.health_check_id)
+            )
+        };
+        changes.push_str(&format!(
+            "<Change><Action>UPSERT</Action><ResourceRecordSet><Name>{}</Name><Type>{}</Type>{set_identifier}{routing}<TTL>{}</TTL><ResourceRecords><ResourceRecord><Value>{}</Value></ResourceRecord></ResourceRecords>{health_check}</ResourceRecordSet></Change>",
+            escape(&r.name),
+            r.record_type(),
+            r.ttl,
+            escape(&r.value),
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ChangeResourceRecordSetsRequest xmlns=\"{XMLNS}\"><ChangeBatch><Comment>{}</Comment><Changes>{changes}</Changes></ChangeBatch></ChangeResourceRecordSetsRequest>",
+        escape(comment)
+    )
+}
+
+/// Upserts the records, in one change batch per hosted zone, signed with
+/// the credentials of the SDK config (e.g., "route53:ChangeResourceRecordSets"
+/// on both the public and the private zone).
+pub async fn upsert(
+    shared_config: &SdkConfig,
+    opts: &sdk::Options,
+    records: &[Record],
+    comment: &str,
+) -> io::Result<()> {
+    let region = shared_config
+        .region()
+        .map(|r| r.to_string())
+        .unwrap_or_default();
+    let (endpoint, signing_region) = endpoint(&region, opts);
+
+    let mut zones: Vec<&str> = Vec::new();
+    for r in records {
+        if !zones.contains(&r.zone_id.as_str()) {
+            zones.push(&r.zone_id);
+        }
+    }
+    for zone_id in zones {
+        let batch: Vec<Record> = records
+            .iter()
+            .filter(|r| r.zone_id == zone_id)
+            .cloned()
+            .collect();
+        let uri = format!(
+            "{endpoint}/{API_VERSION}/hostedzone/{}/rrset",
+            zone_id.trim_start_matches("/hostedzone/")
+        );
+        let body = call(
+            shared_config,
+            signing_region,
+            &uri,
+            change_batch(&batch, &region, comment),
+        )
+        .await?;
+        log::info!(
+            "upserted {} record(s) in hosted zone {zone_id} (change {})",
+            batch.len(),
+            element(&body, "Id").unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// Creates the health check against the IP (or gets the existing one of the
+/// same caller reference back), and returns its ID.
+pub async fn create_health_check(
+    shared_config: &SdkConfig,
+    opts: &sdk::Options,
+    check: &HealthCheck,
+    ip: &str,
+    instance_id: &str,
+) -> io::Result<String> {
+    let region = shared_config
+        .region()
+        .map(|r| r.to_string())
+        .unwrap_or_default();
+    let (endpoint, signing_region) = endpoint(&region, opts);
+    let caller_reference = check.caller_reference(ip, instance_id);
+    let body = call(
+        shared_config,
+        signing_region,
+        &format!("{endpoint}/{API_VERSION}/healthcheck"),
+        check.create_request(ip, &caller_reference),
+    )
+    .await?;
+    let id = element(&body, "Id").unwrap_or_default().to_string();
+    if id.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("no health check ID in the CreateHealthCheck response '{body}'"),
+        ));
+    }
+    log::info!(
+        "health check {id} on {} {ip}:{} ({caller_reference})",
+        check.protocol,
+        check.port
+    );
+    Ok(id)
+}
+
+/// POSTs the XML request, returning the response body of the 2xx statuses.
+async fn call(
+    shared_config: &SdkConfig,
+    signing_region: &str,
+    uri: &str,
+    xml: String,
+) -> io::Result<String> {
+    let request = http::Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header("content-type", "application/xml")
+        .body(xml.into_bytes())
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to build POST {uri} {}", e),
+            )
+        })?;
+    let (status, _, body) = sdk::send_signed(
+        shared_config,
+        signing_region,
+        "route53",
+        request,
+        SigningSettings::default(),
+    )
+    .await?;
+    let body = String::from_utf8_lossy(&body).to_string();
+    if status != StatusCode::OK && status != StatusCode::CREATED {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed POST {uri} {status} '{}'",
+                element(&body, "Message").unwrap_or(&body)
+            ),
+        ));
+    }
+    Ok(body)
+}
+
+/// Returns the text of the first element of the name (e.g., "<Id>...</Id>").
+pub fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{name}>");
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{name}>"))?;
+    Some(&xml[start..start + end])
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
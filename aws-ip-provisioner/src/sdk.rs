@@ -0,0 +1,550 @@
+use std::{
+    future::Future,
+    io::{self, Error, ErrorKind},
+    path::PathBuf,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use aws_config::{
+    meta::region::RegionProviderChain,
+    profile::{ProfileFileCredentialsProvider, ProfileFileRegionProvider},
+    provider_config::ProviderConfig,
+    sts::AssumeRoleProvider,
+    web_identity_token::{StaticConfiguration, WebIdentityTokenCredentialsProvider},
+};
+use aws_sigv4::http_request::{sign, SignableRequest, SigningParams, SigningSettings};
+use aws_smithy_client::{
+    erase::DynConnector,
+    http_connector::{ConnectorSettings, HttpConnector},
+    hyper_ext,
+};
+use aws_smithy_http::{body::SdkBody, endpoint::Endpoint};
+use aws_types::{
+    credentials::{ProvideCredentials, SharedCredentialsProvider},
+    endpoint::{AwsEndpoint, BoxError, CredentialScope, ResolveAwsEndpoint},
+    region::{Region, SigningRegion},
+    SdkConfig,
+};
+use hyper::{
+    body, client::HttpConnector as HyperHttpConnector, http, service::Service, Method, Request,
+    Response, StatusCode, Uri,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{ratelimit, secret, tls};
+
+/// Options for the AWS SDK HTTP client.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Proxy URL to route all AWS API calls (e.g., "http://proxy.internal:3128").
+    /// Empty to connect directly.
+    pub https_proxy: String,
+    /// PEM file with additional CA certificates to trust (e.g., TLS interception proxy).
+    /// Empty to use the system roots only.
+    pub ca_bundle: String,
+    /// Set true to use FIPS 140-2 validated service endpoints (e.g., GovCloud/FedRAMP).
+    pub use_fips: bool,
+    /// Set true to use dual-stack (IPv4 and IPv6) service endpoints (e.g., IPv6-only subnets).
+    pub use_dual_stack: bool,
+    /// Endpoint URL for all the services (e.g., "http://localhost:4566" for LocalStack),
+    /// overriding the FIPS and dual-stack endpoints. Empty for the regional endpoints.
+    pub endpoint_url: String,
+    /// Named profile in the shared config/credentials files (e.g., "~/.aws/config").
+    /// Empty to use the default credentials chain.
+    pub profile: String,
+    /// IAM role to assume with the web identity token (e.g., IRSA in Kubernetes).
+    pub role_arn: String,
+    /// Web identity token file for AssumeRoleWithWebIdentity.
+    /// Empty to use the default credentials chain, which still picks up
+    /// "AWS_WEB_IDENTITY_TOKEN_FILE" and "AWS_ROLE_ARN" environment variables.
+    pub web_identity_token_file: String,
+    /// Session name for the assumed role.
+    pub role_session_name: String,
+    /// Rate limit of all the AWS API calls, including the SDK's own retries
+    /// (see "context::Context"). Unlimited by default.
+    pub ratelimit: Arc<ratelimit::Limiter>,
+    /// Cache of the third-party credentials (see "context::Context"),
+    /// fetched on every use by default.
+    pub secrets: Arc<secret::SecretCache>,
+}
+
+/// Loads an AWS config from default environments, with the HTTP client
+/// that logs the request IDs (and goes through the proxy and trusts the CA bundle if set),
+/// and the credentials from the profile or the web identity token if set.
+pub async fn load_config(reg: Option<String>, opts: &Options) -> io::Result<SdkConfig> {
+    log::info!("loading AWS configuration for region {:?}", reg);
+
+    // credentials providers (e.g., STS) must use the same HTTP client
+    let connector = build_connector(opts)?;
+    let mut provider_config = ProviderConfig::default().with_http_connector(connector.clone());
+    let mut loader =
+        aws_config::from_env().http_connector(HttpConnector::Prebuilt(Some(connector)));
+
+    let mut regp = RegionProviderChain::first_try(reg.map(Region::new));
+    if !opts.profile.is_empty() {
+        regp = regp.or_else(
+            ProfileFileRegionProvider::builder()
+                .configure(&provider_config)
+                .profile_name(&opts.profile)
+                .build(),
+        );
+    }
+    let region = regp
+        .or_default_provider()
+        .or_else(Region::new("us-west-2"))
+        .region()
+        .await
+        .unwrap_or_else(|| Region::new("us-west-2"));
+    provider_config = provider_config.with_region(Some(region.clone()));
+    loader = loader.region(region);
+
+    if !opts.web_identity_token_file.is_empty() {
+        if opts.role_arn.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "web identity token file requires the role ARN",
+            ));
+        }
+        log::info!(
+            "assuming role {} with web identity token {}",
+            opts.role_arn,
+            opts.web_identity_token_file
+        );
+        loader = loader.credentials_provider(
+            WebIdentityTokenCredentialsProvider::builder()
+                .configure(&provider_config)
+                .static_configuration(StaticConfiguration {
+                    web_identity_token_file: PathBuf::from(&opts.web_identity_token_file),
+                    role_arn: opts.role_arn.clone(),
+                    session_name: opts.role_session_name.clone(),
+                })
+                .build(),
+        );
+    } else if !opts.profile.is_empty() {
+        log::info!("loading credentials from profile {}", opts.profile);
+        loader = loader.credentials_provider(
+            ProfileFileCredentialsProvider::builder()
+                .configure(&provider_config)
+                .profile_name(&opts.profile)
+                .build(),
+        );
+    }
+
+    Ok(loader.configure(provider_config).load().await)
+}
+
+/// Returns the config with the credentials of the role assumed with the
+/// credentials of the shared config (e.g., the role in another account),
+/// sharing the region and the HTTP client.
+pub fn assume_role(
+    shared_config: &SdkConfig,
+    opts: &Options,
+    role_arn: &str,
+) -> io::Result<SdkConfig> {
+    let base = shared_config
+        .credentials_provider()
+        .cloned()
+        .ok_or_else(|| Error::new(ErrorKind::Other, "no AWS credentials provider"))?;
+    let region = shared_config
+        .region()
+        .cloned()
+        .unwrap_or_else(|| Region::new("us-west-2"));
+    log::info!("assuming role {role_arn} in {region}");
+
+    let connector = build_connector(opts)?;
+    let provider_config = ProviderConfig::default()
+        .with_http_connector(connector.clone())
+        .with_region(Some(region.clone()));
+    let provider = AssumeRoleProvider::builder(role_arn)
+        .region(region.clone())
+        .session_name(&opts.role_session_name)
+        .configure(&provider_config)
+        .build(base);
+
+    let mut builder = SdkConfig::builder().region(region);
+    builder.set_retry_config(shared_config.retry_config().cloned());
+    builder.set_timeout_config(shared_config.timeout_config().cloned());
+    builder.set_sleep_impl(shared_config.sleep_impl());
+    builder.set_credentials_provider(Some(SharedCredentialsProvider::new(provider)));
+    builder.set_app_name(shared_config.app_name().cloned());
+    builder.set_http_connector(Some(HttpConnector::Prebuilt(Some(connector))));
+    Ok(builder.build())
+}
+
+/// Returns the config for the service (e.g., "ec2", "autoscaling"),
+/// with the custom endpoint URL, or the FIPS and/or dual-stack endpoint if enabled.
+/// The endpoint resolver is set per service, since the SDK config is shared
+/// across the service clients.
+pub fn for_service(
+    shared_config: &SdkConfig,
+    service: &str,
+    opts: &Options,
+) -> io::Result<SdkConfig> {
+    if !opts.use_fips && !opts.use_dual_stack && opts.endpoint_url.is_empty() {
+        return Ok(shared_config.clone());
+    }
+
+    let mut builder = SdkConfig::builder().region(shared_config.region().cloned());
+    if !opts.endpoint_url.is_empty() {
+        log::info!("using endpoint {} for {service}", opts.endpoint_url);
+        let endpoint = Endpoint::immutable(&opts.endpoint_url).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid endpoint URL '{}' ({})", opts.endpoint_url, e),
+            )
+        })?;
+        builder.set_endpoint_resolver(Some(Arc::new(endpoint)));
+    } else {
+        builder.set_endpoint_resolver(Some(Arc::new(ServiceEndpoint {
+            service: service.to_string(),
+            use_fips: opts.use_fips,
+            use_dual_stack: opts.use_dual_stack,
+        })));
+    }
+    builder.set_retry_config(shared_config.retry_config().cloned());
+    builder.set_timeout_config(shared_config.timeout_config().cloned());
+    builder.set_sleep_impl(shared_config.sleep_impl());
+    builder.set_credentials_provider(shared_config.credentials_provider().cloned());
+    builder.set_app_name(shared_config.app_name().cloned());
+    builder.set_http_connector(shared_config.http_connector().cloned());
+    Ok(builder.build())
+}
+
+/// Sends the signed JSON 1.1 request (e.g., the services without the SDK crate
+/// such as SSM and Organizations) in the region, with the HTTP client of the
+/// SDK config, so the proxy and the CA bundle apply.
+pub async fn call_json(
+    shared_config: &SdkConfig,
+    opts: &Options,
+    region: &str,
+    service: &str,
+    target: &str,
+    req: serde_json::Value,
+) -> io::Result<serde_json::Value> {
+    let uri = service_uri(service, region, opts.use_fips, opts.use_dual_stack);
+    let request = http::Request::builder()
+        .method(Method::POST)
+        .uri(&uri)
+        .header("content-type", "application/x-amz-json-1.1")
+        .header("x-amz-target", target)
+        .body(serde_json::to_vec(&req)?)
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to build {target} request {}", e),
+            )
+        })?;
+    let (status, _, bytes) = send_signed(
+        shared_config,
+        region,
+        service,
+        request,
+        SigningSettings::default(),
+    )
+    .await
+    .map_err(|e| Error::new(e.kind(), format!("failed {target} {}", e)))?;
+    if status != StatusCode::OK {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed {target} {status} '{}'",
+                String::from_utf8_lossy(&bytes)
+            ),
+        ));
+    }
+    serde_json::from_slice(&bytes).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("failed to parse {target} response {}", e),
+        )
+    })
+}
+
+/// Signs the request (SigV4) with the credentials of the SDK config, sends it
+/// with the HTTP client of the SDK config, and returns the status, the headers,
+/// and the body of any response, for the callers to handle the non-2xx statuses
+/// (e.g., S3 "412 Precondition Failed" of the conditional writes).
+pub async fn send_signed(
+    shared_config: &SdkConfig,
+    region: &str,
+    service: &str,
+    mut request: http::Request<Vec<u8>>,
+    settings: SigningSettings,
+) -> io::Result<(StatusCode, http::HeaderMap, Vec<u8>)> {
+    let creds = shared_config
+        .credentials_provider()
+        .ok_or_else(|| Error::new(ErrorKind::Other, "no AWS credentials provider"))?
+        .provide_credentials()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to load credentials {}", e),
+            )
+        })?;
+
+    let mut params = SigningParams::builder()
+        .access_key(creds.access_key_id())
+        .secret_key(creds.secret_access_key())
+        .region(region)
+        .service_name(service)
+        .time(SystemTime::now())
+        .settings(settings);
+    params.set_security_token(creds.session_token());
+    let params = params.build().map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to build signing params {}", e),
+        )
+    })?;
+    let (instructions, _) = sign(SignableRequest::from(&request), &params)
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to sign {}", e)))?
+        .into_parts();
+    instructions.apply_to_request(&mut request);
+
+    let mut connector = shared_config
+        .http_connector()
+        .and_then(|c| c.connector(&ConnectorSettings::default(), shared_config.sleep_impl()))
+        .ok_or_else(|| Error::new(ErrorKind::Other, "no HTTP connector in the SDK config"))?;
+
+    let resp = connector
+        .call(request.map(SdkBody::from))
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to send {}", e)))?;
+    let status = resp.status();
+    let headers = resp.headers().clone();
+    let bytes = body::to_bytes(resp.into_body())
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to read response {}", e)))?;
+    Ok((status, headers, bytes.to_vec()))
+}
+
+/// Resolves the FIPS and/or dual-stack endpoint of the service.
+/// ref. <https://docs.aws.amazon.com/general/latest/gr/rande.html#FIPS-endpoints>
+/// ref. <https://docs.aws.amazon.com/general/latest/gr/rande.html#dual-stack-endpoints>
+#[derive(Debug, Clone)]
+struct ServiceEndpoint {
+    service: String,
+    use_fips: bool,
+    use_dual_stack: bool,
+}
+
+impl ResolveAwsEndpoint for ServiceEndpoint {
+    fn resolve_endpoint(&self, region: &Region) -> Result<AwsEndpoint, BoxError> {
+        let uri = service_uri(
+            &self.service,
+            region.as_ref(),
+            self.use_fips,
+            self.use_dual_stack,
+        );
+        log::debug!("resolved {} endpoint {uri}", self.service);
+
+        Ok(AwsEndpoint::new(
+            Endpoint::immutable(uri)?,
+            CredentialScope::builder()
+                .region(SigningRegion::from(region.clone()))
+                .build(),
+        ))
+    }
+}
+
+/// Returns the endpoint URI of the service in the region.
+pub fn service_uri(service: &str, region: &str, use_fips: bool, use_dual_stack: bool) -> String {
+    let service = if use_fips {
+        format!("{service}-fips")
+    } else {
+        service.to_string()
+    };
+    let china = region.starts_with("cn-");
+    let suffix = match (use_dual_stack, china) {
+        (true, false) => "api.aws",
+        (true, true) => "api.amazonwebservices.com.cn",
+        (false, false) => "amazonaws.com",
+        (false, true) => "amazonaws.com.cn",
+    };
+    format!("https://{service}.{region}.{suffix}")
+}
+
+fn build_connector(opts: &Options) -> io::Result<DynConnector> {
+    let tls = tls::client_config(&opts.ca_bundle)?;
+
+    let mut http = HyperHttpConnector::new();
+    http.enforce_http(false);
+
+    if opts.https_proxy.is_empty() {
+        let https = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(tls)
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(http);
+        return Ok(DynConnector::new(RequestIdLogger {
+            inner: hyper_ext::Adapter::builder().build(https),
+            ratelimit: opts.ratelimit.clone(),
+        }));
+    }
+
+    log::info!("routing AWS API calls via proxy {}", opts.https_proxy);
+    let proxy_uri: Uri = opts.https_proxy.parse().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid proxy URL '{}' ({})", opts.https_proxy, e),
+        )
+    })?;
+    let proxy = TunnelConnector { proxy_uri };
+    let https = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls)
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(proxy);
+    Ok(DynConnector::new(RequestIdLogger {
+        inner: hyper_ext::Adapter::builder().build(https),
+        ratelimit: opts.ratelimit.clone(),
+    }))
+}
+
+/// Logs the request ID of every AWS API call (at "warn" for the failed ones),
+/// to diagnose the throttling and permission errors with CloudTrail or AWS support.
+/// Filter with "--log-filter=aws_ip_provisioner::sdk=debug" to log all calls.
+/// Waits for the rate limit before every call, and feeds the throttled and
+/// the successful calls to the adaptive rate limit (see "ratelimit::Limiter::on_throttled"),
+/// including the SDK's own retries.
+#[derive(Debug, Clone)]
+struct RequestIdLogger<S> {
+    inner: S,
+    ratelimit: Arc<ratelimit::Limiter>,
+}
+
+impl<S> Service<Request<SdkBody>> for RequestIdLogger<S>
+where
+    S: Service<Request<SdkBody>, Response = Response<SdkBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<SdkBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<SdkBody>, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<SdkBody>) -> Self::Future {
+        let host = req.uri().host().unwrap_or_default().to_string();
+        let action = action(&req);
+        // the ready service is taken for the call after the rate limit wait,
+        // leaving its clone in place (ref. "tower::Service" docs)
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let ratelimit = self.ratelimit.clone();
+        Box::pin(async move {
+            ratelimit.acquire().await;
+            let resp = inner.call(req).await?;
+            // e.g., "x-amzn-requestid" for EC2 and STS, "x-amz-request-id" for S3
+            let request_id = ["x-amzn-requestid", "x-amz-request-id"]
+                .iter()
+                .find_map(|k| resp.headers().get(*k))
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("-")
+                .to_string();
+            let status = resp.status();
+            if !(status.is_client_error() || status.is_server_error()) {
+                log::debug!("{host} {action} returned {status} (request ID {request_id})");
+                ratelimit.on_success();
+                return Ok(resp);
+            }
+
+            // the error body is small, buffered to tell the throttling from the other errors
+            let (parts, b) = resp.into_parts();
+            let b = body::to_bytes(b).await.unwrap_or_default();
+            match ratelimit::throttling_code(status.as_u16(), &b) {
+                Some(code) => {
+                    log::warn!(
+                        "{host} {action} returned {status} (request ID {request_id}, throttled={code})"
+                    );
+                    ratelimit.on_throttled(code);
+                }
+                None => log::warn!("{host} {action} returned {status} (request ID {request_id})"),
+            }
+            Ok(Response::from_parts(parts, SdkBody::from(b)))
+        })
+    }
+}
+
+/// Returns the API action of the query protocol request (e.g., "DescribeAddresses"),
+/// or the path for the others (e.g., IMDS credentials).
+fn action(req: &Request<SdkBody>) -> String {
+    req.body()
+        .bytes()
+        .and_then(|b| {
+            String::from_utf8_lossy(b)
+                .split('&')
+                .find_map(|kv| kv.strip_prefix("Action="))
+                .map(|v| v.to_string())
+        })
+        .unwrap_or_else(|| req.uri().path().to_string())
+}
+
+/// Connects to the target host via the HTTP CONNECT tunnel of the proxy,
+/// so that TLS is negotiated end-to-end with the AWS endpoint.
+/// ref. <https://www.rfc-editor.org/rfc/rfc9110#name-connect>
+#[derive(Debug, Clone)]
+struct TunnelConnector {
+    proxy_uri: Uri,
+}
+
+impl Service<Uri> for TunnelConnector {
+    type Response = TcpStream;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let proxy_uri = self.proxy_uri.clone();
+        Box::pin(async move {
+            let proxy_host = proxy_uri.host().unwrap_or_default();
+            let proxy_port = proxy_uri.port_u16().unwrap_or(80);
+            let host = dst.host().unwrap_or_default();
+            let port = dst
+                .port_u16()
+                .unwrap_or(if dst.scheme_str() == Some("http") {
+                    80
+                } else {
+                    443
+                });
+
+            let mut stream = TcpStream::connect((proxy_host, proxy_port)).await?;
+            let req = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n\r\n");
+            stream.write_all(req.as_bytes()).await?;
+
+            // read the proxy response header, byte by byte not to consume the tunneled bytes
+            let mut resp = Vec::new();
+            while !resp.ends_with(b"\r\n\r\n") {
+                if resp.len() > 8192 {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "proxy CONNECT response header too large",
+                    ));
+                }
+                let b = stream.read_u8().await?;
+                resp.push(b);
+            }
+            let status_line = String::from_utf8_lossy(&resp);
+            let status_line = status_line.lines().next().unwrap_or_default();
+            if status_line.split_whitespace().nth(1) != Some("200") {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("proxy CONNECT {host}:{port} failed '{status_line}'"),
+                ));
+            }
+            Ok(stream)
+        })
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    io::{self, Error, ErrorKind},
+    sync::Mutex,
+};
+
+use aws_types::SdkConfig;
+use tokio::time::{Duration, Instant};
+
+use crate::sdk;
+
+/// Where to read a credential of a third-party provider (e.g., DNS API token),
+/// so that the secret never appears in flags or config files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// "env:<NAME>" reads the environment variable.
+    Env(String),
+    /// "file:<PATH>" reads the file (e.g., mounted Kubernetes secret).
+    File(String),
+    /// "ssm:<PATH>" reads the SSM parameter, with decryption.
+    Ssm(String),
+    /// "secretsmanager:<NAME>" reads the Secrets Manager secret string.
+    SecretsManager(String),
+}
+
+impl Source {
+    /// Parses "env:<NAME>", "file:<PATH>", "ssm:<PATH>", or "secretsmanager:<NAME>".
+    /// "env" without the name reads "default_env".
+    pub fn parse(s: &str, default_env: &str) -> io::Result<Self> {
+        if s == "env" {
+            return Ok(Source::Env(default_env.to_string()));
+        }
+        let (kind, v) = s.split_once(':').ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid credential source '{s}' (expected env|file:<PATH>|ssm:<PATH>|secretsmanager:<NAME>)"),
+            )
+        })?;
+        if v.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("empty credential source '{s}'"),
+            ));
+        }
+        match kind {
+            "env" => Ok(Source::Env(v.to_string())),
+            "file" => Ok(Source::File(v.to_string())),
+            "ssm" => Ok(Source::Ssm(v.to_string())),
+            "secretsmanager" => Ok(Source::SecretsManager(v.to_string())),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown credential source '{kind}'"),
+            )),
+        }
+    }
+}
+
+/// Reads the secret from the source string (see "Source::parse") through the
+/// cache of the run (see "sdk::Options"), loading the AWS config only for
+/// SSM and Secrets Manager sources (e.g., other cloud providers without AWS credentials).
+pub async fn read(s: &str, default_env: &str, opts: &sdk::Options) -> io::Result<String> {
+    let source = Source::parse(s, default_env)?;
+    opts.secrets.get(&source, opts).await
+}
+
+/// Drops the cached secret of the source string, to re-fetch on the next "read"
+/// (e.g., the provider rejected the credential after a rotation).
+pub fn invalidate(s: &str, default_env: &str, opts: &sdk::Options) {
+    if let Ok(source) = Source::parse(s, default_env) {
+        opts.secrets.invalidate(&source);
+    }
+}
+
+struct Entry {
+    value: String,
+    version: String,
+    fetched_at: Instant,
+}
+
+/// Caches the secrets from SSM and Secrets Manager for "ttl" (zero by default,
+/// to fetch on every use), so that rotated secrets are picked up without
+/// re-fetching on every use. Environment variables and files are always re-read,
+/// since they are cheap and files can be rotated in place (e.g., Kubernetes secret volumes).
+#[derive(Default)]
+pub struct SecretCache {
+    ttl: Mutex<Duration>,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl fmt::Debug for SecretCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // never the secret values
+        f.debug_struct("SecretCache")
+            .field("ttl", &*self.ttl.lock().unwrap())
+            .finish_non_exhaustive()
+    }
+}
+
+impl SecretCache {
+    /// Sets the TTL of the cached secrets (e.g., "--secret-cache-ttl-seconds").
+    pub fn init(&self, ttl: Duration) {
+        *self.ttl.lock().unwrap() = ttl;
+    }
+
+    /// Returns the secret value, fetching it if not cached or expired.
+    pub async fn get(&self, source: &Source, opts: &sdk::Options) -> io::Result<String> {
+        let key = match source {
+            Source::Env(name) => {
+                return env::var(name).map_err(|e| {
+                    Error::new(
+                        ErrorKind::NotFound,
+                        format!("failed to read environment variable {name} ({})", e),
+                    )
+                });
+            }
+            Source::File(path) => {
+                return Ok(fs::read_to_string(path)?.trim().to_string());
+            }
+            Source::Ssm(path) => format!("ssm:{path}"),
+            Source::SecretsManager(name) => format!("secretsmanager:{name}"),
+        };
+        let ttl = *self.ttl.lock().unwrap();
+        if let Some(entry) = self.entries.lock().unwrap().get(&key) {
+            if entry.fetched_at.elapsed() < ttl {
+                return Ok(entry.value.clone());
+            }
+        }
+
+        let shared_config = sdk::load_config(None, opts).await?;
+        let (value, version) = match source {
+            Source::Ssm(path) => get_parameter(&shared_config, opts, path).await?,
+            Source::SecretsManager(name) => get_secret_value(&shared_config, opts, name).await?,
+            _ => unreachable!(),
+        };
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(prev) = entries.get(&key) {
+            if prev.version != version {
+                log::info!(
+                    "secret {key} rotated (version {} -> {version})",
+                    prev.version
+                );
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                value: value.clone(),
+                version,
+                fetched_at: Instant::now(),
+            },
+        );
+        Ok(value)
+    }
+
+    /// Drops the cached secret, to re-fetch on the next "get".
+    pub fn invalidate(&self, source: &Source) {
+        let mut entries = self.entries.lock().unwrap();
+        match source {
+            Source::Ssm(path) => entries.remove(&format!("ssm:{path}")),
+            Source::SecretsManager(name) => entries.remove(&format!("secretsmanager:{name}")),
+            _ => None,
+        };
+    }
+}
+
+/// ref. <https://docs.aws.amazon.com/systems-manager/latest/APIReference/API_GetParameter.html>
+async fn get_parameter(
+    shared_config: &SdkConfig,
+    opts: &sdk::Options,
+    path: &str,
+) -> io::Result<(String, String)> {
+    log::info!("fetching SSM parameter {path}");
+    let req = serde_json::json!({ "Name": path, "WithDecryption": true });
+    let resp = call(shared_config, opts, "ssm", "AmazonSSM.GetParameter", req).await?;
+    let param = &resp["Parameter"];
+    let value = param["Value"].as_str().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("SSM parameter {path} has no value"),
+        )
+    })?;
+    Ok((value.to_string(), param["Version"].to_string()))
+}
+
+/// ref. <https://docs.aws.amazon.com/secretsmanager/latest/apireference/API_GetSecretValue.html>
+async fn get_secret_value(
+    shared_config: &SdkConfig,
+    opts: &sdk::Options,
+    name: &str,
+) -> io::Result<(String, String)> {
+    log::info!("fetching Secrets Manager secret {name}");
+    let req = serde_json::json!({ "SecretId": name });
+    let resp = call(
+        shared_config,
+        opts,
+        "secretsmanager",
+        "secretsmanager.GetSecretValue",
+        req,
+    )
+    .await?;
+    let value = resp["SecretString"].as_str().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("secret {name} has no secret string (binary secrets are not supported)"),
+        )
+    })?;
+    Ok((
+        value.to_string(),
+        resp["VersionId"].as_str().unwrap_or_default().to_string(),
+    ))
+}
+
+/// Sends the signed JSON 1.1 request in the region of the SDK config.
+async fn call(
+    shared_config: &SdkConfig,
+    opts: &sdk::Options,
+    service: &str,
+    target: &str,
+    req: serde_json::Value,
+) -> io::Result<serde_json::Value> {
+    let region = shared_config
+        .region()
+        .map(|r| r.as_ref().to_string())
+        .unwrap_or_else(|| String::from("us-west-2"));
+    sdk::call_json(shared_config, opts, &region, service, target, req).await
+}
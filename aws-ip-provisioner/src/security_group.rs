@@ -0,0 +1,256 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Error, ErrorKind},
+};
+
+use aws_sdk_ec2::model::{Filter, IpPermission, IpRange};
+
+use crate::{command::Flags, ec2, eip, prefix_list::DESCRIPTION_PREFIX};
+
+/// Port range of the ingress rules, e.g., "tcp:30303" or "udp:26656-26657".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortRange {
+    pub protocol: String,
+    pub from_port: i32,
+    pub to_port: i32,
+}
+
+/// Parses the comma-separated port ranges (e.g., "tcp:30303,udp:30303,tcp:26656-26657").
+pub fn parse_port_ranges(s: &str) -> io::Result<Vec<PortRange>> {
+    let invalid = |v: &str, why: &str| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid port range '{v}' ({why}, expected tcp|udp:<port>[-<port>])"),
+        )
+    };
+    let mut ranges = Vec::new();
+    for v in s.split(',').map(|v| v.trim()).filter(|v| !v.is_empty()) {
+        let (protocol, ports) = v.split_once(':').ok_or_else(|| invalid(v, "no protocol"))?;
+        if protocol != "tcp" && protocol != "udp" {
+            return Err(invalid(v, "unknown protocol"));
+        }
+        let (from, to) = ports.split_once('-').unwrap_or((ports, ports));
+        let (from_port, to_port) = match (from.parse::<u16>(), to.parse::<u16>()) {
+            (Ok(from), Ok(to)) if from > 0 && from <= to => (from as i32, to as i32),
+            _ => return Err(invalid(v, "invalid ports")),
+        };
+        ranges.push(PortRange {
+            protocol: protocol.to_string(),
+            from_port,
+            to_port,
+        });
+    }
+    Ok(ranges)
+}
+
+/// Ingress rule from one EIP of the fleet.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Rule {
+    pub protocol: String,
+    pub from_port: i32,
+    pub to_port: i32,
+    /// e.g., "203.0.113.10/32"
+    pub cidr: String,
+    pub description: String,
+}
+
+impl Rule {
+    fn key(&self) -> (&str, i32, i32, &str) {
+        (&self.protocol, self.from_port, self.to_port, &self.cidr)
+    }
+
+    pub fn is_managed(&self) -> bool {
+        self.description.starts_with(DESCRIPTION_PREFIX)
+    }
+}
+
+/// Returns the rules of every port range from every EIP ("public IP", "Id" tag value).
+pub fn desired_rules(port_ranges: &[PortRange], fleet: &[(String, String)]) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for (public_ip, id) in fleet {
+        for r in port_ranges {
+            rules.push(Rule {
+                protocol: r.protocol.clone(),
+                from_port: r.from_port,
+                to_port: r.to_port,
+                cidr: format!("{public_ip}/32"),
+                description: format!("{DESCRIPTION_PREFIX}{id}"),
+            });
+        }
+    }
+    rules.sort();
+    rules
+}
+
+/// Changes to make the security group match the fleet.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub authorize: Vec<Rule>,
+    /// IDs of the managed rules to revoke.
+    pub revoke: Vec<String>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.authorize.is_empty() && self.revoke.is_empty()
+    }
+}
+
+/// Returns the rules to authorize (desired, not in the group), and the
+/// managed rules ("security group rule ID", rule) to revoke (not desired).
+/// The same rules added by hand are left alone.
+pub fn plan(current: &[(String, Rule)], desired: &[Rule]) -> Plan {
+    let existing: BTreeSet<_> = current.iter().map(|(_, r)| r.key()).collect();
+    let wanted: BTreeSet<_> = desired.iter().map(|r| r.key()).collect();
+    let mut authorize: Vec<Rule> = desired
+        .iter()
+        .filter(|r| !existing.contains(&r.key()))
+        .cloned()
+        .collect();
+    authorize.sort();
+    authorize.dedup_by(|a, b| a.key() == b.key());
+    let mut revoke: Vec<String> = current
+        .iter()
+        .filter(|(_, r)| r.is_managed() && !wanted.contains(&r.key()))
+        .map(|(id, _)| id.clone())
+        .collect();
+    revoke.sort();
+    Plan { authorize, revoke }
+}
+
+/// Returns the IPv4 ingress rules of the group with their rule IDs.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_DescribeSecurityGroupRules.html>
+pub async fn ingress_rules(
+    ec2_manager: &ec2::Manager,
+    group_id: &str,
+) -> io::Result<Vec<(String, Rule)>> {
+    let mut rules = Vec::new();
+    let mut next_token: Option<String> = None;
+    loop {
+        let resp = ec2_manager
+            .client()
+            .describe_security_group_rules()
+            .filters(Filter::builder().name("group-id").values(group_id).build())
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed describe_security_group_rules {:?}", e),
+                )
+            })?;
+        for r in resp.security_group_rules().unwrap_or_default() {
+            let cidr = match r.cidr_ipv4() {
+                Some(v) if r.is_egress() == Some(false) => v,
+                _ => continue,
+            };
+            rules.push((
+                r.security_group_rule_id().unwrap_or_default().to_string(),
+                Rule {
+                    protocol: r.ip_protocol().unwrap_or_default().to_string(),
+                    from_port: r.from_port().unwrap_or_default(),
+                    to_port: r.to_port().unwrap_or_default(),
+                    cidr: cidr.to_string(),
+                    description: r.description().unwrap_or_default().to_string(),
+                },
+            ));
+        }
+        next_token = resp.next_token().map(|v| v.to_string());
+        if next_token.is_none() {
+            break;
+        }
+    }
+    Ok(rules)
+}
+
+/// Makes the managed ingress rules of the group match the desired rules,
+/// and returns what changed. The rules that another node authorized or
+/// revoked in the meantime are not errors (e.g., all the nodes sync at once).
+pub async fn sync(
+    ec2_manager: &ec2::Manager,
+    group_id: &str,
+    desired: &[Rule],
+) -> io::Result<Plan> {
+    let current = ingress_rules(ec2_manager, group_id).await?;
+    let plan = plan(&current, desired);
+    if plan.is_empty() {
+        log::info!("security group {group_id} is up to date");
+        return Ok(plan);
+    }
+
+    if !plan.revoke.is_empty() {
+        log::info!("revoking {} rules of {group_id}", plan.revoke.len());
+        let ret = ec2_manager
+            .client()
+            .revoke_security_group_ingress()
+            .group_id(group_id)
+            .set_security_group_rule_ids(Some(plan.revoke.clone()))
+            .send()
+            .await;
+        if let Err(e) = ret {
+            let msg = format!("{:?}", e);
+            if !msg.contains("InvalidSecurityGroupRuleId.NotFound") {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("failed revoke_security_group_ingress {msg}"),
+                ));
+            }
+            log::warn!("rules of {group_id} already revoked '{msg}'");
+        }
+    }
+    // one call per rule, so that a duplicate does not fail the others
+    for r in plan.authorize.iter() {
+        log::info!(
+            "authorizing {} {}-{} from {} in {group_id}",
+            r.protocol,
+            r.from_port,
+            r.to_port,
+            r.cidr
+        );
+        let ret = ec2_manager
+            .client()
+            .authorize_security_group_ingress()
+            .group_id(group_id)
+            .ip_permissions(
+                IpPermission::builder()
+                    .ip_protocol(&r.protocol)
+                    .from_port(r.from_port)
+                    .to_port(r.to_port)
+                    .ip_ranges(
+                        IpRange::builder()
+                            .cidr_ip(&r.cidr)
+                            .description(&r.description)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .send()
+            .await;
+        if let Err(e) = ret {
+            let msg = format!("{:?}", e);
+            if !msg.contains("InvalidPermission.Duplicate") {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("failed authorize_security_group_ingress {msg}"),
+                ));
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// Syncs "--sync-security-group-id" with the associated EIPs of the "Kind"
+/// on "--port-ranges".
+pub async fn sync_fleet(ec2_manager: &ec2::Manager, opts: &Flags) -> io::Result<Plan> {
+    let port_ranges = parse_port_ranges(&opts.port_ranges)?;
+    let fleet = eip::describe_fleet(
+        ec2_manager,
+        &opts.kind_tag_key,
+        &opts.kind_tag_value,
+        &opts.id_tag_key,
+    )
+    .await?;
+    let desired = desired_rules(&port_ranges, &fleet);
+    sync(ec2_manager, &opts.sync_security_group_id, &desired).await
+}
@@ -0,0 +1,309 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Error, ErrorKind},
+    path::Path,
+};
+
+use aws_sdk_ec2::model::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::{conflict, ec2};
+
+/// Mounted EIP file (e.g., "/data/eip.yaml"), with the facts of the EIP the
+/// provisioner already knew, so that the downstream consumers need not
+/// describe the EIP again. "allocation_id" and "public_ip" are the
+/// "ec2::Eip" fields, and the others are empty (or zero) until known.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct State {
+    pub allocation_id: String,
+    pub public_ip: String,
+
+    /// Account and region of the EIP, to tell the volume re-attached to
+    /// an instance elsewhere, where the EIP cannot be used.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub account_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub region: String,
+
+    /// Target private IP ("--private-ip-address"), and the instance it was
+    /// recorded on, since a private IP means nothing on another instance.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub private_ip_address: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub private_ip_instance_id: String,
+    /// IPv6 address managed with the EIP ("--dual-stack").
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub ipv6_address: String,
+
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub association_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub instance_id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub network_interface_id: String,
+    /// Private IP of the network interface that the EIP maps to.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub association_private_ip_address: String,
+    /// Network border group (e.g., "us-west-2", or a Local Zone).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub network_border_group: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+
+    /// Unix timestamps in seconds, zero if not known.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub allocated_at: u64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub associated_at: u64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub updated_at: u64,
+
+    /// Version of the provisioner that last wrote the file.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub tool_version: String,
+
+    /// Keys this version does not know (e.g., written by a newer version), kept as is.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
+
+impl State {
+    /// Loads the mounted EIP file. Returns the default (empty) state if the file
+    /// does not exist.
+    pub fn load(file_path: &str) -> io::Result<Self> {
+        if !Path::new(file_path).exists() {
+            return Ok(Self::default());
+        }
+        let d = fs::read_to_string(file_path)?;
+        serde_yaml::from_str(&d).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid YAML {file_path} '{}'", e),
+            )
+        })
+    }
+
+    /// Loads the mounted EIP file, failing if it does not exist (e.g., not to
+    /// record the values of an EIP before the EIP itself).
+    pub fn load_existing(file_path: &str) -> io::Result<Self> {
+        if !Path::new(file_path).exists() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("mounted EIP file {file_path} does not exist"),
+            ));
+        }
+        Self::load(file_path)
+    }
+
+    /// Writes the mounted EIP file, stamped with the version of the provisioner.
+    /// Renamed into place, so that a concurrent read never sees a partial file.
+    pub fn save(&mut self, file_path: &str) -> io::Result<()> {
+        self.tool_version = env!("CARGO_PKG_VERSION").to_string();
+        if let Some(parent_dir) = Path::new(file_path).parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+        let d = serde_yaml::to_string(self).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize {file_path} '{}'", e),
+            )
+        })?;
+        let tmp = format!("{file_path}.tmp");
+        fs::write(&tmp, d)?;
+        fs::rename(&tmp, file_path)
+    }
+
+    /// Returns the target private IP recorded on the instance, empty if none
+    /// or if recorded on another instance (e.g., the volume re-attached to
+    /// a replacement instance). The files written before the instance was
+    /// recorded fall back to the associated instance.
+    pub fn private_ip_address_of(&self, ec2_instance_id: &str) -> &str {
+        let owner = if self.private_ip_instance_id.is_empty() {
+            &self.instance_id
+        } else {
+            &self.private_ip_instance_id
+        };
+        if owner.is_empty() || owner == ec2_instance_id {
+            &self.private_ip_address
+        } else {
+            ""
+        }
+    }
+
+    /// Returns the EIP of the state.
+    pub fn eip(&self) -> ec2::Eip {
+        ec2::Eip {
+            allocation_id: self.allocation_id.clone(),
+            public_ip: self.public_ip.clone(),
+        }
+    }
+
+    /// Returns true if the state recorded the association of the EIP.
+    pub fn is_associated(&self) -> bool {
+        !self.association_id.is_empty()
+    }
+
+    /// Returns true if the recorded account or region is not the given one.
+    /// The empty (not known) ones match any.
+    pub fn is_foreign(&self, account_id: &str, region: &str) -> bool {
+        let differs = |a: &str, b: &str| !a.is_empty() && !b.is_empty() && a != b;
+        differs(&self.account_id, account_id) || differs(&self.region, region)
+    }
+
+    /// Returns the tag value, empty if not recorded.
+    pub fn tag(&self, key: &str) -> &str {
+        self.tags.get(key).map(|v| v.as_str()).unwrap_or_default()
+    }
+
+    /// Updates the state with the described EIP, clearing the association if
+    /// the EIP is not associated. "associated_at" is set on a new association.
+    pub fn update(&mut self, addr: &Address, now: u64) {
+        let s = |v: Option<&str>| v.unwrap_or_default().to_string();
+        self.allocation_id = s(addr.allocation_id());
+        self.public_ip = s(addr.public_ip());
+        let association_id = s(addr.association_id());
+        if association_id.is_empty() {
+            self.associated_at = 0;
+        } else if association_id != self.association_id {
+            self.associated_at = now;
+        }
+        self.association_id = association_id;
+        self.instance_id = s(addr.instance_id());
+        self.network_interface_id = s(addr.network_interface_id());
+        self.association_private_ip_address = s(addr.private_ip_address());
+        self.network_border_group = s(addr.network_border_group());
+        self.tags = addr
+            .tags()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|t| Some((t.key()?.to_string(), t.value()?.to_string())))
+            .collect();
+        if let Ok(allocated_at) = self.tag(conflict::ALLOCATED_AT_TAG_KEY).parse::<u64>() {
+            self.allocated_at = allocated_at;
+        }
+        self.updated_at = now;
+    }
+}
+
+/// Loads the EIP of the mounted EIP file, failing if the file does not
+/// exist or holds no EIP.
+pub fn load_eip(file_path: &str) -> io::Result<ec2::Eip> {
+    let state = State::load_existing(file_path)?;
+    if state.allocation_id.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{file_path} holds no EIP"),
+        ));
+    }
+    Ok(state.eip())
+}
+
+/// Writes the EIP to the mounted EIP file, keeping the values recorded next
+/// to it. The facts of the previous EIP (e.g., its association) are dropped
+/// if the EIP changed, while the target private IP, the IPv6 address, and
+/// the unknown keys carry over.
+pub fn record_eip(file_path: &str, eip: &ec2::Eip) -> io::Result<()> {
+    let prev = State::load(file_path)?;
+    let mut state = if prev.allocation_id == eip.allocation_id {
+        prev
+    } else {
+        State {
+            private_ip_address: prev.private_ip_address,
+            private_ip_instance_id: prev.private_ip_instance_id,
+            ipv6_address: prev.ipv6_address,
+            extra: prev.extra,
+            ..Default::default()
+        }
+    };
+    state.allocation_id = eip.allocation_id.clone();
+    state.public_ip = eip.public_ip.clone();
+    state.save(file_path)
+}
+
+/// Records the target private IP of the instance next to the EIP, so that
+/// the later runs on the instance keep the mapping without the flag (empty
+/// to clear it).
+pub fn record_private_ip_address(
+    file_path: &str,
+    private_ip_address: &str,
+    ec2_instance_id: &str,
+) -> io::Result<()> {
+    let mut state = State::load_existing(file_path)?;
+    state.private_ip_address = private_ip_address.to_string();
+    state.private_ip_instance_id = if private_ip_address.is_empty() {
+        String::new()
+    } else {
+        ec2_instance_id.to_string()
+    };
+    state.save(file_path)
+}
+
+/// Records the IPv6 address managed with the EIP (empty to clear it).
+pub fn record_ipv6_address(file_path: &str, ipv6_address: &str) -> io::Result<()> {
+    let mut state = State::load_existing(file_path)?;
+    state.ipv6_address = ipv6_address.to_string();
+    state.save(file_path)
+}
+
+/// Records the described EIP in the mounted EIP file (see "State::update"),
+/// keeping the other recorded values. Fails if the file holds another EIP.
+pub fn record(file_path: &str, addr: &Address, now: u64) -> io::Result<State> {
+    let mut state = State::load(file_path)?;
+    if !state.allocation_id.is_empty() && addr.allocation_id() != Some(&state.allocation_id) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{file_path} holds EIP {}, not {:?}",
+                state.allocation_id,
+                addr.allocation_id()
+            ),
+        ));
+    }
+    state.update(addr, now);
+    state.save(file_path)?;
+    Ok(state)
+}
+
+/// Records the association ID that the associate call returned, with the
+/// instance that made it, so that the next runs verify the association
+/// rather than the allocation ID only.
+pub fn record_association(
+    file_path: &str,
+    association_id: &str,
+    instance_id: &str,
+    now: u64,
+) -> io::Result<()> {
+    let mut state = State::load(file_path)?;
+    if state.association_id != association_id {
+        state.associated_at = now;
+    }
+    state.association_id = association_id.to_string();
+    state.instance_id = instance_id.to_string();
+    state.updated_at = now;
+    state.save(file_path)
+}
+
+/// Records the account and the region of the EIP, unless already recorded
+/// (e.g., kept with "--on-foreign-state=ignore"), or not known.
+pub fn record_location(file_path: &str, account_id: &str, region: &str) -> io::Result<()> {
+    let mut state = State::load(file_path)?;
+    let mut changed = false;
+    for (recorded, v) in [
+        (&mut state.account_id, account_id),
+        (&mut state.region, region),
+    ] {
+        if recorded.is_empty() && !v.is_empty() {
+            *recorded = v.to_string();
+            changed = true;
+        }
+    }
+    if changed {
+        state.save(file_path)?;
+    }
+    Ok(())
+}
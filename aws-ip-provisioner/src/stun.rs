@@ -0,0 +1,181 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    time::{timeout, Duration},
+};
+
+use crate::context::Context;
+
+/// Gauge of the discrepancy between the STUN-discovered public IP and the EIP
+/// (1 if they differ, 0 if they match).
+pub const MISMATCH_GAUGE: &str = "eip_public_ip_mismatch";
+
+/// STUN Binding request/response, and the magic cookie of RFC 5389.
+/// ref. <https://www.rfc-editor.org/rfc/rfc5389#section-6>
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+
+/// Timeout of each request, retransmitted up to "ATTEMPTS" times over UDP.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+const ATTEMPTS: usize = 3;
+
+/// Returns the Binding request with the transaction ID.
+pub fn binding_request(transaction_id: &[u8; 12]) -> Vec<u8> {
+    let mut b = Vec::with_capacity(20);
+    b.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // no attributes
+    b.extend_from_slice(&0u16.to_be_bytes());
+    b.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    b.extend_from_slice(transaction_id);
+    b
+}
+
+/// Parses the Binding success response of the transaction, returning the
+/// reflexive transport address (XOR-MAPPED-ADDRESS, or MAPPED-ADDRESS of the
+/// RFC 3489 servers).
+pub fn parse_response(b: &[u8], transaction_id: &[u8; 12]) -> io::Result<SocketAddr> {
+    let invalid = |msg: &str| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid STUN response ({msg})"),
+        )
+    };
+    if b.len() < 20 {
+        return Err(invalid("too short"));
+    }
+    if u16::from_be_bytes([b[0], b[1]]) != BINDING_SUCCESS {
+        return Err(invalid("not a Binding success"));
+    }
+    if &b[8..20] != transaction_id {
+        return Err(invalid("transaction ID mismatch"));
+    }
+    let len = u16::from_be_bytes([b[2], b[3]]) as usize;
+    let attrs = b.get(20..20 + len).ok_or_else(|| invalid("truncated"))?;
+
+    let mut mapped = None;
+    let mut pos = 0;
+    while pos + 4 <= attrs.len() {
+        let typ = u16::from_be_bytes([attrs[pos], attrs[pos + 1]]);
+        let alen = u16::from_be_bytes([attrs[pos + 2], attrs[pos + 3]]) as usize;
+        let v = attrs
+            .get(pos + 4..pos + 4 + alen)
+            .ok_or_else(|| invalid("truncated attribute"))?;
+        match typ {
+            ATTR_XOR_MAPPED_ADDRESS => return address(v, Some(&b[4..20])),
+            ATTR_MAPPED_ADDRESS => mapped = Some(address(v, None)?),
+            _ => {}
+        }
+        // attributes are padded to 4 bytes
+        pos += 4 + ((alen + 3) & !3);
+    }
+    mapped.ok_or_else(|| invalid("no mapped address"))
+}
+
+/// Decodes the address attribute, XOR-ed with the magic cookie and the
+/// transaction ID ("xor") for XOR-MAPPED-ADDRESS.
+fn address(v: &[u8], xor: Option<&[u8]>) -> io::Result<SocketAddr> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "invalid STUN address attribute");
+    if v.len() < 4 {
+        return Err(invalid());
+    }
+    let mask = |i: usize| xor.map(|x| x[i]).unwrap_or(0);
+    let port = u16::from_be_bytes([v[2] ^ mask(0), v[3] ^ mask(1)]);
+    let ip = match v[1] {
+        0x01 if v.len() >= 8 => {
+            let o: Vec<u8> = (0..4).map(|i| v[4 + i] ^ mask(i)).collect();
+            IpAddr::V4(Ipv4Addr::new(o[0], o[1], o[2], o[3]))
+        }
+        0x02 if v.len() >= 20 => {
+            let mut o = [0u8; 16];
+            for (i, b) in o.iter_mut().enumerate() {
+                *b = v[4 + i] ^ mask(i);
+            }
+            IpAddr::V6(Ipv6Addr::from(o))
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+/// Discovers the public IPv4 that the traffic of the instance leaves with,
+/// as seen by the STUN server (e.g., "stun.l.google.com:19302").
+pub async fn discover(server: &str) -> io::Result<IpAddr> {
+    let server_addr = lookup_host(server)
+        .await?
+        .find(|a| a.is_ipv4())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("STUN server {server} has no IPv4 address"),
+            )
+        })?;
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(server_addr).await?;
+
+    let mut transaction_id = [0u8; 12];
+    for chunk in transaction_id.chunks_mut(4) {
+        chunk.copy_from_slice(&random_manager::u32().to_be_bytes());
+    }
+    let req = binding_request(&transaction_id);
+    let mut buf = [0u8; 512];
+    for attempt in 1..=ATTEMPTS {
+        socket.send(&req).await?;
+        match timeout(REQUEST_TIMEOUT, socket.recv(&mut buf)).await {
+            Ok(Ok(n)) => return parse_response(&buf[..n], &transaction_id).map(|a| a.ip()),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => log::debug!("STUN request {attempt} to {server} timed out"),
+        }
+    }
+    Err(Error::new(
+        ErrorKind::TimedOut,
+        format!("STUN server {server} did not respond after {ATTEMPTS} attempts"),
+    ))
+}
+
+/// Compares the EIP with the public IP that the traffic actually leaves with,
+/// setting the "eip_public_ip_mismatch" gauge. Returns the discovered IP,
+/// and the discrepancy (e.g., on every reconcile in "daemon" mode), if any.
+pub async fn check(
+    ctx: &Context,
+    server: &str,
+    public_ip: &str,
+) -> io::Result<(String, Option<String>)> {
+    let discovered = discover(server).await?.to_string();
+    if discovered == public_ip {
+        log::info!("STUN server {server} sees the EIP {public_ip}");
+        ctx.metrics.set_gauge(MISMATCH_GAUGE, 0.0);
+        return Ok((discovered, None));
+    }
+    ctx.metrics.set_gauge(MISMATCH_GAUGE, 1.0);
+    let msg = format!(
+        "STUN server {server} sees {discovered}, not the EIP {public_ip} -- check for a NAT gateway in the route of the subnet, or the EIP association"
+    );
+    Ok((discovered, Some(msg)))
+}
+
+/// Cross-checks the EIP after association, catching what IMDS does not reveal:
+/// a NAT gateway in the path (the default route of the subnet) or the EIP
+/// associated elsewhere. Reports the discrepancy in the summary (and the
+/// "effective_public_ip" resource), never failing the run.
+pub async fn cross_check(ctx: &Context, server: &str, public_ip: &str) {
+    match check(ctx, server, public_ip).await {
+        Ok((discovered, mismatch)) => {
+            ctx.summary.resource("effective_public_ip", &discovered);
+            if let Some(msg) = mismatch {
+                ctx.summary.warn(&msg);
+            }
+        }
+        Err(e) => ctx.summary.warn(&format!(
+            "failed to discover the public IP with STUN server {server} '{}'",
+            e
+        )),
+    }
+}
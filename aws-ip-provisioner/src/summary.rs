@@ -0,0 +1,352 @@
+use std::{
+    collections::BTreeMap,
+    error, fmt, fs,
+    io::{self, Error, ErrorKind},
+    process::ExitCode,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::context::Context;
+
+/// Version of the summary schema, bumped on incompatible changes.
+pub const VERSION: u64 = 1;
+
+/// Exit status when the retries of an operation are exhausted
+/// ("EX_TEMPFAIL" in sysexits.h), to retry the run later rather than page.
+pub const EXIT_RETRIES_EXHAUSTED: u8 = 75;
+
+/// Keys of "resources", in the schema.
+const RESOURCE_KEYS: &[&str] = &[
+    "instance_id",
+    "allocation_id",
+    "public_ip",
+    "ipv6",
+    "effective_public_ip",
+];
+
+/// Warnings, retries per operation, and resource IDs of the run.
+#[derive(Debug, Default)]
+pub struct Summary {
+    warnings: Mutex<Vec<String>>,
+    retries: Mutex<BTreeMap<String, u64>>,
+    resources: Mutex<BTreeMap<String, String>>,
+    skipped: Mutex<String>,
+    attempts: Mutex<BTreeMap<String, Vec<Attempt>>>,
+}
+
+impl Summary {
+    /// Logs the warning, and records it in the summary.
+    pub fn warn(&self, msg: &str) {
+        log::warn!("{msg}");
+        self.warnings.lock().unwrap().push(msg.to_string());
+    }
+
+    /// Records the resource ID (e.g., "allocation_id"), ignoring empty values.
+    pub fn resource(&self, key: &str, value: &str) {
+        if value.is_empty() {
+            return;
+        }
+        self.resources
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), value.to_string());
+    }
+
+    /// Marks the run as skipped for the reason (e.g., the instance needs no EIP).
+    pub fn skip(&self, reason: &str) {
+        *self.skipped.lock().unwrap() = reason.to_string();
+    }
+
+    fn attempt(&self, op: &str, attempt: Attempt) {
+        self.attempts
+            .lock()
+            .unwrap()
+            .entry(op.to_string())
+            .or_default()
+            .push(attempt);
+    }
+}
+
+/// Failed attempt of a retried operation.
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    /// Unix timestamp in milliseconds.
+    pub at: u64,
+    /// Error kind (e.g., "TimedOut").
+    pub code: String,
+    pub error: String,
+    /// Wait before the next attempt, zero for the last one.
+    pub backoff: Duration,
+}
+
+impl Attempt {
+    fn new(err: &Error, backoff: Duration) -> Self {
+        Self {
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            code: format!("{:?}", err.kind()),
+            error: err.to_string(),
+            backoff,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "at": self.at,
+            "code": self.code,
+            "error": self.error,
+            "backoff_seconds": self.backoff.as_secs_f64(),
+        })
+    }
+}
+
+/// Error of the operation whose retries are exhausted, with the attempt history
+/// so that postmortems need not reproduce the failure with debug logs.
+pub struct RetriesExhausted {
+    pub op: String,
+    pub attempts: Vec<Attempt>,
+}
+
+impl fmt::Display for RetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let last = self.attempts.last().map(|a| a.error.as_str());
+        write!(
+            f,
+            "{} (retries of {} exhausted after {} attempts:",
+            last.unwrap_or_default(),
+            self.op,
+            self.attempts.len()
+        )?;
+        for (i, a) in self.attempts.iter().enumerate() {
+            write!(
+                f,
+                " [{}] at {} {} '{}' backoff {:?};",
+                i + 1,
+                a.at,
+                a.code,
+                a.error,
+                a.backoff
+            )?;
+        }
+        write!(f, ")")
+    }
+}
+
+// same as the plain message errors, as printed on exit
+impl fmt::Debug for RetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+impl error::Error for RetriesExhausted {}
+
+/// Attempt history of one call of the retried operation (e.g., "imds"),
+/// also recorded in the summary of the run.
+pub struct History<'a> {
+    summary: &'a Summary,
+    op: String,
+    attempts: Vec<Attempt>,
+}
+
+impl<'a> History<'a> {
+    pub fn new(summary: &'a Summary, op: &str) -> Self {
+        Self {
+            summary,
+            op: op.to_string(),
+            attempts: Vec::new(),
+        }
+    }
+
+    /// Records the failed attempt before waiting for the backoff,
+    /// and increments the retries of the operation.
+    pub fn retry(&mut self, err: &Error, backoff: Duration) {
+        let attempt = Attempt::new(err, backoff);
+        *self
+            .summary
+            .retries
+            .lock()
+            .unwrap()
+            .entry(self.op.clone())
+            .or_insert(0) += 1;
+        self.summary.attempt(&self.op, attempt.clone());
+        self.attempts.push(attempt);
+    }
+
+    /// Records the last failed attempt, and returns the error of the same kind
+    /// with the attempt history. Returns the error as is if never retried.
+    pub fn exhausted(mut self, err: Error) -> Error {
+        if self.attempts.is_empty() {
+            return err;
+        }
+        let attempt = Attempt::new(&err, Duration::ZERO);
+        self.summary.attempt(&self.op, attempt.clone());
+        self.attempts.push(attempt);
+        Error::new(
+            err.kind(),
+            RetriesExhausted {
+                op: self.op,
+                attempts: self.attempts,
+            },
+        )
+    }
+}
+
+/// Returns the exit status of the run result,
+/// "EXIT_RETRIES_EXHAUSTED" if the retries of any operation are exhausted.
+pub fn exit_code(res: &io::Result<()>) -> ExitCode {
+    match res {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e)
+            if e.get_ref()
+                .map(|e| e.is::<RetriesExhausted>())
+                .unwrap_or(false) =>
+        {
+            ExitCode::from(EXIT_RETRIES_EXHAUSTED)
+        }
+        Err(_) => ExitCode::FAILURE,
+    }
+}
+
+/// Returns the summary of the run with the result.
+pub fn render(ctx: &Context, mode: &str, res: &io::Result<()>, total: Duration) -> Value {
+    let summary = &ctx.summary;
+    let skipped = summary.skipped.lock().unwrap().clone();
+    let (outcome, error) = match res {
+        Err(e) => ("failed", e.to_string()),
+        Ok(_) if !skipped.is_empty() => ("skipped", String::new()),
+        Ok(_) => ("succeeded", String::new()),
+    };
+
+    let recorded = summary.resources.lock().unwrap().clone();
+    let mut resources = Map::new();
+    for key in RESOURCE_KEYS.iter() {
+        resources.insert(
+            key.to_string(),
+            Value::from(recorded.get(*key).cloned().unwrap_or_default()),
+        );
+    }
+    let mut attempts = Map::new();
+    for (op, v) in summary.attempts.lock().unwrap().iter() {
+        attempts.insert(
+            op.clone(),
+            Value::from(v.iter().map(|a| a.to_json()).collect::<Vec<_>>()),
+        );
+    }
+    let mut phases = Map::new();
+    for (p, d) in ctx.timing.phases() {
+        phases.insert(p, Value::from(d.as_secs_f64()));
+    }
+
+    json!({
+        "version": VERSION,
+        "tool_version": env!("CARGO_PKG_VERSION"),
+        "mode": mode,
+        "outcome": outcome,
+        "skip_reason": skipped,
+        "error": error,
+        "resources": resources,
+        "durations": {"phases": phases, "total": total.as_secs_f64()},
+        "retries": summary.retries.lock().unwrap().clone(),
+        "attempts": attempts,
+        "warnings": summary.warnings.lock().unwrap().clone(),
+        "finished_at": SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    })
+}
+
+/// Writes the summary of the run as JSON.
+pub fn write(
+    ctx: &Context,
+    file_path: &str,
+    mode: &str,
+    res: &io::Result<()>,
+    total: Duration,
+) -> io::Result<()> {
+    let d = serde_json::to_vec_pretty(&render(ctx, mode, res, total)).map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to serialize summary {}", e),
+        )
+    })?;
+    fs::write(file_path, d)?;
+    log::info!("wrote summary to {file_path}");
+    Ok(())
+}
+
+/// Returns the JSON Schema of the summary file ("--summary-path").
+/// Keep in sync with "render".
+pub fn schema() -> Value {
+    let mut resources = Map::new();
+    for key in RESOURCE_KEYS.iter() {
+        resources.insert(
+            key.to_string(),
+            json!({"type": "string", "description": "Empty if not known (e.g., skipped, failed before)"}),
+        );
+    }
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "aws-ip-provisioner run summary",
+        "type": "object",
+        "required": [
+            "version", "tool_version", "mode", "outcome", "skip_reason", "error",
+            "resources", "durations", "retries", "attempts", "warnings", "finished_at",
+        ],
+        "properties": {
+            "version": {"const": VERSION, "description": "Version of this schema"},
+            "tool_version": {"type": "string"},
+            "mode": {"type": "string", "description": "\"--mode\" of the run"},
+            "outcome": {"enum": ["succeeded", "skipped", "failed"]},
+            "skip_reason": {"type": "string", "description": "Empty unless skipped"},
+            "error": {"type": "string", "description": "Empty unless failed"},
+            "resources": {
+                "type": "object",
+                "required": RESOURCE_KEYS,
+                "properties": resources,
+            },
+            "durations": {
+                "type": "object",
+                "required": ["phases", "total"],
+                "properties": {
+                    "phases": {
+                        "type": "object",
+                        "description": "Seconds per phase (e.g., \"allocate\"), as in \"--timing-report-path\"",
+                        "additionalProperties": {"type": "number"},
+                    },
+                    "total": {"type": "number", "description": "Seconds of the run"},
+                },
+            },
+            "retries": {
+                "type": "object",
+                "description": "Retries per operation (e.g., \"imds\")",
+                "additionalProperties": {"type": "integer", "minimum": 1},
+            },
+            "attempts": {
+                "type": "object",
+                "description": "Failed attempts per retried operation, the last one included if the retries are exhausted",
+                "additionalProperties": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["at", "code", "error", "backoff_seconds"],
+                        "properties": {
+                            "at": {"type": "integer", "description": "Unix timestamp in milliseconds"},
+                            "code": {"type": "string", "description": "Error kind (e.g., \"TimedOut\")"},
+                            "error": {"type": "string"},
+                            "backoff_seconds": {"type": "number", "description": "Wait before the next attempt, zero for the last"},
+                        },
+                    },
+                },
+            },
+            "warnings": {"type": "array", "items": {"type": "string"}},
+            "finished_at": {"type": "integer", "description": "Unix timestamp in seconds"},
+        },
+    })
+}
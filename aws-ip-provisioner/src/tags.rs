@@ -0,0 +1,113 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Error, ErrorKind},
+};
+
+use aws_sdk_ec2::model::Address;
+
+use crate::{conflict, eip, pool};
+
+/// Tag keys that the tool itself writes and reads back (e.g., pool claims),
+/// never removed by "--remove-unknown-tags" nor overwritten by "--desired-tags".
+pub const RESERVED_KEYS: [&str; 6] = [
+    "Name",
+    eip::CLIENT_TOKEN_TAG_KEY,
+    conflict::ALLOCATED_AT_TAG_KEY,
+    pool::STATUS_TAG_KEY,
+    pool::CLAIMED_BY_TAG_KEY,
+    pool::CLAIM_EXPIRES_AT_TAG_KEY,
+];
+
+/// Changes to converge the tag set of an EIP to the desired tags.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diff {
+    /// Missing or changed tags, to create or overwrite.
+    pub upsert: Vec<(String, String)>,
+    /// Unknown tags, to delete.
+    pub remove: Vec<String>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.upsert.is_empty() && self.remove.is_empty()
+    }
+}
+
+/// Parses the comma-separated "key=value" pairs of "--desired-tags"
+/// (e.g., "Team=infra,CostCenter=1234"). The value may be empty ("Key=").
+/// Rejects the tags that the tool sets itself (see "is_reserved").
+pub fn parse(s: &str) -> io::Result<BTreeMap<String, String>> {
+    let tags = pairs(s)?;
+    if let Some(k) = tags.keys().find(|k| is_reserved(k)) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("tag key '{k}' is set by the tool itself"),
+        ));
+    }
+    Ok(tags)
+}
+
+/// Parses the "key=value" pairs as "parse" does, but keeps the reserved keys
+/// (e.g., to report all the problems of the flags at once).
+pub fn pairs(s: &str) -> io::Result<BTreeMap<String, String>> {
+    let mut tags = BTreeMap::new();
+    for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (k, v) = pair.split_once('=').ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("tag '{pair}' is not in the form 'key=value'"),
+            )
+        })?;
+        let k = k.trim();
+        if tags.insert(k.to_string(), v.trim().to_string()).is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("tag key '{k}' is set more than once"),
+            ));
+        }
+    }
+    Ok(tags)
+}
+
+/// Returns the changes to converge the current tags to the desired ones.
+/// With "remove_unknown", deletes the tags that are neither desired nor kept
+/// (e.g., the "Id" and "Kind" tags); the reserved "aws:" tags and the
+/// "RESERVED_KEYS" are always kept. The desired tags never overwrite the
+/// kept or the reserved ones.
+pub fn diff(
+    current: &BTreeMap<String, String>,
+    desired: &BTreeMap<String, String>,
+    remove_unknown: bool,
+    keep: &[&str],
+) -> Diff {
+    let mut d = Diff::default();
+    for (k, v) in desired {
+        if is_reserved(k) || keep.contains(&k.as_str()) {
+            continue;
+        }
+        if current.get(k) != Some(v) {
+            d.upsert.push((k.clone(), v.clone()));
+        }
+    }
+    if remove_unknown {
+        d.remove = current
+            .keys()
+            .filter(|k| !desired.contains_key(*k) && !is_reserved(k) && !keep.contains(&k.as_str()))
+            .cloned()
+            .collect();
+    }
+    d
+}
+
+pub fn is_reserved(key: &str) -> bool {
+    key.to_lowercase().starts_with("aws:") || RESERVED_KEYS.contains(&key)
+}
+
+/// Returns the tags of the address.
+pub fn of(addr: &Address) -> BTreeMap<String, String> {
+    addr.tags()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|t| Some((t.key()?.to_string(), t.value()?.to_string())))
+        .collect()
+}
@@ -0,0 +1,170 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Error},
+    path::Path,
+    time::Duration,
+};
+
+use serde_json::Value;
+
+use crate::{context::Context, metrics, summary};
+
+/// File name in "--metrics-textfile-dir", as read by the node_exporter
+/// textfile collector ("*.prom").
+pub const FILE_NAME: &str = "aws_ip_provisioner.prom";
+
+/// Prefix of the metric names, so that they do not collide with the others
+/// in the same node_exporter.
+const PREFIX: &str = "aws_ip_provisioner_";
+
+/// Outcomes of the run, each exported with 1 for the last run and 0 otherwise.
+const OUTCOMES: &[&str] = &["succeeded", "skipped", "failed"];
+
+/// Returns the metrics of the run summary (see "summary::render") and of the
+/// registry of the run (see "metrics"), in the Prometheus text format.
+pub fn render(ctx: &Context, summary: &Value) -> String {
+    let mut out = String::new();
+    let mode = summary["mode"].as_str().unwrap_or_default();
+    let outcome = summary["outcome"].as_str().unwrap_or_default();
+    family(
+        &mut out,
+        &run_metric(&ctx.metrics, "last_run_outcome"),
+        "gauge",
+        "Outcome of the last run, 1 for the outcome of the last run and 0 for the others.",
+        &OUTCOMES
+            .iter()
+            .map(|o| {
+                (
+                    format!("{{mode=\"{}\",outcome=\"{o}\"}}", escape(mode)),
+                    if *o == outcome { 1.0 } else { 0.0 },
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    family(
+        &mut out,
+        &run_metric(&ctx.metrics, "last_run_timestamp_seconds"),
+        "gauge",
+        "Unix timestamp of the end of the last run.",
+        &[(
+            String::new(),
+            summary["finished_at"].as_u64().unwrap_or_default() as f64,
+        )],
+    );
+    family(
+        &mut out,
+        &run_metric(&ctx.metrics, "last_run_duration_seconds"),
+        "gauge",
+        "Duration of the last run.",
+        &[(
+            String::new(),
+            summary["durations"]["total"].as_f64().unwrap_or_default(),
+        )],
+    );
+    family(
+        &mut out,
+        &run_metric(&ctx.metrics, "last_run_phase_duration_seconds"),
+        "gauge",
+        "Duration of each phase of the last run (e.g., \"allocate\").",
+        &labeled("phase", &summary["durations"]["phases"]),
+    );
+    family(
+        &mut out,
+        &run_metric(&ctx.metrics, "last_run_retries"),
+        "gauge",
+        "Retries of each operation in the last run (e.g., \"imds\").",
+        &labeled("operation", &summary["retries"]),
+    );
+    for (name, v) in ctx.metrics.gauges() {
+        family(
+            &mut out,
+            &format!("{PREFIX}{name}"),
+            "gauge",
+            "",
+            &[(String::new(), v)],
+        );
+    }
+    for (name, v) in ctx.metrics.counters() {
+        family(
+            &mut out,
+            &format!("{PREFIX}{name}"),
+            "counter",
+            "",
+            &[(String::new(), v as f64)],
+        );
+    }
+    out
+}
+
+/// Writes the metrics of the run to "FILE_NAME" in the directory, prefixed
+/// with the namespace (e.g., "internal-vip-aws_ip_provisioner.prom").
+/// The file is renamed into place, so that the collector never reads a partial file.
+pub fn write(
+    ctx: &Context,
+    dir: &str,
+    namespace: &str,
+    mode: &str,
+    res: &io::Result<()>,
+    total: Duration,
+) -> io::Result<()> {
+    let file_name = if namespace.is_empty() {
+        FILE_NAME.to_string()
+    } else {
+        format!("{namespace}-{FILE_NAME}")
+    };
+    let file_path = Path::new(dir).join(file_name);
+    // not "*.prom", so that the collector skips it
+    let tmp = file_path.with_extension("prom.tmp");
+    fs::write(&tmp, render(ctx, &summary::render(ctx, mode, res, total))).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!("failed to write {} '{}'", tmp.display(), e),
+        )
+    })?;
+    fs::rename(&tmp, &file_path)?;
+    log::info!("wrote metrics to {}", file_path.display());
+    Ok(())
+}
+
+/// Returns the name of the run metric, namespaced as the registry metrics.
+fn run_metric(metrics: &metrics::Registry, name: &str) -> String {
+    format!("{PREFIX}{}", metrics.namespaced(name))
+}
+
+/// Returns the samples of the JSON object of numbers, labeled by its keys.
+fn labeled(label: &str, v: &Value) -> Vec<(String, f64)> {
+    v.as_object()
+        .map(|m| {
+            m.iter()
+                .map(|(k, v)| {
+                    (
+                        format!("{{{label}=\"{}\"}}", escape(k)),
+                        v.as_f64().unwrap_or_default(),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Appends the metric family, skipped if no sample.
+fn family(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, f64)]) {
+    if samples.is_empty() {
+        return;
+    }
+    if !help.is_empty() {
+        let _ = writeln!(out, "# HELP {name} {help}");
+    }
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, v) in samples.iter() {
+        let _ = writeln!(out, "{name}{labels} {v}");
+    }
+}
+
+/// Escapes the label value (backslash, double quote, and newline).
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
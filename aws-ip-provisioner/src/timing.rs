@@ -0,0 +1,76 @@
+use std::{
+    fs,
+    future::Future,
+    io::{self, Error, ErrorKind},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde_json::{Map, Value};
+
+/// Durations of the provisioning phases of the run, in the order first seen.
+/// Repeated phases (e.g., "describe") add up.
+#[derive(Debug, Default)]
+pub struct Timings {
+    phases: Mutex<Vec<(String, Duration)>>,
+}
+
+impl Timings {
+    /// Adds the duration to the phase.
+    pub fn add(&self, phase: &str, d: Duration) {
+        log::debug!("phase {phase} took {d:?}");
+        let mut phases = self.phases.lock().unwrap();
+        match phases.iter_mut().find(|(p, _)| p == phase) {
+            Some((_, total)) => *total += d,
+            None => phases.push((phase.to_string(), d)),
+        }
+    }
+
+    /// Runs the future, and adds its duration to the phase.
+    pub async fn measure<T>(&self, phase: &str, fut: impl Future<Output = T>) -> T {
+        let started = Instant::now();
+        let v = fut.await;
+        self.add(phase, started.elapsed());
+        v
+    }
+
+    /// Returns the durations of all the phases.
+    pub fn phases(&self) -> Vec<(String, Duration)> {
+        self.phases.lock().unwrap().clone()
+    }
+
+    /// Returns the one-line summary (e.g., "random_wait=3.012s imds=0.021s ... total=4.210s").
+    pub fn summary(&self, total: Duration) -> String {
+        self.phases()
+            .iter()
+            .map(|(p, d)| format!("{p}={:.3}s", d.as_secs_f64()))
+            .chain(std::iter::once(format!(
+                "total={:.3}s",
+                total.as_secs_f64()
+            )))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Writes the durations in seconds as JSON
+    /// (e.g., {"phases":{"random_wait":3.012,...},"total":4.21}).
+    pub fn write_report(&self, file_path: &str, total: Duration) -> io::Result<()> {
+        let mut m = Map::new();
+        for (p, d) in self.phases() {
+            m.insert(p, Value::from(d.as_secs_f64()));
+        }
+        let mut report = Map::new();
+        report.insert(String::from("phases"), Value::Object(m));
+        report.insert(String::from("total"), Value::from(total.as_secs_f64()));
+
+        let d = serde_json::to_vec_pretty(&Value::Object(report)).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize timing report {}", e),
+            )
+        })?;
+        fs::write(file_path, d)?;
+        log::info!("wrote timing report to {file_path}");
+        Ok(())
+    }
+}
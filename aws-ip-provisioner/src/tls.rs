@@ -0,0 +1,80 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Error, ErrorKind},
+};
+
+use hyper::client::HttpConnector;
+use hyper_rustls::HttpsConnector;
+use rustls::{ClientConfig, RootCertStore};
+
+/// Returns the trusted roots: the system store, the bundled Mozilla roots
+/// (with the "webpki-roots" feature) if the system has none (e.g., the
+/// minimal bootstrap images without "ca-certificates"), and the CA bundle
+/// (e.g., a TLS-inspecting proxy) if not empty.
+pub fn root_store(ca_bundle: &str) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match rustls_native_certs::load_native_certs() {
+        Ok(certs) => {
+            for cert in certs {
+                if let Err(e) = roots.add(&rustls::Certificate(cert.0)) {
+                    log::debug!("skipping invalid native certificate '{}'", e);
+                }
+            }
+        }
+        Err(e) => log::warn!("failed to load the system certificates '{}'", e),
+    }
+    #[cfg(feature = "webpki-roots")]
+    if roots.is_empty() {
+        log::info!("no system certificates, using the bundled roots");
+        roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+    }
+
+    if !ca_bundle.is_empty() {
+        log::info!("loading CA bundle {ca_bundle}");
+        let mut rd = BufReader::new(File::open(ca_bundle)?);
+        let certs = rustls_pemfile::certs(&mut rd)?;
+        let (added, ignored) = roots.add_parsable_certificates(&certs);
+        if added == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("no valid certificate found in {ca_bundle}"),
+            ));
+        }
+        log::info!("added {added} certificates from the CA bundle (ignored {ignored})");
+    }
+    if roots.is_empty() {
+        log::warn!("no trusted certificates (install \"ca-certificates\", set \"--ca-bundle\", or build with the \"webpki-roots\" feature) -- HTTPS calls will fail");
+    }
+    Ok(roots)
+}
+
+/// Returns the rustls client config of the trusted roots.
+pub fn client_config(ca_bundle: &str) -> io::Result<ClientConfig> {
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store(ca_bundle)?)
+        .with_no_client_auth())
+}
+
+/// Returns the HTTP and HTTPS connector of the non-AWS clients (e.g., Consul,
+/// provider APIs). Unlike "with_native_roots", never panics without the system
+/// certificates, so that the plain HTTP endpoints keep working.
+pub fn https_connector() -> HttpsConnector<HttpConnector> {
+    let tls = client_config("").unwrap_or_else(|_| {
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth()
+    });
+    hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls)
+        .https_or_http()
+        .enable_http1()
+        .build()
+}
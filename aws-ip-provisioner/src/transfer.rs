@@ -0,0 +1,478 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    time::SystemTime,
+};
+
+use aws_manager::sts;
+use aws_sdk_ec2::model::{Address, NetworkInterface};
+use aws_sigv4::http_request::{sign, SignableRequest, SigningParams, SigningSettings};
+use aws_smithy_client::http_connector::ConnectorSettings;
+use aws_smithy_http::body::SdkBody;
+use aws_types::{credentials::ProvideCredentials, SdkConfig};
+use hyper::{body, http, Method, StatusCode};
+use tower_service::Service;
+
+use crate::{
+    context::Context,
+    ec2, eip,
+    provisioner::{BoxFuture, Ec2},
+    sdk, state,
+};
+
+/// EC2 query API version.
+const EC2_API_VERSION: &str = "2016-11-15";
+
+/// Allocates the EIPs in the account that owns them (e.g., central networking
+/// account with the BYOIP pool), and transfers each to the local account,
+/// where the address is tagged and associated as usual.
+/// All the other calls go to the local account, since an EIP can only be
+/// associated with the resources of the account that holds it.
+/// ref. <https://docs.aws.amazon.com/vpc/latest/userguide/WorkWithEIPs.html#transfer-EIPs-intro>
+pub struct Transfer<'a> {
+    inner: &'a dyn Ec2,
+    ctx: &'a Context,
+    local_config: SdkConfig,
+    allocation_config: SdkConfig,
+    allocation: ec2::Manager,
+    sdk_opts: sdk::Options,
+    local_account_id: String,
+    allocation_account_id: String,
+}
+
+impl<'a> Transfer<'a> {
+    /// Assumes the allocation role, and resolves both account IDs.
+    pub async fn new(
+        inner: &'a dyn Ec2,
+        ctx: &'a Context,
+        shared_config: &SdkConfig,
+        sdk_opts: &sdk::Options,
+        allocation_role_arn: &str,
+    ) -> io::Result<Transfer<'a>> {
+        let allocation_config = sdk::assume_role(shared_config, sdk_opts, allocation_role_arn)?;
+        let local_account_id =
+            account_id(&sdk::for_service(shared_config, "sts", sdk_opts)?).await?;
+        let allocation_account_id =
+            account_id(&sdk::for_service(&allocation_config, "sts", sdk_opts)?).await?;
+        if local_account_id == allocation_account_id {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "allocation role {allocation_role_arn} is in the local account {local_account_id} (no transfer needed)"
+                ),
+            ));
+        }
+        log::info!(
+            "allocating EIPs in account {allocation_account_id}, to transfer to account {local_account_id}"
+        );
+
+        let allocation_config = sdk::for_service(&allocation_config, "ec2", sdk_opts)?;
+        Ok(Self {
+            inner,
+            ctx,
+            local_config: sdk::for_service(shared_config, "ec2", sdk_opts)?,
+            allocation: ec2::Manager::new(&allocation_config),
+            allocation_config,
+            sdk_opts: sdk_opts.clone(),
+            local_account_id,
+            allocation_account_id,
+        })
+    }
+
+    /// Allocates the EIP in the allocation account, or reuses the one that
+    /// a previous attempt allocated but did not transfer (e.g., crashed).
+    async fn allocate_remote(
+        &self,
+        tags: &[(&str, &str)],
+        client_token: &str,
+        public_ipv4_pool: &str,
+    ) -> io::Result<ec2::Eip> {
+        let prev = eip::describe_by_tags(
+            &self.allocation,
+            &[(eip::CLIENT_TOKEN_TAG_KEY, client_token)],
+        )
+        .await?;
+        if let Some(addr) = prev.first() {
+            let eip = ec2::Eip {
+                allocation_id: addr.allocation_id().unwrap_or_default().to_string(),
+                public_ip: addr.public_ip().unwrap_or_default().to_string(),
+            };
+            log::info!(
+                "found EIP {} in account {} not yet transferred -- reusing",
+                eip.public_ip,
+                self.allocation_account_id
+            );
+            return Ok(eip);
+        }
+        eip::allocate(&self.allocation, tags, public_ipv4_pool).await
+    }
+
+    /// Transfers the EIP from the allocation account to the local account,
+    /// tagging it on acceptance since the tags do not transfer.
+    /// Returns the EIP with its allocation ID in the local account.
+    async fn transfer(&self, eip: &ec2::Eip, tags: &[(&str, &str)]) -> io::Result<ec2::Eip> {
+        log::info!(
+            "transferring EIP {} from account {} to {}",
+            eip.public_ip,
+            self.allocation_account_id,
+            self.local_account_id
+        );
+        enable(
+            &self.allocation_config,
+            &self.sdk_opts,
+            &eip.allocation_id,
+            &self.local_account_id,
+        )
+        .await?;
+        let tags: Vec<(String, String)> = tags
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let allocation_id =
+            accept(&self.local_config, &self.sdk_opts, &eip.public_ip, &tags).await?;
+        Ok(ec2::Eip {
+            allocation_id,
+            public_ip: eip.public_ip.clone(),
+        })
+    }
+}
+
+impl Ec2 for Transfer<'_> {
+    fn describe_by_tags<'a>(
+        &'a self,
+        tags: &'a [(&'a str, &'a str)],
+    ) -> BoxFuture<'a, Vec<Address>> {
+        self.inner.describe_by_tags(tags)
+    }
+
+    fn describe_by_allocation_id<'a>(
+        &'a self,
+        allocation_id: &'a str,
+    ) -> BoxFuture<'a, Option<Address>> {
+        self.inner.describe_by_allocation_id(allocation_id)
+    }
+
+    fn is_instance_running<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, bool> {
+        self.inner.is_instance_running(instance_id)
+    }
+
+    fn is_ready_to_associate<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, bool> {
+        self.inner.is_ready_to_associate(instance_id)
+    }
+
+    fn describe_network_interfaces<'a>(
+        &'a self,
+        instance_id: &'a str,
+    ) -> BoxFuture<'a, Vec<NetworkInterface>> {
+        self.inner.describe_network_interfaces(instance_id)
+    }
+
+    fn assign_ipv6_address<'a>(&'a self, network_interface_id: &'a str) -> BoxFuture<'a, String> {
+        self.inner.assign_ipv6_address(network_interface_id)
+    }
+
+    fn allocate<'a>(
+        &'a self,
+        id_tag: (&'a str, &'a str),
+        kind_tag: (&'a str, &'a str),
+        client_token: &'a str,
+        public_ipv4_pool: &'a str,
+    ) -> BoxFuture<'a, ec2::Eip> {
+        Box::pin(async move {
+            let tags = [
+                ("Name", id_tag.1),
+                id_tag,
+                kind_tag,
+                (eip::CLIENT_TOKEN_TAG_KEY, client_token),
+            ];
+            let remote = self
+                .allocate_remote(&tags, client_token, public_ipv4_pool)
+                .await?;
+            let ret = self.transfer(&remote, &tags).await;
+            let (allocation_id, public_ip) = match &ret {
+                Ok(eip) => (eip.allocation_id.as_str(), eip.public_ip.as_str()),
+                Err(_) => (remote.allocation_id.as_str(), remote.public_ip.as_str()),
+            };
+            self.ctx.audit.record(
+                "transfer",
+                &[
+                    ("allocation_id", allocation_id),
+                    ("public_ip", public_ip),
+                    ("before", &self.allocation_account_id),
+                    ("after", &self.local_account_id),
+                ],
+                &ret,
+            )?;
+            ret
+        })
+    }
+
+    fn create_tags<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        tags: Vec<(String, String)>,
+    ) -> BoxFuture<'a, ()> {
+        self.inner.create_tags(allocation_id, tags)
+    }
+
+    fn delete_tags<'a>(&'a self, allocation_id: &'a str, keys: Vec<String>) -> BoxFuture<'a, ()> {
+        self.inner.delete_tags(allocation_id, keys)
+    }
+
+    /// Releases in this account, where the EIPs are after the transfer.
+    fn release_address<'a>(&'a self, allocation_id: &'a str) -> BoxFuture<'a, ()> {
+        self.inner.release_address(allocation_id)
+    }
+
+    fn disassociate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        association_id: &'a str,
+    ) -> BoxFuture<'a, ()> {
+        self.inner.disassociate(allocation_id, association_id)
+    }
+
+    fn associate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        instance_id: &'a str,
+        target: &'a eip::Target,
+        allow_reassociation: bool,
+    ) -> BoxFuture<'a, String> {
+        self.inner
+            .associate(allocation_id, instance_id, target, allow_reassociation)
+    }
+}
+
+/// Sends the signed EC2 query request with the credentials of the config,
+/// for the operations that the SDK does not have yet (e.g., EIP transfers).
+/// Returns the XML response.
+pub async fn query(
+    config: &SdkConfig,
+    sdk_opts: &sdk::Options,
+    params: &[(&str, &str)],
+) -> io::Result<String> {
+    let action = params
+        .iter()
+        .find(|(k, _)| *k == "Action")
+        .map(|(_, v)| *v)
+        .unwrap_or_default();
+    let region = config
+        .region()
+        .map(|r| r.as_ref().to_string())
+        .unwrap_or_else(|| String::from("us-west-2"));
+    let uri = if sdk_opts.endpoint_url.is_empty() {
+        sdk::service_uri("ec2", &region, sdk_opts.use_fips, sdk_opts.use_dual_stack)
+    } else {
+        sdk_opts.endpoint_url.clone()
+    };
+
+    let creds = config
+        .credentials_provider()
+        .ok_or_else(|| Error::new(ErrorKind::Other, "no AWS credentials provider"))?
+        .provide_credentials()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to load credentials {}", e),
+            )
+        })?;
+
+    let form = params
+        .iter()
+        .chain(std::iter::once(&("Version", EC2_API_VERSION)))
+        .map(|(k, v)| format!("{}={}", encode(k), encode(v)))
+        .collect::<Vec<_>>()
+        .join("&");
+    let mut request = http::Request::builder()
+        .method(Method::POST)
+        .uri(&uri)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(form.into_bytes())
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to build {action} request {}", e),
+            )
+        })?;
+    let mut signing = SigningParams::builder()
+        .access_key(creds.access_key_id())
+        .secret_key(creds.secret_access_key())
+        .region(&region)
+        .service_name("ec2")
+        .time(SystemTime::now())
+        .settings(SigningSettings::default());
+    signing.set_security_token(creds.session_token());
+    let signing = signing.build().map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to build signing params {}", e),
+        )
+    })?;
+    let (instructions, _) = sign(SignableRequest::from(&request), &signing)
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to sign {action} {}", e)))?
+        .into_parts();
+    instructions.apply_to_request(&mut request);
+
+    let mut connector = config
+        .http_connector()
+        .and_then(|c| c.connector(&ConnectorSettings::default(), config.sleep_impl()))
+        .ok_or_else(|| Error::new(ErrorKind::Other, "no HTTP connector in the SDK config"))?;
+
+    let resp = connector
+        .call(request.map(SdkBody::from))
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed {action} {}", e)))?;
+    let status = resp.status();
+    let bytes = body::to_bytes(resp.into_body()).await.map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to read {action} response {}", e),
+        )
+    })?;
+    let body = String::from_utf8_lossy(&bytes).to_string();
+    if status != StatusCode::OK {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("failed {action} {status} '{body}'"),
+        ));
+    }
+    Ok(body)
+}
+
+/// Offers the EIP to the account, returning when the offer expires
+/// (the account must accept it within seven days).
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_EnableAddressTransfer.html>
+pub async fn enable(
+    config: &SdkConfig,
+    sdk_opts: &sdk::Options,
+    allocation_id: &str,
+    transfer_account_id: &str,
+) -> io::Result<String> {
+    let resp = query(
+        config,
+        sdk_opts,
+        &[
+            ("Action", "EnableAddressTransfer"),
+            ("AllocationId", allocation_id),
+            ("TransferAccountId", transfer_account_id),
+        ],
+    )
+    .await?;
+    Ok(xml_value(&resp, "transferOfferExpirationTimestamp").unwrap_or_default())
+}
+
+/// Accepts the EIP offered to the account of the config, tagging it on acceptance
+/// since the tags do not transfer. Returns its allocation ID in the account.
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/APIReference/API_AcceptAddressTransfer.html>
+pub async fn accept(
+    config: &SdkConfig,
+    sdk_opts: &sdk::Options,
+    public_ip: &str,
+    tags: &[(String, String)],
+) -> io::Result<String> {
+    let params = accept_params(public_ip, tags);
+    let params: Vec<(&str, &str)> = params
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect();
+    let resp = query(config, sdk_opts, &params).await?;
+    xml_value(&resp, "allocationId").ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("AcceptAddressTransfer returned no allocation ID '{resp}'"),
+        )
+    })
+}
+
+/// Returns the AcceptAddressTransfer parameters, skipping the "aws:" tags
+/// (reserved, e.g., "aws:cloudformation:stack-name" of the source stack).
+pub fn accept_params(public_ip: &str, tags: &[(String, String)]) -> Vec<(String, String)> {
+    let mut params = vec![
+        (
+            String::from("Action"),
+            String::from("AcceptAddressTransfer"),
+        ),
+        (String::from("Address"), public_ip.to_string()),
+    ];
+    let tags: Vec<&(String, String)> = tags
+        .iter()
+        .filter(|(k, _)| !k.starts_with("aws:"))
+        .collect();
+    if tags.is_empty() {
+        return params;
+    }
+    params.push((
+        String::from("TagSpecification.1.ResourceType"),
+        String::from("elastic-ip"),
+    ));
+    for (i, (k, v)) in tags.iter().enumerate() {
+        params.push((format!("TagSpecification.1.Tag.{}.Key", i + 1), k.clone()));
+        params.push((format!("TagSpecification.1.Tag.{}.Value", i + 1), v.clone()));
+    }
+    params
+}
+
+/// Points the mounted EIP file of the transferred EIP to its allocation ID in
+/// the new account, keeping the values recorded next to it (e.g., the target
+/// private IP), or creates the file if missing. Fails if the file holds another EIP.
+pub fn rewrite_state(file_path: &str, public_ip: &str, allocation_id: &str) -> io::Result<()> {
+    if std::path::Path::new(file_path).exists() {
+        let prev = state::State::load(file_path)?;
+        if prev.public_ip != public_ip {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "mounted EIP file {file_path} holds another EIP {} (not {public_ip})",
+                    prev.public_ip
+                ),
+            ));
+        }
+    }
+    state::record_eip(
+        file_path,
+        &ec2::Eip {
+            allocation_id: allocation_id.to_string(),
+            public_ip: public_ip.to_string(),
+        },
+    )
+}
+
+async fn account_id(sts_config: &SdkConfig) -> io::Result<String> {
+    let identity = sts::Manager::new(sts_config)
+        .get_identity()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!(
+                    "failed sts.get_identity {} (retryable {})",
+                    e.message(),
+                    e.is_retryable()
+                ),
+            )
+        })?;
+    Ok(identity.account_id)
+}
+
+/// Percent-encodes the query parameter (all but the unreserved characters).
+/// ref. <https://www.rfc-editor.org/rfc/rfc3986#section-2.3>
+pub(crate) fn encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{b:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Returns the text of the first element with the name in the XML response.
+fn xml_value(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{name}>"))? + name.len() + 2;
+    let end = xml[start..].find(&format!("</{name}>"))? + start;
+    Some(xml[start..end].to_string())
+}
@@ -0,0 +1,512 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use crate::{
+    command::Flags, config, dns, progress, reachability, route53, secret, security_group,
+    state::State, wireguard,
+};
+
+/// Returns the problems of the flags, empty if valid.
+/// Checks what clap cannot: the tag syntax, the flags that require each other,
+/// the ARNs and URLs, and the files the flags point to.
+pub fn flags(opts: &Flags) -> Vec<String> {
+    let mut problems = Vec::new();
+    problems.extend(tags(opts));
+    if !opts
+        .namespace
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        problems.push(format!(
+            "--namespace '{}' must be alphanumeric, '-', or '_' (used in tag values, file names, and metric names)",
+            opts.namespace
+        ));
+    }
+
+    if !opts.web_identity_token_file.is_empty() {
+        if opts.role_arn.is_empty() {
+            problems.push("--web-identity-token-file requires --role-arn".to_string());
+        }
+        if !opts.aws_profile.is_empty() {
+            problems.push(
+                "--web-identity-token-file and --aws-profile cannot be used together".to_string(),
+            );
+        }
+        problems.extend(file_exists(
+            "--web-identity-token-file",
+            &opts.web_identity_token_file,
+        ));
+    }
+    if !opts.role_arn.is_empty() && opts.web_identity_token_file.is_empty() {
+        problems.push("--role-arn requires --web-identity-token-file".to_string());
+    }
+    problems.extend(arn("--role-arn", &opts.role_arn));
+    problems.extend(arn("--allocation-role-arn", &opts.allocation_role_arn));
+    if !opts.public_ipv4_pool.is_empty() && !opts.public_ipv4_pool.starts_with("ipv4pool-") {
+        problems.push(format!(
+            "--public-ipv4-pool '{}' must start with 'ipv4pool-'",
+            opts.public_ipv4_pool
+        ));
+    }
+
+    if opts.pool_max_free > 0 && opts.pool_max_free < opts.pool_min_free {
+        problems.push(format!(
+            "--pool-max-free {} must not be less than --pool-min-free {}",
+            opts.pool_max_free, opts.pool_min_free
+        ));
+    }
+    if !opts.sync_security_group_id.is_empty() {
+        if !opts.sync_security_group_id.starts_with("sg-") {
+            problems.push(format!(
+                "--sync-security-group-id '{}' must start with 'sg-'",
+                opts.sync_security_group_id
+            ));
+        }
+        match security_group::parse_port_ranges(&opts.port_ranges) {
+            Ok(ranges) if ranges.is_empty() => {
+                problems.push("--sync-security-group-id requires --port-ranges".to_string())
+            }
+            Ok(_) => {}
+            Err(e) => problems.push(format!("--port-ranges {}", e)),
+        }
+    } else if !opts.port_ranges.is_empty() {
+        problems.push("--port-ranges requires --sync-security-group-id".to_string());
+    }
+    // the daemon renews on its watch interval
+    if opts.pool_lease_seconds > 0
+        && opts.pool_lease_seconds < 3 * opts.watch_interval_seconds as u64
+    {
+        problems.push(format!(
+            "--pool-lease-seconds {} must be at least 3 times --watch-interval-seconds {}",
+            opts.pool_lease_seconds, opts.watch_interval_seconds
+        ));
+    }
+
+    if !opts.lifecycle_hook_name.is_empty() && opts.mode != "terminate-hook" {
+        problems.push(format!(
+            "--lifecycle-hook-name requires --mode=terminate-hook (got '{}')",
+            opts.mode
+        ));
+    }
+
+    if !opts.target_eni_tag.is_empty() {
+        if opts.target_device_index != 0 {
+            problems.push(
+                "--target-eni-tag and --target-device-index cannot be used together".to_string(),
+            );
+        }
+        match opts.target_eni_tag.split_once('=') {
+            Some((k, v)) => {
+                problems.extend(tag_key(k).map(|p| format!("--target-eni-tag key '{k}' {p}")));
+                problems.extend(tag_value(v).map(|p| format!("--target-eni-tag value '{v}' {p}")));
+            }
+            None => problems.push(format!(
+                "--target-eni-tag '{}' must be <key>=<value>",
+                opts.target_eni_tag
+            )),
+        }
+    }
+
+    if !opts.private_ip_address.is_empty() && opts.private_ip_address.parse::<Ipv4Addr>().is_err() {
+        problems.push(format!(
+            "--private-ip-address '{}' is not an IPv4 address",
+            opts.private_ip_address
+        ));
+    }
+
+    for resolver in opts
+        .verify_dns_resolvers
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+    {
+        if let Err(e) = dns::parse_resolver(resolver) {
+            problems.push(format!("--verify-dns-resolvers {}", e));
+        }
+    }
+    if !opts.verify_dns_resolvers.is_empty() && opts.verify_dns_name.is_empty() {
+        problems.push(String::from(
+            "--verify-dns-resolvers requires --verify-dns-name",
+        ));
+    }
+
+    if let Err(e) = reachability::parse(&opts.verify_reachability) {
+        problems.push(format!("--verify-reachability {}", e));
+    }
+    problems.extend(url(
+        "--reachability-service-url",
+        &opts.reachability_service_url,
+    ));
+    if opts.verify_reachability.is_empty() != opts.reachability_service_url.is_empty() {
+        problems.push(String::from(
+            "--verify-reachability and --reachability-service-url require each other",
+        ));
+    }
+    for (zone_flag, zone_id, name_flag, name) in [
+        (
+            "--route53-zone-id",
+            &opts.route53_zone_id,
+            "--route53-record-name",
+            &opts.route53_record_name,
+        ),
+        (
+            "--route53-private-zone-id",
+            &opts.route53_private_zone_id,
+            "--route53-private-record-name",
+            &opts.route53_private_record_name,
+        ),
+    ] {
+        if zone_id.is_empty() != name.is_empty() {
+            problems.push(format!("{zone_flag} and {name_flag} require each other"));
+        }
+    }
+    if let Err(e) = route53::Routing::parse(&opts.route53_routing) {
+        problems.push(format!("--route53-routing {}", e));
+    }
+    if let Err(e) = route53::HealthCheck::parse(&opts.route53_health_check) {
+        problems.push(format!("--route53-health-check {}", e));
+    }
+    let simple = matches!(opts.route53_routing.as_str(), "" | "simple");
+    if opts.route53_zone_id.is_empty() && (!simple || !opts.route53_health_check.is_empty()) {
+        problems.push(String::from(
+            "--route53-routing and --route53-health-check require --route53-zone-id",
+        ));
+    }
+    let stun_port = opts.stun_server.rsplit_once(':').and_then(|(host, port)| {
+        if host.is_empty() {
+            None
+        } else {
+            port.parse::<u16>().ok()
+        }
+    });
+    if !opts.stun_server.is_empty() && stun_port.is_none() {
+        problems.push(format!(
+            "--stun-server '{}' is not host:port (e.g., stun.l.google.com:19302)",
+            opts.stun_server
+        ));
+    }
+    if let Err(e) = wireguard::parse(&opts.wireguard_peers) {
+        problems.push(format!("--wireguard-peers {}", e));
+    }
+
+    if cfg!(windows) {
+        if opts.firewall_backend != "none" {
+            problems.push(format!(
+                "--firewall-backend={} is not available on Windows",
+                opts.firewall_backend
+            ));
+        }
+        if opts.log_target != "stderr" {
+            problems.push(format!(
+                "--log-target={} is not available on Windows",
+                opts.log_target
+            ));
+        }
+    }
+    if opts.firewall_backend != "none" {
+        if opts.firewall_rules_file.is_empty() {
+            problems.push(format!(
+                "--firewall-backend={} requires --firewall-rules-file",
+                opts.firewall_backend
+            ));
+        } else {
+            problems.extend(rules_file(&opts.firewall_rules_file));
+        }
+    }
+
+    problems.extend(url("--endpoint-url", &opts.endpoint_url));
+    problems.extend(url("--imds-endpoint", &opts.imds_endpoint));
+    problems.extend(url("--https-proxy", &opts.https_proxy));
+    if opts.uses_consul() {
+        if !cfg!(feature = "consul") {
+            problems.push("Consul flags require the \"consul\" feature".to_string());
+        }
+        problems.extend(url("--consul-address", &opts.consul_address));
+    }
+    if opts.alert().is_enabled() {
+        problems.extend(url("--alert-url", &opts.alert_url));
+        if let Err(e) = secret::Source::parse(&opts.alert_key_source, "") {
+            problems.push(format!("--alert-key-source {}", e));
+        }
+        if opts.alert_failure_threshold == 0 {
+            problems.push("--alert-failure-threshold must be positive to alert".to_string());
+        }
+    }
+    for (flag, source, events_flag, events) in [
+        (
+            "--slack-webhook-source",
+            &opts.slack_webhook_source,
+            "--slack-events",
+            &opts.slack_events,
+        ),
+        (
+            "--discord-webhook-source",
+            &opts.discord_webhook_source,
+            "--discord-events",
+            &opts.discord_events,
+        ),
+    ] {
+        if source.is_empty() {
+            continue;
+        }
+        if let Err(e) = secret::Source::parse(source, "") {
+            problems.push(format!("{flag} {}", e));
+        }
+        for event in events
+            .split(',')
+            .map(|e| e.trim())
+            .filter(|e| !e.is_empty())
+        {
+            if !progress::EVENTS.contains(&event) {
+                problems.push(format!(
+                    "{events_flag} has unknown event '{event}' (expected {})",
+                    progress::EVENTS.join("|")
+                ));
+            }
+        }
+    }
+    problems.extend(url("--drain-url", &opts.drain_url));
+    if opts.drain().is_enabled() && opts.drain_timeout_seconds == 0 {
+        problems.push("--drain-timeout-seconds must be positive to drain".to_string());
+    }
+    if opts.consul_service_port > 0 && opts.consul_service_name.is_empty() {
+        problems.push("--consul-service-port requires --consul-service-name".to_string());
+    }
+    if !opts.ca_bundle.is_empty() {
+        problems.extend(file_exists("--ca-bundle", &opts.ca_bundle));
+    }
+    if !opts.config_file.is_empty() {
+        problems.extend(config_file(opts, &opts.config_file));
+    }
+    problems
+}
+
+/// Returns the problems of the config file, empty if it would be applied as a whole.
+pub fn config_file(opts: &Flags, file_path: &str) -> Vec<String> {
+    let mut updated = opts.clone();
+    if let Err(e) = config::apply(&mut updated, file_path) {
+        return vec![e.to_string()];
+    }
+    tags(&updated)
+        .into_iter()
+        .map(|p| format!("config file {file_path}: {p}"))
+        .collect()
+}
+
+/// Returns the problems of the mounted EIP file, empty if it would be reused.
+pub fn state_file(file_path: &str) -> Vec<String> {
+    if !Path::new(file_path).exists() {
+        return vec![format!("state file {file_path} does not exist")];
+    }
+    let state = match State::load(file_path) {
+        Ok(state) => state,
+        Err(e) => return vec![format!("state file {file_path} is not valid ({})", e)],
+    };
+
+    let mut problems = Vec::new();
+    let valid_id = state
+        .allocation_id
+        .strip_prefix("eipalloc-")
+        .map(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit()))
+        .unwrap_or(false);
+    if !valid_id {
+        problems.push(format!(
+            "state file {file_path} allocation_id '{}' is not an EIP allocation ID (eipalloc-...)",
+            state.allocation_id
+        ));
+    }
+    if state.public_ip.parse::<Ipv4Addr>().is_err() {
+        problems.push(format!(
+            "state file {file_path} public_ip '{}' is not an IPv4 address",
+            state.public_ip
+        ));
+    }
+    let ip = &state.private_ip_address;
+    if !ip.is_empty() && ip.parse::<Ipv4Addr>().is_err() {
+        problems.push(format!(
+            "state file {file_path} private_ip_address '{ip}' is not an IPv4 address"
+        ));
+    }
+    let ip = &state.ipv6_address;
+    if !ip.is_empty() && ip.parse::<Ipv6Addr>().is_err() {
+        problems.push(format!(
+            "state file {file_path} ipv6_address '{ip}' is not an IPv6 address"
+        ));
+    }
+    problems
+}
+
+/// Returns the problems of the EIP tags.
+/// ref. https://docs.aws.amazon.com/tag-editor/latest/userguide/tagging.html
+fn tags(opts: &Flags) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (flag, key) in [
+        ("--id-tag-key", &opts.id_tag_key),
+        ("--kind-tag-key", &opts.kind_tag_key),
+    ] {
+        if let Some(p) = tag_key(key) {
+            problems.push(format!("{flag} '{key}' {p}"));
+        }
+    }
+    for (flag, value) in [
+        ("--id-tag-value", &opts.id_tag_value),
+        ("--kind-tag-value", &opts.kind_tag_value),
+    ] {
+        if let Some(p) = tag_value(value) {
+            problems.push(format!("{flag} '{value}' {p}"));
+        }
+    }
+    match crate::tags::pairs(&opts.desired_tags) {
+        Ok(desired) => {
+            for (k, v) in desired {
+                if let Some(p) = tag_key(&k).or_else(|| tag_value(&v)) {
+                    problems.push(format!("--desired-tags '{k}={v}' {p}"));
+                } else if crate::tags::is_reserved(&k) {
+                    problems.push(format!("--desired-tags '{k}' is set by the tool itself"));
+                } else if k == opts.id_tag_key || k == opts.kind_tag_key {
+                    problems.push(format!(
+                        "--desired-tags '{k}' conflicts with --id-tag-key or --kind-tag-key"
+                    ));
+                }
+            }
+        }
+        Err(e) => problems.push(format!("--desired-tags {}", e)),
+    }
+    problems
+}
+
+fn tag_key(key: &str) -> Option<String> {
+    if key.is_empty() || key.chars().count() > 128 {
+        return Some("must be 1 to 128 characters".to_string());
+    }
+    if key.to_lowercase().starts_with("aws:") {
+        return Some("must not start with the reserved prefix 'aws:'".to_string());
+    }
+    tag_chars(key)
+}
+
+fn tag_value(value: &str) -> Option<String> {
+    if value.chars().count() > 256 {
+        return Some("must be at most 256 characters".to_string());
+    }
+    tag_chars(value)
+}
+
+fn tag_chars(s: &str) -> Option<String> {
+    s.chars()
+        .find(|c| !(c.is_alphanumeric() || " _.:/=+-@".contains(*c)))
+        .map(|c| {
+            format!("has invalid character '{c}' (allowed: letters, digits, spaces, and _.:/=+-@)")
+        })
+}
+
+/// Returns the problem of the IAM role ARN (e.g., "arn:aws:iam::123456789012:role/name").
+fn arn(flag: &str, s: &str) -> Option<String> {
+    if s.is_empty() {
+        return None;
+    }
+    let fields: Vec<&str> = s.splitn(6, ':').collect();
+    let valid = fields.len() == 6
+        && fields[0] == "arn"
+        && fields[1].starts_with("aws")
+        && fields[2] == "iam"
+        && fields[4].len() == 12
+        && fields[4].chars().all(|c| c.is_ascii_digit())
+        && fields[5].starts_with("role/")
+        && fields[5].len() > "role/".len();
+    if valid {
+        None
+    } else {
+        Some(format!(
+            "{flag} '{s}' is not an IAM role ARN (arn:aws:iam::<account>:role/<name>)"
+        ))
+    }
+}
+
+fn url(flag: &str, s: &str) -> Option<String> {
+    if s.is_empty() {
+        return None;
+    }
+    let host = s
+        .strip_prefix("https://")
+        .or_else(|| s.strip_prefix("http://"))
+        .unwrap_or_default();
+    if host.is_empty() || host.starts_with('/') {
+        return Some(format!(
+            "{flag} '{s}' is not an http(s) URL (e.g., https://host:port)"
+        ));
+    }
+    None
+}
+
+fn file_exists(flag: &str, s: &str) -> Option<String> {
+    if Path::new(s).is_file() {
+        None
+    } else {
+        Some(format!("{flag} '{s}' does not exist"))
+    }
+}
+
+/// Returns the problems of the firewall rules file, checking the addresses and CIDRs
+/// that are not templated (e.g., "10.0.0.0/8", but not "{private_ip}/32").
+fn rules_file(file_path: &str) -> Vec<String> {
+    let rules = match fs::read_to_string(file_path) {
+        Ok(d) => d,
+        Err(e) => {
+            return vec![format!(
+                "failed to read firewall rules file {file_path} '{}'",
+                e
+            )]
+        }
+    };
+    let mut problems = Vec::new();
+    for (i, line) in rules.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        for token in line.split(|c: char| c.is_whitespace() || c == ',' || c == '{' || c == '}') {
+            if !looks_like_address(token) {
+                continue;
+            }
+            if let Some(p) = cidr(token) {
+                problems.push(format!("firewall rules file {file_path}:{} {p}", i + 1));
+            }
+        }
+    }
+    problems
+}
+
+/// Returns true if the token is made of the address characters and has a dot-separated
+/// IPv4 shape or a prefix length, so that the ports and words are not checked.
+fn looks_like_address(token: &str) -> bool {
+    if token.is_empty()
+        || !token
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() || c == '.' || c == ':' || c == '/')
+    {
+        return false;
+    }
+    let addr = token.split('/').next().unwrap_or_default();
+    addr.matches('.').count() == 3 || (addr.contains(':') && token.contains('/'))
+}
+
+fn cidr(s: &str) -> Option<String> {
+    let (addr, prefix) = match s.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (s, None),
+    };
+    let ip = match addr.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => return Some(format!("'{s}' is not a valid address")),
+    };
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    match prefix.map(|p| p.parse::<u8>()) {
+        None => None,
+        Some(Ok(p)) if p <= max => None,
+        _ => Some(format!("'{s}' has an invalid prefix length (0 to {max})")),
+    }
+}
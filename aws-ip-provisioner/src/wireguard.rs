@@ -0,0 +1,332 @@
+use std::{
+    fmt, fs,
+    io::{self, Error, ErrorKind, Write},
+    process::Stdio,
+};
+
+use hyper::{Body, Method, Request};
+use serde::Serialize;
+use tokio::{
+    process::Command,
+    time::{timeout, Duration},
+};
+
+use crate::{context::Context, tls};
+
+/// Counter of the peers that failed to take the new endpoint.
+pub const FAILED_COUNTER: &str = "wireguard_peer_update_failures_total";
+
+/// Timeout of each peer update (e.g., the SSH connection and "wg set").
+const UPDATE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Peer of the mesh to point to the new endpoint of this node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// "ssh://[user@]host[:port]", running "wg set" on the peer.
+    Ssh { destination: String, port: u16 },
+    /// "file:<path>", rewriting the "Endpoint" of the config file
+    /// (e.g., rendered for the peers by the config management).
+    File(String),
+    /// "http(s)://...", POSTing the endpoint to the mesh controller.
+    Api(String),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Ssh { destination, port } => write!(f, "ssh://{destination}:{port}"),
+            Target::File(path) => write!(f, "file:{path}"),
+            Target::Api(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// Body of the endpoint update POSTed to the mesh controller.
+#[derive(Debug, Serialize)]
+pub struct Update<'a> {
+    pub interface: &'a str,
+    pub public_key: &'a str,
+    pub endpoint: &'a str,
+}
+
+/// Parses the comma-separated targets of "--wireguard-peers"
+/// (e.g., "ssh://root@10.0.1.5,file:/etc/wireguard/peers/wg0.conf").
+pub fn parse(s: &str) -> io::Result<Vec<Target>> {
+    let mut targets = Vec::new();
+    for v in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let target = if let Some(rest) = v.strip_prefix("ssh://") {
+            let (destination, port) = match rest.rsplit_once(':') {
+                Some((d, p)) => (
+                    d,
+                    p.parse::<u16>().map_err(|_| {
+                        Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("'{v}' has an invalid port '{p}'"),
+                        )
+                    })?,
+                ),
+                None => (rest, 22),
+            };
+            if destination.is_empty() || destination.ends_with('@') {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("'{v}' has no host"),
+                ));
+            }
+            Target::Ssh {
+                destination: destination.to_string(),
+                port,
+            }
+        } else if let Some(path) = v.strip_prefix("file:") {
+            Target::File(path.to_string())
+        } else if v.starts_with("http://") || v.starts_with("https://") {
+            Target::Api(v.to_string())
+        } else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "'{v}' is not 'ssh://[user@]host[:port]', 'file:<path>', or an http(s) URL"
+                ),
+            ));
+        };
+        targets.push(target);
+    }
+    Ok(targets)
+}
+
+/// Returns the config with the "Endpoint" of the peer (by its public key)
+/// set to the endpoint, and whether it changed. Fails if the config has no such peer.
+pub fn set_endpoint(config: &str, public_key: &str, endpoint: &str) -> io::Result<(String, bool)> {
+    let mut out: Vec<String> = Vec::new();
+    // index in "out" of the current "[Peer]" header, its endpoint line, and whether it matches
+    let mut peer: Option<(usize, Option<usize>, bool)> = None;
+    let mut found = false;
+    let mut changed = false;
+
+    let mut finish = |out: &mut Vec<String>, peer: Option<(usize, Option<usize>, bool)>| {
+        if let Some((header, endpoint_line, true)) = peer {
+            found = true;
+            let line = format!("Endpoint = {endpoint}");
+            match endpoint_line {
+                Some(i) if out[i] == line => {}
+                Some(i) => {
+                    out[i] = line;
+                    changed = true;
+                }
+                None => {
+                    out.insert(header + 1, line);
+                    changed = true;
+                }
+            }
+        }
+    };
+    for line in config.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            finish(&mut out, peer.take());
+            if trimmed.eq_ignore_ascii_case("[peer]") {
+                peer = Some((out.len(), None, false));
+            }
+        } else if let (Some((_, endpoint_line, matches)), Some((k, v))) =
+            (peer.as_mut(), trimmed.split_once('='))
+        {
+            match k.trim().to_lowercase().as_str() {
+                "publickey" if v.trim() == public_key => *matches = true,
+                "endpoint" => *endpoint_line = Some(out.len()),
+                _ => {}
+            }
+        }
+        out.push(line.to_string());
+    }
+    finish(&mut out, peer.take());
+
+    if !found {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            format!("no [Peer] with PublicKey {public_key}"),
+        ));
+    }
+    let mut updated = out.join("\n");
+    if config.ends_with('\n') {
+        updated.push('\n');
+    }
+    Ok((updated, changed))
+}
+
+/// Returns the public key and the listen port of the local interface
+/// with "wg show" (e.g., for the peers to identify this node).
+pub async fn local_peer(interface: &str) -> io::Result<(String, u16)> {
+    let public_key = wg(&["show", interface, "public-key"]).await?;
+    let port = wg(&["show", interface, "listen-port"]).await?;
+    let port = port.parse::<u16>().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid listen port '{port}' of {interface}"),
+        )
+    })?;
+    Ok((public_key, port))
+}
+
+async fn wg(args: &[&str]) -> io::Result<String> {
+    let out = Command::new("wg")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| Error::new(e.kind(), format!("failed to run wg {:?} ({})", args, e)))?;
+    if !out.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "wg {:?} exited with {} {}",
+                args,
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Points the peers to the new endpoint of this node (e.g., "203.0.113.7:51820")
+/// after the EIP association (rotation, failover), so that the mesh converges
+/// without waiting for the peers to re-resolve. Reports the peers that failed
+/// in the summary (and "wireguard_peer_update_failures_total"), never failing the run.
+pub async fn update_peers(
+    ctx: &Context,
+    targets: &[Target],
+    interface: &str,
+    public_key: &str,
+    endpoint: &str,
+) {
+    for target in targets {
+        let res = timeout(
+            UPDATE_TIMEOUT,
+            update_peer(target, interface, public_key, endpoint),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(Error::new(
+                ErrorKind::TimedOut,
+                format!("timed out after {UPDATE_TIMEOUT:?}"),
+            ))
+        });
+        match res {
+            Ok(()) => log::info!("pointed WireGuard peer {target} to {endpoint}"),
+            Err(e) => {
+                ctx.metrics.inc_counter(FAILED_COUNTER);
+                ctx.summary.warn(&format!(
+                    "failed to point WireGuard peer {target} to {endpoint} '{}'",
+                    e
+                ));
+            }
+        }
+    }
+}
+
+/// Points the peer to the endpoint.
+pub async fn update_peer(
+    target: &Target,
+    interface: &str,
+    public_key: &str,
+    endpoint: &str,
+) -> io::Result<()> {
+    match target {
+        Target::Ssh { destination, port } => {
+            let out = Command::new("ssh")
+                .args([
+                    "-o",
+                    "BatchMode=yes",
+                    "-o",
+                    "ConnectTimeout=10",
+                    "-p",
+                    &port.to_string(),
+                    destination,
+                    "wg",
+                    "set",
+                    interface,
+                    "peer",
+                    public_key,
+                    "endpoint",
+                    endpoint,
+                ])
+                .stdin(Stdio::null())
+                .kill_on_drop(true)
+                .output()
+                .await?;
+            if !out.status.success() {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "ssh exited with {} {}",
+                        out.status,
+                        String::from_utf8_lossy(&out.stderr).trim()
+                    ),
+                ));
+            }
+            Ok(())
+        }
+        Target::File(path) => {
+            let config = fs::read_to_string(path)?;
+            let (updated, changed) = set_endpoint(&config, public_key, endpoint)?;
+            if changed {
+                // atomic, as the peers may reload the file at any time
+                let tmp = format!("{path}.tmp");
+                write_private(&tmp, &updated)?;
+                // keeps the mode of the original (e.g., 0600 of the private key)
+                fs::set_permissions(&tmp, fs::metadata(path)?.permissions())?;
+                fs::rename(&tmp, path)?;
+            }
+            Ok(())
+        }
+        Target::Api(url) => {
+            let body = serde_json::to_string(&Update {
+                interface,
+                public_key,
+                endpoint,
+            })
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to serialize {}", e)))?;
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri(url)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .map_err(|e| {
+                    Error::new(ErrorKind::InvalidInput, format!("invalid URL {url} {}", e))
+                })?;
+            let client = hyper::Client::builder().build::<_, Body>(tls::https_connector());
+            let resp = client
+                .request(req)
+                .await
+                .map_err(|e| Error::new(ErrorKind::Other, format!("failed POST {}", e)))?;
+            let status = resp.status();
+            if !status.is_success() {
+                let b = hyper::body::to_bytes(resp.into_body())
+                    .await
+                    .unwrap_or_default();
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("returned {status} '{}'", String::from_utf8_lossy(&b).trim()),
+                ));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Writes the new file readable only by the owner, as the config has
+/// the private key (replacing any stale file, whose mode would be kept).
+fn write_private(path: &str, contents: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut opts = fs::OpenOptions::new();
+    opts.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        opts.mode(0o600);
+    }
+    opts.open(path)?.write_all(contents.as_bytes())
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use aws_ip_provisioner::{
+    alert::{self, Action, Alert, Alerter},
+    sdk,
+};
+use serde_json::json;
+
+#[test]
+fn builds_opsgenie_requests() {
+    let opsgenie = Alert {
+        provider: "opsgenie".to_string(),
+        key_source: "env".to_string(),
+        url: String::new(),
+        failure_threshold: 3,
+    };
+    assert_eq!(opsgenie.default_key_env(), "OPSGENIE_API_KEY");
+    let details = json!({ "instance_id": "i-1", "id": "node-1" });
+
+    let (url, authorization, body) =
+        alert::request(&opsgenie, "key", Action::Trigger, "dedup", "down", &details);
+    assert_eq!(url, alert::OPSGENIE_URL);
+    assert_eq!(authorization.as_deref(), Some("GenieKey key"));
+    assert_eq!(body["alias"], "dedup");
+    assert_eq!(body["message"], "down");
+
+    let (url, _, _) = alert::request(
+        &opsgenie,
+        "key",
+        Action::Resolve,
+        "aws-ip-provisioner/i-1/node-1",
+        "up",
+        &details,
+    );
+    assert_eq!(
+        url,
+        format!(
+            "{}/aws-ip-provisioner%2Fi-1%2Fnode-1/close?identifierType=alias",
+            alert::OPSGENIE_URL
+        )
+    );
+}
+
+#[tokio::test]
+async fn triggers_once_and_resolves_on_recovery() {
+    let (url, requests) = common::serve("/v2/enqueue", "202 Accepted");
+    let key_file = std::env::temp_dir().join(format!("alert-key-{}", std::process::id()));
+    std::fs::write(&key_file, "routing-key\n").unwrap();
+    let mut alerter = Alerter::new(
+        Alert {
+            provider: "pagerduty".to_string(),
+            key_source: format!("file:{}", key_file.display()),
+            url,
+            failure_threshold: 2,
+        },
+        sdk::Options::default(),
+        "i-0123456789abcdef0",
+        "node-1",
+    );
+
+    alerter.record_failure("timed out").await;
+    assert!(!alerter.is_triggered());
+    assert!(requests.try_recv().is_err());
+
+    alerter.record_failure("timed out").await;
+    alerter.record_failure("timed out").await;
+    assert!(alerter.is_triggered());
+    alerter.record_success().await;
+    assert!(!alerter.is_triggered());
+    // no resolve without an open alert
+    alerter.record_success().await;
+
+    let requests: Vec<common::Request> = requests.try_iter().collect();
+    assert_eq!(requests.len(), 2, "{requests:?}");
+    let trigger = &requests[0].body;
+    assert!(
+        requests[0].line.starts_with("POST /v2/enqueue "),
+        "{}",
+        requests[0].line
+    );
+    assert!(requests[0].authorization.is_empty());
+    assert_eq!(trigger["routing_key"], "routing-key");
+    assert_eq!(trigger["event_action"], "trigger");
+    assert_eq!(
+        trigger["dedup_key"],
+        "aws-ip-provisioner/i-0123456789abcdef0/node-1"
+    );
+    assert_eq!(trigger["payload"]["severity"], "critical");
+    assert_eq!(
+        trigger["payload"]["custom_details"]["consecutive_failures"],
+        2
+    );
+    assert_eq!(requests[1].body["event_action"], "resolve");
+    assert_eq!(requests[1].body["dedup_key"], trigger["dedup_key"]);
+    std::fs::remove_file(&key_file).unwrap();
+}
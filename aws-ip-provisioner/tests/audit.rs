@@ -0,0 +1,208 @@
+use std::{
+    env, fs,
+    io::{Error, ErrorKind},
+};
+
+use aws_ip_provisioner::audit::{self, AuditLog, Entry, Filter, Holding};
+
+#[test]
+fn records_and_queries_the_history() {
+    let path = env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+    let audit_log = AuditLog::default();
+
+    // disabled by default
+    audit_log
+        .record("allocate", &[("allocation_id", "eipalloc-0")], &Ok(()))
+        .unwrap();
+    assert!(fs::metadata(path).is_err());
+
+    audit_log
+        .enable(
+            path,
+            "arn:aws:sts::123456789012:assumed-role/test",
+            "TEST-ID",
+        )
+        .unwrap();
+    audit_log
+        .record(
+            "allocate",
+            &[
+                ("allocation_id", "eipalloc-1"),
+                ("public_ip", "3.4.5.6"),
+                ("before", ""),
+                ("after", ""),
+            ],
+            &Ok(()),
+        )
+        .unwrap();
+    audit_log
+        .record(
+            "associate",
+            &[
+                ("allocation_id", "eipalloc-1"),
+                ("before", ""),
+                ("after", "i-1"),
+            ],
+            &Ok(()),
+        )
+        .unwrap();
+    // not an allocation, association, or release
+    audit_log
+        .record("retag", &[("allocation_id", "eipalloc-1")], &Ok(()))
+        .unwrap();
+    audit_log
+        .record(
+            "release_address",
+            &[("allocation_id", "eipalloc-2"), ("after", "")],
+            &Err::<(), _>(Error::new(ErrorKind::Other, "InvalidAllocationID.NotFound")),
+        )
+        .unwrap();
+    // e.g., the host crashed mid-write
+    let mut d = fs::read_to_string(path).unwrap();
+    d.push_str("{\"ts\":1");
+    fs::write(path, d).unwrap();
+    audit_log.disable();
+    assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 5);
+
+    let entries = audit::query(path, &Filter::default()).unwrap();
+    let actions: Vec<&str> = entries.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, vec!["allocate", "associate", "release_address"]);
+    assert_eq!(entries[2].result, "failure");
+    assert_eq!(entries[2].error, "InvalidAllocationID.NotFound");
+    assert!(entries.iter().all(|e| e.id == "TEST-ID"));
+    assert!(entries
+        .iter()
+        .all(|e| e.caller == "arn:aws:sts::123456789012:assumed-role/test"));
+
+    // the public IP of the association is filled in from its allocation
+    let entries = audit::query(
+        path,
+        &Filter {
+            public_ip: String::from("3.4.5.6"),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1].after, "i-1");
+
+    let entries = audit::query(
+        path,
+        &Filter {
+            action: String::from("associate"),
+            since: u64::MAX,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(entries.is_empty());
+
+    fs::remove_file(path).unwrap();
+    assert!(audit::query(path, &Filter::default()).is_err());
+}
+
+fn entry(ts: u64, action: &str, allocation_id: &str, after: &str, id: &str) -> Entry {
+    Entry {
+        ts,
+        action: action.to_string(),
+        allocation_id: allocation_id.to_string(),
+        after: after.to_string(),
+        result: String::from("success"),
+        id: id.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn assembles_the_timeline_of_the_address() {
+    let mut allocate = entry(100, "allocate", "eipalloc-1", "", "node-1");
+    allocate.public_ip = String::from("3.4.5.6");
+    let mut failed = entry(250, "associate", "eipalloc-1", "i-3", "node-2");
+    failed.result = String::from("failure");
+    let mut other = entry(110, "allocate", "eipalloc-2", "", "node-2");
+    other.public_ip = String::from("3.4.5.7");
+    let audit_logs = vec![
+        (
+            String::from("node-1.audit.jsonl"),
+            vec![
+                allocate,
+                entry(110, "associate", "eipalloc-1", "i-1", "node-1"),
+                // re-run on the next boot
+                entry(150, "associate", "eipalloc-1", "i-1", "node-1"),
+            ],
+        ),
+        (
+            String::from("node-2.audit.jsonl"),
+            vec![
+                other,
+                entry(120, "associate", "eipalloc-2", "i-2", "node-2"),
+                // stolen from node-1, which never recorded the end
+                entry(200, "associate", "eipalloc-1", "i-2", "node-2"),
+                failed,
+                entry(300, "disassociate", "eipalloc-1", "", "node-2"),
+                entry(400, "associate", "eipalloc-1", "eni-1", "node-2"),
+            ],
+        ),
+    ];
+
+    let holdings = audit::timeline("3.4.5.6", &audit_logs);
+    assert_eq!(
+        holdings,
+        vec![
+            Holding {
+                public_ip: String::from("3.4.5.6"),
+                allocation_id: String::from("eipalloc-1"),
+                holder: String::from("i-1"),
+                id: String::from("node-1"),
+                audit_log_file: String::from("node-1.audit.jsonl"),
+                from: 110,
+                until: Some(200),
+            },
+            Holding {
+                public_ip: String::from("3.4.5.6"),
+                allocation_id: String::from("eipalloc-1"),
+                holder: String::from("i-2"),
+                id: String::from("node-2"),
+                audit_log_file: String::from("node-2.audit.jsonl"),
+                from: 200,
+                until: Some(300),
+            },
+            Holding {
+                public_ip: String::from("3.4.5.6"),
+                allocation_id: String::from("eipalloc-1"),
+                holder: String::from("eni-1"),
+                id: String::from("node-2"),
+                audit_log_file: String::from("node-2.audit.jsonl"),
+                from: 400,
+                until: None,
+            },
+        ]
+    );
+
+    // who had the address at 250, 350, and now
+    let at = |ts: u64| -> Vec<String> {
+        holdings
+            .iter()
+            .filter(|h| h.held_at(ts))
+            .map(|h| h.holder.clone())
+            .collect()
+    };
+    assert_eq!(at(250), vec!["i-2"]);
+    assert!(at(350).is_empty());
+    assert_eq!(at(u64::MAX), vec!["eni-1"]);
+
+    assert!(audit::timeline("3.4.5.8", &audit_logs).is_empty());
+}
+
+#[test]
+fn parses_since() {
+    assert_eq!(audit::parse_since("", 1000).unwrap(), 0);
+    assert_eq!(audit::parse_since("90s", 1000).unwrap(), 910);
+    assert_eq!(audit::parse_since("1h", 10000).unwrap(), 6400);
+    assert_eq!(audit::parse_since("7d", 100).unwrap(), 0);
+    assert_eq!(audit::parse_since("1673000000", 0).unwrap(), 1673000000);
+    assert!(audit::parse_since("1w", 1000).is_err());
+    assert!(audit::parse_since("h", 1000).is_err());
+}
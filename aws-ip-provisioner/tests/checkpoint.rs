@@ -0,0 +1,42 @@
+use std::{env, fs};
+
+use aws_ip_provisioner::checkpoint::Checkpoint;
+
+#[test]
+fn resumes_the_same_run() {
+    let path = env::temp_dir().join(format!("checkpoint-{}.json", std::process::id()));
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+
+    let mut checkpoint = Checkpoint::open(path, "inventory org=true").unwrap();
+    assert_eq!(checkpoint.done(), 0);
+    checkpoint
+        .record("123456789012", &vec!["eipalloc-1".to_string()])
+        .unwrap();
+    checkpoint
+        .record("210987654321", &Vec::<String>::new())
+        .unwrap();
+
+    // e.g., interrupted, and rerun with the same flags
+    let checkpoint = Checkpoint::open(path, "inventory org=true").unwrap();
+    assert_eq!(checkpoint.done(), 2);
+    assert_eq!(
+        checkpoint.get::<Vec<String>>("123456789012"),
+        Some(vec!["eipalloc-1".to_string()])
+    );
+    assert_eq!(checkpoint.get::<Vec<String>>("111111111111"), None);
+
+    // another run starts over, and the finished run leaves no file
+    assert_eq!(
+        Checkpoint::open(path, "inventory org=false")
+            .unwrap()
+            .done(),
+        0
+    );
+    checkpoint.finish().unwrap();
+    assert!(fs::metadata(path).is_err());
+
+    fs::write(path, "{").unwrap();
+    assert!(Checkpoint::open(path, "inventory org=true").is_err());
+    fs::remove_file(path).unwrap();
+}
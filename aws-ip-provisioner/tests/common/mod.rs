@@ -0,0 +1,182 @@
+//! Fixtures shared by the integration tests.
+
+// each test crate uses a part of the fixtures
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+};
+
+use aws_ip_provisioner::command::{self, Flags};
+use serde_json::Value;
+
+/// Returns an empty directory unique to the test.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("aws-ip-provisioner-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Parses the flags as the command line would, with the required flags unless overridden.
+pub fn flags(extra: &[&str]) -> Flags {
+    let mut argv = vec![command::NAME.to_string()];
+    for required in [
+        "--id-tag-key=Id",
+        "--id-tag-value=node-1",
+        "--kind-tag-key=Kind",
+        "--kind-tag-value=test",
+        "--mounted-eip-file-path=/data/eip.yaml",
+    ] {
+        let name = required.split('=').next().unwrap();
+        if !extra.iter().any(|v| v.starts_with(name)) {
+            argv.push(required.to_string());
+        }
+    }
+    argv.extend(extra.iter().map(|v| v.to_string()));
+    command::parse_flags(&command::new().get_matches_from(argv))
+}
+
+/// Same as "flags", with the state file in the directory of the test.
+pub fn state_flags(name: &str, extra: &[&str]) -> Flags {
+    let eip_file = format!(
+        "--mounted-eip-file-path={}",
+        test_dir(name).join("eip.yaml").display()
+    );
+    let mut argv = vec![eip_file.as_str()];
+    argv.extend(extra);
+    flags(&argv)
+}
+
+/// Request received by "serve_with", as sent by the client.
+#[derive(Debug, Clone)]
+pub struct RawRequest {
+    /// Request line and headers (e.g., "GET /path?query HTTP/1.1\r\nhost: ...").
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+impl RawRequest {
+    /// Returns the method of the request line (e.g., "PUT").
+    pub fn method(&self) -> &str {
+        self.head.split(' ').next().unwrap_or_default()
+    }
+
+    /// Returns the path and the query of the request line.
+    pub fn target(&self) -> &str {
+        self.head.split(' ').nth(1).unwrap_or_default()
+    }
+
+    /// Returns the value of the header, with the name case-insensitive.
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.head.lines().skip(1).find_map(|l| {
+            let (k, v) = l.split_once(':')?;
+            k.trim()
+                .eq_ignore_ascii_case(name)
+                .then(|| v.trim().to_string())
+        })
+    }
+}
+
+/// Serves HTTP on a local port, one request per connection, answering each
+/// with the status and the body returned by the handler.
+/// Returns the URL of the path.
+pub fn serve_with<F>(path: &str, mut handler: F) -> String
+where
+    F: FnMut(&RawRequest) -> (&'static str, Vec<u8>) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}{path}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let req = read_request(&mut stream);
+            let (status, body) = handler(&req);
+            let mut resp = format!(
+                "HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                body.len()
+            )
+            .into_bytes();
+            resp.extend(body);
+            let _ = stream.write_all(&resp);
+        }
+    });
+    url
+}
+
+/// Reads the headers and the content-length body.
+fn read_request(stream: &mut impl Read) -> RawRequest {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).unwrap();
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&buf[..pos]).to_string();
+            let len = head
+                .lines()
+                .find_map(|l| {
+                    l.to_lowercase()
+                        .strip_prefix("content-length:")
+                        .map(|v| v.trim().parse::<usize>().unwrap())
+                })
+                .unwrap_or_default();
+            let body = &buf[pos + 4..];
+            if body.len() >= len {
+                return RawRequest {
+                    head,
+                    body: body[..len].to_vec(),
+                };
+            }
+        }
+        if n == 0 {
+            panic!("connection closed mid-request");
+        }
+    }
+}
+
+/// Serves HTTP on a local port, answering every request with the status and the body.
+/// Returns the URL of the path, and the requests in the order received.
+pub fn serve_raw(
+    path: &str,
+    status: &'static str,
+    body: &'static str,
+) -> (String, mpsc::Receiver<RawRequest>) {
+    let (tx, rx) = mpsc::channel();
+    let url = serve_with(path, move |req| {
+        let _ = tx.send(req.clone());
+        (status, body.as_bytes().to_vec())
+    });
+    (url, rx)
+}
+
+/// Request received by "serve".
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// Request line (e.g., "POST /hook HTTP/1.1").
+    pub line: String,
+    /// Value of the authorization header, empty if none.
+    pub authorization: String,
+    pub body: Value,
+}
+
+/// Serves HTTP on a local port, sending each request with its JSON body
+/// before answering with the status (e.g., "202 Accepted").
+/// Returns the URL of the path, and the requests in the order received.
+pub fn serve(path: &str, status: &'static str) -> (String, mpsc::Receiver<Request>) {
+    let (tx, rx) = mpsc::channel();
+    let url = serve_with(path, move |req| {
+        let _ = tx.send(Request {
+            line: req.head.lines().next().unwrap().to_string(),
+            authorization: req.header("authorization").unwrap_or_default(),
+            body: serde_json::from_slice(&req.body).unwrap(),
+        });
+        (status, Vec::new())
+    });
+    (url, rx)
+}
@@ -0,0 +1,99 @@
+#![cfg(feature = "consul")]
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use aws_ip_provisioner::{consul, ec2, state};
+
+type Store = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+/// Serves the Consul KV "?raw" reads and the writes from memory.
+fn fake_kv() -> (String, Store) {
+    let kv: Store = Arc::new(Mutex::new(HashMap::new()));
+    let store = kv.clone();
+    let addr = common::serve_with("", move |req| {
+        let key = req
+            .target()
+            .trim_start_matches("/v1/kv/")
+            .split('?')
+            .next()
+            .unwrap()
+            .to_string();
+        match req.method() {
+            "PUT" => {
+                store.lock().unwrap().insert(key, req.body.clone());
+                ("200 OK", b"true".to_vec())
+            }
+            _ => match store.lock().unwrap().get(&key) {
+                Some(v) => ("200 OK", v.clone()),
+                None => ("404 Not Found", Vec::new()),
+            },
+        }
+    });
+    (addr, kv)
+}
+
+#[tokio::test]
+async fn saves_and_restores_state() {
+    let (addr, kv) = fake_kv();
+    let dir = common::test_dir("consul-state");
+    let file_path = dir.join("eip.yaml");
+    let opts = common::flags(&[
+        "--state-backend=consul",
+        &format!("--consul-address={addr}"),
+        "--consul-kv-prefix=/clusters/a/",
+        &format!("--mounted-eip-file-path={}", file_path.display()),
+    ]);
+    let client = opts.consul();
+
+    // nothing to restore yet
+    assert!(!consul::restore_state(&client, &opts).await.unwrap());
+    assert!(!file_path.exists());
+
+    fs::create_dir_all(&dir).unwrap();
+    let eip = ec2::Eip {
+        allocation_id: String::from("eipalloc-1"),
+        public_ip: String::from("203.0.113.1"),
+    };
+    state::record_eip(file_path.to_str().unwrap(), &eip).unwrap();
+    consul::save_state(&client, &opts).await.unwrap();
+    assert!(kv.lock().unwrap().contains_key("clusters/a/state/node-1"));
+
+    // replacement instance with a fresh volume
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(consul::restore_state(&client, &opts).await.unwrap());
+    assert_eq!(state::load_eip(file_path.to_str().unwrap()).unwrap(), eip);
+    // the existing file is never overwritten
+    assert!(!consul::restore_state(&client, &opts).await.unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn defines_service_with_health_check() {
+    let eip = ec2::Eip {
+        allocation_id: String::from("eipalloc-1"),
+        public_ip: String::from("203.0.113.1"),
+    };
+    let opts = common::flags(&[
+        "--kind-tag-value=mainnet",
+        "--consul-service-name=p2p",
+        "--consul-service-port=30303",
+    ]);
+    let def = consul::service_definition(&opts, &eip, "i-1", "10.0.0.5");
+    assert_eq!(def["ID"], "p2p-node-1");
+    assert_eq!(def["Address"], "203.0.113.1");
+    assert_eq!(def["Port"], 30303);
+    assert_eq!(def["Tags"][0], "mainnet");
+    assert_eq!(def["Meta"]["allocation_id"], "eipalloc-1");
+    assert_eq!(def["Check"]["TCP"], "10.0.0.5:30303");
+
+    let opts = common::flags(&["--kind-tag-value=mainnet", "--consul-service-name=p2p"]);
+    let def = consul::service_definition(&opts, &eip, "i-1", "10.0.0.5");
+    assert!(def.get("Port").is_none());
+    assert!(def.get("Check").is_none());
+}
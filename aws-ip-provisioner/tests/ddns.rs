@@ -0,0 +1,144 @@
+//! Tests of the dynamic DNS updaters against a local DNS server and HTTP endpoint.
+
+mod common;
+
+use std::{io::ErrorKind, net::IpAddr};
+
+use aws_ip_provisioner::ddns::{self, Tsig};
+use ring::hmac;
+use tokio::net::UdpSocket;
+
+/// base64 of "0123456789abcdef0123456789abcdef".
+const SECRET: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+#[test]
+fn signs_the_update() {
+    let key = Tsig::new("ddns-key", "hmac-sha256", SECRET).unwrap();
+    let msg = ddns::update_message(
+        0x1234,
+        "lab.example.com",
+        "node1.lab.example.com",
+        "203.0.113.7".parse().unwrap(),
+        60,
+    )
+    .unwrap();
+    // opcode UPDATE, one zone, two updates
+    assert_eq!(&msg[..12], &[0x12, 0x34, 0x28, 0, 0, 1, 0, 0, 0, 2, 0, 0]);
+    assert_eq!(&msg[msg.len() - 4..], &[203, 0, 113, 7]);
+
+    let signed = ddns::sign(&msg, &key, 1_700_000_000).unwrap();
+    assert_eq!(&signed[10..12], &[0, 1]);
+    // key name, then TSIG (250) of class ANY
+    let tsig = &signed[msg.len()..];
+    assert_eq!(&tsig[..10], b"\x08ddns-key\x00");
+    assert_eq!(&tsig[10..14], &[0, 250, 0, 255]);
+
+    // the MAC covers the unsigned message and the TSIG variables
+    let mut digest = msg.clone();
+    digest.extend(b"\x08ddns-key\x00");
+    digest.extend([0, 255, 0, 0, 0, 0]);
+    digest.extend(b"\x0bhmac-sha256\x00");
+    digest.extend(&1_700_000_000u64.to_be_bytes()[2..]);
+    digest.extend([1, 44, 0, 0, 0, 0]);
+    let mac = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, b"0123456789abcdef0123456789abcdef"),
+        &digest,
+    );
+    assert!(signed
+        .windows(mac.as_ref().len())
+        .any(|w| w == mac.as_ref()));
+
+    assert!(Tsig::new("ddns-key", "hmac-md5", SECRET).is_err());
+    assert!(Tsig::new("ddns-key", "hmac-sha256", "not base64!").is_err());
+}
+
+#[tokio::test]
+async fn updates_with_rfc2136() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 1500];
+        loop {
+            let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+            // refuses the unsigned updates
+            let rcode = if buf[11] == 0 { 5 } else { 0 };
+            let mut resp = buf[..12].to_vec();
+            resp[2] |= 0x80;
+            resp[3] = rcode;
+            resp.extend(&buf[12..n]);
+            socket.send_to(&resp, peer).await.unwrap();
+        }
+    });
+
+    let ip: IpAddr = "2001:db8::7".parse().unwrap();
+    let key = Tsig::new("ddns-key", "hmac-sha512", SECRET).unwrap();
+    ddns::rfc2136(
+        server,
+        "lab.example.com",
+        "node1.lab.example.com",
+        ip,
+        60,
+        Some(&key),
+    )
+    .await
+    .unwrap();
+
+    let e = ddns::rfc2136(
+        server,
+        "lab.example.com",
+        "node1.lab.example.com",
+        ip,
+        60,
+        None,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    assert!(e.to_string().contains("REFUSED"), "{e}");
+}
+
+#[tokio::test]
+async fn updates_with_dyndns2() {
+    let (url, requests) = common::serve_raw("/nic/update", "200 OK", "good 203.0.113.7");
+    ddns::dyndns2(&url, "node1.example.net", "user", "pass", "203.0.113.7")
+        .await
+        .unwrap();
+    let req = requests.recv().unwrap().head;
+    assert!(
+        req.starts_with("GET /nic/update?hostname=node1.example.net&myip=203.0.113.7 "),
+        "{req}"
+    );
+    // base64 of "user:pass"
+    assert!(req.contains("authorization: Basic dXNlcjpwYXNz"), "{req}");
+    assert!(req.contains("user-agent: ip-manager/"), "{req}");
+
+    let (url, _) = common::serve_raw("/nic/update", "200 OK", "badauth");
+    let e = ddns::dyndns2(&url, "node1.example.net", "user", "wrong", "203.0.113.7")
+        .await
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+
+    assert!(ddns::parse_dyndns2_response("nochg 203.0.113.7\n").is_ok());
+    assert_eq!(
+        ddns::parse_dyndns2_response("nohost").unwrap_err().kind(),
+        ErrorKind::NotFound
+    );
+}
+
+#[tokio::test]
+async fn updates_with_duckdns() {
+    let (url, requests) = common::serve_raw("/nic/update", "200 OK", "OK");
+    ddns::duckdns(&url, "mynode.duckdns.org", "token", "203.0.113.7")
+        .await
+        .unwrap();
+    assert!(requests
+        .recv()
+        .unwrap()
+        .head
+        .starts_with("GET /nic/update?domains=mynode&token=token&ip=203.0.113.7 "));
+
+    let (url, _) = common::serve_raw("/nic/update", "200 OK", "KO");
+    assert!(ddns::duckdns(&url, "mynode", "wrong", "203.0.113.7")
+        .await
+        .is_err());
+}
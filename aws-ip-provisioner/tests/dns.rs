@@ -0,0 +1,129 @@
+//! Tests of the DNS propagation verification against a local resolver.
+
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, SocketAddr},
+};
+
+use aws_ip_provisioner::{context::Context, dns};
+use tokio::net::UdpSocket;
+
+/// Starts a resolver that answers every query with the addresses of its type
+/// (NXDOMAIN if empty), returning its address.
+async fn serve(addrs: Vec<IpAddr>) -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let local = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+            let query = &buf[..n];
+            // header, then the question ending with the type and class
+            let qtype = u16::from_be_bytes([query[n - 4], query[n - 3]]);
+            let answers: Vec<&IpAddr> = addrs
+                .iter()
+                .filter(|ip| {
+                    if qtype == 28 {
+                        ip.is_ipv6()
+                    } else {
+                        ip.is_ipv4()
+                    }
+                })
+                .collect();
+
+            let mut resp = query[..2].to_vec();
+            let rcode = if addrs.is_empty() { 3 } else { 0 };
+            resp.extend((0x8180u16 | rcode).to_be_bytes());
+            resp.extend([0, 1]);
+            resp.extend((answers.len() as u16).to_be_bytes());
+            resp.extend([0, 0, 0, 0]);
+            resp.extend(&query[12..]);
+            for ip in answers {
+                let rdata = match ip {
+                    IpAddr::V4(v) => v.octets().to_vec(),
+                    IpAddr::V6(v) => v.octets().to_vec(),
+                };
+                // pointer to the name in the question
+                resp.extend([0xc0, 12]);
+                resp.extend(qtype.to_be_bytes());
+                resp.extend([0, 1, 0, 0, 0, 60]);
+                resp.extend((rdata.len() as u16).to_be_bytes());
+                resp.extend(rdata);
+            }
+            socket.send_to(&resp, peer).await.unwrap();
+        }
+    });
+    local
+}
+
+#[tokio::test]
+async fn verifies_name_on_resolvers() {
+    let v4 = serve(vec!["203.0.113.7".parse().unwrap()]).await;
+    let dual = serve(vec![
+        "203.0.113.7".parse().unwrap(),
+        "2001:db8::10".parse().unwrap(),
+    ])
+    .await;
+
+    dns::verify(
+        &Context::default(),
+        "node-1.example.com",
+        &[String::from("203.0.113.7")],
+        &format!("{v4},{dual}"),
+        0,
+    )
+    .await
+    .unwrap();
+    dns::verify(
+        &Context::default(),
+        "node-1.example.com.",
+        &[String::from("203.0.113.7"), String::from("2001:db8::10")],
+        &dual.to_string(),
+        0,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn times_out_until_propagated() {
+    // still the previous address
+    let stale = serve(vec!["198.51.100.1".parse().unwrap()]).await;
+    let err = dns::verify(
+        &Context::default(),
+        "node-1.example.com",
+        &[String::from("203.0.113.7")],
+        &stale.to_string(),
+        0,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(err.to_string().contains(&stale.to_string()), "{err}");
+
+    // not created yet
+    let missing = serve(Vec::new()).await;
+    let err = dns::verify(
+        &Context::default(),
+        "node-1.example.com",
+        &[String::from("203.0.113.7")],
+        &missing.to_string(),
+        0,
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+}
+
+#[test]
+fn parses_resolvers() {
+    assert_eq!(
+        dns::parse_resolver("10.0.0.2").unwrap(),
+        "10.0.0.2:53".parse().unwrap()
+    );
+    assert_eq!(
+        dns::parse_resolver("[fd00:ec2::253]:5353").unwrap(),
+        "[fd00:ec2::253]:5353".parse().unwrap()
+    );
+    assert!(dns::parse_resolver("ns1.example.com").is_err());
+}
@@ -0,0 +1,62 @@
+use std::{
+    fs,
+    net::{TcpListener, TcpStream},
+    time::{Duration, Instant},
+};
+
+use aws_ip_provisioner::drain::{self, Drain};
+
+#[test]
+fn counts_established_connections() {
+    let proc_net_tcp = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1 1 0000000000000000 100 0 0 10 0
+   1: 0500000A:1F90 0A00000A:C350 01 00000000:00000000 00:00000000 00000000     0        0 2 1 0000000000000000 20 4 30 10 -1
+   2: 0500000A:1F90 0B00000A:C351 06 00000000:00000000 00:00000000 00000000     0        0 0 3 0000000000000000
+   3: 0500000A:C352 0A00000A:1F90 01 00000000:00000000 00:00000000 00000000     0        0 3 1 0000000000000000 20 4 30 10 -1
+";
+    // only the established (not listening, not TIME_WAIT) on the local port 8080
+    assert_eq!(drain::count_established(proc_net_tcp, 8080), 1);
+    assert_eq!(drain::count_established(proc_net_tcp, 443), 0);
+
+    let netstat = "
+Active Connections
+
+  Proto  Local Address          Foreign Address        State
+  TCP    0.0.0.0:8080           0.0.0.0:0              LISTENING
+  TCP    10.0.0.5:8080          10.0.0.10:50000        ESTABLISHED
+  TCP    10.0.0.5:8080          10.0.0.11:50001        TIME_WAIT
+  TCP    10.0.0.5:50002         10.0.0.10:8080         ESTABLISHED
+  TCP    [::1]:8080             [::1]:50003            ESTABLISHED
+";
+    assert_eq!(drain::count_netstat_established(netstat, 8080), 2);
+    assert_eq!(drain::count_netstat_established(netstat, 443), 0);
+}
+
+#[tokio::test]
+async fn signals_and_waits_until_deadline() {
+    let marker = std::env::temp_dir().join(format!("drain-{}", std::process::id()));
+    let _ = fs::remove_file(&marker);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let conn = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+    let d = Drain {
+        cmd: format!("touch {}", marker.display()),
+        url: String::new(),
+        port,
+        timeout: Duration::from_secs(1),
+    };
+    assert!(d.is_enabled());
+    let started = Instant::now();
+    d.run(&[("public_ip", String::from("203.0.113.1"))]).await;
+    assert!(marker.exists());
+    // the connection never closes, so it waits for the deadline
+    assert!(started.elapsed() >= Duration::from_secs(1));
+
+    drop(conn);
+    drop(listener);
+    let started = Instant::now();
+    d.run(&[]).await;
+    assert!(started.elapsed() < Duration::from_secs(1));
+    fs::remove_file(&marker).unwrap();
+}
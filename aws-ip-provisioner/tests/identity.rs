@@ -0,0 +1,215 @@
+//! Tests of the instance identity document verification, with the PKCS7
+//! signatures of a test certificate (the same format as the AWS "rsa2048").
+
+use std::io::ErrorKind;
+
+use aws_ip_provisioner::identity::{self, Signed};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Self-signed RSA-2048 certificate that signed the fixtures.
+const CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIDcTCCAlmgAwIBAgIUBecn9+NoS1TnQff6OHhvrEHuOLswDQYJKoZIhvcNAQEL
+BQAwRzELMAkGA1UEBhMCVVMxEzARBgNVBAgMCldhc2hpbmd0b24xEDAOBgNVBAcM
+B1NlYXR0bGUxETAPBgNVBAoMCFRlc3QgRUMyMCAXDTI2MTAxNjAyMzUwNVoYDzIx
+MjYwOTIyMDIzNTA1WjBHMQswCQYDVQQGEwJVUzETMBEGA1UECAwKV2FzaGluZ3Rv
+bjEQMA4GA1UEBwwHU2VhdHRsZTERMA8GA1UECgwIVGVzdCBFQzIwggEiMA0GCSqG
+SIb3DQEBAQUAA4IBDwAwggEKAoIBAQCyt0O3edTisvwiaLWnDmo5zYyoKrRDCXIW
+vFrLSvHOEsNmRJIQlV+p6ELUAMhgk6aLa9S7pl+Nc0JWG8YO2E/DGPuDeazKSBvy
++j9eEAGjBwk5kT/hvOkeUtbslc6i7e431HlPNJNe+Yx2+Lj+4UcF1E8jFPFE0NAC
+WW/RBl+5G6cRX0U6UKTZkSnKmvU44A3GEYhD9i2c+EXalz2SDYogGEdjveTKwDTZ
+bvRHvVY0UGcLQoUC6eLxxcOZn0Lkw4zLN0cy9XEPI/zPpgfii7di2Ec78vMv7ewc
+SyWDoTcPqug4+DtZqDiTIsED8YBs0n8FA3FbUoE79bvVXSRceYKVAgMBAAGjUzBR
+MB0GA1UdDgQWBBQ6UPiEMrAWTr7O3LOq9s3RgW3c2jAfBgNVHSMEGDAWgBQ6UPiE
+MrAWTr7O3LOq9s3RgW3c2jAPBgNVHRMBAf8EBTADAQH/MA0GCSqGSIb3DQEBCwUA
+A4IBAQBecZ9idxBzPk9H2iUvsfG57DsXFWiTtLHjbGcvksYNaHHxJpJ1swjQPrHI
+aqrbYxBU0AQn0/PVMfENvyDSnpPRTy1m6PLk/tkTByYDjqv+vLQW3CXPmKdTgsSJ
+ynxr0JyPf8aM32sCHOqXXGG106MKcyeI//cJxJP12GBv6qunGe7yEGxTqgSD1Qgi
+bDf7HOpUgwHp7oMxz8QAbxc/0UBKMeVr1GsIdW3bFDff21f4dn1nSf3UKITMKvDp
+GbM6slKX86HeJ8FC3jlZZYj0UVYW0nkr3eSG+KMrU03bGPML0ioFyG6PSiR6EUWR
+7AX/vbdrLregctHr3KzLs+5X9WNq
+-----END CERTIFICATE-----
+";
+
+/// Another RSA-2048 certificate, which did not sign the fixtures.
+const OTHER_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIDAzCCAeugAwIBAgIUcagh+qSqgf+IendgM0CkA90jAQcwDQYJKoZIhvcNAQEL
+BQAwEDEOMAwGA1UECgwFT3RoZXIwIBcNMjYxMDE2MDIzNTA1WhgPMjEyNjA5MjIw
+MjM1MDVaMBAxDjAMBgNVBAoMBU90aGVyMIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8A
+MIIBCgKCAQEAuMElDr4ThYjdYxJazLBbuoQxj7MxgE6qR7FA2iVclknTRpU4QZVx
+qofKqPpDh4nqBKWQsIvTdgLiGJ8LZdevaKHfbrS4r+8fVQR/nKeNQevP35PrH3mI
+SSEM8zQvCO0bt/tm3xqvNr31I3BTHXUXW57OL+ziIGl9PSYKkCslG4Yg1YPiBoqW
+rgfKhkOjvTv22MBZzwoAV+TacpF0+RyUvudouTx7cl403f67jtmTWVoSmvSjFbi6
+J53kac5msClLtszHr2FeiqbMRJZswEeOqMxPHRWigm3bGIp91112mOw/JRNJER4O
+2N0gXFWStXX00H4V0Q0TVaQI90NufdVfewIDAQABo1MwUTAdBgNVHQ4EFgQUoyUN
+30vs8WNoBgQdX3KbQleziNAwHwYDVR0jBBgwFoAUoyUN30vs8WNoBgQdX3KbQlez
+iNAwDwYDVR0TAQH/BAUwAwEB/zANBgkqhkiG9w0BAQsFAAOCAQEAHZkeh77mJljA
+rm2bcA2NXvrqKjB9jH4HWUqd8qhnA2nZJmVXQO2htMoeWir8d5nfsqjnlv0DnjCP
+PG8oyoEUxOOM/x+2P5QOl/XIEBaoKX0agYEYFP8Khg5+h+7BNhourky84VQ9LJe5
+VKfDusBWhjhKiAF/OdmtofbloARxynPgf4oG7jXUTtf6IbX3FQNxb6nXVcdjhV1P
+P7PqDkJ4RNBzJHFzt/0UtRCJP9xWddzoleUBOP2kIjJ3cwpUlwezD+C/KDKispjN
+V6JFrBLY31JD5e4K5lrhn375cQcPfogawVUTwd9yhTyYAP6mllOgUhfOGhi2TgjR
+QQ7IRRwHHQ==
+-----END CERTIFICATE-----
+";
+
+const DOCUMENT: &str = r#"{
+  "accountId" : "123456789012",
+  "architecture" : "x86_64",
+  "availabilityZone" : "us-west-2a",
+  "imageId" : "ami-0123456789abcdef0",
+  "instanceId" : "i-0123456789abcdef0",
+  "instanceType" : "t3.micro",
+  "pendingTime" : "2024-01-01T00:00:00Z",
+  "privateIp" : "10.0.0.10",
+  "region" : "us-west-2",
+  "version" : "2017-09-30"
+}"#;
+
+/// "openssl smime -sign -nodetach -nocerts -md sha256 -stream" (BER, indefinite lengths).
+const PKCS7_BER: &str = "-----BEGIN PKCS7-----
+MIAGCSqGSIb3DQEHAqCAMIACAQExDzANBglghkgBZQMEAgEFADCABgkqhkiG9w0B
+BwGggCSABIIBT3sKICAiYWNjb3VudElkIiA6ICIxMjM0NTY3ODkwMTIiLAogICJh
+cmNoaXRlY3R1cmUiIDogIng4Nl82NCIsCiAgImF2YWlsYWJpbGl0eVpvbmUiIDog
+InVzLXdlc3QtMmEiLAogICJpbWFnZUlkIiA6ICJhbWktMDEyMzQ1Njc4OWFiY2Rl
+ZjAiLAogICJpbnN0YW5jZUlkIiA6ICJpLTAxMjM0NTY3ODlhYmNkZWYwIiwKICAi
+aW5zdGFuY2VUeXBlIiA6ICJ0My5taWNybyIsCiAgInBlbmRpbmdUaW1lIiA6ICIy
+MDI0LTAxLTAxVDAwOjAwOjAwWiIsCiAgInByaXZhdGVJcCIgOiAiMTAuMC4wLjEw
+IiwKICAicmVnaW9uIiA6ICJ1cy13ZXN0LTIiLAogICJ2ZXJzaW9uIiA6ICIyMDE3
+LTA5LTMwIgp9AAAAAAAAMYICcTCCAm0CAQEwXzBHMQswCQYDVQQGEwJVUzETMBEG
+A1UECAwKV2FzaGluZ3RvbjEQMA4GA1UEBwwHU2VhdHRsZTERMA8GA1UECgwIVGVz
+dCBFQzICFAXnJ/fjaEtU50H3+jh4b6xB7ji7MA0GCWCGSAFlAwQCAQUAoIHkMBgG
+CSqGSIb3DQEJAzELBgkqhkiG9w0BBwEwHAYJKoZIhvcNAQkFMQ8XDTI2MTAxNjAy
+MzUwNVowLwYJKoZIhvcNAQkEMSIEIIzo7RaOeicGOw2J46tJwGip1/v4PeQXGRzQ
+eFfn0j4WMHkGCSqGSIb3DQEJDzFsMGowCwYJYIZIAWUDBAEqMAsGCWCGSAFlAwQB
+FjALBglghkgBZQMEAQIwCgYIKoZIhvcNAwcwDgYIKoZIhvcNAwICAgCAMA0GCCqG
+SIb3DQMCAgFAMAcGBSsOAwIHMA0GCCqGSIb3DQMCAgEoMA0GCSqGSIb3DQEBAQUA
+BIIBAJnVz7J5MPxi+FCHaP378uuxYhQPhwO/KA19Di6rSp/raImvfSlC+Je37Oo0
+4j7GFGp7mMCFcZIVq8AvMBRwCqKOsL0HozUjY4WXXRVuKTxYufZyQ7RAz+3lypuK
+K258tqgcqw1D5P+gWJyjKgC/3KB4AY9MwII66KYpr/YYKuGZSfv42Q6rjntVy+s8
+OvID1SifHwKkaIKLVwIXy5vAtrgZmti1mOZYkwAoCzCg5HFqOW7P3ygCkLHfgpwB
+zEThICv7ohRSuQ4KCWs9e5cBrNY6qYYWd/ayY8zqFpzj2qgFMY3ZRCPLo5HFQVnO
+z1rMvWjlVN0MaRuD1VviLOZ7KEEAAAAAAAA=
+-----END PKCS7-----
+";
+
+/// Same without "-stream" (DER), without the PEM armor as served by IMDS.
+const PKCS7_DER: &str = "MIIEAgYJKoZIhvcNAQcCoIID8zCCA+8CAQExDzANBglghkgBZQMEAgEFADCCAWIG
+CSqGSIb3DQEHAaCCAVMEggFPewogICJhY2NvdW50SWQiIDogIjEyMzQ1Njc4OTAx
+MiIsCiAgImFyY2hpdGVjdHVyZSIgOiAieDg2XzY0IiwKICAiYXZhaWxhYmlsaXR5
+Wm9uZSIgOiAidXMtd2VzdC0yYSIsCiAgImltYWdlSWQiIDogImFtaS0wMTIzNDU2
+Nzg5YWJjZGVmMCIsCiAgImluc3RhbmNlSWQiIDogImktMDEyMzQ1Njc4OWFiY2Rl
+ZjAiLAogICJpbnN0YW5jZVR5cGUiIDogInQzLm1pY3JvIiwKICAicGVuZGluZ1Rp
+bWUiIDogIjIwMjQtMDEtMDFUMDA6MDA6MDBaIiwKICAicHJpdmF0ZUlwIiA6ICIx
+MC4wLjAuMTAiLAogICJyZWdpb24iIDogInVzLXdlc3QtMiIsCiAgInZlcnNpb24i
+IDogIjIwMTctMDktMzAiCn0xggJxMIICbQIBATBfMEcxCzAJBgNVBAYTAlVTMRMw
+EQYDVQQIDApXYXNoaW5ndG9uMRAwDgYDVQQHDAdTZWF0dGxlMREwDwYDVQQKDAhU
+ZXN0IEVDMgIUBecn9+NoS1TnQff6OHhvrEHuOLswDQYJYIZIAWUDBAIBBQCggeQw
+GAYJKoZIhvcNAQkDMQsGCSqGSIb3DQEHATAcBgkqhkiG9w0BCQUxDxcNMjYxMDE2
+MDIzNTA1WjAvBgkqhkiG9w0BCQQxIgQgjOjtFo56JwY7DYnjq0nAaKnX+/g95BcZ
+HNB4V+fSPhYweQYJKoZIhvcNAQkPMWwwajALBglghkgBZQMEASowCwYJYIZIAWUD
+BAEWMAsGCWCGSAFlAwQBAjAKBggqhkiG9w0DBzAOBggqhkiG9w0DAgICAIAwDQYI
+KoZIhvcNAwICAUAwBwYFKw4DAgcwDQYIKoZIhvcNAwICASgwDQYJKoZIhvcNAQEB
+BQAEggEAmdXPsnkw/GL4UIdo/fvy67FiFA+HA78oDX0OLqtKn+toia99KUL4l7fs
+6jTiPsYUanuYwIVxkhWrwC8wFHAKoo6wvQejNSNjhZddFW4pPFi59nJDtEDP7eXK
+m4orbny2qByrDUPk/6BYnKMqAL/coHgBj0zAgjropimv9hgq4ZlJ+/jZDquOe1XL
+6zw68gPVKJ8fAqRogotXAhfLm8C2uBma2LWY5liTACgLMKDkcWo5bs/fKAKQsd+C
+nAHMROEgK/uiFFK5DgoJaz17lwGs1jqphhZ39rJjzOoWnOPaqAUxjdlEI8ujkcVB
+Wc7PWsy9aOVU3QxpG4PVW+Is5nsoQQ==
+";
+
+fn signed(document: &str, pkcs7: &str) -> Signed {
+    Signed {
+        document: document.to_string(),
+        pkcs7: pkcs7.to_string(),
+    }
+}
+
+#[test]
+fn verifies_document() {
+    for pkcs7 in [PKCS7_BER, PKCS7_DER] {
+        let doc = identity::verify(&signed(DOCUMENT, pkcs7), CERTIFICATE).unwrap();
+        assert_eq!(doc.account_id, "123456789012");
+        assert_eq!(doc.instance_id, "i-0123456789abcdef0");
+        assert_eq!(doc.region, "us-west-2");
+        assert_eq!(doc.private_ip, "10.0.0.10");
+        assert_eq!(doc, identity::parse(DOCUMENT).unwrap());
+    }
+}
+
+#[test]
+fn rejects_tampered_document() {
+    let tampered = DOCUMENT.replace("123456789012", "210987654321");
+    let err = identity::verify(&signed(&tampered, PKCS7_DER), CERTIFICATE).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn rejects_other_certificate() {
+    let err = identity::verify(&signed(DOCUMENT, PKCS7_BER), OTHER_CERTIFICATE).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert!(err.to_string().contains("does not verify"), "{err}");
+}
+
+#[test]
+fn rejects_invalid_pkcs7() {
+    let err = identity::verify(&signed(DOCUMENT, "not base64!"), CERTIFICATE).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+    // valid base64 of a truncated signature
+    let truncated = &PKCS7_DER[..PKCS7_DER.len() / 2 / 4 * 4];
+    let err = identity::verify(&signed(DOCUMENT, truncated), CERTIFICATE).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_deeply_nested_ber() {
+    // "MIAwgDCA" is "30 80 30 80 30 80", SEQUENCEs of indefinite length in one another
+    let nested = "MIAwgDCA".repeat(100_000);
+    let err = identity::verify(&signed(DOCUMENT, &nested), CERTIFICATE).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("nested too deeply"), "{err}");
+}
+
+/// Returns the DER TLV of the content.
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut v = vec![tag];
+    match content.len() {
+        n if n < 0x80 => v.push(n as u8),
+        n if n <= 0xff => v.extend([0x81, n as u8]),
+        n => v.extend([0x82, (n >> 8) as u8, n as u8]),
+    }
+    v.extend(content);
+    v
+}
+
+#[test]
+fn rejects_deeply_nested_octet_string() {
+    // constructed OCTET STRINGs of definite length in one another, as the content
+    let mut octets = der(0x04, b"{}");
+    for _ in 0..100 {
+        octets = der(0x24, &octets);
+    }
+    // OID 1.2.840.113549.1.7.2 (signedData) and 1.2.840.113549.1.7.1 (data)
+    let signed_data_oid = der(
+        0x06,
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02],
+    );
+    let data_oid = der(
+        0x06,
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01],
+    );
+    let inner = der(0x30, &[data_oid, der(0xa0, &octets)].concat());
+    let signed_data = der(
+        0x30,
+        &[der(0x02, &[1]), der(0x31, &[]), inner, der(0x31, &[])].concat(),
+    );
+    let content_info = der(0x30, &[signed_data_oid, der(0xa0, &signed_data)].concat());
+
+    let err = identity::verify(
+        &signed(DOCUMENT, &STANDARD.encode(content_info)),
+        CERTIFICATE,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidData);
+    assert!(err.to_string().contains("nested too deeply"), "{err}");
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use aws_ip_provisioner::imds::Imds;
+
+/// Tokens issued so far, the last of which is valid.
+type Issued = Arc<Mutex<Vec<String>>>;
+
+/// Serves IMDSv2: the PUT issues a new token, and the GET answers 401
+/// unless with the last issued token.
+fn serve() -> (String, Issued) {
+    let issued: Issued = Arc::new(Mutex::new(Vec::new()));
+    let tokens = issued.clone();
+    let endpoint = common::serve_with("", move |req| {
+        let token = req.header("x-aws-ec2-metadata-token").unwrap_or_default();
+        let mut tokens = tokens.lock().unwrap();
+        if req.head.starts_with("PUT /latest/api/token ") {
+            tokens.push(format!("token-{}", tokens.len() + 1));
+            ("200 OK", tokens.last().unwrap().clone().into_bytes())
+        } else if tokens.last() == Some(&token) {
+            ("200 OK", b"i-1".to_vec())
+        } else {
+            ("401 Unauthorized", Vec::new())
+        }
+    });
+    (endpoint, issued)
+}
+
+#[tokio::test]
+async fn reuses_session_token() {
+    let (endpoint, issued) = serve();
+    let mut imds = Imds::new(true, 0);
+    imds.endpoint = endpoint;
+
+    assert_eq!(imds.fetch("instance-id").await.unwrap(), "i-1");
+    assert_eq!(imds.fetch("instance-id").await.unwrap(), "i-1");
+    // shared by the clones
+    assert_eq!(imds.clone().fetch("instance-id").await.unwrap(), "i-1");
+    assert_eq!(issued.lock().unwrap().len(), 1);
+
+    // revoked before its expiry, so fetched once more
+    issued.lock().unwrap().push(String::from("token-revoked"));
+    assert_eq!(imds.fetch("instance-id").await.unwrap(), "i-1");
+    assert_eq!(issued.lock().unwrap().len(), 3);
+    assert_eq!(imds.fetch("instance-id").await.unwrap(), "i-1");
+    assert_eq!(issued.lock().unwrap().len(), 3);
+}
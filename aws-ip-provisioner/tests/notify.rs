@@ -0,0 +1,127 @@
+mod common;
+
+use std::sync::Arc;
+
+use aws_ip_provisioner::{
+    context::Context,
+    notify::{DiscordWebhook, Event, PhaseCommand, Sink, SlackWebhook, Subscription},
+    progress, sdk,
+};
+use serde_json::Value;
+use tokio::time::Duration;
+
+#[test]
+fn formats_event_text() {
+    let event = Event::new(
+        progress::ASSOCIATED,
+        &[("allocation_id", "eipalloc-1"), ("instance_id", "i-1")],
+    );
+    assert_eq!(
+        event.text(),
+        "aws-ip-provisioner associated: allocation_id=eipalloc-1, instance_id=i-1"
+    );
+    assert_eq!(
+        Event::new(progress::DONE, &[]).text(),
+        "aws-ip-provisioner done"
+    );
+
+    let subscription = Subscription::new(
+        " associated, failed ,",
+        Arc::new(SlackWebhook {
+            url_source: "env".to_string(),
+            sdk_opts: sdk::Options::default(),
+        }),
+    );
+    assert_eq!(subscription.events, vec!["associated", "failed"]);
+    assert!(subscription.wants("failed"));
+    assert!(!subscription.wants("allocated"));
+}
+
+#[tokio::test]
+async fn notifies_subscribed_events() {
+    let (slack_url, slack) = common::serve("/hook", "200 OK");
+    let (discord_url, discord) = common::serve("/hook", "204 No Content");
+    let dir = std::env::temp_dir();
+    let slack_file = dir.join(format!("notify-slack-{}", std::process::id()));
+    let discord_file = dir.join(format!("notify-discord-{}", std::process::id()));
+    std::fs::write(&slack_file, &slack_url).unwrap();
+    std::fs::write(&discord_file, &discord_url).unwrap();
+
+    let ctx = Context::default();
+    ctx.notify.init(vec![
+        Subscription::new(
+            "allocated,failed",
+            Arc::new(SlackWebhook {
+                url_source: format!("file:{}", slack_file.display()),
+                sdk_opts: sdk::Options::default(),
+            }),
+        ),
+        Subscription::new(
+            "failed",
+            Arc::new(DiscordWebhook {
+                url_source: format!("file:{}", discord_file.display()),
+                sdk_opts: sdk::Options::default(),
+            }),
+        ),
+    ]);
+    // notified even without "--progress=ndjson"
+    progress::emit(
+        &ctx,
+        progress::ALLOCATED,
+        &[("allocation_id", "eipalloc-1")],
+    );
+    progress::emit(&ctx, progress::DONE, &[]);
+    ctx.notify.flush(Duration::from_secs(10)).await;
+    progress::emit(&ctx, progress::FAILED, &[("error", &"x".repeat(3000))]);
+    ctx.notify.flush(Duration::from_secs(10)).await;
+
+    let text = |v: Value, key: &str| v[key].as_str().unwrap().to_string();
+    assert_eq!(
+        text(slack.recv().unwrap().body, "text"),
+        "aws-ip-provisioner allocated: allocation_id=eipalloc-1"
+    );
+    assert!(
+        text(slack.recv().unwrap().body, "text").starts_with("aws-ip-provisioner failed: error=x")
+    );
+    assert!(slack.try_recv().is_err());
+
+    let content = text(discord.recv().unwrap().body, "content");
+    assert!(content.starts_with("aws-ip-provisioner failed: error=x"));
+    assert_eq!(content.chars().count(), 2000);
+    assert!(discord.try_recv().is_err());
+
+    std::fs::remove_file(&slack_file).unwrap();
+    std::fs::remove_file(&discord_file).unwrap();
+}
+
+#[tokio::test]
+async fn runs_phase_command_with_payload() {
+    let out = std::env::temp_dir().join(format!("notify-phase-{}", std::process::id()));
+    let _ = std::fs::remove_file(&out);
+
+    let sink = PhaseCommand::new(
+        &format!(
+            "sh -c 'echo \"$IP_MANAGER_PHASE {{phase}} $IP_MANAGER_PAYLOAD\" >> {}'",
+            out.display()
+        ),
+        10,
+    );
+    let event = Event::new(progress::ASSOCIATED, &[("allocation_id", "eipalloc-1")]);
+    sink.send(&event).await.unwrap();
+
+    let line = std::fs::read_to_string(&out).unwrap();
+    let (phase, rest) = line.trim().split_once(' ').unwrap();
+    let (rendered, payload) = rest.split_once(' ').unwrap();
+    assert_eq!(phase, "associated");
+    assert_eq!(rendered, "associated");
+    let payload: Value = serde_json::from_str(payload).unwrap();
+    assert_eq!(payload["event"], "associated");
+    assert_eq!(payload["allocation_id"], "eipalloc-1");
+    assert!(payload["ts"].as_u64().unwrap() > 0);
+
+    // reported to "dispatch", which logs it rather than failing the run
+    let failing = PhaseCommand::new("false", 10);
+    assert!(failing.send(&event).await.is_err());
+
+    std::fs::remove_file(&out).unwrap();
+}
@@ -0,0 +1,75 @@
+use aws_ip_provisioner::{
+    peers::{self, Document, Object, Peer},
+    sdk,
+};
+
+fn peer(id: &str, kind: &str, public_ip: &str) -> Peer {
+    Peer {
+        id: id.to_string(),
+        kind: kind.to_string(),
+        public_ip: public_ip.to_string(),
+        updated_at: 0,
+    }
+}
+
+#[test]
+fn upserts_and_renders_peers() {
+    let mut doc = Document::default();
+    assert!(doc.upsert(peer("node-2", "mainnet", "203.0.113.2")));
+    assert!(doc.upsert(peer("node-1", "mainnet", "203.0.113.1")));
+    assert!(doc.upsert(peer("node-1", "testnet", "198.51.100.1")));
+    // already published
+    assert!(!doc.upsert(peer("node-1", "mainnet", "203.0.113.1")));
+    // re-provisioned with another EIP
+    assert!(doc.upsert(peer("node-2", "mainnet", "203.0.113.3")));
+    assert_eq!(
+        doc.peers,
+        vec![
+            peer("node-1", "mainnet", "203.0.113.1"),
+            peer("node-2", "mainnet", "203.0.113.3"),
+            peer("node-1", "testnet", "198.51.100.1"),
+        ]
+    );
+
+    assert_eq!(
+        doc.render("mainnet", "", "{public_ip}:30303"),
+        "203.0.113.1:30303\n203.0.113.3:30303\n"
+    );
+    assert_eq!(
+        doc.render("", "node-1", "{kind}/{id}={public_ip}"),
+        "mainnet/node-2=203.0.113.3\n"
+    );
+
+    // round-trips the file written by the older versions without "updated_at"
+    let doc: Document = serde_json::from_str(
+        r#"{"peers":[{"id":"node-1","kind":"mainnet","public_ip":"203.0.113.1"}]}"#,
+    )
+    .unwrap();
+    assert_eq!(doc.peers, vec![peer("node-1", "mainnet", "203.0.113.1")]);
+}
+
+#[test]
+fn resolves_object_uri() {
+    let object = Object {
+        region: String::from("us-west-2"),
+        bucket: String::from("my.cluster-config"),
+        key: String::from("peers/main net+1.json"),
+    };
+    let mut opts = sdk::Options::default();
+    assert_eq!(
+        object.uri(&opts),
+        "https://s3.us-west-2.amazonaws.com/my.cluster-config/peers/main%20net%2B1.json"
+    );
+    opts.use_fips = true;
+    opts.use_dual_stack = true;
+    assert_eq!(
+        object.uri(&opts),
+        "https://s3-fips.dualstack.us-west-2.amazonaws.com/my.cluster-config/peers/main%20net%2B1.json"
+    );
+    opts.endpoint_url = String::from("http://localhost:4566/");
+    assert_eq!(
+        object.uri(&opts),
+        "http://localhost:4566/my.cluster-config/peers/main%20net%2B1.json"
+    );
+    assert_eq!(peers::encode_key("a/b~c_d.e-f"), "a/b~c_d.e-f");
+}
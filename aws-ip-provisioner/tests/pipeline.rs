@@ -0,0 +1,88 @@
+//! Tests of the bounded-parallel address pipeline.
+
+use std::{
+    io::{self, Error, ErrorKind},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use aws_ip_provisioner::{pipeline, provisioner::BoxFuture};
+use tokio::time::{sleep, Duration, Instant};
+
+#[tokio::test]
+async fn runs_concurrently_within_the_bound() {
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let max_seen = Arc::new(AtomicUsize::new(0));
+    let tasks = (0..8)
+        .map(|i| {
+            let in_flight = in_flight.clone();
+            let max_seen = max_seen.clone();
+            async move {
+                let n = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_seen.fetch_max(n, Ordering::SeqCst);
+                sleep(Duration::from_millis(50)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok::<_, io::Error>(i)
+            }
+        })
+        .collect();
+
+    let started = Instant::now();
+    let results = pipeline::run_bounded(4, tasks).await;
+    let elapsed = started.elapsed();
+
+    assert_eq!(max_seen.load(Ordering::SeqCst), 4);
+    // two rounds of four, rather than eight sequential calls
+    assert!(elapsed < Duration::from_millis(300), "took {elapsed:?}");
+    let values: Vec<i32> = results.into_iter().map(|r| r.unwrap()).collect();
+    assert_eq!(values, (0..8).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn keeps_order_and_isolates_failures() {
+    let tasks = vec![
+        Box::pin(async {
+            sleep(Duration::from_millis(30)).await;
+            Ok(String::from("eipalloc-1"))
+        }) as BoxFuture<'static, String>,
+        Box::pin(async { Err(Error::new(ErrorKind::Other, "AddressLimitExceeded")) }),
+        Box::pin(async { Ok(String::from("eipalloc-3")) }),
+    ];
+
+    let results = pipeline::run_bounded(2, tasks).await;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap(), "eipalloc-1");
+    assert!(results[1].is_err());
+    assert_eq!(results[2].as_ref().unwrap(), "eipalloc-3");
+}
+
+#[tokio::test]
+async fn zero_bound_runs_sequentially() {
+    let results = pipeline::run_bounded(0, vec![async { Ok::<_, io::Error>(1) }]).await;
+    assert_eq!(results.len(), 1);
+}
+
+#[tokio::test]
+async fn runs_borrowing_tasks() {
+    // e.g., the EIP and the IPv6 steps of the same provisioner
+    let (mut eip, mut ipv6) = (String::new(), String::new());
+    let tasks: Vec<BoxFuture<'_, ()>> = vec![
+        Box::pin(async {
+            sleep(Duration::from_millis(30)).await;
+            eip = String::from("eipalloc-1");
+            Ok(())
+        }),
+        Box::pin(async {
+            ipv6 = String::from("2001:db8::10");
+            Ok(())
+        }),
+    ];
+    let results = pipeline::run_bounded(2, tasks).await;
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(
+        (eip.as_str(), ipv6.as_str()),
+        ("eipalloc-1", "2001:db8::10")
+    );
+}
@@ -0,0 +1,46 @@
+use aws_ip_provisioner::prefix_list::{self, Entry, Plan};
+
+fn entry(cidr: &str, description: &str) -> Entry {
+    Entry {
+        cidr: cidr.to_string(),
+        description: description.to_string(),
+    }
+}
+
+#[test]
+fn plans_adds_and_removes_of_managed_entries() {
+    let current = vec![
+        entry("203.0.113.1/32", "ip-manager:node-1"),
+        // released since the last sync
+        entry("203.0.113.2/32", "ip-manager:node-2"),
+        // added by hand, never removed
+        entry("198.51.100.0/24", "office"),
+    ];
+    let desired = vec![
+        Entry::for_eip("203.0.113.3", "node-3"),
+        Entry::for_eip("203.0.113.1", "node-1"),
+    ];
+    assert_eq!(
+        prefix_list::plan(&current, &desired),
+        Plan {
+            add: vec![entry("203.0.113.3/32", "ip-manager:node-3")],
+            remove: vec![String::from("203.0.113.2/32")],
+        }
+    );
+
+    // in sync
+    let current = vec![
+        entry("203.0.113.1/32", "ip-manager:node-1"),
+        entry("203.0.113.3/32", "ip-manager:node-3"),
+    ];
+    assert!(prefix_list::plan(&current, &desired).is_empty());
+}
+
+#[test]
+fn leaves_cidrs_added_by_hand() {
+    // the same address allow-listed by hand is neither added again nor removed
+    let current = vec![entry("203.0.113.1/32", "bastion")];
+    let desired = vec![Entry::for_eip("203.0.113.1", "node-1")];
+    assert!(prefix_list::plan(&current, &desired).is_empty());
+    assert!(prefix_list::plan(&current, &[]).is_empty());
+}
@@ -0,0 +1,1837 @@
+//! Tests of the allocate/reuse/associate decisions of "Provisioner",
+//! against in-memory fakes of EC2, IMDS, the clock, and the RNG.
+
+mod common;
+
+use std::{
+    io::{self, Error, ErrorKind},
+    sync::Mutex,
+};
+
+use aws_ip_provisioner::{
+    command::{self, Flags},
+    context::Context,
+    ec2, eip, pool,
+    provisioner::{BoxFuture, Clock, Ec2, Metadata, Provisioner, Rng},
+    state,
+};
+use aws_sdk_ec2::model::{
+    Address, NetworkInterface, NetworkInterfaceAttachment, NetworkInterfacePrivateIpAddress, Tag,
+};
+use tokio::time::Duration;
+
+const LOCAL_INSTANCE_ID: &str = "i-local";
+const OTHER_INSTANCE_ID: &str = "i-other";
+const NOW: u64 = 1_700_000_000;
+const ACCOUNT_ID: &str = "111122223333";
+const REGION: &str = "us-west-2";
+
+#[derive(Default)]
+struct State {
+    addresses: Vec<Address>,
+    running: Vec<String>,
+    network_interfaces: Vec<NetworkInterface>,
+    /// Readiness checks to fail before the local instance is ready to associate.
+    not_ready_polls: u32,
+    /// Mutating calls in order (e.g., "associate eipalloc-1 i-local").
+    calls: Vec<String>,
+    /// Describes that return the address as before the last association,
+    /// as EC2 does until DescribeAddresses catches up.
+    stale_describes: u32,
+    stale: Option<Address>,
+    /// Instance that claims the same pool EIP right after each claim,
+    /// so that its tags win.
+    rival_claimant: Option<String>,
+    /// Address that another instance claims right after the next listing by tags,
+    /// so that the listing is stale.
+    claimed_after_listing: Option<String>,
+}
+
+#[derive(Default)]
+struct FakeEc2 {
+    state: Mutex<State>,
+}
+
+impl FakeEc2 {
+    fn with_address(self, allocation_id: &str, tags: &[(&str, &str)]) -> Self {
+        self.state.lock().unwrap().addresses.push(
+            Address::builder()
+                .allocation_id(allocation_id)
+                .public_ip(format!("203.0.113.{}", allocation_id.len()))
+                .set_tags(Some(
+                    tags.iter()
+                        .map(|(k, v)| Tag::builder().key(*k).value(*v).build())
+                        .collect(),
+                ))
+                .build(),
+        );
+        self
+    }
+
+    /// Associates the address with the instance (or the network interface if "None").
+    fn with_association(self, allocation_id: &str, instance_id: Option<&str>) -> Self {
+        {
+            let mut state = self.state.lock().unwrap();
+            let addr = state
+                .addresses
+                .iter_mut()
+                .find(|a| a.allocation_id() == Some(allocation_id))
+                .unwrap();
+            addr.association_id = Some(format!("eipassoc-{allocation_id}"));
+            addr.instance_id = instance_id.map(|v| v.to_string());
+            addr.network_interface_id = Some(String::from("eni-other"));
+        }
+        self
+    }
+
+    fn with_rival_claimant(self, instance_id: &str) -> Self {
+        self.state.lock().unwrap().rival_claimant = Some(instance_id.to_string());
+        self
+    }
+
+    fn with_claim_after_listing(self, allocation_id: &str) -> Self {
+        self.state.lock().unwrap().claimed_after_listing = Some(allocation_id.to_string());
+        self
+    }
+
+    fn with_stale_describes(self, n: u32) -> Self {
+        self.state.lock().unwrap().stale_describes = n;
+        self
+    }
+
+    fn with_running(self, instance_id: &str) -> Self {
+        self.state
+            .lock()
+            .unwrap()
+            .running
+            .push(instance_id.to_string());
+        self
+    }
+
+    /// Attaches the network interface to the instance at the device index.
+    fn with_network_interface(
+        self,
+        network_interface_id: &str,
+        instance_id: &str,
+        device_index: i32,
+        tags: &[(&str, &str)],
+    ) -> Self {
+        self.state.lock().unwrap().network_interfaces.push(
+            NetworkInterface::builder()
+                .network_interface_id(network_interface_id)
+                .attachment(
+                    NetworkInterfaceAttachment::builder()
+                        .instance_id(instance_id)
+                        .device_index(device_index)
+                        .build(),
+                )
+                .set_tag_set(Some(
+                    tags.iter()
+                        .map(|(k, v)| Tag::builder().key(*k).value(*v).build())
+                        .collect(),
+                ))
+                .build(),
+        );
+        self
+    }
+
+    /// Assigns the (e.g., secondary) private IP to the network interface.
+    fn with_private_ip_address(self, network_interface_id: &str, private_ip_address: &str) -> Self {
+        let mut state = self.state.lock().unwrap();
+        let ni = state
+            .network_interfaces
+            .iter_mut()
+            .find(|ni| ni.network_interface_id() == Some(network_interface_id))
+            .unwrap();
+        ni.private_ip_addresses.get_or_insert_with(Vec::new).push(
+            NetworkInterfacePrivateIpAddress::builder()
+                .private_ip_address(private_ip_address)
+                .build(),
+        );
+        drop(state);
+        self
+    }
+
+    /// Sets the IPv6 address of the network interface.
+    fn with_ipv6_address(self, network_interface_id: &str, ipv6_address: &str) -> Self {
+        let mut state = self.state.lock().unwrap();
+        let ni = state
+            .network_interfaces
+            .iter_mut()
+            .find(|ni| ni.network_interface_id() == Some(network_interface_id))
+            .unwrap();
+        ni.ipv6_address = Some(ipv6_address.to_string());
+        drop(state);
+        self
+    }
+
+    fn with_not_ready_polls(self, n: u32) -> Self {
+        self.state.lock().unwrap().not_ready_polls = n;
+        self
+    }
+
+    fn calls(&self) -> Vec<String> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    fn tag(&self, allocation_id: &str, key: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let addr = state
+            .addresses
+            .iter()
+            .find(|a| a.allocation_id() == Some(allocation_id))?;
+        addr.tags()
+            .unwrap_or_default()
+            .iter()
+            .find(|t| t.key() == Some(key))
+            .and_then(|t| t.value().map(|v| v.to_string()))
+    }
+
+    fn associated_network_interface(&self, allocation_id: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .addresses
+            .iter()
+            .find(|a| a.allocation_id() == Some(allocation_id))
+            .and_then(|a| a.network_interface_id().map(|v| v.to_string()))
+    }
+
+    fn associated_private_ip_address(&self, allocation_id: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .addresses
+            .iter()
+            .find(|a| a.allocation_id() == Some(allocation_id))
+            .and_then(|a| a.private_ip_address().map(|v| v.to_string()))
+    }
+
+    fn associated_instance(&self, allocation_id: &str) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .addresses
+            .iter()
+            .find(|a| a.allocation_id() == Some(allocation_id))
+            .and_then(|a| a.instance_id().map(|v| v.to_string()))
+    }
+}
+
+fn has_tag(addr: &Address, k: &str, v: &str) -> bool {
+    addr.tags()
+        .unwrap_or_default()
+        .iter()
+        .any(|t| t.key() == Some(k) && t.value() == Some(v))
+}
+
+impl Ec2 for FakeEc2 {
+    fn describe_by_tags<'a>(
+        &'a self,
+        tags: &'a [(&'a str, &'a str)],
+    ) -> BoxFuture<'a, Vec<Address>> {
+        let mut state = self.state.lock().unwrap();
+        let addrs = state
+            .addresses
+            .iter()
+            .filter(|a| tags.iter().all(|(k, v)| has_tag(a, k, v)))
+            .cloned()
+            .collect();
+        if let Some(allocation_id) = state.claimed_after_listing.take() {
+            let addr = state
+                .addresses
+                .iter_mut()
+                .find(|a| a.allocation_id() == Some(allocation_id.as_str()))
+                .unwrap();
+            for tag in addr.tags.iter_mut().flatten() {
+                if tag.key() == Some(pool::STATUS_TAG_KEY) {
+                    *tag = Tag::builder()
+                        .key(pool::STATUS_TAG_KEY)
+                        .value(pool::STATUS_CLAIMED)
+                        .build();
+                }
+            }
+        }
+        Box::pin(async move { Ok(addrs) })
+    }
+
+    fn describe_by_allocation_id<'a>(
+        &'a self,
+        allocation_id: &'a str,
+    ) -> BoxFuture<'a, Option<Address>> {
+        let mut state = self.state.lock().unwrap();
+        let stale = state
+            .stale
+            .clone()
+            .filter(|a| a.allocation_id() == Some(allocation_id));
+        let addr = if stale.is_some() && state.stale_describes > 0 {
+            state.stale_describes -= 1;
+            stale
+        } else {
+            state
+                .addresses
+                .iter()
+                .find(|a| a.allocation_id() == Some(allocation_id))
+                .cloned()
+        };
+        Box::pin(async move { Ok(addr) })
+    }
+
+    fn is_instance_running<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, bool> {
+        let running = self
+            .state
+            .lock()
+            .unwrap()
+            .running
+            .iter()
+            .any(|v| v == instance_id);
+        Box::pin(async move { Ok(running) })
+    }
+
+    fn is_ready_to_associate<'a>(&'a self, _instance_id: &'a str) -> BoxFuture<'a, bool> {
+        let mut state = self.state.lock().unwrap();
+        let ready = state.not_ready_polls == 0;
+        state.not_ready_polls = state.not_ready_polls.saturating_sub(1);
+        Box::pin(async move { Ok(ready) })
+    }
+
+    fn describe_network_interfaces<'a>(
+        &'a self,
+        instance_id: &'a str,
+    ) -> BoxFuture<'a, Vec<NetworkInterface>> {
+        let state = self.state.lock().unwrap();
+        let enis = state
+            .network_interfaces
+            .iter()
+            .filter(|ni| ni.attachment().and_then(|a| a.instance_id()) == Some(instance_id))
+            .cloned()
+            .collect();
+        Box::pin(async move { Ok(enis) })
+    }
+
+    fn assign_ipv6_address<'a>(&'a self, network_interface_id: &'a str) -> BoxFuture<'a, String> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .calls
+            .push(format!("assign_ipv6_address {network_interface_id}"));
+        let ret = match state
+            .network_interfaces
+            .iter_mut()
+            .find(|ni| ni.network_interface_id() == Some(network_interface_id))
+        {
+            Some(ni) => {
+                ni.ipv6_address = Some(String::from("2001:db8::10"));
+                Ok(String::from("2001:db8::10"))
+            }
+            None => Err(Error::new(ErrorKind::NotFound, network_interface_id)),
+        };
+        Box::pin(async move { ret })
+    }
+
+    fn delete_tags<'a>(&'a self, allocation_id: &'a str, keys: Vec<String>) -> BoxFuture<'a, ()> {
+        let mut state = self.state.lock().unwrap();
+        for k in keys.iter() {
+            state.calls.push(format!("delete_tags {allocation_id} {k}"));
+        }
+        let ret = match state
+            .addresses
+            .iter_mut()
+            .find(|a| a.allocation_id() == Some(allocation_id))
+        {
+            Some(addr) => {
+                let existing: Vec<Tag> = addr
+                    .tags()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|t| !keys.iter().any(|k| t.key() == Some(k.as_str())))
+                    .cloned()
+                    .collect();
+                addr.tags = Some(existing);
+                Ok(())
+            }
+            None => Err(not_found(allocation_id)),
+        };
+        Box::pin(async move { ret })
+    }
+
+    fn disassociate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        association_id: &'a str,
+    ) -> BoxFuture<'a, ()> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .calls
+            .push(format!("disassociate {allocation_id} {association_id}"));
+        let ret = match state
+            .addresses
+            .iter_mut()
+            .find(|a| a.allocation_id() == Some(allocation_id))
+        {
+            Some(addr) => {
+                addr.association_id = None;
+                addr.instance_id = None;
+                addr.network_interface_id = None;
+                addr.private_ip_address = None;
+                Ok(())
+            }
+            None => Err(not_found(allocation_id)),
+        };
+        Box::pin(async move { ret })
+    }
+
+    fn release_address<'a>(&'a self, allocation_id: &'a str) -> BoxFuture<'a, ()> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!("release_address {allocation_id}"));
+        state
+            .addresses
+            .retain(|a| a.allocation_id() != Some(allocation_id));
+        Box::pin(async move { Ok(()) })
+    }
+
+    fn allocate<'a>(
+        &'a self,
+        id_tag: (&'a str, &'a str),
+        kind_tag: (&'a str, &'a str),
+        client_token: &'a str,
+        _public_ipv4_pool: &'a str,
+    ) -> BoxFuture<'a, ec2::Eip> {
+        let mut state = self.state.lock().unwrap();
+        let n = state.addresses.len() + 100;
+        let eip = ec2::Eip {
+            allocation_id: format!("eipalloc-{n}"),
+            public_ip: format!("198.51.100.{n}"),
+        };
+        state.addresses.push(
+            Address::builder()
+                .allocation_id(&eip.allocation_id)
+                .public_ip(&eip.public_ip)
+                .tags(Tag::builder().key(id_tag.0).value(id_tag.1).build())
+                .tags(Tag::builder().key(kind_tag.0).value(kind_tag.1).build())
+                .tags(
+                    Tag::builder()
+                        .key(eip::CLIENT_TOKEN_TAG_KEY)
+                        .value(client_token)
+                        .build(),
+                )
+                .build(),
+        );
+        state.calls.push(format!("allocate {}", eip.allocation_id));
+        Box::pin(async move { Ok(eip) })
+    }
+
+    fn create_tags<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        tags: Vec<(String, String)>,
+    ) -> BoxFuture<'a, ()> {
+        let mut state = self.state.lock().unwrap();
+        for (k, v) in tags.iter() {
+            state
+                .calls
+                .push(format!("create_tags {allocation_id} {k}={v}"));
+        }
+        let mut tags = tags;
+        if let Some(rival) = state.rival_claimant.take() {
+            // the rival tags the same EIP last, and so wins it
+            for (k, v) in tags.iter_mut() {
+                if k == pool::CLAIMED_BY_TAG_KEY {
+                    *v = rival.clone();
+                }
+            }
+        }
+        let ret = match state
+            .addresses
+            .iter_mut()
+            .find(|a| a.allocation_id() == Some(allocation_id))
+        {
+            Some(addr) => {
+                let mut existing: Vec<Tag> = addr
+                    .tags()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter(|t| !tags.iter().any(|(k, _)| t.key() == Some(k.as_str())))
+                    .cloned()
+                    .collect();
+                existing.extend(
+                    tags.iter()
+                        .map(|(k, v)| Tag::builder().key(k).value(v).build()),
+                );
+                addr.tags = Some(existing);
+                Ok(())
+            }
+            None => Err(not_found(allocation_id)),
+        };
+        Box::pin(async move { ret })
+    }
+
+    fn associate<'a>(
+        &'a self,
+        allocation_id: &'a str,
+        instance_id: &'a str,
+        target: &'a eip::Target,
+        allow_reassociation: bool,
+    ) -> BoxFuture<'a, String> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(format!(
+            "associate {allocation_id} {} allow_reassociation={allow_reassociation}",
+            target.describe(instance_id)
+        ));
+        // unique per association, as in EC2
+        let association_id = format!("eipassoc-{allocation_id}-{}", state.calls.len());
+        state.stale = state
+            .addresses
+            .iter()
+            .find(|a| a.allocation_id() == Some(allocation_id))
+            .cloned();
+        let ret = match state
+            .addresses
+            .iter_mut()
+            .find(|a| a.allocation_id() == Some(allocation_id))
+        {
+            Some(addr) if addr.association_id().is_some() && !allow_reassociation => {
+                Err(Error::new(ErrorKind::Other, "Resource.AlreadyAssociated"))
+            }
+            Some(addr) => {
+                addr.association_id = Some(association_id.clone());
+                addr.instance_id = Some(instance_id.to_string());
+                addr.network_interface_id = if target.network_interface_id.is_empty() {
+                    None
+                } else {
+                    Some(target.network_interface_id.clone())
+                };
+                addr.private_ip_address = if target.private_ip_address.is_empty() {
+                    None
+                } else {
+                    Some(target.private_ip_address.clone())
+                };
+                Ok(association_id)
+            }
+            None => Err(not_found(allocation_id)),
+        };
+        Box::pin(async move { ret })
+    }
+}
+
+fn not_found(allocation_id: &str) -> io::Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("InvalidAllocationID.NotFound {allocation_id}"),
+    )
+}
+
+struct FakeMetadata;
+
+impl Metadata for FakeMetadata {
+    fn instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async { Ok(LOCAL_INSTANCE_ID.to_string()) })
+    }
+
+    fn public_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn local_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async { Ok(Some(String::from("10.0.0.10"))) })
+    }
+
+    fn asg_members<'a>(&'a self, _instance_id: &'a str) -> BoxFuture<'a, Vec<String>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn account_and_region(&self) -> BoxFuture<'_, (String, String)> {
+        Box::pin(async { Ok((ACCOUNT_ID.to_string(), REGION.to_string())) })
+    }
+}
+
+/// Instance in the auto scaling group with the (sorted) members.
+struct AsgMetadata(&'static [&'static str]);
+
+impl Metadata for AsgMetadata {
+    fn instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async { Ok(LOCAL_INSTANCE_ID.to_string()) })
+    }
+
+    fn public_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn local_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async { Ok(Some(String::from("10.0.0.10"))) })
+    }
+
+    fn asg_members<'a>(&'a self, _instance_id: &'a str) -> BoxFuture<'a, Vec<String>> {
+        let members = self.0.iter().map(|v| v.to_string()).collect();
+        Box::pin(async move { Ok(members) })
+    }
+
+    fn account_and_region(&self) -> BoxFuture<'_, (String, String)> {
+        Box::pin(async { Ok((ACCOUNT_ID.to_string(), REGION.to_string())) })
+    }
+}
+
+/// Instance with the public IPv4 (e.g., auto-assigned at launch).
+struct PublicMetadata(&'static str);
+
+impl Metadata for PublicMetadata {
+    fn instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async { Ok(LOCAL_INSTANCE_ID.to_string()) })
+    }
+
+    fn public_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async move { Ok(Some(self.0.to_string())) })
+    }
+
+    fn local_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async { Ok(Some(String::from("10.0.0.10"))) })
+    }
+
+    fn asg_members<'a>(&'a self, _instance_id: &'a str) -> BoxFuture<'a, Vec<String>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn account_and_region(&self) -> BoxFuture<'_, (String, String)> {
+        Box::pin(async { Ok((ACCOUNT_ID.to_string(), REGION.to_string())) })
+    }
+}
+
+/// Instance in an IPv6-only subnet, with no IPv4.
+struct Ipv6OnlyMetadata;
+
+impl Metadata for Ipv6OnlyMetadata {
+    fn instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async { Ok(LOCAL_INSTANCE_ID.to_string()) })
+    }
+
+    fn public_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn local_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn asg_members<'a>(&'a self, _instance_id: &'a str) -> BoxFuture<'a, Vec<String>> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+
+    fn account_and_region(&self) -> BoxFuture<'_, (String, String)> {
+        Box::pin(async { Ok((ACCOUNT_ID.to_string(), REGION.to_string())) })
+    }
+}
+
+#[derive(Default)]
+struct FakeClock {
+    slept: Mutex<Vec<Duration>>,
+}
+
+impl Clock for FakeClock {
+    fn now_unix_seconds(&self) -> u64 {
+        NOW
+    }
+
+    fn sleep(&self, d: Duration) -> BoxFuture<'_, ()> {
+        self.slept.lock().unwrap().push(d);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+struct FakeRng(u32);
+
+impl Rng for FakeRng {
+    fn u32(&self) -> u32 {
+        self.0
+    }
+}
+
+fn write_state(opts: &Flags, allocation_id: &str) {
+    state::record_eip(
+        &opts.mounted_eip_file_path,
+        &ec2::Eip {
+            allocation_id: allocation_id.to_string(),
+            public_ip: format!("203.0.113.{}", allocation_id.len()),
+        },
+    )
+    .unwrap();
+}
+
+async fn provision(opts: &Flags, ec2: &FakeEc2) -> io::Result<ec2::Eip> {
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+    Provisioner::new(&ctx, opts, ec2, &FakeMetadata, &clock, &FakeRng(0))
+        .provision(LOCAL_INSTANCE_ID)
+        .await
+}
+
+#[tokio::test]
+async fn allocates_and_associates_without_state_file_and_pool() {
+    let opts = common::state_flags("allocate", &[]);
+    let ec2 = FakeEc2::default();
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-100");
+    assert_eq!(
+        ec2.calls(),
+        vec![
+            "allocate eipalloc-100".to_string(),
+            format!("create_tags eipalloc-100 AllocatedAt={NOW}"),
+            "associate eipalloc-100 i-local allow_reassociation=false".to_string(),
+        ]
+    );
+    assert_eq!(
+        state::load_eip(&opts.mounted_eip_file_path).unwrap(),
+        eip,
+        "state file must record the allocated EIP"
+    );
+}
+
+#[tokio::test]
+async fn reuses_eip_allocated_before_crash() {
+    let opts = common::state_flags("client-token", &[]);
+    let ec2 = FakeEc2::default();
+
+    // the previous attempt allocated, then crashed before syncing the state file
+    let token = eip::client_token(LOCAL_INSTANCE_ID, "node-1");
+    let orphan = ec2
+        .allocate(("Id", "node-1"), ("Kind", "test"), &token, "")
+        .await
+        .unwrap();
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_eq!(eip, orphan);
+    assert_eq!(
+        ec2.calls(),
+        vec![
+            "allocate eipalloc-100".to_string(),
+            format!("create_tags eipalloc-100 AllocatedAt={NOW}"),
+            "associate eipalloc-100 i-local allow_reassociation=false".to_string(),
+        ],
+        "retry must not allocate a second EIP"
+    );
+    assert_eq!(state::load_eip(&opts.mounted_eip_file_path).unwrap(), eip);
+}
+
+#[tokio::test]
+async fn recovers_eip_by_tags_without_state_file() {
+    let opts = common::state_flags("recover-by-tags", &[]);
+    // the volume was replaced, but the EIP of the terminated instance survived
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Id", "node-1"), ("Kind", "test")])
+        .with_association("eipalloc-1", Some(OTHER_INSTANCE_ID));
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-1");
+    assert!(
+        !ec2.calls().iter().any(|c| c.starts_with("allocate")),
+        "must not allocate: {:?}",
+        ec2.calls()
+    );
+    assert_eq!(ec2.tag("eipalloc-1", "AllocatedAt"), Some(NOW.to_string()));
+    assert_eq!(
+        ec2.associated_instance("eipalloc-1").as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+    assert_eq!(state::load_eip(&opts.mounted_eip_file_path).unwrap(), eip);
+}
+
+#[tokio::test]
+async fn does_not_recover_eip_of_running_instance() {
+    let opts = common::state_flags("recover-by-tags-running", &[]);
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Id", "node-1"), ("Kind", "test")])
+        .with_association("eipalloc-1", Some(OTHER_INSTANCE_ID))
+        .with_running(OTHER_INSTANCE_ID)
+        .with_address(
+            "eipalloc-22",
+            &[
+                ("Id", "node-1"),
+                ("Kind", "test"),
+                ("PoolStatus", "available"),
+            ],
+        );
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    // the pool-available one is claimed via the pool, not recovered
+    assert_eq!(eip.allocation_id, "eipalloc-22");
+    assert_eq!(
+        ec2.tag("eipalloc-22", "PoolStatus").as_deref(),
+        Some("claimed")
+    );
+    assert_eq!(
+        ec2.associated_instance("eipalloc-1").as_deref(),
+        Some(OTHER_INSTANCE_ID)
+    );
+}
+
+#[test]
+fn client_token_is_deterministic() {
+    let token = eip::client_token("i-local", "node-1");
+    assert_eq!(token, eip::client_token("i-local", "node-1"));
+    assert_eq!(token.len(), 16);
+    assert_ne!(token, eip::client_token("i-local", "node-2"));
+    assert_ne!(token, eip::client_token("i-other", "node-1"));
+}
+
+#[tokio::test]
+async fn reuses_state_file_across_runs() {
+    let opts = common::state_flags("reuse", &[]);
+    let ec2 = FakeEc2::default();
+
+    let first = provision(&opts, &ec2).await.unwrap();
+    let calls = ec2.calls().len();
+    let second = provision(&opts, &ec2).await.unwrap();
+    assert_eq!(first, second);
+    assert_eq!(
+        ec2.calls().len(),
+        calls,
+        "second run must neither allocate nor associate"
+    );
+}
+
+#[tokio::test]
+async fn skips_association_if_already_associated() {
+    let opts = common::state_flags("already-associated", &[]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_association("eipalloc-1", Some(LOCAL_INSTANCE_ID));
+
+    provision(&opts, &ec2).await.unwrap();
+    assert!(ec2.calls().is_empty(), "unexpected calls {:?}", ec2.calls());
+}
+
+#[tokio::test]
+async fn associates_unassociated_state_file_eip() {
+    let opts = common::state_flags("unassociated", &[]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default().with_address("eipalloc-1", &[("Kind", "test")]);
+
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(
+        ec2.calls(),
+        vec!["associate eipalloc-1 i-local allow_reassociation=false"]
+    );
+}
+
+#[tokio::test]
+async fn associates_state_file_eip_while_holding_another() {
+    let opts = common::state_flags("holding-another", &[]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_address("eipalloc-22", &[("Kind", "test")])
+        .with_association("eipalloc-22", Some(LOCAL_INSTANCE_ID));
+
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(
+        ec2.associated_instance("eipalloc-1").as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+}
+
+#[tokio::test]
+async fn refuses_to_steal_from_live_instance() {
+    let opts = common::state_flags("no-steal-live", &["--no-steal=true"]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_association("eipalloc-1", Some(OTHER_INSTANCE_ID))
+        .with_running(OTHER_INSTANCE_ID);
+
+    let err = provision(&opts, &ec2).await.unwrap_err();
+    assert!(err.to_string().contains("no-steal"), "{}", err);
+    assert!(ec2.calls().is_empty(), "unexpected calls {:?}", ec2.calls());
+    assert_eq!(
+        ec2.associated_instance("eipalloc-1").as_deref(),
+        Some(OTHER_INSTANCE_ID)
+    );
+}
+
+#[tokio::test]
+async fn refuses_to_steal_from_network_interface() {
+    let opts = common::state_flags("no-steal-eni", &["--no-steal=true"]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_association("eipalloc-1", None);
+
+    let err = provision(&opts, &ec2).await.unwrap_err();
+    assert!(err.to_string().contains("eni-other"), "{}", err);
+}
+
+#[tokio::test]
+async fn takes_over_from_stopped_instance() {
+    let opts = common::state_flags("stopped", &["--no-steal=true"]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_association("eipalloc-1", Some(OTHER_INSTANCE_ID));
+
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(
+        ec2.calls(),
+        vec!["associate eipalloc-1 i-local allow_reassociation=true"]
+    );
+}
+
+#[tokio::test]
+async fn steals_from_live_instance_without_no_steal() {
+    let opts = common::state_flags("steal", &["--no-steal=false"]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_association("eipalloc-1", Some(OTHER_INSTANCE_ID))
+        .with_running(OTHER_INSTANCE_ID);
+
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(
+        ec2.associated_instance("eipalloc-1").as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+}
+
+#[tokio::test]
+async fn records_association_id() {
+    let opts = common::state_flags("association-id", &[]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default().with_address("eipalloc-1", &[("Kind", "test")]);
+
+    provision(&opts, &ec2).await.unwrap();
+    let recorded = state::State::load(&opts.mounted_eip_file_path).unwrap();
+    assert_eq!(recorded.association_id, "eipassoc-eipalloc-1-1");
+    assert_eq!(recorded.instance_id, LOCAL_INSTANCE_ID);
+    assert_eq!(recorded.associated_at, NOW);
+
+    // verified, not associated again
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(ec2.calls().len(), 1);
+}
+
+#[tokio::test]
+async fn repairs_association_moved_elsewhere() {
+    let opts = common::state_flags("association-moved", &["--no-steal=false"]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default().with_address("eipalloc-1", &[("Kind", "test")]);
+    provision(&opts, &ec2).await.unwrap();
+
+    // the same allocation, re-associated elsewhere behind our back
+    let ec2 = ec2
+        .with_association("eipalloc-1", Some(OTHER_INSTANCE_ID))
+        .with_running(OTHER_INSTANCE_ID);
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(
+        ec2.calls()[1..],
+        vec!["associate eipalloc-1 i-local allow_reassociation=true"]
+    );
+    let recorded = state::State::load(&opts.mounted_eip_file_path).unwrap();
+    assert_eq!(recorded.association_id, "eipassoc-eipalloc-1-2");
+    assert_eq!(
+        ec2.associated_instance("eipalloc-1").as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+}
+
+#[tokio::test]
+async fn updates_association_id_reassociated_locally() {
+    let opts = common::state_flags("association-local", &[]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default().with_address("eipalloc-1", &[("Kind", "test")]);
+    provision(&opts, &ec2).await.unwrap();
+
+    // e.g., an operator disassociated and associated it back
+    let ec2 = ec2.with_association("eipalloc-1", Some(LOCAL_INSTANCE_ID));
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(ec2.calls().len(), 1);
+    let recorded = state::State::load(&opts.mounted_eip_file_path).unwrap();
+    assert_eq!(recorded.association_id, "eipassoc-eipalloc-1");
+}
+
+#[tokio::test]
+async fn verifies_association_until_reflected() {
+    let opts = common::state_flags("verify-association", &[]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_stale_describes(2);
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+
+    Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(0))
+        .provision(LOCAL_INSTANCE_ID)
+        .await
+        .unwrap();
+    assert_eq!(
+        *clock.slept.lock().unwrap(),
+        vec![Duration::from_millis(250), Duration::from_millis(500)]
+    );
+
+    // a back-to-back run sees the association, rather than associating once more
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(ec2.calls().len(), 1, "unexpected calls {:?}", ec2.calls());
+}
+
+#[tokio::test]
+async fn fails_if_association_never_reflected() {
+    let opts = common::state_flags("verify-association-timeout", &[]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_stale_describes(u32::MAX);
+
+    let err = provision(&opts, &ec2).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut, "{}", err);
+}
+
+/// Records the state file as written in another region.
+fn write_foreign_state(opts: &Flags, allocation_id: &str) {
+    write_state(opts, allocation_id);
+    let mut recorded = state::State::load(&opts.mounted_eip_file_path).unwrap();
+    recorded.account_id = ACCOUNT_ID.to_string();
+    recorded.region = String::from("eu-west-1");
+    recorded.save(&opts.mounted_eip_file_path).unwrap();
+}
+
+#[tokio::test]
+async fn records_account_and_region() {
+    let opts = common::state_flags("location", &[]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default().with_address("eipalloc-1", &[("Kind", "test")]);
+
+    provision(&opts, &ec2).await.unwrap();
+    let recorded = state::State::load(&opts.mounted_eip_file_path).unwrap();
+    assert_eq!(recorded.account_id, ACCOUNT_ID);
+    assert_eq!(recorded.region, REGION);
+}
+
+#[tokio::test]
+async fn fails_on_foreign_state_by_default() {
+    let opts = common::state_flags("foreign-fail", &[]);
+    write_foreign_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default().with_address("eipalloc-1", &[("Kind", "test")]);
+
+    let err = provision(&opts, &ec2).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("eu-west-1"), "{err}");
+    assert!(ec2.calls().is_empty(), "unexpected calls {:?}", ec2.calls());
+}
+
+#[tokio::test]
+async fn uses_foreign_state_if_ignored() {
+    let opts = common::state_flags("foreign-ignore", &["--on-foreign-state=ignore"]);
+    write_foreign_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default().with_address("eipalloc-1", &[("Kind", "test")]);
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-1");
+    let recorded = state::State::load(&opts.mounted_eip_file_path).unwrap();
+    assert_eq!(recorded.region, "eu-west-1");
+}
+
+#[tokio::test]
+async fn reallocates_on_foreign_state() {
+    let opts = common::state_flags("foreign-reallocate", &["--on-foreign-state=reallocate"]);
+    write_foreign_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default();
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-100");
+    let recorded = state::State::load(&opts.mounted_eip_file_path).unwrap();
+    assert_eq!(recorded.allocation_id, "eipalloc-100");
+    assert_eq!(recorded.region, REGION);
+}
+
+#[tokio::test]
+async fn claims_available_pool_eip() {
+    let opts = common::state_flags("pool-claim", &[]);
+    let ec2 = FakeEc2::default().with_address(
+        "eipalloc-1",
+        &[
+            ("Kind", "test"),
+            (pool::STATUS_TAG_KEY, pool::STATUS_AVAILABLE),
+        ],
+    );
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-1");
+    assert!(!ec2.calls().iter().any(|c| c.starts_with("allocate")));
+    assert_eq!(ec2.tag("eipalloc-1", "Id").as_deref(), Some("node-1"));
+    assert_eq!(
+        ec2.tag("eipalloc-1", pool::STATUS_TAG_KEY).as_deref(),
+        Some(pool::STATUS_CLAIMED)
+    );
+    assert_eq!(
+        ec2.associated_instance("eipalloc-1").as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+}
+
+#[tokio::test]
+async fn claims_next_pool_eip_if_claimed_concurrently() {
+    let opts = common::state_flags("pool-race", &[]);
+    let ec2 = FakeEc2::default()
+        .with_address(
+            "eipalloc-1",
+            &[
+                ("Kind", "test"),
+                (pool::STATUS_TAG_KEY, pool::STATUS_AVAILABLE),
+            ],
+        )
+        .with_address(
+            "eipalloc-22",
+            &[
+                ("Kind", "test"),
+                (pool::STATUS_TAG_KEY, pool::STATUS_AVAILABLE),
+            ],
+        )
+        .with_rival_claimant(OTHER_INSTANCE_ID);
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+
+    // both instances tag the oldest, and the rival tags it last
+    let eip = Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(0))
+        .provision(LOCAL_INSTANCE_ID)
+        .await
+        .unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-22");
+    assert_eq!(
+        ec2.tag("eipalloc-1", pool::CLAIMED_BY_TAG_KEY).as_deref(),
+        Some(OTHER_INSTANCE_ID)
+    );
+    assert_eq!(
+        ec2.tag("eipalloc-22", pool::CLAIMED_BY_TAG_KEY).as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+    assert_eq!(
+        ec2.associated_instance("eipalloc-22").as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+    assert_eq!(ec2.associated_instance("eipalloc-1"), None);
+    assert_eq!(
+        *clock.slept.lock().unwrap(),
+        vec![pool::CLAIM_SETTLE_DELAY, pool::CLAIM_SETTLE_DELAY]
+    );
+}
+
+#[tokio::test]
+async fn skips_associated_pool_eip() {
+    let opts = common::state_flags("pool-associated", &[]);
+    let ec2 = FakeEc2::default()
+        .with_address(
+            "eipalloc-1",
+            &[
+                ("Kind", "test"),
+                (pool::STATUS_TAG_KEY, pool::STATUS_AVAILABLE),
+            ],
+        )
+        .with_association("eipalloc-1", Some(OTHER_INSTANCE_ID));
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_ne!(eip.allocation_id, "eipalloc-1");
+    assert!(ec2.calls()[0].starts_with("allocate"));
+}
+
+#[tokio::test]
+async fn ignores_pool_eip_of_other_kind() {
+    let opts = common::state_flags("pool-other-kind", &[]);
+    let ec2 = FakeEc2::default().with_address(
+        "eipalloc-1",
+        &[
+            ("Kind", "other"),
+            (pool::STATUS_TAG_KEY, pool::STATUS_AVAILABLE),
+        ],
+    );
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_ne!(eip.allocation_id, "eipalloc-1");
+}
+
+#[tokio::test]
+async fn claims_pool_eip_with_lease() {
+    let opts = common::state_flags("pool-lease", &["--pool-lease-seconds=600"]);
+    let ec2 = FakeEc2::default().with_address(
+        "eipalloc-1",
+        &[
+            ("Kind", "test"),
+            (pool::STATUS_TAG_KEY, pool::STATUS_AVAILABLE),
+        ],
+    );
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-1");
+    assert_eq!(
+        ec2.tag("eipalloc-1", pool::CLAIMED_BY_TAG_KEY).as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+    assert_eq!(
+        ec2.tag("eipalloc-1", pool::CLAIM_EXPIRES_AT_TAG_KEY),
+        Some((NOW + 600).to_string())
+    );
+}
+
+#[tokio::test]
+async fn reclaims_only_expired_unassociated_claims() {
+    let opts = common::state_flags("pool-reclaim", &["--pool-lease-seconds=600"]);
+    let expired = (NOW - 1).to_string();
+    let live = (NOW + 300).to_string();
+    let ec2 = FakeEc2::default()
+        // the live claimant keeps renewing
+        .with_address(
+            "eipalloc-1",
+            &[
+                ("Kind", "test"),
+                (pool::STATUS_TAG_KEY, pool::STATUS_CLAIMED),
+                (pool::CLAIMED_BY_TAG_KEY, OTHER_INSTANCE_ID),
+                (pool::CLAIM_EXPIRES_AT_TAG_KEY, &live),
+            ],
+        )
+        // expired, but the claimant holds the association
+        .with_address(
+            "eipalloc-22",
+            &[
+                ("Kind", "test"),
+                (pool::STATUS_TAG_KEY, pool::STATUS_CLAIMED),
+                (pool::CLAIMED_BY_TAG_KEY, OTHER_INSTANCE_ID),
+                (pool::CLAIM_EXPIRES_AT_TAG_KEY, &expired),
+            ],
+        )
+        .with_association("eipalloc-22", Some(OTHER_INSTANCE_ID))
+        // the claimant crashed before associating
+        .with_address(
+            "eipalloc-333",
+            &[
+                ("Kind", "test"),
+                (pool::STATUS_TAG_KEY, pool::STATUS_CLAIMED),
+                (pool::CLAIMED_BY_TAG_KEY, "i-crashed"),
+                (pool::CLAIM_EXPIRES_AT_TAG_KEY, &expired),
+            ],
+        );
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-333");
+    assert_eq!(
+        ec2.tag("eipalloc-333", pool::CLAIMED_BY_TAG_KEY).as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+    assert_eq!(ec2.tag("eipalloc-1", "Id"), None);
+    assert_eq!(ec2.tag("eipalloc-22", "Id"), None);
+}
+
+#[tokio::test]
+async fn renews_only_own_lease() {
+    let ec2 = FakeEc2::default()
+        .with_address(
+            "eipalloc-1",
+            &[
+                (pool::STATUS_TAG_KEY, pool::STATUS_CLAIMED),
+                (pool::CLAIMED_BY_TAG_KEY, LOCAL_INSTANCE_ID),
+                (pool::CLAIM_EXPIRES_AT_TAG_KEY, "100"),
+            ],
+        )
+        .with_address(
+            "eipalloc-22",
+            &[
+                (pool::STATUS_TAG_KEY, pool::STATUS_CLAIMED),
+                (pool::CLAIMED_BY_TAG_KEY, OTHER_INSTANCE_ID),
+                (pool::CLAIM_EXPIRES_AT_TAG_KEY, "100"),
+            ],
+        );
+    let lease = pool::Lease {
+        instance_id: LOCAL_INSTANCE_ID,
+        seconds: 600,
+        now: NOW,
+    };
+
+    assert!(pool::renew(&ec2, "eipalloc-1", &lease).await.unwrap());
+    assert_eq!(
+        ec2.tag("eipalloc-1", pool::CLAIM_EXPIRES_AT_TAG_KEY),
+        Some((NOW + 600).to_string())
+    );
+    // reclaimed by another after the lease expired
+    assert!(!pool::renew(&ec2, "eipalloc-22", &lease).await.unwrap());
+    assert_eq!(
+        ec2.tag("eipalloc-22", pool::CLAIM_EXPIRES_AT_TAG_KEY)
+            .as_deref(),
+        Some("100")
+    );
+}
+
+#[tokio::test]
+async fn autoscales_pool_within_bounds() {
+    // pre-allocates up to the minimum, ignoring the claimed and the associated
+    let opts = common::state_flags("pool-scale-out", &["--pool-min-free=3"]);
+    let ec2 = pool_of_two()
+        .with_address(
+            "eipalloc-333",
+            &[
+                ("Kind", "test"),
+                (pool::STATUS_TAG_KEY, pool::STATUS_CLAIMED),
+            ],
+        )
+        .with_association("eipalloc-1", Some(OTHER_INSTANCE_ID));
+    let scaled = pool::autoscale(&ec2, &opts, NOW).await.unwrap();
+    assert_eq!(
+        scaled,
+        pool::Scaled {
+            free: 1,
+            allocated: 2,
+            released: 0
+        }
+    );
+    let allocated: Vec<String> = ec2
+        .calls()
+        .iter()
+        .filter_map(|c| c.strip_prefix("allocate ").map(|id| id.to_string()))
+        .collect();
+    assert_eq!(allocated.len(), 2);
+    for id in allocated.iter() {
+        assert_eq!(
+            ec2.tag(id, pool::STATUS_TAG_KEY).as_deref(),
+            Some(pool::STATUS_AVAILABLE)
+        );
+        assert_eq!(ec2.tag(id, "Kind").as_deref(), Some("test"));
+    }
+
+    // releases the newest down to the maximum
+    let opts = common::state_flags("pool-scale-in", &["--pool-max-free=1"]);
+    let ec2 = pool_of_two();
+    let scaled = pool::autoscale(&ec2, &opts, NOW).await.unwrap();
+    assert_eq!(scaled.released, 1);
+    assert_eq!(ec2.calls(), vec!["release_address eipalloc-1"]);
+
+    // does not release the newest if claimed since the listing
+    let ec2 = pool_of_two().with_claim_after_listing("eipalloc-1");
+    let scaled = pool::autoscale(&ec2, &opts, NOW).await.unwrap();
+    assert_eq!(scaled.released, 0);
+    assert!(ec2.calls().is_empty(), "unexpected calls {:?}", ec2.calls());
+
+    // within the bounds
+    let opts = common::state_flags("pool-steady", &["--pool-min-free=1", "--pool-max-free=2"]);
+    let ec2 = pool_of_two();
+    let scaled = pool::autoscale(&ec2, &opts, NOW).await.unwrap();
+    assert_eq!(scaled.free, 2);
+    assert!(ec2.calls().is_empty(), "unexpected calls {:?}", ec2.calls());
+}
+
+fn pool_of_two() -> FakeEc2 {
+    FakeEc2::default()
+        .with_address(
+            "eipalloc-1",
+            &[
+                ("Kind", "test"),
+                (pool::STATUS_TAG_KEY, pool::STATUS_AVAILABLE),
+                ("AllocatedAt", "200"),
+            ],
+        )
+        .with_address(
+            "eipalloc-22",
+            &[
+                ("Kind", "test"),
+                (pool::STATUS_TAG_KEY, pool::STATUS_AVAILABLE),
+                ("AllocatedAt", "100"),
+            ],
+        )
+}
+
+#[tokio::test]
+async fn resolves_pool_conflict_by_policy() {
+    let opts = common::state_flags("conflict-oldest", &["--conflict-policy=oldest"]);
+    let eip = provision(&opts, &pool_of_two()).await.unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-22");
+
+    let opts = common::state_flags("conflict-newest", &["--conflict-policy=newest"]);
+    let eip = provision(&opts, &pool_of_two()).await.unwrap();
+    assert_eq!(eip.allocation_id, "eipalloc-1");
+
+    let opts = common::state_flags("conflict-fail", &["--conflict-policy=fail"]);
+    let ec2 = pool_of_two();
+    assert!(provision(&opts, &ec2).await.is_err());
+    assert!(ec2.calls().is_empty(), "unexpected calls {:?}", ec2.calls());
+}
+
+#[tokio::test]
+async fn waits_for_instance_ready_before_associating() {
+    let opts = common::state_flags("ready", &["--associate-ready-timeout-seconds=30"]);
+    let ec2 = FakeEc2::default().with_not_ready_polls(2);
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+
+    let eip = Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(0))
+        .provision(LOCAL_INSTANCE_ID)
+        .await
+        .unwrap();
+    assert_eq!(
+        ec2.associated_instance(&eip.allocation_id).as_deref(),
+        Some(LOCAL_INSTANCE_ID)
+    );
+    assert_eq!(
+        *clock.slept.lock().unwrap(),
+        vec![Duration::from_secs(5), Duration::from_secs(5)]
+    );
+}
+
+#[tokio::test]
+async fn fails_if_instance_not_ready_in_time() {
+    let opts = common::state_flags("not-ready", &["--associate-ready-timeout-seconds=10"]);
+    let ec2 = FakeEc2::default().with_not_ready_polls(100);
+
+    let err = provision(&opts, &ec2).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    assert!(
+        !ec2.calls().iter().any(|c| c.starts_with("associate")),
+        "must not associate: {:?}",
+        ec2.calls()
+    );
+
+    // zero skips the wait
+    let opts = common::state_flags("ready-no-wait", &["--associate-ready-timeout-seconds=0"]);
+    let ec2 = FakeEc2::default().with_not_ready_polls(100);
+    provision(&opts, &ec2).await.unwrap();
+}
+
+#[tokio::test]
+async fn associates_with_target_network_interface() {
+    let ec2 = || {
+        FakeEc2::default()
+            .with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[])
+            .with_network_interface("eni-public", LOCAL_INSTANCE_ID, 1, &[("Name", "public")])
+            .with_network_interface("eni-remote", OTHER_INSTANCE_ID, 1, &[("Name", "public")])
+    };
+
+    let opts = common::state_flags("target-eni-tag", &["--target-eni-tag=Name=public"]);
+    let by_tag = ec2();
+    let eip = provision(&opts, &by_tag).await.unwrap();
+    assert_eq!(
+        by_tag
+            .associated_network_interface(&eip.allocation_id)
+            .as_deref(),
+        Some("eni-public")
+    );
+
+    let opts = common::state_flags("target-device-index", &["--target-device-index=1"]);
+    let by_index = ec2();
+    let eip = provision(&opts, &by_index).await.unwrap();
+    assert!(by_index.calls().contains(&format!(
+        "associate {} i-local/eni-public allow_reassociation=false",
+        eip.allocation_id
+    )));
+}
+
+#[tokio::test]
+async fn reassociates_from_wrong_network_interface() {
+    let opts = common::state_flags("wrong-eni", &["--target-eni-tag=Name=public"]);
+    write_state(&opts, "eipalloc-1");
+    // associated with the local instance, but on "eni-other" (e.g., before the ENI replacement)
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_association("eipalloc-1", Some(LOCAL_INSTANCE_ID))
+        .with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[])
+        .with_network_interface("eni-public", LOCAL_INSTANCE_ID, 2, &[("Name", "public")]);
+
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(
+        ec2.calls(),
+        vec!["associate eipalloc-1 i-local/eni-public allow_reassociation=true"]
+    );
+
+    // no-op once on the target
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(ec2.calls().len(), 1, "unexpected calls {:?}", ec2.calls());
+}
+
+#[tokio::test]
+async fn fails_if_target_network_interface_not_attached() {
+    let opts = common::state_flags("target-eni-missing", &["--target-eni-tag=Name=public"]);
+    let ec2 = FakeEc2::default()
+        .with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[])
+        .with_network_interface("eni-remote", OTHER_INSTANCE_ID, 1, &[("Name", "public")]);
+
+    let err = provision(&opts, &ec2).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(
+        !ec2.calls().iter().any(|c| c.starts_with("associate")),
+        "must not associate: {:?}",
+        ec2.calls()
+    );
+}
+
+#[tokio::test]
+async fn associates_with_private_ip_address() {
+    let opts = common::state_flags("private-ip", &["--private-ip-address=10.0.0.20"]);
+    let ec2 = FakeEc2::default()
+        .with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[])
+        .with_private_ip_address("eni-primary", "10.0.0.10")
+        .with_network_interface("eni-service", LOCAL_INSTANCE_ID, 1, &[])
+        .with_private_ip_address("eni-service", "10.0.0.11")
+        .with_private_ip_address("eni-service", "10.0.0.20");
+
+    let eip = provision(&opts, &ec2).await.unwrap();
+    assert!(ec2.calls().contains(&format!(
+        "associate {} i-local/eni-service/10.0.0.20 allow_reassociation=false",
+        eip.allocation_id
+    )));
+    assert_eq!(
+        state::State::load(&opts.mounted_eip_file_path)
+            .unwrap()
+            .private_ip_address,
+        "10.0.0.20"
+    );
+
+    // the recorded private IP is kept without the flag
+    let opts = Flags {
+        private_ip_address: String::new(),
+        ..opts
+    };
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(
+        ec2.associated_private_ip_address(&eip.allocation_id)
+            .as_deref(),
+        Some("10.0.0.20")
+    );
+    assert_eq!(
+        state::State::load(&opts.mounted_eip_file_path)
+            .unwrap()
+            .private_ip_address,
+        "10.0.0.20"
+    );
+}
+
+#[tokio::test]
+async fn ignores_private_ip_address_of_another_instance() {
+    let opts = common::state_flags("private-ip-other-instance", &[]);
+    // the volume of another instance, with the private IP recorded there
+    write_state(&opts, "eipalloc-1");
+    state::record_private_ip_address(&opts.mounted_eip_file_path, "10.0.0.20", OTHER_INSTANCE_ID)
+        .unwrap();
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[])
+        .with_private_ip_address("eni-primary", "10.0.0.10");
+
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(
+        ec2.calls(),
+        vec!["associate eipalloc-1 i-local allow_reassociation=false"]
+    );
+    let recorded = state::State::load(&opts.mounted_eip_file_path).unwrap();
+    assert_eq!(recorded.private_ip_address, "");
+    assert_eq!(recorded.private_ip_instance_id, "");
+}
+
+#[tokio::test]
+async fn reassociates_from_wrong_private_ip_address() {
+    let opts = common::state_flags(
+        "wrong-private-ip",
+        &["--target-device-index=1", "--private-ip-address=10.0.0.20"],
+    );
+    write_state(&opts, "eipalloc-1");
+    // associated with the right ENI, but mapped to its primary private IP
+    let ec2 = FakeEc2::default()
+        .with_address("eipalloc-1", &[("Kind", "test")])
+        .with_network_interface("eni-service", LOCAL_INSTANCE_ID, 1, &[])
+        .with_private_ip_address("eni-service", "10.0.0.11")
+        .with_private_ip_address("eni-service", "10.0.0.20");
+    let target = eip::Target {
+        network_interface_id: String::from("eni-service"),
+        private_ip_address: String::from("10.0.0.11"),
+    };
+    ec2.associate("eipalloc-1", LOCAL_INSTANCE_ID, &target, false)
+        .await
+        .unwrap();
+    let before = ec2.calls().len();
+
+    provision(&opts, &ec2).await.unwrap();
+    assert_eq!(
+        ec2.calls()[before..],
+        ["associate eipalloc-1 i-local/eni-service/10.0.0.20 allow_reassociation=true"]
+    );
+}
+
+#[tokio::test]
+async fn fails_if_private_ip_address_not_on_target() {
+    let opts = common::state_flags(
+        "private-ip-missing",
+        &["--target-device-index=1", "--private-ip-address=10.0.0.20"],
+    );
+    let ec2 = FakeEc2::default()
+        .with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[])
+        .with_private_ip_address("eni-primary", "10.0.0.20")
+        .with_network_interface("eni-service", LOCAL_INSTANCE_ID, 1, &[])
+        .with_private_ip_address("eni-service", "10.0.0.11");
+
+    assert!(provision(&opts, &ec2).await.is_err());
+    assert!(
+        !ec2.calls().iter().any(|c| c.starts_with("associate")),
+        "must not associate: {:?}",
+        ec2.calls()
+    );
+}
+
+#[tokio::test]
+async fn waits_random_seconds_up_to_limit() {
+    let opts = common::state_flags("wait", &["--initial-wait-random-seconds=10"]);
+    let ec2 = FakeEc2::default();
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+    let waited = Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(17))
+        .initial_wait(LOCAL_INSTANCE_ID)
+        .await
+        .unwrap();
+    assert_eq!(waited, 7);
+    assert_eq!(*clock.slept.lock().unwrap(), vec![Duration::from_secs(7)]);
+
+    let opts = common::state_flags("no-wait", &["--initial-wait-random-seconds=0"]);
+    let clock = FakeClock::default();
+    let waited = Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(17))
+        .initial_wait(LOCAL_INSTANCE_ID)
+        .await
+        .unwrap();
+    assert_eq!(waited, 0);
+    assert!(clock.slept.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn waits_by_hash_of_instance_id() {
+    let opts = common::state_flags(
+        "wait-hash",
+        &[
+            "--initial-wait-random-seconds=60",
+            "--wait-strategy=hash-instance-id",
+        ],
+    );
+    let ec2 = FakeEc2::default();
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+    let mut waits = Vec::new();
+    for rng in [0, 17] {
+        waits.push(
+            Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(rng))
+                .initial_wait(LOCAL_INSTANCE_ID)
+                .await
+                .unwrap(),
+        );
+    }
+    // the same across restarts, regardless of the randomness
+    assert_eq!(waits[0], waits[1]);
+    assert_eq!(
+        waits[0] as u64,
+        eip::fnv1a(LOCAL_INSTANCE_ID.as_bytes()) % 60
+    );
+}
+
+#[tokio::test]
+async fn waits_by_ordinal_in_asg() {
+    let opts = common::state_flags(
+        "wait-ordinal",
+        &[
+            "--initial-wait-random-seconds=60",
+            "--wait-strategy=ordinal-from-asg",
+        ],
+    );
+    let ec2 = FakeEc2::default();
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+
+    // third of four instances gets the third quarter of the window
+    let metadata = AsgMetadata(&["i-a", "i-b", LOCAL_INSTANCE_ID, "i-other"]);
+    let waited = Provisioner::new(&ctx, &opts, &ec2, &metadata, &clock, &FakeRng(17))
+        .initial_wait(LOCAL_INSTANCE_ID)
+        .await
+        .unwrap();
+    assert_eq!(waited, 30);
+
+    // not in an auto scaling group
+    let waited = Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(17))
+        .initial_wait(LOCAL_INSTANCE_ID)
+        .await
+        .unwrap();
+    assert_eq!(waited, 17);
+}
+
+#[tokio::test]
+async fn reads_instance_id_from_metadata() {
+    let opts = common::state_flags("instance-id", &[]);
+    let ec2 = FakeEc2::default();
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(0));
+    assert_eq!(provisioner.instance_id().await.unwrap(), LOCAL_INSTANCE_ID);
+}
+
+#[tokio::test]
+async fn reports_unmanaged_public_ip() {
+    let opts = common::state_flags("unmanaged-public-ip", &["--skip-if-public-ip=true"]);
+    let ec2 = FakeEc2::default();
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+
+    // no public IPv4 at all
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(0));
+    assert_eq!(provisioner.unmanaged_public_ip().await.unwrap(), None);
+
+    // auto-assigned public IPv4, without the state file
+    let metadata = PublicMetadata("198.51.100.7");
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &metadata, &clock, &FakeRng(0));
+    assert_eq!(
+        provisioner.unmanaged_public_ip().await.unwrap().as_deref(),
+        Some("198.51.100.7")
+    );
+    assert!(ec2.calls().is_empty());
+}
+
+#[tokio::test]
+async fn managed_eip_is_not_skipped() {
+    let opts = common::state_flags("managed-public-ip", &["--skip-if-public-ip=true"]);
+    write_state(&opts, "eipalloc-1");
+    let ec2 = FakeEc2::default();
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+
+    // the public IPv4 is the EIP in the state file (e.g., restart)
+    let metadata = PublicMetadata("203.0.113.10");
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &metadata, &clock, &FakeRng(0));
+    assert_eq!(provisioner.unmanaged_public_ip().await.unwrap(), None);
+
+    // another address than the one in the state file
+    let metadata = PublicMetadata("198.51.100.7");
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &metadata, &clock, &FakeRng(0));
+    assert_eq!(
+        provisioner.unmanaged_public_ip().await.unwrap().as_deref(),
+        Some("198.51.100.7")
+    );
+}
+
+#[tokio::test]
+async fn ensures_ipv6_on_eip_network_interface() {
+    let opts = common::state_flags(
+        "dual-stack",
+        &["--dual-stack=true", "--target-eni-tag=Name=public"],
+    );
+    let ec2 = FakeEc2::default()
+        .with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[])
+        .with_ipv6_address("eni-primary", "2001:db8::7")
+        .with_network_interface("eni-public", LOCAL_INSTANCE_ID, 1, &[("Name", "public")]);
+    let eip = provision(&opts, &ec2).await.unwrap();
+
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(0));
+    let ipv6 = provisioner.ensure_ipv6(LOCAL_INSTANCE_ID).await.unwrap();
+    assert_eq!(ipv6, "2001:db8::10");
+    assert!(ec2
+        .calls()
+        .contains(&String::from("assign_ipv6_address eni-public")));
+
+    // recorded next to the EIP, and kept across the EIP updates
+    state::record_ipv6_address(&opts.mounted_eip_file_path, &ipv6).unwrap();
+    state::record_eip(&opts.mounted_eip_file_path, &eip).unwrap();
+    assert_eq!(
+        state::State::load(&opts.mounted_eip_file_path)
+            .unwrap()
+            .ipv6_address,
+        "2001:db8::10"
+    );
+    assert_eq!(state::load_eip(&opts.mounted_eip_file_path).unwrap(), eip);
+
+    // no-op once assigned
+    let before = ec2.calls().len();
+    assert_eq!(
+        provisioner.ensure_ipv6(LOCAL_INSTANCE_ID).await.unwrap(),
+        "2001:db8::10"
+    );
+    assert!(!ec2.calls()[before..]
+        .iter()
+        .any(|c| c.starts_with("assign_ipv6_address")));
+}
+
+#[tokio::test]
+async fn fails_on_ipv6_only_with_guidance() {
+    let opts = common::state_flags("ipv6-only-fail", &[]);
+    let ec2 = FakeEc2::default().with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[]);
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &FakeMetadata, &clock, &FakeRng(0));
+    assert_eq!(
+        provisioner.ipv6_only(LOCAL_INSTANCE_ID).await.unwrap(),
+        None
+    );
+
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &Ipv6OnlyMetadata, &clock, &FakeRng(0));
+    let err = provisioner.ipv6_only(LOCAL_INSTANCE_ID).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(err.to_string().contains("--ipv6-only=assign-ipv6"), "{err}");
+    assert!(ec2.calls().is_empty(), "unexpected calls {:?}", ec2.calls());
+}
+
+#[tokio::test]
+async fn assigns_ipv6_on_ipv6_only() {
+    let opts = common::state_flags("ipv6-only-assign", &["--ipv6-only=assign-ipv6"]);
+    let ctx = Context::default();
+    let clock = FakeClock::default();
+
+    // keeps the global address already assigned
+    let ec2 = FakeEc2::default()
+        .with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[])
+        .with_ipv6_address("eni-primary", "2001:db8::7");
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &Ipv6OnlyMetadata, &clock, &FakeRng(0));
+    assert_eq!(
+        provisioner
+            .ipv6_only(LOCAL_INSTANCE_ID)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("2001:db8::7")
+    );
+    assert!(ec2.calls().is_empty(), "unexpected calls {:?}", ec2.calls());
+
+    // a link-local address is not reachable, so assigns one
+    let ec2 = FakeEc2::default()
+        .with_network_interface("eni-primary", LOCAL_INSTANCE_ID, 0, &[])
+        .with_ipv6_address("eni-primary", "fe80::1")
+        .with_network_interface("eni-secondary", LOCAL_INSTANCE_ID, 1, &[]);
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, &Ipv6OnlyMetadata, &clock, &FakeRng(0));
+    assert_eq!(
+        provisioner
+            .ipv6_only(LOCAL_INSTANCE_ID)
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("2001:db8::10")
+    );
+    assert_eq!(ec2.calls(), vec!["assign_ipv6_address eni-primary"]);
+}
+
+#[tokio::test]
+async fn namespaces_do_not_share_eip_or_state_file() {
+    let public = common::state_flags("namespace", &["--namespace=public"]);
+    let mut internal = common::state_flags("namespace-internal", &["--namespace=internal-vip"]);
+    // same volume and flags otherwise
+    internal.mounted_eip_file_path = command::namespaced_file_path(
+        "internal-vip",
+        &common::test_dir("namespace")
+            .join("eip.yaml")
+            .to_string_lossy(),
+    );
+    assert!(public.mounted_eip_file_path.ends_with("/public-eip.yaml"));
+    assert!(internal
+        .mounted_eip_file_path
+        .ends_with("/internal-vip-eip.yaml"));
+    let ec2 = FakeEc2::default();
+
+    let public_eip = provision(&public, &ec2).await.unwrap();
+    let internal_eip = provision(&internal, &ec2).await.unwrap();
+    assert_ne!(public_eip.allocation_id, internal_eip.allocation_id);
+    assert_eq!(
+        ec2.tag(&public_eip.allocation_id, "Id").as_deref(),
+        Some("public-node-1")
+    );
+    assert_eq!(
+        ec2.tag(&internal_eip.allocation_id, "Kind").as_deref(),
+        Some("internal-vip-test")
+    );
+    assert_eq!(
+        state::load_eip(&public.mounted_eip_file_path).unwrap(),
+        public_eip
+    );
+    assert_eq!(
+        state::load_eip(&internal.mounted_eip_file_path).unwrap(),
+        internal_eip
+    );
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use aws_ip_provisioner::{metrics, ratelimit};
+use tokio::time::{Duration, Instant};
+
+#[test]
+fn detects_throttling() {
+    let ec2 =
+        b"<Response><Errors><Error><Code>RequestLimitExceeded</Code></Error></Errors></Response>";
+    assert_eq!(
+        ratelimit::throttling_code(503, ec2),
+        Some("RequestLimitExceeded")
+    );
+    let sts = b"<ErrorResponse><Error><Code>Throttling</Code></Error></ErrorResponse>";
+    assert_eq!(ratelimit::throttling_code(400, sts), Some("Throttling"));
+    let json = br#"{"__type":"com.amazon.coral.availability#ThrottlingException"}"#;
+    assert_eq!(
+        ratelimit::throttling_code(400, json),
+        Some("ThrottlingException")
+    );
+    assert_eq!(
+        ratelimit::throttling_code(429, b""),
+        Some("TooManyRequests")
+    );
+
+    let denied =
+        b"<Response><Errors><Error><Code>UnauthorizedOperation</Code></Error></Errors></Response>";
+    assert_eq!(ratelimit::throttling_code(403, denied), None);
+    assert_eq!(ratelimit::throttling_code(200, ec2), None);
+}
+
+#[test]
+fn backs_off_and_recovers() {
+    let metrics = Arc::new(metrics::Registry::default());
+    let limiter = ratelimit::Limiter::new(metrics.clone());
+    limiter.init(8);
+    assert_eq!(limiter.adaptive_rps(), None);
+    limiter.on_success();
+    assert_eq!(limiter.adaptive_rps(), None);
+
+    // multiplicative decrease
+    limiter.on_throttled("RequestLimitExceeded");
+    assert_eq!(limiter.adaptive_rps(), Some(4.0));
+    limiter.on_throttled("RequestLimitExceeded");
+    assert_eq!(limiter.adaptive_rps(), Some(2.0));
+    assert_eq!(metrics.counters()[ratelimit::THROTTLED_COUNTER], 2);
+    assert_eq!(metrics.gauges()[ratelimit::ADAPTIVE_RPS_GAUGE], 2.0);
+
+    // additive increase, until back at "--max-api-rps"
+    for _ in 0..10 {
+        limiter.on_success();
+    }
+    assert!((limiter.adaptive_rps().unwrap() - 3.0).abs() < 1e-9);
+    for _ in 0..60 {
+        limiter.on_success();
+    }
+    assert_eq!(limiter.adaptive_rps(), None);
+    assert_eq!(metrics.gauges()[ratelimit::ADAPTIVE_RPS_GAUGE], 0.0);
+}
+
+#[tokio::test]
+async fn limits_each_scope_separately() {
+    let limiter = ratelimit::Limiter::default();
+    limiter.init_scoped(10);
+    let calls = |n: usize| {
+        let limiter = &limiter;
+        async move {
+            for _ in 0..n {
+                limiter.acquire().await;
+            }
+        }
+    };
+
+    // the burst of one scope does not slow down another
+    let started = Instant::now();
+    ratelimit::scoped("a/us-west-2".to_string(), calls(10)).await;
+    ratelimit::scoped("b/us-west-2".to_string(), calls(10)).await;
+    calls(100).await;
+    assert!(started.elapsed() < Duration::from_millis(100));
+
+    ratelimit::scoped("a/us-west-2".to_string(), calls(2)).await;
+    assert!(started.elapsed() >= Duration::from_millis(150));
+}
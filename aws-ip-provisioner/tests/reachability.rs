@@ -0,0 +1,117 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use aws_ip_provisioner::{
+    context::Context,
+    identity::Signed,
+    reachability::{self, Target},
+};
+
+/// Answers every request as reachable only for the open port,
+/// recording the request lines.
+fn reflector(open_port: u16) -> (String, Arc<Mutex<Vec<String>>>) {
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let recorded = lines.clone();
+    let url = common::serve_with("/reachability", move |req| {
+        let line = req.head.lines().next().unwrap().to_string();
+        let body = if line.contains(&format!("port={open_port} ")) {
+            r#"{"reachable":true}"#
+        } else {
+            r#"{"reachable":false,"error":"connection timed out"}"#
+        };
+        recorded.lock().unwrap().push(line);
+        ("200 OK", body.as_bytes().to_vec())
+    });
+    (url, lines)
+}
+
+#[test]
+fn parses_targets() {
+    assert_eq!(
+        reachability::parse("tcp://:9651, tcp://[2001:db8::1]:443,").unwrap(),
+        vec![
+            Target {
+                host: String::new(),
+                port: 9651
+            },
+            Target {
+                host: "2001:db8::1".to_string(),
+                port: 443
+            },
+        ]
+    );
+    assert!(reachability::parse("").unwrap().is_empty());
+    assert!(reachability::parse("udp://:53").is_err());
+    assert!(reachability::parse("tcp://:0").is_err());
+    assert!(reachability::parse("tcp://host").is_err());
+}
+
+#[test]
+fn never_probes_internal_addresses() {
+    for ip in [
+        "10.0.0.5",
+        "172.31.0.1",
+        "192.168.1.1",
+        "127.0.0.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "255.255.255.255",
+        "224.0.0.1",
+        "::1",
+        "::",
+        "fd00:ec2::254",
+        "fe80::1",
+        "::ffff:10.0.0.5",
+    ] {
+        assert!(!reachability::is_public(ip.parse().unwrap()), "{ip}");
+    }
+    for ip in ["52.1.2.3", "100.128.0.1", "2600:1f14::1", "::ffff:52.1.2.3"] {
+        assert!(reachability::is_public(ip.parse().unwrap()), "{ip}");
+    }
+}
+
+#[tokio::test]
+async fn verifies_from_the_reflector() {
+    let (url, lines) = reflector(9651);
+    let signed = Signed {
+        document: String::from("{}"),
+        pkcs7: String::from("unused"),
+    };
+    let targets = reachability::parse("tcp://:9651").unwrap();
+    reachability::verify(
+        &Context::default(),
+        &url,
+        &signed,
+        "203.0.113.7",
+        &targets,
+        10,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        lines.lock().unwrap()[0],
+        "POST /reachability?protocol=tcp&host=203.0.113.7&port=9651 HTTP/1.1"
+    );
+
+    // e.g., the security group does not allow the port yet
+    let targets = reachability::parse("tcp://:9651,tcp://:30303").unwrap();
+    let e = reachability::verify(
+        &Context::default(),
+        &url,
+        &signed,
+        "203.0.113.7",
+        &targets,
+        0,
+    )
+    .await
+    .unwrap_err();
+    assert!(
+        e.to_string()
+            .contains("tcp://203.0.113.7:30303 (connection timed out)"),
+        "{e}"
+    );
+    assert!(e.to_string().contains("security groups"), "{e}");
+    assert!(!e.to_string().contains(":9651"), "{e}");
+}
@@ -0,0 +1,167 @@
+use aws_ip_provisioner::{
+    route53::{self, HealthCheck, Record, Routing},
+    sdk,
+};
+
+fn record(zone_id: &str, name: &str, value: &str) -> Record {
+    Record {
+        zone_id: zone_id.to_string(),
+        name: name.to_string(),
+        ttl: 60,
+        value: value.to_string(),
+        routing: Routing::Simple,
+        set_identifier: String::new(),
+        health_check_id: String::new(),
+    }
+}
+
+#[test]
+fn builds_change_batch() {
+    let xml = route53::change_batch(
+        &[
+            record("Z1", "node-1.example.com", "203.0.113.7"),
+            record("Z1", "node-1.example.com", "2001:db8::7"),
+        ],
+        "us-west-2",
+        "aws-ip-provisioner i-1 <test>",
+    );
+    assert!(xml.starts_with("<?xml"), "{xml}");
+    assert!(
+        xml.contains("<ChangeResourceRecordSetsRequest xmlns=\"https://route53.amazonaws.com/doc/2013-04-01/\">"),
+        "{xml}"
+    );
+    assert!(
+        xml.contains("<Comment>aws-ip-provisioner i-1 &lt;test&gt;</Comment>"),
+        "{xml}"
+    );
+    assert!(xml.contains("<Change><Action>UPSERT</Action><ResourceRecordSet><Name>node-1.example.com</Name><Type>A</Type><TTL>60</TTL><ResourceRecords><ResourceRecord><Value>203.0.113.7</Value></ResourceRecord></ResourceRecords></ResourceRecordSet></Change>"), "{xml}");
+    assert!(xml.contains("<Type>AAAA</Type>"), "{xml}");
+}
+
+#[test]
+fn builds_routing_records() {
+    let mut failover = record("Z1", "api.example.com", "203.0.113.7");
+    failover.routing = Routing::Failover("PRIMARY");
+    failover.set_identifier = String::from("i-1");
+    failover.health_check_id = String::from("hc-1");
+    let mut latency = record("Z1", "api.example.com", "203.0.113.8");
+    latency.routing = Routing::Latency;
+    latency.set_identifier = String::from("i-2");
+    let mut weighted = record("Z1", "api.example.com", "203.0.113.9");
+    weighted.routing = Routing::Weighted(10);
+    weighted.set_identifier = String::from("i-3");
+
+    let xml = route53::change_batch(&[failover, latency, weighted], "us-west-2", "");
+    assert!(xml.contains("<Name>api.example.com</Name><Type>A</Type><SetIdentifier>i-1</SetIdentifier><Failover>PRIMARY</Failover><TTL>60</TTL>"), "{xml}");
+    assert!(
+        xml.contains("</ResourceRecords><HealthCheckId>hc-1</HealthCheckId></ResourceRecordSet>"),
+        "{xml}"
+    );
+    assert!(
+        xml.contains("<SetIdentifier>i-2</SetIdentifier><Region>us-west-2</Region>"),
+        "{xml}"
+    );
+    assert!(
+        xml.contains("<SetIdentifier>i-3</SetIdentifier><Weight>10</Weight>"),
+        "{xml}"
+    );
+    assert_eq!(xml.matches("<HealthCheckId>").count(), 1, "{xml}");
+}
+
+#[test]
+fn parses_routing() {
+    assert_eq!(Routing::parse("simple").unwrap(), Routing::Simple);
+    assert_eq!(
+        Routing::parse("failover-secondary").unwrap(),
+        Routing::Failover("SECONDARY")
+    );
+    assert_eq!(
+        Routing::parse("weighted:255").unwrap(),
+        Routing::Weighted(255)
+    );
+    assert_eq!(Routing::parse("latency").unwrap(), Routing::Latency);
+    assert!(Routing::parse("weighted:256").is_err());
+    assert!(Routing::parse("geolocation").is_err());
+}
+
+#[test]
+fn parses_health_check() {
+    assert_eq!(HealthCheck::parse("").unwrap(), None);
+    assert_eq!(
+        HealthCheck::parse("tcp:9651").unwrap(),
+        Some(HealthCheck {
+            protocol: String::from("TCP"),
+            port: 9651,
+            path: String::new(),
+        })
+    );
+    assert_eq!(
+        HealthCheck::parse("https:443/health")
+            .unwrap()
+            .unwrap()
+            .path,
+        "/health"
+    );
+    assert_eq!(HealthCheck::parse("http:80").unwrap().unwrap().path, "/");
+    for invalid in ["tcp", "tcp:0", "tcp:22/path", "udp:53", "http:x/health"] {
+        assert!(HealthCheck::parse(invalid).is_err(), "{invalid}");
+    }
+}
+
+#[test]
+fn builds_health_check_request() {
+    let check = HealthCheck::parse("http:8080/health").unwrap().unwrap();
+    let reference = check.caller_reference("203.0.113.7", "i-1");
+    assert!(reference.starts_with("ip-manager-"), "{reference}");
+    assert!(reference.len() <= 64, "{reference}");
+    // the same per EIP, so that the reruns get the existing health check back
+    assert_eq!(reference, check.caller_reference("203.0.113.7", "i-1"));
+    assert_ne!(reference, check.caller_reference("203.0.113.8", "i-1"));
+
+    let xml = check.create_request("203.0.113.7", &reference);
+    assert!(
+        xml.contains(&format!("<CallerReference>{reference}</CallerReference>")),
+        "{xml}"
+    );
+    assert!(xml.contains("<HealthCheckConfig><IPAddress>203.0.113.7</IPAddress><Port>8080</Port><Type>HTTP</Type><ResourcePath>/health</ResourcePath>"), "{xml}");
+
+    let xml = HealthCheck::parse("tcp:9651")
+        .unwrap()
+        .unwrap()
+        .create_request("203.0.113.7", &reference);
+    assert!(!xml.contains("ResourcePath"), "{xml}");
+}
+
+#[test]
+fn resolves_global_endpoint() {
+    let mut opts = sdk::Options::default();
+    assert_eq!(
+        route53::endpoint("us-west-2", &opts),
+        ("https://route53.amazonaws.com".to_string(), "us-east-1")
+    );
+    assert_eq!(
+        route53::endpoint("cn-north-1", &opts),
+        (
+            "https://route53.amazonaws.com.cn".to_string(),
+            "cn-northwest-1"
+        )
+    );
+    opts.use_fips = true;
+    assert_eq!(
+        route53::endpoint("us-gov-west-1", &opts).0,
+        "https://route53-fips.amazonaws.com"
+    );
+    opts.endpoint_url = String::from("http://localhost:4566/");
+    assert_eq!(
+        route53::endpoint("us-west-2", &opts).0,
+        "http://localhost:4566"
+    );
+}
+
+#[test]
+fn reads_response_elements() {
+    let resp = "<ChangeResourceRecordSetsResponse><ChangeInfo><Id>/change/C1</Id><Status>PENDING</Status></ChangeInfo></ChangeResourceRecordSetsResponse>";
+    assert_eq!(route53::element(resp, "Id"), Some("/change/C1"));
+    assert_eq!(route53::element(resp, "Status"), Some("PENDING"));
+    assert_eq!(route53::element(resp, "Message"), None);
+}
@@ -0,0 +1,91 @@
+use aws_ip_provisioner::security_group::{self, Plan, PortRange, Rule};
+
+fn rule(protocol: &str, port: i32, cidr: &str, description: &str) -> Rule {
+    Rule {
+        protocol: protocol.to_string(),
+        from_port: port,
+        to_port: port,
+        cidr: cidr.to_string(),
+        description: description.to_string(),
+    }
+}
+
+#[test]
+fn parses_port_ranges() {
+    assert_eq!(
+        security_group::parse_port_ranges("tcp:30303, udp:26656-26657").unwrap(),
+        vec![
+            PortRange {
+                protocol: String::from("tcp"),
+                from_port: 30303,
+                to_port: 30303,
+            },
+            PortRange {
+                protocol: String::from("udp"),
+                from_port: 26656,
+                to_port: 26657,
+            },
+        ]
+    );
+    assert!(security_group::parse_port_ranges("").unwrap().is_empty());
+    for invalid in [
+        "30303",
+        "icmp:8",
+        "tcp:0",
+        "tcp:2-1",
+        "udp:70000",
+        "tcp:a-b",
+    ] {
+        assert!(
+            security_group::parse_port_ranges(invalid).is_err(),
+            "{invalid}"
+        );
+    }
+}
+
+#[test]
+fn plans_rules_of_managed_fleet() {
+    let ranges = security_group::parse_port_ranges("tcp:30303,udp:30303").unwrap();
+    let fleet = vec![
+        (String::from("203.0.113.1"), String::from("node-1")),
+        (String::from("203.0.113.3"), String::from("node-3")),
+    ];
+    let desired = security_group::desired_rules(&ranges, &fleet);
+    assert_eq!(desired.len(), 4);
+
+    let current = vec![
+        (
+            String::from("sgr-1"),
+            rule("tcp", 30303, "203.0.113.1/32", "ip-manager:node-1"),
+        ),
+        (
+            String::from("sgr-2"),
+            rule("udp", 30303, "203.0.113.1/32", "ip-manager:node-1"),
+        ),
+        // released since the last sync
+        (
+            String::from("sgr-3"),
+            rule("tcp", 30303, "203.0.113.2/32", "ip-manager:node-2"),
+        ),
+        // added by hand, never revoked
+        (
+            String::from("sgr-4"),
+            rule("tcp", 22, "198.51.100.0/24", "office"),
+        ),
+        // the same rule added by hand is not authorized again
+        (
+            String::from("sgr-5"),
+            rule("udp", 30303, "203.0.113.3/32", "peer"),
+        ),
+    ];
+    assert_eq!(
+        security_group::plan(&current, &desired),
+        Plan {
+            authorize: vec![rule("tcp", 30303, "203.0.113.3/32", "ip-manager:node-3")],
+            revoke: vec![String::from("sgr-3")],
+        }
+    );
+    // in sync
+    let desired = security_group::desired_rules(&ranges, &fleet[..1]);
+    assert!(security_group::plan(&current[..2], &desired).is_empty());
+}
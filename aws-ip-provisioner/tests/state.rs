@@ -0,0 +1,92 @@
+use std::{env, fs};
+
+use aws_ip_provisioner::{conflict, ec2, state};
+use aws_sdk_ec2::model::{Address, Tag};
+
+fn addr(association_id: &str) -> Address {
+    Address::builder()
+        .allocation_id("eipalloc-1")
+        .public_ip("203.0.113.7")
+        .set_association_id(Some(association_id.to_string()).filter(|s| !s.is_empty()))
+        .instance_id("i-1")
+        .network_interface_id("eni-1")
+        .private_ip_address("10.0.0.5")
+        .network_border_group("us-west-2")
+        .tags(Tag::builder().key("Kind").value("test").build())
+        .tags(
+            Tag::builder()
+                .key(conflict::ALLOCATED_AT_TAG_KEY)
+                .value("1600000000")
+                .build(),
+        )
+        .build()
+}
+
+#[test]
+fn records_the_described_eip() {
+    let dir = env::temp_dir().join(format!("aws-ip-provisioner-state-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("data").join("eip.yaml");
+    let path = path.to_str().unwrap();
+
+    // the values cannot be recorded before the EIP
+    assert!(state::record_private_ip_address(path, "10.0.0.5", "i-1").is_err());
+    assert!(state::load_eip(path).is_err());
+
+    // with a key this version does not know (e.g., written by a later version)
+    state::record_eip(
+        path,
+        &ec2::Eip {
+            allocation_id: String::from("eipalloc-1"),
+            public_ip: String::from("203.0.113.7"),
+        },
+    )
+    .unwrap();
+    state::record_private_ip_address(path, "10.0.0.5", "i-1").unwrap();
+    let mut d = fs::read_to_string(path).unwrap();
+    d.push_str("future_key: 1\n");
+    fs::write(path, d).unwrap();
+
+    let s = state::record(path, &addr("eipassoc-1"), 100).unwrap();
+    assert_eq!(s.association_id, "eipassoc-1");
+    assert_eq!(s.instance_id, "i-1");
+    assert_eq!(s.network_border_group, "us-west-2");
+    assert_eq!(s.tag("Kind"), "test");
+    assert_eq!(s.allocated_at, 1600000000);
+    assert_eq!(s.associated_at, 100);
+    assert_eq!(s.updated_at, 100);
+    assert_eq!(s.tool_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(s.private_ip_address, "10.0.0.5");
+    assert!(s.extra.contains_key("future_key"));
+    assert_eq!(state::State::load(path).unwrap(), s);
+
+    // the older versions still read the EIP
+    let eip: ec2::Eip = serde_yaml::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    assert_eq!(eip, s.eip());
+    assert_eq!(state::load_eip(path).unwrap(), eip);
+
+    // "associated_at" stays on the same association, and is cleared without one
+    let s = state::record(path, &addr("eipassoc-1"), 200).unwrap();
+    assert_eq!((s.associated_at, s.updated_at), (100, 200));
+    let s = state::record(path, &addr(""), 300).unwrap();
+    assert!(!s.is_associated());
+    assert_eq!(s.associated_at, 0);
+
+    // the facts of the previous EIP do not carry over to the next one
+    state::record_eip(
+        path,
+        &ec2::Eip {
+            allocation_id: String::from("eipalloc-2"),
+            public_ip: String::from("203.0.113.8"),
+        },
+    )
+    .unwrap();
+    let s = state::State::load(path).unwrap();
+    assert_eq!(s.allocation_id, "eipalloc-2");
+    assert!(s.tags.is_empty());
+    assert_eq!(s.allocated_at, 0);
+    assert_eq!(s.private_ip_address, "10.0.0.5");
+    assert!(state::record(path, &addr("eipassoc-1"), 400).is_err());
+
+    fs::remove_dir_all(&dir).unwrap();
+}
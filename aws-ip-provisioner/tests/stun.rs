@@ -0,0 +1,78 @@
+use std::net::{SocketAddr, UdpSocket};
+
+use aws_ip_provisioner::{context::Context, stun, summary};
+
+const TRANSACTION_ID: [u8; 12] = [
+    0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+];
+
+/// Binding success with the XOR-MAPPED-ADDRESS of the address.
+/// ref. <https://www.rfc-editor.org/rfc/rfc5769#section-2.2>
+fn response(transaction_id: &[u8], addr: SocketAddr) -> Vec<u8> {
+    let ip = match addr {
+        SocketAddr::V4(a) => a.ip().octets(),
+        SocketAddr::V6(_) => unreachable!(),
+    };
+    let cookie = 0x2112_A442u32.to_be_bytes();
+    let port = addr.port() ^ 0x2112;
+    let mut b = vec![0x01, 0x01, 0x00, 20];
+    b.extend_from_slice(&cookie);
+    b.extend_from_slice(transaction_id);
+    // an unknown attribute (SOFTWARE) with padding first
+    b.extend_from_slice(&[0x80, 0x22, 0x00, 0x03, b'a', b'b', b'c', 0x00]);
+    b.extend_from_slice(&[0x00, 0x20, 0x00, 0x08, 0x00, 0x01]);
+    b.extend_from_slice(&port.to_be_bytes());
+    b.extend((0..4).map(|i| ip[i] ^ cookie[i]));
+    b
+}
+
+#[test]
+fn parses_binding_response() {
+    let req = stun::binding_request(&TRANSACTION_ID);
+    assert_eq!(req.len(), 20);
+    assert_eq!(&req[..8], &[0x00, 0x01, 0x00, 0x00, 0x21, 0x12, 0xa4, 0x42]);
+
+    let addr: SocketAddr = "192.0.2.1:32853".parse().unwrap();
+    let b = response(&TRANSACTION_ID, addr);
+    assert_eq!(stun::parse_response(&b, &TRANSACTION_ID).unwrap(), addr);
+
+    let mut other = TRANSACTION_ID;
+    other[0] ^= 1;
+    assert!(stun::parse_response(&b, &other).is_err());
+    assert!(stun::parse_response(&b[..24], &TRANSACTION_ID).is_err());
+}
+
+#[tokio::test]
+async fn cross_checks_the_eip() {
+    // answers every Binding request with 203.0.113.9 (e.g., a NAT gateway)
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let server_addr = server.local_addr().unwrap().to_string();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 512];
+        while let Ok((n, from)) = server.recv_from(&mut buf) {
+            let b = response(&buf[8..n.min(20)], "203.0.113.9:40000".parse().unwrap());
+            server.send_to(&b, from).unwrap();
+        }
+    });
+
+    let ctx = Context::default();
+    let (discovered, mismatch) = stun::check(&ctx, &server_addr, "203.0.113.9")
+        .await
+        .unwrap();
+    assert_eq!(discovered, "203.0.113.9");
+    assert!(mismatch.is_none());
+    assert_eq!(ctx.metrics.gauges()[stun::MISMATCH_GAUGE], 0.0);
+
+    stun::cross_check(&ctx, &server_addr, "198.51.100.7").await;
+    assert_eq!(ctx.metrics.gauges()[stun::MISMATCH_GAUGE], 1.0);
+    let s = summary::render(&ctx, "provision", &Ok(()), std::time::Duration::ZERO);
+    assert_eq!(s["resources"]["effective_public_ip"], "203.0.113.9");
+    let warnings = s["warnings"].as_array().unwrap();
+    assert!(
+        warnings[0]
+            .as_str()
+            .unwrap()
+            .contains("sees 203.0.113.9, not the EIP 198.51.100.7"),
+        "{warnings:?}"
+    );
+}
@@ -0,0 +1,128 @@
+//! Tests of the run summary ("--summary-path") and its schema.
+
+use std::{
+    env, fs,
+    io::{self, Error, ErrorKind},
+    process::ExitCode,
+    time::Duration,
+};
+
+use aws_ip_provisioner::{context::Context, summary};
+use serde_json::Value;
+
+fn keys(v: &Value) -> Vec<&str> {
+    let mut keys: Vec<&str> = v.as_object().unwrap().keys().map(|k| k.as_str()).collect();
+    keys.sort();
+    keys
+}
+
+fn strings(v: &Value) -> Vec<&str> {
+    let mut v: Vec<&str> = v
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_str().unwrap())
+        .collect();
+    v.sort();
+    v
+}
+
+#[test]
+fn summarizes_the_run() {
+    let ctx = Context::default();
+    ctx.summary.resource("instance_id", "i-local");
+    ctx.summary.resource("allocation_id", "eipalloc-1");
+    ctx.summary.resource("public_ip", "");
+    let mut history = summary::History::new(&ctx.summary, "imds");
+    let timed_out = Error::new(ErrorKind::TimedOut, "timed out");
+    history.retry(&timed_out, Duration::from_millis(200));
+    history.retry(&timed_out, Duration::from_millis(400));
+    ctx.summary.warn("IMDS hop limit is 1");
+
+    let file_path = env::temp_dir().join(format!(
+        "aws-ip-provisioner-summary-{}.json",
+        std::process::id()
+    ));
+    let file_path = file_path.to_str().unwrap();
+    summary::write(
+        &ctx,
+        file_path,
+        "provision",
+        &Ok(()),
+        Duration::from_millis(1500),
+    )
+    .unwrap();
+    let s: Value = serde_json::from_slice(&fs::read(file_path).unwrap()).unwrap();
+    fs::remove_file(file_path).unwrap();
+
+    assert_eq!(s["version"], summary::VERSION);
+    assert_eq!(s["outcome"], "succeeded");
+    assert_eq!(s["error"], "");
+    assert_eq!(s["resources"]["instance_id"], "i-local");
+    assert_eq!(s["resources"]["allocation_id"], "eipalloc-1");
+    // empty until known
+    assert_eq!(s["resources"]["public_ip"], "");
+    assert_eq!(s["retries"]["imds"], 2);
+    assert_eq!(s["attempts"]["imds"][0]["code"], "TimedOut");
+    assert_eq!(s["attempts"]["imds"][1]["backoff_seconds"], 0.4);
+    assert_eq!(s["warnings"][0], "IMDS hop limit is 1");
+    assert_eq!(s["durations"]["total"], 1.5);
+
+    let res: io::Result<()> = Err(Error::new(ErrorKind::TimedOut, "not ready"));
+    let s = summary::render(&ctx, "daemon", &res, Duration::ZERO);
+    assert_eq!(s["outcome"], "failed");
+    assert_eq!(s["error"], "not ready");
+
+    ctx.summary.skip("IPv6-only instance");
+    let s = summary::render(&ctx, "provision", &Ok(()), Duration::ZERO);
+    assert_eq!(s["outcome"], "skipped");
+    assert_eq!(s["skip_reason"], "IPv6-only instance");
+}
+
+#[test]
+fn surfaces_exhausted_retries() {
+    let ctx = Context::default();
+    let mut history = summary::History::new(&ctx.summary, "dns_verify");
+    history.retry(
+        &Error::new(ErrorKind::Other, "stale"),
+        Duration::from_secs(5),
+    );
+    let err = history.exhausted(Error::new(ErrorKind::TimedOut, "not propagated"));
+    assert_eq!(err.kind(), ErrorKind::TimedOut);
+    let msg = err.to_string();
+    assert!(msg.starts_with("not propagated"), "{msg}");
+    assert!(msg.contains("after 2 attempts"), "{msg}");
+    assert!(msg.contains("Other 'stale' backoff 5s"), "{msg}");
+    assert!(
+        msg.contains("TimedOut 'not propagated' backoff 0ns"),
+        "{msg}"
+    );
+    assert_eq!(
+        summary::exit_code(&Err(err)),
+        ExitCode::from(summary::EXIT_RETRIES_EXHAUSTED)
+    );
+
+    // never retried, as is
+    let err = summary::History::new(&ctx.summary, "imds")
+        .exhausted(Error::new(ErrorKind::Other, "refused"));
+    assert_eq!(err.to_string(), "refused");
+    assert_eq!(summary::exit_code(&Err(err)), ExitCode::FAILURE);
+    assert_eq!(summary::exit_code(&Ok(())), ExitCode::SUCCESS);
+}
+
+#[test]
+fn schema_matches_summary() {
+    let schema = summary::schema();
+    let s = summary::render(&Context::default(), "provision", &Ok(()), Duration::ZERO);
+
+    assert_eq!(keys(&s), strings(&schema["required"]));
+    assert_eq!(keys(&s), keys(&schema["properties"]));
+    assert_eq!(
+        keys(&s["resources"]),
+        strings(&schema["properties"]["resources"]["required"])
+    );
+    assert_eq!(
+        keys(&s["durations"]),
+        strings(&schema["properties"]["durations"]["required"])
+    );
+}
@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+
+use aws_ip_provisioner::tags::{self, Diff};
+
+fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn parses_desired_tags() {
+    assert_eq!(
+        tags::parse(" Team=infra, CostCenter=1234,Empty= ,").unwrap(),
+        map(&[("Team", "infra"), ("CostCenter", "1234"), ("Empty", "")])
+    );
+    assert!(tags::parse("").unwrap().is_empty());
+    assert!(tags::parse("Team").is_err());
+    assert!(tags::parse("Team=a,Team=b").is_err());
+
+    // the tool's own tags
+    let err = tags::parse("Team=infra,Name=x").unwrap_err();
+    assert!(
+        err.to_string().contains("'Name' is set by the tool"),
+        "{err}"
+    );
+    assert!(tags::parse("aws:cloudformation:stack-name=s").is_err());
+    assert_eq!(tags::pairs("Name=x").unwrap(), map(&[("Name", "x")]));
+}
+
+#[test]
+fn converges_to_desired_tags() {
+    let current = map(&[
+        ("Id", "node-1"),
+        ("Kind", "ip-manager"),
+        ("ClientToken", "abc"),
+        ("aws:cloudformation:stack-name", "s"),
+        ("Team", "old"),
+        ("Owner", "someone"),
+        ("CostCenter", "1234"),
+    ]);
+    let desired = map(&[("Team", "infra"), ("CostCenter", "1234"), ("Env", "prod")]);
+
+    let diff = tags::diff(&current, &desired, false, &["Id", "Kind"]);
+    assert_eq!(
+        diff,
+        Diff {
+            upsert: vec![
+                ("Env".to_string(), "prod".to_string()),
+                ("Team".to_string(), "infra".to_string()),
+            ],
+            remove: Vec::new(),
+        }
+    );
+
+    // only the unknown "Owner" goes, the tool's own and "aws:" tags stay
+    let diff = tags::diff(&current, &desired, true, &["Id", "Kind"]);
+    assert_eq!(diff.remove, vec!["Owner".to_string()]);
+
+    let converged = map(&[
+        ("Id", "node-1"),
+        ("Kind", "ip-manager"),
+        ("Team", "infra"),
+        ("CostCenter", "1234"),
+        ("Env", "prod"),
+    ]);
+    assert!(tags::diff(&converged, &desired, true, &["Id", "Kind"]).is_empty());
+
+    // never overwrites the kept or the reserved tags
+    let desired = map(&[("Id", "node-2"), ("ClientToken", "x"), ("Team", "infra")]);
+    let diff = tags::diff(&current, &desired, false, &["Id", "Kind"]);
+    assert_eq!(diff.upsert, vec![("Team".to_string(), "infra".to_string())]);
+}
@@ -0,0 +1,75 @@
+//! Tests of the node_exporter textfile collector output ("--metrics-textfile-dir").
+
+use std::{
+    env, fs,
+    io::{Error, ErrorKind},
+    time::Duration,
+};
+
+use aws_ip_provisioner::{context::Context, textfile};
+use serde_json::json;
+
+#[test]
+fn renders_the_run() {
+    let ctx = Context::default();
+    ctx.metrics.set_gauge("pool_free_addresses", 3.0);
+    ctx.metrics.inc_counter("eip_reassociated_total");
+    let s = textfile::render(
+        &ctx,
+        &json!({
+            "mode": "provision",
+            "outcome": "failed",
+            "finished_at": 1673000000,
+            "durations": {"phases": {"imds": 0.021, "allocate": 1.5}, "total": 4.25},
+            "retries": {"imds": 2},
+        }),
+    );
+    let lines: Vec<&str> = s.lines().filter(|l| !l.starts_with('#')).collect();
+    for expected in [
+        "aws_ip_provisioner_last_run_outcome{mode=\"provision\",outcome=\"succeeded\"} 0",
+        "aws_ip_provisioner_last_run_outcome{mode=\"provision\",outcome=\"failed\"} 1",
+        "aws_ip_provisioner_last_run_timestamp_seconds 1673000000",
+        "aws_ip_provisioner_last_run_duration_seconds 4.25",
+        "aws_ip_provisioner_last_run_phase_duration_seconds{phase=\"allocate\"} 1.5",
+        "aws_ip_provisioner_last_run_phase_duration_seconds{phase=\"imds\"} 0.021",
+        "aws_ip_provisioner_last_run_retries{operation=\"imds\"} 2",
+        "aws_ip_provisioner_pool_free_addresses 3",
+        "aws_ip_provisioner_eip_reassociated_total 1",
+    ] {
+        assert!(lines.contains(&expected), "missing {expected} in\n{s}");
+    }
+    assert!(s.contains("# TYPE aws_ip_provisioner_eip_reassociated_total counter\n"));
+
+    // no retries, no family
+    let s = textfile::render(&ctx, &json!({"mode": "provision", "outcome": "succeeded"}));
+    assert!(!s.contains("last_run_retries"));
+}
+
+#[test]
+fn writes_the_file() {
+    let dir = env::temp_dir().join(format!("textfile-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let dir_path = dir.to_str().unwrap();
+
+    let ctx = Context::default();
+    textfile::write(
+        &ctx,
+        dir_path,
+        "internal-vip",
+        "provision",
+        &Err(Error::new(ErrorKind::Other, "denied")),
+        Duration::from_secs(1),
+    )
+    .unwrap();
+    let mut files: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    assert_eq!(files, vec!["internal-vip-aws_ip_provisioner.prom"]);
+    let s = fs::read_to_string(dir.join(&files[0])).unwrap();
+    assert!(s.contains("outcome=\"failed\"} 1\n"));
+
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(textfile::write(&ctx, dir_path, "", "provision", &Ok(()), Duration::ZERO).is_err());
+}
@@ -0,0 +1,22 @@
+use std::{env, fs, io::ErrorKind};
+
+use aws_ip_provisioner::tls;
+
+#[test]
+fn rejects_ca_bundle_without_certificates() {
+    let path = env::temp_dir().join(format!("tls-test-{}.pem", std::process::id()));
+    fs::write(&path, "not a certificate\n").unwrap();
+    let err = tls::root_store(path.to_str().unwrap()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    fs::remove_file(&path).unwrap();
+
+    let err = tls::root_store("/nonexistent/ca-bundle.pem").unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn builds_connector_without_ca_bundle() {
+    // never panics, even without the system certificates
+    let _ = tls::https_connector();
+    assert!(tls::client_config("").is_ok());
+}
@@ -0,0 +1,71 @@
+use std::fs;
+
+use aws_ip_provisioner::{ec2, state, transfer};
+
+#[test]
+fn carries_over_tags_but_reserved() {
+    let tags = vec![
+        ("Id".to_string(), "node-1".to_string()),
+        (
+            "aws:cloudformation:stack-name".to_string(),
+            "old".to_string(),
+        ),
+        ("Kind".to_string(), "mainnet".to_string()),
+    ];
+    let params = transfer::accept_params("203.0.113.7", &tags);
+    let get = |k: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == k)
+            .map(|(_, v)| v.as_str())
+    };
+    assert_eq!(get("Action"), Some("AcceptAddressTransfer"));
+    assert_eq!(get("Address"), Some("203.0.113.7"));
+    assert_eq!(get("TagSpecification.1.ResourceType"), Some("elastic-ip"));
+    assert_eq!(get("TagSpecification.1.Tag.1.Key"), Some("Id"));
+    assert_eq!(get("TagSpecification.1.Tag.2.Key"), Some("Kind"));
+    assert_eq!(get("TagSpecification.1.Tag.2.Value"), Some("mainnet"));
+    assert_eq!(get("TagSpecification.1.Tag.3.Key"), None);
+
+    // no tag specification without tags
+    let params = transfer::accept_params("203.0.113.7", &[]);
+    assert_eq!(params.len(), 2);
+}
+
+#[test]
+fn rewrites_state_to_new_allocation_id() {
+    let dir = std::env::temp_dir().join(format!("transfer-state-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file_path = dir.join("eip.yaml").display().to_string();
+    state::record_eip(
+        &file_path,
+        &ec2::Eip {
+            allocation_id: "eipalloc-old".to_string(),
+            public_ip: "203.0.113.7".to_string(),
+        },
+    )
+    .unwrap();
+    state::record_private_ip_address(&file_path, "10.0.0.5", "i-1").unwrap();
+
+    transfer::rewrite_state(&file_path, "203.0.113.7", "eipalloc-new").unwrap();
+    let s = state::State::load(&file_path).unwrap();
+    assert_eq!(s.allocation_id, "eipalloc-new");
+    assert_eq!(s.public_ip, "203.0.113.7");
+    assert_eq!(s.private_ip_address, "10.0.0.5");
+
+    // another EIP's file is left as is
+    assert!(transfer::rewrite_state(&file_path, "198.51.100.1", "eipalloc-other").is_err());
+    assert_eq!(
+        state::load_eip(&file_path).unwrap().allocation_id,
+        "eipalloc-new"
+    );
+
+    // created if missing
+    let missing = dir.join("missing.yaml").display().to_string();
+    transfer::rewrite_state(&missing, "203.0.113.7", "eipalloc-new").unwrap();
+    assert_eq!(
+        state::load_eip(&missing).unwrap().allocation_id,
+        "eipalloc-new"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,262 @@
+//! Tests of the offline validation of the flags, config file, and state file,
+//! and of their schemas.
+
+mod common;
+
+use std::fs;
+
+use aws_ip_provisioner::{config, ec2, eip, state::State, validate};
+use serde_json::{json, Value};
+
+#[test]
+fn accepts_default_flags() {
+    assert_eq!(validate::flags(&common::flags(&[])), Vec::<String>::new());
+}
+
+#[test]
+fn checks_namespace() {
+    let opts = common::flags(&["--namespace=internal-vip"]);
+    assert_eq!(opts.id_tag_value, "internal-vip-node-1");
+    assert_eq!(opts.mounted_eip_file_path, "/data/internal-vip-eip.yaml");
+    assert_eq!(validate::flags(&opts), Vec::<String>::new());
+
+    let problems = validate::flags(&common::flags(&["--namespace=vip/1"]));
+    assert_eq!(problems.len(), 1, "{problems:#?}");
+    assert!(problems[0].starts_with("--namespace 'vip/1' must be alphanumeric"));
+}
+
+#[test]
+fn checks_desired_tags() {
+    let opts = common::flags(&["--desired-tags=Team=infra,CostCenter=1234"]);
+    assert_eq!(validate::flags(&opts), Vec::<String>::new());
+
+    let problems = validate::flags(&common::flags(&[
+        "--desired-tags=Id=x,ClientToken=y,Team=a#b",
+    ]));
+    assert_eq!(problems.len(), 3, "{problems:#?}");
+    assert!(problems[0].contains("'ClientToken' is set by the tool"));
+    assert!(problems[1].contains("'Id' conflicts with --id-tag-key"));
+    assert!(problems[2].contains("'Team=a#b' has invalid character '#'"));
+}
+
+#[test]
+fn checks_security_group_sync() {
+    let opts = common::flags(&[
+        "--sync-security-group-id=sg-0123456789abcdef0",
+        "--port-ranges=tcp:30303,udp:30303",
+    ]);
+    assert_eq!(validate::flags(&opts), Vec::<String>::new());
+
+    let problems = validate::flags(&common::flags(&["--sync-security-group-id=0123"]));
+    assert_eq!(
+        problems,
+        vec![
+            String::from("--sync-security-group-id '0123' must start with 'sg-'"),
+            String::from("--sync-security-group-id requires --port-ranges"),
+        ]
+    );
+    let problems = validate::flags(&common::flags(&["--port-ranges=tcp:22"]));
+    assert_eq!(
+        problems,
+        vec![String::from(
+            "--port-ranges requires --sync-security-group-id"
+        )]
+    );
+}
+
+#[test]
+fn reports_every_flag_problem() {
+    let dir = common::test_dir("flags");
+    let rules = dir.join("rules");
+    fs::write(
+        &rules,
+        "# allow the office\nfilter INPUT -s 10.0.0.0/33 -d {private_ip}/32 -j ACCEPT\nfilter INPUT -s 10.0.0.256 -j ACCEPT\nfilter INPUT -s 192.0.2.0/24 -p tcp --dport 443 -j ACCEPT\n",
+    )
+    .unwrap();
+
+    let problems = validate::flags(&common::flags(&[
+        "--id-tag-key=aws:id",
+        "--kind-tag-value=bad#value",
+        "--web-identity-token-file=/does/not/exist",
+        "--allocation-role-arn=arn:aws:iam::123:role/x",
+        "--lifecycle-hook-name=hook",
+        "--firewall-backend=iptables",
+        &format!("--firewall-rules-file={}", rules.display()),
+        "--endpoint-url=localhost:4566",
+        "--route53-private-zone-id=Z2",
+        "--route53-routing=weighted:300",
+    ]));
+    let expected = [
+        "--id-tag-key 'aws:id' must not start",
+        "--kind-tag-value 'bad#value' has invalid character '#'",
+        "--web-identity-token-file requires --role-arn",
+        "--web-identity-token-file '/does/not/exist' does not exist",
+        "--allocation-role-arn 'arn:aws:iam::123:role/x' is not an IAM role ARN",
+        "--lifecycle-hook-name requires --mode=terminate-hook",
+        ":2 '10.0.0.0/33' has an invalid prefix length",
+        ":3 '10.0.0.256' is not a valid address",
+        "--endpoint-url 'localhost:4566' is not an http(s) URL",
+        "--route53-private-zone-id and --route53-private-record-name require each other",
+        "--route53-routing invalid Route53 routing 'weighted:300'",
+        "--route53-routing and --route53-health-check require --route53-zone-id",
+    ];
+    for e in expected {
+        assert!(
+            problems.iter().any(|p| p.contains(e)),
+            "missing {e:?} in {problems:#?}"
+        );
+    }
+    assert_eq!(problems.len(), expected.len(), "{problems:#?}");
+}
+
+#[test]
+fn checks_config_file() {
+    let dir = common::test_dir("config");
+    let config_file = dir.join("config.json");
+    let opts = common::flags(&[]);
+
+    fs::write(
+        &config_file,
+        r#"{"reconcile-interval-seconds": 60, "kind-tag-value": "prod"}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        validate::config_file(&opts, config_file.to_str().unwrap()),
+        Vec::<String>::new()
+    );
+
+    fs::write(&config_file, r#"{"kind-tag-key": "aws:kind"}"#).unwrap();
+    let problems = validate::config_file(&opts, config_file.to_str().unwrap());
+    assert_eq!(problems.len(), 1, "{problems:#?}");
+    assert!(problems[0].contains("--kind-tag-key 'aws:kind'"));
+
+    fs::write(&config_file, r#"{"mode": "daemon"}"#).unwrap();
+    let problems = validate::config_file(&opts, config_file.to_str().unwrap());
+    assert!(problems[0].contains("'mode' is unknown"), "{problems:#?}");
+
+    // the reloaded "Kind" tag value is namespaced as the flag is
+    let mut opts = common::flags(&["--namespace=internal-vip"]);
+    assert_eq!(opts.kind_tag_value, "internal-vip-test");
+    fs::write(&config_file, r#"{"kind-tag-value": "prod"}"#).unwrap();
+    config::apply(&mut opts, config_file.to_str().unwrap()).unwrap();
+    assert_eq!(opts.kind_tag_value, "internal-vip-prod");
+}
+
+#[test]
+fn checks_state_file() {
+    let dir = common::test_dir("state");
+    let state_file = dir.join("eip.yaml");
+    let path = state_file.to_str().unwrap();
+
+    assert!(validate::state_file(path)[0].contains("does not exist"));
+
+    fs::write(
+        &state_file,
+        "allocation_id: eipalloc-0123abcd\npublic_ip: 203.0.113.7\n",
+    )
+    .unwrap();
+    assert_eq!(validate::state_file(path), Vec::<String>::new());
+
+    fs::write(
+        &state_file,
+        "allocation_id: alloc-1\npublic_ip: 203.0.113\n",
+    )
+    .unwrap();
+    let problems = validate::state_file(path);
+    assert_eq!(problems.len(), 2, "{problems:#?}");
+
+    fs::write(&state_file, "not: [yaml").unwrap();
+    assert!(validate::state_file(path)[0].contains("is not valid"));
+}
+
+#[test]
+fn config_schema_matches_reloadable_keys() {
+    let schema = config::schema();
+    let mut sample = serde_json::Map::new();
+    for (key, prop) in schema["properties"].as_object().unwrap() {
+        let v = if let Some(values) = prop["enum"].as_array() {
+            values[0].clone()
+        } else if let Some(examples) = prop["examples"].as_array() {
+            examples[0].clone()
+        } else {
+            match prop["type"].as_str().unwrap() {
+                "integer" => json!(1),
+                "boolean" => json!(true),
+                _ => json!("x"),
+            }
+        };
+        sample.insert(key.clone(), v);
+    }
+
+    let dir = common::test_dir("schema");
+    let config_file = dir.join("config.json");
+    fs::write(&config_file, Value::Object(sample).to_string()).unwrap();
+    let mut opts = common::flags(&[]);
+    let changed = config::apply(&mut opts, config_file.to_str().unwrap()).unwrap();
+    assert!(!changed.is_empty());
+}
+
+#[test]
+fn state_schema_matches_state_file() {
+    let schema = eip::state_schema();
+    let state = serde_json::to_value(ec2::Eip {
+        allocation_id: String::from("eipalloc-0123abcd"),
+        public_ip: String::from("203.0.113.7"),
+    })
+    .unwrap();
+    let mut fields: Vec<&str> = state
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(|k| k.as_str())
+        .collect();
+    let mut required: Vec<&str> = schema["required"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_str().unwrap())
+        .collect();
+    fields.sort();
+    required.sort();
+    assert_eq!(fields, required);
+
+    // the rest are recorded next to the "ec2::Eip" fields
+    let mut properties: Vec<&str> = schema["properties"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(|k| k.as_str())
+        .collect();
+    properties.sort();
+    let full = serde_json::to_value(State {
+        allocation_id: String::from("eipalloc-0123abcd"),
+        public_ip: String::from("203.0.113.7"),
+        account_id: String::from("111122223333"),
+        region: String::from("us-west-2"),
+        private_ip_address: String::from("10.0.0.5"),
+        private_ip_instance_id: String::from("i-0123abcd"),
+        ipv6_address: String::from("2600:1f14::5"),
+        association_id: String::from("eipassoc-0123abcd"),
+        instance_id: String::from("i-0123abcd"),
+        network_interface_id: String::from("eni-0123abcd"),
+        association_private_ip_address: String::from("10.0.0.5"),
+        network_border_group: String::from("us-west-2"),
+        tags: [(String::from("Kind"), String::from("test"))].into(),
+        allocated_at: 1,
+        associated_at: 2,
+        updated_at: 3,
+        tool_version: String::from("0.0.0"),
+        extra: Default::default(),
+    })
+    .unwrap();
+    let mut fields: Vec<&str> = full
+        .as_object()
+        .unwrap()
+        .keys()
+        .map(|k| k.as_str())
+        .collect();
+    fields.sort();
+    assert_eq!(fields, properties);
+    assert!(fields.contains(&"private_ip_address"));
+    assert!(fields.contains(&"ipv6_address"));
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use std::{env, fs};
+
+use aws_ip_provisioner::wireguard::{self, Target};
+
+const CONFIG: &str = "[Interface]
+PrivateKey = cGVlcg==
+ListenPort = 51820
+
+[Peer]
+# node1
+PublicKey = bm9kZTE=
+AllowedIPs = 10.8.0.1/32
+Endpoint = 198.51.100.1:51820
+
+[Peer]
+# node2
+PublicKey = bm9kZTI=
+AllowedIPs = 10.8.0.2/32
+";
+
+#[test]
+fn parses_targets() {
+    assert_eq!(
+        wireguard::parse("ssh://root@10.0.1.5, ssh://peer:2222,file:/etc/wireguard/wg0.conf,https://mesh/v1/endpoints").unwrap(),
+        vec![
+            Target::Ssh {
+                destination: "root@10.0.1.5".to_string(),
+                port: 22
+            },
+            Target::Ssh {
+                destination: "peer".to_string(),
+                port: 2222
+            },
+            Target::File("/etc/wireguard/wg0.conf".to_string()),
+            Target::Api("https://mesh/v1/endpoints".to_string()),
+        ]
+    );
+    assert!(wireguard::parse("").unwrap().is_empty());
+    assert!(wireguard::parse("ssh://root@").is_err());
+    assert!(wireguard::parse("ssh://peer:x").is_err());
+    assert!(wireguard::parse("10.0.1.5").is_err());
+}
+
+#[test]
+fn sets_the_endpoint_of_the_peer() {
+    // replaces the endpoint of node1, leaving the others alone
+    let (updated, changed) =
+        wireguard::set_endpoint(CONFIG, "bm9kZTE=", "203.0.113.7:51820").unwrap();
+    assert!(changed);
+    assert!(updated.contains("AllowedIPs = 10.8.0.1/32\nEndpoint = 203.0.113.7:51820\n"));
+    assert!(!updated.contains("198.51.100.1"));
+    assert!(updated.ends_with("AllowedIPs = 10.8.0.2/32\n"));
+
+    // adds the endpoint to the peer without one
+    let (updated, changed) =
+        wireguard::set_endpoint(CONFIG, "bm9kZTI=", "203.0.113.7:51820").unwrap();
+    assert!(changed);
+    assert!(updated.contains("[Peer]\nEndpoint = 203.0.113.7:51820\n# node2\n"));
+
+    let (same, changed) =
+        wireguard::set_endpoint(CONFIG, "bm9kZTE=", "198.51.100.1:51820").unwrap();
+    assert!(!changed);
+    assert_eq!(same, CONFIG);
+
+    assert!(wireguard::set_endpoint(CONFIG, "bm9kZTM=", "203.0.113.7:51820").is_err());
+}
+
+#[tokio::test]
+async fn updates_the_peers() {
+    let path = env::temp_dir().join(format!("wg0-{}.conf", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    fs::write(&path, CONFIG).unwrap();
+    wireguard::update_peer(
+        &Target::File(path.clone()),
+        "wg0",
+        "bm9kZTE=",
+        "203.0.113.7:51820",
+    )
+    .await
+    .unwrap();
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .contains("Endpoint = 203.0.113.7:51820"));
+    fs::remove_file(&path).unwrap();
+
+    // keeps the mode of the config with the private key
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::write(&path, CONFIG).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        wireguard::update_peer(
+            &Target::File(path.clone()),
+            "wg0",
+            "bm9kZTI=",
+            "203.0.113.8:51820",
+        )
+        .await
+        .unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        fs::remove_file(&path).unwrap();
+    }
+
+    let (url, requests) = common::serve_raw("/v1/endpoints", "204 No Content", "");
+    wireguard::update_peer(&Target::Api(url), "wg0", "bm9kZTE=", "203.0.113.7:51820")
+        .await
+        .unwrap();
+    let req = requests.recv().unwrap();
+    assert!(req.head.starts_with("POST /v1/endpoints "), "{req:?}");
+    assert_eq!(
+        String::from_utf8(req.body).unwrap(),
+        r#"{"interface":"wg0","public_key":"bm9kZTE=","endpoint":"203.0.113.7:51820"}"#
+    );
+}
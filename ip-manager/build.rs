@@ -0,0 +1,93 @@
+//! Records the build info reported by "ip-manager version --verbose"
+//! and the "/buildinfo" endpoint of "ip-manager serve".
+
+use std::{
+    env,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-env-changed=GITHUB_SHA");
+
+    let git_commit = output("git", &["rev-parse", "HEAD"])
+        .or_else(|| env::var("GITHUB_SHA").ok())
+        .unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=IP_MANAGER_GIT_COMMIT={git_commit}");
+    // re-run on new commits, without re-running on every build
+    if let Some(git_dir) = output("git", &["rev-parse", "--absolute-git-dir"]) {
+        let head = Path::new(&git_dir).join("HEAD");
+        println!("cargo:rerun-if-changed={}", head.display());
+        if let Some(r) = std::fs::read_to_string(&head)
+            .ok()
+            .and_then(|v| v.strip_prefix("ref: ").map(|r| r.trim().to_string()))
+        {
+            let ref_path = Path::new(&git_dir).join(r);
+            if ref_path.exists() {
+                println!("cargo:rerun-if-changed={}", ref_path.display());
+            }
+        }
+    }
+
+    // reproducible builds set the timestamp
+    // ref. <https://reproducible-builds.org/docs/source-date-epoch/>
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    println!(
+        "cargo:rustc-env=IP_MANAGER_BUILD_TIMESTAMP={}",
+        rfc3339(secs)
+    );
+
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let rustc_version = output(&rustc, &["--version"]).unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=IP_MANAGER_RUSTC_VERSION={rustc_version}");
+    println!(
+        "cargo:rustc-env=IP_MANAGER_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+}
+
+/// Returns the trimmed stdout of the command, or "None" if it fails.
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    let v = String::from_utf8_lossy(&out.stdout).trim().to_string();
+    if v.is_empty() {
+        None
+    } else {
+        Some(v)
+    }
+}
+
+/// Formats the Unix seconds as UTC RFC 3339 (e.g., "2023-01-02T03:04:05Z").
+/// ref. <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn rfc3339(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
@@ -0,0 +1,556 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Error, ErrorKind},
+    net::IpAddr,
+    path::Path,
+    process::Command as Process,
+};
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+
+use crate::{
+    iface,
+    provider::{self, Address, BoxFuture, Provider},
+};
+
+pub const NAME: &str = "bgp";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages self-hosted addresses announced via BGP")
+        .subcommand_required(true)
+        .subcommand(
+            with_args(
+                Command::new("announce")
+                    .about("Announces the address from the local host via BGP")
+                    .long_about(
+                        "
+
+Instead of calling a cloud API, announces the address as a host route
+through the local BGP speaker, for on-prem floating IPs and anycast.
+
+With \"--speaker=bird\", renders the static routes (and the peer session if
+\"--local-as\", \"--peer-as\", and \"--peer-address\" are set) into \"--bird-config-path\",
+which the main BIRD 2 config must include, and reloads with \"birdc configure\".
+
+With \"--speaker=gobgp\", adds the route to the global RIB of the running gobgpd,
+whose peers are configured separately.
+
+The address is recorded in the state file, so that the next run on the same volume
+announces the same address. \"ip-manager bgp withdraw\" withdraws it on release.
+
+e.g.,
+
+$ ip-manager bgp announce \
+--address=192.0.2.10 \
+--speaker=bird \
+--bird-config-path=/etc/bird/ip-manager.conf \
+--local-as=65001 \
+--peer-as=65000 \
+--peer-address=10.0.0.1 \
+--configure-interface=lo \
+--state-file-path=/data/bgp-ip.yaml
+
+",
+                    )
+                    .arg(
+                        Arg::new("ADDRESS")
+                            .long("address")
+                            .help("Sets the address to announce (e.g., 192.0.2.10, announced as /32 or /128)")
+                            .required(true)
+                            .num_args(1),
+                    )
+                    .arg(
+                        Arg::new("ROUTER_ID")
+                            .long("router-id")
+                            .help("Sets the ID of the local host to record the announcement (empty to use the hostname)")
+                            .required(false)
+                            .num_args(1),
+                    )
+                    .arg(
+                        Arg::new("NO_STEAL")
+                            .long("no-steal")
+                            .help("Aborts rather than announcing the address that is announced by another host (only the local speaker is checked)")
+                            .required(false)
+                            .num_args(1)
+                            .value_parser(value_parser!(bool))
+                            .default_value("true"),
+                    ),
+            ),
+        )
+        .subcommand(with_args(
+            Command::new("withdraw")
+                .about("Withdraws the address announced by \"ip-manager bgp announce\"")
+                .arg(
+                    Arg::new("ADDRESS")
+                        .long("address")
+                        .help("Sets the address to withdraw (empty to read from the state file)")
+                        .required(false)
+                        .num_args(1),
+                ),
+        ))
+}
+
+/// Adds the flags shared by "announce" and "withdraw",
+/// so that "withdraw" renders the same BIRD peer session.
+fn with_args(cmd: Command) -> Command {
+    cmd.arg(
+        Arg::new("SPEAKER")
+            .long("speaker")
+            .help("Sets the local BGP speaker to drive")
+            .required(false)
+            .num_args(1)
+            .value_parser(["bird", "gobgp"])
+            .default_value("bird"),
+    )
+    .arg(
+        Arg::new("BIRD_CONFIG_PATH")
+            .long("bird-config-path")
+            .help("Sets the BIRD config file to render (included by the main BIRD config)")
+            .required(false)
+            .num_args(1)
+            .default_value("/etc/bird/ip-manager.conf"),
+    )
+    .arg(
+        Arg::new("BIRDC_PATH")
+            .long("birdc-path")
+            .help("Sets the birdc executable")
+            .required(false)
+            .num_args(1)
+            .default_value("birdc"),
+    )
+    .arg(
+        Arg::new("GOBGP_PATH")
+            .long("gobgp-path")
+            .help("Sets the gobgp executable")
+            .required(false)
+            .num_args(1)
+            .default_value("gobgp"),
+    )
+    .arg(
+        Arg::new("LOCAL_AS")
+            .long("local-as")
+            .help("Sets the local AS number of the rendered BIRD peer session (0 to skip rendering the session)")
+            .required(false)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .default_value("0"),
+    )
+    .arg(
+        Arg::new("PEER_AS")
+            .long("peer-as")
+            .help("Sets the peer AS number of the rendered BIRD peer session")
+            .required(false)
+            .num_args(1)
+            .value_parser(value_parser!(u32))
+            .default_value("0"),
+    )
+    .arg(
+        Arg::new("PEER_ADDRESS")
+            .long("peer-address")
+            .help("Sets the peer address of the rendered BIRD peer session")
+            .required(false)
+            .num_args(1),
+    )
+    .arg(
+        Arg::new("CONFIGURE_INTERFACE")
+            .long("configure-interface")
+            .help("Sets the network interface to add the address to, so that the host accepts its traffic (empty to skip)")
+            .required(false)
+            .num_args(1)
+            .default_value("lo"),
+    )
+    .arg(
+        Arg::new("STATE_FILE_PATH")
+            .long("state-file-path")
+            .help("Sets the file path to store the address information mapped to this volume path")
+            .required(false)
+            .num_args(1)
+            .default_value("/data/bgp-ip.yaml"),
+    )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub address: String,
+    pub speaker: String,
+    pub bird_config_path: String,
+    pub birdc_path: String,
+    pub gobgp_path: String,
+    pub local_as: u32,
+    pub peer_as: u32,
+    pub peer_address: String,
+    pub router_id: String,
+    pub configure_interface: String,
+    pub no_steal: bool,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    // "withdraw" does not define the announce-only flags
+    let get = |id: &str| -> String {
+        matches
+            .try_get_one::<String>(id)
+            .ok()
+            .flatten()
+            .cloned()
+            .unwrap_or_default()
+    };
+    Flags {
+        address: get("ADDRESS"),
+        speaker: matches
+            .get_one::<String>("SPEAKER")
+            .unwrap_or(&String::from("bird"))
+            .clone(),
+        bird_config_path: matches
+            .get_one::<String>("BIRD_CONFIG_PATH")
+            .unwrap_or(&String::from("/etc/bird/ip-manager.conf"))
+            .clone(),
+        birdc_path: matches
+            .get_one::<String>("BIRDC_PATH")
+            .unwrap_or(&String::from("birdc"))
+            .clone(),
+        gobgp_path: matches
+            .get_one::<String>("GOBGP_PATH")
+            .unwrap_or(&String::from("gobgp"))
+            .clone(),
+        local_as: *matches.get_one::<u32>("LOCAL_AS").unwrap_or(&0),
+        peer_as: *matches.get_one::<u32>("PEER_AS").unwrap_or(&0),
+        peer_address: matches
+            .get_one::<String>("PEER_ADDRESS")
+            .unwrap_or(&String::new())
+            .clone(),
+        router_id: get("ROUTER_ID"),
+        configure_interface: matches
+            .get_one::<String>("CONFIGURE_INTERFACE")
+            .unwrap_or(&String::from("lo"))
+            .clone(),
+        no_steal: matches
+            .try_get_one::<bool>("NO_STEAL")
+            .ok()
+            .flatten()
+            .copied()
+            .unwrap_or(true),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/bgp-ip.yaml"))
+            .clone(),
+    }
+}
+
+/// Announces the address, recording it in the state file.
+pub async fn announce(opts: Flags) -> io::Result<Address> {
+    let ip = parse_ip(&opts.address)?;
+    let router_id = if opts.router_id.is_empty() {
+        hostname()?
+    } else {
+        opts.router_id.clone()
+    };
+    let bgp = Bgp {
+        speaker: Speaker::new(&opts)?,
+        router_id,
+        ip,
+    };
+    let addr = provider::provision(&bgp, &opts.state_file_path, opts.no_steal).await?;
+
+    if !opts.configure_interface.is_empty() {
+        iface::add_address(&opts.configure_interface, &host_prefix(&addr.ip)?)?;
+    }
+    Ok(addr)
+}
+
+/// Withdraws the address and removes the state file, releasing it for other hosts.
+pub async fn withdraw(opts: Flags) -> io::Result<Address> {
+    let ip = if opts.address.is_empty() {
+        Address::load(&opts.state_file_path)?.ip
+    } else {
+        opts.address.clone()
+    };
+    let ip = parse_ip(&ip)?;
+    let speaker = Speaker::new(&opts)?;
+    speaker.withdraw(&ip)?;
+
+    if !opts.configure_interface.is_empty() {
+        iface::remove_address(&opts.configure_interface, &host_prefix(&ip.to_string())?)?;
+    }
+    if Path::new(&opts.state_file_path).exists() {
+        log::info!("removing state file {}", opts.state_file_path);
+        fs::remove_file(&opts.state_file_path)?;
+    }
+    Ok(to_address(&ip))
+}
+
+fn parse_ip(s: &str) -> io::Result<IpAddr> {
+    let s = s
+        .strip_suffix("/32")
+        .or_else(|| s.strip_suffix("/128"))
+        .unwrap_or(s);
+    s.parse().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid address '{s}' ({})", e),
+        )
+    })
+}
+
+/// Returns the host route of the address (e.g., "192.0.2.10/32").
+fn host_prefix(ip: &str) -> io::Result<String> {
+    Ok(match parse_ip(ip)? {
+        IpAddr::V4(ip) => format!("{ip}/32"),
+        IpAddr::V6(ip) => format!("{ip}/128"),
+    })
+}
+
+fn to_address(ip: &IpAddr) -> Address {
+    Address {
+        provider: NAME.to_string(),
+        id: ip.to_string(),
+        ip: ip.to_string(),
+    }
+}
+
+fn hostname() -> io::Result<String> {
+    let name = fs::read_to_string("/etc/hostname")?;
+    Ok(name.trim().to_string())
+}
+
+/// Local BGP speaker to announce the routes from.
+enum Speaker {
+    Bird {
+        config_path: String,
+        birdc_path: String,
+        session: Option<Session>,
+    },
+    Gobgp {
+        gobgp_path: String,
+    },
+}
+
+/// BGP peer session rendered in the BIRD config.
+struct Session {
+    local_as: u32,
+    peer_as: u32,
+    peer_address: IpAddr,
+}
+
+/// Names of the BIRD protocols in the rendered config.
+const BIRD_STATIC4: &str = "ip_manager4";
+const BIRD_STATIC6: &str = "ip_manager6";
+const BIRD_PEER: &str = "ip_manager_peer";
+
+impl Speaker {
+    fn new(opts: &Flags) -> io::Result<Self> {
+        match opts.speaker.as_str() {
+            "bird" => {
+                let session = if opts.local_as > 0 || opts.peer_as > 0 {
+                    if opts.local_as == 0 || opts.peer_as == 0 || opts.peer_address.is_empty() {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            "peer session requires --local-as, --peer-as, and --peer-address",
+                        ));
+                    }
+                    Some(Session {
+                        local_as: opts.local_as,
+                        peer_as: opts.peer_as,
+                        peer_address: parse_ip(&opts.peer_address)?,
+                    })
+                } else {
+                    None
+                };
+                Ok(Speaker::Bird {
+                    config_path: opts.bird_config_path.clone(),
+                    birdc_path: opts.birdc_path.clone(),
+                    session,
+                })
+            }
+            "gobgp" => Ok(Speaker::Gobgp {
+                gobgp_path: opts.gobgp_path.clone(),
+            }),
+            s => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown speaker '{s}'"),
+            )),
+        }
+    }
+
+    fn announced(&self, ip: &IpAddr) -> io::Result<bool> {
+        match self {
+            Speaker::Bird { config_path, .. } => Ok(bird_routes(config_path)?.contains(ip)),
+            Speaker::Gobgp { gobgp_path } => {
+                let prefix = host_prefix(&ip.to_string())?;
+                let out = run(gobgp_path, &["global", "rib", "-a", family(ip), &prefix])?;
+                Ok(out.contains(&prefix))
+            }
+        }
+    }
+
+    fn announce(&self, ip: &IpAddr) -> io::Result<()> {
+        log::info!("announcing {ip}");
+        match self {
+            Speaker::Bird { config_path, .. } => {
+                let mut routes = bird_routes(config_path)?;
+                routes.insert(*ip);
+                self.reload_bird(&routes)
+            }
+            Speaker::Gobgp { gobgp_path } => {
+                let prefix = host_prefix(&ip.to_string())?;
+                run(
+                    gobgp_path,
+                    &["global", "rib", "-a", family(ip), "add", &prefix],
+                )?;
+                Ok(())
+            }
+        }
+    }
+
+    fn withdraw(&self, ip: &IpAddr) -> io::Result<()> {
+        log::info!("withdrawing {ip}");
+        match self {
+            Speaker::Bird { config_path, .. } => {
+                let mut routes = bird_routes(config_path)?;
+                routes.remove(ip);
+                self.reload_bird(&routes)
+            }
+            Speaker::Gobgp { gobgp_path } => {
+                let prefix = host_prefix(&ip.to_string())?;
+                run(
+                    gobgp_path,
+                    &["global", "rib", "-a", family(ip), "del", &prefix],
+                )?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Renders the BIRD config with the routes, and reloads BIRD.
+    fn reload_bird(&self, routes: &BTreeSet<IpAddr>) -> io::Result<()> {
+        let (config_path, birdc_path, session) = match self {
+            Speaker::Bird {
+                config_path,
+                birdc_path,
+                session,
+            } => (config_path, birdc_path, session),
+            Speaker::Gobgp { .. } => return Ok(()),
+        };
+        let mut d = String::from("# Generated by ip-manager, do not edit.\n");
+        for (name, channel, is_v4) in [(BIRD_STATIC4, "ipv4", true), (BIRD_STATIC6, "ipv6", false)]
+        {
+            d.push_str(&format!("\nprotocol static {name} {{\n    {channel};\n"));
+            for ip in routes.iter().filter(|ip| ip.is_ipv4() == is_v4) {
+                d.push_str(&format!(
+                    "    route {} blackhole;\n",
+                    host_prefix(&ip.to_string())?
+                ));
+            }
+            d.push_str("}\n");
+        }
+        if let Some(s) = session {
+            let (channel, name) = if s.peer_address.is_ipv4() {
+                ("ipv4", BIRD_STATIC4)
+            } else {
+                ("ipv6", BIRD_STATIC6)
+            };
+            d.push_str(&format!(
+                "\nprotocol bgp {BIRD_PEER} {{\n    local as {};\n    neighbor {} as {};\n    {channel} {{\n        import none;\n        export where proto = \"{name}\";\n    }};\n}}\n",
+                s.local_as, s.peer_address, s.peer_as
+            ));
+        }
+
+        log::info!("rendering BIRD config {config_path}");
+        if let Some(parent_dir) = Path::new(config_path).parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+        fs::write(config_path, d)?;
+        run(birdc_path, &["configure"])?;
+        Ok(())
+    }
+}
+
+/// Returns the routes in the rendered BIRD config, empty if not rendered yet.
+fn bird_routes(config_path: &str) -> io::Result<BTreeSet<IpAddr>> {
+    if !Path::new(config_path).exists() {
+        return Ok(BTreeSet::new());
+    }
+    let d = fs::read_to_string(config_path)?;
+    let mut routes = BTreeSet::new();
+    for line in d.lines() {
+        if let Some(prefix) = line
+            .trim()
+            .strip_prefix("route ")
+            .and_then(|v| v.strip_suffix(" blackhole;"))
+        {
+            routes.insert(parse_ip(prefix)?);
+        }
+    }
+    Ok(routes)
+}
+
+fn family(ip: &IpAddr) -> &'static str {
+    if ip.is_ipv4() {
+        "ipv4"
+    } else {
+        "ipv6"
+    }
+}
+
+/// Runs the speaker CLI, returning its stdout.
+fn run(path: &str, args: &[&str]) -> io::Result<String> {
+    let out = Process::new(path).args(args).output()?;
+    if !out.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed '{path} {}' {}",
+                args.join(" "),
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+/// BGP backend, announcing the configured address from the local speaker.
+/// The address is given rather than allocated, so "claim" always returns it.
+pub struct Bgp {
+    speaker: Speaker,
+    router_id: String,
+    ip: IpAddr,
+}
+
+impl Provider for Bgp {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move { Ok(self.router_id.clone()) })
+    }
+
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move { Ok(Some(to_address(&self.ip))) })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            Err(Error::new(
+                ErrorKind::Unsupported,
+                "BGP addresses are not allocated (set --address)",
+            ))
+        })
+    }
+
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            // other hosts' announcements are not visible to the local speaker
+            if self.speaker.announced(&parse_ip(&addr.ip)?)? {
+                return Ok(Some(self.router_id.clone()));
+            }
+            Ok(None)
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, _instance_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move { self.speaker.announce(&parse_ip(&addr.ip)?) })
+    }
+}
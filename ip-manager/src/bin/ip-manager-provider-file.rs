@@ -0,0 +1,154 @@
+//! Reference provider plugin for "ip-manager plugin --name=file".
+//!
+//! Hands out addresses from a static pool, recording the assignments in a local JSON file.
+//! Nothing is routed, so it is meant for testing and as the starting point of new plugins,
+//! which only need the JSON request and response shapes below (no dependency on this crate).
+//!
+//! Config:
+//! - "db": path of the JSON file to record addresses (required)
+//! - "pool": comma-separated IPs that "allocate" hands out
+//! - "instance_id": local instance ID (defaults to the hostname)
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+    process,
+};
+
+use serde_json::{json, Value};
+
+const PROTOCOL_VERSION: u64 = 1;
+
+fn main() {
+    let mut input = String::new();
+    let resp = io::stdin()
+        .read_to_string(&mut input)
+        .map_err(|e| format!("failed to read request {}", e))
+        .and_then(|_| {
+            serde_json::from_str::<Value>(&input).map_err(|e| format!("invalid request {}", e))
+        })
+        .and_then(|req| handle(&req));
+    match resp {
+        Ok(v) => println!("{v}"),
+        Err(e) => {
+            eprintln!("ip-manager-provider-file: {e}");
+            println!("{}", json!({ "error": e }));
+            process::exit(1);
+        }
+    }
+}
+
+fn handle(req: &Value) -> Result<Value, String> {
+    if req["protocol_version"].as_u64() != Some(PROTOCOL_VERSION) {
+        return Err(format!(
+            "unsupported protocol version {}",
+            req["protocol_version"]
+        ));
+    }
+    let config = &req["config"];
+    match req["operation"].as_str().unwrap_or_default() {
+        "describe" => Ok(json!({ "protocol_version": PROTOCOL_VERSION })),
+        "local_instance_id" => Ok(json!({ "instance_id": local_instance_id(config)? })),
+        "claim" => {
+            let db = load(config)?;
+            let addr = addresses(&db)
+                .iter()
+                .find(|a| a["instance_id"].is_null())
+                .map(|a| json!({ "id": a["id"], "ip": a["ip"] }));
+            Ok(json!({ "address": addr }))
+        }
+        "allocate" => {
+            let mut db = load(config)?;
+            let used: Vec<String> = addresses(&db)
+                .iter()
+                .filter_map(|a| a["ip"].as_str().map(|v| v.to_string()))
+                .collect();
+            let ip = config["pool"]
+                .as_str()
+                .unwrap_or_default()
+                .split(',')
+                .map(|v| v.trim())
+                .find(|v| !v.is_empty() && !used.iter().any(|u| u == v))
+                .ok_or("address pool is exhausted")?
+                .to_string();
+            let addr = json!({ "id": ip, "ip": ip });
+            db["addresses"]
+                .as_array_mut()
+                .ok_or("invalid db")?
+                .push(json!({ "id": ip, "ip": ip, "instance_id": null }));
+            save(config, &db)?;
+            Ok(json!({ "address": addr }))
+        }
+        "assigned_to" => {
+            let db = load(config)?;
+            let id = req["address"]["id"]
+                .as_str()
+                .ok_or("request has no address")?;
+            let addr = addresses(&db)
+                .iter()
+                .find(|a| a["id"].as_str() == Some(id))
+                .ok_or_else(|| format!("address {id} not found"))?;
+            Ok(json!({ "instance_id": addr["instance_id"] }))
+        }
+        "assign" => {
+            let mut db = load(config)?;
+            let id = req["address"]["id"]
+                .as_str()
+                .ok_or("request has no address")?;
+            let instance_id = req["instance_id"]
+                .as_str()
+                .ok_or("request has no instance_id")?;
+            let addr = db["addresses"]
+                .as_array_mut()
+                .ok_or("invalid db")?
+                .iter_mut()
+                .find(|a| a["id"].as_str() == Some(id))
+                .ok_or_else(|| format!("address {id} not found"))?;
+            addr["instance_id"] = json!(instance_id);
+            save(config, &db)?;
+            Ok(json!({}))
+        }
+        op => Err(format!("unknown operation '{op}'")),
+    }
+}
+
+fn local_instance_id(config: &Value) -> Result<String, String> {
+    if let Some(id) = config["instance_id"].as_str() {
+        return Ok(id.to_string());
+    }
+    fs::read_to_string("/etc/hostname")
+        .map(|v| v.trim().to_string())
+        .map_err(|e| format!("failed to read hostname {}", e))
+}
+
+fn addresses(db: &Value) -> &[Value] {
+    db["addresses"]
+        .as_array()
+        .map(|v| v.as_slice())
+        .unwrap_or(&[])
+}
+
+fn db_path(config: &Value) -> Result<&str, String> {
+    config["db"]
+        .as_str()
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| String::from("config has no 'db'"))
+}
+
+fn load(config: &Value) -> Result<Value, String> {
+    let path = db_path(config)?;
+    if !Path::new(path).exists() {
+        return Ok(json!({ "addresses": [] }));
+    }
+    let d = fs::read_to_string(path).map_err(|e| format!("failed to read {path} {}", e))?;
+    serde_json::from_str(&d).map_err(|e| format!("invalid db {path} {}", e))
+}
+
+fn save(config: &Value, db: &Value) -> Result<(), String> {
+    let path = db_path(config)?;
+    if let Some(parent_dir) = Path::new(path).parent() {
+        fs::create_dir_all(parent_dir).map_err(|e| format!("failed to create {path} {}", e))?;
+    }
+    fs::write(path, db.to_string()).map_err(|e| format!("failed to write {path} {}", e))
+}
@@ -0,0 +1,97 @@
+use std::io::{self, Error, ErrorKind};
+
+use clap::{crate_version, Arg, ArgAction, ArgMatches, Command};
+use serde::Serialize;
+
+use crate::command::FEATURES;
+
+pub const NAME: &str = "version";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Prints the version (with \"--verbose\", the build info)")
+        .long_about(
+            "
+
+With \"--verbose\", prints the git commit, the build timestamp, the rustc version,
+the target triple, and the Cargo features built in, to tell apart the binaries
+of a fleet running mixed versions (also served at \"/buildinfo\" by \"serve\").
+Prints JSON with the global \"--output=json\".
+
+e.g.,
+
+$ ip-manager version --verbose
+$ ip-manager --output=json version --verbose
+
+",
+        )
+        .arg(
+            Arg::new("VERBOSE")
+                .long("verbose")
+                .help("Prints the build info")
+                .required(false)
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Build info of the binary, recorded by "build.rs".
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: String,
+    pub git_commit: String,
+    pub build_timestamp: String,
+    pub rustc_version: String,
+    pub target: String,
+    pub features: Vec<String>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: crate_version!().to_string(),
+            git_commit: env!("IP_MANAGER_GIT_COMMIT").to_string(),
+            build_timestamp: env!("IP_MANAGER_BUILD_TIMESTAMP").to_string(),
+            rustc_version: env!("IP_MANAGER_RUSTC_VERSION").to_string(),
+            target: env!("IP_MANAGER_TARGET").to_string(),
+            features: FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+pub fn execute(matches: &ArgMatches) -> io::Result<()> {
+    let output = matches
+        .get_one::<String>("OUTPUT")
+        .cloned()
+        .unwrap_or_default();
+    let info = BuildInfo::current();
+    if !matches.get_flag("VERBOSE") {
+        if output == "json" {
+            println!("{}", serde_json::json!({ "version": info.version }));
+        } else {
+            println!("{} {}", crate::command::NAME, info.version);
+        }
+        return Ok(());
+    }
+    if output == "json" {
+        let d = serde_json::to_string(&info).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize build info {}", e),
+            )
+        })?;
+        println!("{d}");
+        return Ok(());
+    }
+    println!("version: {}", info.version);
+    println!("git commit: {}", info.git_commit);
+    println!("build timestamp: {}", info.build_timestamp);
+    println!("rustc version: {}", info.rustc_version);
+    println!("target: {}", info.target);
+    println!("features: {}", info.features.join(","));
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{command as aws_eip, iam};
+use clap::{Arg, ArgMatches, Command};
+use serde_json::{json, Value};
+
+pub const NAME: &str = "generate-cfn";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about(
+            "Prints the CloudFormation template of the infra that \"aws eip\" needs with the flags",
+        )
+        .long_about(
+            "
+
+Renders the IAM role, its policy, and the instance profile with exactly the
+actions the trailing \"aws eip\" flags require (e.g., \"--mode=terminate-hook\"
+adds autoscaling:CompleteLifecycleAction), so that the infra definitions
+change together with the flags.
+
+With \"--allocation-role-arn\", the policy to attach to the role in the
+account that owns the EIPs is in the \"AllocationRolePolicy\" output.
+
+Prints YAML, or JSON with the global \"--output=json\".
+
+e.g.,
+
+$ ip-manager generate-cfn \
+--name=ip-manager \
+-- \
+--id-tag-value=TEST-ID \
+--kind-tag-value=aws-ip-provisioner \
+--mode=terminate-hook \
+--lifecycle-hook-name=release-eip \
+> ip-manager.cfn.yaml
+
+",
+        )
+        .arg(
+            Arg::new("NAME")
+                .long("name")
+                .help("Sets the name prefix of the IAM role and instance profile")
+                .required(false)
+                .num_args(1)
+                .default_value("ip-manager"),
+        )
+        .arg(
+            Arg::new("ARGS")
+                .help("Sets the flags of \"aws eip\" to run with")
+                .required(false)
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        name: matches
+            .get_one::<String>("NAME")
+            .unwrap_or(&String::from("ip-manager"))
+            .clone(),
+        args: matches
+            .get_many::<String>("ARGS")
+            .map(|args| args.cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// Prints the template, as JSON if "output" is "json".
+pub fn execute(opts: Flags, output: &str) -> io::Result<()> {
+    let template = render(&opts)?;
+    let d = if output == "json" {
+        serde_json::to_string_pretty(&template).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize template {}", e),
+            )
+        })?
+    } else {
+        serde_yaml::to_string(&template).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize template {}", e),
+            )
+        })?
+    };
+    println!("{}", d.trim_end());
+    Ok(())
+}
+
+/// Returns the CloudFormation template.
+pub fn render(opts: &Flags) -> io::Result<Value> {
+    let mut argv = vec![aws_eip::NAME.to_string()];
+    argv.extend(opts.args.iter().cloned());
+    let eip_opts =
+        aws_eip::parse_flags(&aws_eip::new().try_get_matches_from(&argv).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid \"aws eip\" flags {}", e),
+            )
+        })?);
+
+    let mut statements = vec![json!({
+        "Effect": "Allow",
+        "Action": iam::required_actions(&eip_opts),
+        "Resource": "*",
+    })];
+    if !eip_opts.allocation_role_arn.is_empty() {
+        statements.push(json!({
+            "Effect": "Allow",
+            "Action": "sts:AssumeRole",
+            "Resource": eip_opts.allocation_role_arn,
+        }));
+    }
+
+    let mut template = json!({
+        "AWSTemplateFormatVersion": "2010-09-09",
+        "Description": format!(
+            "IAM role and instance profile for aws-ip-provisioner {}",
+            env!("CARGO_PKG_VERSION")
+        ),
+        "Resources": {
+            "Role": {
+                "Type": "AWS::IAM::Role",
+                "Properties": {
+                    "RoleName": format!("{}-role", opts.name),
+                    "AssumeRolePolicyDocument": {
+                        "Version": "2012-10-17",
+                        "Statement": [{
+                            "Effect": "Allow",
+                            "Principal": {"Service": "ec2.amazonaws.com"},
+                            "Action": "sts:AssumeRole",
+                        }],
+                    },
+                    "Policies": [{
+                        "PolicyName": opts.name,
+                        "PolicyDocument": {"Version": "2012-10-17", "Statement": statements},
+                    }],
+                },
+            },
+            "InstanceProfile": {
+                "Type": "AWS::IAM::InstanceProfile",
+                "Properties": {
+                    "InstanceProfileName": format!("{}-instance-profile", opts.name),
+                    "Roles": [{"Ref": "Role"}],
+                },
+            },
+        },
+        "Outputs": {
+            "RoleArn": {"Value": {"Fn::GetAtt": ["Role", "Arn"]}},
+            "InstanceProfileArn": {"Value": {"Fn::GetAtt": ["InstanceProfile", "Arn"]}},
+        },
+    });
+    if !eip_opts.allocation_role_arn.is_empty() {
+        // the allocation role is in the other account, so only the policy is rendered
+        let policy = json!({
+            "Version": "2012-10-17",
+            "Statement": [{
+                "Effect": "Allow",
+                "Action": iam::allocation_role_actions(),
+                "Resource": "*",
+            }],
+        });
+        template["Outputs"]["AllocationRolePolicy"] = json!({
+            "Description": format!("Policy of {}", eip_opts.allocation_role_arn),
+            "Value": policy.to_string(),
+        });
+    }
+    Ok(template)
+}
@@ -0,0 +1,528 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{command as aws_eip, logging};
+use clap::{crate_version, Arg, ArgMatches, Command};
+
+#[cfg(feature = "bgp")]
+use crate::bgp;
+#[cfg(feature = "ddns")]
+use crate::ddns;
+#[cfg(feature = "digitalocean")]
+use crate::digitalocean;
+#[cfg(feature = "hetzner")]
+use crate::hetzner;
+#[cfg(feature = "keepalived")]
+use crate::keepalived;
+#[cfg(feature = "linode")]
+use crate::linode;
+#[cfg(feature = "openstack")]
+use crate::openstack;
+#[cfg(feature = "plugin")]
+use crate::plugin;
+#[cfg(feature = "portmap")]
+use crate::portmap;
+#[cfg(any(
+    feature = "bgp",
+    feature = "ddns",
+    feature = "digitalocean",
+    feature = "hetzner",
+    feature = "keepalived",
+    feature = "linode",
+    feature = "openstack",
+    feature = "plugin",
+    feature = "portmap",
+    feature = "scaleway",
+    feature = "vultr"
+))]
+use crate::provider::Address;
+#[cfg(feature = "scaleway")]
+use crate::scaleway;
+#[cfg(feature = "vultr")]
+use crate::vultr;
+use crate::{
+    buildinfo, cfn, completions, cost,
+    detect::{self, Cloud},
+    history, inventory, k8s, peers, prefixlist, schema, selftest, selfupdate, serve, state,
+    transfer, tui, validate, windows,
+};
+
+pub const NAME: &str = "ip-manager";
+
+/// Optional Cargo features, and whether each is built in.
+pub const FEATURES: &[(&str, bool)] = &[
+    ("bgp", cfg!(feature = "bgp")),
+    ("chaos", cfg!(feature = "chaos")),
+    ("consul", cfg!(feature = "consul")),
+    ("ddns", cfg!(feature = "ddns")),
+    ("digitalocean", cfg!(feature = "digitalocean")),
+    ("hetzner", cfg!(feature = "hetzner")),
+    ("keepalived", cfg!(feature = "keepalived")),
+    ("linode", cfg!(feature = "linode")),
+    ("openstack", cfg!(feature = "openstack")),
+    ("plugin", cfg!(feature = "plugin")),
+    ("portmap", cfg!(feature = "portmap")),
+    ("scaleway", cfg!(feature = "scaleway")),
+    ("vultr", cfg!(feature = "vultr")),
+    ("webpki-roots", cfg!(feature = "webpki-roots")),
+];
+
+/// Returns the "--version" report: the version, and the features
+/// built in ("+") or left out ("-"), e.g., "features: +bgp -chaos ...".
+pub fn long_version() -> String {
+    let features: Vec<String> = FEATURES
+        .iter()
+        .map(|(name, enabled)| format!("{}{name}", if *enabled { '+' } else { '-' }))
+        .collect();
+    format!("{}\nfeatures: {}", crate_version!(), features.join(" "))
+}
+
+pub fn new() -> Command {
+    let cmd = Command::new(NAME)
+        .version(crate_version!())
+        .long_version(long_version())
+        .about("Manages the public IP of the local instance across cloud providers")
+        .long_about(
+            "
+
+Each provider has its own subcommand tree, sharing the global flags
+(\"--log-level\", \"--log-filter\", \"--log-target\", \"--output\", \"--state-backend\").
+
+e.g.,
+
+$ ip-manager aws eip \
+--log-level=info \
+--initial-wait-random-seconds=70 \
+--id-tag-key=Id \
+--id-tag-value=TEST-ID \
+--kind-tag-key=Kind \
+--kind-tag-value=aws-ip-provisioner \
+--mounted-eip-file-path=/data/eip.yaml
+
+\"aws-ip-provisioner\" is the alias of \"ip-manager aws eip\".
+
+\"run\" dispatches the trailing flags to the provider subcommand,
+so that one machine image or DaemonSet manifest works across clouds:
+
+$ ip-manager run --provider=auto -- \
+--id-tag-value=TEST-ID \
+--kind-tag-value=aws-ip-provisioner
+
+",
+        )
+        .args(
+            aws_eip::global_args()
+                .into_iter()
+                .map(|arg| arg.global(true)),
+        )
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .subcommand(
+            Command::new("aws")
+                .about("Manages AWS addresses")
+                .subcommand_required(true)
+                .subcommand(aws_eip::with_args(
+                    Command::new("eip")
+                        .about("Provisions the Elastic IP to the local EC2 instance"),
+                )),
+        )
+        .subcommand(buildinfo::command())
+        .subcommand(completions::command())
+        .subcommand(cost::command())
+        .subcommand(history::command())
+        .subcommand(inventory::command())
+        .subcommand(peers::command())
+        .subcommand(state::command())
+        .subcommand(transfer::command())
+        .subcommand(prefixlist::command())
+        .subcommand(selftest::command())
+        .subcommand(selfupdate::command())
+        .subcommand(serve::command())
+        .subcommand(schema::command())
+        .subcommand(k8s::command())
+        .subcommand(cfn::command())
+        .subcommand(tui::command())
+        .subcommand(validate::command())
+        .subcommand(windows::command());
+
+    #[cfg(feature = "bgp")]
+    let cmd = cmd.subcommand(bgp::command());
+    #[cfg(feature = "ddns")]
+    let cmd = cmd.subcommand(ddns::command());
+    #[cfg(feature = "digitalocean")]
+    let cmd = cmd.subcommand(digitalocean::command());
+    #[cfg(feature = "hetzner")]
+    let cmd = cmd.subcommand(hetzner::command());
+    #[cfg(feature = "keepalived")]
+    let cmd = cmd.subcommand(keepalived::command());
+    #[cfg(feature = "linode")]
+    let cmd = cmd.subcommand(linode::command());
+    #[cfg(feature = "openstack")]
+    let cmd = cmd.subcommand(openstack::command());
+    #[cfg(feature = "plugin")]
+    let cmd = cmd.subcommand(plugin::command());
+    #[cfg(feature = "portmap")]
+    let cmd = cmd.subcommand(portmap::command());
+    #[cfg(feature = "scaleway")]
+    let cmd = cmd.subcommand(scaleway::command());
+    #[cfg(feature = "vultr")]
+    let cmd = cmd.subcommand(vultr::command());
+
+    cmd.subcommand(
+            Command::new("run")
+                .about("Runs the default subcommand of the provider (e.g., \"aws eip\")")
+                .arg(
+                    Arg::new("PROVIDER")
+                        .long("provider")
+                        .help("Sets the provider (\"auto\" to probe the metadata services of EC2, DigitalOcean, Hetzner, OpenStack, Scaleway, Vultr, and Linode; GCE and Azure are detected, but have no subcommand yet)")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser([
+                            "auto",
+                            "aws",
+                            "digitalocean",
+                            "hetzner",
+                            "linode",
+                            "openstack",
+                            "scaleway",
+                            "vultr",
+                        ])
+                        .default_value("auto"),
+                )
+                .arg(
+                    Arg::new("ARGS")
+                        .help("Sets the flags of the provider subcommand")
+                        .required(false)
+                        .num_args(0..)
+                        .trailing_var_arg(true)
+                        .allow_hyphen_values(true),
+                ),
+    )
+}
+
+pub async fn execute(matches: &ArgMatches) -> io::Result<()> {
+    if let Some(("run", sub)) = matches.subcommand() {
+        let matches = resolve_run(sub).await?;
+        return dispatch(&matches).await;
+    }
+    dispatch(matches).await
+}
+
+/// Resolves "run" into the matches of the provider subcommand,
+/// carrying over the global flags.
+async fn resolve_run(matches: &ArgMatches) -> io::Result<ArgMatches> {
+    let provider = matches
+        .get_one::<String>("PROVIDER")
+        .unwrap_or(&String::from("auto"))
+        .clone();
+    let cloud = if provider == "auto" {
+        init_logger(matches)?;
+        detect::detect().await?
+    } else {
+        Cloud::parse(&provider)?
+    };
+
+    let mut argv = vec![NAME.to_string()];
+    for id in [
+        "LOG_LEVEL",
+        "LOG_FILTER",
+        "LOG_TARGET",
+        "OUTPUT",
+        "STATE_BACKEND",
+    ] {
+        if let Some(v) = matches.get_one::<String>(id) {
+            argv.push(format!("--{}={v}", id.to_lowercase().replace('_', "-")));
+        }
+    }
+    argv.extend(subcommand_of(cloud)?.iter().map(|s| s.to_string()));
+    if let Some(args) = matches.get_many::<String>("ARGS") {
+        argv.extend(args.cloned());
+    }
+    log::info!("running {:?}", argv);
+    new().try_get_matches_from(argv).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid flags for provider {cloud} {}", e),
+        )
+    })
+}
+
+/// Returns the default subcommand of the provider.
+fn subcommand_of(cloud: Cloud) -> io::Result<&'static [&'static str]> {
+    match cloud {
+        Cloud::Aws => Ok(&["aws", "eip"]),
+        #[cfg(feature = "digitalocean")]
+        Cloud::DigitalOcean => Ok(&["digitalocean", "reserved-ip"]),
+        #[cfg(feature = "hetzner")]
+        Cloud::Hetzner => Ok(&["hetzner", "floating-ip"]),
+        #[cfg(feature = "openstack")]
+        Cloud::OpenStack => Ok(&["openstack", "floating-ip"]),
+        #[cfg(feature = "scaleway")]
+        Cloud::Scaleway => Ok(&["scaleway", "flexible-ip"]),
+        #[cfg(feature = "vultr")]
+        Cloud::Vultr => Ok(&["vultr", "reserved-ip"]),
+        #[cfg(feature = "linode")]
+        Cloud::Linode => Ok(&["linode", "reserved-ip"]),
+        _ => Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("provider {cloud} is not supported yet, or not built in (see \"--version\")"),
+        )),
+    }
+}
+
+/// Dispatches the matched subcommand to its provider.
+async fn dispatch(matches: &ArgMatches) -> io::Result<()> {
+    match matches.subcommand() {
+        Some(("aws", sub)) => match sub.subcommand() {
+            Some(("eip", sub)) => aws_eip::execute(aws_eip::parse_flags(sub)).await,
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "ddns")]
+        Some((ddns::NAME, sub)) => match sub.subcommand() {
+            Some(("update", sub)) => {
+                init_logger(sub)?;
+                let addr = ddns::execute(ddns::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "digitalocean")]
+        Some((digitalocean::NAME, sub)) => match sub.subcommand() {
+            Some(("reserved-ip", sub)) => {
+                init_logger(sub)?;
+                let addr = digitalocean::execute(digitalocean::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "hetzner")]
+        Some((hetzner::NAME, sub)) => match sub.subcommand() {
+            Some(("floating-ip", sub)) => {
+                init_logger(sub)?;
+                let addr = hetzner::execute(hetzner::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "linode")]
+        Some((linode::NAME, sub)) => match sub.subcommand() {
+            Some(("reserved-ip", sub)) => {
+                init_logger(sub)?;
+                let addr = linode::execute(linode::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "vultr")]
+        Some((vultr::NAME, sub)) => match sub.subcommand() {
+            Some(("reserved-ip", sub)) => {
+                init_logger(sub)?;
+                let addr = vultr::execute(vultr::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "openstack")]
+        Some((openstack::NAME, sub)) => match sub.subcommand() {
+            Some(("floating-ip", sub)) => {
+                init_logger(sub)?;
+                let addr = openstack::execute(openstack::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "scaleway")]
+        Some((scaleway::NAME, sub)) => match sub.subcommand() {
+            Some(("flexible-ip", sub)) => {
+                init_logger(sub)?;
+                let addr = scaleway::execute(scaleway::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "portmap")]
+        Some((portmap::NAME, sub)) => match sub.subcommand() {
+            Some(("port-mapping", sub)) => {
+                init_logger(sub)?;
+                let addr = portmap::execute(portmap::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "bgp")]
+        Some((bgp::NAME, sub)) => match sub.subcommand() {
+            Some(("announce", sub)) => {
+                init_logger(sub)?;
+                let addr = bgp::announce(bgp::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            Some(("withdraw", sub)) => {
+                init_logger(sub)?;
+                let addr = bgp::withdraw(bgp::parse_flags(sub)).await?;
+                print_output(sub, &addr)
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        #[cfg(feature = "keepalived")]
+        Some((keepalived::NAME, sub)) => match sub.subcommand() {
+            Some(("vrrp", sub)) => {
+                init_logger(sub)?;
+                match keepalived::execute(keepalived::parse_flags(sub)).await? {
+                    Some(addr) => print_output(sub, &addr),
+                    None => Ok(()),
+                }
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        Some((buildinfo::NAME, sub)) => buildinfo::execute(sub),
+        Some((completions::NAME, sub)) => completions::execute(sub),
+        Some((schema::NAME, sub)) => schema::execute(sub),
+        Some((k8s::NAME, sub)) => k8s::execute(k8s::parse_flags(sub)),
+        Some((windows::NAME, sub)) => windows::execute(windows::parse_flags(sub)),
+        Some((cfn::NAME, sub)) => {
+            let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+            cfn::execute(cfn::parse_flags(sub), &output)
+        }
+        Some((cost::NAME, sub)) => {
+            init_logger(sub)?;
+            let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+            cost::execute(cost::parse_flags(sub), &output).await
+        }
+        Some((peers::NAME, sub)) => match sub.subcommand() {
+            Some(("publish", sub)) => {
+                init_logger(sub)?;
+                let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+                peers::publish(peers::parse_flags(sub), &output).await
+            }
+            Some(("fetch", sub)) => {
+                init_logger(sub)?;
+                peers::fetch(peers::parse_flags(sub)).await
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        Some((state::NAME, sub)) => match sub.subcommand() {
+            Some(("export", sub)) => {
+                init_logger(sub)?;
+                state::export(state::parse_flags(sub)).await
+            }
+            Some(("import", sub)) => {
+                init_logger(sub)?;
+                let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+                state::import(state::parse_flags(sub), &output).await
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        Some((transfer::NAME, sub)) => match sub.subcommand() {
+            Some(("initiate", sub)) => {
+                init_logger(sub)?;
+                transfer::initiate(transfer::parse_flags(sub)).await
+            }
+            Some(("accept", sub)) => {
+                init_logger(sub)?;
+                let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+                transfer::accept(transfer::parse_flags(sub), &output).await
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        Some((prefixlist::NAME, sub)) => match sub.subcommand() {
+            Some(("sync", sub)) => {
+                init_logger(sub)?;
+                let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+                prefixlist::sync(prefixlist::parse_flags(sub), &output).await
+            }
+            _ => Err(unknown_subcommand(sub)),
+        },
+        Some((history::NAME, sub)) => {
+            init_logger(sub)?;
+            let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+            history::execute(history::parse_flags(sub), &output)
+        }
+        Some((inventory::NAME, sub)) => {
+            init_logger(sub)?;
+            let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+            inventory::execute(inventory::parse_flags(sub), &output).await
+        }
+        Some((selftest::NAME, sub)) => {
+            init_logger(sub)?;
+            let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+            selftest::execute(selftest::parse_flags(sub), &output).await
+        }
+        Some((selfupdate::NAME, sub)) => {
+            init_logger(sub)?;
+            let output = sub.get_one::<String>("OUTPUT").cloned().unwrap_or_default();
+            selfupdate::execute(selfupdate::parse_flags(sub), &output).await
+        }
+        Some((serve::NAME, sub)) => {
+            init_logger(sub)?;
+            serve::execute(serve::parse_flags(sub)).await
+        }
+        Some((validate::NAME, sub)) => validate::execute(validate::parse_flags(sub)),
+        // no logger, which would write over the screen
+        Some((tui::NAME, sub)) => tui::execute(tui::parse_flags(sub)).await,
+        #[cfg(feature = "plugin")]
+        Some((plugin::NAME, sub)) => {
+            init_logger(sub)?;
+            let addr = plugin::execute(plugin::parse_flags(sub)).await?;
+            print_output(sub, &addr)
+        }
+        _ => Err(unknown_subcommand(matches)),
+    }
+}
+
+/// Initializes the logger with the global "--log-level", "--log-filter", and "--log-target" flags.
+/// No-op if already initialized.
+fn init_logger(matches: &ArgMatches) -> io::Result<()> {
+    let log_level = matches
+        .get_one::<String>("LOG_LEVEL")
+        .unwrap_or(&String::from("info"))
+        .clone();
+    let log_filter = matches
+        .get_one::<String>("LOG_FILTER")
+        .unwrap_or(&String::new())
+        .clone();
+    let log_target = matches
+        .get_one::<String>("LOG_TARGET")
+        .unwrap_or(&String::from("stderr"))
+        .clone();
+    logging::init(&log_level, &log_filter, &log_target)
+}
+
+/// Prints the provisioned address to stdout with the global "--output" flag.
+#[cfg(any(
+    feature = "bgp",
+    feature = "ddns",
+    feature = "digitalocean",
+    feature = "hetzner",
+    feature = "keepalived",
+    feature = "linode",
+    feature = "openstack",
+    feature = "plugin",
+    feature = "portmap",
+    feature = "scaleway",
+    feature = "vultr"
+))]
+fn print_output(matches: &ArgMatches, addr: &Address) -> io::Result<()> {
+    match matches.get_one::<String>("OUTPUT").map(|s| s.as_str()) {
+        Some("json") => {
+            let d = serde_json::to_string(addr).map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to serialize address {}", e),
+                )
+            })?;
+            println!("{d}");
+        }
+        _ => log::info!(
+            "successfully provisioned {} address {}",
+            addr.provider,
+            addr.ip
+        ),
+    }
+    Ok(())
+}
+
+fn unknown_subcommand(matches: &ArgMatches) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("unknown subcommand {:?}", matches.subcommand_name()),
+    )
+}
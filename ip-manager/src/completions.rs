@@ -0,0 +1,40 @@
+use std::io;
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+use clap_complete::{generate, Shell};
+
+use crate::command;
+
+pub const NAME: &str = "completions";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Generates the shell completion script")
+        .long_about(
+            "
+
+Prints the completion script of the full subcommand tree and the flags
+(with their possible values) to stdout.
+
+e.g.,
+
+$ ip-manager completions bash > /etc/bash_completion.d/ip-manager
+$ ip-manager completions zsh > \"${fpath[1]}/_ip-manager\"
+$ ip-manager completions fish > ~/.config/fish/completions/ip-manager.fish
+
+",
+        )
+        .arg(
+            Arg::new("SHELL")
+                .help("Sets the shell to generate the script for")
+                .required(true)
+                .num_args(1)
+                .value_parser(value_parser!(Shell)),
+        )
+}
+
+pub fn execute(matches: &ArgMatches) -> io::Result<()> {
+    let shell = *matches.get_one::<Shell>("SHELL").unwrap();
+    generate(shell, &mut command::new(), command::NAME, &mut io::stdout());
+    Ok(())
+}
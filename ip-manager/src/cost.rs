@@ -0,0 +1,328 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Error, ErrorKind},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_ip_provisioner::{conflict, ec2, eip, pipeline, pool, sdk};
+use aws_sdk_ec2::model::{Address, Filter};
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use serde::Serialize;
+
+pub const NAME: &str = "cost";
+
+/// Hours in a month, as on the AWS pricing pages.
+const HOURS_PER_MONTH: f64 = 730.0;
+
+/// Regions described at once with "--all-regions".
+const MAX_PARALLEL_REGIONS: usize = 8;
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Estimates the monthly cost of the idle tool-managed EIPs")
+        .long_about(
+            "
+
+Lists the tool-managed EIPs (with the \"Kind\" tag) that are idle, either
+not associated or returned to the pool, and estimates their cost, grouped
+by the \"Kind\" and \"Id\" tags:
+
+- monthly: the cost of keeping the idle EIPs for another month
+- accrued: the cost since the EIP was allocated (\"AllocatedAt\" tag)
+
+Since February 2024, AWS charges all public IPv4 addresses at the same hourly
+rate, associated or not. The associated EIPs replace the instance's public IP,
+so only the idle ones are the cost of keeping the addresses.
+ref. https://aws.amazon.com/vpc/pricing/
+
+e.g.,
+
+$ ip-manager cost \
+--kind-tag-key=Kind \
+--kind-tag-value=aws-ip-provisioner
+
+$ ip-manager --output=json cost
+
+# all the enabled regions of the account, concurrently
+$ ip-manager cost --all-regions
+
+",
+        )
+        .arg(
+            Arg::new("ID_TAG_KEY")
+                .long("id-tag-key")
+                .help("Sets the key of the EIP tag that identifies the node")
+                .required(false)
+                .num_args(1)
+                .default_value("Id"),
+        )
+        .arg(
+            Arg::new("KIND_TAG_KEY")
+                .long("kind-tag-key")
+                .help("Sets the key of the EIP tag that groups the tool-managed EIPs")
+                .required(false)
+                .num_args(1)
+                .default_value("Kind"),
+        )
+        .arg(
+            Arg::new("KIND_TAG_VALUE")
+                .long("kind-tag-value")
+                .help("Sets the value of the EIP tag that groups the tool-managed EIPs (empty for all kinds)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("HOURLY_PRICE")
+                .long("hourly-price")
+                .help("Sets the price of a public IPv4 address per hour in USD")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(f64))
+                .default_value("0.005"),
+        )
+        .arg(
+            Arg::new("ALL_REGIONS")
+                .long("all-regions")
+                .help("Lists the idle EIPs in all the enabled regions rather than the configured one")
+                .required(false)
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub id_tag_key: String,
+    pub kind_tag_key: String,
+    pub kind_tag_value: String,
+    pub hourly_price: f64,
+    pub all_regions: bool,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        id_tag_key: matches
+            .get_one::<String>("ID_TAG_KEY")
+            .unwrap_or(&String::from("Id"))
+            .clone(),
+        kind_tag_key: matches
+            .get_one::<String>("KIND_TAG_KEY")
+            .unwrap_or(&String::from("Kind"))
+            .clone(),
+        kind_tag_value: matches
+            .get_one::<String>("KIND_TAG_VALUE")
+            .unwrap_or(&String::new())
+            .clone(),
+        hourly_price: *matches.get_one::<f64>("HOURLY_PRICE").unwrap_or(&0.005),
+        all_regions: matches.get_flag("ALL_REGIONS"),
+    }
+}
+
+/// Idle EIP with its estimated cost.
+#[derive(Debug, Serialize)]
+pub struct IdleAddress {
+    pub region: String,
+    pub allocation_id: String,
+    pub public_ip: String,
+    pub pool_status: String,
+    /// Hours since the allocation, "None" without the "AllocatedAt" tag.
+    pub age_hours: Option<f64>,
+    pub monthly_cost: f64,
+    pub accrued_cost: Option<f64>,
+}
+
+/// Idle EIPs with the same region, "Kind", and "Id" tags.
+#[derive(Debug, Serialize)]
+pub struct Group {
+    pub region: String,
+    pub kind: String,
+    pub id: String,
+    pub monthly_cost: f64,
+    pub accrued_cost: f64,
+    pub addresses: Vec<IdleAddress>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub hourly_price: f64,
+    pub idle: usize,
+    pub total: usize,
+    pub monthly_cost: f64,
+    pub accrued_cost: f64,
+    pub groups: Vec<Group>,
+    /// Regions described.
+    pub regions: Vec<String>,
+    /// Regions that failed (e.g., denied by the SCP), reported rather than failing the others.
+    pub errors: Vec<String>,
+}
+
+/// Prints the cost report, as JSON if "output" is "json".
+pub async fn execute(opts: Flags, output: &str) -> io::Result<()> {
+    let shared_config = sdk::load_config(None, &sdk::Options::default()).await?;
+    let ec2_manager = ec2::Manager::new(&sdk::for_service(
+        &shared_config,
+        "ec2",
+        &sdk::Options::default(),
+    )?);
+    let regions = if opts.all_regions {
+        eip::describe_regions(&ec2_manager).await?
+    } else {
+        vec![shared_config
+            .region()
+            .map(|r| r.to_string())
+            .unwrap_or_default()]
+    };
+
+    let filter = if opts.kind_tag_value.is_empty() {
+        Filter::builder()
+            .name("tag-key")
+            .values(&opts.kind_tag_key)
+            .build()
+    } else {
+        Filter::builder()
+            .name(format!("tag:{}", opts.kind_tag_key))
+            .values(&opts.kind_tag_value)
+            .build()
+    };
+    let tasks: Vec<_> = regions
+        .iter()
+        .map(|region| {
+            let (region, filter) = (region.clone(), filter.clone());
+            async move {
+                let shared_config =
+                    sdk::load_config(Some(region), &sdk::Options::default()).await?;
+                let ec2_manager = ec2::Manager::new(&sdk::for_service(
+                    &shared_config,
+                    "ec2",
+                    &sdk::Options::default(),
+                )?);
+                eip::describe(&ec2_manager, vec![filter]).await
+            }
+        })
+        .collect();
+    let mut addrs = Vec::new();
+    let mut errors = Vec::new();
+    for (region, ret) in regions
+        .iter()
+        .zip(pipeline::run_bounded(MAX_PARALLEL_REGIONS, tasks).await)
+    {
+        match ret {
+            Ok(v) => addrs.extend(v.into_iter().map(|a| (region.clone(), a))),
+            Err(e) if regions.len() == 1 => return Err(e),
+            Err(e) => {
+                log::warn!("failed to describe EIPs in {region} '{}'", e);
+                errors.push(format!("{region}: {}", e));
+            }
+        }
+    }
+    let mut report = report(&opts, &addrs, now_unix_seconds());
+    report.regions = regions;
+    report.errors = errors;
+
+    if output == "json" {
+        let d = serde_json::to_string_pretty(&report).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize cost report {}", e),
+            )
+        })?;
+        println!("{d}");
+        return Ok(());
+    }
+
+    println!(
+        "{:<16} {:<24} {:<24} {:>4} {:>10} {:>10}",
+        "REGION", "KIND", "ID", "IDLE", "MONTHLY", "ACCRUED"
+    );
+    for g in report.groups.iter() {
+        println!(
+            "{:<16} {:<24} {:<24} {:>4} {:>10} {:>10}",
+            g.region,
+            g.kind,
+            g.id,
+            g.addresses.len(),
+            format!("${:.2}", g.monthly_cost),
+            format!("${:.2}", g.accrued_cost)
+        );
+    }
+    println!(
+        "\n{} of {} EIPs idle, ${:.2}/month (${:.2} accrued, at ${}/hour)",
+        report.idle, report.total, report.monthly_cost, report.accrued_cost, report.hourly_price
+    );
+    for e in report.errors.iter() {
+        println!("failed to describe {e}");
+    }
+    Ok(())
+}
+
+/// Returns the cost of the idle EIPs, grouped by the region, "Kind", and "Id" tags.
+/// An EIP is idle if not associated, or returned to the pool.
+fn report(opts: &Flags, addrs: &[(String, Address)], now: u64) -> Report {
+    let mut groups: BTreeMap<(String, String, String), Vec<IdleAddress>> = BTreeMap::new();
+    for (region, a) in addrs {
+        let pool_status = tag(a, pool::STATUS_TAG_KEY);
+        if a.association_id().is_some() && pool_status != pool::STATUS_AVAILABLE {
+            continue;
+        }
+        let age_hours = tag(a, conflict::ALLOCATED_AT_TAG_KEY)
+            .parse::<u64>()
+            .ok()
+            .map(|since| now.saturating_sub(since) as f64 / 3600.0);
+        groups
+            .entry((
+                region.clone(),
+                tag(a, &opts.kind_tag_key),
+                tag(a, &opts.id_tag_key),
+            ))
+            .or_default()
+            .push(IdleAddress {
+                region: region.clone(),
+                allocation_id: a.allocation_id().unwrap_or_default().to_string(),
+                public_ip: a.public_ip().unwrap_or_default().to_string(),
+                pool_status,
+                age_hours,
+                monthly_cost: opts.hourly_price * HOURS_PER_MONTH,
+                accrued_cost: age_hours.map(|h| opts.hourly_price * h),
+            });
+    }
+
+    let groups: Vec<Group> = groups
+        .into_iter()
+        .map(|((region, kind, id), addresses)| Group {
+            region,
+            kind,
+            id,
+            monthly_cost: addresses.iter().map(|a| a.monthly_cost).sum(),
+            accrued_cost: addresses.iter().filter_map(|a| a.accrued_cost).sum(),
+            addresses,
+        })
+        .collect();
+    Report {
+        hourly_price: opts.hourly_price,
+        idle: groups.iter().map(|g| g.addresses.len()).sum(),
+        total: addrs.len(),
+        monthly_cost: groups.iter().map(|g| g.monthly_cost).sum(),
+        accrued_cost: groups.iter().map(|g| g.accrued_cost).sum(),
+        groups,
+        regions: Vec::new(),
+        errors: Vec::new(),
+    }
+}
+
+fn tag(addr: &Address, key: &str) -> String {
+    addr.tags()
+        .unwrap_or_default()
+        .iter()
+        .find(|t| t.key() == Some(key))
+        .and_then(|t| t.value())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn now_unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
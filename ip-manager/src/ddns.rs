@@ -0,0 +1,435 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    net::IpAddr,
+    path::Path,
+    sync::Mutex,
+};
+
+use aws_ip_provisioner::{ddns, dns, sdk, secret, stun};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use tokio::time::{sleep, Duration};
+
+use crate::provider::{self, Address, BoxFuture, Provider};
+
+pub const NAME: &str = "ddns";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages dynamic DNS names")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("update")
+                .about("Points the dynamic DNS name to the public IP of the local host")
+                .long_about(
+                    "
+
+For the non-cloud nodes whose public IP changes (e.g., home-lab nodes behind
+a residential ISP), points the name to the public IP with DuckDNS, Dyn, No-IP,
+or the DNS UPDATE of RFC 2136 (TSIG-signed, as \"nsupdate\"), so that the node
+keeps a stable name.
+
+The public IP is \"--public-ip\", or discovered with the STUN server. The last
+IP the name was pointed to is recorded in the state file, so the name is only
+updated when the IP changes (the services block the clients that update
+without a change). Runs once, or every \"--interval-seconds\".
+
+The credentials are the token of DuckDNS, \"<username>:<password>\" of Dyn
+and No-IP (the updater client key as the password of Dyn), or the base64 TSIG
+secret of RFC 2136 (empty for the unsigned updates).
+
+e.g.,
+
+$ ip-manager ddns update \
+--service=rfc2136 \
+--hostname=node1.lab.example.com \
+--server=192.0.2.53 \
+--tsig-key-name=ddns-key \
+--credentials-source=file:/etc/ip-manager/tsig.key \
+--interval-seconds=300
+
+",
+                )
+                .arg(
+                    Arg::new("SERVICE")
+                        .long("service")
+                        .help("Sets the dynamic DNS service")
+                        .required(true)
+                        .num_args(1)
+                        .value_parser(["duckdns", "dyn", "noip", "rfc2136"]),
+                )
+                .arg(
+                    Arg::new("HOSTNAME")
+                        .long("hostname")
+                        .help("Sets the name to update (e.g., \"mynode.duckdns.org\")")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("CREDENTIALS_SOURCE")
+                        .long("credentials-source")
+                        .help("Sets where to read the credentials (\"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\")")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("env:DDNS_CREDENTIALS"),
+                )
+                .arg(
+                    Arg::new("SERVER")
+                        .long("server")
+                        .help("Sets the primary server of the zone (\"<ip>[:<port>]\") for rfc2136, or the update URL of the other services (empty for the service default)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("ZONE")
+                        .long("zone")
+                        .help("Sets the zone of the name for rfc2136 (empty for the parent of the name)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("TSIG_KEY_NAME")
+                        .long("tsig-key-name")
+                        .help("Sets the TSIG key name for rfc2136")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("TSIG_ALGORITHM")
+                        .long("tsig-algorithm")
+                        .help("Sets the TSIG algorithm for rfc2136")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(["hmac-sha256", "hmac-sha512"])
+                        .default_value("hmac-sha256"),
+                )
+                .arg(
+                    Arg::new("TTL")
+                        .long("ttl")
+                        .help("Sets the TTL of the record for rfc2136 in seconds")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(u32))
+                        .default_value("60"),
+                )
+                .arg(
+                    Arg::new("PUBLIC_IP")
+                        .long("public-ip")
+                        .help("Sets the public IP to point the name to (empty to discover with \"--stun-server\")")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("STUN_SERVER")
+                        .long("stun-server")
+                        .help("Sets the STUN server to discover the public IP with")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("stun.l.google.com:19302"),
+                )
+                .arg(
+                    Arg::new("INTERVAL_SECONDS")
+                        .long("interval-seconds")
+                        .help("Sets the interval to check the public IP in seconds (0 to update once)")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(u64))
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::new("STATE_FILE_PATH")
+                        .long("state-file-path")
+                        .help("Sets the file path to store the name and its last IP")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/data/ddns.yaml"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub service: String,
+    pub hostname: String,
+    pub credentials_source: String,
+    pub server: String,
+    pub zone: String,
+    pub tsig_key_name: String,
+    pub tsig_algorithm: String,
+    pub ttl: u32,
+    pub public_ip: String,
+    pub stun_server: String,
+    pub interval_seconds: u64,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    let s = |id: &str| {
+        matches
+            .get_one::<String>(id)
+            .unwrap_or(&String::new())
+            .clone()
+    };
+    Flags {
+        service: s("SERVICE"),
+        hostname: s("HOSTNAME").trim_end_matches('.').to_string(),
+        credentials_source: s("CREDENTIALS_SOURCE"),
+        server: s("SERVER"),
+        zone: s("ZONE"),
+        tsig_key_name: s("TSIG_KEY_NAME"),
+        tsig_algorithm: s("TSIG_ALGORITHM"),
+        ttl: *matches.get_one::<u32>("TTL").unwrap_or(&60),
+        public_ip: s("PUBLIC_IP"),
+        stun_server: s("STUN_SERVER"),
+        interval_seconds: *matches.get_one::<u64>("INTERVAL_SECONDS").unwrap_or(&0),
+        state_file_path: s("STATE_FILE_PATH"),
+    }
+}
+
+/// Updates the name, once or every "--interval-seconds".
+/// In the loop, a failed update is logged and retried at the next interval.
+pub async fn execute(opts: Flags) -> io::Result<Address> {
+    if Path::new(&opts.state_file_path).exists() {
+        let addr = Address::load(&opts.state_file_path)?;
+        if addr.provider == NAME && addr.id != opts.hostname {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "state file {} is for {}, not {}",
+                    opts.state_file_path, addr.id, opts.hostname
+                ),
+            ));
+        }
+    }
+    let credentials = secret::read(
+        &opts.credentials_source,
+        "DDNS_CREDENTIALS",
+        &sdk::Options::default(),
+    )
+    .await?;
+    let ddns = Ddns::new(&opts, &credentials)?;
+
+    loop {
+        match update(&ddns, &opts).await {
+            Ok(addr) if opts.interval_seconds == 0 => return Ok(addr),
+            Ok(_) => {}
+            Err(e) if opts.interval_seconds > 0 => {
+                log::warn!("failed to update {} '{}'", opts.hostname, e);
+            }
+            Err(e) => return Err(e),
+        }
+        sleep(Duration::from_secs(opts.interval_seconds)).await;
+    }
+}
+
+async fn update(ddns: &Ddns, opts: &Flags) -> io::Result<Address> {
+    let ip = if opts.public_ip.is_empty() {
+        stun::discover(&opts.stun_server).await?.to_string()
+    } else {
+        opts.public_ip.clone()
+    };
+    *ddns.public_ip.lock().unwrap() = ip.clone();
+
+    // the public IP is the "instance", so the name is never left on the previous one
+    let mut addr = provider::provision(ddns, &opts.state_file_path, false).await?;
+    if addr.ip != ip {
+        addr.ip = ip;
+        addr.sync(&opts.state_file_path)?;
+    }
+    Ok(addr)
+}
+
+enum Service {
+    DuckDns {
+        url: String,
+        token: String,
+    },
+    DynDns2 {
+        url: String,
+        username: String,
+        password: String,
+    },
+    Rfc2136 {
+        server: String,
+        zone: String,
+        ttl: u32,
+        key: Option<ddns::Tsig>,
+    },
+}
+
+/// Dynamic DNS backend, where the address ID is the name and the IP is
+/// the one the name was last pointed to (empty if never).
+pub struct Ddns {
+    service: Service,
+    hostname: String,
+    /// Public IP of this round of updates.
+    public_ip: Mutex<String>,
+}
+
+impl Ddns {
+    fn new(opts: &Flags, credentials: &str) -> io::Result<Self> {
+        let url = |default: &str| {
+            if opts.server.is_empty() {
+                default.to_string()
+            } else {
+                opts.server.clone()
+            }
+        };
+        let user_password = || {
+            credentials
+                .trim()
+                .split_once(':')
+                .map(|(u, p)| (u.to_string(), p.to_string()))
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "credentials of {} must be '<username>:<password>'",
+                            opts.service
+                        ),
+                    )
+                })
+        };
+        let service = match opts.service.as_str() {
+            "duckdns" => Service::DuckDns {
+                url: url(ddns::DUCKDNS_URL),
+                token: credentials.trim().to_string(),
+            },
+            "dyn" | "noip" => {
+                let (username, password) = user_password()?;
+                Service::DynDns2 {
+                    url: url(if opts.service == "dyn" {
+                        ddns::DYN_URL
+                    } else {
+                        ddns::NOIP_URL
+                    }),
+                    username,
+                    password,
+                }
+            }
+            "rfc2136" => {
+                if opts.server.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "'--server' is required for rfc2136",
+                    ));
+                }
+                dns::parse_resolver(&opts.server)?;
+                let key = if credentials.trim().is_empty() {
+                    log::warn!("no TSIG secret -- sending unsigned DNS updates");
+                    None
+                } else if opts.tsig_key_name.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "'--tsig-key-name' is required with the TSIG secret",
+                    ));
+                } else {
+                    Some(ddns::Tsig::new(
+                        &opts.tsig_key_name,
+                        &opts.tsig_algorithm,
+                        credentials,
+                    )?)
+                };
+                let zone = if opts.zone.is_empty() {
+                    opts.hostname
+                        .split_once('.')
+                        .map(|(_, parent)| parent.to_string())
+                        .unwrap_or_default()
+                } else {
+                    opts.zone.clone()
+                };
+                Service::Rfc2136 {
+                    server: opts.server.clone(),
+                    zone,
+                    ttl: opts.ttl,
+                    key,
+                }
+            }
+            s => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown dynamic DNS service '{s}'"),
+                ))
+            }
+        };
+        Ok(Self {
+            service,
+            hostname: opts.hostname.clone(),
+            public_ip: Mutex::new(String::new()),
+        })
+    }
+}
+
+impl Provider for Ddns {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    /// Returns the current public IP of the host.
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move { Ok(self.public_ip.lock().unwrap().clone()) })
+    }
+
+    /// Names are registered with the service beforehand, never claimed.
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move { Ok(None) })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            Ok(Address {
+                provider: NAME.to_string(),
+                id: self.hostname.clone(),
+                ip: String::new(),
+            })
+        })
+    }
+
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            if addr.ip.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(addr.ip.clone()))
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, ip: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            log::info!("pointing {} to {ip}", addr.id);
+            match &self.service {
+                Service::DuckDns { url, token } => ddns::duckdns(url, &addr.id, token, ip).await,
+                Service::DynDns2 {
+                    url,
+                    username,
+                    password,
+                } => ddns::dyndns2(url, &addr.id, username, password, ip).await,
+                Service::Rfc2136 {
+                    server,
+                    zone,
+                    ttl,
+                    key,
+                } => {
+                    let ip = ip.parse::<IpAddr>().map_err(|e| {
+                        Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("invalid public IP '{ip}' ({})", e),
+                        )
+                    })?;
+                    ddns::rfc2136(
+                        dns::parse_resolver(server)?,
+                        zone,
+                        &addr.id,
+                        ip,
+                        *ttl,
+                        key.as_ref(),
+                    )
+                    .await
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    fmt, fs,
+    io::{self, Error, ErrorKind},
+};
+
+use hyper::{Body, Client, Method, Request, StatusCode};
+use tokio::time::{timeout, Duration};
+
+/// Link-local address of the metadata services of most supported providers.
+/// Scaleway serves its own at "169.254.42.42".
+const METADATA_ENDPOINT: &str = "http://169.254.169.254";
+
+/// Timeout for each probe, short enough not to delay the startup
+/// on the other clouds where the request is dropped.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// DMI chassis asset tag of Azure virtual machines.
+const AZURE_CHASSIS_ASSET_TAG: &str = "7783-7084-3265-9085-8269-3286-77";
+
+/// Cloud provider of the local machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cloud {
+    Aws,
+    Gcp,
+    Azure,
+    DigitalOcean,
+    Hetzner,
+    OpenStack,
+    Scaleway,
+    Vultr,
+    Linode,
+}
+
+impl Cloud {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Cloud::Aws => "aws",
+            Cloud::Gcp => "gcp",
+            Cloud::Azure => "azure",
+            Cloud::DigitalOcean => "digitalocean",
+            Cloud::Hetzner => "hetzner",
+            Cloud::OpenStack => "openstack",
+            Cloud::Scaleway => "scaleway",
+            Cloud::Vultr => "vultr",
+            Cloud::Linode => "linode",
+        }
+    }
+
+    pub fn parse(s: &str) -> io::Result<Self> {
+        match s {
+            "aws" => Ok(Cloud::Aws),
+            "gcp" => Ok(Cloud::Gcp),
+            "azure" => Ok(Cloud::Azure),
+            "digitalocean" => Ok(Cloud::DigitalOcean),
+            "hetzner" => Ok(Cloud::Hetzner),
+            "openstack" => Ok(Cloud::OpenStack),
+            "scaleway" => Ok(Cloud::Scaleway),
+            "vultr" => Ok(Cloud::Vultr),
+            "linode" => Ok(Cloud::Linode),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("unknown provider '{s}'"),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Cloud {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Probes the metadata services of all supported providers concurrently,
+/// and falls back to the DMI vendor if none responds
+/// (e.g., IMDSv2 hop limit dropping responses to containers).
+pub async fn detect() -> io::Result<Cloud> {
+    log::info!("probing metadata services to detect the cloud provider");
+    let (aws, gcp, azure, digitalocean, hetzner, openstack, scaleway, vultr, linode) = tokio::join!(
+        probe_aws(),
+        probe_gcp(),
+        probe_azure(),
+        probe_digitalocean(),
+        probe_hetzner(),
+        probe_openstack(),
+        probe_scaleway(),
+        probe_vultr(),
+        probe_linode()
+    );
+    // EC2 last, since other providers (e.g., OpenStack, Vultr) also serve the EC2-compatible metadata
+    for (cloud, found) in [
+        (Cloud::OpenStack, openstack),
+        (Cloud::Gcp, gcp),
+        (Cloud::Azure, azure),
+        (Cloud::DigitalOcean, digitalocean),
+        (Cloud::Hetzner, hetzner),
+        (Cloud::Scaleway, scaleway),
+        (Cloud::Vultr, vultr),
+        (Cloud::Linode, linode),
+        (Cloud::Aws, aws),
+    ] {
+        if found {
+            log::info!("detected provider {cloud} via metadata service");
+            return Ok(cloud);
+        }
+    }
+
+    if let Some(cloud) = detect_dmi() {
+        log::info!("detected provider {cloud} via DMI vendor");
+        return Ok(cloud);
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        "failed to detect the cloud provider (no metadata service responded)",
+    ))
+}
+
+/// ref. <https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/instancedata-data-retrieval.html>
+async fn probe_aws() -> bool {
+    let token = Request::builder()
+        .method(Method::PUT)
+        .uri(format!("{METADATA_ENDPOINT}/latest/api/token"))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", "60")
+        .body(Body::empty());
+    if probe(token, None).await {
+        return true;
+    }
+    // IMDSv1-only instances
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(format!("{METADATA_ENDPOINT}/latest/meta-data/instance-id"))
+        .body(Body::empty());
+    probe(req, None).await
+}
+
+/// ref. <https://cloud.google.com/compute/docs/metadata/querying-metadata>
+async fn probe_gcp() -> bool {
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(format!(
+            "{METADATA_ENDPOINT}/computeMetadata/v1/instance/id"
+        ))
+        .header("Metadata-Flavor", "Google")
+        .body(Body::empty());
+    probe(req, Some(("Metadata-Flavor", "Google"))).await
+}
+
+/// ref. <https://learn.microsoft.com/en-us/azure/virtual-machines/instance-metadata-service>
+async fn probe_azure() -> bool {
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(format!(
+            "{METADATA_ENDPOINT}/metadata/instance/compute/vmId?api-version=2021-02-01&format=text"
+        ))
+        .header("Metadata", "true")
+        .body(Body::empty());
+    probe(req, None).await
+}
+
+/// ref. <https://docs.digitalocean.com/reference/api/metadata-api/>
+async fn probe_digitalocean() -> bool {
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(format!("{METADATA_ENDPOINT}/metadata/v1/id"))
+        .body(Body::empty());
+    probe(req, None).await
+}
+
+/// ref. <https://docs.hetzner.cloud/#server-metadata>
+async fn probe_hetzner() -> bool {
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(format!(
+            "{METADATA_ENDPOINT}/hetzner/v1/metadata/instance-id"
+        ))
+        .body(Body::empty());
+    probe(req, None).await
+}
+
+/// ref. <https://docs.openstack.org/nova/latest/user/metadata.html>
+async fn probe_openstack() -> bool {
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(format!(
+            "{METADATA_ENDPOINT}/openstack/latest/meta_data.json"
+        ))
+        .body(Body::empty());
+    probe(req, None).await
+}
+
+/// ref. <https://www.scaleway.com/en/docs/compute/instances/how-to/use-instance-metadata/>
+async fn probe_scaleway() -> bool {
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri("http://169.254.42.42/conf?format=json")
+        .body(Body::empty());
+    probe(req, None).await
+}
+
+/// ref. <https://www.vultr.com/metadata/>
+async fn probe_vultr() -> bool {
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(format!("{METADATA_ENDPOINT}/v1/instance-v2-id"))
+        .body(Body::empty());
+    probe(req, None).await
+}
+
+/// ref. <https://techdocs.akamai.com/cloud-computing/docs/overview-of-the-metadata-service>
+async fn probe_linode() -> bool {
+    let req = Request::builder()
+        .method(Method::PUT)
+        .uri(format!("{METADATA_ENDPOINT}/v1/token"))
+        .header("Metadata-Token-Expiry-Seconds", "60")
+        .body(Body::empty());
+    probe(req, None).await
+}
+
+/// Returns true if the request succeeds within the timeout,
+/// with the expected response header if any.
+async fn probe(
+    req: Result<Request<Body>, hyper::http::Error>,
+    expected_header: Option<(&str, &str)>,
+) -> bool {
+    let req = match req {
+        Ok(v) => v,
+        Err(e) => {
+            log::warn!("failed to build probe request '{}'", e);
+            return false;
+        }
+    };
+    let uri = req.uri().to_string();
+    let resp = match timeout(PROBE_TIMEOUT, Client::new().request(req)).await {
+        Ok(Ok(v)) => v,
+        Ok(Err(e)) => {
+            log::debug!("probe {uri} failed '{}'", e);
+            return false;
+        }
+        Err(_) => {
+            log::debug!("probe {uri} timed out");
+            return false;
+        }
+    };
+    if resp.status() != StatusCode::OK {
+        log::debug!("probe {uri} returned {}", resp.status());
+        return false;
+    }
+    match expected_header {
+        Some((k, v)) => resp.headers().get(k).map(|h| h == v).unwrap_or(false),
+        None => true,
+    }
+}
+
+/// Detects the provider from the DMI system vendor (Linux only).
+fn detect_dmi() -> Option<Cloud> {
+    let vendor = fs::read_to_string("/sys/class/dmi/id/sys_vendor").ok()?;
+    let vendor = vendor.trim();
+    if vendor.starts_with("Amazon EC2") {
+        Some(Cloud::Aws)
+    } else if vendor.starts_with("Google") {
+        Some(Cloud::Gcp)
+    } else if vendor.starts_with("DigitalOcean") {
+        Some(Cloud::DigitalOcean)
+    } else if vendor.starts_with("Hetzner") {
+        Some(Cloud::Hetzner)
+    } else if vendor.starts_with("OpenStack") {
+        Some(Cloud::OpenStack)
+    } else if vendor.starts_with("Scaleway") {
+        Some(Cloud::Scaleway)
+    } else if vendor.starts_with("Vultr") {
+        Some(Cloud::Vultr)
+    } else if vendor.starts_with("Linode") || vendor.starts_with("Akamai") {
+        Some(Cloud::Linode)
+    } else if fs::read_to_string("/sys/class/dmi/id/chassis_asset_tag")
+        .map(|tag| tag.trim() == AZURE_CHASSIS_ASSET_TAG)
+        .unwrap_or(false)
+    {
+        // "Microsoft Corporation" vendor is also Hyper-V outside Azure
+        Some(Cloud::Azure)
+    } else {
+        None
+    }
+}
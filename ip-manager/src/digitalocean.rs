@@ -0,0 +1,245 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{sdk, secret};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::{
+    http::{self, Client},
+    provider::{self, Address, BoxFuture, Provider},
+};
+
+pub const NAME: &str = "digitalocean";
+
+const API_URL: &str = "https://api.digitalocean.com/v2";
+
+/// ref. <https://docs.digitalocean.com/reference/api/metadata-api/>
+const METADATA_URL: &str = "http://169.254.169.254/metadata/v1";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages DigitalOcean addresses")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("reserved-ip")
+                .about("Provisions the Reserved IP to the local Droplet")
+                .long_about(
+                    "
+
+The Droplet ID and region are fetched from the metadata service.
+
+Reserved IPs cannot be tagged, so an unassigned Reserved IP is only reused
+with \"--reuse-unassigned=true\" (any unassigned Reserved IP in the region).
+Otherwise, a new one is reserved unless the state file exists.
+
+Requires the API token with reserved_ip:read, reserved_ip:create, and reserved_ip:update scopes.
+
+e.g.,
+
+$ ip-manager digitalocean reserved-ip \
+--token-source=env:DIGITALOCEAN_TOKEN \
+--state-file-path=/data/reserved-ip.yaml
+
+",
+                )
+                .arg(
+                    Arg::new("TOKEN_SOURCE")
+                        .long("token-source")
+                        .help("Sets where to read the API token (\"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\")")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("env:DIGITALOCEAN_TOKEN"),
+                )
+                .arg(
+                    Arg::new("REUSE_UNASSIGNED")
+                        .long("reuse-unassigned")
+                        .help("Reuses an unassigned Reserved IP in the region rather than reserving a new one")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(bool))
+                        .default_value("false"),
+                )
+                .arg(
+                    Arg::new("NO_STEAL")
+                        .long("no-steal")
+                        .help("Aborts rather than re-assigning the Reserved IP that is assigned to another Droplet")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(bool))
+                        .default_value("true"),
+                )
+                .arg(
+                    Arg::new("STATE_FILE_PATH")
+                        .long("state-file-path")
+                        .help("Sets the file path to store the Reserved IP information mapped to this volume path")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/data/reserved-ip.yaml"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub token_source: String,
+    pub reuse_unassigned: bool,
+    pub no_steal: bool,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        token_source: matches
+            .get_one::<String>("TOKEN_SOURCE")
+            .unwrap_or(&String::from("env:DIGITALOCEAN_TOKEN"))
+            .clone(),
+        reuse_unassigned: *matches
+            .get_one::<bool>("REUSE_UNASSIGNED")
+            .unwrap_or(&false),
+        no_steal: *matches.get_one::<bool>("NO_STEAL").unwrap_or(&true),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/reserved-ip.yaml"))
+            .clone(),
+    }
+}
+
+pub async fn execute(opts: Flags) -> io::Result<Address> {
+    let token = secret::read(
+        &opts.token_source,
+        "DIGITALOCEAN_TOKEN",
+        &sdk::Options::default(),
+    )
+    .await?;
+    let do_provider = DigitalOcean {
+        client: Client::new(
+            API_URL,
+            vec![(String::from("Authorization"), format!("Bearer {token}"))],
+        ),
+        reuse_unassigned: opts.reuse_unassigned,
+    };
+    provider::provision(&do_provider, &opts.state_file_path, opts.no_steal).await
+}
+
+/// DigitalOcean Reserved IP backend.
+/// ref. <https://docs.digitalocean.com/reference/api/api-reference/#tag/Reserved-IPs>
+pub struct DigitalOcean {
+    client: Client,
+    reuse_unassigned: bool,
+}
+
+impl DigitalOcean {
+    async fn region(&self) -> io::Result<String> {
+        http::fetch_metadata(&format!("{METADATA_URL}/region"), &[]).await
+    }
+
+    async fn wait_action(&self, ip: &str, action_id: u64) -> io::Result<()> {
+        let started = Instant::now();
+        loop {
+            let resp = self
+                .client
+                .get(&format!("/reserved_ips/{ip}/actions/{action_id}"))
+                .await?;
+            match resp["action"]["status"].as_str() {
+                Some("completed") => return Ok(()),
+                Some("errored") => {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        format!("action {action_id} on {ip} errored"),
+                    ));
+                }
+                status => log::info!("action {action_id} on {ip} is {status:?}"),
+            }
+            if started.elapsed() > Duration::from_secs(300) {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("action {action_id} on {ip} did not complete in time"),
+                ));
+            }
+            sleep(Duration::from_secs(3)).await;
+        }
+    }
+}
+
+fn to_address(v: &serde_json::Value) -> io::Result<Address> {
+    let ip = v["ip"]
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "reserved IP has no 'ip'"))?;
+    Ok(Address {
+        provider: NAME.to_string(),
+        id: ip.to_string(),
+        ip: ip.to_string(),
+    })
+}
+
+impl Provider for DigitalOcean {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move { http::fetch_metadata(&format!("{METADATA_URL}/id"), &[]).await })
+    }
+
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move {
+            if !self.reuse_unassigned {
+                return Ok(None);
+            }
+            let region = self.region().await?;
+            let resp = self.client.get("/reserved_ips?per_page=200").await?;
+            for v in resp["reserved_ips"].as_array().into_iter().flatten() {
+                if v["droplet"].is_null() && v["region"]["slug"].as_str() == Some(&region) {
+                    return Ok(Some(to_address(v)?));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            let region = self.region().await?;
+            log::info!("reserving IP in {region}");
+            let resp = self
+                .client
+                .post("/reserved_ips", serde_json::json!({ "region": region }))
+                .await?;
+            to_address(&resp["reserved_ip"])
+        })
+    }
+
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let resp = self
+                .client
+                .get(&format!("/reserved_ips/{}", addr.ip))
+                .await?;
+            Ok(resp["reserved_ip"]["droplet"]["id"]
+                .as_u64()
+                .map(|id| id.to_string()))
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, instance_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let droplet_id: u64 = instance_id.parse().map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid droplet ID '{instance_id}' ({})", e),
+                )
+            })?;
+            let resp = self
+                .client
+                .post(
+                    &format!("/reserved_ips/{}/actions", addr.ip),
+                    serde_json::json!({ "type": "assign", "droplet_id": droplet_id }),
+                )
+                .await?;
+            let action_id = resp["action"]["id"]
+                .as_u64()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "assign action has no 'id'"))?;
+            self.wait_action(&addr.ip, action_id).await
+        })
+    }
+}
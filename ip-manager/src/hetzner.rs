@@ -0,0 +1,316 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{sdk, secret};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::{
+    http::{self, Client},
+    iface,
+    provider::{self, Address, BoxFuture, Provider},
+};
+
+pub const NAME: &str = "hetzner";
+
+const API_URL: &str = "https://api.hetzner.cloud/v1";
+
+/// ref. <https://docs.hetzner.cloud/#server-metadata>
+const METADATA_URL: &str = "http://169.254.169.254/hetzner/v1/metadata";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages Hetzner Cloud addresses")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("floating-ip")
+                .about("Provisions the Floating IP to the local server")
+                .long_about(
+                    "
+
+The server ID and location are fetched from the metadata service.
+
+An unassigned Floating IP with the label in the same home location is claimed,
+or a new one is created with the label, unless the state file exists.
+
+Hetzner routes the Floating IP to the server without configuring it in the OS,
+so \"--configure-interface\" adds it to the interface (requires CAP_NET_ADMIN).
+
+Requires the API token with read and write permissions.
+
+e.g.,
+
+$ ip-manager hetzner floating-ip \
+--token-source=env:HCLOUD_TOKEN \
+--label-key=Kind \
+--label-value=ip-manager \
+--configure-interface=eth0 \
+--state-file-path=/data/floating-ip.yaml
+
+",
+                )
+                .arg(
+                    Arg::new("TOKEN_SOURCE")
+                        .long("token-source")
+                        .help("Sets where to read the API token (\"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\")")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("env:HCLOUD_TOKEN"),
+                )
+                .arg(
+                    Arg::new("IP_TYPE")
+                        .long("ip-type")
+                        .help("Sets the Floating IP type to create")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(["ipv4", "ipv6"])
+                        .default_value("ipv4"),
+                )
+                .arg(
+                    Arg::new("LABEL_KEY")
+                        .long("label-key")
+                        .help("Sets the label key to find and create tool-managed Floating IPs")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("Kind"),
+                )
+                .arg(
+                    Arg::new("LABEL_VALUE")
+                        .long("label-value")
+                        .help("Sets the label value to find and create tool-managed Floating IPs")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("ip-manager"),
+                )
+                .arg(
+                    Arg::new("CONFIGURE_INTERFACE")
+                        .long("configure-interface")
+                        .help("Sets the network interface to add the Floating IP to (e.g., eth0, empty to skip)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("NO_STEAL")
+                        .long("no-steal")
+                        .help("Aborts rather than re-assigning the Floating IP that is assigned to another server")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(bool))
+                        .default_value("true"),
+                )
+                .arg(
+                    Arg::new("STATE_FILE_PATH")
+                        .long("state-file-path")
+                        .help("Sets the file path to store the Floating IP information mapped to this volume path")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/data/floating-ip.yaml"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub token_source: String,
+    pub ip_type: String,
+    pub label_key: String,
+    pub label_value: String,
+    pub configure_interface: String,
+    pub no_steal: bool,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        token_source: matches
+            .get_one::<String>("TOKEN_SOURCE")
+            .unwrap_or(&String::from("env:HCLOUD_TOKEN"))
+            .clone(),
+        ip_type: matches
+            .get_one::<String>("IP_TYPE")
+            .unwrap_or(&String::from("ipv4"))
+            .clone(),
+        label_key: matches
+            .get_one::<String>("LABEL_KEY")
+            .unwrap_or(&String::from("Kind"))
+            .clone(),
+        label_value: matches
+            .get_one::<String>("LABEL_VALUE")
+            .unwrap_or(&String::from("ip-manager"))
+            .clone(),
+        configure_interface: matches
+            .get_one::<String>("CONFIGURE_INTERFACE")
+            .unwrap_or(&String::new())
+            .clone(),
+        no_steal: *matches.get_one::<bool>("NO_STEAL").unwrap_or(&true),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/floating-ip.yaml"))
+            .clone(),
+    }
+}
+
+pub async fn execute(opts: Flags) -> io::Result<Address> {
+    let token = secret::read(&opts.token_source, "HCLOUD_TOKEN", &sdk::Options::default()).await?;
+    let hetzner = Hetzner {
+        client: Client::new(
+            API_URL,
+            vec![(String::from("Authorization"), format!("Bearer {token}"))],
+        ),
+        ip_type: opts.ip_type.clone(),
+        label_key: opts.label_key.clone(),
+        label_value: opts.label_value.clone(),
+    };
+    let addr = provider::provision(&hetzner, &opts.state_file_path, opts.no_steal).await?;
+
+    if !opts.configure_interface.is_empty() {
+        // IPv6 Floating IP is a /64 network, so configure its first address
+        let cidr = match addr.ip.strip_suffix("/64") {
+            Some(prefix) => format!("{prefix}1/64"),
+            None => format!("{}/32", addr.ip),
+        };
+        iface::add_address(&opts.configure_interface, &cidr)?;
+    }
+    Ok(addr)
+}
+
+/// Hetzner Cloud Floating IP backend.
+/// ref. <https://docs.hetzner.cloud/#floating-ips>
+pub struct Hetzner {
+    client: Client,
+    ip_type: String,
+    label_key: String,
+    label_value: String,
+}
+
+impl Hetzner {
+    /// Returns the location of the local server (e.g., "fsn1" from "fsn1-dc14").
+    async fn location(&self) -> io::Result<String> {
+        let az = http::fetch_metadata(&format!("{METADATA_URL}/availability-zone"), &[]).await?;
+        Ok(az.split('-').next().unwrap_or_default().to_string())
+    }
+
+    async fn wait_action(&self, action_id: u64) -> io::Result<()> {
+        let started = Instant::now();
+        loop {
+            let resp = self.client.get(&format!("/actions/{action_id}")).await?;
+            match resp["action"]["status"].as_str() {
+                Some("success") => return Ok(()),
+                Some("error") => {
+                    return Err(Error::new(
+                        ErrorKind::Other,
+                        format!("action {action_id} failed {}", resp["action"]["error"]),
+                    ));
+                }
+                status => log::info!("action {action_id} is {status:?}"),
+            }
+            if started.elapsed() > Duration::from_secs(300) {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("action {action_id} did not complete in time"),
+                ));
+            }
+            sleep(Duration::from_secs(2)).await;
+        }
+    }
+}
+
+fn to_address(v: &serde_json::Value) -> io::Result<Address> {
+    let id = v["id"]
+        .as_u64()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "floating IP has no 'id'"))?;
+    let ip = v["ip"]
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "floating IP has no 'ip'"))?;
+    Ok(Address {
+        provider: NAME.to_string(),
+        id: id.to_string(),
+        ip: ip.to_string(),
+    })
+}
+
+impl Provider for Hetzner {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(
+            async move { http::fetch_metadata(&format!("{METADATA_URL}/instance-id"), &[]).await },
+        )
+    }
+
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move {
+            let location = self.location().await?;
+            let resp = self
+                .client
+                .get(&format!(
+                    "/floating_ips?label_selector={}=={}&per_page=50",
+                    self.label_key, self.label_value
+                ))
+                .await?;
+            for v in resp["floating_ips"].as_array().into_iter().flatten() {
+                if v["server"].is_null()
+                    && v["type"].as_str() == Some(&self.ip_type)
+                    && v["home_location"]["name"].as_str() == Some(&location)
+                {
+                    return Ok(Some(to_address(v)?));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            let location = self.location().await?;
+            log::info!("creating {} Floating IP in {location}", self.ip_type);
+            let resp = self
+                .client
+                .post(
+                    "/floating_ips",
+                    serde_json::json!({
+                        "type": self.ip_type,
+                        "home_location": location,
+                        "labels": { &self.label_key: &self.label_value },
+                    }),
+                )
+                .await?;
+            to_address(&resp["floating_ip"])
+        })
+    }
+
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let resp = self
+                .client
+                .get(&format!("/floating_ips/{}", addr.id))
+                .await?;
+            Ok(resp["floating_ip"]["server"]
+                .as_u64()
+                .map(|id| id.to_string()))
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, instance_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let server_id: u64 = instance_id.parse().map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid server ID '{instance_id}' ({})", e),
+                )
+            })?;
+            let resp = self
+                .client
+                .post(
+                    &format!("/floating_ips/{}/actions/assign", addr.id),
+                    serde_json::json!({ "server": server_id }),
+                )
+                .await?;
+            let action_id = resp["action"]["id"]
+                .as_u64()
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "assign action has no 'id'"))?;
+            self.wait_action(action_id).await
+        })
+    }
+}
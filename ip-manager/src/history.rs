@@ -0,0 +1,230 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_ip_provisioner::audit::{self, Entry, Filter, Holding};
+use clap::{Arg, ArgMatches, Command};
+use serde::Serialize;
+
+pub const NAME: &str = "history";
+
+pub const DEFAULT_AUDIT_LOG_FILE: &str = "/var/lib/ip-manager/audit.jsonl";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Lists the allocations, associations, and releases recorded in the audit log")
+        .long_about(
+            "
+
+Lists the allocations, associations, and releases of this host, as recorded
+in the audit log of \"aws eip --audit-log-file\", oldest first. Unlike the
+mounted EIP file, which only holds the current EIP, the audit log keeps every
+call with its timestamp and result, for the forensic history of the host.
+The audit log is the ledger of the host, so there is no separate database to
+enable (e.g., no \"--ledger-path\").
+
+With \"--address\", shows the timeline of which instance (and \"Id\") held
+the address when instead, assembled from the audit logs of \"--audit-log-file\"
+(e.g., collected from every host of the fleet), and with \"--at\", only who
+held it at the time, for the incident investigations.
+
+e.g.,
+
+$ ip-manager history --audit-log-file=/var/lib/ip-manager/audit.jsonl
+$ ip-manager history --allocation-id=eipalloc-... --since=7d
+$ ip-manager --output=json history --action=associate
+
+$ ip-manager history \
+--audit-log-file=/tmp/node-1.audit.jsonl,/tmp/node-2.audit.jsonl \
+--address=3.4.5.6 \
+--at=1673000000
+
+",
+        )
+        .arg(
+            Arg::new("AUDIT_LOG_FILE")
+                .long("audit-log-file")
+                .help("Sets the comma-separated audit log files (see \"aws eip --audit-log-file\")")
+                .required(false)
+                .num_args(1)
+                .default_value(DEFAULT_AUDIT_LOG_FILE),
+        )
+        .arg(
+            Arg::new("ALLOCATION_ID")
+                .long("allocation-id")
+                .help("Lists the calls of the allocation ID only")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ACTION")
+                .long("action")
+                .help("Lists the calls of the action only (\"allocate\", \"associate\", \"disassociate\", \"release\", or \"release_address\")")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("SINCE")
+                .long("since")
+                .help("Lists the calls since the age (e.g., \"24h\", \"7d\") or the seconds since the epoch (empty for all)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ADDRESS")
+                .long("address")
+                .help("Shows the timeline of who held the public IP instead of the calls")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("AT")
+                .long("at")
+                .help("Shows who held \"--address\" at the time, as the age (e.g., \"3h\") or the seconds since the epoch (empty for the whole timeline)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub audit_log_files: Vec<String>,
+    pub allocation_id: String,
+    pub action: String,
+    pub since: String,
+    pub address: String,
+    pub at: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    let get = |id: &str| matches.get_one::<String>(id).cloned().unwrap_or_default();
+    Flags {
+        audit_log_files: get("AUDIT_LOG_FILE")
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect(),
+        allocation_id: get("ALLOCATION_ID"),
+        action: get("ACTION"),
+        since: get("SINCE"),
+        address: get("ADDRESS"),
+        at: get("AT"),
+    }
+}
+
+/// Prints the matching audit log entries, or the timeline of "--address",
+/// as JSON if "output" is "json".
+pub fn execute(opts: Flags, output: &str) -> io::Result<()> {
+    if opts.audit_log_files.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "no --audit-log-file specified",
+        ));
+    }
+    if !opts.action.is_empty() && !audit::HISTORY_ACTIONS.contains(&opts.action.as_str()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "unknown action '{}' (expected one of {:?})",
+                opts.action,
+                audit::HISTORY_ACTIONS
+            ),
+        ));
+    }
+    if !opts.at.is_empty() && opts.address.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--at requires --address",
+        ));
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    if !opts.address.is_empty() {
+        let mut audit_logs = Vec::new();
+        for path in opts.audit_log_files.iter() {
+            audit_logs.push((path.clone(), audit::query(path, &Filter::default())?));
+        }
+        let mut holdings = audit::timeline(&opts.address, &audit_logs);
+        if !opts.at.is_empty() {
+            let at = audit::parse_since(&opts.at, now)?;
+            holdings.retain(|h| h.held_at(at));
+        }
+        return print(output, &holdings, || print_timeline(&holdings));
+    }
+
+    let filter = Filter {
+        allocation_id: opts.allocation_id.clone(),
+        public_ip: String::new(),
+        action: opts.action.clone(),
+        since: audit::parse_since(&opts.since, now)?,
+    };
+    let mut entries = Vec::new();
+    for path in opts.audit_log_files.iter() {
+        entries.extend(audit::query(path, &filter)?);
+    }
+    // stable, so the calls of the same second keep their order in each audit log
+    entries.sort_by_key(|e| e.ts);
+    print(output, &entries, || print_table(&entries))
+}
+
+/// Prints the value as JSON if "output" is "json", otherwise with "print_text".
+fn print<T: Serialize + ?Sized>(output: &str, v: &T, print_text: impl Fn()) -> io::Result<()> {
+    if output != "json" {
+        print_text();
+        return Ok(());
+    }
+    let d = serde_json::to_string_pretty(v).map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to serialize history {}", e),
+        )
+    })?;
+    println!("{d}");
+    Ok(())
+}
+
+fn print_table(entries: &[Entry]) {
+    println!(
+        "{:<12} {:<16} {:<28} {:<16} {:<22} {:<22} {:<8}",
+        "TS", "ACTION", "ALLOCATION ID", "PUBLIC IP", "BEFORE", "AFTER", "RESULT"
+    );
+    for e in entries.iter() {
+        println!(
+            "{:<12} {:<16} {:<28} {:<16} {:<22} {:<22} {:<8}",
+            e.ts, e.action, e.allocation_id, e.public_ip, e.before, e.after, e.result
+        );
+        if !e.error.is_empty() {
+            println!("  error: {}", e.error);
+        }
+    }
+}
+
+fn print_timeline(holdings: &[Holding]) {
+    println!(
+        "{:<12} {:<12} {:<22} {:<24} {:<28} AUDIT LOG",
+        "FROM", "UNTIL", "HOLDER", "ID", "ALLOCATION ID"
+    );
+    for h in holdings.iter() {
+        println!(
+            "{:<12} {:<12} {:<22} {:<24} {:<28} {}",
+            h.from,
+            h.until
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| String::from("-")),
+            h.holder,
+            h.id,
+            h.allocation_id,
+            h.audit_log_file
+        );
+    }
+}
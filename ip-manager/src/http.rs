@@ -0,0 +1,158 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::tls;
+use hyper::{body, client::HttpConnector, Body, HeaderMap, Method, Request, StatusCode};
+use hyper_rustls::HttpsConnector;
+use tokio::time::{timeout, Duration};
+
+/// JSON REST client for the provider APIs.
+#[derive(Clone)]
+pub struct Client {
+    inner: hyper::Client<HttpsConnector<HttpConnector>>,
+    base_url: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl Client {
+    /// Creates a client with the base URL (e.g., "https://api.digitalocean.com/v2")
+    /// and the headers sent with every request (e.g., "Authorization").
+    pub fn new(base_url: &str, headers: Vec<(String, String)>) -> Self {
+        let https = tls::https_connector();
+        Self {
+            inner: hyper::Client::builder().build(https),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            headers,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Sends the request with the optional JSON body to the path (e.g., "/reserved_ips"),
+    /// and returns the JSON response ("Null" for an empty body).
+    /// Returns "ErrorKind::NotFound" for 404.
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> io::Result<serde_json::Value> {
+        self.request_with_headers(method, path, body)
+            .await
+            .map(|(_, v)| v)
+    }
+
+    /// Same as "request" but also returns the response headers
+    /// (e.g., OpenStack Keystone "X-Subject-Token").
+    pub async fn request_with_headers(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> io::Result<(HeaderMap, serde_json::Value)> {
+        let uri = format!("{}{path}", self.base_url);
+        let mut req = Request::builder().method(method.clone()).uri(&uri);
+        for (k, v) in self.headers.iter() {
+            req = req.header(k, v);
+        }
+        let req = match body {
+            Some(v) => req
+                .header("Content-Type", "application/json")
+                .body(Body::from(v.to_string())),
+            None => req.body(Body::empty()),
+        }
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to build {method} {uri} {}", e),
+            )
+        })?;
+
+        log::debug!("sending {method} {uri}");
+        let resp = timeout(self.timeout, self.inner.request(req))
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, format!("{method} {uri} timed out")))?
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed {method} {uri} {}", e)))?;
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let bytes = body::to_bytes(resp.into_body()).await.map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to read {method} {uri} response {}", e),
+            )
+        })?;
+        if status == StatusCode::NOT_FOUND {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{method} {uri} not found"),
+            ));
+        }
+        if !status.is_success() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "{method} {uri} returned {status} '{}'",
+                    String::from_utf8_lossy(&bytes)
+                ),
+            ));
+        }
+        if bytes.is_empty() {
+            return Ok((headers, serde_json::Value::Null));
+        }
+        let v = serde_json::from_slice(&bytes).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse {method} {uri} response {}", e),
+            )
+        })?;
+        Ok((headers, v))
+    }
+
+    pub async fn get(&self, path: &str) -> io::Result<serde_json::Value> {
+        self.request(Method::GET, path, None).await
+    }
+
+    pub async fn post(&self, path: &str, body: serde_json::Value) -> io::Result<serde_json::Value> {
+        self.request(Method::POST, path, Some(body)).await
+    }
+
+    pub async fn put(&self, path: &str, body: serde_json::Value) -> io::Result<serde_json::Value> {
+        self.request(Method::PUT, path, Some(body)).await
+    }
+}
+
+/// Fetches the plain text from the link-local metadata service (e.g., DigitalOcean droplet ID).
+pub async fn fetch_metadata(url: &str, headers: &[(&str, &str)]) -> io::Result<String> {
+    metadata_request(Method::GET, url, headers).await
+}
+
+/// Sends the request to the link-local metadata service, and returns the plain text
+/// (e.g., PUT for the Linode metadata token).
+pub async fn metadata_request(
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+) -> io::Result<String> {
+    let mut req = Request::builder().method(method).uri(url);
+    for (k, v) in headers {
+        req = req.header(*k, *v);
+    }
+    let req = req
+        .body(Body::empty())
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to build GET {url} {}", e)))?;
+    let resp = timeout(Duration::from_secs(2), hyper::Client::new().request(req))
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, format!("{url} timed out")))?
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed {url} {}", e)))?;
+    let status = resp.status();
+    let bytes = body::to_bytes(resp.into_body())
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to read {url} {}", e)))?;
+    let text = String::from_utf8_lossy(&bytes).trim().to_string();
+    if status != StatusCode::OK {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("{url} returned {status} '{text}'"),
+        ));
+    }
+    Ok(text)
+}
@@ -0,0 +1,133 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    process::Command,
+};
+
+/// Adds the address (e.g., "203.0.113.1/32") to the network interface with "ip addr add",
+/// for providers that route the floating address to the instance
+/// without configuring it in the guest OS (e.g., Hetzner).
+/// No-op if the interface already has the address.
+pub fn add_address(dev: &str, cidr: &str) -> io::Result<()> {
+    let ip = cidr.split('/').next().unwrap_or_default();
+    if has_address(dev, ip)? {
+        log::info!("interface {dev} already has {ip}");
+        return Ok(());
+    }
+
+    log::info!("adding {cidr} to interface {dev}");
+    #[cfg(not(windows))]
+    run_ip(&["addr", "add", cidr, "dev", dev])?;
+    #[cfg(windows)]
+    run_netsh(&netsh_args("add", dev, cidr))?;
+    Ok(())
+}
+
+/// Removes the address from the network interface with "ip addr del".
+/// No-op if the interface does not have the address.
+pub fn remove_address(dev: &str, cidr: &str) -> io::Result<()> {
+    let ip = cidr.split('/').next().unwrap_or_default();
+    if !has_address(dev, ip)? {
+        log::info!("interface {dev} does not have {ip}");
+        return Ok(());
+    }
+
+    log::info!("removing {cidr} from interface {dev}");
+    #[cfg(not(windows))]
+    run_ip(&["addr", "del", cidr, "dev", dev])?;
+    #[cfg(windows)]
+    run_netsh(&netsh_args("delete", dev, cidr))?;
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn has_address(dev: &str, ip: &str) -> io::Result<bool> {
+    let existing = run_ip(&["-o", "addr", "show", "dev", dev])?;
+    Ok(existing
+        .split_whitespace()
+        .any(|v| v.split('/').next() == Some(ip)))
+}
+
+/// Runs the "ip" command, returning its stdout.
+#[cfg(not(windows))]
+fn run_ip(args: &[&str]) -> io::Result<String> {
+    let out = Command::new("ip").args(args).output()?;
+    if !out.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed 'ip {}' {}",
+                args.join(" "),
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+#[cfg(windows)]
+fn has_address(dev: &str, ip: &str) -> io::Result<bool> {
+    let family = if ip.contains(':') { "ipv6" } else { "ipv4" };
+    let existing = run_netsh(&[
+        "interface".to_string(),
+        family.to_string(),
+        "show".to_string(),
+        "addresses".to_string(),
+        format!("interface={dev}"),
+    ])?;
+    Ok(existing
+        .split_whitespace()
+        .any(|v| v.split('%').next() == Some(ip)))
+}
+
+/// Returns the "netsh" arguments to add or delete the address on the interface
+/// (the Windows counterpart of "ip addr add|del"), e.g.,
+/// "interface ipv4 add address name=Ethernet address=203.0.113.1 mask=255.255.255.255 skipassource=true".
+/// The address is never the source of the outbound connections, as on Linux
+/// for the "/32" of the floating address.
+pub fn netsh_args(op: &str, dev: &str, cidr: &str) -> Vec<String> {
+    let (ip, prefix_len) = cidr.split_once('/').unwrap_or((cidr, ""));
+    let mut args = vec!["interface".to_string()];
+    if ip.contains(':') {
+        args.extend([
+            "ipv6".to_string(),
+            op.to_string(),
+            "address".to_string(),
+            format!("interface={dev}"),
+            format!("address={ip}"),
+        ]);
+    } else {
+        let prefix_len = prefix_len.parse::<u32>().unwrap_or(32).min(32);
+        let mask = std::net::Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0));
+        args.extend([
+            "ipv4".to_string(),
+            op.to_string(),
+            "address".to_string(),
+            format!("name={dev}"),
+            format!("address={ip}"),
+        ]);
+        if op == "add" {
+            args.push(format!("mask={mask}"));
+        }
+    }
+    if op == "add" {
+        args.push("skipassource=true".to_string());
+    }
+    args
+}
+
+/// Runs the "netsh" command, returning its stdout.
+#[cfg(windows)]
+fn run_netsh(args: &[String]) -> io::Result<String> {
+    let out = Command::new("netsh").args(args).output()?;
+    if !out.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed 'netsh {}' {}",
+                args.join(" "),
+                String::from_utf8_lossy(&out.stdout).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
@@ -0,0 +1,477 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    sync::{Arc, Mutex},
+};
+
+use aws_ip_provisioner::{checkpoint::Checkpoint, ec2, eip, pipeline, pool, ratelimit, sdk};
+use aws_manager::sts;
+use aws_sdk_ec2::model::{Address, Filter};
+use aws_types::SdkConfig;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use serde::{Deserialize, Serialize};
+
+pub const NAME: &str = "inventory";
+
+/// Organizations is a global service with the endpoint in "us-east-1".
+/// ref. <https://docs.aws.amazon.com/general/latest/gr/ao.html>
+const ORGANIZATIONS_REGION: &str = "us-east-1";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Lists all the tool-managed EIPs, in every member account of the organization with \"--org\"")
+        .long_about(
+            "
+
+Lists the tool-managed EIPs (with the \"Kind\" tag), associated or not,
+with the account, region, tags, pool status, and the associated instance.
+
+With \"--org\", lists the active member accounts of the AWS Organization
+(\"organizations:ListAccounts\", from the management or the delegated
+administrator account), assumes \"--audit-role-name\" in each of them,
+and aggregates the EIPs into one report. The caller's own account is
+described with the caller's credentials. The audit role only needs
+\"ec2:DescribeAddresses\". Accounts that fail (e.g., no such role) are
+reported without failing the others.
+
+On a large organization, \"--checkpoint-file\" saves the EIPs of each
+account as soon as it is described, so that an interrupted run (or a run
+with failed accounts) resumes with the same flags, describing only the
+accounts not yet done. The file is removed once all accounts succeed.
+\"--max-parallel-accounts\" bounds the accounts described at once, and
+\"--max-api-rps-per-account\" the API calls to each account and region,
+so that the inventory never throttles the workloads in the accounts.
+
+e.g.,
+
+$ ip-manager inventory \
+--kind-tag-key=Kind \
+--kind-tag-value=aws-ip-provisioner
+
+$ ip-manager inventory \
+--org \
+--audit-role-name=ip-manager-audit \
+--format=csv > eips.csv
+
+$ ip-manager inventory \
+--org \
+--checkpoint-file=/tmp/inventory.checkpoint.json \
+--max-api-rps-per-account=5
+
+",
+        )
+        .arg(
+            Arg::new("ID_TAG_KEY")
+                .long("id-tag-key")
+                .help("Sets the key of the EIP tag that identifies the node")
+                .required(false)
+                .num_args(1)
+                .default_value("Id"),
+        )
+        .arg(
+            Arg::new("KIND_TAG_KEY")
+                .long("kind-tag-key")
+                .help("Sets the key of the EIP tag that groups the tool-managed EIPs")
+                .required(false)
+                .num_args(1)
+                .default_value("Kind"),
+        )
+        .arg(
+            Arg::new("KIND_TAG_VALUE")
+                .long("kind-tag-value")
+                .help("Sets the value of the EIP tag that groups the tool-managed EIPs (empty for all kinds)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ORG")
+                .long("org")
+                .help("Lists the EIPs in all the active member accounts of the organization")
+                .required(false)
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("AUDIT_ROLE_NAME")
+                .long("audit-role-name")
+                .help("Sets the name of the IAM role to assume in each member account with \"--org\"")
+                .required(false)
+                .num_args(1)
+                .default_value("OrganizationAccountAccessRole"),
+        )
+        .arg(
+            Arg::new("CHECKPOINT_FILE")
+                .long("checkpoint-file")
+                .help("Sets the file to save the progress to, resumed by the next run with the same flags (empty to disable)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("MAX_PARALLEL_ACCOUNTS")
+                .long("max-parallel-accounts")
+                .help("Sets the maximum number of accounts to describe at once with \"--org\"")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(usize))
+                .default_value("8"),
+        )
+        .arg(
+            Arg::new("MAX_API_RPS_PER_ACCOUNT")
+                .long("max-api-rps-per-account")
+                .help("Sets the maximum number of AWS API requests per second to each account and region (0 to disable)")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("0"),
+        )
+        .arg(
+            Arg::new("FORMAT")
+                .long("format")
+                .help("Sets the report format (\"text\" follows \"--output\")")
+                .required(false)
+                .num_args(1)
+                .value_parser(["text", "json", "csv"])
+                .default_value("text"),
+        )
+}
+
+/// Defines flag options.
+#[derive(Clone)]
+pub struct Flags {
+    pub id_tag_key: String,
+    pub kind_tag_key: String,
+    pub kind_tag_value: String,
+    pub org: bool,
+    pub audit_role_name: String,
+    pub checkpoint_file: String,
+    pub max_parallel_accounts: usize,
+    pub max_api_rps_per_account: u32,
+    pub format: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        id_tag_key: matches
+            .get_one::<String>("ID_TAG_KEY")
+            .unwrap_or(&String::from("Id"))
+            .clone(),
+        kind_tag_key: matches
+            .get_one::<String>("KIND_TAG_KEY")
+            .unwrap_or(&String::from("Kind"))
+            .clone(),
+        kind_tag_value: matches
+            .get_one::<String>("KIND_TAG_VALUE")
+            .unwrap_or(&String::new())
+            .clone(),
+        org: matches.get_flag("ORG"),
+        audit_role_name: matches
+            .get_one::<String>("AUDIT_ROLE_NAME")
+            .unwrap_or(&String::from("OrganizationAccountAccessRole"))
+            .clone(),
+        checkpoint_file: matches
+            .get_one::<String>("CHECKPOINT_FILE")
+            .unwrap_or(&String::new())
+            .clone(),
+        max_parallel_accounts: *matches
+            .get_one::<usize>("MAX_PARALLEL_ACCOUNTS")
+            .unwrap_or(&8),
+        max_api_rps_per_account: *matches
+            .get_one::<u32>("MAX_API_RPS_PER_ACCOUNT")
+            .unwrap_or(&0),
+        format: matches
+            .get_one::<String>("FORMAT")
+            .unwrap_or(&String::from("text"))
+            .clone(),
+    }
+}
+
+/// Tool-managed EIP in the account.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    pub account_id: String,
+    pub region: String,
+    pub allocation_id: String,
+    pub public_ip: String,
+    pub kind: String,
+    pub id: String,
+    pub pool_status: String,
+    /// Empty if not associated.
+    pub instance_id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Inventory {
+    /// Accounts described.
+    pub accounts: Vec<String>,
+    pub addresses: Vec<Entry>,
+    /// Accounts that failed (e.g., no audit role), reported rather than failing the others.
+    pub errors: Vec<String>,
+}
+
+/// Prints the inventory in "--format", or as JSON if "output" is "json".
+pub async fn execute(opts: Flags, output: &str) -> io::Result<()> {
+    let sdk_opts = sdk::Options::default();
+    let shared_config = sdk::load_config(None, &sdk_opts).await?;
+    let identity = sts::Manager::new(&shared_config)
+        .get_identity()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!(
+                    "failed sts.get_identity {} (retryable {})",
+                    e.message(),
+                    e.is_retryable()
+                ),
+            )
+        })?;
+    let accounts = if opts.org {
+        list_accounts(&shared_config, &sdk_opts).await?
+    } else {
+        vec![identity.account_id.clone()]
+    };
+    // e.g., "aws-us-gov" in "arn:aws-us-gov:sts::..."
+    let partition = identity.role_arn.split(':').nth(1).unwrap_or("aws");
+
+    let filter = if opts.kind_tag_value.is_empty() {
+        Filter::builder()
+            .name("tag-key")
+            .values(&opts.kind_tag_key)
+            .build()
+    } else {
+        Filter::builder()
+            .name(format!("tag:{}", opts.kind_tag_key))
+            .values(&opts.kind_tag_value)
+            .build()
+    };
+    let region = shared_config
+        .region()
+        .map(|r| r.to_string())
+        .unwrap_or_default();
+    sdk_opts.ratelimit.init_scoped(opts.max_api_rps_per_account);
+    let checkpoint = Checkpoint::open(
+        &opts.checkpoint_file,
+        &format!(
+            "{NAME} org={} region={region} kind={}={} id={}",
+            opts.org, opts.kind_tag_key, opts.kind_tag_value, opts.id_tag_key
+        ),
+    )?;
+    let mut inventory = Inventory {
+        accounts: accounts.clone(),
+        addresses: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    let mut pending = Vec::new();
+    for account_id in accounts.iter() {
+        match checkpoint.get::<Vec<Entry>>(account_id) {
+            Some(entries) => inventory.addresses.extend(entries),
+            None => pending.push(account_id.clone()),
+        }
+    }
+    let checkpoint = Arc::new(Mutex::new(checkpoint));
+    let mut tasks = Vec::new();
+    for account_id in pending.iter() {
+        let config = if *account_id == identity.account_id {
+            shared_config.clone()
+        } else {
+            let role_arn = format!(
+                "arn:{partition}:iam::{account_id}:role/{}",
+                opts.audit_role_name
+            );
+            sdk::assume_role(&shared_config, &sdk_opts, &role_arn)?
+        };
+        let (filter, sdk_opts) = (filter.clone(), sdk_opts.clone());
+        let (opts, account_id, region) = (opts.clone(), account_id.clone(), region.clone());
+        let checkpoint = checkpoint.clone();
+        tasks.push(ratelimit::scoped(
+            format!("{account_id}/{region}"),
+            async move {
+                let ec2_manager = ec2::Manager::new(&sdk::for_service(&config, "ec2", &sdk_opts)?);
+                let entries: Vec<Entry> = eip::describe(&ec2_manager, vec![filter])
+                    .await?
+                    .iter()
+                    .map(|a| entry(&opts, &account_id, &region, a))
+                    .collect();
+                checkpoint.lock().unwrap().record(&account_id, &entries)?;
+                Ok(entries)
+            },
+        ));
+    }
+
+    for (account_id, ret) in pending
+        .iter()
+        .zip(pipeline::run_bounded(opts.max_parallel_accounts, tasks).await)
+    {
+        match ret {
+            Ok(entries) => inventory.addresses.extend(entries),
+            Err(e) if accounts.len() == 1 => return Err(e),
+            Err(e) => {
+                log::warn!("failed to describe EIPs in account {account_id} '{}'", e);
+                inventory.errors.push(format!("{account_id}: {}", e));
+            }
+        }
+    }
+    let checkpoint = checkpoint.lock().unwrap();
+    if inventory.errors.is_empty() {
+        checkpoint.finish()?;
+    } else if !opts.checkpoint_file.is_empty() {
+        log::warn!(
+            "{} accounts failed -- rerun with --checkpoint-file={} to retry only them",
+            inventory.errors.len(),
+            opts.checkpoint_file
+        );
+    }
+    inventory.addresses.sort_by(|a, b| {
+        (&a.account_id, &a.kind, &a.id, &a.allocation_id).cmp(&(
+            &b.account_id,
+            &b.kind,
+            &b.id,
+            &b.allocation_id,
+        ))
+    });
+
+    let format = if opts.format == "text" {
+        output
+    } else {
+        opts.format.as_str()
+    };
+    match format {
+        "json" => {
+            let d = serde_json::to_string_pretty(&inventory).map_err(|e| {
+                Error::new(
+                    ErrorKind::Other,
+                    format!("failed to serialize inventory {}", e),
+                )
+            })?;
+            println!("{d}");
+        }
+        "csv" => print!("{}", to_csv(&inventory.addresses)),
+        _ => {
+            println!(
+                "{:<14} {:<14} {:<24} {:<24} {:<28} {:<16} {:<10} INSTANCE",
+                "ACCOUNT", "REGION", "KIND", "ID", "ALLOCATION", "PUBLIC IP", "POOL"
+            );
+            for e in inventory.addresses.iter() {
+                println!(
+                    "{:<14} {:<14} {:<24} {:<24} {:<28} {:<16} {:<10} {}",
+                    e.account_id,
+                    e.region,
+                    e.kind,
+                    e.id,
+                    e.allocation_id,
+                    e.public_ip,
+                    e.pool_status,
+                    e.instance_id
+                );
+            }
+            println!(
+                "\n{} EIPs in {} accounts",
+                inventory.addresses.len(),
+                inventory.accounts.len()
+            );
+            for e in inventory.errors.iter() {
+                println!("failed to describe {e}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the IDs of the active member accounts, following "NextToken".
+/// ref. <https://docs.aws.amazon.com/organizations/latest/APIReference/API_ListAccounts.html>
+async fn list_accounts(shared_config: &SdkConfig, opts: &sdk::Options) -> io::Result<Vec<String>> {
+    let opts = sdk::Options {
+        // no dual-stack endpoint for Organizations
+        use_dual_stack: false,
+        ..opts.clone()
+    };
+    let mut accounts = Vec::new();
+    let mut next_token = None;
+    loop {
+        let mut req = serde_json::json!({});
+        if let Some(token) = next_token {
+            req["NextToken"] = serde_json::Value::String(token);
+        }
+        let resp = sdk::call_json(
+            shared_config,
+            &opts,
+            ORGANIZATIONS_REGION,
+            "organizations",
+            "AWSOrganizationsV20161128.ListAccounts",
+            req,
+        )
+        .await?;
+        for a in resp["Accounts"].as_array().into_iter().flatten() {
+            if a["Status"] != "ACTIVE" {
+                continue;
+            }
+            if let Some(id) = a["Id"].as_str() {
+                accounts.push(id.to_string());
+            }
+        }
+        next_token = resp["NextToken"].as_str().map(|s| s.to_string());
+        if next_token.is_none() {
+            break;
+        }
+    }
+    log::info!(
+        "listed {} active accounts in the organization",
+        accounts.len()
+    );
+    Ok(accounts)
+}
+
+fn entry(opts: &Flags, account_id: &str, region: &str, addr: &Address) -> Entry {
+    Entry {
+        account_id: account_id.to_string(),
+        region: region.to_string(),
+        allocation_id: addr.allocation_id().unwrap_or_default().to_string(),
+        public_ip: addr.public_ip().unwrap_or_default().to_string(),
+        kind: tag(addr, &opts.kind_tag_key),
+        id: tag(addr, &opts.id_tag_key),
+        pool_status: tag(addr, pool::STATUS_TAG_KEY),
+        instance_id: addr.instance_id().unwrap_or_default().to_string(),
+    }
+}
+
+/// Renders the entries as CSV with the header (RFC 4180 quoting).
+fn to_csv(entries: &[Entry]) -> String {
+    let mut out =
+        String::from("account_id,region,allocation_id,public_ip,kind,id,pool_status,instance_id\n");
+    for e in entries {
+        let fields = [
+            &e.account_id,
+            &e.region,
+            &e.allocation_id,
+            &e.public_ip,
+            &e.kind,
+            &e.id,
+            &e.pool_status,
+            &e.instance_id,
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn tag(addr: &Address, key: &str) -> String {
+    addr.tags()
+        .unwrap_or_default()
+        .iter()
+        .find(|t| t.key() == Some(key))
+        .and_then(|t| t.value())
+        .unwrap_or_default()
+        .to_string()
+}
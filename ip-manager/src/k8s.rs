@@ -0,0 +1,285 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    path::Path,
+};
+
+use aws_ip_provisioner::command as aws_eip;
+use clap::{Arg, ArgMatches, Command};
+use serde_json::{json, Value};
+
+pub const NAME: &str = "generate-k8s";
+
+/// Token file that the EKS pod identity webhook projects for IRSA.
+/// ref. https://docs.aws.amazon.com/eks/latest/userguide/iam-roles-for-service-accounts.html
+const IRSA_TOKEN_FILE: &str = "/var/run/secrets/eks.amazonaws.com/serviceaccount/token";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Prints the Kubernetes manifests to run \"aws eip\" with the flags")
+        .long_about(
+            "
+
+Renders the ready-to-apply manifests for the trailing \"aws eip\" flags:
+the ServiceAccount (annotated with \"--role-arn\" for IRSA), the DaemonSet
+(one EIP per node, \"--mode=daemon\" by default) or the Job, and the hostPath
+or PersistentVolumeClaim for the directory of \"--mounted-eip-file-path\".
+
+With \"--role-arn\", the web identity token file defaults to the one that
+EKS projects into the pod.
+
+e.g.,
+
+$ ip-manager generate-k8s \
+--mode=daemonset \
+--namespace=kube-system \
+--image=example.com/ip-manager:latest \
+-- \
+--id-tag-value=TEST-ID \
+--kind-tag-value=aws-ip-provisioner \
+--role-arn=arn:aws:iam::123456789012:role/ip-manager \
+| kubectl apply -f -
+
+",
+        )
+        .arg(
+            Arg::new("MODE")
+                .long("mode")
+                .help("Sets the workload kind (\"daemonset\" for every node, \"job\" for a one-off provision)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["daemonset", "job"])
+                .default_value("daemonset"),
+        )
+        .arg(
+            Arg::new("NAMESPACE")
+                .long("namespace")
+                .help("Sets the namespace of the resources")
+                .required(false)
+                .num_args(1)
+                .default_value("kube-system"),
+        )
+        .arg(
+            Arg::new("NAME")
+                .long("name")
+                .help("Sets the name of the resources")
+                .required(false)
+                .num_args(1)
+                .default_value("ip-manager"),
+        )
+        .arg(
+            Arg::new("IMAGE")
+                .long("image")
+                .help("Sets the container image with the \"ip-manager\" binary")
+                .required(true)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("STATE_VOLUME")
+                .long("state-volume")
+                .help("Sets the volume of the state file (\"host-path\" to keep it on the node, \"pvc\" only for \"job\")")
+                .required(false)
+                .num_args(1)
+                .value_parser(["host-path", "pvc"])
+                .default_value("host-path"),
+        )
+        .arg(
+            Arg::new("STATE_STORAGE_SIZE")
+                .long("state-storage-size")
+                .help("Sets the requested storage of the PersistentVolumeClaim (only used for \"--state-volume=pvc\")")
+                .required(false)
+                .num_args(1)
+                .default_value("1Gi"),
+        )
+        .arg(
+            Arg::new("STATE_STORAGE_CLASS")
+                .long("state-storage-class")
+                .help("Sets the storage class of the PersistentVolumeClaim (only used for \"--state-volume=pvc\", empty for the default)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ARGS")
+                .help("Sets the flags of \"aws eip\" to run with")
+                .required(false)
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub mode: String,
+    pub namespace: String,
+    pub name: String,
+    pub image: String,
+    pub state_volume: String,
+    pub state_storage_size: String,
+    pub state_storage_class: String,
+    pub args: Vec<String>,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        mode: matches
+            .get_one::<String>("MODE")
+            .unwrap_or(&String::from("daemonset"))
+            .clone(),
+        namespace: matches
+            .get_one::<String>("NAMESPACE")
+            .unwrap_or(&String::from("kube-system"))
+            .clone(),
+        name: matches
+            .get_one::<String>("NAME")
+            .unwrap_or(&String::from("ip-manager"))
+            .clone(),
+        image: matches
+            .get_one::<String>("IMAGE")
+            .unwrap_or(&String::new())
+            .clone(),
+        state_volume: matches
+            .get_one::<String>("STATE_VOLUME")
+            .unwrap_or(&String::from("host-path"))
+            .clone(),
+        state_storage_size: matches
+            .get_one::<String>("STATE_STORAGE_SIZE")
+            .unwrap_or(&String::from("1Gi"))
+            .clone(),
+        state_storage_class: matches
+            .get_one::<String>("STATE_STORAGE_CLASS")
+            .unwrap_or(&String::new())
+            .clone(),
+        args: matches
+            .get_many::<String>("ARGS")
+            .map(|args| args.cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+pub fn execute(opts: Flags) -> io::Result<()> {
+    print!("{}", render(&opts)?);
+    Ok(())
+}
+
+/// Returns the multi-document YAML of the manifests.
+pub fn render(opts: &Flags) -> io::Result<String> {
+    if opts.mode == "daemonset" && opts.state_volume == "pvc" {
+        // one claim cannot be mounted on every node, and the EIP is per node anyway
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--state-volume=pvc is only supported with --mode=job",
+        ));
+    }
+
+    let mut argv = vec![aws_eip::NAME.to_string()];
+    argv.extend(opts.args.iter().cloned());
+    let eip_opts =
+        aws_eip::parse_flags(&aws_eip::new().try_get_matches_from(&argv).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid \"aws eip\" flags {}", e),
+            )
+        })?);
+
+    let mut args = vec![String::from("aws"), String::from("eip")];
+    args.extend(opts.args.iter().cloned());
+    if opts.mode == "daemonset" && !has_flag(&opts.args, "--mode") {
+        args.push(String::from("--mode=daemon"));
+    }
+    if !eip_opts.role_arn.is_empty() && eip_opts.web_identity_token_file.is_empty() {
+        args.push(format!("--web-identity-token-file={IRSA_TOKEN_FILE}"));
+    }
+
+    let state_dir = Path::new(&eip_opts.mounted_eip_file_path)
+        .parent()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| String::from("/"));
+    let volume = if opts.state_volume == "pvc" {
+        json!({"name": "state", "persistentVolumeClaim": {"claimName": format!("{}-state", opts.name)}})
+    } else {
+        json!({"name": "state", "hostPath": {"path": state_dir, "type": "DirectoryOrCreate"}})
+    };
+
+    let labels = json!({"app.kubernetes.io/name": opts.name});
+    let mut command = vec![String::from("ip-manager")];
+    command.extend(args);
+    let pod_spec = json!({
+        "serviceAccountName": opts.name,
+        // for IMDS and the instance's own ENIs, as the EIP belongs to the node
+        "hostNetwork": opts.mode == "daemonset",
+        "restartPolicy": if opts.mode == "daemonset" { "Always" } else { "OnFailure" },
+        "containers": [{
+            "name": "ip-manager",
+            "image": opts.image,
+            "command": command,
+            "volumeMounts": [{"name": "state", "mountPath": state_dir}],
+        }],
+        "volumes": [volume],
+    });
+    let template = json!({"metadata": {"labels": labels}, "spec": pod_spec});
+
+    let mut service_account = json!({
+        "apiVersion": "v1",
+        "kind": "ServiceAccount",
+        "metadata": {"name": opts.name, "namespace": opts.namespace},
+    });
+    if !eip_opts.role_arn.is_empty() {
+        service_account["metadata"]["annotations"] =
+            json!({"eks.amazonaws.com/role-arn": eip_opts.role_arn});
+    }
+    let mut docs = vec![service_account];
+
+    if opts.state_volume == "pvc" {
+        let mut spec = json!({
+            "accessModes": ["ReadWriteOnce"],
+            "resources": {"requests": {"storage": opts.state_storage_size}},
+        });
+        if !opts.state_storage_class.is_empty() {
+            spec["storageClassName"] = json!(opts.state_storage_class);
+        }
+        docs.push(json!({
+            "apiVersion": "v1",
+            "kind": "PersistentVolumeClaim",
+            "metadata": {"name": format!("{}-state", opts.name), "namespace": opts.namespace},
+            "spec": spec,
+        }));
+    }
+
+    docs.push(if opts.mode == "daemonset" {
+        json!({
+            "apiVersion": "apps/v1",
+            "kind": "DaemonSet",
+            "metadata": {"name": opts.name, "namespace": opts.namespace, "labels": labels},
+            "spec": {"selector": {"matchLabels": labels}, "template": template},
+        })
+    } else {
+        json!({
+            "apiVersion": "batch/v1",
+            "kind": "Job",
+            "metadata": {"name": opts.name, "namespace": opts.namespace, "labels": labels},
+            "spec": {"backoffLimit": 6, "template": template},
+        })
+    });
+
+    let mut d = String::new();
+    for doc in docs.iter() {
+        d.push_str("---\n");
+        d.push_str(&to_yaml(doc)?);
+    }
+    Ok(d)
+}
+
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter()
+        .any(|a| a == flag || a.starts_with(&format!("{flag}=")))
+}
+
+fn to_yaml(v: &Value) -> io::Result<String> {
+    serde_yaml::to_string(v).map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to serialize manifest {}", e),
+        )
+    })
+}
@@ -0,0 +1,446 @@
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+    net::IpAddr,
+    path::Path,
+    process::Command as Process,
+};
+
+use aws_ip_provisioner::{sdk, secret};
+use clap::{value_parser, Arg, ArgMatches, Command};
+
+use crate::provider::Address;
+
+pub const NAME: &str = "keepalived";
+
+/// Name of the rendered health check "vrrp_script".
+const SCRIPT_NAME: &str = "chk_ip_manager";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages LAN floating IPs with keepalived (VRRP)")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("vrrp")
+                .about("Renders the keepalived VRRP config for the address pool, and reloads keepalived on change")
+                .long_about(
+                    "
+
+Renders one VRRP instance per address in \"--pool\" into \"--config-path\",
+which the main keepalived config must include, and runs \"--reload-command\"
+only if the rendered config changed. Every node renders the same pool,
+so that the others take over the addresses of a failed node.
+
+The node is preferred (\"--priority\") as the master of its claimed address,
+and is the backup (\"--backup-priority\") for the others. The claim is recorded
+in the state file, so that the next run on the same volume keeps the same address.
+
+With \"--health-script\", an instance goes into the FAULT state (releasing the address)
+while the script fails.
+
+e.g.,
+
+$ ip-manager keepalived vrrp \
+--pool=192.168.1.10/24,192.168.1.11/24 \
+--claim=192.168.1.10 \
+--interface=eth0 \
+--virtual-router-id-base=51 \
+--auth-pass-source=file:/etc/ip-manager/vrrp-pass \
+--health-script=\"/usr/bin/curl -fs http://localhost:8080/health\" \
+--state-file-path=/data/vrrp-ip.yaml
+
+",
+                )
+                .arg(
+                    Arg::new("POOL")
+                        .long("pool")
+                        .help("Sets the comma-separated virtual IPs (e.g., 192.168.1.10/24) to manage with VRRP")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("CLAIM")
+                        .long("claim")
+                        .help("Sets the address in the pool for this node to be the master of (empty to read from the state file, or to be the backup of all)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("INTERFACE")
+                        .long("interface")
+                        .help("Sets the network interface to run VRRP on")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("eth0"),
+                )
+                .arg(
+                    Arg::new("VIRTUAL_ROUTER_ID_BASE")
+                        .long("virtual-router-id-base")
+                        .help("Sets the virtual router ID of the first address in the pool (incremented for the rest)")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(u8))
+                        .default_value("51"),
+                )
+                .arg(
+                    Arg::new("PRIORITY")
+                        .long("priority")
+                        .help("Sets the VRRP priority for the claimed address")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(u8))
+                        .default_value("150"),
+                )
+                .arg(
+                    Arg::new("BACKUP_PRIORITY")
+                        .long("backup-priority")
+                        .help("Sets the VRRP priority for the other addresses in the pool")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(u8))
+                        .default_value("100"),
+                )
+                .arg(
+                    Arg::new("UNICAST_PEERS")
+                        .long("unicast-peers")
+                        .help("Sets the comma-separated peer addresses to advertise to with unicast (empty for multicast)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("AUTH_PASS_SOURCE")
+                        .long("auth-pass-source")
+                        .help("Sets where to read the VRRP password (\"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\", empty to disable)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("HEALTH_SCRIPT")
+                        .long("health-script")
+                        .help("Sets the health check command (empty to skip)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("HEALTH_INTERVAL_SECONDS")
+                        .long("health-interval-seconds")
+                        .help("Sets the interval of the health check command")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(u32))
+                        .default_value("2"),
+                )
+                .arg(
+                    Arg::new("CONFIG_PATH")
+                        .long("config-path")
+                        .help("Sets the keepalived config file to render (included by the main keepalived config)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/etc/keepalived/ip-manager.conf"),
+                )
+                .arg(
+                    Arg::new("RELOAD_COMMAND")
+                        .long("reload-command")
+                        .help("Sets the command to reload keepalived when the config changes (empty to skip)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("systemctl reload keepalived"),
+                )
+                .arg(
+                    Arg::new("STATE_FILE_PATH")
+                        .long("state-file-path")
+                        .help("Sets the file path to store the claimed address mapped to this volume path")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/data/vrrp-ip.yaml"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub pool: String,
+    pub claim: String,
+    pub interface: String,
+    pub virtual_router_id_base: u8,
+    pub priority: u8,
+    pub backup_priority: u8,
+    pub unicast_peers: String,
+    pub auth_pass_source: String,
+    pub health_script: String,
+    pub health_interval_seconds: u32,
+    pub config_path: String,
+    pub reload_command: String,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        pool: matches
+            .get_one::<String>("POOL")
+            .unwrap_or(&String::new())
+            .clone(),
+        claim: matches
+            .get_one::<String>("CLAIM")
+            .unwrap_or(&String::new())
+            .clone(),
+        interface: matches
+            .get_one::<String>("INTERFACE")
+            .unwrap_or(&String::from("eth0"))
+            .clone(),
+        virtual_router_id_base: *matches
+            .get_one::<u8>("VIRTUAL_ROUTER_ID_BASE")
+            .unwrap_or(&51),
+        priority: *matches.get_one::<u8>("PRIORITY").unwrap_or(&150),
+        backup_priority: *matches.get_one::<u8>("BACKUP_PRIORITY").unwrap_or(&100),
+        unicast_peers: matches
+            .get_one::<String>("UNICAST_PEERS")
+            .unwrap_or(&String::new())
+            .clone(),
+        auth_pass_source: matches
+            .get_one::<String>("AUTH_PASS_SOURCE")
+            .unwrap_or(&String::new())
+            .clone(),
+        health_script: matches
+            .get_one::<String>("HEALTH_SCRIPT")
+            .unwrap_or(&String::new())
+            .clone(),
+        health_interval_seconds: *matches
+            .get_one::<u32>("HEALTH_INTERVAL_SECONDS")
+            .unwrap_or(&2),
+        config_path: matches
+            .get_one::<String>("CONFIG_PATH")
+            .unwrap_or(&String::from("/etc/keepalived/ip-manager.conf"))
+            .clone(),
+        reload_command: matches
+            .get_one::<String>("RELOAD_COMMAND")
+            .unwrap_or(&String::from("systemctl reload keepalived"))
+            .clone(),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/vrrp-ip.yaml"))
+            .clone(),
+    }
+}
+
+/// Virtual IP in the pool (e.g., "192.168.1.10/24").
+struct VirtualIp {
+    ip: IpAddr,
+    cidr: String,
+}
+
+impl VirtualIp {
+    fn parse(s: &str) -> io::Result<Self> {
+        let (ip, prefix) = match s.split_once('/') {
+            Some((ip, prefix)) => (ip, Some(prefix)),
+            None => (s, None),
+        };
+        let ip: IpAddr = ip.parse().map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid virtual IP '{s}' ({})", e),
+            )
+        })?;
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid prefix length in '{s}'"),
+                )
+            })?,
+            None => max,
+        };
+        Ok(VirtualIp {
+            ip,
+            cidr: format!("{ip}/{prefix}"),
+        })
+    }
+}
+
+/// Renders the VRRP config, reloading keepalived if it changed.
+/// Returns the claimed address, if any.
+pub async fn execute(opts: Flags) -> io::Result<Option<Address>> {
+    let pool = opts
+        .pool
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(VirtualIp::parse)
+        .collect::<io::Result<Vec<_>>>()?;
+    if pool.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "empty --pool"));
+    }
+    if usize::from(opts.virtual_router_id_base) + pool.len() > 256 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "virtual router IDs exceed 255 -- lower --virtual-router-id-base",
+        ));
+    }
+    if opts.priority <= opts.backup_priority {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--priority must be higher than --backup-priority",
+        ));
+    }
+    validate(&opts.interface, "--interface")?;
+    validate(&opts.health_script, "--health-script")?;
+
+    let claim = claim(&opts, &pool)?;
+    let auth_pass = if opts.auth_pass_source.is_empty() {
+        String::new()
+    } else {
+        let pass = secret::read(&opts.auth_pass_source, "", &sdk::Options::default()).await?;
+        validate(&pass, "VRRP password")?;
+        // PASS authentication uses the first 8 characters only
+        pass.chars().take(8).collect()
+    };
+    let unicast_peers = opts
+        .unicast_peers
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            v.parse::<IpAddr>().map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("invalid unicast peer '{v}' ({})", e),
+                )
+            })
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut d = String::from("# Generated by ip-manager, do not edit.\n");
+    if !opts.health_script.is_empty() {
+        d.push_str(&format!(
+            "\nvrrp_script {SCRIPT_NAME} {{\n    script \"{}\"\n    interval {}\n    fall 2\n    rise 2\n}}\n",
+            opts.health_script, opts.health_interval_seconds
+        ));
+    }
+    for (i, vip) in pool.iter().enumerate() {
+        let vrid = usize::from(opts.virtual_router_id_base) + i;
+        let priority = if claim.as_ref().map(|a| a.ip == vip.ip.to_string()) == Some(true) {
+            opts.priority
+        } else {
+            opts.backup_priority
+        };
+        d.push_str(&format!(
+            "\nvrrp_instance ip_manager_{vrid} {{\n    state BACKUP\n    interface {}\n    virtual_router_id {vrid}\n    priority {priority}\n    advert_int 1\n",
+            opts.interface
+        ));
+        if !unicast_peers.is_empty() {
+            d.push_str("    unicast_peer {\n");
+            for peer in unicast_peers.iter() {
+                d.push_str(&format!("        {peer}\n"));
+            }
+            d.push_str("    }\n");
+        }
+        if !auth_pass.is_empty() {
+            d.push_str(&format!(
+                "    authentication {{\n        auth_type PASS\n        auth_pass {auth_pass}\n    }}\n"
+            ));
+        }
+        d.push_str(&format!(
+            "    virtual_ipaddress {{\n        {} dev {}\n    }}\n",
+            vip.cidr, opts.interface
+        ));
+        if !opts.health_script.is_empty() {
+            d.push_str(&format!(
+                "    track_script {{\n        {SCRIPT_NAME}\n    }}\n"
+            ));
+        }
+        d.push_str("}\n");
+    }
+
+    let existing = fs::read_to_string(&opts.config_path).unwrap_or_default();
+    if existing == d {
+        log::info!("keepalived config {} is up-to-date", opts.config_path);
+        return Ok(claim);
+    }
+    log::info!("rendering keepalived config {}", opts.config_path);
+    if let Some(parent_dir) = Path::new(&opts.config_path).parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+    fs::write(&opts.config_path, d)?;
+    reload(&opts.reload_command)?;
+    Ok(claim)
+}
+
+/// Returns the claimed address from the flag or the state file,
+/// and records it in the state file.
+fn claim(opts: &Flags, pool: &[VirtualIp]) -> io::Result<Option<Address>> {
+    let ip = if !opts.claim.is_empty() {
+        VirtualIp::parse(&opts.claim)?.ip.to_string()
+    } else if Path::new(&opts.state_file_path).exists() {
+        let addr = Address::load(&opts.state_file_path)?;
+        if addr.provider != NAME {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "state file {} is for provider {}, not {NAME}",
+                    opts.state_file_path, addr.provider
+                ),
+            ));
+        }
+        addr.ip
+    } else {
+        log::info!("no claimed address -- backup for all addresses in the pool");
+        return Ok(None);
+    };
+
+    let i = pool
+        .iter()
+        .position(|vip| vip.ip.to_string() == ip)
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("claimed address {ip} is not in the pool"),
+            )
+        })?;
+    let addr = Address {
+        provider: NAME.to_string(),
+        id: (usize::from(opts.virtual_router_id_base) + i).to_string(),
+        ip,
+    };
+    addr.sync(&opts.state_file_path)?;
+    log::info!(
+        "claimed address {} (virtual router ID {})",
+        addr.ip,
+        addr.id
+    );
+    Ok(Some(addr))
+}
+
+/// Rejects the values that would break out of the rendered config.
+fn validate(v: &str, name: &str) -> io::Result<()> {
+    if v.contains(['"', '\n', '{', '}', '#', '!']) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{name} has characters not allowed in the keepalived config"),
+        ));
+    }
+    Ok(())
+}
+
+fn reload(cmd: &str) -> io::Result<()> {
+    let mut args = cmd.split_whitespace();
+    let program = match args.next() {
+        Some(v) => v,
+        None => {
+            log::info!("skipping keepalived reload");
+            return Ok(());
+        }
+    };
+    log::info!("reloading keepalived with '{cmd}'");
+    let out = Process::new(program).args(args).output()?;
+    if !out.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed '{cmd}' {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,325 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{sdk, secret};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use hyper::Method;
+
+use crate::{
+    http::{self, Client},
+    iface,
+    provider::{self, Address, BoxFuture, Provider},
+};
+
+pub const NAME: &str = "linode";
+
+const API_URL: &str = "https://api.linode.com/v4";
+
+/// ref. <https://techdocs.akamai.com/cloud-computing/docs/overview-of-the-metadata-service>
+const METADATA_URL: &str = "http://169.254.169.254/v1";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages Linode addresses")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("reserved-ip")
+                .about("Provisions the reserved IP to the local Linode")
+                .long_about(
+                    "
+
+The Linode ID and region are fetched from the metadata service.
+
+An unassigned reserved IP with the tag in the same region is claimed,
+or a new one is reserved with the tag, unless the state file exists.
+
+\"--assign-mode=assign\" moves the reserved IP to the local Linode,
+and \"--assign-mode=share\" adds the local Linode to the IP sharing list instead,
+for failover setups that announce the IP from one of the sharing Linodes (e.g., lelastic).
+
+Linode does not configure additional IPs without Network Helper,
+so \"--configure-interface\" adds it to the interface (requires CAP_NET_ADMIN).
+
+e.g.,
+
+$ ip-manager linode reserved-ip \
+--token-source=env:LINODE_TOKEN \
+--tag=ip-manager \
+--configure-interface=eth0 \
+--state-file-path=/data/reserved-ip.yaml
+
+",
+                )
+                .arg(
+                    Arg::new("TOKEN_SOURCE")
+                        .long("token-source")
+                        .help("Sets where to read the API token (\"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\")")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("env:LINODE_TOKEN"),
+                )
+                .arg(
+                    Arg::new("TAG")
+                        .long("tag")
+                        .help("Sets the tag to find and create tool-managed reserved IPs")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("ip-manager"),
+                )
+                .arg(
+                    Arg::new("ASSIGN_MODE")
+                        .long("assign-mode")
+                        .help("Sets how to bind the reserved IP to the local Linode (\"assign\" to move, \"share\" to add to the IP sharing list)")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(["assign", "share"])
+                        .default_value("assign"),
+                )
+                .arg(
+                    Arg::new("CONFIGURE_INTERFACE")
+                        .long("configure-interface")
+                        .help("Sets the network interface to add the reserved IP to (e.g., eth0, empty to skip)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("NO_STEAL")
+                        .long("no-steal")
+                        .help("Aborts rather than re-assigning the reserved IP that is assigned to another Linode")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(bool))
+                        .default_value("true"),
+                )
+                .arg(
+                    Arg::new("STATE_FILE_PATH")
+                        .long("state-file-path")
+                        .help("Sets the file path to store the reserved IP information mapped to this volume path")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/data/reserved-ip.yaml"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub token_source: String,
+    pub tag: String,
+    pub assign_mode: String,
+    pub configure_interface: String,
+    pub no_steal: bool,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        token_source: matches
+            .get_one::<String>("TOKEN_SOURCE")
+            .unwrap_or(&String::from("env:LINODE_TOKEN"))
+            .clone(),
+        tag: matches
+            .get_one::<String>("TAG")
+            .unwrap_or(&String::from("ip-manager"))
+            .clone(),
+        assign_mode: matches
+            .get_one::<String>("ASSIGN_MODE")
+            .unwrap_or(&String::from("assign"))
+            .clone(),
+        configure_interface: matches
+            .get_one::<String>("CONFIGURE_INTERFACE")
+            .unwrap_or(&String::new())
+            .clone(),
+        no_steal: *matches.get_one::<bool>("NO_STEAL").unwrap_or(&true),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/reserved-ip.yaml"))
+            .clone(),
+    }
+}
+
+pub async fn execute(opts: Flags) -> io::Result<Address> {
+    let token = secret::read(&opts.token_source, "LINODE_TOKEN", &sdk::Options::default()).await?;
+    let linode = Linode {
+        client: Client::new(
+            API_URL,
+            vec![(String::from("Authorization"), format!("Bearer {token}"))],
+        ),
+        tag: opts.tag.clone(),
+        share: opts.assign_mode == "share",
+    };
+    let addr = provider::provision(&linode, &opts.state_file_path, opts.no_steal).await?;
+
+    if !opts.configure_interface.is_empty() {
+        iface::add_address(&opts.configure_interface, &format!("{}/32", addr.ip))?;
+    }
+    Ok(addr)
+}
+
+/// Linode reserved IP backend.
+/// ref. <https://techdocs.akamai.com/linode-api/reference/post-reserve-ip>
+/// ref. <https://techdocs.akamai.com/linode-api/reference/post-share-ips>
+pub struct Linode {
+    client: Client,
+    tag: String,
+    share: bool,
+}
+
+impl Linode {
+    /// Returns the local instance metadata with the session token.
+    async fn instance(&self) -> io::Result<serde_json::Value> {
+        let token = http::metadata_request(
+            Method::PUT,
+            &format!("{METADATA_URL}/token"),
+            &[("Metadata-Token-Expiry-Seconds", "300")],
+        )
+        .await?;
+        let instance = http::fetch_metadata(
+            &format!("{METADATA_URL}/instance"),
+            &[("Metadata-Token", &token), ("Accept", "application/json")],
+        )
+        .await?;
+        serde_json::from_str(&instance).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse instance metadata {}", e),
+            )
+        })
+    }
+
+    /// Returns the IPs shared with the Linode.
+    async fn shared_ips(&self, linode_id: &str) -> io::Result<Vec<String>> {
+        let resp = self
+            .client
+            .get(&format!("/linode/instances/{linode_id}/ips"))
+            .await?;
+        Ok(resp["ipv4"]["shared"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|v| v["address"].as_str().map(|v| v.to_string()))
+            .collect())
+    }
+
+    async fn region(&self) -> io::Result<String> {
+        self.instance().await?["region"]
+            .as_str()
+            .map(|v| v.to_string())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "instance metadata has no 'region'"))
+    }
+}
+
+fn to_address(v: &serde_json::Value) -> io::Result<Address> {
+    let ip = v["address"]
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "reserved IP has no 'address'"))?;
+    Ok(Address {
+        provider: NAME.to_string(),
+        id: ip.to_string(),
+        ip: ip.to_string(),
+    })
+}
+
+impl Provider for Linode {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move {
+            self.instance().await?["id"]
+                .as_u64()
+                .map(|id| id.to_string())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "instance metadata has no 'id'"))
+        })
+    }
+
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move {
+            let region = self.region().await?;
+            let resp = self
+                .client
+                .get("/networking/reserved/ips?page_size=500")
+                .await?;
+            for v in resp["data"].as_array().into_iter().flatten() {
+                let tagged = v["tags"]
+                    .as_array()
+                    .map(|tags| tags.iter().any(|t| t.as_str() == Some(&self.tag)))
+                    .unwrap_or(false);
+                if tagged && v["linode_id"].is_null() && v["region"].as_str() == Some(&region) {
+                    return Ok(Some(to_address(v)?));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            let region = self.region().await?;
+            log::info!("reserving IP in {region}");
+            let resp = self
+                .client
+                .post(
+                    "/networking/reserved/ips",
+                    serde_json::json!({ "region": region, "tags": [self.tag] }),
+                )
+                .await?;
+            to_address(&resp)
+        })
+    }
+
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            if self.share {
+                // shared IPs stay assigned to the primary Linode, so only check the local sharing
+                let local_id = self.local_instance_id().await?;
+                if self.shared_ips(&local_id).await?.contains(&addr.ip) {
+                    return Ok(Some(local_id));
+                }
+                return Ok(None);
+            }
+            let resp = self
+                .client
+                .get(&format!("/networking/ips/{}", addr.ip))
+                .await?;
+            Ok(resp["linode_id"].as_u64().map(|id| id.to_string()))
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, instance_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let linode_id: u64 = instance_id.parse().map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid Linode ID '{instance_id}' ({})", e),
+                )
+            })?;
+            if self.share {
+                // sharing replaces the list of IPs shared with the Linode, so keep the existing ones
+                let mut ips = self.shared_ips(instance_id).await?;
+                if !ips.contains(&addr.ip) {
+                    ips.push(addr.ip.clone());
+                }
+                self.client
+                    .post(
+                        "/networking/ips/share",
+                        serde_json::json!({ "linode_id": linode_id, "ips": ips }),
+                    )
+                    .await?;
+                return Ok(());
+            }
+
+            let region = self.region().await?;
+            self.client
+                .post(
+                    "/networking/ips/assign",
+                    serde_json::json!({
+                        "region": region,
+                        "assignments": [{ "address": addr.ip, "linode_id": linode_id }],
+                    }),
+                )
+                .await?;
+            Ok(())
+        })
+    }
+}
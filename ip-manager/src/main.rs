@@ -0,0 +1,68 @@
+#[cfg(feature = "bgp")]
+pub mod bgp;
+pub mod buildinfo;
+pub mod cfn;
+pub mod command;
+pub mod completions;
+pub mod cost;
+#[cfg(feature = "ddns")]
+pub mod ddns;
+pub mod detect;
+#[cfg(feature = "digitalocean")]
+pub mod digitalocean;
+#[cfg(feature = "hetzner")]
+pub mod hetzner;
+pub mod history;
+#[cfg(any(
+    feature = "digitalocean",
+    feature = "hetzner",
+    feature = "linode",
+    feature = "openstack",
+    feature = "scaleway",
+    feature = "vultr"
+))]
+pub mod http;
+#[cfg(any(feature = "bgp", feature = "hetzner", feature = "linode"))]
+pub mod iface;
+pub mod inventory;
+pub mod k8s;
+#[cfg(feature = "keepalived")]
+pub mod keepalived;
+#[cfg(feature = "linode")]
+pub mod linode;
+#[cfg(feature = "openstack")]
+pub mod openstack;
+pub mod peers;
+#[cfg(feature = "plugin")]
+pub mod plugin;
+#[cfg(feature = "portmap")]
+pub mod portmap;
+pub mod prefixlist;
+pub mod provider;
+#[cfg(feature = "scaleway")]
+pub mod scaleway;
+pub mod schema;
+pub mod selftest;
+pub mod selfupdate;
+pub mod serve;
+pub mod state;
+pub mod transfer;
+pub mod tui;
+pub mod validate;
+#[cfg(feature = "vultr")]
+pub mod vultr;
+pub mod windows;
+
+use std::process::ExitCode;
+
+use aws_ip_provisioner::summary;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let matches = command::new().get_matches();
+    let res = command::execute(&matches).await;
+    if let Err(e) = &res {
+        eprintln!("Error: {:?}", e);
+    }
+    summary::exit_code(&res)
+}
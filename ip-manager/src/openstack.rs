@@ -0,0 +1,493 @@
+use std::{
+    env,
+    io::{self, Error, ErrorKind},
+};
+
+use aws_ip_provisioner::{sdk, secret};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use hyper::Method;
+
+use crate::{
+    http::{self, Client},
+    provider::{self, Address, BoxFuture, Provider},
+};
+
+pub const NAME: &str = "openstack";
+
+/// ref. <https://docs.openstack.org/nova/latest/user/metadata.html>
+const METADATA_URL: &str = "http://169.254.169.254/openstack/latest/meta_data.json";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages OpenStack addresses")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("floating-ip")
+                .about("Provisions the Neutron floating IP to the local instance port")
+                .long_about(
+                    "
+
+The instance UUID is fetched from the metadata service, and its first port is associated.
+
+An unassociated floating IP with the tag on the external network is claimed,
+or a new one is allocated from the external network and tagged, unless the state file exists.
+
+Authenticates with Keystone v3, by the application credential if set, or by the password.
+Empty flags fall back to the \"OS_*\" environment variables of the OpenStack RC file.
+
+e.g.,
+
+$ ip-manager openstack floating-ip \
+--auth-url=https://keystone.example.com:5000/v3 \
+--application-credential-id=TEST-ID \
+--application-credential-secret-source=env:OS_APPLICATION_CREDENTIAL_SECRET \
+--external-network=public \
+--state-file-path=/data/floating-ip.yaml
+
+",
+                )
+                .arg(
+                    Arg::new("AUTH_URL")
+                        .long("auth-url")
+                        .help("Sets the Keystone v3 URL (defaults to \"OS_AUTH_URL\")")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("APPLICATION_CREDENTIAL_ID")
+                        .long("application-credential-id")
+                        .help("Sets the application credential ID (defaults to \"OS_APPLICATION_CREDENTIAL_ID\", empty to use the password)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("APPLICATION_CREDENTIAL_SECRET_SOURCE")
+                        .long("application-credential-secret-source")
+                        .help("Sets where to read the application credential secret (\"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\")")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("env:OS_APPLICATION_CREDENTIAL_SECRET"),
+                )
+                .arg(
+                    Arg::new("USERNAME")
+                        .long("username")
+                        .help("Sets the user name for the password authentication (defaults to \"OS_USERNAME\")")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("USER_DOMAIN_NAME")
+                        .long("user-domain-name")
+                        .help("Sets the user domain name for the password authentication (defaults to \"OS_USER_DOMAIN_NAME\", or \"Default\")")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("PROJECT_ID")
+                        .long("project-id")
+                        .help("Sets the project ID to scope the password authentication (defaults to \"OS_PROJECT_ID\")")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("PASSWORD_SOURCE")
+                        .long("password-source")
+                        .help("Sets where to read the password (\"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\")")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("env:OS_PASSWORD"),
+                )
+                .arg(
+                    Arg::new("REGION")
+                        .long("region")
+                        .help("Sets the region of the network endpoint in the service catalog (defaults to \"OS_REGION_NAME\", empty for any)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("INTERFACE")
+                        .long("interface")
+                        .help("Sets the interface of the network endpoint in the service catalog")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(["public", "internal", "admin"])
+                        .default_value("public"),
+                )
+                .arg(
+                    Arg::new("EXTERNAL_NETWORK")
+                        .long("external-network")
+                        .help("Sets the name of the external network to allocate the floating IP from")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("TAG")
+                        .long("tag")
+                        .help("Sets the tag to find and create tool-managed floating IPs")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("ip-manager"),
+                )
+                .arg(
+                    Arg::new("NO_STEAL")
+                        .long("no-steal")
+                        .help("Aborts rather than re-associating the floating IP that is associated with another instance")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(bool))
+                        .default_value("true"),
+                )
+                .arg(
+                    Arg::new("STATE_FILE_PATH")
+                        .long("state-file-path")
+                        .help("Sets the file path to store the floating IP information mapped to this volume path")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/data/floating-ip.yaml"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub auth_url: String,
+    pub application_credential_id: String,
+    pub application_credential_secret_source: String,
+    pub username: String,
+    pub user_domain_name: String,
+    pub project_id: String,
+    pub password_source: String,
+    pub region: String,
+    pub interface: String,
+    pub external_network: String,
+    pub tag: String,
+    pub no_steal: bool,
+    pub state_file_path: String,
+}
+
+/// Returns the flag value, or the environment variable if the flag is empty.
+fn flag_or_env(matches: &ArgMatches, id: &str, env_key: &str, default: &str) -> String {
+    match matches.get_one::<String>(id) {
+        Some(v) if !v.is_empty() => v.clone(),
+        _ => env::var(env_key).unwrap_or_else(|_| default.to_string()),
+    }
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        auth_url: flag_or_env(matches, "AUTH_URL", "OS_AUTH_URL", ""),
+        application_credential_id: flag_or_env(
+            matches,
+            "APPLICATION_CREDENTIAL_ID",
+            "OS_APPLICATION_CREDENTIAL_ID",
+            "",
+        ),
+        application_credential_secret_source: matches
+            .get_one::<String>("APPLICATION_CREDENTIAL_SECRET_SOURCE")
+            .unwrap_or(&String::from("env:OS_APPLICATION_CREDENTIAL_SECRET"))
+            .clone(),
+        username: flag_or_env(matches, "USERNAME", "OS_USERNAME", ""),
+        user_domain_name: flag_or_env(
+            matches,
+            "USER_DOMAIN_NAME",
+            "OS_USER_DOMAIN_NAME",
+            "Default",
+        ),
+        project_id: flag_or_env(matches, "PROJECT_ID", "OS_PROJECT_ID", ""),
+        password_source: matches
+            .get_one::<String>("PASSWORD_SOURCE")
+            .unwrap_or(&String::from("env:OS_PASSWORD"))
+            .clone(),
+        region: flag_or_env(matches, "REGION", "OS_REGION_NAME", ""),
+        interface: matches
+            .get_one::<String>("INTERFACE")
+            .unwrap_or(&String::from("public"))
+            .clone(),
+        external_network: matches
+            .get_one::<String>("EXTERNAL_NETWORK")
+            .unwrap_or(&String::new())
+            .clone(),
+        tag: matches
+            .get_one::<String>("TAG")
+            .unwrap_or(&String::from("ip-manager"))
+            .clone(),
+        no_steal: *matches.get_one::<bool>("NO_STEAL").unwrap_or(&true),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/floating-ip.yaml"))
+            .clone(),
+    }
+}
+
+pub async fn execute(opts: Flags) -> io::Result<Address> {
+    if opts.auth_url.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "empty Keystone URL (set --auth-url or OS_AUTH_URL)",
+        ));
+    }
+    let (token, network_url) = authenticate(&opts).await?;
+    let client = Client::new(
+        &format!("{}/v2.0", network_url.trim_end_matches('/')),
+        vec![(String::from("X-Auth-Token"), token)],
+    );
+
+    let resp = client
+        .get(&format!("/networks?name={}", opts.external_network))
+        .await?;
+    let external_network_id = resp["networks"][0]["id"]
+        .as_str()
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("external network {} not found", opts.external_network),
+            )
+        })?
+        .to_string();
+
+    let openstack = OpenStack {
+        client,
+        external_network_id,
+        tag: opts.tag.clone(),
+    };
+    provider::provision(&openstack, &opts.state_file_path, opts.no_steal).await
+}
+
+/// Issues the Keystone token, and returns it with the network endpoint URL from the catalog.
+/// ref. <https://docs.openstack.org/api-ref/identity/v3/#password-authentication-with-scoped-authorization>
+/// ref. <https://docs.openstack.org/api-ref/identity/v3/#authenticating-with-an-application-credential>
+async fn authenticate(opts: &Flags) -> io::Result<(String, String)> {
+    let req = if !opts.application_credential_id.is_empty() {
+        let app_secret = secret::read(
+            &opts.application_credential_secret_source,
+            "OS_APPLICATION_CREDENTIAL_SECRET",
+            &sdk::Options::default(),
+        )
+        .await?;
+        serde_json::json!({
+            "auth": {
+                "identity": {
+                    "methods": ["application_credential"],
+                    "application_credential": {
+                        "id": opts.application_credential_id,
+                        "secret": app_secret,
+                    },
+                },
+            },
+        })
+    } else {
+        let password = secret::read(
+            &opts.password_source,
+            "OS_PASSWORD",
+            &sdk::Options::default(),
+        )
+        .await?;
+        serde_json::json!({
+            "auth": {
+                "identity": {
+                    "methods": ["password"],
+                    "password": {
+                        "user": {
+                            "name": opts.username,
+                            "domain": { "name": opts.user_domain_name },
+                            "password": password,
+                        },
+                    },
+                },
+                "scope": { "project": { "id": opts.project_id } },
+            },
+        })
+    };
+
+    log::info!("authenticating with Keystone {}", opts.auth_url);
+    let (headers, resp) = Client::new(&opts.auth_url, Vec::new())
+        .request_with_headers(Method::POST, "/auth/tokens", Some(req))
+        .await?;
+    let token = headers
+        .get("X-Subject-Token")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Keystone returned no token"))?
+        .to_string();
+
+    for service in resp["token"]["catalog"].as_array().into_iter().flatten() {
+        if service["type"].as_str() != Some("network") {
+            continue;
+        }
+        for endpoint in service["endpoints"].as_array().into_iter().flatten() {
+            if endpoint["interface"].as_str() != Some(&opts.interface) {
+                continue;
+            }
+            if !opts.region.is_empty() && endpoint["region_id"].as_str() != Some(&opts.region) {
+                continue;
+            }
+            if let Some(url) = endpoint["url"].as_str() {
+                return Ok((token, url.to_string()));
+            }
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!(
+            "no {} network endpoint in the service catalog (region '{}')",
+            opts.interface, opts.region
+        ),
+    ))
+}
+
+/// OpenStack Neutron floating IP backend.
+/// ref. <https://docs.openstack.org/api-ref/network/v2/#floating-ips-floatingips>
+pub struct OpenStack {
+    client: Client,
+    external_network_id: String,
+    tag: String,
+}
+
+impl OpenStack {
+    /// Returns the first port of the instance.
+    async fn instance_port(&self, instance_id: &str) -> io::Result<String> {
+        let resp = self
+            .client
+            .get(&format!("/ports?device_id={instance_id}"))
+            .await?;
+        resp["ports"][0]["id"]
+            .as_str()
+            .map(|v| v.to_string())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("no port found for instance {instance_id}"),
+                )
+            })
+    }
+}
+
+fn to_address(v: &serde_json::Value) -> io::Result<Address> {
+    let id = v["id"]
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "floating IP has no 'id'"))?;
+    let ip = v["floating_ip_address"].as_str().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidData,
+            "floating IP has no 'floating_ip_address'",
+        )
+    })?;
+    Ok(Address {
+        provider: NAME.to_string(),
+        id: id.to_string(),
+        ip: ip.to_string(),
+    })
+}
+
+impl Provider for OpenStack {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move {
+            let meta = http::fetch_metadata(METADATA_URL, &[]).await?;
+            let meta: serde_json::Value = serde_json::from_str(&meta).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("failed to parse {METADATA_URL} {}", e),
+                )
+            })?;
+            meta["uuid"]
+                .as_str()
+                .map(|v| v.to_string())
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "metadata has no 'uuid'"))
+        })
+    }
+
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move {
+            let resp = self
+                .client
+                .get(&format!(
+                    "/floatingips?floating_network_id={}&tags={}",
+                    self.external_network_id, self.tag
+                ))
+                .await?;
+            for v in resp["floatingips"].as_array().into_iter().flatten() {
+                if v["port_id"].is_null() {
+                    return Ok(Some(to_address(v)?));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            log::info!(
+                "allocating floating IP from network {}",
+                self.external_network_id
+            );
+            let resp = self
+                .client
+                .post(
+                    "/floatingips",
+                    serde_json::json!({
+                        "floatingip": { "floating_network_id": self.external_network_id },
+                    }),
+                )
+                .await?;
+            let addr = to_address(&resp["floatingip"])?;
+            // tags cannot be set on creation
+            self.client
+                .request(
+                    Method::PUT,
+                    &format!("/floatingips/{}/tags/{}", addr.id, self.tag),
+                    None,
+                )
+                .await?;
+            Ok(addr)
+        })
+    }
+
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let resp = self
+                .client
+                .get(&format!("/floatingips/{}", addr.id))
+                .await?;
+            let port_id = match resp["floatingip"]["port_id"].as_str() {
+                Some(v) => v.to_string(),
+                None => return Ok(None),
+            };
+            let resp = self.client.get(&format!("/ports/{port_id}")).await?;
+            Ok(Some(
+                resp["port"]["device_id"]
+                    .as_str()
+                    .unwrap_or(&port_id)
+                    .to_string(),
+            ))
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, instance_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let port_id = self.instance_port(instance_id).await?;
+            // disassociate first, since Neutron rejects moving between ports in one update
+            let resp = self
+                .client
+                .get(&format!("/floatingips/{}", addr.id))
+                .await?;
+            if !resp["floatingip"]["port_id"].is_null() {
+                self.client
+                    .put(
+                        &format!("/floatingips/{}", addr.id),
+                        serde_json::json!({ "floatingip": { "port_id": null } }),
+                    )
+                    .await?;
+            }
+            self.client
+                .put(
+                    &format!("/floatingips/{}", addr.id),
+                    serde_json::json!({ "floatingip": { "port_id": port_id } }),
+                )
+                .await?;
+            Ok(())
+        })
+    }
+}
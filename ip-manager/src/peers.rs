@@ -0,0 +1,292 @@
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_ip_provisioner::{imds::Imds, peers, sdk};
+use clap::{Arg, ArgMatches, Command};
+
+pub const NAME: &str = "peers";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Exchanges the public IPs of the nodes via a shared S3 object")
+        .subcommand_required(true)
+        .subcommand(
+            with_object_args(
+                Command::new("publish")
+                    .about("Publishes the public IP of the local node to the peers file")
+                    .long_about(
+                        "
+
+Adds (or replaces) the public IP of the local node, with its \"Id\" and \"Kind\",
+in the peers file on S3, for the clusters that bootstrap from a static peer list
+(e.g., after \"aws eip\" in the boot script, or as its \"--post-associate-hook\").
+
+The object is updated with optimistic concurrency: the write is conditional
+on the ETag of the read (\"If-Match\", or \"If-None-Match: *\" for the first node),
+so that the nodes booting together never overwrite each other, and retried from
+the read on conflict. An already published IP does not rewrite the object.
+Requires \"s3:GetObject\" and \"s3:PutObject\" on the object.
+
+e.g.,
+
+$ ip-manager peers publish \
+--bucket=my-cluster-config \
+--key=peers/mainnet.json \
+--id=node-1 \
+--kind=mainnet
+
+",
+                    )
+                    .arg(
+                        Arg::new("ID")
+                            .long("id")
+                            .help("Sets the ID of the local node (e.g., the \"Id\" tag value of its EIP)")
+                            .required(true)
+                            .num_args(1),
+                    )
+                    .arg(
+                        Arg::new("KIND")
+                            .long("kind")
+                            .help("Sets the kind of the local node (e.g., the \"Kind\" tag value of its EIP)")
+                            .required(false)
+                            .num_args(1)
+                            .default_value(""),
+                    )
+                    .arg(
+                        Arg::new("PUBLIC_IP")
+                            .long("public-ip")
+                            .help("Sets the public IP to publish (empty to fetch the public IPv4 from the instance metadata)")
+                            .required(false)
+                            .num_args(1)
+                            .default_value(""),
+                    ),
+            ),
+        )
+        .subcommand(
+            with_object_args(
+                Command::new("fetch")
+                    .about("Renders the peers file from S3 into a local file")
+                    .long_about(
+                        "
+
+Renders \"--template\" for each peer of \"--kind\" in the peers file on S3,
+one per line, into \"--output-file\" (or stdout), e.g., the static peer list
+of the node config. The file is only rewritten when the peers change, so
+it can run periodically (e.g., a systemd timer) without touching the file.
+
+The template variables are \"{id}\", \"{kind}\", and \"{public_ip}\".
+
+e.g.,
+
+$ ip-manager peers fetch \
+--bucket=my-cluster-config \
+--key=peers/mainnet.json \
+--kind=mainnet \
+--exclude-id=node-1 \
+--template='{public_ip}:30303' \
+--output-file=/etc/node/static-peers.txt
+
+",
+                    )
+                    .arg(
+                        Arg::new("KIND")
+                            .long("kind")
+                            .help("Sets the kind of the peers to render (empty for all kinds)")
+                            .required(false)
+                            .num_args(1)
+                            .default_value(""),
+                    )
+                    .arg(
+                        Arg::new("EXCLUDE_ID")
+                            .long("exclude-id")
+                            .help("Sets the ID of the peer not to render (e.g., the local node)")
+                            .required(false)
+                            .num_args(1)
+                            .default_value(""),
+                    )
+                    .arg(
+                        Arg::new("TEMPLATE")
+                            .long("template")
+                            .help("Sets the line to render per peer, with \"{id}\", \"{kind}\", and \"{public_ip}\"")
+                            .required(false)
+                            .num_args(1)
+                            .default_value("{public_ip}"),
+                    )
+                    .arg(
+                        Arg::new("OUTPUT_FILE")
+                            .long("output-file")
+                            .help("Sets the file to render the peers into (empty for stdout)")
+                            .required(false)
+                            .num_args(1)
+                            .default_value(""),
+                    ),
+            ),
+        )
+}
+
+fn with_object_args(cmd: Command) -> Command {
+    cmd.arg(
+        Arg::new("BUCKET")
+            .long("bucket")
+            .help("Sets the S3 bucket of the peers file")
+            .required(true)
+            .num_args(1),
+    )
+    .arg(
+        Arg::new("KEY")
+            .long("key")
+            .help("Sets the S3 object key of the peers file")
+            .required(false)
+            .num_args(1)
+            .default_value("ip-manager/peers.json"),
+    )
+    .arg(
+        Arg::new("BUCKET_REGION")
+            .long("bucket-region")
+            .help("Sets the region of the S3 bucket (empty for the region of the AWS config)")
+            .required(false)
+            .num_args(1)
+            .default_value(""),
+    )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub bucket: String,
+    pub key: String,
+    pub bucket_region: String,
+
+    /// "publish" only.
+    pub id: String,
+    pub public_ip: String,
+
+    pub kind: String,
+
+    /// "fetch" only.
+    pub exclude_id: String,
+    pub template: String,
+    pub output_file: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    let get = |id: &str| {
+        matches
+            .try_get_one::<String>(id)
+            .ok()
+            .flatten()
+            .cloned()
+            .unwrap_or_default()
+    };
+    Flags {
+        bucket: get("BUCKET"),
+        key: get("KEY"),
+        bucket_region: get("BUCKET_REGION"),
+        id: get("ID"),
+        public_ip: get("PUBLIC_IP"),
+        kind: get("KIND"),
+        exclude_id: get("EXCLUDE_ID"),
+        template: get("TEMPLATE"),
+        output_file: get("OUTPUT_FILE"),
+    }
+}
+
+async fn object(opts: &Flags) -> io::Result<(aws_types::SdkConfig, peers::Object)> {
+    let region = if opts.bucket_region.is_empty() {
+        None
+    } else {
+        Some(opts.bucket_region.clone())
+    };
+    let shared_config = sdk::load_config(region, &sdk::Options::default()).await?;
+    let region = shared_config
+        .region()
+        .map(|r| r.to_string())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no region for the S3 bucket"))?;
+    let object = peers::Object {
+        region,
+        bucket: opts.bucket.clone(),
+        key: opts.key.clone(),
+    };
+    Ok((shared_config, object))
+}
+
+/// Publishes the public IP of the local node.
+pub async fn publish(opts: Flags, output: &str) -> io::Result<()> {
+    let public_ip = if opts.public_ip.is_empty() {
+        Imds::new(false, 5)
+            .fetch("public-ipv4")
+            .await?
+            .trim()
+            .to_string()
+    } else {
+        opts.public_ip.clone()
+    };
+    if public_ip.parse::<std::net::IpAddr>().is_err() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid public IP '{public_ip}' to publish"),
+        ));
+    }
+    let peer = peers::Peer {
+        id: opts.id.clone(),
+        kind: opts.kind.clone(),
+        public_ip,
+        updated_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+    };
+
+    let (shared_config, object) = object(&opts).await?;
+    let published =
+        peers::publish(&shared_config, &sdk::Options::default(), &object, &peer).await?;
+    if output == "json" {
+        println!(
+            "{}",
+            serde_json::json!({
+                "id": peer.id,
+                "kind": peer.kind,
+                "public_ip": peer.public_ip,
+                "changed": published,
+            })
+        );
+    }
+    Ok(())
+}
+
+/// Renders the peers file, only rewriting "--output-file" on change.
+pub async fn fetch(opts: Flags) -> io::Result<()> {
+    let (shared_config, object) = object(&opts).await?;
+    let doc = peers::get(&shared_config, &sdk::Options::default(), &object)
+        .await?
+        .map(|(doc, _)| doc)
+        .unwrap_or_default();
+    let rendered = doc.render(&opts.kind, &opts.exclude_id, &opts.template);
+    if opts.output_file.is_empty() {
+        print!("{rendered}");
+        return Ok(());
+    }
+
+    if fs::read_to_string(&opts.output_file).unwrap_or_default() == rendered {
+        log::info!("{} is up to date", opts.output_file);
+        return Ok(());
+    }
+    if let Some(parent_dir) = Path::new(&opts.output_file).parent() {
+        if !parent_dir.as_os_str().is_empty() {
+            fs::create_dir_all(parent_dir)?;
+        }
+    }
+    // renamed, so that the readers never see a partial file
+    let tmp = format!("{}.tmp", opts.output_file);
+    fs::write(&tmp, &rendered)?;
+    fs::rename(&tmp, &opts.output_file)?;
+    log::info!(
+        "rendered {} peers into {}",
+        rendered.lines().count(),
+        opts.output_file
+    );
+    Ok(())
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{self, Error, ErrorKind},
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    process,
+    time::{timeout, Duration},
+};
+
+use crate::provider::{self, Address, BoxFuture, Provider};
+
+pub const NAME: &str = "plugin";
+
+/// Executable name prefix of provider plugins (e.g., "ip-manager-provider-file").
+pub const EXECUTABLE_PREFIX: &str = "ip-manager-provider-";
+
+/// Version of the plugin protocol, bumped on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Provisions the address with an external provider plugin")
+        .long_about(
+            "
+
+Runs the executable \"ip-manager-provider-<NAME>\" for each provider operation,
+writing one JSON request to its stdin and reading one JSON response from its stdout
+(stderr is passed through for logs). Plugins are looked up in \"--plugin-dir\", or in PATH.
+
+Request:
+{\"protocol_version\":1,\"operation\":\"<OPERATION>\",\"config\":{...},\"address\":{...},\"instance_id\":\"...\"}
+
+Operations and the response fields:
+- \"describe\": {\"protocol_version\":1}
+- \"local_instance_id\": {\"instance_id\":\"...\"}
+- \"claim\": {\"address\":{\"id\":\"...\",\"ip\":\"...\"}}, or {\"address\":null} if nothing to reuse
+- \"allocate\": {\"address\":{\"id\":\"...\",\"ip\":\"...\"}}
+- \"assigned_to\" (with \"address\"): {\"instance_id\":\"...\"}, or {\"instance_id\":null}
+- \"assign\" (with \"address\" and \"instance_id\"): {}
+
+On failure (including unknown operations), the plugin exits non-zero,
+optionally with {\"error\":\"...\"} on stdout.
+
+e.g.,
+
+$ ip-manager plugin \
+--name=file \
+--config=db=/var/lib/ip-manager/file-provider.json \
+--config=pool=192.0.2.10,192.0.2.11 \
+--state-file-path=/data/plugin-ip.yaml
+
+",
+        )
+        .arg(
+            Arg::new("NAME")
+                .long("name")
+                .help("Sets the plugin name (runs \"ip-manager-provider-<NAME>\")")
+                .required(true)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("PLUGIN_DIR")
+                .long("plugin-dir")
+                .help("Sets the directory to find the plugin executable (empty to search PATH)")
+                .required(false)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("CONFIG")
+                .long("config")
+                .help("Sets the plugin config as KEY=VALUE (can be repeated)")
+                .required(false)
+                .num_args(1)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("TIMEOUT_SECONDS")
+                .long("timeout-seconds")
+                .help("Sets the timeout for each plugin operation")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u64))
+                .default_value("300"),
+        )
+        .arg(
+            Arg::new("NO_STEAL")
+                .long("no-steal")
+                .help("Aborts rather than re-assigning the address that is assigned to another instance")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(bool))
+                .default_value("true"),
+        )
+        .arg(
+            Arg::new("STATE_FILE_PATH")
+                .long("state-file-path")
+                .help("Sets the file path to store the address information mapped to this volume path")
+                .required(false)
+                .num_args(1)
+                .default_value("/data/plugin-ip.yaml"),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub name: String,
+    pub plugin_dir: String,
+    pub config: Vec<String>,
+    pub timeout_seconds: u64,
+    pub no_steal: bool,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        name: matches
+            .get_one::<String>("NAME")
+            .unwrap_or(&String::new())
+            .clone(),
+        plugin_dir: matches
+            .get_one::<String>("PLUGIN_DIR")
+            .unwrap_or(&String::new())
+            .clone(),
+        config: matches
+            .get_many::<String>("CONFIG")
+            .map(|v| v.cloned().collect())
+            .unwrap_or_default(),
+        timeout_seconds: *matches.get_one::<u64>("TIMEOUT_SECONDS").unwrap_or(&300),
+        no_steal: *matches.get_one::<bool>("NO_STEAL").unwrap_or(&true),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/plugin-ip.yaml"))
+            .clone(),
+    }
+}
+
+pub async fn execute(opts: Flags) -> io::Result<Address> {
+    let mut config = BTreeMap::new();
+    for kv in opts.config.iter() {
+        let (k, v) = kv.split_once('=').ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid plugin config '{kv}' (expected KEY=VALUE)"),
+            )
+        })?;
+        config.insert(k.to_string(), v.to_string());
+    }
+    let plugin = Plugin {
+        name: opts.name.clone(),
+        path: find(&opts.name, &opts.plugin_dir)?,
+        config,
+        timeout: Duration::from_secs(opts.timeout_seconds),
+    };
+    plugin.describe().await?;
+    provider::provision(&plugin, &opts.state_file_path, opts.no_steal).await
+}
+
+/// Returns the path of the plugin executable in the directory, or in PATH if empty.
+pub fn find(name: &str, plugin_dir: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name.contains(std::path::is_separator) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid plugin name '{name}'"),
+        ));
+    }
+    let file_name = format!("{EXECUTABLE_PREFIX}{name}");
+    let dirs: Vec<PathBuf> = if plugin_dir.is_empty() {
+        env::var_os("PATH")
+            .map(|p| env::split_paths(&p).collect())
+            .unwrap_or_default()
+    } else {
+        vec![PathBuf::from(plugin_dir)]
+    };
+    dirs.iter()
+        .map(|dir| dir.join(&file_name))
+        .find(|p| p.is_file())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("plugin executable {file_name} not found in {dirs:?}"),
+            )
+        })
+}
+
+/// Request written to the plugin stdin.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Request {
+    pub protocol_version: u32,
+    pub operation: String,
+    pub config: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<PluginAddress>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<String>,
+}
+
+/// Response read from the plugin stdout.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Default)]
+pub struct Response {
+    pub protocol_version: Option<u32>,
+    pub instance_id: Option<String>,
+    pub address: Option<PluginAddress>,
+    pub error: Option<String>,
+}
+
+/// Address as seen by the plugin, without the provider name.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct PluginAddress {
+    pub id: String,
+    pub ip: String,
+}
+
+/// Provider backed by an external plugin executable.
+pub struct Plugin {
+    name: String,
+    path: PathBuf,
+    config: BTreeMap<String, String>,
+    timeout: Duration,
+}
+
+impl Plugin {
+    /// Checks that the plugin speaks the same protocol version.
+    async fn describe(&self) -> io::Result<()> {
+        let resp = self.call("describe", None, None).await?;
+        match resp.protocol_version {
+            Some(PROTOCOL_VERSION) => Ok(()),
+            v => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "plugin {} speaks protocol version {v:?}, expected {PROTOCOL_VERSION}",
+                    self.name
+                ),
+            )),
+        }
+    }
+
+    /// Runs the plugin for one operation.
+    async fn call(
+        &self,
+        operation: &str,
+        addr: Option<&Address>,
+        instance_id: Option<&str>,
+    ) -> io::Result<Response> {
+        let req = Request {
+            protocol_version: PROTOCOL_VERSION,
+            operation: operation.to_string(),
+            config: self.config.clone(),
+            address: addr.map(|a| PluginAddress {
+                id: a.id.clone(),
+                ip: a.ip.clone(),
+            }),
+            instance_id: instance_id.map(|v| v.to_string()),
+        };
+        let d = serde_json::to_vec(&req).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize plugin request {}", e),
+            )
+        })?;
+        log::debug!("running plugin {} '{operation}'", self.path.display());
+
+        let out = timeout(self.timeout, run(&self.path, &d))
+            .await
+            .map_err(|_| {
+                Error::new(
+                    ErrorKind::TimedOut,
+                    format!("plugin {} '{operation}' timed out", self.name),
+                )
+            })??;
+        let resp: Response = if out.stdout.iter().all(|b| b.is_ascii_whitespace()) {
+            Response::default()
+        } else {
+            serde_json::from_slice(&out.stdout).map_err(|e| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid plugin {} '{operation}' response {}", self.name, e),
+                )
+            })?
+        };
+        if !out.status.success() || resp.error.is_some() {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "plugin {} '{operation}' failed ({}) {}",
+                    self.name,
+                    out.status,
+                    resp.error.unwrap_or_default()
+                ),
+            ));
+        }
+        Ok(resp)
+    }
+
+    fn to_address(&self, addr: PluginAddress) -> Address {
+        Address {
+            provider: self.name.clone(),
+            id: addr.id,
+            ip: addr.ip,
+        }
+    }
+}
+
+async fn run(path: &Path, stdin: &[u8]) -> io::Result<std::process::Output> {
+    let mut child = process::Command::new(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .kill_on_drop(true)
+        .spawn()?;
+    if let Some(mut w) = child.stdin.take() {
+        // plugins may exit without reading the request (e.g., on "describe")
+        if let Err(e) = w.write_all(stdin).await {
+            if e.kind() != ErrorKind::BrokenPipe {
+                return Err(e);
+            }
+        }
+    }
+    child.wait_with_output().await
+}
+
+impl Provider for Plugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move {
+            self.call("local_instance_id", None, None)
+                .await?
+                .instance_id
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidData,
+                        format!("plugin {} returned no 'instance_id'", self.name),
+                    )
+                })
+        })
+    }
+
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move {
+            let resp = self.call("claim", None, None).await?;
+            Ok(resp.address.map(|a| self.to_address(a)))
+        })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            let resp = self.call("allocate", None, None).await?;
+            resp.address.map(|a| self.to_address(a)).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("plugin {} returned no 'address'", self.name),
+                )
+            })
+        })
+    }
+
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let resp = self.call("assigned_to", Some(addr), None).await?;
+            Ok(resp.instance_id)
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, instance_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            self.call("assign", Some(addr), Some(instance_id)).await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,741 @@
+use std::{
+    fs,
+    io::{self, Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use clap::{value_parser, Arg, ArgMatches, Command};
+use hyper::{Body, Method, Request};
+use tokio::{
+    net::{lookup_host, UdpSocket},
+    time::{timeout, Duration, Instant},
+};
+
+use crate::provider::{self, Address, BoxFuture, Provider};
+
+pub const NAME: &str = "portmap";
+
+/// NAT-PMP server port of the gateway.
+/// ref. <https://www.rfc-editor.org/rfc/rfc6886#section-3.1>
+const NATPMP_PORT: u16 = 5351;
+
+/// NAT-PMP retransmission starts at 250ms, doubling on every attempt.
+const NATPMP_INITIAL_TIMEOUT: Duration = Duration::from_millis(250);
+const NATPMP_ATTEMPTS: u32 = 5;
+
+/// SSDP multicast address, and the search target of the Internet Gateway Device.
+/// ref. <http://upnp.org/specs/arch/UPnP-arch-DeviceArchitecture-v1.1.pdf>
+const SSDP_ADDR: &str = "239.255.255.250:1900";
+const SSDP_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+const SSDP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// WAN connection services of the IGD that manage the port mappings.
+const UPNP_SERVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:",
+    "urn:schemas-upnp-org:service:WANPPPConnection:",
+];
+
+/// UPnP error code of "GetSpecificPortMappingEntry" for no such mapping.
+const UPNP_NO_SUCH_ENTRY: &str = "714";
+
+/// Timeout of each UPnP HTTP request to the gateway.
+const UPNP_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages the port mappings of the local router (UPnP IGD or NAT-PMP)")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("port-mapping")
+                .about("Provisions the port mapping of the router to the local host")
+                .long_about(
+                    "
+
+For the home-lab nodes behind a consumer router, requests the port mapping
+from the external port of the router to the local host, and records the
+external IP and port in the state file, so that the same external port is
+requested again on restarts.
+
+With \"--method=auto\", NAT-PMP is tried first, then UPnP IGD. The NAT-PMP
+gateway defaults to the default route of the host. The UPnP gateway is
+discovered with SSDP, unless \"--gateway\" is the URL of its device
+description (e.g., \"http://192.168.1.1:5000/rootDesc.xml\").
+
+The mappings expire after \"--lifetime-seconds\", so the command should be
+re-run periodically (e.g., by a systemd timer at half the lifetime) to renew
+the lease. The external IP is re-read on every run, and the state file is
+updated if the ISP changed it.
+
+e.g.,
+
+$ ip-manager portmap port-mapping \
+--protocol=tcp \
+--internal-port=9651 \
+--state-file-path=/data/port-mapping.yaml
+
+",
+                )
+                .arg(
+                    Arg::new("METHOD")
+                        .long("method")
+                        .help("Sets the port mapping protocol")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(["auto", "natpmp", "upnp"])
+                        .default_value("auto"),
+                )
+                .arg(
+                    Arg::new("GATEWAY")
+                        .long("gateway")
+                        .help("Sets the NAT-PMP gateway (\"<ip>[:<port>]\") or the UPnP device description URL (empty to discover)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("PROTOCOL")
+                        .long("protocol")
+                        .help("Sets the protocol of the port mapping")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(["tcp", "udp"])
+                        .default_value("tcp"),
+                )
+                .arg(
+                    Arg::new("INTERNAL_PORT")
+                        .long("internal-port")
+                        .help("Sets the port of the local host to map to")
+                        .required(true)
+                        .num_args(1)
+                        .value_parser(value_parser!(u16).range(1..)),
+                )
+                .arg(
+                    Arg::new("EXTERNAL_PORT")
+                        .long("external-port")
+                        .help("Sets the external port to request (0 for the same as the internal port)")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(u16))
+                        .default_value("0"),
+                )
+                .arg(
+                    Arg::new("LIFETIME_SECONDS")
+                        .long("lifetime-seconds")
+                        .help("Sets the lease of the port mapping in seconds")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(u32).range(1..))
+                        .default_value("7200"),
+                )
+                .arg(
+                    Arg::new("DESCRIPTION")
+                        .long("description")
+                        .help("Sets the description of the UPnP port mapping")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("ip-manager"),
+                )
+                .arg(
+                    Arg::new("NO_STEAL")
+                        .long("no-steal")
+                        .help("Aborts rather than re-mapping the external port that is mapped to another host")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(bool))
+                        .default_value("true"),
+                )
+                .arg(
+                    Arg::new("STATE_FILE_PATH")
+                        .long("state-file-path")
+                        .help("Sets the file path to store the port mapping information mapped to this volume path")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/data/port-mapping.yaml"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub method: String,
+    pub gateway: String,
+    pub protocol: String,
+    pub internal_port: u16,
+    pub external_port: u16,
+    pub lifetime_seconds: u32,
+    pub description: String,
+    pub no_steal: bool,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        method: matches
+            .get_one::<String>("METHOD")
+            .unwrap_or(&String::from("auto"))
+            .clone(),
+        gateway: matches
+            .get_one::<String>("GATEWAY")
+            .unwrap_or(&String::new())
+            .clone(),
+        protocol: matches
+            .get_one::<String>("PROTOCOL")
+            .unwrap_or(&String::from("tcp"))
+            .clone(),
+        internal_port: *matches.get_one::<u16>("INTERNAL_PORT").unwrap_or(&0),
+        external_port: *matches.get_one::<u16>("EXTERNAL_PORT").unwrap_or(&0),
+        lifetime_seconds: *matches.get_one::<u32>("LIFETIME_SECONDS").unwrap_or(&7200),
+        description: matches
+            .get_one::<String>("DESCRIPTION")
+            .unwrap_or(&String::from("ip-manager"))
+            .clone(),
+        no_steal: *matches.get_one::<bool>("NO_STEAL").unwrap_or(&true),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/port-mapping.yaml"))
+            .clone(),
+    }
+}
+
+pub async fn execute(opts: Flags) -> io::Result<Address> {
+    let gateway = match opts.method.as_str() {
+        "natpmp" => Gateway::NatPmp(natpmp_gateway(&opts.gateway).await?),
+        "upnp" => upnp_gateway(&opts.gateway).await?,
+        _ => match natpmp_gateway(&opts.gateway).await {
+            Ok(addr) if natpmp_external_address(addr).await.is_ok() => Gateway::NatPmp(addr),
+            _ => {
+                log::info!("no NAT-PMP gateway -- trying UPnP IGD");
+                upnp_gateway(&opts.gateway).await?
+            }
+        },
+    };
+    let portmap = PortMap {
+        gateway,
+        protocol: opts.protocol.clone(),
+        internal_port: opts.internal_port,
+        external_port: if opts.external_port == 0 {
+            opts.internal_port
+        } else {
+            opts.external_port
+        },
+        lifetime_seconds: opts.lifetime_seconds,
+        description: opts.description.clone(),
+    };
+    let mut addr = provider::provision(&portmap, &opts.state_file_path, opts.no_steal).await?;
+
+    // home ISPs reassign the external IP, while the mapped port stays
+    let ip = portmap.external_ip().await?;
+    if ip != addr.ip {
+        log::warn!("external IP changed from {} to {ip}", addr.ip);
+        addr.ip = ip;
+        addr.sync(&opts.state_file_path)?;
+    }
+    Ok(addr)
+}
+
+/// Router that maps the external ports.
+enum Gateway {
+    /// NAT-PMP server address (e.g., "192.168.1.1:5351").
+    NatPmp(SocketAddr),
+    /// UPnP control URL, and the service type of the WAN connection.
+    Upnp {
+        control_url: String,
+        service_type: String,
+    },
+}
+
+/// Port mapping backend of the local router, where the address ID is the
+/// external port (e.g., "tcp:9651") and the instance ID is the LAN IP of the host.
+/// ref. <https://www.rfc-editor.org/rfc/rfc6886>
+/// ref. <http://upnp.org/specs/gw/UPnP-gw-WANIPConnection-v2-Service.pdf>
+pub struct PortMap {
+    gateway: Gateway,
+    protocol: String,
+    internal_port: u16,
+    external_port: u16,
+    lifetime_seconds: u32,
+    description: String,
+}
+
+impl PortMap {
+    async fn external_ip(&self) -> io::Result<String> {
+        match &self.gateway {
+            Gateway::NatPmp(gw) => Ok(natpmp_external_address(*gw).await?.to_string()),
+            Gateway::Upnp {
+                control_url,
+                service_type,
+            } => {
+                let resp = soap(control_url, service_type, "GetExternalIPAddress", &[]).await?;
+                element(&resp, "NewExternalIPAddress")
+                    .filter(|ip| !ip.is_empty())
+                    .map(str::to_string)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "gateway has no external IP"))
+            }
+        }
+    }
+
+    /// Maps the external port to the internal port of the host,
+    /// returning the external port the gateway mapped.
+    async fn map(&self, external_port: u16, internal_client: &str) -> io::Result<u16> {
+        match &self.gateway {
+            Gateway::NatPmp(gw) => {
+                let req = natpmp_mapping_request(
+                    &self.protocol,
+                    self.internal_port,
+                    external_port,
+                    self.lifetime_seconds,
+                );
+                let resp = natpmp_call(*gw, &req).await?;
+                let (mapped, lifetime) = parse_natpmp_mapping(&resp, &self.protocol)?;
+                log::info!(
+                    "NAT-PMP gateway {gw} mapped {}:{mapped} for {lifetime} seconds",
+                    self.protocol
+                );
+                Ok(mapped)
+            }
+            Gateway::Upnp {
+                control_url,
+                service_type,
+            } => {
+                soap(
+                    control_url,
+                    service_type,
+                    "AddPortMapping",
+                    &[
+                        ("NewRemoteHost", String::new()),
+                        ("NewExternalPort", external_port.to_string()),
+                        ("NewProtocol", self.protocol.to_uppercase()),
+                        ("NewInternalPort", self.internal_port.to_string()),
+                        ("NewInternalClient", internal_client.to_string()),
+                        ("NewEnabled", String::from("1")),
+                        ("NewPortMappingDescription", self.description.clone()),
+                        ("NewLeaseDuration", self.lifetime_seconds.to_string()),
+                    ],
+                )
+                .await?;
+                Ok(external_port)
+            }
+        }
+    }
+}
+
+/// Returns the external port of the address ID (e.g., "tcp:9651").
+fn port_of(addr: &Address) -> io::Result<u16> {
+    addr.id
+        .rsplit_once(':')
+        .and_then(|(_, p)| p.parse::<u16>().ok())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid port mapping ID '{}'", addr.id),
+            )
+        })
+}
+
+impl Provider for PortMap {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    /// Returns the LAN IP the host reaches the gateway with.
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move {
+            let gw = match &self.gateway {
+                Gateway::NatPmp(gw) => *gw,
+                Gateway::Upnp { control_url, .. } => {
+                    let uri: hyper::Uri = control_url.parse().map_err(|e| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("invalid control URL {control_url} {}", e),
+                        )
+                    })?;
+                    let host = uri.host().unwrap_or_default().to_string();
+                    let port = uri.port_u16().unwrap_or(80);
+                    let resolved = lookup_host((host.as_str(), port)).await?.next();
+                    resolved.ok_or_else(|| {
+                        Error::new(ErrorKind::NotFound, format!("failed to resolve {host}"))
+                    })?
+                }
+            };
+            // connecting a UDP socket sends nothing, but picks the route
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket.connect(gw).await?;
+            Ok(socket.local_addr()?.ip().to_string())
+        })
+    }
+
+    /// Routers hold no unmapped ports to reuse.
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move { Ok(None) })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            let internal_client = self.local_instance_id().await?;
+            let port = self.map(self.external_port, &internal_client).await?;
+            Ok(Address {
+                provider: NAME.to_string(),
+                id: format!("{}:{port}", self.protocol),
+                ip: self.external_ip().await?,
+            })
+        })
+    }
+
+    /// NAT-PMP cannot look up the mappings, and only maps to the requester,
+    /// so the mapping is always re-requested (renewing the lease).
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let (control_url, service_type) = match &self.gateway {
+                Gateway::NatPmp(_) => return Ok(None),
+                Gateway::Upnp {
+                    control_url,
+                    service_type,
+                } => (control_url, service_type),
+            };
+            let res = soap(
+                control_url,
+                service_type,
+                "GetSpecificPortMappingEntry",
+                &[
+                    ("NewRemoteHost", String::new()),
+                    ("NewExternalPort", port_of(addr)?.to_string()),
+                    ("NewProtocol", self.protocol.to_uppercase()),
+                ],
+            )
+            .await;
+            match res {
+                Ok(resp) => Ok(element(&resp, "NewInternalClient").map(str::to_string)),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, instance_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let port = port_of(addr)?;
+            if let Gateway::Upnp {
+                control_url,
+                service_type,
+            } = &self.gateway
+            {
+                // "AddPortMapping" conflicts with the mapping to another host
+                if self.assigned_to(addr).await?.is_some() {
+                    soap(
+                        control_url,
+                        service_type,
+                        "DeletePortMapping",
+                        &[
+                            ("NewRemoteHost", String::new()),
+                            ("NewExternalPort", port.to_string()),
+                            ("NewProtocol", self.protocol.to_uppercase()),
+                        ],
+                    )
+                    .await?;
+                }
+            }
+            let mapped = self.map(port, instance_id).await?;
+            if mapped != port {
+                return Err(Error::new(
+                    ErrorKind::AddrInUse,
+                    format!(
+                        "gateway mapped {}:{mapped} rather than the recorded port {port} -- the port is taken by another host",
+                        self.protocol
+                    ),
+                ));
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Returns the NAT-PMP server of "--gateway", or of the default route.
+async fn natpmp_gateway(gateway: &str) -> io::Result<SocketAddr> {
+    if gateway.is_empty() {
+        return Ok(SocketAddr::new(IpAddr::V4(default_gateway()?), NATPMP_PORT));
+    }
+    if let Ok(ip) = gateway.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, NATPMP_PORT));
+    }
+    gateway.parse::<SocketAddr>().map_err(|_| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid NAT-PMP gateway '{gateway}' (expected '<ip>[:<port>]')"),
+        )
+    })
+}
+
+/// Returns the IPv4 gateway of the default route.
+fn default_gateway() -> io::Result<Ipv4Addr> {
+    let routes = fs::read_to_string("/proc/net/route").map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!(
+                "failed to read the default route ({}) -- set \"--gateway\"",
+                e
+            ),
+        )
+    })?;
+    parse_default_route(&routes)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "no default route -- set \"--gateway\""))
+}
+
+/// Parses "/proc/net/route", where the addresses are little-endian hex
+/// (e.g., "0101A8C0" for "192.168.1.1").
+fn parse_default_route(routes: &str) -> Option<Ipv4Addr> {
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 3 || fields[1] != "00000000" {
+            return None;
+        }
+        let gw = u32::from_str_radix(fields[2], 16).ok()?;
+        Some(Ipv4Addr::from(gw.swap_bytes()))
+    })
+}
+
+/// Returns the mapping request of the protocol
+/// ("udp" for opcode 1, "tcp" for opcode 2).
+fn natpmp_mapping_request(
+    protocol: &str,
+    internal_port: u16,
+    external_port: u16,
+    lifetime_seconds: u32,
+) -> Vec<u8> {
+    let mut b = vec![0, if protocol == "udp" { 1 } else { 2 }, 0, 0];
+    b.extend_from_slice(&internal_port.to_be_bytes());
+    b.extend_from_slice(&external_port.to_be_bytes());
+    b.extend_from_slice(&lifetime_seconds.to_be_bytes());
+    b
+}
+
+/// Checks the version, the opcode, and the result code of the response.
+fn check_natpmp_response(b: &[u8], opcode: u8, len: usize) -> io::Result<()> {
+    if b.len() < len || b[0] != 0 || b[1] != 128 + opcode {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid NAT-PMP response {:?}", b),
+        ));
+    }
+    match u16::from_be_bytes([b[2], b[3]]) {
+        0 => Ok(()),
+        code => {
+            let reason = match code {
+                1 => "unsupported version",
+                2 => "not authorized -- enable NAT-PMP on the router",
+                3 => "network failure -- the router has no external IP yet",
+                4 => "out of resources",
+                5 => "unsupported opcode",
+                _ => "unknown result code",
+            };
+            Err(Error::new(
+                ErrorKind::Other,
+                format!("NAT-PMP request failed with {code} ({reason})"),
+            ))
+        }
+    }
+}
+
+/// Parses the mapping response, returning the mapped external port and the lifetime.
+fn parse_natpmp_mapping(b: &[u8], protocol: &str) -> io::Result<(u16, u32)> {
+    check_natpmp_response(b, if protocol == "udp" { 1 } else { 2 }, 16)?;
+    Ok((
+        u16::from_be_bytes([b[10], b[11]]),
+        u32::from_be_bytes([b[12], b[13], b[14], b[15]]),
+    ))
+}
+
+async fn natpmp_external_address(gateway: SocketAddr) -> io::Result<Ipv4Addr> {
+    let b = natpmp_call(gateway, &[0, 0]).await?;
+    check_natpmp_response(&b, 0, 12)?;
+    Ok(Ipv4Addr::new(b[8], b[9], b[10], b[11]))
+}
+
+/// Sends the NAT-PMP request, retransmitting with the doubling timeout.
+async fn natpmp_call(gateway: SocketAddr, req: &[u8]) -> io::Result<Vec<u8>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(gateway).await?;
+    let mut buf = [0u8; 64];
+    let mut wait = NATPMP_INITIAL_TIMEOUT;
+    for attempt in 1..=NATPMP_ATTEMPTS {
+        socket.send(req).await?;
+        match timeout(wait, socket.recv(&mut buf)).await {
+            Ok(Ok(n)) => return Ok(buf[..n].to_vec()),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => log::debug!("NAT-PMP request {attempt} to {gateway} timed out"),
+        }
+        wait *= 2;
+    }
+    Err(Error::new(
+        ErrorKind::TimedOut,
+        format!("NAT-PMP gateway {gateway} did not respond after {NATPMP_ATTEMPTS} attempts"),
+    ))
+}
+
+/// Returns the UPnP gateway of the device description URL of "--gateway",
+/// or of the first Internet Gateway Device answering the SSDP search.
+async fn upnp_gateway(gateway: &str) -> io::Result<Gateway> {
+    let location = if gateway.starts_with("http://") {
+        gateway.to_string()
+    } else {
+        ssdp_search().await?
+    };
+    let desc = http_call(Method::GET, &location, vec![], String::new()).await?;
+    for service in desc.split("<service>").skip(1) {
+        let service_type = match element(service, "serviceType") {
+            Some(v) if UPNP_SERVICES.iter().any(|s| v.starts_with(s)) => v,
+            _ => continue,
+        };
+        let control = element(service, "controlURL").ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{service_type} of {location} has no controlURL"),
+            )
+        })?;
+        let base = element(&desc, "URLBase").unwrap_or(&location);
+        let control_url = resolve_url(base, control);
+        log::info!("found UPnP {service_type} at {control_url}");
+        return Ok(Gateway::Upnp {
+            control_url,
+            service_type: service_type.to_string(),
+        });
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!("{location} has no WAN connection service -- not an Internet Gateway Device"),
+    ))
+}
+
+/// Returns the "LOCATION" of the first Internet Gateway Device answering the search.
+async fn ssdp_search() -> io::Result<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    let req = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {SSDP_ADDR}\r\nMAN: \"ssdp:discover\"\r\nMX: 2\r\nST: {SSDP_SEARCH_TARGET}\r\n\r\n"
+    );
+    socket.send_to(req.as_bytes(), SSDP_ADDR).await?;
+
+    let deadline = Instant::now() + SSDP_TIMEOUT;
+    let mut buf = [0u8; 2048];
+    while let Ok(res) = timeout(
+        deadline.saturating_duration_since(Instant::now()),
+        socket.recv_from(&mut buf),
+    )
+    .await
+    {
+        let (n, from) = res?;
+        let resp = String::from_utf8_lossy(&buf[..n]);
+        let location = resp.lines().find_map(|l| {
+            let (k, v) = l.split_once(':')?;
+            if k.trim().eq_ignore_ascii_case("location") {
+                Some(v.trim().to_string())
+            } else {
+                None
+            }
+        });
+        if let Some(location) = location {
+            log::info!("found UPnP gateway {from} at {location}");
+            return Ok(location);
+        }
+    }
+    Err(Error::new(
+        ErrorKind::NotFound,
+        format!("no UPnP gateway answered the SSDP search in {SSDP_TIMEOUT:?} -- enable UPnP on the router, or set \"--gateway\""),
+    ))
+}
+
+/// Resolves the (relative) control URL against the base URL.
+fn resolve_url(base: &str, url: &str) -> String {
+    if url.starts_with("http://") {
+        return url.to_string();
+    }
+    let origin_end = base
+        .strip_prefix("http://")
+        .and_then(|rest| rest.find('/'))
+        .map(|i| i + "http://".len())
+        .unwrap_or(base.len());
+    format!(
+        "{}/{}",
+        base[..origin_end].trim_end_matches('/'),
+        url.trim_start_matches('/')
+    )
+}
+
+/// Returns the text of the first element (e.g., "<controlURL>/ctl</controlURL>").
+fn element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{name}>");
+    let close = format!("</{name}>");
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&close)? + start;
+    Some(xml[start..end].trim())
+}
+
+/// Invokes the SOAP action of the WAN connection service, returning the response body.
+/// Returns "ErrorKind::NotFound" for no such mapping.
+async fn soap(
+    control_url: &str,
+    service_type: &str,
+    action: &str,
+    args: &[(&str, String)],
+) -> io::Result<String> {
+    let args: String = args
+        .iter()
+        .map(|(k, v)| format!("<{k}>{v}</{k}>"))
+        .collect();
+    let body = format!(
+        "<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:{action} xmlns:u=\"{service_type}\">{args}</u:{action}></s:Body></s:Envelope>"
+    );
+    let headers = vec![
+        ("Content-Type", String::from("text/xml; charset=\"utf-8\"")),
+        ("SOAPAction", format!("\"{service_type}#{action}\"")),
+    ];
+    http_call(Method::POST, control_url, headers, body)
+        .await
+        .map_err(|e| {
+            let msg = e.to_string();
+            match element(&msg, "errorCode") {
+                Some(UPNP_NO_SUCH_ENTRY) => Error::new(ErrorKind::NotFound, msg),
+                Some(code) => Error::new(
+                    e.kind(),
+                    format!(
+                        "UPnP {action} failed with {code} ({})",
+                        element(&msg, "errorDescription").unwrap_or_default()
+                    ),
+                ),
+                None => e,
+            }
+        })
+}
+
+/// Sends the plain HTTP request to the gateway on the LAN.
+async fn http_call(
+    method: Method,
+    url: &str,
+    headers: Vec<(&str, String)>,
+    body: String,
+) -> io::Result<String> {
+    let mut req = Request::builder().method(method).uri(url);
+    for (k, v) in headers {
+        req = req.header(k, v);
+    }
+    let req = req.body(Body::from(body)).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid gateway URL {url} {}", e),
+        )
+    })?;
+    let resp = timeout(UPNP_TIMEOUT, hyper::Client::new().request(req))
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, format!("gateway {url} timed out")))?
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed gateway request {}", e)))?;
+    let status = resp.status();
+    let b = hyper::body::to_bytes(resp.into_body())
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("failed to read gateway {}", e)))?;
+    let b = String::from_utf8_lossy(&b).to_string();
+    if !status.is_success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("gateway {url} returned {status} '{}'", b.trim()),
+        ));
+    }
+    Ok(b)
+}
@@ -0,0 +1,179 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{ec2, eip, prefix_list, sdk};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use tokio::time::{sleep, Duration};
+
+pub const NAME: &str = "prefix-list";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages the EC2 managed prefix list of the tool-managed EIPs")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("sync")
+                .about("Syncs the managed prefix list with the associated tool-managed EIPs")
+                .long_about(
+                    "
+
+Makes the managed prefix list contain exactly the associated tool-managed
+EIPs (with the \"Kind\" tag, as \"<public-ip>/32\"), so that the security
+groups that reference the prefix list allow the fleet's public IPs:
+
+- adds the EIPs associated since the last sync (described as \"ip-manager:<Id>\")
+- removes the entries of the EIPs released or returned to the pool
+- never touches the entries added by hand (other descriptions)
+
+Runs once, or every \"--interval-seconds\" to keep the prefix list in lockstep
+(e.g., a Kubernetes Deployment, or \"--post-associate-hook\" of \"aws eip\").
+Requires \"ec2:DescribeAddresses\", \"ec2:DescribeManagedPrefixLists\",
+\"ec2:GetManagedPrefixListEntries\", and \"ec2:ModifyManagedPrefixList\".
+
+e.g.,
+
+$ ip-manager prefix-list sync \
+--prefix-list-id=pl-0123456789abcdef0 \
+--kind-tag-key=Kind \
+--kind-tag-value=aws-ip-provisioner \
+--interval-seconds=60
+
+",
+                )
+                .arg(
+                    Arg::new("PREFIX_LIST_ID")
+                        .long("prefix-list-id")
+                        .help("Sets the ID of the IPv4 managed prefix list to sync")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("ID_TAG_KEY")
+                        .long("id-tag-key")
+                        .help("Sets the key of the EIP tag that identifies the node (in the entry description)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("Id"),
+                )
+                .arg(
+                    Arg::new("KIND_TAG_KEY")
+                        .long("kind-tag-key")
+                        .help("Sets the key of the EIP tag that groups the tool-managed EIPs")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("Kind"),
+                )
+                .arg(
+                    Arg::new("KIND_TAG_VALUE")
+                        .long("kind-tag-value")
+                        .help("Sets the value of the EIP tag that groups the tool-managed EIPs (empty for all kinds)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("INTERVAL_SECONDS")
+                        .long("interval-seconds")
+                        .help("Sets the interval to sync in seconds (0 to sync once)")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(u64))
+                        .default_value("0"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub prefix_list_id: String,
+    pub id_tag_key: String,
+    pub kind_tag_key: String,
+    pub kind_tag_value: String,
+    pub interval_seconds: u64,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        prefix_list_id: matches
+            .get_one::<String>("PREFIX_LIST_ID")
+            .unwrap_or(&String::new())
+            .clone(),
+        id_tag_key: matches
+            .get_one::<String>("ID_TAG_KEY")
+            .unwrap_or(&String::from("Id"))
+            .clone(),
+        kind_tag_key: matches
+            .get_one::<String>("KIND_TAG_KEY")
+            .unwrap_or(&String::from("Kind"))
+            .clone(),
+        kind_tag_value: matches
+            .get_one::<String>("KIND_TAG_VALUE")
+            .unwrap_or(&String::new())
+            .clone(),
+        interval_seconds: *matches.get_one::<u64>("INTERVAL_SECONDS").unwrap_or(&0),
+    }
+}
+
+/// Syncs the prefix list, once or every "--interval-seconds".
+/// In the loop, a failed sync is logged and retried at the next interval.
+pub async fn sync(opts: Flags, output: &str) -> io::Result<()> {
+    let shared_config = sdk::load_config(None, &sdk::Options::default()).await?;
+    let ec2_manager = ec2::Manager::new(&sdk::for_service(
+        &shared_config,
+        "ec2",
+        &sdk::Options::default(),
+    )?);
+
+    loop {
+        match sync_once(&ec2_manager, &opts).await {
+            Ok(plan) => print_plan(&opts.prefix_list_id, &plan, output)?,
+            Err(e) if opts.interval_seconds > 0 => {
+                log::warn!("failed to sync prefix list {} '{}'", opts.prefix_list_id, e);
+            }
+            Err(e) => return Err(e),
+        }
+        if opts.interval_seconds == 0 {
+            return Ok(());
+        }
+        sleep(Duration::from_secs(opts.interval_seconds)).await;
+    }
+}
+
+async fn sync_once(ec2_manager: &ec2::Manager, opts: &Flags) -> io::Result<prefix_list::Plan> {
+    let fleet = eip::describe_fleet(
+        ec2_manager,
+        &opts.kind_tag_key,
+        &opts.kind_tag_value,
+        &opts.id_tag_key,
+    )
+    .await?;
+    let desired: Vec<prefix_list::Entry> = fleet
+        .iter()
+        .map(|(ip, id)| prefix_list::Entry::for_eip(ip, id))
+        .collect();
+    prefix_list::sync(ec2_manager, &opts.prefix_list_id, &desired).await
+}
+
+fn print_plan(prefix_list_id: &str, plan: &prefix_list::Plan, output: &str) -> io::Result<()> {
+    let added: Vec<&str> = plan.add.iter().map(|e| e.cidr.as_str()).collect();
+    if output == "json" {
+        let d = serde_json::to_string(&serde_json::json!({
+            "prefix_list_id": prefix_list_id,
+            "added": added,
+            "removed": plan.remove,
+        }))
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize prefix list changes {}", e),
+            )
+        })?;
+        println!("{d}");
+    } else if !plan.is_empty() {
+        log::info!(
+            "synced prefix list {prefix_list_id} (added {:?}, removed {:?})",
+            added,
+            plan.remove
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use std::{
+    fs::{self, File},
+    future::Future,
+    io::{self, Error, ErrorKind, Write},
+    path::Path,
+    pin::Pin,
+};
+
+use serde::{Deserialize, Serialize};
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// Public IP address of a provider, persisted in the state file
+/// so that the same address is reused on restarts.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Address {
+    /// Provider name (e.g., "digitalocean").
+    pub provider: String,
+    /// Provider resource ID of the address (same as the IP if the provider has no separate ID).
+    pub id: String,
+    pub ip: String,
+}
+
+impl Address {
+    /// Loads the address from the YAML state file.
+    pub fn load(file_path: &str) -> io::Result<Self> {
+        log::info!("loading address from {}", file_path);
+        let f = File::open(file_path)?;
+        serde_yaml::from_reader(f).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid YAML in {file_path}: {}", e),
+            )
+        })
+    }
+
+    /// Saves the address to the YAML state file.
+    pub fn sync(&self, file_path: &str) -> io::Result<()> {
+        log::info!("syncing address to {}", file_path);
+        if let Some(parent_dir) = Path::new(file_path).parent() {
+            fs::create_dir_all(parent_dir)?;
+        }
+        let d = serde_yaml::to_string(self).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize address {}", e),
+            )
+        })?;
+        let mut f = File::create(file_path)?;
+        f.write_all(d.as_bytes())
+    }
+}
+
+/// Cloud backend that reserves public IP addresses and assigns them to the local instance.
+/// Each implementation owns its API client and the selector (e.g., tags) for reuse.
+pub trait Provider: Send + Sync {
+    /// Returns the provider name to record in the state file.
+    fn name(&self) -> &str;
+
+    /// Returns the ID of the local instance from the metadata service.
+    fn local_instance_id(&self) -> BoxFuture<'_, String>;
+
+    /// Finds an unassigned address reserved by this tool for reuse.
+    /// Returns "None" if nothing is available.
+    fn claim(&self) -> BoxFuture<'_, Option<Address>>;
+
+    /// Reserves a new address.
+    fn allocate(&self) -> BoxFuture<'_, Address>;
+
+    /// Returns the ID of the instance the address is assigned to, if any.
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>>;
+
+    /// Assigns the address to the instance, waiting until it completes.
+    fn assign<'a>(&'a self, addr: &'a Address, instance_id: &'a str) -> BoxFuture<'a, ()>;
+}
+
+/// Loads (or claims, or allocates) the address and assigns it to the local instance.
+/// Commands may run multiple times with idempotency.
+pub async fn provision(
+    provider: &dyn Provider,
+    state_file_path: &str,
+    no_steal: bool,
+) -> io::Result<Address> {
+    let instance_id = provider.local_instance_id().await?;
+    log::info!(
+        "provisioning {} address to the local instance {instance_id}",
+        provider.name()
+    );
+
+    let addr = if Path::new(state_file_path).exists() {
+        log::info!("state file {state_file_path} exists -- loading existing address");
+        let addr = Address::load(state_file_path)?;
+        if addr.provider != provider.name() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "state file {state_file_path} is for provider {}, not {}",
+                    addr.provider,
+                    provider.name()
+                ),
+            ));
+        }
+        addr
+    } else if let Some(addr) = provider.claim().await? {
+        log::info!("claimed unassigned address {}", addr.ip);
+        addr
+    } else {
+        log::info!("no address to reuse -- allocating one");
+        provider.allocate().await?
+    };
+    addr.sync(state_file_path)?;
+
+    match provider.assigned_to(&addr).await? {
+        Some(id) if id == instance_id => {
+            log::info!("address {} is already assigned to {instance_id}", addr.ip);
+            return Ok(addr);
+        }
+        Some(id) if no_steal => {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!(
+                    "address {} is assigned to another instance {id} -- skipping (no-steal)",
+                    addr.ip
+                ),
+            ));
+        }
+        Some(id) => log::warn!(
+            "address {} is assigned to another instance {id} -- re-assigning",
+            addr.ip
+        ),
+        None => {}
+    }
+    provider.assign(&addr, &instance_id).await?;
+    log::info!("assigned address {} to {instance_id}", addr.ip);
+    Ok(addr)
+}
@@ -0,0 +1,348 @@
+use std::{
+    env,
+    io::{self, Error, ErrorKind},
+};
+
+use aws_ip_provisioner::{sdk, secret};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use hyper::Method;
+
+use crate::{
+    http::{self, Client},
+    provider::{self, Address, BoxFuture, Provider},
+};
+
+pub const NAME: &str = "scaleway";
+
+const API_URL: &str = "https://api.scaleway.com";
+
+/// ref. <https://www.scaleway.com/en/docs/compute/instances/how-to/use-instance-metadata/>
+const METADATA_URL: &str = "http://169.254.42.42/conf?format=json";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages Scaleway addresses")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("flexible-ip")
+                .about("Provisions the flexible IP to the local instance or Elastic Metal server")
+                .long_about(
+                    "
+
+For instances, the server ID, zone, and project are fetched from the metadata service.
+Elastic Metal servers have no metadata service, so \"--server-id\", \"--zone\",
+and \"--project-id\" must be set.
+
+An unattached flexible IP with the tag in the zone is claimed,
+or a new one is created with the tag, unless the state file exists.
+
+e.g.,
+
+$ ip-manager scaleway flexible-ip \
+--secret-key-source=env:SCW_SECRET_KEY \
+--server-type=instance \
+--tag=ip-manager \
+--state-file-path=/data/flexible-ip.yaml
+
+$ ip-manager scaleway flexible-ip \
+--secret-key-source=env:SCW_SECRET_KEY \
+--server-type=baremetal \
+--server-id=TEST-SERVER-ID \
+--zone=fr-par-2 \
+--project-id=TEST-PROJECT-ID \
+--state-file-path=/data/flexible-ip.yaml
+
+",
+                )
+                .arg(
+                    Arg::new("SECRET_KEY_SOURCE")
+                        .long("secret-key-source")
+                        .help("Sets where to read the API secret key (\"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\")")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("env:SCW_SECRET_KEY"),
+                )
+                .arg(
+                    Arg::new("SERVER_TYPE")
+                        .long("server-type")
+                        .help("Sets the type of the local server")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(["instance", "baremetal"])
+                        .default_value("instance"),
+                )
+                .arg(
+                    Arg::new("SERVER_ID")
+                        .long("server-id")
+                        .help("Sets the local server ID (empty to fetch from the instance metadata)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("ZONE")
+                        .long("zone")
+                        .help("Sets the zone (e.g., fr-par-1, empty to fetch from the instance metadata)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("PROJECT_ID")
+                        .long("project-id")
+                        .help("Sets the project ID to create flexible IPs in (defaults to \"SCW_DEFAULT_PROJECT_ID\", or the instance metadata)")
+                        .required(false)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("TAG")
+                        .long("tag")
+                        .help("Sets the tag to find and create tool-managed flexible IPs")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("ip-manager"),
+                )
+                .arg(
+                    Arg::new("NO_STEAL")
+                        .long("no-steal")
+                        .help("Aborts rather than re-attaching the flexible IP that is attached to another server")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(bool))
+                        .default_value("true"),
+                )
+                .arg(
+                    Arg::new("STATE_FILE_PATH")
+                        .long("state-file-path")
+                        .help("Sets the file path to store the flexible IP information mapped to this volume path")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/data/flexible-ip.yaml"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub secret_key_source: String,
+    pub server_type: String,
+    pub server_id: String,
+    pub zone: String,
+    pub project_id: String,
+    pub tag: String,
+    pub no_steal: bool,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        secret_key_source: matches
+            .get_one::<String>("SECRET_KEY_SOURCE")
+            .unwrap_or(&String::from("env:SCW_SECRET_KEY"))
+            .clone(),
+        server_type: matches
+            .get_one::<String>("SERVER_TYPE")
+            .unwrap_or(&String::from("instance"))
+            .clone(),
+        server_id: matches
+            .get_one::<String>("SERVER_ID")
+            .unwrap_or(&String::new())
+            .clone(),
+        zone: matches
+            .get_one::<String>("ZONE")
+            .unwrap_or(&String::new())
+            .clone(),
+        project_id: match matches.get_one::<String>("PROJECT_ID") {
+            Some(v) if !v.is_empty() => v.clone(),
+            _ => env::var("SCW_DEFAULT_PROJECT_ID").unwrap_or_default(),
+        },
+        tag: matches
+            .get_one::<String>("TAG")
+            .unwrap_or(&String::from("ip-manager"))
+            .clone(),
+        no_steal: *matches.get_one::<bool>("NO_STEAL").unwrap_or(&true),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/flexible-ip.yaml"))
+            .clone(),
+    }
+}
+
+pub async fn execute(opts: Flags) -> io::Result<Address> {
+    let mut server_id = opts.server_id.clone();
+    let mut zone = opts.zone.clone();
+    let mut project_id = opts.project_id.clone();
+    if server_id.is_empty() || zone.is_empty() || project_id.is_empty() {
+        if opts.server_type == "baremetal" {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Elastic Metal requires --server-id, --zone, and --project-id",
+            ));
+        }
+        let meta = http::fetch_metadata(METADATA_URL, &[]).await?;
+        let meta: serde_json::Value = serde_json::from_str(&meta).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to parse instance metadata {}", e),
+            )
+        })?;
+        if server_id.is_empty() {
+            server_id = meta["id"].as_str().unwrap_or_default().to_string();
+        }
+        if zone.is_empty() {
+            zone = meta["zone"].as_str().unwrap_or_default().to_string();
+        }
+        if project_id.is_empty() {
+            project_id = meta["project"].as_str().unwrap_or_default().to_string();
+        }
+    }
+    log::info!("using server {server_id} in zone {zone}");
+
+    let secret_key = secret::read(
+        &opts.secret_key_source,
+        "SCW_SECRET_KEY",
+        &sdk::Options::default(),
+    )
+    .await?;
+    let scaleway = Scaleway {
+        client: Client::new(API_URL, vec![(String::from("X-Auth-Token"), secret_key)]),
+        baremetal: opts.server_type == "baremetal",
+        server_id,
+        zone,
+        project_id,
+        tag: opts.tag.clone(),
+    };
+    provider::provision(&scaleway, &opts.state_file_path, opts.no_steal).await
+}
+
+/// Scaleway flexible IP backend, for instances and Elastic Metal servers.
+/// ref. <https://www.scaleway.com/en/developers/api/instance/#path-ips>
+/// ref. <https://www.scaleway.com/en/developers/api/elastic-metal-flexible-ip/>
+pub struct Scaleway {
+    client: Client,
+    baremetal: bool,
+    server_id: String,
+    zone: String,
+    project_id: String,
+    tag: String,
+}
+
+impl Scaleway {
+    fn path(&self, suffix: &str) -> String {
+        if self.baremetal {
+            format!("/flexible-ip/v1alpha1/zones/{}/fips{suffix}", self.zone)
+        } else {
+            format!("/instance/v1/zones/{}/ips{suffix}", self.zone)
+        }
+    }
+
+    fn to_address(&self, v: &serde_json::Value) -> io::Result<Address> {
+        let id = v["id"]
+            .as_str()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "flexible IP has no 'id'"))?;
+        let ip = if self.baremetal {
+            v["ip_address"].as_str().map(|v| v.trim_end_matches("/32"))
+        } else {
+            v["address"].as_str()
+        }
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "flexible IP has no address"))?;
+        Ok(Address {
+            provider: NAME.to_string(),
+            id: id.to_string(),
+            ip: ip.to_string(),
+        })
+    }
+
+    /// Returns the attached server ID, if any.
+    fn attached_server(&self, v: &serde_json::Value) -> Option<String> {
+        if self.baremetal {
+            v["server_id"].as_str().map(|v| v.to_string())
+        } else {
+            v["server"]["id"].as_str().map(|v| v.to_string())
+        }
+    }
+}
+
+impl Provider for Scaleway {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move { Ok(self.server_id.clone()) })
+    }
+
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move {
+            let resp = self
+                .client
+                .get(&self.path(&format!("?tags={}&per_page=100", self.tag)))
+                .await?;
+            let list_key = if self.baremetal {
+                "flexible_ips"
+            } else {
+                "ips"
+            };
+            for v in resp[list_key].as_array().into_iter().flatten() {
+                if self.attached_server(v).is_none() {
+                    return Ok(Some(self.to_address(v)?));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            log::info!("creating flexible IP in {}", self.zone);
+            let req = if self.baremetal {
+                serde_json::json!({ "project_id": self.project_id, "tags": [self.tag], "is_ipv6": false })
+            } else {
+                serde_json::json!({ "project": self.project_id, "tags": [self.tag] })
+            };
+            let resp = self.client.post(&self.path(""), req).await?;
+            let v = if self.baremetal { &resp } else { &resp["ip"] };
+            self.to_address(v)
+        })
+    }
+
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let resp = self
+                .client
+                .get(&self.path(&format!("/{}", addr.id)))
+                .await?;
+            let v = if self.baremetal { &resp } else { &resp["ip"] };
+            Ok(self.attached_server(v))
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, instance_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if self.baremetal {
+                // attach fails if already attached elsewhere
+                if self.assigned_to(addr).await?.is_some() {
+                    self.client
+                        .post(
+                            &self.path("/detach"),
+                            serde_json::json!({ "fips_ids": [addr.id] }),
+                        )
+                        .await?;
+                }
+                self.client
+                    .post(
+                        &self.path("/attach"),
+                        serde_json::json!({ "fips_ids": [addr.id], "server_id": instance_id }),
+                    )
+                    .await?;
+                return Ok(());
+            }
+            self.client
+                .request(
+                    Method::PATCH,
+                    &self.path(&format!("/{}", addr.id)),
+                    Some(serde_json::json!({ "server": instance_id })),
+                )
+                .await?;
+            Ok(())
+        })
+    }
+}
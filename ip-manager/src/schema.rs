@@ -0,0 +1,64 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{config, eip, summary};
+use clap::{ArgMatches, Command};
+use serde_json::Value;
+
+pub const NAME: &str = "generate-schema";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Prints the JSON Schema of the files that the tool reads or writes")
+        .long_about(
+            "
+
+Prints the JSON Schema of the files of this version of the tool to stdout,
+so that editors and GitOps pipelines can validate them before deployment,
+and the orchestrators can parse the run summary.
+
+e.g.,
+
+$ ip-manager generate-schema config > config.schema.json
+$ ip-manager generate-schema state > eip.schema.json
+$ ip-manager generate-schema summary > summary.schema.json
+
+",
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("config")
+                .about("Prints the schema of the config file (\"--config-file\")"),
+        )
+        .subcommand(
+            Command::new("state").about(
+                "Prints the schema of the mounted EIP file (\"--mounted-eip-file-path\", YAML)",
+            ),
+        )
+        .subcommand(
+            Command::new("summary")
+                .about("Prints the schema of the run summary file (\"--summary-path\")"),
+        )
+}
+
+pub fn execute(matches: &ArgMatches) -> io::Result<()> {
+    match matches.subcommand_name() {
+        Some("config") => print(&config::schema()),
+        Some("state") => print(&eip::state_schema()),
+        Some("summary") => print(&summary::schema()),
+        name => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown schema {:?}", name),
+        )),
+    }
+}
+
+fn print(schema: &Value) -> io::Result<()> {
+    let d = serde_json::to_string_pretty(schema).map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to serialize schema {}", e),
+        )
+    })?;
+    println!("{d}");
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    time::Instant,
+};
+
+use aws_ip_provisioner::{
+    command as aws_eip,
+    context::Context,
+    ec2, eip, imds,
+    provisioner::{AwsEc2, Ec2},
+    sdk,
+};
+use aws_sdk_ec2::model::Filter;
+use clap::{Arg, ArgMatches, Command};
+use serde::Serialize;
+
+pub const NAME: &str = "self-test";
+
+/// Key of the tag on the temporary EIP, to find the leftovers
+/// (e.g., the run was killed before the release).
+pub const SELF_TEST_TAG_KEY: &str = "SelfTest";
+
+/// Quota errors, to report with the guidance.
+const QUOTA_ERRORS: &[&str] = &["AddressLimitExceeded", "MaxPublicIpv4AddressLimitExceeded"];
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Allocates, associates, and releases a temporary EIP to check the IAM policy, quotas, and connectivity")
+        .long_about(
+            "
+
+Exercises the full path of \"aws eip\" with a temporary EIP, without touching
+the real Id-tagged address (e.g., in the machine image validation pipeline):
+
+1. resolves the instance ID (IMDS, or \"--instance-id-fallback\")
+2. allocates the temporary EIP with the same \"Kind\" tag, the \"Id\" tag with
+   the \"-self-test\" suffix, and the \"SelfTest=true\" tag
+3. associates the EIP with the instance and disassociates it right after,
+   or checks the association with the dry run if the instance already has
+   a public IP (\"--associate=auto\") or with \"--associate=dry-run\"
+4. releases the EIP, even if any of the above failed
+
+Takes the same flags as \"aws eip\" (credentials, endpoints, IMDS, tags),
+and exits non-zero if any step failed. The IAM policy must also allow
+\"ec2:DisassociateAddress\" and \"ec2:ReleaseAddress\", which \"aws eip\"
+itself does not need.
+
+e.g.,
+
+$ ip-manager self-test \
+-- \
+--id-tag-key=Id \
+--id-tag-value=TEST-ID \
+--kind-tag-key=Kind \
+--kind-tag-value=aws-ip-provisioner \
+--mounted-eip-file-path=/data/eip.yaml
+
+$ ip-manager --output=json self-test --associate=dry-run -- <aws eip flags>
+
+",
+        )
+        .arg(
+            Arg::new("ASSOCIATE")
+                .long("associate")
+                .help("Sets whether to associate the temporary EIP (auto to dry-run if the instance already has a public IP)")
+                .required(false)
+                .num_args(1)
+                .value_parser(["auto", "dry-run"])
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::new("ARGS")
+                .help("Sets the flags of \"aws eip\" to test with")
+                .required(false)
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub associate: String,
+    pub args: Vec<String>,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        associate: matches
+            .get_one::<String>("ASSOCIATE")
+            .unwrap_or(&String::from("auto"))
+            .clone(),
+        args: matches
+            .get_many::<String>("ARGS")
+            .map(|args| args.cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// Result of each step.
+#[derive(Debug, Serialize)]
+pub struct Step {
+    pub name: String,
+    pub ok: bool,
+    pub seconds: f64,
+    /// Resource ID on success, error on failure.
+    pub detail: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub ok: bool,
+    pub instance_id: String,
+    pub allocation_id: String,
+    pub public_ip: String,
+    pub steps: Vec<Step>,
+}
+
+impl Report {
+    /// Records the step, returning the value on success.
+    fn record<T>(
+        &mut self,
+        name: &str,
+        started: Instant,
+        res: io::Result<(T, String)>,
+    ) -> Option<T> {
+        let seconds = started.elapsed().as_secs_f64();
+        let (ret, ok, detail) = match res {
+            Ok((v, detail)) => (Some(v), true, detail),
+            Err(e) => (None, false, guidance(&e.to_string())),
+        };
+        self.steps.push(Step {
+            name: name.to_string(),
+            ok,
+            seconds,
+            detail,
+        });
+        ret
+    }
+}
+
+/// Prints the report of the steps, as JSON if "output" is "json".
+pub async fn execute(opts: Flags, output: &str) -> io::Result<()> {
+    let mut argv = vec![aws_eip::NAME.to_string()];
+    argv.extend(opts.args.iter().cloned());
+    let eip_opts = aws_eip::new()
+        .try_get_matches_from(argv)
+        .map(|matches| aws_eip::parse_flags(&matches))
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid \"aws eip\" flags {}", e),
+            )
+        })?;
+
+    let ctx = Context::default();
+    ctx.ratelimit.init(eip_opts.max_api_rps);
+    let sdk_opts = eip_opts.sdk_options(&ctx);
+    let shared_config = sdk::load_config(None, &sdk_opts).await?;
+    let ec2_manager = ec2::Manager::new(&sdk::for_service(&shared_config, "ec2", &sdk_opts)?);
+
+    let report = run(&ctx, &ec2_manager, &eip_opts, &opts.associate).await;
+    if output == "json" {
+        let d = serde_json::to_string_pretty(&report).map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed to serialize self-test report {}", e),
+            )
+        })?;
+        println!("{d}");
+    } else {
+        println!("{:<16} {:<6} {:>8}  DETAIL", "STEP", "OK", "SECONDS");
+        for s in report.steps.iter() {
+            println!(
+                "{:<16} {:<6} {:>8.2}  {}",
+                s.name, s.ok, s.seconds, s.detail
+            );
+        }
+    }
+
+    if !report.ok {
+        let failed: Vec<&str> = report
+            .steps
+            .iter()
+            .filter(|s| !s.ok)
+            .map(|s| s.name.as_str())
+            .collect();
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("self-test failed at {:?}", failed),
+        ));
+    }
+    Ok(())
+}
+
+/// Runs the steps, releasing the temporary EIP once allocated.
+pub async fn run(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    eip_opts: &aws_eip::Flags,
+    associate: &str,
+) -> Report {
+    let mut report = Report::default();
+
+    let started = Instant::now();
+    let res = imds::fetch_instance_id(
+        &eip_opts.imds(ctx),
+        ec2_manager,
+        &eip_opts.instance_id_fallback,
+    )
+    .await
+    .map(|id| (id.clone(), id));
+    let instance_id = match report.record("instance_id", started, res) {
+        Some(id) => id,
+        None => return report,
+    };
+    report.instance_id = instance_id.clone();
+
+    let started = Instant::now();
+    let id_tag_value = format!("{}-self-test", eip_opts.id_tag_value);
+    let tags = [
+        (eip_opts.id_tag_key.as_str(), id_tag_value.as_str()),
+        (
+            eip_opts.kind_tag_key.as_str(),
+            eip_opts.kind_tag_value.as_str(),
+        ),
+        (SELF_TEST_TAG_KEY, "true"),
+    ];
+    let res = eip::allocate(ec2_manager, &tags, &eip_opts.public_ipv4_pool)
+        .await
+        .map(|eip| (eip.clone(), eip.allocation_id));
+    let allocated = match report.record("allocate", started, res) {
+        Some(eip) => eip,
+        None => return report,
+    };
+    report.allocation_id = allocated.allocation_id.clone();
+    report.public_ip = allocated.public_ip.clone();
+
+    associate_and_disassociate(
+        ctx,
+        ec2_manager,
+        &mut report,
+        &allocated,
+        &instance_id,
+        associate,
+    )
+    .await;
+
+    let started = Instant::now();
+    let res = eip::release_address(ctx, ec2_manager, &allocated.allocation_id)
+        .await
+        .map(|_| ((), allocated.allocation_id.clone()));
+    report.record("release", started, res);
+
+    report.ok = report.steps.iter().all(|s| s.ok);
+    report
+}
+
+async fn associate_and_disassociate(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    report: &mut Report,
+    allocated: &ec2::Eip,
+    instance_id: &str,
+    associate: &str,
+) {
+    let started = Instant::now();
+    let has_public_ip = if associate == "dry-run" {
+        Ok(true)
+    } else {
+        eip::describe_instances(
+            ec2_manager,
+            vec![Filter::builder()
+                .name("instance-id")
+                .values(instance_id)
+                .build()],
+        )
+        .await
+        .map(|instances| instances.iter().any(|i| i.public_ip_address().is_some()))
+    };
+    let dry_run = match has_public_ip {
+        Ok(v) => v,
+        Err(e) => {
+            report.record::<()>("associate", started, Err(e));
+            return;
+        }
+    };
+    if dry_run {
+        // associating would replace the public IP of the instance
+        let res = eip::associate_dry_run(ec2_manager, &allocated.allocation_id, instance_id)
+            .await
+            .map(|_| ((), String::from("dry run")));
+        report.record("associate", started, res);
+        return;
+    }
+
+    let res = AwsEc2 { ec2_manager, ctx }
+        .associate(
+            &allocated.allocation_id,
+            instance_id,
+            &eip::Target::default(),
+            false,
+        )
+        .await
+        .map(|_| ((), instance_id.to_string()));
+    if report.record("associate", started, res).is_none() {
+        return;
+    }
+
+    let started = Instant::now();
+    let res = match eip::describe_by_allocation_id(ec2_manager, &allocated.allocation_id).await {
+        Ok(Some(addr)) if addr.instance_id() == Some(instance_id) => {
+            let association_id = addr.association_id().unwrap_or_default().to_string();
+            eip::disassociate(ctx, ec2_manager, &allocated.allocation_id, &association_id)
+                .await
+                .map(|_| ((), association_id))
+        }
+        Ok(_) => Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "{} is not associated with {instance_id} after the association",
+                allocated.allocation_id
+            ),
+        )),
+        Err(e) => Err(e),
+    };
+    report.record("disassociate", started, res);
+}
+
+/// Adds the guidance to the quota errors.
+fn guidance(err: &str) -> String {
+    if QUOTA_ERRORS.iter().any(|q| err.contains(q)) {
+        return format!(
+            "{err} (EIP quota reached: release the idle EIPs, see \"ip-manager cost\", or request a quota increase for \"EC2-VPC Elastic IPs\")"
+        );
+    }
+    err.to_string()
+}
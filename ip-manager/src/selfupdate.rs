@@ -0,0 +1,326 @@
+use std::{
+    env, fs,
+    io::{self, Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use aws_ip_provisioner::tls;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use hyper::{body, header, Body, Request, StatusCode};
+use ring::{digest, signature};
+use tokio::time::{timeout, Duration};
+
+pub const NAME: &str = "self-update";
+
+/// Ed25519 public key (base64) of the release signatures, set by the release build.
+const RELEASE_PUBLIC_KEY: Option<&str> = option_env!("IP_MANAGER_RELEASE_PUBLIC_KEY");
+
+/// Most redirects to follow (e.g., GitHub release assets redirect to the object storage).
+const MAX_REDIRECTS: usize = 5;
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Replaces the binary with the verified release of the channel")
+        .long_about(
+            "
+
+Downloads \"ip-manager.<target>\" of the release for the current platform
+(e.g., \"x86_64-unknown-linux-gnu\"), verifies its SHA-256 (\".sha256\") and its
+Ed25519 signature (\".sig\") with the release public key, and atomically replaces
+the binary (written next to it, then renamed over it), so that the long-lived
+instances update without re-baking the machine image. Restart the running
+daemons to pick up the new binary (e.g., \"systemctl restart ip-manager\").
+
+The \"stable\" channel is the latest published release, and \"latest\" the build
+of the last commit. A binary that already matches the release is not replaced.
+
+e.g.,
+
+$ sudo ip-manager self-update --channel=stable
+$ ip-manager self-update --channel=latest --dry-run
+
+",
+        )
+        .arg(
+            Arg::new("CHANNEL")
+                .long("channel")
+                .help("Sets the release channel")
+                .required(false)
+                .num_args(1)
+                .value_parser(["stable", "latest"])
+                .default_value("stable"),
+        )
+        .arg(
+            Arg::new("PUBLIC_KEY")
+                .long("public-key")
+                .help("Sets the Ed25519 public key (base64) of the release signatures (empty for the key built into the release)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("DOWNLOAD_URL")
+                .long("download-url")
+                .help("Sets the base URL of the release assets (\"<url>/<tag>/<asset>\", e.g., a mirror)")
+                .required(false)
+                .num_args(1)
+                .default_value("https://github.com/gyuho/ip-manager/releases/download"),
+        )
+        .arg(
+            Arg::new("API_URL")
+                .long("api-url")
+                .help("Sets the GitHub API URL of the repository, to resolve the \"stable\" release")
+                .required(false)
+                .num_args(1)
+                .default_value("https://api.github.com/repos/gyuho/ip-manager"),
+        )
+        .arg(
+            Arg::new("EXE_PATH")
+                .long("exe-path")
+                .help("Sets the binary to replace (empty for the running one)")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("DRY_RUN")
+                .long("dry-run")
+                .help("Downloads and verifies the release without replacing the binary")
+                .required(false)
+                .action(ArgAction::SetTrue),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub channel: String,
+    pub public_key: String,
+    pub download_url: String,
+    pub api_url: String,
+    pub exe_path: String,
+    pub dry_run: bool,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    let get = |id: &str| matches.get_one::<String>(id).cloned().unwrap_or_default();
+    Flags {
+        channel: get("CHANNEL"),
+        public_key: get("PUBLIC_KEY"),
+        download_url: get("DOWNLOAD_URL"),
+        api_url: get("API_URL"),
+        exe_path: get("EXE_PATH"),
+        dry_run: matches.get_flag("DRY_RUN"),
+    }
+}
+
+pub async fn execute(opts: Flags, output: &str) -> io::Result<()> {
+    let public_key = if !opts.public_key.is_empty() {
+        opts.public_key.clone()
+    } else {
+        RELEASE_PUBLIC_KEY.unwrap_or_default().to_string()
+    };
+    if public_key.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "no release public key built in -- set --public-key",
+        ));
+    }
+    let asset = asset_name(env::consts::OS, env::consts::ARCH).ok_or_else(|| {
+        Error::new(
+            ErrorKind::Unsupported,
+            format!("no release for {}/{}", env::consts::OS, env::consts::ARCH),
+        )
+    })?;
+    let exe_path = if opts.exe_path.is_empty() {
+        env::current_exe()?
+    } else {
+        PathBuf::from(&opts.exe_path)
+    };
+
+    let tag = if opts.channel == "latest" {
+        String::from("latest")
+    } else {
+        stable_tag(&opts.api_url).await?
+    };
+    let base = format!("{}/{tag}/{asset}", opts.download_url.trim_end_matches('/'));
+    log::info!("downloading {base} (channel {})", opts.channel);
+    let binary = download(&base).await?;
+    let sha256sum = download(&format!("{base}.sha256")).await?;
+    let sig = download(&format!("{base}.sig")).await?;
+    verify(
+        &binary,
+        &String::from_utf8_lossy(&sha256sum),
+        &String::from_utf8_lossy(&sig),
+        &public_key,
+    )?;
+    log::info!("verified {asset} of {tag} ({} bytes)", binary.len());
+
+    let current = fs::read(&exe_path).unwrap_or_default();
+    let updated = current != binary;
+    if !updated {
+        log::info!("{} is already the {tag} release", exe_path.display());
+    } else if opts.dry_run {
+        log::info!("dry run -- not replacing {}", exe_path.display());
+    } else {
+        replace(&exe_path, &binary)?;
+        log::info!(
+            "replaced {} with the {tag} release -- restart to run it",
+            exe_path.display()
+        );
+    }
+    if output == "json" {
+        println!(
+            "{}",
+            serde_json::json!({
+                "channel": opts.channel,
+                "tag": tag,
+                "asset": asset,
+                "sha256": hex(digest::digest(&digest::SHA256, &binary).as_ref()),
+                "updated": updated && !opts.dry_run,
+            })
+        );
+    }
+    Ok(())
+}
+
+/// Returns the release asset of the platform, as built by the release workflow.
+pub fn asset_name(os: &str, arch: &str) -> Option<String> {
+    let target = match (os, arch) {
+        ("linux", "x86_64") => "x86_64-unknown-linux-gnu",
+        ("linux", "aarch64") => "aarch64-unknown-linux-gnu",
+        ("macos", "x86_64") => "x86_64-apple-darwin",
+        ("macos", "aarch64") => "aarch64-apple-darwin",
+        _ => return None,
+    };
+    Some(format!("ip-manager.{target}"))
+}
+
+/// Checks the SHA-256 against the "sha256sum" output (e.g., "<hex>  ip-manager.<target>"),
+/// and the base64 Ed25519 signature of the binary with the base64 public key.
+pub fn verify(binary: &[u8], sha256sum: &str, sig: &str, public_key: &str) -> io::Result<()> {
+    let expected = sha256sum.split_whitespace().next().unwrap_or_default();
+    let got = hex(digest::digest(&digest::SHA256, binary).as_ref());
+    if !expected.eq_ignore_ascii_case(&got) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("SHA-256 mismatch (expected '{expected}', got '{got}')"),
+        ));
+    }
+    let decode = |what: &str, v: &str| {
+        STANDARD.decode(v.trim()).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid {what} base64 {}", e),
+            )
+        })
+    };
+    let public_key = decode("public key", public_key)?;
+    let sig = decode("signature", sig)?;
+    signature::UnparsedPublicKey::new(&signature::ED25519, &public_key)
+        .verify(binary, &sig)
+        .map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "release signature does not match the public key",
+            )
+        })
+}
+
+fn hex(d: &[u8]) -> String {
+    d.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Returns the tag of the latest published (not draft, not pre-release) release.
+/// ref. <https://docs.github.com/en/rest/releases/releases#get-the-latest-release>
+async fn stable_tag(api_url: &str) -> io::Result<String> {
+    let d = download(&format!(
+        "{}/releases/latest",
+        api_url.trim_end_matches('/')
+    ))
+    .await?;
+    let v: serde_json::Value = serde_json::from_slice(&d)?;
+    v["tag_name"]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "no tag_name in the latest release"))
+}
+
+/// GETs the URL following the redirects, expecting 200.
+async fn download(url: &str) -> io::Result<Vec<u8>> {
+    let client = hyper::Client::builder().build::<_, Body>(tls::https_connector());
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let req = Request::get(&url)
+            // required by the GitHub API
+            .header(
+                header::USER_AGENT,
+                format!("ip-manager/{}", env!("CARGO_PKG_VERSION")),
+            )
+            .body(Body::empty())
+            .map_err(|e| Error::new(ErrorKind::InvalidInput, format!("invalid URL {url} {}", e)))?;
+        let resp = timeout(Duration::from_secs(300), client.request(req))
+            .await
+            .map_err(|_| Error::new(ErrorKind::TimedOut, format!("GET {url} timed out")))?
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed GET {url} {}", e)))?;
+        let status = resp.status();
+        if status.is_redirection() {
+            url = resp
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, format!("{status} without location"))
+                })?
+                .to_string();
+            continue;
+        }
+        let d = body::to_bytes(resp.into_body())
+            .await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed to read {url} {}", e)))?;
+        if status != StatusCode::OK {
+            return Err(Error::new(
+                if status == StatusCode::NOT_FOUND {
+                    ErrorKind::NotFound
+                } else {
+                    ErrorKind::Other
+                },
+                format!("failed GET {url} {status}"),
+            ));
+        }
+        return Ok(d.to_vec());
+    }
+    Err(Error::new(
+        ErrorKind::Other,
+        format!("too many redirects from {url}"),
+    ))
+}
+
+/// Writes the binary next to the path (same file system) with the permissions
+/// of the path, and renames it over the path, so that it is never partially written.
+fn replace(path: &Path, binary: &[u8]) -> io::Result<()> {
+    let tmp = PathBuf::from(format!("{}.new", path.display()));
+    {
+        let mut f = fs::File::create(&tmp)?;
+        f.write_all(binary)?;
+        f.sync_all()?;
+    }
+    match fs::metadata(path) {
+        Ok(m) => fs::set_permissions(&tmp, m.permissions())?,
+        Err(_) => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&tmp, fs::Permissions::from_mode(0o755))?;
+            }
+        }
+    }
+    // the running binary cannot be overwritten on Windows, but can be renamed
+    #[cfg(windows)]
+    if path.exists() {
+        let old = PathBuf::from(format!("{}.old", path.display()));
+        let _ = fs::remove_file(&old);
+        fs::rename(path, &old)?;
+    }
+    fs::rename(&tmp, path)
+}
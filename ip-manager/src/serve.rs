@@ -0,0 +1,614 @@
+use std::{
+    convert::Infallible,
+    fs,
+    io::{self, Error, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    sync::Arc,
+};
+
+use aws_ip_provisioner::{
+    command as aws_eip,
+    context::Context,
+    ec2, eip, identity, lifecycle, metrics,
+    provisioner::{AwsEc2, BoxFuture, Metadata, Provisioner, SystemClock, SystemRng},
+    ratelimit, reachability, sdk,
+};
+use aws_sdk_ec2::model::{Filter, InstanceStateName};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use hyper::{
+    body,
+    header::CONTENT_LENGTH,
+    server::conn::AddrStream,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use tokio::sync::Mutex;
+
+use crate::buildinfo::BuildInfo;
+
+pub const NAME: &str = "serve";
+
+/// Path of the provisioning endpoint.
+pub const EIP_PATH: &str = "/v1/eip";
+/// Path of the build info of the server (e.g., to find the servers of an old version).
+pub const BUILDINFO_PATH: &str = "/buildinfo";
+/// Path of the reflector of "aws eip --verify-reachability" ("--reachability-reflector").
+pub const REACHABILITY_PATH: &str = "/reachability";
+
+/// Larger bodies are rejected before parsing (the document is ~500 bytes).
+const MAX_BODY_BYTES: usize = 16 * 1024;
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Serves the EIP requests of the instances, allocating and associating on their behalf")
+        .long_about(
+            "
+
+Runs the central EIP server, so that only the server's role needs the
+EC2 mutating permissions (e.g., \"ec2:AllocateAddress\"), not every
+instance role.
+
+The instance POSTs its instance identity document to \"/v1/eip\", signed
+as {\"document\": ..., \"pkcs7\": ...} with the \"rsa2048\" signature, which
+must verify with \"--identity-certificate\" (the AWS RSA-2048 public
+certificate of the region). The server refuses to start without the
+certificate, unless \"--insecure-accept-unsigned-documents\" is set, which
+takes any document as is (e.g., for tests): anyone who can reach the server
+then provisions for any instance whose source address it can spoof.
+The server then accepts it only if the document's region is the server's region,
+the instance exists in the server's account and is running, and the
+instance's private IP is both the document's \"privateIp\" and the source
+address of the connection (so the server must be reached directly, not
+through a NAT or a load balancer). It then provisions the EIP exactly as
+\"aws eip\" would, with the \"Id\" tag value and the state file of the
+instance (\"<state-dir>/<instance-id>.yaml\"), and returns the EIP as JSON.
+
+Takes the same flags as \"aws eip\" (credentials, endpoints, tags, pool),
+replacing \"--id-tag-value\" and \"--mounted-eip-file-path\" per instance.
+The requests are provisioned one at a time.
+
+With \"--reachability-reflector\", also answers \"POST /reachability?protocol=tcp&port=<port>\"
+by connecting to the port from the server, as the reflector of \"aws eip --verify-reachability\"
+(\"--reachability-service-url=http://<server>:9540/reachability\"). The body is the identity
+document, admitted exactly as for \"/v1/eip\", and the server only probes the public IP of
+the admitted instance (as DescribeInstances reports it, \"host\" must be the same if set),
+never a private, loopback, link-local, or metadata address. The probe goes to the public IP
+through the internet gateway, crossing the security groups and the network ACLs of the EIP
+as the clients would.
+
+e.g.,
+
+$ ip-manager serve \
+--listen-address=0.0.0.0:9540 \
+--state-dir=/var/lib/ip-manager/serve \
+--identity-certificate=/etc/ip-manager/aws-rsa2048-us-west-2.pem \
+-- \
+--id-tag-key=Id \
+--id-tag-value=unused \
+--kind-tag-key=Kind \
+--kind-tag-value=aws-ip-provisioner \
+--mounted-eip-file-path=unused
+
+# on the instance
+$ TOKEN=$(curl -s -X PUT http://169.254.169.254/latest/api/token -H 'X-aws-ec2-metadata-token-ttl-seconds: 60')
+$ IMDS=http://169.254.169.254/latest/dynamic/instance-identity
+$ jq -n \
+--arg document \"$(curl -s $IMDS/document -H \"X-aws-ec2-metadata-token: $TOKEN\")\" \
+--arg pkcs7 \"$(curl -s $IMDS/rsa2048 -H \"X-aws-ec2-metadata-token: $TOKEN\")\" \
+'{document: $document, pkcs7: $pkcs7}' \
+| curl -s -X POST --data-binary @- http://10.0.0.10:9540/v1/eip
+
+",
+        )
+        .arg(
+            Arg::new("LISTEN_ADDRESS")
+                .long("listen-address")
+                .help("Sets the address to listen on for the EIP requests")
+                .required(false)
+                .num_args(1)
+                .default_value("0.0.0.0:9540"),
+        )
+        .arg(
+            Arg::new("STATE_DIR")
+                .long("state-dir")
+                .help("Sets the directory of the state files of the instances")
+                .required(false)
+                .num_args(1)
+                .default_value("/var/lib/ip-manager/serve"),
+        )
+        .arg(
+            Arg::new("IDENTITY_CERTIFICATE")
+                .long("identity-certificate")
+                .help("Sets the AWS RSA-2048 public certificate (PEM file) of the region to verify the signed identity documents with (required unless \"--insecure-accept-unsigned-documents\")")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("INSECURE_ACCEPT_UNSIGNED_DOCUMENTS")
+                .long("insecure-accept-unsigned-documents")
+                .help("Accepts the unsigned identity documents without \"--identity-certificate\" (INSECURE: anyone reaching the server can provision for the instances whose source address it can spoof)")
+                .required(false)
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("REACHABILITY_REFLECTOR")
+                .long("reachability-reflector")
+                .help("Serves \"/reachability\", probing the TCP ports of the admitted instances' public IPs for \"aws eip --verify-reachability\"")
+                .required(false)
+                .num_args(0)
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ARGS")
+                .help("Sets the flags of \"aws eip\" to provision with")
+                .required(false)
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub listen_address: String,
+    pub state_dir: String,
+    pub identity_certificate: String,
+    pub insecure_accept_unsigned_documents: bool,
+    pub reachability_reflector: bool,
+    pub args: Vec<String>,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        listen_address: matches
+            .get_one::<String>("LISTEN_ADDRESS")
+            .unwrap_or(&String::from("0.0.0.0:9540"))
+            .clone(),
+        state_dir: matches
+            .get_one::<String>("STATE_DIR")
+            .unwrap_or(&String::from("/var/lib/ip-manager/serve"))
+            .clone(),
+        identity_certificate: matches
+            .get_one::<String>("IDENTITY_CERTIFICATE")
+            .unwrap_or(&String::new())
+            .clone(),
+        insecure_accept_unsigned_documents: matches.get_flag("INSECURE_ACCEPT_UNSIGNED_DOCUMENTS"),
+        reachability_reflector: matches.get_flag("REACHABILITY_REFLECTOR"),
+        args: matches
+            .get_many::<String>("ARGS")
+            .map(|args| args.cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+struct State {
+    /// Shared by all the requests, each with its own run state otherwise
+    /// (see "Context::shared").
+    metrics: Arc<metrics::Registry>,
+    ratelimit: Arc<ratelimit::Limiter>,
+    eip_opts: aws_eip::Flags,
+    ec2_manager: ec2::Manager,
+    region: String,
+    state_dir: String,
+    /// AWS public certificate (PEM) to verify the PKCS7 signatures with,
+    /// empty only with "--insecure-accept-unsigned-documents".
+    certificate: String,
+    reachability_reflector: bool,
+    /// Serializes the provisioning, so that the pool claims and the
+    /// allocations of the concurrent requests do not race.
+    lock: Mutex<()>,
+}
+
+/// Serves the EIP requests until the process is killed.
+pub async fn execute(opts: Flags) -> io::Result<()> {
+    let mut argv = vec![aws_eip::NAME.to_string()];
+    argv.extend(opts.args.iter().cloned());
+    let eip_opts = aws_eip::new()
+        .try_get_matches_from(argv)
+        .map(|matches| aws_eip::parse_flags(&matches))
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid \"aws eip\" flags {}", e),
+            )
+        })?;
+    let addr: SocketAddr = opts.listen_address.parse().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid listen address '{}' ({})", opts.listen_address, e),
+        )
+    })?;
+    fs::create_dir_all(&opts.state_dir)?;
+    let certificate = if !opts.identity_certificate.is_empty() {
+        fs::read_to_string(&opts.identity_certificate)?
+    } else if opts.insecure_accept_unsigned_documents {
+        log::warn!("INSECURE: accepting unsigned instance identity documents without \"--identity-certificate\"");
+        String::new()
+    } else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "\"--identity-certificate\" is required to verify the instance identity documents (or \"--insecure-accept-unsigned-documents\")",
+        ));
+    };
+
+    let ctx = Context::default();
+    ctx.ratelimit.init(eip_opts.max_api_rps);
+    let sdk_opts = eip_opts.sdk_options(&ctx);
+    let shared_config = sdk::load_config(None, &sdk_opts).await?;
+    let ec2_manager = ec2::Manager::new(&sdk::for_service(&shared_config, "ec2", &sdk_opts)?);
+    let state = Arc::new(State {
+        metrics: ctx.metrics.clone(),
+        ratelimit: ctx.ratelimit.clone(),
+        eip_opts,
+        ec2_manager,
+        region: shared_config
+            .region()
+            .map(|r| r.to_string())
+            .unwrap_or_default(),
+        state_dir: opts.state_dir.clone(),
+        certificate,
+        reachability_reflector: opts.reachability_reflector,
+        lock: Mutex::new(()),
+    });
+
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        let (state, remote_addr) = (state.clone(), conn.remote_addr());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                handle(state.clone(), remote_addr, req)
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::AddrInUse,
+                format!("failed to listen on {addr} ({})", e),
+            )
+        })?
+        .serve(make_svc);
+    log::info!("serving EIP requests on {addr}{EIP_PATH}");
+    server
+        .await
+        .map_err(|e| Error::new(ErrorKind::Other, format!("server failed {}", e)))
+}
+
+async fn handle(
+    state: Arc<State>,
+    remote_addr: SocketAddr,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if req.uri().path() == BUILDINFO_PATH && req.method() == Method::GET {
+        let b = serde_json::to_vec(&BuildInfo::current()).unwrap_or_default();
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/json")
+            .body(Body::from(b))
+            .unwrap());
+    }
+    if req.uri().path() == REACHABILITY_PATH && state.reachability_reflector {
+        if req.method() != Method::POST {
+            return Ok(respond(StatusCode::METHOD_NOT_ALLOWED, "POST only"));
+        }
+        let (host, port) = match reachability_query(req.uri().query().unwrap_or_default()) {
+            Ok(v) => v,
+            Err(e) => return Ok(respond(StatusCode::BAD_REQUEST, &e.to_string())),
+        };
+        let metadata = match admit(&state, remote_addr, req).await {
+            Ok(v) => v,
+            Err(resp) => return Ok(resp),
+        };
+        let public_ip = match probe_target(&metadata, &host) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!(
+                    "denied reachability probe of {} '{}'",
+                    metadata.instance_id,
+                    e
+                );
+                return Ok(respond(StatusCode::FORBIDDEN, &e.to_string()));
+            }
+        };
+        let verdict = reachability::probe(&public_ip.to_string(), port).await;
+        log::info!(
+            "probed tcp://{public_ip}:{port} for {} (reachable {})",
+            metadata.instance_id,
+            verdict.reachable
+        );
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&verdict).unwrap_or_default()))
+            .unwrap());
+    }
+    if req.uri().path() != EIP_PATH {
+        return Ok(respond(StatusCode::NOT_FOUND, "not found"));
+    }
+    if req.method() != Method::POST {
+        return Ok(respond(StatusCode::METHOD_NOT_ALLOWED, "POST only"));
+    }
+    let metadata = match admit(&state, remote_addr, req).await {
+        Ok(v) => v,
+        Err(resp) => return Ok(resp),
+    };
+    let ret = provision(&state, &metadata).await;
+    match ret.and_then(|eip| serde_json::to_vec(&eip).map_err(Error::from)) {
+        Ok(b) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/json")
+            .body(Body::from(b))
+            .unwrap()),
+        Err(e) => {
+            log::warn!(
+                "failed to provision EIP for {} '{}'",
+                metadata.instance_id,
+                e
+            );
+            Ok(respond(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))
+        }
+    }
+}
+
+/// Reads the identity document of the request body and validates it,
+/// the admission of both "/v1/eip" and "/reachability".
+async fn admit(
+    state: &State,
+    remote_addr: SocketAddr,
+    req: Request<Body>,
+) -> Result<RemoteMetadata, Response<Body>> {
+    let path = req.uri().path().to_string();
+    let too_large = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())
+        .map(|n| n > MAX_BODY_BYTES)
+        .unwrap_or(false);
+    if too_large {
+        return Err(respond(StatusCode::PAYLOAD_TOO_LARGE, "body too large"));
+    }
+    let bytes = match body::to_bytes(req.into_body()).await {
+        Ok(b) if b.len() <= MAX_BODY_BYTES => b,
+        Ok(_) => return Err(respond(StatusCode::PAYLOAD_TOO_LARGE, "body too large")),
+        Err(e) => return Err(respond(StatusCode::BAD_REQUEST, &e.to_string())),
+    };
+    let doc = match read_document(state, &bytes) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            log::warn!("denied {path} request from {remote_addr} '{}'", e);
+            return Err(respond(StatusCode::FORBIDDEN, &e.to_string()));
+        }
+        Err(e) => return Err(respond(StatusCode::BAD_REQUEST, &e.to_string())),
+    };
+    log::info!(
+        "{path} request of {} in {} from {remote_addr}",
+        doc.instance_id,
+        doc.account_id
+    );
+
+    match validate(state, &doc, remote_addr).await {
+        Ok(metadata) => Ok(metadata),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            log::warn!("denied {path} request of {} '{}'", doc.instance_id, e);
+            Err(respond(StatusCode::FORBIDDEN, &e.to_string()))
+        }
+        Err(e) => {
+            log::warn!("failed to validate {} '{}'", doc.instance_id, e);
+            Err(respond(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()))
+        }
+    }
+}
+
+/// Returns the public IP of the admitted instance to probe, so that the
+/// reflector is never pointed at the other hosts (e.g., to scan them).
+fn probe_target(metadata: &RemoteMetadata, host: &str) -> io::Result<IpAddr> {
+    let deny = |reason: String| Err(Error::new(ErrorKind::PermissionDenied, reason));
+    let public_ip = match metadata.public_ip.as_deref() {
+        Some(v) if !v.is_empty() => v,
+        _ => {
+            return deny(format!(
+                "instance {} has no public IP",
+                metadata.instance_id
+            ))
+        }
+    };
+    if !host.is_empty() && host != public_ip {
+        return deny(format!(
+            "host {host} is not the public IP {public_ip} of instance {}",
+            metadata.instance_id
+        ));
+    }
+    let ip: IpAddr = public_ip.parse().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid public IP '{public_ip}' ({e})"),
+        )
+    })?;
+    if !reachability::is_public(ip) {
+        return deny(format!("{ip} is not a public address"));
+    }
+    Ok(ip)
+}
+
+/// Reads the signed document ("identity::Signed" as JSON), verifying it with
+/// "--identity-certificate". Only "--insecure-accept-unsigned-documents"
+/// (no certificate) takes the documents as is, signed or not.
+fn read_document(state: &State, bytes: &[u8]) -> io::Result<identity::Document> {
+    let signed: Option<identity::Signed> = serde_json::from_slice::<serde_json::Value>(bytes)
+        .ok()
+        .filter(|v| v.get("pkcs7").is_some())
+        .and_then(|v| serde_json::from_value(v).ok());
+    match signed {
+        Some(signed) if !state.certificate.is_empty() => {
+            identity::verify(&signed, &state.certificate)
+        }
+        None if !state.certificate.is_empty() => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "unsigned instance identity document (\"pkcs7\" required)",
+        )),
+        // "--insecure-accept-unsigned-documents"
+        Some(signed) => identity::parse(&signed.document),
+        None => identity::parse(&String::from_utf8_lossy(bytes)),
+    }
+}
+
+/// Checks the document against the instance in the server's account, and
+/// the connection's source address against the instance's private IP.
+async fn validate(
+    state: &State,
+    doc: &identity::Document,
+    remote_addr: SocketAddr,
+) -> io::Result<RemoteMetadata> {
+    let deny = |reason: String| Err(Error::new(ErrorKind::PermissionDenied, reason));
+    if doc.region != state.region {
+        return deny(format!(
+            "region {} is not the server's region {}",
+            doc.region, state.region
+        ));
+    }
+    let instances = eip::describe_instances(
+        &state.ec2_manager,
+        vec![Filter::builder()
+            .name("instance-id")
+            .values(&doc.instance_id)
+            .build()],
+    )
+    .await?;
+    let instance = match instances.first() {
+        Some(v) => v,
+        None => {
+            return deny(format!(
+                "instance {} not found in the server's account",
+                doc.instance_id
+            ))
+        }
+    };
+    let running = instance
+        .state()
+        .and_then(|s| s.name())
+        .map(|n| *n == InstanceStateName::Running)
+        .unwrap_or(false);
+    if !running {
+        return deny(format!("instance {} is not running", doc.instance_id));
+    }
+    let private_ip = instance.private_ip_address().unwrap_or_default();
+    if private_ip.is_empty() || private_ip != doc.private_ip {
+        return deny(format!(
+            "private IP {} of the document is not the instance's {private_ip}",
+            doc.private_ip
+        ));
+    }
+    if remote_addr.ip().to_string() != private_ip {
+        return deny(format!(
+            "request from {} is not from the instance's private IP {private_ip}",
+            remote_addr.ip()
+        ));
+    }
+
+    Ok(RemoteMetadata {
+        ec2_manager: state.ec2_manager.clone(),
+        instance_id: doc.instance_id.clone(),
+        private_ip: private_ip.to_string(),
+        public_ip: instance.public_ip_address().map(|s| s.to_string()),
+        account_id: doc.account_id.clone(),
+        region: doc.region.clone(),
+    })
+}
+
+/// Provisions the EIP as "aws eip" would, with the instance's "Id" tag and state file.
+async fn provision(state: &State, metadata: &RemoteMetadata) -> io::Result<ec2::Eip> {
+    let _guard = state.lock.lock().await;
+    let mut opts = state.eip_opts.clone();
+    opts.id_tag_value = metadata.instance_id.clone();
+    opts.mounted_eip_file_path = Path::new(&state.state_dir)
+        .join(format!("{}.yaml", metadata.instance_id))
+        .display()
+        .to_string();
+    let ctx = Context::shared(state.metrics.clone(), state.ratelimit.clone());
+    let ec2 = AwsEc2 {
+        ec2_manager: &state.ec2_manager,
+        ctx: &ctx,
+    };
+    let provisioner = Provisioner::new(&ctx, &opts, &ec2, metadata, &SystemClock, &SystemRng);
+    let eip = provisioner.provision(&metadata.instance_id).await?;
+    log::info!(
+        "provisioned EIP {} ({}) for {}",
+        eip.public_ip,
+        eip.allocation_id,
+        metadata.instance_id
+    );
+    Ok(eip)
+}
+
+/// Metadata of the requesting instance, from the validated document
+/// and DescribeInstances rather than the local IMDS.
+struct RemoteMetadata {
+    ec2_manager: ec2::Manager,
+    instance_id: String,
+    private_ip: String,
+    public_ip: Option<String>,
+    account_id: String,
+    region: String,
+}
+
+impl Metadata for RemoteMetadata {
+    fn instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move { Ok(self.instance_id.clone()) })
+    }
+
+    fn public_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async move { Ok(self.public_ip.clone()) })
+    }
+
+    fn local_ipv4(&self) -> BoxFuture<'_, Option<String>> {
+        Box::pin(async move { Ok(Some(self.private_ip.clone())) })
+    }
+
+    fn asg_members<'a>(&'a self, instance_id: &'a str) -> BoxFuture<'a, Vec<String>> {
+        Box::pin(lifecycle::asg_members(&self.ec2_manager, instance_id))
+    }
+
+    fn account_and_region(&self) -> BoxFuture<'_, (String, String)> {
+        Box::pin(async move { Ok((self.account_id.clone(), self.region.clone())) })
+    }
+}
+
+/// Parses "protocol=tcp&host=<ip>&port=<port>" of the reflector request,
+/// where the host is optional (the instance's public IP).
+fn reachability_query(query: &str) -> io::Result<(String, u16)> {
+    let (mut protocol, mut host, mut port) = ("tcp", "", "");
+    for kv in query.split('&') {
+        match kv.split_once('=') {
+            Some(("protocol", v)) => protocol = v,
+            Some(("host", v)) => host = v,
+            Some(("port", v)) => port = v,
+            _ => {}
+        }
+    }
+    if protocol != "tcp" {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("unsupported protocol '{protocol}'"),
+        ));
+    }
+    let port = port.parse::<u16>().map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid port '{port}' ({e})"),
+        )
+    })?;
+    Ok((host.to_string(), port))
+}
+
+fn respond(status: StatusCode, error: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::json!({ "error": error }).to_string(),
+        ))
+        .unwrap()
+}
@@ -0,0 +1,435 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Error, ErrorKind, Read},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_ip_provisioner::{context::Context, ec2, eip, imds::Imds, platform, sdk, state::State};
+use aws_manager::sts;
+use aws_sdk_ec2::model::Address;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+pub const NAME: &str = "state";
+
+/// Version of the bundle format, bumped on incompatible changes.
+pub const BUNDLE_VERSION: u32 = 1;
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Exports or imports the mounted EIP file to move the EIP to a replacement instance")
+        .subcommand_required(true)
+        .subcommand(
+            with_state_args(
+                Command::new("export")
+                    .about("Bundles the mounted EIP file with its verified EIP into a portable file")
+                    .long_about(
+                        "
+
+Bundles the mounted EIP file with the facts of its EIP verified against EC2
+(account, region, tags, and the associated instance), so that the replacement
+instance (e.g., a new volume, or a new availability zone) takes over the EIP
+on purpose rather than relying on the mounted file surviving.
+Requires \"ec2:DescribeAddresses\".
+
+e.g.,
+
+$ ip-manager state export --bundle-file=/tmp/node-1.eip.json
+
+",
+                    )
+                    .arg(
+                        Arg::new("BUNDLE_FILE")
+                            .long("bundle-file")
+                            .help("Sets the file to write the bundle to (empty for stdout)")
+                            .required(false)
+                            .num_args(1)
+                            .default_value(""),
+                    ),
+            ),
+        )
+        .subcommand(
+            with_state_args(
+                Command::new("import")
+                    .about("Verifies the bundle against EC2 and writes its mounted EIP file")
+                    .long_about(
+                        "
+
+Verifies the bundle of \"state export\" (checksum, then the EIP against EC2:
+same account and region, same public IP, same \"Id\" tag value), and writes its
+mounted EIP file, so that the next \"aws eip\" run reuses the EIP.
+
+The import fails if the EIP is associated with an instance other than the
+exporting one, or if the mounted EIP file already holds another EIP, unless
+\"--force\". With \"--force\", the EIP is also disassociated from the exporting
+instance, so that the next run takes it over without \"--no-steal=false\".
+Requires \"ec2:DescribeAddresses\", and \"ec2:DisassociateAddress\" with \"--force\".
+
+e.g.,
+
+$ ip-manager state import --bundle-file=/tmp/node-1.eip.json
+$ ip-manager aws eip ...
+
+",
+                    )
+                    .arg(
+                        Arg::new("BUNDLE_FILE")
+                            .long("bundle-file")
+                            .help("Sets the bundle file to import (empty for stdin)")
+                            .required(false)
+                            .num_args(1)
+                            .default_value(""),
+                    )
+                    .arg(
+                        Arg::new("FORCE")
+                            .long("force")
+                            .help("Takes over the EIP from the exporting instance, and overwrites the mounted EIP file of another EIP")
+                            .required(false)
+                            .num_args(0)
+                            .action(ArgAction::SetTrue),
+                    ),
+            ),
+        )
+}
+
+fn with_state_args(cmd: Command) -> Command {
+    cmd.arg(
+        Arg::new("MOUNTED_EIP_FILE_PATH")
+            .long("mounted-eip-file-path")
+            .help("Sets the mounted EIP file of \"aws eip\"")
+            .required(false)
+            .num_args(1)
+            .default_value(platform::DEFAULT_MOUNTED_EIP_FILE_PATH),
+    )
+    .arg(
+        Arg::new("ID_TAG_KEY")
+            .long("id-tag-key")
+            .help("Sets the key of the EIP tag that identifies the node")
+            .required(false)
+            .num_args(1)
+            .default_value("Id"),
+    )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub mounted_eip_file_path: String,
+    pub id_tag_key: String,
+    pub bundle_file: String,
+
+    /// "import" only.
+    pub force: bool,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    let get = |id: &str| matches.get_one::<String>(id).cloned().unwrap_or_default();
+    Flags {
+        mounted_eip_file_path: get("MOUNTED_EIP_FILE_PATH"),
+        id_tag_key: get("ID_TAG_KEY"),
+        bundle_file: get("BUNDLE_FILE"),
+        force: matches
+            .try_get_one::<bool>("FORCE")
+            .ok()
+            .flatten()
+            .copied()
+            .unwrap_or_default(),
+    }
+}
+
+/// Mounted EIP file with its EIP as verified at export.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bundle {
+    pub version: u32,
+    pub exported_at: u64,
+    pub account_id: String,
+    pub region: String,
+    pub allocation_id: String,
+    pub public_ip: String,
+    /// "Id" tag value.
+    pub id: String,
+    pub tags: BTreeMap<String, String>,
+    /// Instance associated with the EIP at export, empty if none.
+    pub instance_id: String,
+    /// Content of the mounted EIP file.
+    pub state: String,
+    /// SHA-256 (hex) of "state".
+    pub state_sha256: String,
+}
+
+impl Bundle {
+    /// Checks the version and the checksum, before any AWS call.
+    pub fn verify(&self) -> io::Result<()> {
+        if self.version != BUNDLE_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "unsupported bundle version {} (expected {BUNDLE_VERSION})",
+                    self.version
+                ),
+            ));
+        }
+        if sha256(&self.state) != self.state_sha256 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "bundle state does not match its checksum (edited or truncated?)",
+            ));
+        }
+        let eip: State = serde_yaml::from_str(&self.state).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid bundle state '{}'", e),
+            )
+        })?;
+        if eip.allocation_id != self.allocation_id || eip.public_ip != self.public_ip {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "bundle state is not of the bundle's EIP",
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn sha256(s: &str) -> String {
+    digest::digest(&digest::SHA256, s.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn tags(addr: &Address) -> BTreeMap<String, String> {
+    addr.tags()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|t| Some((t.key()?.to_string(), t.value()?.to_string())))
+        .collect()
+}
+
+/// Returns the caller's account, the region, and the EC2 manager.
+async fn connect() -> io::Result<(String, String, ec2::Manager)> {
+    let sdk_opts = sdk::Options::default();
+    let shared_config = sdk::load_config(None, &sdk_opts).await?;
+    let region = shared_config
+        .region()
+        .map(|r| r.to_string())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no region in the AWS config"))?;
+    let identity = sts::Manager::new(&shared_config)
+        .get_identity()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed sts.get_identity {}", e.message()),
+            )
+        })?;
+    let ec2_manager = ec2::Manager::new(&sdk::for_service(&shared_config, "ec2", &sdk_opts)?);
+    Ok((identity.account_id, region, ec2_manager))
+}
+
+/// Describes the EIP, failing if it is gone or no longer the public IP.
+async fn describe(
+    ec2_manager: &ec2::Manager,
+    allocation_id: &str,
+    public_ip: &str,
+) -> io::Result<Address> {
+    let addr = eip::describe_by_allocation_id(ec2_manager, allocation_id)
+        .await?
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("EIP {allocation_id} no longer exists (released?)"),
+            )
+        })?;
+    if addr.public_ip() != Some(public_ip) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "EIP {allocation_id} is {:?}, not {public_ip}",
+                addr.public_ip()
+            ),
+        ));
+    }
+    Ok(addr)
+}
+
+/// Writes the bundle of the mounted EIP file.
+pub async fn export(opts: Flags) -> io::Result<()> {
+    let state = fs::read_to_string(&opts.mounted_eip_file_path).map_err(|e| {
+        Error::new(
+            e.kind(),
+            format!(
+                "failed to read mounted EIP file {} '{}'",
+                opts.mounted_eip_file_path, e
+            ),
+        )
+    })?;
+    let eip: State = serde_yaml::from_str(&state).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "invalid mounted EIP file {} '{}'",
+                opts.mounted_eip_file_path, e
+            ),
+        )
+    })?;
+
+    let (account_id, region, ec2_manager) = connect().await?;
+    let addr = describe(&ec2_manager, &eip.allocation_id, &eip.public_ip).await?;
+    let tags = tags(&addr);
+    let bundle = Bundle {
+        version: BUNDLE_VERSION,
+        exported_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        account_id,
+        region,
+        allocation_id: eip.allocation_id.clone(),
+        public_ip: eip.public_ip.clone(),
+        id: tags.get(&opts.id_tag_key).cloned().unwrap_or_default(),
+        tags,
+        instance_id: addr.instance_id().unwrap_or_default().to_string(),
+        state_sha256: sha256(&state),
+        state,
+    };
+    let d = serde_json::to_string_pretty(&bundle).map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to serialize bundle {}", e),
+        )
+    })?;
+    if opts.bundle_file.is_empty() {
+        println!("{d}");
+    } else {
+        fs::write(&opts.bundle_file, format!("{d}\n"))?;
+    }
+    log::info!(
+        "exported EIP {} ({}) of '{}'",
+        bundle.public_ip,
+        bundle.allocation_id,
+        bundle.id
+    );
+    Ok(())
+}
+
+/// Verifies the bundle and writes its mounted EIP file.
+pub async fn import(opts: Flags, output: &str) -> io::Result<()> {
+    let d = if opts.bundle_file.is_empty() {
+        let mut d = String::new();
+        io::stdin().read_to_string(&mut d)?;
+        d
+    } else {
+        fs::read_to_string(&opts.bundle_file)?
+    };
+    let bundle: Bundle = serde_json::from_str(&d)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("invalid bundle '{}'", e)))?;
+    bundle.verify()?;
+
+    // before any AWS call, not to take over the EIP for a file that cannot be written
+    if Path::new(&opts.mounted_eip_file_path).exists() && !opts.force {
+        let existing = State::load(&opts.mounted_eip_file_path)?;
+        if existing.allocation_id != bundle.allocation_id {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "mounted EIP file {} holds another EIP {} -- set --force to overwrite",
+                    opts.mounted_eip_file_path, existing.allocation_id
+                ),
+            ));
+        }
+    }
+
+    let (account_id, region, ec2_manager) = connect().await?;
+    if account_id != bundle.account_id || region != bundle.region {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "bundle is of {}/{}, not {account_id}/{region} -- an EIP cannot be used in another region, and moves to another account with \"ip-manager transfer\"",
+                bundle.account_id, bundle.region
+            ),
+        ));
+    }
+    let addr = describe(&ec2_manager, &bundle.allocation_id, &bundle.public_ip).await?;
+    let id = tags(&addr)
+        .get(&opts.id_tag_key)
+        .cloned()
+        .unwrap_or_default();
+    if id != bundle.id {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "EIP {} is now '{id}', not '{}' as exported",
+                bundle.allocation_id, bundle.id
+            ),
+        ));
+    }
+
+    // the local instance, if any (e.g., not when staged from elsewhere)
+    let local_instance_id = Imds::new(false, 0)
+        .fetch("instance-id")
+        .await
+        .map(|v| v.trim().to_string())
+        .unwrap_or_default();
+    let associated = addr.instance_id().unwrap_or_default();
+    let mut disassociated = false;
+    if !associated.is_empty() && associated != local_instance_id {
+        if associated != bundle.instance_id && !opts.force {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "EIP {} is associated with {associated}, not the exporting instance '{}' -- set --force to take it over",
+                    bundle.allocation_id, bundle.instance_id
+                ),
+            ));
+        }
+        if opts.force {
+            eip::disassociate(
+                &Context::default(),
+                &ec2_manager,
+                &bundle.allocation_id,
+                addr.association_id().unwrap_or_default(),
+            )
+            .await?;
+            disassociated = true;
+        } else {
+            log::warn!(
+                "EIP {} is still associated with the exporting instance {associated} -- stop it first, or set --force to take it over",
+                bundle.allocation_id
+            );
+        }
+    }
+
+    if let Some(parent_dir) = Path::new(&opts.mounted_eip_file_path).parent() {
+        if !parent_dir.as_os_str().is_empty() {
+            fs::create_dir_all(parent_dir)?;
+        }
+    }
+    // renamed, so that a concurrent "aws eip" run never reads a partial file
+    let tmp = format!("{}.tmp", opts.mounted_eip_file_path);
+    fs::write(&tmp, &bundle.state)?;
+    fs::rename(&tmp, &opts.mounted_eip_file_path)?;
+    log::info!(
+        "imported EIP {} ({}) of '{}' into {}",
+        bundle.public_ip,
+        bundle.allocation_id,
+        bundle.id,
+        opts.mounted_eip_file_path
+    );
+    if output == "json" {
+        println!(
+            "{}",
+            serde_json::json!({
+                "allocation_id": bundle.allocation_id,
+                "public_ip": bundle.public_ip,
+                "id": bundle.id,
+                "mounted_eip_file_path": opts.mounted_eip_file_path,
+                "disassociated": disassociated,
+            })
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,364 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Error, ErrorKind, Read},
+};
+
+use aws_ip_provisioner::{context::Context, ec2, eip, sdk, transfer};
+use aws_manager::sts;
+use aws_types::SdkConfig;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use serde::{Deserialize, Serialize};
+
+pub const NAME: &str = "transfer";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Transfers an EIP to another AWS account, with its tags and the mounted EIP file")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("initiate")
+                .about("Offers the EIP to the other account, and writes the transfer file with its tags")
+                .long_about(
+                    "
+
+Offers the EIP to \"--transfer-account-id\" (EnableAddressTransfer), which must
+accept it within seven days with \"transfer accept\". Since the tags do not
+transfer, they are written to the transfer file (with the public IP and the
+offer expiration) to carry them over on acceptance. The EIP must not be
+associated, or set \"--disassociate\". Requires \"ec2:DescribeAddresses\",
+\"ec2:EnableAddressTransfer\", and \"ec2:DisassociateAddress\" with \"--disassociate\".
+
+e.g.,
+
+$ ip-manager transfer initiate \
+--allocation-id=eipalloc-0123456789abcdef0 \
+--transfer-account-id=210987654321 \
+--transfer-file=/tmp/node-1.transfer.json
+
+",
+                )
+                .arg(
+                    Arg::new("ALLOCATION_ID")
+                        .long("allocation-id")
+                        .help("Sets the allocation ID of the EIP to transfer")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("TRANSFER_ACCOUNT_ID")
+                        .long("transfer-account-id")
+                        .help("Sets the AWS account ID to transfer the EIP to")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("DISASSOCIATE")
+                        .long("disassociate")
+                        .help("Disassociates the EIP first if associated")
+                        .required(false)
+                        .num_args(0)
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("TRANSFER_FILE")
+                        .long("transfer-file")
+                        .help("Sets the file to write the transfer (public IP and tags) to (empty for stdout)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("AUDIT_LOG_FILE")
+                        .long("audit-log-file")
+                        .help("Sets the file to append the audit log of the transfer to (empty to disable)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                ),
+        )
+        .subcommand(
+            Command::new("accept")
+                .about("Accepts the EIP offered to this account, re-applying its tags")
+                .long_about(
+                    "
+
+Accepts the EIP of the transfer file of \"transfer initiate\"
+(AcceptAddressTransfer) with the credentials of the receiving account,
+re-applying the tags of the source account (but the reserved \"aws:\" ones).
+With \"--mounted-eip-file-path\", points the mounted EIP file to the new
+allocation ID (keeping the values recorded next to it), so that the next
+\"aws eip\" run in the receiving account reuses the EIP.
+Requires \"ec2:AcceptAddressTransfer\" and \"ec2:CreateTags\".
+
+e.g.,
+
+$ ip-manager transfer accept \
+--transfer-file=/tmp/node-1.transfer.json \
+--mounted-eip-file-path=/data/eip.yaml
+
+",
+                )
+                .arg(
+                    Arg::new("TRANSFER_FILE")
+                        .long("transfer-file")
+                        .help("Sets the transfer file of \"transfer initiate\" (empty for stdin)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("MOUNTED_EIP_FILE_PATH")
+                        .long("mounted-eip-file-path")
+                        .help("Sets the mounted EIP file to point to the accepted EIP (empty to leave the files as is)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                )
+                .arg(
+                    Arg::new("AUDIT_LOG_FILE")
+                        .long("audit-log-file")
+                        .help("Sets the file to append the audit log of the transfer to (empty to disable)")
+                        .required(false)
+                        .num_args(1)
+                        .default_value(""),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub transfer_file: String,
+    pub audit_log_file: String,
+
+    /// "initiate" only.
+    pub allocation_id: String,
+    pub transfer_account_id: String,
+    pub disassociate: bool,
+
+    /// "accept" only.
+    pub mounted_eip_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    let get = |id: &str| {
+        matches
+            .try_get_one::<String>(id)
+            .ok()
+            .flatten()
+            .cloned()
+            .unwrap_or_default()
+    };
+    Flags {
+        transfer_file: get("TRANSFER_FILE"),
+        audit_log_file: get("AUDIT_LOG_FILE"),
+        allocation_id: get("ALLOCATION_ID"),
+        transfer_account_id: get("TRANSFER_ACCOUNT_ID"),
+        disassociate: matches
+            .try_get_one::<bool>("DISASSOCIATE")
+            .ok()
+            .flatten()
+            .copied()
+            .unwrap_or_default(),
+        mounted_eip_file_path: get("MOUNTED_EIP_FILE_PATH"),
+    }
+}
+
+/// Pending transfer, handed from the source account to the receiving account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Offer {
+    pub public_ip: String,
+    /// Allocation ID in the source account.
+    pub allocation_id: String,
+    pub source_account_id: String,
+    pub transfer_account_id: String,
+    /// Expiration of the offer (e.g., "2023-01-09T00:00:00.000Z").
+    pub expires_at: String,
+    /// Tags in the source account, carried over on acceptance.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Returns the caller's account and the EC2 config.
+async fn connect(
+    ctx: &Context,
+    opts: &Flags,
+    sdk_opts: &sdk::Options,
+) -> io::Result<(String, SdkConfig)> {
+    let shared_config = sdk::load_config(None, sdk_opts).await?;
+    ctx.audit
+        .init(&opts.audit_log_file, "", &shared_config)
+        .await?;
+    let identity = sts::Manager::new(&shared_config)
+        .get_identity()
+        .await
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::Other,
+                format!("failed sts.get_identity {}", e.message()),
+            )
+        })?;
+    let ec2_config = sdk::for_service(&shared_config, "ec2", sdk_opts)?;
+    Ok((identity.account_id, ec2_config))
+}
+
+/// Offers the EIP to the other account, and writes the transfer file.
+pub async fn initiate(opts: Flags) -> io::Result<()> {
+    let ctx = Context::default();
+    let sdk_opts = sdk::Options::default();
+    let (account_id, ec2_config) = connect(&ctx, &opts, &sdk_opts).await?;
+    if account_id == opts.transfer_account_id {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("EIP is already in account {account_id}"),
+        ));
+    }
+    let ec2_manager = ec2::Manager::new(&ec2_config);
+    let addr = eip::describe_by_allocation_id(&ec2_manager, &opts.allocation_id)
+        .await?
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("EIP {} does not exist", opts.allocation_id),
+            )
+        })?;
+    if let Some(association_id) = addr.association_id() {
+        if !opts.disassociate {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "EIP {} is associated with {} -- set --disassociate to transfer it anyway",
+                    opts.allocation_id,
+                    addr.instance_id()
+                        .or_else(|| addr.network_interface_id())
+                        .unwrap_or_default()
+                ),
+            ));
+        }
+        eip::disassociate(&ctx, &ec2_manager, &opts.allocation_id, association_id).await?;
+    }
+
+    let public_ip = addr.public_ip().unwrap_or_default().to_string();
+    let ret = transfer::enable(
+        &ec2_config,
+        &sdk_opts,
+        &opts.allocation_id,
+        &opts.transfer_account_id,
+    )
+    .await;
+    ctx.audit.record(
+        "transfer",
+        &[
+            ("allocation_id", &opts.allocation_id),
+            ("public_ip", &public_ip),
+            ("before", &account_id),
+            ("after", &opts.transfer_account_id),
+        ],
+        &ret,
+    )?;
+    let offer = Offer {
+        public_ip,
+        allocation_id: opts.allocation_id.clone(),
+        source_account_id: account_id,
+        transfer_account_id: opts.transfer_account_id.clone(),
+        expires_at: ret?,
+        tags: addr
+            .tags()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|t| Some((t.key()?.to_string(), t.value()?.to_string())))
+            .collect(),
+    };
+    let d = serde_json::to_string_pretty(&offer).map_err(|e| {
+        Error::new(
+            ErrorKind::Other,
+            format!("failed to serialize transfer {}", e),
+        )
+    })?;
+    if opts.transfer_file.is_empty() {
+        println!("{d}");
+    } else {
+        fs::write(&opts.transfer_file, format!("{d}\n"))?;
+    }
+    log::info!(
+        "offered EIP {} to account {} (accept by {})",
+        offer.public_ip,
+        offer.transfer_account_id,
+        offer.expires_at
+    );
+    Ok(())
+}
+
+/// Accepts the EIP of the transfer file, and rewrites the mounted EIP file.
+pub async fn accept(opts: Flags, output: &str) -> io::Result<()> {
+    let d = if opts.transfer_file.is_empty() {
+        let mut d = String::new();
+        io::stdin().read_to_string(&mut d)?;
+        d
+    } else {
+        fs::read_to_string(&opts.transfer_file)?
+    };
+    let offer: Offer = serde_json::from_str(&d).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("invalid transfer file '{}'", e),
+        )
+    })?;
+
+    let ctx = Context::default();
+    let sdk_opts = sdk::Options::default();
+    let (account_id, ec2_config) = connect(&ctx, &opts, &sdk_opts).await?;
+    if account_id != offer.transfer_account_id {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "EIP {} is offered to account {}, not {account_id}",
+                offer.public_ip, offer.transfer_account_id
+            ),
+        ));
+    }
+    let tags: Vec<(String, String)> = offer
+        .tags
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    let ret = transfer::accept(&ec2_config, &sdk_opts, &offer.public_ip, &tags).await;
+    ctx.audit.record(
+        "transfer",
+        &[
+            (
+                "allocation_id",
+                ret.as_deref().unwrap_or(&offer.allocation_id),
+            ),
+            ("public_ip", &offer.public_ip),
+            ("before", &offer.source_account_id),
+            ("after", &account_id),
+        ],
+        &ret,
+    )?;
+    let allocation_id = ret?;
+    log::info!(
+        "accepted EIP {} as {allocation_id} from account {}",
+        offer.public_ip,
+        offer.source_account_id
+    );
+
+    if !opts.mounted_eip_file_path.is_empty() {
+        transfer::rewrite_state(
+            &opts.mounted_eip_file_path,
+            &offer.public_ip,
+            &allocation_id,
+        )?;
+    }
+    if output == "json" {
+        println!(
+            "{}",
+            serde_json::json!({
+                "public_ip": offer.public_ip,
+                "allocation_id": allocation_id,
+                "source_account_id": offer.source_account_id,
+                "mounted_eip_file_path": opts.mounted_eip_file_path,
+            })
+        );
+    }
+    Ok(())
+}
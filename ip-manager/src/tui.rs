@@ -0,0 +1,654 @@
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind, Stdout},
+    panic, thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_ip_provisioner::{
+    conflict, context::Context, ec2, eip, hostname, pool, provisioner::AwsEc2, sdk,
+};
+use aws_sdk_ec2::model::{Address, Filter};
+use clap::{value_parser, Arg, ArgMatches, Command};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+    tty::IsTty,
+};
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Direction, Layout},
+    style::{Modifier, Style},
+    widgets::{Cell, Paragraph, Row as TableRow, Table, TableState},
+    Frame, Terminal,
+};
+use tokio::{
+    sync::mpsc,
+    time::{interval, Duration},
+};
+
+pub const NAME: &str = "tui";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Shows the live state of the tool-managed EIPs in the terminal")
+        .long_about(
+            "
+
+Lists the EIPs with the \"Kind\" tag, with their pool status, association
+(instance or ENI), age, DNS name, and drift markers:
+
+- \"claimed-unassociated\": claimed from the pool, but not associated
+- \"available-associated\": returned to the pool, but still associated
+- \"instance-<state>\": associated with an instance that is not running
+
+Keys:
+
+- \"j\"/\"k\" (or arrows): move the selection
+- \"space\": mark the selected EIP for swap
+- \"s\": swap the associations (and the \"Id\" tags) of the marked and the selected EIPs
+- \"r\": return the selected EIP to the pool (disassociate)
+- \"g\": refresh now
+- \"q\": quit
+
+Nodes in \"watch\" mode re-associate the EIPs in their mounted files,
+so stop them before swapping.
+
+e.g.,
+
+$ ip-manager tui \
+--kind-tag-key=Kind \
+--kind-tag-value=aws-ip-provisioner
+
+",
+        )
+        .arg(
+            Arg::new("ID_TAG_KEY")
+                .long("id-tag-key")
+                .help("Sets the key of the EIP tag that identifies the node (swapped with the associations)")
+                .required(false)
+                .num_args(1)
+                .default_value("Id"),
+        )
+        .arg(
+            Arg::new("KIND_TAG_KEY")
+                .long("kind-tag-key")
+                .help("Sets the key of the EIP tag that groups the tool-managed EIPs")
+                .required(false)
+                .num_args(1)
+                .default_value("Kind"),
+        )
+        .arg(
+            Arg::new("KIND_TAG_VALUE")
+                .long("kind-tag-value")
+                .help("Sets the value of the EIP tag that groups the tool-managed EIPs")
+                .required(true)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("REFRESH_INTERVAL_SECONDS")
+                .long("refresh-interval-seconds")
+                .help("Sets the interval to describe the EIPs again")
+                .required(false)
+                .num_args(1)
+                .value_parser(value_parser!(u32))
+                .default_value("5"),
+        )
+        .arg(
+            Arg::new("AUDIT_LOG_FILE")
+                .long("audit-log-file")
+                .help("Sets the file to append the audit log of the releases and swaps to (empty to disable)")
+                .required(false)
+                .num_args(1),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub id_tag_key: String,
+    pub kind_tag_key: String,
+    pub kind_tag_value: String,
+    pub refresh_interval_seconds: u32,
+    pub audit_log_file: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        id_tag_key: matches
+            .get_one::<String>("ID_TAG_KEY")
+            .unwrap_or(&String::from("Id"))
+            .clone(),
+        kind_tag_key: matches
+            .get_one::<String>("KIND_TAG_KEY")
+            .unwrap_or(&String::from("Kind"))
+            .clone(),
+        kind_tag_value: matches.get_one::<String>("KIND_TAG_VALUE").unwrap().clone(),
+        refresh_interval_seconds: *matches
+            .get_one::<u32>("REFRESH_INTERVAL_SECONDS")
+            .unwrap_or(&5),
+        audit_log_file: matches
+            .get_one::<String>("AUDIT_LOG_FILE")
+            .unwrap_or(&String::new())
+            .clone(),
+    }
+}
+
+/// EIP as listed in the dashboard.
+struct Row {
+    allocation_id: String,
+    public_ip: String,
+    id: String,
+    pool_status: String,
+    /// Instance ID, or the ENI ID if not associated with an instance.
+    target: String,
+    instance_id: Option<String>,
+    allocated_at: Option<u64>,
+    dns_name: String,
+    drift: Vec<String>,
+}
+
+enum Key {
+    Up,
+    Down,
+    Mark,
+    Swap,
+    Release,
+    Refresh,
+    Yes,
+    Quit,
+    /// e.g., the terminal was resized
+    Redraw,
+    Other,
+}
+
+/// Mutating action waiting for the confirmation, by the allocation IDs
+/// (the rows may be reordered by the refresh in the meantime).
+enum Pending {
+    Release(String),
+    Swap(String, String),
+}
+
+pub async fn execute(opts: Flags) -> io::Result<()> {
+    if !io::stdout().is_tty() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("{NAME} requires a terminal"),
+        ));
+    }
+    let shared_config = sdk::load_config(None, &sdk::Options::default()).await?;
+    let ctx = Context::default();
+    ctx.audit
+        .init(&opts.audit_log_file, "", &shared_config)
+        .await?;
+    let ec2_manager = ec2::Manager::new(&sdk::for_service(
+        &shared_config,
+        "ec2",
+        &sdk::Options::default(),
+    )?);
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut screen = Screen::enter()?;
+    thread::spawn(move || read_keys(tx));
+
+    let mut rows: Vec<Row> = Vec::new();
+    let mut selected = 0;
+    // allocation ID, so that the mark follows the EIP across the refreshes
+    let mut marked: Option<String> = None;
+    let mut pending: Option<Pending> = None;
+    let mut status = String::from("loading...");
+    let mut ticker = interval(Duration::from_secs(
+        opts.refresh_interval_seconds.max(1) as u64
+    ));
+    loop {
+        screen
+            .terminal
+            .draw(|f| render(f, &opts, &rows, selected, marked.as_deref(), &status))?;
+        let key = tokio::select! {
+            _ = ticker.tick() => Key::Refresh,
+            key = rx.recv() => key.unwrap_or(Key::Quit),
+        };
+
+        if let Some(p) = pending.take() {
+            match key {
+                Key::Refresh => {
+                    // keeps waiting for the confirmation
+                    if let Ok(v) = fetch(&ec2_manager, &opts).await {
+                        rows = v;
+                    }
+                    pending = Some(p);
+                    continue;
+                }
+                Key::Redraw => {
+                    pending = Some(p);
+                    continue;
+                }
+                Key::Yes => {
+                    status = match apply(&ctx, &ec2_manager, &opts, &rows, p).await {
+                        Ok(done) => done,
+                        Err(e) => format!("failed: {e}"),
+                    }
+                }
+                _ => status = String::from("cancelled"),
+            }
+            marked = None;
+            match fetch(&ec2_manager, &opts).await {
+                Ok(v) => rows = v,
+                Err(e) => status = format!("{status} (failed to describe EIPs: {e})"),
+            }
+            selected = selected.min(rows.len().saturating_sub(1));
+            continue;
+        }
+        match key {
+            Key::Up => selected = selected.saturating_sub(1),
+            Key::Down => selected = (selected + 1).min(rows.len().saturating_sub(1)),
+            Key::Mark if !rows.is_empty() => {
+                let id = &rows[selected].allocation_id;
+                marked = if marked.as_ref() == Some(id) {
+                    None
+                } else {
+                    Some(id.clone())
+                };
+            }
+            Key::Release if !rows.is_empty() => {
+                status = format!("release {} to the pool? [y/N]", rows[selected].public_ip);
+                pending = Some(Pending::Release(rows[selected].allocation_id.clone()));
+            }
+            Key::Swap => match rows
+                .iter()
+                .find(|r| marked.as_ref() == Some(&r.allocation_id))
+            {
+                Some(m) if m.allocation_id != rows[selected].allocation_id => {
+                    status = format!(
+                        "swap {} and {}? [y/N]",
+                        m.public_ip, rows[selected].public_ip
+                    );
+                    pending = Some(Pending::Swap(
+                        m.allocation_id.clone(),
+                        rows[selected].allocation_id.clone(),
+                    ));
+                }
+                _ => status = String::from("mark another EIP with space first"),
+            },
+            Key::Refresh => match fetch(&ec2_manager, &opts).await {
+                Ok(v) => {
+                    status = format!("{} EIPs (refreshed at {})", v.len(), now_unix_seconds());
+                    rows = v;
+                }
+                Err(e) => {
+                    // does not show the stale state
+                    status = format!("failed to describe EIPs: {e}");
+                    rows = Vec::new();
+                }
+            },
+            Key::Quit => return Ok(()),
+            _ => {}
+        }
+        selected = selected.min(rows.len().saturating_sub(1));
+    }
+}
+
+async fn fetch(ec2_manager: &ec2::Manager, opts: &Flags) -> io::Result<Vec<Row>> {
+    let addrs =
+        eip::describe_by_tags(ec2_manager, &[(&opts.kind_tag_key, &opts.kind_tag_value)]).await?;
+
+    let instance_ids: Vec<String> = addrs
+        .iter()
+        .filter_map(|a| a.instance_id().map(|v| v.to_string()))
+        .collect();
+    let mut states = HashMap::new();
+    let mut public_dns_names = HashMap::new();
+    if !instance_ids.is_empty() {
+        let instances = eip::describe_instances(
+            ec2_manager,
+            vec![Filter::builder()
+                .name("instance-id")
+                .set_values(Some(instance_ids))
+                .build()],
+        )
+        .await?;
+        for i in instances.iter() {
+            let id = i.instance_id().unwrap_or_default().to_string();
+            if let Some(name) = i.state().and_then(|s| s.name()) {
+                states.insert(id.clone(), name.as_str().to_string());
+            }
+            if let Some(dns) = i.public_dns_name().filter(|v| !v.is_empty()) {
+                public_dns_names.insert(id, dns.to_string());
+            }
+        }
+    }
+    let allocation_ids: Vec<String> = addrs
+        .iter()
+        .filter_map(|a| a.allocation_id().map(|v| v.to_string()))
+        .collect();
+    let ptrs = hostname::ptr_records(ec2_manager, &allocation_ids).await?;
+
+    let mut rows: Vec<Row> = addrs
+        .iter()
+        .map(|a| {
+            let allocation_id = a.allocation_id().unwrap_or_default().to_string();
+            let instance_id = a.instance_id().map(|v| v.to_string());
+            let state = instance_id.as_ref().and_then(|id| states.get(id));
+            let dns_name = ptrs
+                .get(&allocation_id)
+                .or_else(|| instance_id.as_ref().and_then(|id| public_dns_names.get(id)))
+                .cloned()
+                .unwrap_or_default();
+            Row {
+                public_ip: a.public_ip().unwrap_or_default().to_string(),
+                id: tag(a, &opts.id_tag_key),
+                pool_status: tag(a, pool::STATUS_TAG_KEY),
+                target: instance_id
+                    .clone()
+                    .or_else(|| a.network_interface_id().map(|v| v.to_string()))
+                    .unwrap_or_default(),
+                allocated_at: tag(a, conflict::ALLOCATED_AT_TAG_KEY).parse().ok(),
+                drift: drift(a, state.map(|s| s.as_str())),
+                dns_name,
+                instance_id,
+                allocation_id,
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| a.id.cmp(&b.id).then(a.public_ip.cmp(&b.public_ip)));
+    Ok(rows)
+}
+
+/// Returns the markers where the association disagrees with the pool status
+/// or the instance state.
+fn drift(addr: &Address, instance_state: Option<&str>) -> Vec<String> {
+    let mut markers = Vec::new();
+    let associated = addr.association_id().is_some();
+    match tag(addr, pool::STATUS_TAG_KEY).as_str() {
+        pool::STATUS_CLAIMED if !associated => markers.push(String::from("claimed-unassociated")),
+        pool::STATUS_AVAILABLE if associated => markers.push(String::from("available-associated")),
+        _ => {}
+    }
+    if let Some(state) = instance_state {
+        if state != "running" {
+            markers.push(format!("instance-{state}"));
+        }
+    }
+    markers
+}
+
+/// Runs the confirmed action, returning the status line.
+async fn apply(
+    ctx: &Context,
+    ec2_manager: &ec2::Manager,
+    opts: &Flags,
+    rows: &[Row],
+    pending: Pending,
+) -> io::Result<String> {
+    let find = |allocation_id: &str| {
+        rows.iter()
+            .find(|r| r.allocation_id == allocation_id)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::NotFound,
+                    format!("EIP {allocation_id} no longer listed"),
+                )
+            })
+    };
+    match pending {
+        Pending::Release(id) => {
+            let row = find(&id)?;
+            pool::release(
+                &AwsEc2 { ec2_manager, ctx },
+                &ec2::Eip {
+                    allocation_id: row.allocation_id.clone(),
+                    public_ip: row.public_ip.clone(),
+                },
+            )
+            .await?;
+            Ok(format!("returned {} to the pool", row.public_ip))
+        }
+        Pending::Swap(a, b) => {
+            let (a, b) = (find(&a)?, find(&b)?);
+            let (inst_a, inst_b) = match (&a.instance_id, &b.instance_id) {
+                (Some(x), Some(y)) => (x, y),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "both EIPs must be associated with instances",
+                    ))
+                }
+            };
+            eip::reassociate(ctx, ec2_manager, &a.allocation_id, inst_b).await?;
+            if let Err(e) = eip::reassociate(ctx, ec2_manager, &b.allocation_id, inst_a).await {
+                // the first re-association displaced the second EIP, so moves both back
+                // rather than leaving the first instance without any
+                let rollback = match eip::reassociate(ctx, ec2_manager, &a.allocation_id, inst_a)
+                    .await
+                {
+                    Ok(_) => eip::reassociate(ctx, ec2_manager, &b.allocation_id, inst_b)
+                        .await
+                        .map_err(|re| {
+                            Error::new(
+                                re.kind(),
+                                format!(
+                                    "{} is left unassociated, {} is back on {inst_a} ({re})",
+                                    b.public_ip, a.public_ip
+                                ),
+                            )
+                        }),
+                    Err(re) => Err(Error::new(
+                        re.kind(),
+                        format!(
+                            "{} is left on {inst_b}, {} is unassociated, and {inst_a} has no EIP ({re})",
+                            a.public_ip, b.public_ip
+                        ),
+                    )),
+                };
+                return Err(match rollback {
+                    Ok(_) => Error::new(
+                        e.kind(),
+                        format!(
+                            "failed to associate {} with {inst_a}, rolled back the swap ({e})",
+                            b.public_ip
+                        ),
+                    ),
+                    Err(re) => Error::new(
+                        e.kind(),
+                        format!(
+                            "failed to associate {} with {inst_a} ({e}), and to roll back the swap: {re}",
+                            b.public_ip
+                        ),
+                    ),
+                });
+            }
+            for (row, id) in [(a, &b.id), (b, &a.id)] {
+                eip::create_tags(
+                    ctx,
+                    ec2_manager,
+                    &row.allocation_id,
+                    vec![
+                        (String::from("Name"), id.clone()),
+                        (opts.id_tag_key.clone(), id.clone()),
+                    ],
+                )
+                .await?;
+            }
+            Ok(format!("swapped {} and {}", a.public_ip, b.public_ip))
+        }
+    }
+}
+
+fn render<B: Backend>(
+    f: &mut Frame<B>,
+    opts: &Flags,
+    rows: &[Row],
+    selected: usize,
+    marked: Option<&str>,
+    status: &str,
+) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(2),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .split(f.size());
+    f.render_widget(
+        Paragraph::new(format!(
+            "ip-manager tui -- {}={} (j/k move, space mark, s swap, r release, g refresh, q quit)",
+            opts.kind_tag_key, opts.kind_tag_value
+        )),
+        chunks[0],
+    );
+
+    let now = now_unix_seconds();
+    let table = Table::new(rows.iter().map(|row| {
+        TableRow::new(vec![
+            Cell::from(if marked == Some(row.allocation_id.as_str()) {
+                "*"
+            } else {
+                ""
+            }),
+            Cell::from(row.public_ip.as_str()),
+            Cell::from(row.allocation_id.as_str()),
+            Cell::from(row.id.as_str()),
+            Cell::from(row.pool_status.as_str()),
+            Cell::from(row.target.as_str()),
+            Cell::from(row.allocated_at.map(|t| age(now, t)).unwrap_or_default()),
+            Cell::from(row.dns_name.as_str()),
+            Cell::from(row.drift.join(",")),
+        ])
+    }))
+    .header(
+        TableRow::new(vec![
+            "",
+            "PUBLIC IP",
+            "ALLOCATION ID",
+            "ID",
+            "POOL",
+            "TARGET",
+            "AGE",
+            "DNS",
+            "DRIFT",
+        ])
+        .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .widths(&[
+        Constraint::Length(1),
+        Constraint::Length(16),
+        Constraint::Length(26),
+        Constraint::Length(20),
+        Constraint::Length(10),
+        Constraint::Length(22),
+        Constraint::Length(7),
+        Constraint::Length(40),
+        Constraint::Min(10),
+    ])
+    .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    // scrolls to the selected row if the rows do not fit
+    let mut state = TableState::default();
+    if !rows.is_empty() {
+        state.select(Some(selected));
+    }
+    f.render_stateful_widget(table, chunks[1], &mut state);
+
+    f.render_widget(Paragraph::new(status), chunks[2]);
+}
+
+/// Returns the human-readable age (e.g., "3d4h", "12m").
+fn age(now: u64, since: u64) -> String {
+    let secs = now.saturating_sub(since);
+    match secs {
+        s if s >= 86400 => format!("{}d{}h", s / 86400, s % 86400 / 3600),
+        s if s >= 3600 => format!("{}h{}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m", s / 60),
+        s => format!("{s}s"),
+    }
+}
+
+fn tag(addr: &Address, key: &str) -> String {
+    addr.tags()
+        .unwrap_or_default()
+        .iter()
+        .find(|t| t.key() == Some(key))
+        .and_then(|t| t.value())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn now_unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Reads the key presses until the terminal closes.
+fn read_keys(tx: mpsc::UnboundedSender<Key>) {
+    while let Ok(ev) = event::read() {
+        let key = match ev {
+            // Windows also reports the releases
+            Event::Key(KeyEvent {
+                code,
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }) => match code {
+                KeyCode::Char('k') | KeyCode::Up => Key::Up,
+                KeyCode::Char('j') | KeyCode::Down => Key::Down,
+                KeyCode::Char(' ') => Key::Mark,
+                KeyCode::Char('s') => Key::Swap,
+                KeyCode::Char('r') => Key::Release,
+                KeyCode::Char('g') => Key::Refresh,
+                KeyCode::Char('y') | KeyCode::Char('Y') => Key::Yes,
+                KeyCode::Char('q') => Key::Quit,
+                // "Ctrl-C" is not a signal in raw mode
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => Key::Quit,
+                _ => Key::Other,
+            },
+            Event::Resize(..) => Key::Redraw,
+            _ => continue,
+        };
+        if tx.send(key).is_err() {
+            return;
+        }
+    }
+}
+
+/// Terminal in raw mode on the alternate screen, restored on drop
+/// (including on errors) and on panic.
+struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl Screen {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen, cursor::Hide).map_err(|e| {
+            let _ = restore();
+            e
+        })?;
+        let terminal = Terminal::new(CrosstermBackend::new(stdout)).map_err(|e| {
+            let _ = restore();
+            e
+        })?;
+        // restores before the panic message is printed, which would otherwise
+        // be lost on the alternate screen and leave the terminal raw
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = restore();
+            hook(info);
+        }));
+        Ok(Self { terminal })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = restore();
+    }
+}
+
+fn restore() -> io::Result<()> {
+    terminal::disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen, cursor::Show)
+}
@@ -0,0 +1,141 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{command as aws_eip, validate};
+use clap::{Arg, ArgMatches, Command};
+
+pub const NAME: &str = "validate";
+
+/// Placeholders for the required "aws eip" flags,
+/// to check the config file without the flags.
+const PLACEHOLDER_ARGS: &[&str] = &[
+    "--id-tag-key=Id",
+    "--id-tag-value=validate",
+    "--kind-tag-key=Kind",
+    "--kind-tag-value=validate",
+    "--mounted-eip-file-path=/data/eip.yaml",
+];
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Validates the \"aws eip\" flags, config file, and state file without calling AWS")
+        .long_about(
+            "
+
+Checks the flags that clap cannot (tag key and value syntax, flags that
+require each other, IAM role ARNs, URLs, firewall rules file CIDRs, and the
+files the flags point to), the reloadable config file, and the mounted EIP
+state file. Prints every problem and exits non-zero if any, so that it can
+run in CI before baking the machine image.
+
+e.g.,
+
+$ ip-manager validate \
+--config=/etc/ip-manager/config.json \
+--state=/data/eip.yaml \
+-- \
+--id-tag-value=TEST-ID \
+--kind-tag-value=aws-ip-provisioner \
+--firewall-backend=nftables \
+--firewall-rules-file=/etc/ip-manager/rules.nft
+
+",
+        )
+        .arg(
+            Arg::new("CONFIG")
+                .long("config")
+                .help("Sets the config file to validate (see \"--config-file\")")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("STATE")
+                .long("state")
+                .help("Sets the mounted EIP file to validate (see \"--mounted-eip-file-path\")")
+                .required(false)
+                .num_args(1)
+                .default_value(""),
+        )
+        .arg(
+            Arg::new("ARGS")
+                .help("Sets the flags of \"aws eip\" to validate")
+                .required(false)
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub config: String,
+    pub state: String,
+    pub args: Vec<String>,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        config: matches
+            .get_one::<String>("CONFIG")
+            .unwrap_or(&String::new())
+            .clone(),
+        state: matches
+            .get_one::<String>("STATE")
+            .unwrap_or(&String::new())
+            .clone(),
+        args: matches
+            .get_many::<String>("ARGS")
+            .map(|args| args.cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// Returns the problems found, empty if all valid.
+pub fn problems(opts: &Flags) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut argv = vec![aws_eip::NAME.to_string()];
+    if opts.args.is_empty() {
+        argv.extend(PLACEHOLDER_ARGS.iter().map(|s| s.to_string()));
+    } else {
+        argv.extend(opts.args.iter().cloned());
+    }
+    let eip_opts = match aws_eip::new().try_get_matches_from(argv) {
+        Ok(matches) => Some(aws_eip::parse_flags(&matches)),
+        Err(e) => {
+            // e.g., "error: invalid value 'x' for '--mode <MODE>'"
+            let msg = e.to_string();
+            problems.push(msg.lines().next().unwrap_or_default().to_string());
+            None
+        }
+    };
+    if let Some(eip_opts) = &eip_opts {
+        if !opts.args.is_empty() {
+            problems.extend(validate::flags(eip_opts));
+        }
+        // skip if already checked with "--config-file"
+        if !opts.config.is_empty() && opts.config != eip_opts.config_file {
+            problems.extend(validate::config_file(eip_opts, &opts.config));
+        }
+    }
+    if !opts.state.is_empty() {
+        problems.extend(validate::state_file(&opts.state));
+    }
+    problems
+}
+
+/// Prints the problems to stderr, and fails if any.
+pub fn execute(opts: Flags) -> io::Result<()> {
+    let problems = problems(&opts);
+    for p in problems.iter() {
+        eprintln!("{p}");
+    }
+    if !problems.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} problem(s) found", problems.len()),
+        ));
+    }
+    println!("valid");
+    Ok(())
+}
@@ -0,0 +1,232 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{sdk, secret};
+use clap::{value_parser, Arg, ArgMatches, Command};
+
+use crate::{
+    http::{self, Client},
+    provider::{self, Address, BoxFuture, Provider},
+};
+
+pub const NAME: &str = "vultr";
+
+const API_URL: &str = "https://api.vultr.com/v2";
+
+/// ref. <https://www.vultr.com/metadata/>
+const METADATA_URL: &str = "http://169.254.169.254/v1";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Manages Vultr addresses")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("reserved-ip")
+                .about("Provisions the Reserved IP to the local instance")
+                .long_about(
+                    "
+
+The instance ID and region are fetched from the metadata service.
+
+An unattached Reserved IP with the label in the same region is claimed,
+or a new one is created with the label, unless the state file exists.
+
+e.g.,
+
+$ ip-manager vultr reserved-ip \
+--api-key-source=env:VULTR_API_KEY \
+--label=ip-manager \
+--state-file-path=/data/reserved-ip.yaml
+
+",
+                )
+                .arg(
+                    Arg::new("API_KEY_SOURCE")
+                        .long("api-key-source")
+                        .help("Sets where to read the API key (\"env:<NAME>\", \"file:<PATH>\", \"ssm:<PATH>\", or \"secretsmanager:<NAME>\")")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("env:VULTR_API_KEY"),
+                )
+                .arg(
+                    Arg::new("LABEL")
+                        .long("label")
+                        .help("Sets the label to find and create tool-managed Reserved IPs")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("ip-manager"),
+                )
+                .arg(
+                    Arg::new("NO_STEAL")
+                        .long("no-steal")
+                        .help("Aborts rather than re-attaching the Reserved IP that is attached to another instance")
+                        .required(false)
+                        .num_args(1)
+                        .value_parser(value_parser!(bool))
+                        .default_value("true"),
+                )
+                .arg(
+                    Arg::new("STATE_FILE_PATH")
+                        .long("state-file-path")
+                        .help("Sets the file path to store the Reserved IP information mapped to this volume path")
+                        .required(false)
+                        .num_args(1)
+                        .default_value("/data/reserved-ip.yaml"),
+                ),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub api_key_source: String,
+    pub label: String,
+    pub no_steal: bool,
+    pub state_file_path: String,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        api_key_source: matches
+            .get_one::<String>("API_KEY_SOURCE")
+            .unwrap_or(&String::from("env:VULTR_API_KEY"))
+            .clone(),
+        label: matches
+            .get_one::<String>("LABEL")
+            .unwrap_or(&String::from("ip-manager"))
+            .clone(),
+        no_steal: *matches.get_one::<bool>("NO_STEAL").unwrap_or(&true),
+        state_file_path: matches
+            .get_one::<String>("STATE_FILE_PATH")
+            .unwrap_or(&String::from("/data/reserved-ip.yaml"))
+            .clone(),
+    }
+}
+
+pub async fn execute(opts: Flags) -> io::Result<Address> {
+    let api_key = secret::read(
+        &opts.api_key_source,
+        "VULTR_API_KEY",
+        &sdk::Options::default(),
+    )
+    .await?;
+    let vultr = Vultr {
+        client: Client::new(
+            API_URL,
+            vec![(String::from("Authorization"), format!("Bearer {api_key}"))],
+        ),
+        label: opts.label.clone(),
+    };
+    provider::provision(&vultr, &opts.state_file_path, opts.no_steal).await
+}
+
+/// Vultr Reserved IP backend.
+/// ref. <https://www.vultr.com/api/#tag/reserved-ip>
+pub struct Vultr {
+    client: Client,
+    label: String,
+}
+
+impl Vultr {
+    /// Returns the region ID of the local instance (e.g., "ewr").
+    async fn region(&self) -> io::Result<String> {
+        let region =
+            http::fetch_metadata(&format!("{METADATA_URL}/region/regioncode"), &[]).await?;
+        Ok(region.to_lowercase())
+    }
+}
+
+fn to_address(v: &serde_json::Value) -> io::Result<Address> {
+    let id = v["id"]
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "reserved IP has no 'id'"))?;
+    let ip = v["subnet"]
+        .as_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "reserved IP has no 'subnet'"))?;
+    Ok(Address {
+        provider: NAME.to_string(),
+        id: id.to_string(),
+        ip: ip.to_string(),
+    })
+}
+
+/// Returns the attached instance ID, "None" if empty.
+fn attached_instance(v: &serde_json::Value) -> Option<String> {
+    match v["instance_id"].as_str() {
+        Some(id) if !id.is_empty() => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+impl Provider for Vultr {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    fn local_instance_id(&self) -> BoxFuture<'_, String> {
+        Box::pin(async move {
+            http::fetch_metadata(&format!("{METADATA_URL}/instance-v2-id"), &[]).await
+        })
+    }
+
+    fn claim(&self) -> BoxFuture<'_, Option<Address>> {
+        Box::pin(async move {
+            let region = self.region().await?;
+            let resp = self.client.get("/reserved-ips?per_page=500").await?;
+            for v in resp["reserved_ips"].as_array().into_iter().flatten() {
+                if v["label"].as_str() == Some(&self.label)
+                    && v["region"].as_str() == Some(&region)
+                    && v["ip_type"].as_str() == Some("v4")
+                    && attached_instance(v).is_none()
+                {
+                    return Ok(Some(to_address(v)?));
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn allocate(&self) -> BoxFuture<'_, Address> {
+        Box::pin(async move {
+            let region = self.region().await?;
+            log::info!("creating Reserved IP in {region}");
+            let resp = self
+                .client
+                .post(
+                    "/reserved-ips",
+                    serde_json::json!({ "region": region, "ip_type": "v4", "label": self.label }),
+                )
+                .await?;
+            to_address(&resp["reserved_ip"])
+        })
+    }
+
+    fn assigned_to<'a>(&'a self, addr: &'a Address) -> BoxFuture<'a, Option<String>> {
+        Box::pin(async move {
+            let resp = self
+                .client
+                .get(&format!("/reserved-ips/{}", addr.id))
+                .await?;
+            Ok(attached_instance(&resp["reserved_ip"]))
+        })
+    }
+
+    fn assign<'a>(&'a self, addr: &'a Address, instance_id: &'a str) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            // attach fails if already attached elsewhere
+            if self.assigned_to(addr).await?.is_some() {
+                self.client
+                    .post(
+                        &format!("/reserved-ips/{}/detach", addr.id),
+                        serde_json::json!({}),
+                    )
+                    .await?;
+            }
+            self.client
+                .post(
+                    &format!("/reserved-ips/{}/attach", addr.id),
+                    serde_json::json!({ "instance_id": instance_id }),
+                )
+                .await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,149 @@
+use std::io::{self, Error, ErrorKind};
+
+use aws_ip_provisioner::{command as aws_eip, platform};
+use clap::{Arg, ArgMatches, Command};
+
+pub const NAME: &str = "generate-windows-task";
+
+pub fn command() -> Command {
+    Command::new(NAME)
+        .about("Prints the PowerShell script that runs \"aws eip\" at startup on Windows")
+        .long_about(
+            "
+
+Renders the PowerShell script that registers the scheduled task to run
+\"ip-manager aws eip\" with the trailing flags at every startup as SYSTEM,
+restarted on failure and never stopped for running too long (e.g., \"--mode=daemon\"),
+for the Windows nodes without systemd. Run it once from the EC2Launch user data
+or the image build.
+
+e.g.,
+
+$ ip-manager generate-windows-task \
+--exe-path='C:\\Program Files\\ip-manager\\ip-manager.exe' \
+-- \
+--id-tag-key=Id \
+--id-tag-value=TEST-ID \
+--kind-tag-key=Kind \
+--kind-tag-value=aws-ip-provisioner \
+--mounted-eip-file-path='C:\\ProgramData\\ip-manager\\eip.yaml' \
+--mode=daemon \
+> register-ip-manager.ps1
+
+",
+        )
+        .arg(
+            Arg::new("NAME")
+                .long("name")
+                .help("Sets the name of the scheduled task")
+                .required(false)
+                .num_args(1)
+                .default_value("ip-manager"),
+        )
+        .arg(
+            Arg::new("EXE_PATH")
+                .long("exe-path")
+                .help("Sets the path of \"ip-manager.exe\" on the node")
+                .required(false)
+                .num_args(1)
+                .default_value(r"C:\Program Files\ip-manager\ip-manager.exe"),
+        )
+        .arg(
+            Arg::new("ARGS")
+                .help("Sets the flags of \"aws eip\" to run with")
+                .required(false)
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true),
+        )
+}
+
+/// Defines flag options.
+pub struct Flags {
+    pub name: String,
+    pub exe_path: String,
+    pub args: Vec<String>,
+}
+
+pub fn parse_flags(matches: &ArgMatches) -> Flags {
+    Flags {
+        name: matches
+            .get_one::<String>("NAME")
+            .unwrap_or(&String::from("ip-manager"))
+            .clone(),
+        exe_path: matches
+            .get_one::<String>("EXE_PATH")
+            .cloned()
+            .unwrap_or_default(),
+        args: matches
+            .get_many::<String>("ARGS")
+            .map(|args| args.cloned().collect())
+            .unwrap_or_default(),
+    }
+}
+
+pub fn execute(opts: Flags) -> io::Result<()> {
+    print!("{}", render(&opts)?);
+    Ok(())
+}
+
+/// Quotes the argument for the Windows command line, if needed.
+/// ref. <https://learn.microsoft.com/en-us/cpp/c-language/parsing-c-command-line-arguments>
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+        return arg.to_string();
+    }
+    format!("\"{}\"", arg.replace('"', "\\\""))
+}
+
+/// Returns the PowerShell script.
+pub fn render(opts: &Flags) -> io::Result<String> {
+    let mut argv = vec![aws_eip::NAME.to_string()];
+    argv.extend(opts.args.iter().cloned());
+    let eip_opts =
+        aws_eip::parse_flags(&aws_eip::new().try_get_matches_from(&argv).map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid \"aws eip\" flags {}", e),
+            )
+        })?);
+    // the node's path, whichever the separator of the host rendering it
+    let state_dir = eip_opts
+        .mounted_eip_file_path
+        .rsplit_once(['\\', '/'])
+        .map(|(dir, _)| dir.to_string())
+        .unwrap_or_default();
+
+    let mut task_args = vec!["aws".to_string(), "eip".to_string()];
+    task_args.extend(opts.args.iter().map(|a| quote_arg(a)));
+    let q = platform::powershell_quote;
+
+    let mut s = format!(
+        "# registers the scheduled task of ip-manager {}\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    s.push_str("$ErrorActionPreference = 'Stop'\n");
+    if !state_dir.is_empty() {
+        s.push_str(&format!(
+            "New-Item -ItemType Directory -Force -Path {} | Out-Null\n",
+            q(&state_dir)
+        ));
+    }
+    s.push_str(&format!(
+        "$action = New-ScheduledTaskAction -Execute {} -Argument {}\n",
+        q(&opts.exe_path),
+        q(&task_args.join(" "))
+    ));
+    s.push_str("$trigger = New-ScheduledTaskTrigger -AtStartup\n");
+    s.push_str("$principal = New-ScheduledTaskPrincipal -UserId 'SYSTEM' -LogonType ServiceAccount -RunLevel Highest\n");
+    s.push_str("$settings = New-ScheduledTaskSettingsSet -StartWhenAvailable -RestartCount 999 -RestartInterval (New-TimeSpan -Minutes 1) -ExecutionTimeLimit ([TimeSpan]::Zero)\n");
+    s.push_str(&format!(
+        "Register-ScheduledTask -TaskName {} -Action $action -Trigger $trigger -Principal $principal -Settings $settings -Force | Out-Null\n",
+        q(&opts.name)
+    ));
+    s.push_str(&format!(
+        "Start-ScheduledTask -TaskName {}\n",
+        q(&opts.name)
+    ));
+    Ok(s)
+}
@@ -0,0 +1,14 @@
+//! Fixtures shared by the integration tests.
+
+// each test crate uses a part of the fixtures
+#![allow(dead_code)]
+
+use std::{env, fs, path::PathBuf};
+
+/// Returns an empty directory unique to the test.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("ip-manager-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
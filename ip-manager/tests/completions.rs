@@ -0,0 +1,26 @@
+//! Tests of the shell completion scripts of the command tree.
+
+use std::process::Command;
+
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+#[test]
+fn completes_subcommands_and_flags() {
+    for shell in ["bash", "zsh", "fish"] {
+        let out = Command::new(IP_MANAGER)
+            .args(["completions", shell])
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{shell}");
+        let script = String::from_utf8(out.stdout).unwrap();
+        for word in ["ip-manager", "history", "kind-tag-value"] {
+            assert!(script.contains(word), "{shell} script without '{word}'");
+        }
+    }
+
+    let out = Command::new(IP_MANAGER)
+        .args(["completions", "tcsh"])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+}
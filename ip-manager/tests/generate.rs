@@ -0,0 +1,197 @@
+//! End-to-end tests of the "generate-k8s", "generate-cfn", and "generate-windows-task" subcommands.
+
+use std::process::Command;
+
+use serde_yaml::Value;
+
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+const EIP_ARGS: &[&str] = &[
+    "--id-tag-key=Id",
+    "--id-tag-value=node-1",
+    "--kind-tag-key=Kind",
+    "--kind-tag-value=test",
+    "--mounted-eip-file-path=/var/lib/ip-manager/eip.yaml",
+];
+
+/// Runs "ip-manager" with the args, returning the exit status and stdout.
+fn run(args: &[&str]) -> (bool, String) {
+    let out = Command::new(IP_MANAGER).args(args).output().unwrap();
+    (out.status.success(), String::from_utf8(out.stdout).unwrap())
+}
+
+fn k8s(extra: &[&str], eip_args: &[&str]) -> Vec<Value> {
+    let mut args = vec!["generate-k8s", "--image=example.com/ip-manager:test"];
+    args.extend(extra);
+    args.push("--");
+    args.extend(EIP_ARGS);
+    args.extend(eip_args);
+    let (ok, out) = run(&args);
+    assert!(ok);
+    out.split("---\n")
+        .filter(|d| !d.trim().is_empty())
+        .map(|d| serde_yaml::from_str(d).unwrap())
+        .collect()
+}
+
+fn find<'a>(docs: &'a [Value], kind: &str) -> &'a Value {
+    docs.iter()
+        .find(|d| d["kind"].as_str() == Some(kind))
+        .unwrap_or_else(|| panic!("no {kind} in {docs:#?}"))
+}
+
+#[test]
+fn renders_daemonset_with_irsa() {
+    let docs = k8s(
+        &[],
+        &["--role-arn=arn:aws:iam::123456789012:role/ip-manager"],
+    );
+    assert_eq!(docs.len(), 2);
+
+    let sa = find(&docs, "ServiceAccount");
+    assert_eq!(
+        sa["metadata"]["annotations"]["eks.amazonaws.com/role-arn"].as_str(),
+        Some("arn:aws:iam::123456789012:role/ip-manager")
+    );
+
+    let spec = &find(&docs, "DaemonSet")["spec"]["template"]["spec"];
+    let command: Vec<&str> = spec["containers"][0]["command"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_str().unwrap())
+        .collect();
+    assert_eq!(&command[..3], &["ip-manager", "aws", "eip"]);
+    assert!(command.contains(&"--mode=daemon"));
+    assert!(command.contains(
+        &"--web-identity-token-file=/var/run/secrets/eks.amazonaws.com/serviceaccount/token"
+    ));
+    assert_eq!(
+        spec["volumes"][0]["hostPath"]["path"].as_str(),
+        Some("/var/lib/ip-manager")
+    );
+}
+
+#[test]
+fn renders_job_with_pvc() {
+    let docs = k8s(
+        &[
+            "--mode=job",
+            "--state-volume=pvc",
+            "--state-storage-class=gp3",
+        ],
+        &[],
+    );
+    assert_eq!(docs.len(), 3);
+    assert!(find(&docs, "ServiceAccount")["metadata"]["annotations"].is_null());
+    assert_eq!(
+        find(&docs, "PersistentVolumeClaim")["spec"]["storageClassName"].as_str(),
+        Some("gp3")
+    );
+
+    let spec = &find(&docs, "Job")["spec"]["template"]["spec"];
+    assert_eq!(
+        spec["volumes"][0]["persistentVolumeClaim"]["claimName"].as_str(),
+        Some("ip-manager-state")
+    );
+    let command = spec["containers"][0]["command"].as_sequence().unwrap();
+    assert!(!command.iter().any(|v| v.as_str() == Some("--mode=daemon")));
+}
+
+#[test]
+fn rejects_invalid_k8s_flags() {
+    // a claim cannot be mounted on every node
+    let mut args = vec![
+        "generate-k8s",
+        "--image=example.com/ip-manager:test",
+        "--state-volume=pvc",
+        "--",
+    ];
+    args.extend(EIP_ARGS);
+    assert!(!run(&args).0);
+
+    assert!(!run(&["generate-k8s", "--image=x", "--", "--mode=bad"]).0);
+}
+
+fn cfn(eip_args: &[&str]) -> Value {
+    let mut args = vec!["generate-cfn", "--"];
+    args.extend(EIP_ARGS);
+    args.extend(eip_args);
+    let (ok, out) = run(&args);
+    assert!(ok);
+    serde_yaml::from_str(&out).unwrap()
+}
+
+fn actions(template: &Value) -> Vec<&str> {
+    template["Resources"]["Role"]["Properties"]["Policies"][0]["PolicyDocument"]["Statement"][0]
+        ["Action"]
+        .as_sequence()
+        .unwrap()
+        .iter()
+        .map(|v| v.as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn renders_iam_for_the_flags() {
+    let base = cfn(&[]);
+    assert_eq!(
+        actions(&base),
+        [
+            "ec2:AllocateAddress",
+            "ec2:AssociateAddress",
+            "ec2:CreateTags",
+            "ec2:DescribeAddresses",
+            "ec2:DescribeInstances",
+        ]
+    );
+    assert!(base["Outputs"]["AllocationRolePolicy"].is_null());
+
+    let hook = cfn(&["--mode=terminate-hook", "--lifecycle-hook-name=hook"]);
+    let hook_actions = actions(&hook);
+    assert!(hook_actions.contains(&"autoscaling:CompleteLifecycleAction"));
+    assert!(hook_actions.contains(&"ec2:DisassociateAddress"));
+
+    let transfer = cfn(&["--allocation-role-arn=arn:aws:iam::123456789012:role/eips"]);
+    assert!(actions(&transfer).contains(&"ec2:AcceptAddressTransfer"));
+    let statements = transfer["Resources"]["Role"]["Properties"]["Policies"][0]["PolicyDocument"]
+        ["Statement"]
+        .as_sequence()
+        .unwrap();
+    assert_eq!(
+        statements[1]["Resource"].as_str(),
+        Some("arn:aws:iam::123456789012:role/eips")
+    );
+    assert!(transfer["Outputs"]["AllocationRolePolicy"]["Value"]
+        .as_str()
+        .unwrap()
+        .contains("ec2:EnableAddressTransfer"));
+}
+
+#[test]
+fn renders_windows_task() {
+    let (ok, out) = run(&[
+        "generate-windows-task",
+        "--name=eip",
+        "--",
+        "--id-tag-key=Id",
+        "--id-tag-value=node-1",
+        "--kind-tag-key=Kind",
+        "--kind-tag-value=test",
+        r"--mounted-eip-file-path=C:\ProgramData\ip-manager\eip.yaml",
+        "--mode=daemon",
+        "--post-associate-cmd=update-dns.cmd it's {public_ip}",
+    ]);
+    assert!(ok);
+    assert!(out.contains(r"New-Item -ItemType Directory -Force -Path 'C:\ProgramData\ip-manager'"));
+    // quoted for the command line, then for PowerShell
+    assert!(
+        out.contains(r#" --mode=daemon "--post-associate-cmd=update-dns.cmd it''s {public_ip}"'"#)
+    );
+    assert!(out.contains("Register-ScheduledTask -TaskName 'eip'"));
+    assert!(out.contains("-ExecutionTimeLimit ([TimeSpan]::Zero)"));
+
+    // the "aws eip" flags are checked
+    let (ok, _) = run(&["generate-windows-task", "--", "--mode=unknown"]);
+    assert!(!ok);
+}
@@ -0,0 +1,184 @@
+#![cfg(feature = "plugin")]
+
+//! Conformance tests of the provider plugin protocol against the reference plugin
+//! "ip-manager-provider-file", and end-to-end tests of "ip-manager plugin".
+
+mod common;
+
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use serde_json::{json, Value};
+
+const PLUGIN: &str = env!("CARGO_BIN_EXE_ip-manager-provider-file");
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+fn config(dir: &Path, instance_id: &str) -> Value {
+    json!({
+        "db": dir.join("db.json").display().to_string(),
+        "pool": "192.0.2.10,192.0.2.11",
+        "instance_id": instance_id,
+    })
+}
+
+/// Runs the plugin with the request, returning the exit status and the parsed stdout.
+fn call(req: Value) -> (bool, Value) {
+    let mut child = Command::new(PLUGIN)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(req.to_string().as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    let resp = serde_json::from_slice(&out.stdout).unwrap_or(Value::Null);
+    (out.status.success(), resp)
+}
+
+fn op(config: &Value, operation: &str, extra: Value) -> (bool, Value) {
+    let mut req = json!({
+        "protocol_version": 1,
+        "operation": operation,
+        "config": config,
+    });
+    if let Value::Object(m) = extra {
+        for (k, v) in m {
+            req[k] = v;
+        }
+    }
+    call(req)
+}
+
+#[test]
+fn describe_returns_protocol_version() {
+    let dir = common::test_dir("describe");
+    let (ok, resp) = op(&config(&dir, "i-1"), "describe", json!({}));
+    assert!(ok);
+    assert_eq!(resp["protocol_version"], json!(1));
+}
+
+#[test]
+fn unknown_operation_fails_with_error() {
+    let dir = common::test_dir("unknown");
+    let (ok, resp) = op(&config(&dir, "i-1"), "unknown", json!({}));
+    assert!(!ok);
+    assert!(resp["error"].is_string());
+}
+
+#[test]
+fn unsupported_protocol_version_fails() {
+    let dir = common::test_dir("version");
+    let (ok, resp) = call(json!({
+        "protocol_version": 999,
+        "operation": "describe",
+        "config": config(&dir, "i-1"),
+    }));
+    assert!(!ok);
+    assert!(resp["error"].is_string());
+}
+
+#[test]
+fn lifecycle() {
+    let dir = common::test_dir("lifecycle");
+    let cfg = config(&dir, "i-1");
+
+    let (ok, resp) = op(&cfg, "local_instance_id", json!({}));
+    assert!(ok);
+    assert_eq!(resp["instance_id"], json!("i-1"));
+
+    // nothing to reuse yet
+    let (ok, resp) = op(&cfg, "claim", json!({}));
+    assert!(ok);
+    assert!(resp["address"].is_null());
+
+    let (ok, resp) = op(&cfg, "allocate", json!({}));
+    assert!(ok);
+    let addr = resp["address"].clone();
+    assert!(addr["id"].is_string());
+    assert_eq!(addr["ip"], json!("192.0.2.10"));
+
+    // allocated but unassigned, so claimable
+    let (ok, resp) = op(&cfg, "claim", json!({}));
+    assert!(ok);
+    assert_eq!(resp["address"], addr);
+
+    let (ok, resp) = op(&cfg, "assigned_to", json!({ "address": addr }));
+    assert!(ok);
+    assert!(resp["instance_id"].is_null());
+
+    let (ok, _) = op(
+        &cfg,
+        "assign",
+        json!({ "address": addr, "instance_id": "i-1" }),
+    );
+    assert!(ok);
+
+    let (ok, resp) = op(&cfg, "assigned_to", json!({ "address": addr }));
+    assert!(ok);
+    assert_eq!(resp["instance_id"], json!("i-1"));
+
+    let (ok, resp) = op(&cfg, "claim", json!({}));
+    assert!(ok);
+    assert!(resp["address"].is_null());
+}
+
+#[test]
+fn allocate_fails_when_pool_is_exhausted() {
+    let dir = common::test_dir("exhausted");
+    let cfg = config(&dir, "i-1");
+    assert!(op(&cfg, "allocate", json!({})).0);
+    assert!(op(&cfg, "allocate", json!({})).0);
+    let (ok, resp) = op(&cfg, "allocate", json!({}));
+    assert!(!ok);
+    assert!(resp["error"].is_string());
+}
+
+/// Runs "ip-manager plugin" with the reference plugin, returning the stdout if successful.
+fn provision(dir: &Path, instance_id: &str) -> Option<Value> {
+    let plugin_dir = Path::new(PLUGIN).parent().unwrap();
+    let out = Command::new(IP_MANAGER)
+        .args([
+            "--output=json",
+            "plugin",
+            "--name=file",
+            &format!("--plugin-dir={}", plugin_dir.display()),
+            &format!("--config=db={}", dir.join("db.json").display()),
+            "--config=pool=192.0.2.10",
+            &format!("--config=instance_id={instance_id}"),
+            &format!("--state-file-path={}", dir.join("plugin-ip.yaml").display()),
+        ])
+        .stderr(Stdio::null())
+        .output()
+        .unwrap();
+    if !out.status.success() {
+        return None;
+    }
+    Some(serde_json::from_slice(&out.stdout).unwrap())
+}
+
+#[test]
+fn provision_is_idempotent() {
+    let dir = common::test_dir("provision");
+    let addr = provision(&dir, "i-1").unwrap();
+    assert_eq!(addr["provider"], json!("file"));
+    assert_eq!(addr["ip"], json!("192.0.2.10"));
+    assert!(dir.join("plugin-ip.yaml").exists());
+
+    assert_eq!(provision(&dir, "i-1").unwrap(), addr);
+}
+
+#[test]
+fn provision_does_not_steal() {
+    let dir = common::test_dir("no-steal");
+    assert!(provision(&dir, "i-1").is_some());
+    // same state file (e.g., the volume moved) while the address is still assigned to i-1
+    assert!(provision(&dir, "i-2").is_none());
+}
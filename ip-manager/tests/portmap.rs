@@ -0,0 +1,100 @@
+#![cfg(feature = "portmap")]
+
+//! End-to-end tests of "ip-manager portmap port-mapping" against a fake NAT-PMP gateway.
+
+use std::{
+    env, fs,
+    net::UdpSocket,
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+/// Answers the external address requests with "203.0.113.7", and maps
+/// every requested external port except "taken" (mapped to the next one),
+/// recording the requested external ports.
+fn gateway(taken: u16) -> (String, Arc<Mutex<Vec<u16>>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    let requested = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requested.clone();
+    thread::spawn(move || loop {
+        let mut buf = [0u8; 64];
+        let (n, from) = socket.recv_from(&mut buf).unwrap();
+        let resp = match buf[1] {
+            0 if n == 2 => vec![0, 128, 0, 0, 0, 0, 0, 1, 203, 0, 113, 7],
+            op @ (1 | 2) if n == 12 => {
+                let external = u16::from_be_bytes([buf[6], buf[7]]);
+                recorded.lock().unwrap().push(external);
+                let mapped = if external == taken {
+                    external + 1
+                } else {
+                    external
+                };
+                let mut b = vec![0, 128 + op, 0, 0, 0, 0, 0, 1, buf[4], buf[5]];
+                b.extend_from_slice(&mapped.to_be_bytes());
+                b.extend_from_slice(&buf[8..12]);
+                b
+            }
+            op => vec![0, 128 + op, 0, 5],
+        };
+        socket.send_to(&resp, from).unwrap();
+    });
+    (addr, requested)
+}
+
+fn port_mapping(gateway: &str, state_file: &str, external_port: &str) -> (bool, String) {
+    let out = Command::new(IP_MANAGER)
+        .args([
+            "portmap",
+            "port-mapping",
+            "--method=natpmp",
+            &format!("--gateway={gateway}"),
+            "--protocol=udp",
+            "--internal-port=9651",
+            &format!("--external-port={external_port}"),
+            &format!("--state-file-path={state_file}"),
+        ])
+        .output()
+        .unwrap();
+    (
+        out.status.success(),
+        String::from_utf8_lossy(&out.stderr).to_string(),
+    )
+}
+
+#[test]
+fn maps_and_reuses_the_external_port() {
+    let (gw, requested) = gateway(30303);
+    let state_file = env::temp_dir().join(format!("port-mapping-{}.yaml", std::process::id()));
+    let state_file = state_file.to_str().unwrap();
+    let _ = fs::remove_file(state_file);
+
+    let (ok, stderr) = port_mapping(&gw, state_file, "0");
+    assert!(ok, "{stderr}");
+    let state = fs::read_to_string(state_file).unwrap();
+    assert!(state.contains("provider: portmap"), "{state}");
+    assert!(state.contains("id: udp:9651"), "{state}");
+    assert!(state.contains("ip: 203.0.113.7"), "{state}");
+
+    // NAT-PMP cannot look up the mapping, so it is re-requested after the allocation
+    assert_eq!(*requested.lock().unwrap(), vec![9651, 9651]);
+
+    // restarts renew the recorded port, not the flag
+    let (ok, stderr) = port_mapping(&gw, state_file, "9999");
+    assert!(ok, "{stderr}");
+    assert_eq!(*requested.lock().unwrap(), vec![9651, 9651, 9651]);
+
+    // e.g., another host on the LAN holds the recorded port
+    fs::write(
+        state_file,
+        "provider: portmap\nid: udp:30303\nip: 203.0.113.7\n",
+    )
+    .unwrap();
+    let (ok, stderr) = port_mapping(&gw, state_file, "0");
+    assert!(!ok);
+    assert!(stderr.contains("taken by another host"), "{stderr}");
+    fs::remove_file(state_file).unwrap();
+}
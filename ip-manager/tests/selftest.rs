@@ -0,0 +1,62 @@
+//! Tests of the "self-test" subcommand without AWS.
+
+use std::process::Command;
+
+use serde_json::Value;
+
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+/// Nothing listens on the port, so that IMDS and AWS are unreachable.
+const UNREACHABLE: &str = "http://127.0.0.1:1";
+
+fn self_test(eip_args: &[&str]) -> (bool, String) {
+    let imds_endpoint = format!("--imds-endpoint={UNREACHABLE}");
+    let endpoint_url = format!("--endpoint-url={UNREACHABLE}");
+    let mut args = vec![
+        "--output=json",
+        "self-test",
+        "--",
+        "--id-tag-key=Id",
+        "--id-tag-value=node-1",
+        "--kind-tag-key=Kind",
+        "--kind-tag-value=test",
+        "--mounted-eip-file-path=/var/lib/ip-manager/eip.yaml",
+        "--imds-retries=0",
+        &imds_endpoint,
+        &endpoint_url,
+    ];
+    args.extend(eip_args);
+    let out = Command::new(IP_MANAGER)
+        .args(&args)
+        .env("AWS_REGION", "us-west-2")
+        .env("AWS_ACCESS_KEY_ID", "test")
+        .env("AWS_SECRET_ACCESS_KEY", "test")
+        .output()
+        .unwrap();
+    (out.status.success(), String::from_utf8(out.stdout).unwrap())
+}
+
+#[test]
+fn stops_before_allocating_without_instance_id() {
+    let (ok, out) = self_test(&["--instance-id-fallback=none"]);
+    assert!(!ok);
+
+    let report: Value = serde_json::from_str(&out).unwrap();
+    assert_eq!(report["ok"], false);
+    assert_eq!(report["allocation_id"], "");
+    let steps = report["steps"].as_array().unwrap();
+    assert_eq!(steps.len(), 1, "{report:#}");
+    assert_eq!(steps[0]["name"], "instance_id");
+    assert_eq!(steps[0]["ok"], false);
+    assert!(
+        steps[0]["detail"].as_str().unwrap().contains("IMDS"),
+        "{report:#}"
+    );
+}
+
+#[test]
+fn rejects_invalid_eip_flags() {
+    let (ok, out) = self_test(&["--mode=invalid"]);
+    assert!(!ok);
+    assert!(out.is_empty());
+}
@@ -0,0 +1,140 @@
+//! Tests of the "self-update" subcommand against a local release server.
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::TcpListener,
+    process::Command,
+    thread,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
+
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+/// Serves the release assets of the "latest" tag, redirecting them once
+/// as GitHub does, and returns the base URL.
+fn serve(binary: Vec<u8>, sha256sum: String, sig: String) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).unwrap();
+            let req = String::from_utf8_lossy(&buf[..n]).to_string();
+            let path = req
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or_default()
+                .to_string();
+            let resp = if let Some(rest) = path.strip_prefix("/latest/") {
+                format!("HTTP/1.1 302 Found\r\nlocation: http://{addr}/objects/{rest}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .into_bytes()
+            } else {
+                let body = if path.ends_with(".sha256") {
+                    sha256sum.clone().into_bytes()
+                } else if path.ends_with(".sig") {
+                    sig.clone().into_bytes()
+                } else {
+                    binary.clone()
+                };
+                let mut d = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                    body.len()
+                )
+                .into_bytes();
+                d.extend(body);
+                d
+            };
+            let _ = stream.write_all(&resp);
+        }
+    });
+    format!("http://{addr}")
+}
+
+fn self_update(url: &str, public_key: &str, exe_path: &str, dry_run: bool) -> bool {
+    let mut args = vec![
+        "self-update".to_string(),
+        "--channel=latest".to_string(),
+        format!("--download-url={url}"),
+        format!("--public-key={public_key}"),
+        format!("--exe-path={exe_path}"),
+    ];
+    if dry_run {
+        args.push("--dry-run".to_string());
+    }
+    Command::new(IP_MANAGER)
+        .args(&args)
+        .status()
+        .unwrap()
+        .success()
+}
+
+#[test]
+fn replaces_verified_release() {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+    let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+    let public_key = STANDARD.encode(key.public_key().as_ref());
+
+    let binary = b"#!/bin/sh\necho new\n".to_vec();
+    let sha256sum: String = digest::digest(&digest::SHA256, &binary)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let sig = STANDARD.encode(key.sign(&binary).as_ref());
+    let url = serve(binary.clone(), format!("{sha256sum}  ip-manager\n"), sig);
+
+    let exe_path = env::temp_dir().join(format!("ip-manager-self-update-{}", std::process::id()));
+    let exe_path_str = exe_path.to_str().unwrap();
+    fs::write(&exe_path, b"old").unwrap();
+
+    assert!(self_update(&url, &public_key, exe_path_str, true));
+    assert_eq!(fs::read(&exe_path).unwrap(), b"old");
+
+    assert!(self_update(&url, &public_key, exe_path_str, false));
+    assert_eq!(fs::read(&exe_path).unwrap(), binary);
+    fs::remove_file(&exe_path).unwrap();
+}
+
+#[test]
+fn rejects_release_of_other_key() {
+    let rng = SystemRandom::new();
+    let signer =
+        Ed25519KeyPair::from_pkcs8(Ed25519KeyPair::generate_pkcs8(&rng).unwrap().as_ref()).unwrap();
+    let other =
+        Ed25519KeyPair::from_pkcs8(Ed25519KeyPair::generate_pkcs8(&rng).unwrap().as_ref()).unwrap();
+
+    let binary = b"tampered".to_vec();
+    let sha256sum: String = digest::digest(&digest::SHA256, &binary)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let url = serve(
+        binary,
+        sha256sum,
+        STANDARD.encode(signer.sign(b"tampered").as_ref()),
+    );
+
+    let exe_path = env::temp_dir().join(format!(
+        "ip-manager-self-update-other-{}",
+        std::process::id()
+    ));
+    fs::write(&exe_path, b"old").unwrap();
+    let public_key = STANDARD.encode(other.public_key().as_ref());
+    assert!(!self_update(
+        &url,
+        &public_key,
+        exe_path.to_str().unwrap(),
+        false
+    ));
+    assert_eq!(fs::read(&exe_path).unwrap(), b"old");
+    fs::remove_file(&exe_path).unwrap();
+}
@@ -0,0 +1,190 @@
+//! Tests of the "serve" subcommand, for the requests rejected before any AWS call.
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command},
+    thread,
+    time::Duration,
+};
+
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+/// Kills the server when the test ends, even on panic.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn start(serve_args: &[&str]) -> (Server, String) {
+    // free port, released right before the server binds it
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let state_dir = std::env::temp_dir().join(format!("ip-manager-serve-{}", std::process::id()));
+    let listen_address = format!("--listen-address={addr}");
+    let state_dir = format!("--state-dir={}", state_dir.display());
+    let mut args = vec!["serve", &listen_address, &state_dir];
+    args.extend(serve_args);
+    args.extend([
+        "--",
+        "--id-tag-key=Id",
+        "--id-tag-value=unused",
+        "--kind-tag-key=Kind",
+        "--kind-tag-value=test",
+        "--mounted-eip-file-path=unused",
+        // nothing listens on the port
+        "--endpoint-url=http://127.0.0.1:1",
+    ]);
+    let child = Command::new(IP_MANAGER)
+        .args(&args)
+        .env("AWS_REGION", "us-west-2")
+        .env("AWS_ACCESS_KEY_ID", "test")
+        .env("AWS_SECRET_ACCESS_KEY", "test")
+        .spawn()
+        .unwrap();
+    let server = Server(child);
+    for _ in 0..100 {
+        if TcpStream::connect(&addr).is_ok() {
+            return (server, addr);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    panic!("server did not listen on {addr}");
+}
+
+/// Returns the status code and the body.
+fn request(addr: &str, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).unwrap();
+    let status = resp.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = resp
+        .split("\r\n\r\n")
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+    (status, body)
+}
+
+#[test]
+fn requires_certificate_by_default() {
+    let out = Command::new(IP_MANAGER)
+        .args([
+            "serve",
+            "--listen-address=127.0.0.1:0",
+            "--",
+            "--id-tag-key=Id",
+            "--id-tag-value=unused",
+            "--kind-tag-key=Kind",
+            "--kind-tag-value=test",
+            "--mounted-eip-file-path=unused",
+        ])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("--identity-certificate"), "{stderr}");
+}
+
+#[test]
+fn rejects_invalid_requests() {
+    let (_server, addr) = start(&["--insecure-accept-unsigned-documents"]);
+
+    assert_eq!(request(&addr, "POST", "/v2/eip", "{}").0, 404);
+    assert_eq!(request(&addr, "GET", "/v1/eip", "").0, 405);
+
+    let (status, body) = request(&addr, "POST", "/v1/eip", "not json");
+    assert_eq!(status, 400);
+    assert!(
+        body.contains("invalid instance identity document"),
+        "{body}"
+    );
+
+    let doc = r#"{"accountId":"123456789012","instanceId":"i-0123456789abcdef0","region":"us-east-1","privateIp":"127.0.0.1"}"#;
+    let (status, body) = request(&addr, "POST", "/v1/eip", doc);
+    assert_eq!(status, 403);
+    assert!(body.contains("not the server's region us-west-2"), "{body}");
+}
+
+#[test]
+fn serves_build_info() {
+    let (_server, addr) = start(&["--insecure-accept-unsigned-documents"]);
+
+    let (status, body) = request(&addr, "GET", "/buildinfo", "");
+    assert_eq!(status, 200);
+    let info: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert!(info["git_commit"].is_string());
+    assert!(info["target"].is_string());
+}
+
+#[test]
+fn admits_reachability_probes() {
+    let (_server, addr) = start(&["--insecure-accept-unsigned-documents"]);
+    assert_eq!(request(&addr, "POST", "/reachability", "").0, 404);
+
+    let (_server, addr) = start(&[
+        "--insecure-accept-unsigned-documents",
+        "--reachability-reflector",
+    ]);
+    assert_eq!(request(&addr, "GET", "/reachability?port=22", "").0, 405);
+    let (status, _) = request(&addr, "POST", "/reachability?protocol=udp&port=53", "{}");
+    assert_eq!(status, 400);
+
+    // the same admission as "/v1/eip", before probing anything
+    let (status, body) = request(
+        &addr,
+        "POST",
+        "/reachability?protocol=tcp&host=127.0.0.1&port=22",
+        "not json",
+    );
+    assert_eq!(status, 400);
+    assert!(
+        body.contains("invalid instance identity document"),
+        "{body}"
+    );
+    let doc = r#"{"accountId":"123456789012","instanceId":"i-0123456789abcdef0","region":"us-east-1","privateIp":"127.0.0.1"}"#;
+    let (status, body) = request(
+        &addr,
+        "POST",
+        "/reachability?protocol=tcp&host=127.0.0.1&port=22",
+        doc,
+    );
+    assert_eq!(status, 403);
+    assert!(body.contains("not the server's region us-west-2"), "{body}");
+}
+
+#[test]
+fn rejects_unsigned_document_with_certificate() {
+    let certificate =
+        std::env::temp_dir().join(format!("ip-manager-serve-{}.pem", std::process::id()));
+    std::fs::write(&certificate, "unused").unwrap();
+    let identity_certificate = format!("--identity-certificate={}", certificate.display());
+    let (_server, addr) = start(&[&identity_certificate]);
+
+    let doc = r#"{"accountId":"123456789012","instanceId":"i-0123456789abcdef0","region":"us-west-2","privateIp":"127.0.0.1"}"#;
+    let (status, body) = request(&addr, "POST", "/v1/eip", doc);
+    assert_eq!(status, 403);
+    assert!(
+        body.contains("unsigned instance identity document"),
+        "{body}"
+    );
+
+    let signed = r#"{"document":"{}","pkcs7":"not base64!"}"#;
+    let (status, body) = request(&addr, "POST", "/v1/eip", signed);
+    assert_eq!(status, 400);
+    assert!(body.contains("invalid base64 PKCS7"), "{body}");
+}
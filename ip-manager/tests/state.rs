@@ -0,0 +1,80 @@
+//! Tests of the "state" subcommand, for the bundles rejected before any AWS call.
+
+mod common;
+
+use std::{fs, process::Command};
+
+use ring::digest;
+
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+const STATE: &str = "allocation_id: eipalloc-0123456789abcdef0\npublic_ip: 203.0.113.7\n";
+
+fn bundle(state: &str, state_sha256: &str) -> String {
+    serde_json::json!({
+        "version": 1,
+        "exported_at": 1673000000,
+        "account_id": "123456789012",
+        "region": "us-west-2",
+        "allocation_id": "eipalloc-0123456789abcdef0",
+        "public_ip": "203.0.113.7",
+        "id": "node-1",
+        "tags": { "Id": "node-1" },
+        "instance_id": "i-0123456789abcdef0",
+        "state": state,
+        "state_sha256": state_sha256,
+    })
+    .to_string()
+}
+
+fn import(name: &str, bundle: &str, mounted_eip_file: Option<&str>) -> String {
+    let dir = common::test_dir(&format!("state-{name}"));
+    let bundle_file = dir.join("bundle.json");
+    fs::write(&bundle_file, bundle).unwrap();
+    let mounted_eip_file_path = dir.join("eip.yaml");
+    if let Some(d) = mounted_eip_file {
+        fs::write(&mounted_eip_file_path, d).unwrap();
+    }
+    let out = Command::new(IP_MANAGER)
+        .args([
+            "state",
+            "import",
+            &format!("--bundle-file={}", bundle_file.display()),
+            &format!(
+                "--mounted-eip-file-path={}",
+                mounted_eip_file_path.display()
+            ),
+        ])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    // untouched
+    assert_eq!(
+        fs::read_to_string(&mounted_eip_file_path).ok().as_deref(),
+        mounted_eip_file
+    );
+    fs::remove_dir_all(&dir).unwrap();
+    String::from_utf8(out.stderr).unwrap()
+}
+
+#[test]
+fn rejects_tampered_bundle() {
+    let sha256: String = digest::digest(&digest::SHA256, STATE.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    let stderr = import(
+        "tampered",
+        &bundle(&STATE.replace("203.0.113.7", "203.0.113.8"), &sha256),
+        None,
+    );
+    assert!(stderr.contains("does not match its checksum"), "{stderr}");
+
+    let stderr = import(
+        "other",
+        &bundle(STATE, &sha256),
+        Some("allocation_id: eipalloc-1\npublic_ip: 198.51.100.1\n"),
+    );
+    assert!(stderr.contains("holds another EIP eipalloc-1"), "{stderr}");
+}
@@ -0,0 +1,18 @@
+//! Tests of the "tui" subcommand without a terminal.
+
+use std::process::{Command, Stdio};
+
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+#[test]
+fn requires_a_terminal() {
+    // e.g., piped to a file, or run by a service manager
+    let out = Command::new(IP_MANAGER)
+        .args(["tui", "--kind-tag-value=aws-ip-provisioner"])
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    assert!(stderr.contains("tui requires a terminal"), "{stderr}");
+}
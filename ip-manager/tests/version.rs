@@ -0,0 +1,52 @@
+//! Tests of the "--version" features report and the "version" build info.
+
+use std::process::Command;
+
+const IP_MANAGER: &str = env!("CARGO_BIN_EXE_ip-manager");
+
+#[test]
+fn reports_features() {
+    let out = Command::new(IP_MANAGER).arg("--version").output().unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let features = stdout
+        .lines()
+        .find_map(|l| l.strip_prefix("features: "))
+        .expect("no features line");
+    for f in features.split(' ') {
+        let (sign, name) = f.split_at(1);
+        assert!(sign == "+" || sign == "-", "{f}");
+        assert!(!name.is_empty());
+    }
+    assert!(features
+        .split(' ')
+        .any(|f| f == "+consul" || f == "-consul"));
+
+    // "-V" stays the short version
+    let out = Command::new(IP_MANAGER).arg("-V").output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(!stdout.contains("features"));
+}
+
+#[test]
+fn reports_build_info() {
+    let out = Command::new(IP_MANAGER)
+        .args(["--output=json", "version", "--verbose"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let info: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    for key in ["git_commit", "build_timestamp", "rustc_version", "target"] {
+        assert!(!info[key].as_str().unwrap().is_empty(), "{key}");
+    }
+    assert!(info["build_timestamp"].as_str().unwrap().ends_with('Z'));
+    assert!(info["features"].is_array());
+
+    let out = Command::new(IP_MANAGER).arg("version").output().unwrap();
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(
+        stdout.trim(),
+        format!("ip-manager {}", env!("CARGO_PKG_VERSION"))
+    );
+}
@@ -0,0 +1,275 @@
+//! Harness to run the "aws-ip-provisioner" binary end-to-end against LocalStack,
+//! with a fake instance metadata service for the local instance.
+//!
+//! The tests are ignored by default, since they need Docker:
+//!
+//! ```bash
+//! cargo build -p aws-ip-provisioner
+//! cargo test -p it -- --ignored --test-threads=1
+//! ```
+//!
+//! Set "IT_LOCALSTACK_ENDPOINT" to use a running LocalStack instead of starting one,
+//! and "IT_AWS_IP_PROVISIONER_BIN" to test a binary other than "target/debug".
+
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Error, ErrorKind},
+    path::PathBuf,
+    process::{Command, Output},
+    sync::{Arc, Mutex},
+};
+
+use aws_ip_provisioner::{ec2, eip, sdk};
+use aws_sdk_ec2::model::{Address, Filter, Tag};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time::{sleep, Duration},
+};
+
+pub const REGION: &str = "us-east-1";
+
+/// LocalStack image to start, pinned for reproducible API behavior.
+const LOCALSTACK_IMAGE: &str = "localstack/localstack:3.0";
+
+/// LocalStack container, removed on drop.
+pub struct LocalStack {
+    pub endpoint: String,
+    container_id: Option<String>,
+}
+
+impl LocalStack {
+    /// Starts LocalStack (or connects to "IT_LOCALSTACK_ENDPOINT"),
+    /// and waits until the EC2 API responds.
+    pub async fn start() -> io::Result<Self> {
+        // static credentials, as LocalStack accepts any
+        env::set_var("AWS_ACCESS_KEY_ID", "test");
+        env::set_var("AWS_SECRET_ACCESS_KEY", "test");
+        env::set_var("AWS_REGION", REGION);
+
+        let ls = match env::var("IT_LOCALSTACK_ENDPOINT") {
+            Ok(endpoint) => Self {
+                endpoint,
+                container_id: None,
+            },
+            Err(_) => {
+                let out = docker(&[
+                    "run",
+                    "--detach",
+                    "--rm",
+                    "--publish",
+                    "127.0.0.1::4566",
+                    "--env",
+                    "SERVICES=ec2,autoscaling",
+                    LOCALSTACK_IMAGE,
+                ])?;
+                let container_id = out.trim().to_string();
+                let port = docker(&["port", &container_id, "4566/tcp"])?;
+                // e.g., "127.0.0.1:49153"
+                let addr = port.lines().next().unwrap_or_default().trim().to_string();
+                log::info!("started LocalStack {container_id} on {addr}");
+                Self {
+                    endpoint: format!("http://{addr}"),
+                    container_id: Some(container_id),
+                }
+            }
+        };
+
+        let manager = ls.ec2_manager().await?;
+        for i in 0..60 {
+            match manager.client().describe_regions().send().await {
+                Ok(_) => return Ok(ls),
+                Err(e) => log::info!("waiting for LocalStack ({i}) '{:?}'", e),
+            }
+            sleep(Duration::from_secs(2)).await;
+        }
+        Err(Error::new(
+            ErrorKind::TimedOut,
+            format!("LocalStack {} did not become ready", ls.endpoint),
+        ))
+    }
+
+    /// Returns the EC2 client of LocalStack to set up and assert on the resources.
+    pub async fn ec2_manager(&self) -> io::Result<ec2::Manager> {
+        let opts = sdk::Options {
+            endpoint_url: self.endpoint.clone(),
+            ..Default::default()
+        };
+        let shared_config = sdk::load_config(Some(REGION.to_string()), &opts).await?;
+        Ok(ec2::Manager::new(&sdk::for_service(
+            &shared_config,
+            "ec2",
+            &opts,
+        )?))
+    }
+
+    /// Launches an instance to provision the EIP to, returning its ID.
+    pub async fn run_instance(&self) -> io::Result<String> {
+        let manager = self.ec2_manager().await?;
+        let images = manager
+            .client()
+            .describe_images()
+            .send()
+            .await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed describe_images {:?}", e)))?;
+        let image_id = images
+            .images()
+            .unwrap_or_default()
+            .iter()
+            .find_map(|i| i.image_id())
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no image in LocalStack"))?
+            .to_string();
+        let resp = manager
+            .client()
+            .run_instances()
+            .image_id(image_id)
+            .min_count(1)
+            .max_count(1)
+            .send()
+            .await
+            .map_err(|e| Error::new(ErrorKind::Other, format!("failed run_instances {:?}", e)))?;
+        resp.instances()
+            .unwrap_or_default()
+            .iter()
+            .find_map(|i| i.instance_id())
+            .map(|v| v.to_string())
+            .ok_or_else(|| Error::new(ErrorKind::Other, "run_instances returned no instance"))
+    }
+
+    /// Describes the EIPs with the "Kind" tag.
+    pub async fn addresses(&self, kind_tag_value: &str) -> io::Result<Vec<Address>> {
+        let manager = self.ec2_manager().await?;
+        eip::describe(
+            &manager,
+            vec![Filter::builder()
+                .name("tag:Kind")
+                .values(kind_tag_value)
+                .build()],
+        )
+        .await
+    }
+}
+
+impl Drop for LocalStack {
+    fn drop(&mut self) {
+        if let Some(container_id) = self.container_id.take() {
+            if let Err(e) = docker(&["rm", "--force", &container_id]) {
+                log::warn!("failed to remove LocalStack {container_id} '{}'", e);
+            }
+        }
+    }
+}
+
+fn docker(args: &[&str]) -> io::Result<String> {
+    let out = Command::new("docker").args(args).output()?;
+    if !out.status.success() {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!(
+                "failed 'docker {}' {}",
+                args.join(" "),
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+/// Fake instance metadata service of one instance, serving the IMDSv2 token
+/// and the "meta-data" paths set by the test (404 for the others).
+pub struct FakeImds {
+    pub endpoint: String,
+    meta_data: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl FakeImds {
+    pub async fn start(instance_id: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}", listener.local_addr()?);
+        let meta_data = Arc::new(Mutex::new(HashMap::from([
+            (String::from("instance-id"), instance_id.to_string()),
+            (String::from("local-ipv4"), String::from("10.0.0.10")),
+        ])));
+
+        let served = meta_data.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let req = String::from_utf8_lossy(&buf[..n]).to_string();
+                // e.g., "GET /latest/meta-data/instance-id HTTP/1.1"
+                let path = req.split_whitespace().nth(1).unwrap_or_default();
+                let body = if path == "/latest/api/token" {
+                    Some(String::from("it-token"))
+                } else {
+                    path.strip_prefix("/latest/meta-data/")
+                        .and_then(|p| served.lock().unwrap().get(p).cloned())
+                };
+                let resp = match body {
+                    Some(b) => format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{b}",
+                        b.len()
+                    ),
+                    None => String::from(
+                        "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    ),
+                };
+                let _ = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        Ok(Self {
+            endpoint,
+            meta_data,
+        })
+    }
+
+    /// Sets the "meta-data/{path}" value (e.g., "autoscaling/target-lifecycle-state").
+    pub fn set(&self, path: &str, v: &str) {
+        self.meta_data
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), v.to_string());
+    }
+}
+
+/// Returns the "aws-ip-provisioner" binary to test.
+pub fn bin() -> PathBuf {
+    match env::var("IT_AWS_IP_PROVISIONER_BIN") {
+        Ok(v) => PathBuf::from(v),
+        Err(_) => PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("..")
+            .join("target")
+            .join("debug")
+            .join("aws-ip-provisioner"),
+    }
+}
+
+/// Runs the binary against LocalStack and the fake IMDS, with the extra flags.
+pub fn run(ls: &LocalStack, imds: &FakeImds, extra: &[&str]) -> io::Result<Output> {
+    let out = Command::new(bin())
+        .arg(format!("--endpoint-url={}", ls.endpoint))
+        .arg(format!("--imds-endpoint={}", imds.endpoint))
+        .arg("--imds-retries=0")
+        .arg("--initial-wait-random-seconds=0")
+        .arg("--log-level=debug")
+        .args(extra)
+        .env("AWS_REGION", REGION)
+        .output()?;
+    log::info!(
+        "aws-ip-provisioner {:?} exited with {}\n{}",
+        extra,
+        out.status,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    Ok(out)
+}
+
+/// Returns the tag value of the address.
+pub fn tag(addr: &Address, key: &str) -> Option<String> {
+    addr.tags()
+        .unwrap_or_default()
+        .iter()
+        .find(|t: &&Tag| t.key() == Some(key))
+        .and_then(|t| t.value().map(|v| v.to_string()))
+}
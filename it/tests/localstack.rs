@@ -0,0 +1,123 @@
+//! End-to-end tests of the provision, reuse, and release paths against LocalStack.
+//! Ignored by default (requires Docker), see "it/src/lib.rs".
+
+use std::{env, fs, path::PathBuf};
+
+use aws_ip_provisioner::{pool, state};
+use it::{run, tag, FakeImds, LocalStack};
+
+/// Returns an empty directory unique to the test, as the mounted volume.
+fn volume(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("it-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn tag_flags(id: &str, kind: &str, eip_file: &str) -> Vec<String> {
+    vec![
+        String::from("--id-tag-key=Id"),
+        format!("--id-tag-value={id}"),
+        String::from("--kind-tag-key=Kind"),
+        format!("--kind-tag-value={kind}"),
+        format!("--mounted-eip-file-path={eip_file}"),
+    ]
+}
+
+fn args(v: &[String]) -> Vec<&str> {
+    v.iter().map(|s| s.as_str()).collect()
+}
+
+#[tokio::test]
+#[ignore]
+async fn provision_reuse_and_release() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let kind = format!("it-{}", std::process::id());
+    let ls = LocalStack::start().await.unwrap();
+
+    // first instance allocates and associates a new EIP
+    let instance_1 = ls.run_instance().await.unwrap();
+    let imds_1 = FakeImds::start(&instance_1).await.unwrap();
+    let eip_file_1 = volume("node-1").join("eip.yaml").display().to_string();
+    let mut flags = tag_flags("node-1", &kind, &eip_file_1);
+    flags.push(String::from("--mode=provision"));
+
+    let out = run(&ls, &imds_1, &args(&flags)).unwrap();
+    assert!(out.status.success(), "first provision failed");
+    let eip = state::load_eip(&eip_file_1).unwrap();
+
+    let addrs = ls.addresses(&kind).await.unwrap();
+    assert_eq!(addrs.len(), 1, "expected one EIP {:?}", addrs);
+    assert_eq!(addrs[0].allocation_id(), Some(eip.allocation_id.as_str()));
+    assert_eq!(addrs[0].instance_id(), Some(instance_1.as_str()));
+    assert_eq!(tag(&addrs[0], "Id").as_deref(), Some("node-1"));
+    assert!(tag(&addrs[0], "AllocatedAt").is_some());
+
+    // second run on the same volume reuses the EIP in the state file
+    let out = run(&ls, &imds_1, &args(&flags)).unwrap();
+    assert!(out.status.success(), "second provision failed");
+    assert_eq!(state::load_eip(&eip_file_1).unwrap(), eip);
+    let addrs = ls.addresses(&kind).await.unwrap();
+    assert_eq!(addrs.len(), 1, "second run must not allocate {:?}", addrs);
+    assert_eq!(addrs[0].instance_id(), Some(instance_1.as_str()));
+
+    // scale-in returns the EIP to the pool
+    imds_1.set("autoscaling/target-lifecycle-state", "Terminated");
+    let mut flags = tag_flags("node-1", &kind, &eip_file_1);
+    flags.push(String::from("--mode=terminate-hook"));
+    let out = run(&ls, &imds_1, &args(&flags)).unwrap();
+    assert!(out.status.success(), "terminate-hook failed");
+
+    let addrs = ls.addresses(&kind).await.unwrap();
+    assert_eq!(addrs.len(), 1);
+    assert_eq!(addrs[0].association_id(), None);
+    assert_eq!(
+        tag(&addrs[0], pool::STATUS_TAG_KEY).as_deref(),
+        Some(pool::STATUS_AVAILABLE)
+    );
+
+    // replacement instance on a new volume claims the same EIP from the pool
+    let instance_2 = ls.run_instance().await.unwrap();
+    let imds_2 = FakeImds::start(&instance_2).await.unwrap();
+    let eip_file_2 = volume("node-2").join("eip.yaml").display().to_string();
+    let mut flags = tag_flags("node-2", &kind, &eip_file_2);
+    flags.push(String::from("--mode=provision"));
+    let out = run(&ls, &imds_2, &args(&flags)).unwrap();
+    assert!(out.status.success(), "replacement provision failed");
+    assert_eq!(state::load_eip(&eip_file_2).unwrap(), eip);
+
+    let addrs = ls.addresses(&kind).await.unwrap();
+    assert_eq!(addrs.len(), 1, "replacement must not allocate {:?}", addrs);
+    assert_eq!(addrs[0].instance_id(), Some(instance_2.as_str()));
+    assert_eq!(tag(&addrs[0], "Id").as_deref(), Some("node-2"));
+    assert_eq!(
+        tag(&addrs[0], pool::STATUS_TAG_KEY).as_deref(),
+        Some(pool::STATUS_CLAIMED)
+    );
+}
+
+#[tokio::test]
+#[ignore]
+async fn refuses_to_steal_from_live_instance() {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let kind = format!("it-steal-{}", std::process::id());
+    let ls = LocalStack::start().await.unwrap();
+
+    let instance_1 = ls.run_instance().await.unwrap();
+    let imds_1 = FakeImds::start(&instance_1).await.unwrap();
+    let eip_file = volume("shared").join("eip.yaml").display().to_string();
+    let mut flags = tag_flags("node-1", &kind, &eip_file);
+    flags.push(String::from("--mode=provision"));
+    let out = run(&ls, &imds_1, &args(&flags)).unwrap();
+    assert!(out.status.success(), "first provision failed");
+
+    // another running instance mounts the same volume
+    let instance_2 = ls.run_instance().await.unwrap();
+    let imds_2 = FakeImds::start(&instance_2).await.unwrap();
+    flags.push(String::from("--no-steal=true"));
+    let out = run(&ls, &imds_2, &args(&flags)).unwrap();
+    assert!(!out.status.success(), "must not steal from a live instance");
+
+    let addrs = ls.addresses(&kind).await.unwrap();
+    assert_eq!(addrs[0].instance_id(), Some(instance_1.as_str()));
+}
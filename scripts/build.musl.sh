@@ -0,0 +1,26 @@
+#!/usr/bin/env bash
+set -xue
+
+if ! [[ "$0" =~ scripts/build.musl.sh ]]; then
+  echo "must be run from repository root"
+  exit 255
+fi
+
+# fully static binaries for the minimal bootstrap images (no glibc, no "ca-certificates")
+# rustup target add x86_64-unknown-linux-musl
+#
+# all TLS is rustls, and "webpki-roots" bundles the root certificates for the images
+# without a system store
+if cargo tree --target x86_64-unknown-linux-musl --features ip-manager/webpki-roots -i openssl-sys >/dev/null 2>&1; then
+  echo "OpenSSL must not be linked (see 'cargo tree -i openssl-sys')"
+  exit 255
+fi
+cargo build \
+--release \
+--target x86_64-unknown-linux-musl \
+--features ip-manager/webpki-roots \
+--bin aws-ip-provisioner \
+--bin ip-manager
+
+file ./target/x86_64-unknown-linux-musl/release/ip-manager | grep -q "statically linked"
+./target/x86_64-unknown-linux-musl/release/ip-manager --version
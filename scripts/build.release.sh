@@ -9,6 +9,8 @@ fi
 # "--bin" can be specified multiple times for each directory in "bin/*" or workspaces
 cargo build \
 --release \
---bin aws-ip-provisioner
+--bin aws-ip-provisioner \
+--bin ip-manager
 
 ./target/release/aws-ip-provisioner --help
+./target/release/ip-manager --help
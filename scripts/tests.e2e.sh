@@ -0,0 +1,14 @@
+#!/usr/bin/env bash
+set -xue
+
+if ! [[ "$0" =~ scripts/tests.e2e.sh ]]; then
+  echo "must be run from repository root"
+  exit 255
+fi
+
+# requires Docker to start LocalStack
+# (or set IT_LOCALSTACK_ENDPOINT to use a running one)
+cargo build -p aws-ip-provisioner
+RUST_LOG=info cargo test -p it -- --ignored --test-threads=1 --show-output
+
+echo "ALL SUCCESS!"